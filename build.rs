@@ -0,0 +1,51 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// embedded-rulesのfeatureが有効な場合は、rulesフォルダをzipに圧縮してバイナリに埋め込めるようにする
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBEDDED_RULES").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-changed=rules");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("rules.zip");
+    let mut zip = ZipWriter::new(File::create(&out_path).unwrap());
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let count = add_dir(&mut zip, Path::new("rules"), options).unwrap();
+    if count == 0 {
+        panic!("The rules folder is empty. Please run \"git submodule update --init\" before building with the embedded-rules feature.");
+    }
+    zip.finish().unwrap();
+}
+
+// フォルダ内のファイルを再帰的にzipに追加し、追加したファイル数を返す。.gitフォルダは含めない
+fn add_dir(zip: &mut ZipWriter<File>, dir: &Path, options: FileOptions) -> io::Result<usize> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    let mut count = 0;
+    for path in entries {
+        if path.file_name().map_or(false, |name| name == ".git") {
+            continue;
+        }
+        if path.is_dir() {
+            count += add_dir(zip, &path, options)?;
+            continue;
+        }
+        let name = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+        zip.start_file(name, options)?;
+        io::copy(&mut File::open(&path)?, zip)?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -1,12 +1,42 @@
 use crate::detections::configs;
+use crate::detections::external_sort::EXTERNAL_SORTER;
+use crate::detections::graph;
+use crate::detections::hash_lookup::{get_detection_hash, HashLookup};
+use crate::detections::host_score::HostScores;
 use crate::detections::print;
 use crate::detections::print::AlertMessage;
+use crate::detections::suppression;
 use crate::detections::utils;
+use crate::notify::eventlog::{EventLogWriter, EVENTLOG_NAME};
+use crate::notify::splunk::SplunkHec;
+use crate::notify::syslog::SyslogForwarder;
+use crate::notify::webhook::WebhookNotifier;
+use crate::output::anonymize::{ANONYMIZER, ANONYMIZE_FLAG};
+use crate::output::auto_name::{self, OUTPUT_AUTO_TEMPLATE};
+use crate::output::csv_dialect::CsvDialect;
+use crate::output::diff::{self, BaselineDiff};
+use crate::output::html::HtmlReport;
+use crate::output::json::JsonOutput;
+use crate::output::misp::MispExporter;
+use crate::output::noise::{AUTO_TUNE_NOISE_THRESHOLD, NOISE_COUNTER};
+use crate::output::output_filter::OutputFilter;
+use crate::output::parquet::ParquetOutput;
+use crate::output::raw_xml::RawXmlExporter;
+use crate::output::redaction::{Redactor, REDACTION_RULES_CONFIG};
+use crate::output::rule_count::{RULE_COUNT_FILTER, RULE_HIT_COUNTER};
+use crate::output::rule_meta::{RuleMetaCache, RuleMetaColumns, SelectedRuleMeta};
+use crate::output::rule_summary::RuleSummary;
+use crate::output::sqlite::SqliteOutput;
+use crate::output::stix::StixOutput;
+use crate::output::xlsx::XlsxOutput;
+use crate::triage::get_triage_host;
+use crate::wef;
 use chrono::{DateTime, Local, TimeZone, Utc};
 use csv::QuoteStyle;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use serde::Serialize;
+use std::borrow::Cow;
 use std::error::Error;
 use std::fs::File;
 use std::io;
@@ -20,8 +50,11 @@ use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 pub struct CsvFormat<'a> {
     timestamp: &'a str,
     computer: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    triage_host: Option<&'a str>,
     channel: &'a str,
     event_i_d: &'a str,
+    record_i_d: &'a str,
     level: &'a str,
     mitre_attack: &'a str,
     rule_title: &'a str,
@@ -30,6 +63,22 @@ pub struct CsvFormat<'a> {
     record_information: Option<&'a str>,
     rule_path: &'a str,
     file_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_references: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_false_positives: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_date: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_modified: Option<&'a str>,
+    #[serde(rename = "VTHash", skip_serializing_if = "Option::is_none")]
+    vt_hash: Option<&'a str>,
+    #[serde(rename = "VTDetections", skip_serializing_if = "Option::is_none")]
+    vt_detections: Option<&'a str>,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,6 +86,8 @@ pub struct CsvFormat<'a> {
 pub struct DisplayFormat<'a> {
     timestamp: &'a str,
     pub computer: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triage_host: Option<&'a str>,
     pub channel: &'a str,
     pub event_i_d: &'a str,
     pub level: &'a str,
@@ -52,7 +103,7 @@ lazy_static! {
 
 /// level_color.txtファイルを読み込み対応する文字色のマッピングを返却する関数
 pub fn set_output_color() -> HashMap<String, Color> {
-    let read_result = utils::read_csv("config/level_color.txt");
+    let read_result = utils::read_csv(&configs::config_path("level_color.txt"));
     let mut color_map: HashMap<String, Color> = HashMap::new();
     if configs::CONFIG.read().unwrap().args.is_present("no-color") {
         return color_map;
@@ -109,25 +160,43 @@ pub fn after_fact() {
     };
 
     let mut displayflag = false;
-    let mut target: Box<dyn io::Write> =
-        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
-            // output to file
-            match File::create(csv_path) {
-                Ok(file) => Box::new(BufWriter::new(file)),
-                Err(err) => {
-                    AlertMessage::alert(
-                        &mut BufWriter::new(std::io::stderr().lock()),
-                        &format!("Failed to open file. {}", err),
-                    )
-                    .ok();
-                    process::exit(1);
-                }
+    // --output-autoの場合は検知したComputerと日時の範囲からファイル名を決める
+    let csv_path = match OUTPUT_AUTO_TEMPLATE.as_ref() {
+        Some(template) => {
+            let clobber = configs::CONFIG.read().unwrap().args.is_present("clobber");
+            let csv_path = auto_name::output_path(template, clobber);
+            println!("Saving the timeline to {}", csv_path);
+            Some(csv_path)
+        }
+        None => configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("output")
+            .map(|path| path.to_string()),
+    };
+    let mut target: Box<dyn io::Write> = if let Some(csv_path) = csv_path {
+        // output to file
+        match File::create(csv_path).and_then(|file| {
+            CsvDialect::from_config()
+                .unwrap_or_default()
+                .wrap(BufWriter::new(file))
+        }) {
+            Ok(writer) => writer,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to open file. {}", err),
+                )
+                .ok();
+                process::exit(1);
             }
-        } else {
-            displayflag = true;
-            // stdoutput (termcolor crate color output is not csv writer)
-            Box::new(BufWriter::new(io::stdout()))
-        };
+        }
+    } else {
+        displayflag = true;
+        // stdoutput (termcolor crate color output is not csv writer)
+        Box::new(BufWriter::new(io::stdout()))
+    };
     let color_map = set_output_color();
     if let Err(err) = emit_csv(&mut target, displayflag, color_map) {
         fn_emit_csv_err(Box::new(err));
@@ -142,80 +211,328 @@ fn emit_csv<W: std::io::Write>(
     let disp_wtr = BufferWriter::stdout(ColorChoice::Always);
     let mut disp_wtr_buf = disp_wtr.buffer();
 
-    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    let mut wtr = CsvDialect::from_config()
+        .unwrap_or_default()
+        .writer_builder()
+        .from_writer(writer);
 
     let messages = print::MESSAGES.lock().unwrap();
+    let mut sorter = EXTERNAL_SORTER.lock().unwrap();
+    let detections = sorter
+        .sorted_detections(messages.iter())
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
     // level is devided by "Critical","High","Medium","Low","Informational","Undefined".
     let mut total_detect_counts_by_level: Vec<u128> = vec![0; 6];
     let mut unique_detect_counts_by_level: Vec<u128> = vec![0; 6];
     let mut detected_rule_files: Vec<String> = Vec::new();
+    let host_scores_csv = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("host-scores-csv")
+        .map(|path| path.to_string());
+    let host_scores_flag = host_scores_csv.is_some()
+        || configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("host-scores");
+    let mut host_scores = HostScores::new();
+    let rule_summary_csv = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("rule-summary")
+        .map(|path| path.to_string());
+    let mut rule_summary = RuleSummary::new();
+    // --remote-hostsの場合も収集元のホスト名をTriageHost列に出力する
+    let triage_flag = configs::CONFIG.read().unwrap().args.is_present("triage")
+        || configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("remote-hosts");
+    let mut splunk_hec = create_splunk_hec();
+    let mut syslog = create_syslog_forwarder();
+    let mut eventlog = create_eventlog_writer();
+    let mut webhook = create_webhook_notifier();
+    let mut misp = if configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("output-misp")
+        || configs::CONFIG.read().unwrap().args.is_present("misp-url")
+    {
+        Some(MispExporter::default())
+    } else {
+        None
+    };
+    let mut sqlite = create_sqlite_output();
+    let mut parquet = create_parquet_output();
+    let mut xlsx = create_xlsx_output();
+    let mut jsonl = create_json_output();
+    let mut stix = create_stix_output();
+    let mut hash_lookup = create_hash_lookup();
+    let mut anonymizer = if *ANONYMIZE_FLAG {
+        Some(std::mem::take(&mut *ANONYMIZER.lock().unwrap()))
+    } else {
+        None
+    };
+    let redactor = create_redactor();
+    let output_filter = OutputFilter::from_config().ok().flatten();
+    let mut filtered_out_count = 0;
+    let mut rule_count_suppressor = RULE_COUNT_FILTER.as_ref().map(|filter| {
+        std::mem::take(&mut *RULE_HIT_COUNTER.lock().unwrap()).into_suppressor(filter)
+    });
+    let mut noise_collapser = AUTO_TUNE_NOISE_THRESHOLD.map(|threshold| {
+        std::mem::take(&mut *NOISE_COUNTER.lock().unwrap()).into_collapser(threshold)
+    });
+    let mut baseline_diff = create_baseline_diff();
+    let rule_meta_columns = RuleMetaColumns::from_config().unwrap_or_default();
+    let mut rule_metas = RuleMetaCache::new();
+    let html_report_dir = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("html-report")
+        .map(|dir| dir.to_string());
+    let mut html_report = HtmlReport::new();
+    let mut raw_xml = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("raw-xml")
+        .map(RawXmlExporter::new);
 
     println!();
     let mut plus_header = true;
-    for (time, detect_infos) in messages.iter() {
-        for detect_info in detect_infos {
-            let mut level = detect_info.level.to_string();
-            if level == "informational" {
-                level = "info".to_string();
+    for (time, detect_info) in detections {
+        let time = &time;
+        // --filter-outputの条件に一致しない検知は出力しない
+        if let Some(filter) = output_filter.as_ref() {
+            if !filter.is_match(&detect_info) {
+                filtered_out_count += 1;
+                continue;
             }
-            if displayflag {
-                let recinfo = detect_info
-                    .record_information
-                    .as_ref()
-                    .map(|recinfo| _format_cellpos(recinfo, ColPos::Last));
-                let details = detect_info
-                    .detail
-                    .chars()
-                    .filter(|&c| !c.is_control())
-                    .collect::<String>();
-
-                let dispformat = DisplayFormat {
-                    timestamp: &_format_cellpos(&format_time(time), ColPos::First),
-                    level: &_format_cellpos(&level, ColPos::Other),
-                    computer: &_format_cellpos(&detect_info.computername, ColPos::Other),
-                    event_i_d: &_format_cellpos(&detect_info.eventid, ColPos::Other),
-                    channel: &_format_cellpos(&detect_info.channel, ColPos::Other),
-                    rule_title: &_format_cellpos(&detect_info.alert, ColPos::Other),
-                    details: &_format_cellpos(&details, ColPos::Other),
-                    record_information: recinfo.as_deref(),
-                };
-
-                disp_wtr_buf
-                    .set_color(
-                        ColorSpec::new().set_fg(_get_output_color(&color_map, &detect_info.level)),
-                    )
-                    .ok();
-                write!(
-                    disp_wtr_buf,
-                    "{}",
-                    _get_serialized_disp_output(dispformat, plus_header)
+        }
+        // --min-rule-countと--max-rule-countの範囲外の件数のルールの検知は出力しない
+        if let Some(suppressor) = rule_count_suppressor.as_mut() {
+            if !suppressor.is_reported(&detect_info) {
+                continue;
+            }
+        }
+        // --auto-tune-noiseが指定されている場合は同じルールと詳細の大量の検知を件数付きの1行にまとめる
+        let detect_info = match noise_collapser.as_mut() {
+            Some(collapser) => match collapser.collapse(detect_info) {
+                Some(detect_info) => detect_info,
+                None => continue,
+            },
+            None => detect_info,
+        };
+        let detect_info = match anonymizer.as_mut() {
+            Some(anonymizer) => Cow::Owned(anonymizer.anonymize(&detect_info)),
+            None => detect_info,
+        };
+        let detect_info = match redactor.as_ref() {
+            Some(redactor) => Cow::Owned(redactor.redact(&detect_info)),
+            None => detect_info,
+        };
+        // --diffが指定されている場合は前回の実行結果にない検知だけを出力する。
+        // JSON Linesのファイルは次回の比較に使えるように全ての検知を保存する
+        if let Some(diff) = baseline_diff.as_mut() {
+            if !diff.is_new(&detect_info) {
+                if let Some(output) = jsonl.as_mut() {
+                    let level = if detect_info.level == "informational" {
+                        "info"
+                    } else {
+                        detect_info.level.as_str()
+                    };
+                    if let Err(err) = output.add(time, &format_time(time), level, &detect_info) {
+                        AlertMessage::alert(
+                            &mut BufWriter::new(std::io::stderr().lock()),
+                            &format!("Failed to write the JSON file. {}", err),
+                        )
+                        .ok();
+                        jsonl = None;
+                    }
+                }
+                continue;
+            }
+        }
+        let mut level = detect_info.level.to_string();
+        if level == "informational" {
+            level = "info".to_string();
+        }
+        let triage_host = if triage_flag {
+            // WEFで転送されたイベントはコレクターではなく元のコンピュータ名にする
+            let host = wef::source_host(
+                &detect_info.filepath,
+                &detect_info.computername,
+                get_triage_host(&detect_info.filepath),
+            );
+            Some(host.unwrap_or_else(|| "-".to_string()))
+        } else {
+            None
+        };
+        if displayflag {
+            let recinfo = detect_info
+                .record_information
+                .as_ref()
+                .map(|recinfo| _format_cellpos(recinfo, ColPos::Last));
+            let details = detect_info
+                .detail
+                .chars()
+                .filter(|&c| !c.is_control())
+                .collect::<String>();
+
+            let triage_host_cell = triage_host
+                .as_ref()
+                .map(|host| _format_cellpos(host, ColPos::Other));
+            let dispformat = DisplayFormat {
+                timestamp: &_format_cellpos(&format_time(time), ColPos::First),
+                level: &_format_cellpos(&level, ColPos::Other),
+                computer: &_format_cellpos(&detect_info.computername, ColPos::Other),
+                triage_host: triage_host_cell.as_deref(),
+                event_i_d: &_format_cellpos(&detect_info.eventid, ColPos::Other),
+                channel: &_format_cellpos(&detect_info.channel, ColPos::Other),
+                rule_title: &_format_cellpos(&detect_info.alert, ColPos::Other),
+                details: &_format_cellpos(&details, ColPos::Other),
+                record_information: recinfo.as_deref(),
+            };
+
+            disp_wtr_buf
+                .set_color(
+                    ColorSpec::new().set_fg(_get_output_color(&color_map, &detect_info.level)),
                 )
                 .ok();
-                plus_header = false;
+            write!(
+                disp_wtr_buf,
+                "{}",
+                _get_serialized_disp_output(dispformat, plus_header)
+            )
+            .ok();
+            plus_header = false;
+        } else {
+            // csv output format
+            let rule_meta = if rule_meta_columns.is_empty() {
+                SelectedRuleMeta::default()
             } else {
-                // csv output format
-                wtr.serialize(CsvFormat {
-                    timestamp: &format_time(time),
-                    level: &level,
-                    computer: &detect_info.computername,
-                    event_i_d: &detect_info.eventid,
-                    channel: &detect_info.channel,
-                    mitre_attack: &detect_info.tag_info,
-                    rule_title: &detect_info.alert,
-                    details: &detect_info.detail,
-                    record_information: detect_info.record_information.as_deref(),
-                    file_path: &detect_info.filepath,
-                    rule_path: &detect_info.rulepath,
-                })?;
+                rule_meta_columns.select(rule_metas.get(&detect_info.rulepath))
+            };
+            // ハッシュ値がないレコードの場合は空の列にする
+            let (vt_hash, vt_detections) = match hash_lookup.as_mut() {
+                Some(lookup) => {
+                    match get_detection_hash(&detect_info.filepath, &detect_info.record_id) {
+                        Some(hash) => {
+                            let verdict = lookup.lookup(&hash);
+                            (Some(hash), Some(verdict))
+                        }
+                        None => (Some(String::default()), Some(String::default())),
+                    }
+                }
+                None => (None, None),
+            };
+            wtr.serialize(CsvFormat {
+                timestamp: &format_time(time),
+                level: &level,
+                computer: &detect_info.computername,
+                triage_host: triage_host.as_deref(),
+                event_i_d: &detect_info.eventid,
+                record_i_d: &detect_info.record_id,
+                channel: &detect_info.channel,
+                mitre_attack: &detect_info.tag_info,
+                rule_title: &detect_info.alert,
+                details: &detect_info.detail,
+                record_information: detect_info.record_information.as_deref(),
+                file_path: &detect_info.filepath,
+                rule_path: &detect_info.rulepath,
+                rule_description: rule_meta.rule_description,
+                rule_references: rule_meta.rule_references,
+                rule_false_positives: rule_meta.rule_false_positives,
+                rule_author: rule_meta.rule_author,
+                rule_date: rule_meta.rule_date,
+                rule_modified: rule_meta.rule_modified,
+                vt_hash: vt_hash.as_deref(),
+                vt_detections: vt_detections.as_deref(),
+            })?;
+        }
+        let level_suffix = *configs::LEVELMAP
+            .get(&detect_info.level.to_uppercase())
+            .unwrap_or(&0) as usize;
+        if !detected_rule_files.contains(&detect_info.rulepath) {
+            detected_rule_files.push(detect_info.rulepath.clone());
+            unique_detect_counts_by_level[level_suffix] += 1;
+        }
+        total_detect_counts_by_level[level_suffix] += 1;
+        if host_scores_flag {
+            host_scores.add(&detect_info);
+        }
+        if rule_summary_csv.is_some() {
+            rule_summary.add(time, &detect_info);
+        }
+        if let Some(hec) = splunk_hec.as_mut() {
+            hec.add(time, format_time(time), &detect_info);
+        }
+        if let Some(syslog) = syslog.as_mut() {
+            syslog.send(time, &detect_info);
+        }
+        if let Some(eventlog) = eventlog.as_mut() {
+            eventlog.write(time, &detect_info);
+        }
+        if let Some(webhook) = webhook.as_mut() {
+            webhook.add(time, &detect_info);
+        }
+        if let Some(misp) = misp.as_mut() {
+            misp.add(&detect_info);
+        }
+        if let Some(sqlite) = sqlite.as_mut() {
+            sqlite.add(time, &detect_info);
+        }
+        if html_report_dir.is_some() {
+            html_report.add(time, &detect_info);
+        }
+        if let Some(exporter) = raw_xml.as_mut() {
+            exporter.add(&format_time(time), &detect_info);
+        }
+        if let Some(output) = jsonl.as_mut() {
+            if let Err(err) = output.add(time, &format_time(time), &level, &detect_info) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the JSON file. {}", err),
+                )
+                .ok();
+                jsonl = None;
+            }
+        }
+        if let Some(output) = stix.as_mut() {
+            if let Err(err) = output.add(time, &detect_info) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the STIX file. {}", err),
+                )
+                .ok();
+                stix = None;
             }
-            let level_suffix = *configs::LEVELMAP
-                .get(&detect_info.level.to_uppercase())
-                .unwrap_or(&0) as usize;
-            if !detected_rule_files.contains(&detect_info.rulepath) {
-                detected_rule_files.push(detect_info.rulepath.clone());
-                unique_detect_counts_by_level[level_suffix] += 1;
+        }
+        if let Some(output) = xlsx.as_mut() {
+            if let Err(err) = output.add(&format_time(time), &level, &detect_info) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the xlsx file. {}", err),
+                )
+                .ok();
+                xlsx = None;
+            }
+        }
+        if let Some(output) = parquet.as_mut() {
+            if let Err(err) = output.add(time, &level, &detect_info) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the Parquet file. {}", err),
+                )
+                .ok();
+                parquet = None;
             }
-            total_detect_counts_by_level[level_suffix] += 1;
         }
     }
     if displayflag {
@@ -236,9 +553,561 @@ fn emit_csv<W: std::io::Write>(
         "detections".to_string(),
         &color_map,
     );
+    if host_scores_flag {
+        host_scores.print();
+    }
+    if let Some(mut hec) = splunk_hec {
+        hec.flush();
+        println!("Detections sent to Splunk HEC: {}", hec.sent);
+        if hec.failed > 0 {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to send {} detections to Splunk HEC.", hec.failed),
+            )
+            .ok();
+        }
+        println!();
+    }
+    if let Some(mut syslog) = syslog {
+        syslog.flush();
+        println!("Detections sent to syslog: {}", syslog.sent);
+        if syslog.failed > 0 {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to send {} detections to syslog.", syslog.failed),
+            )
+            .ok();
+        }
+        println!();
+    }
+    if let Some(eventlog) = eventlog {
+        println!(
+            "Detections written to the {} event log: {}",
+            EVENTLOG_NAME, eventlog.written
+        );
+        if eventlog.failed > 0 {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!(
+                    "Failed to write {} detections to the {} event log.",
+                    eventlog.failed, EVENTLOG_NAME
+                ),
+            )
+            .ok();
+        }
+        println!();
+    }
+    if let Some(mut webhook) = webhook {
+        webhook.finish();
+        println!("Webhook notifications sent: {}", webhook.sent);
+        if webhook.failed > 0 {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to send {} webhook notifications.", webhook.failed),
+            )
+            .ok();
+        }
+        println!();
+    }
+    if let Some(misp) = misp {
+        export_misp(&misp);
+    }
+    // サマリーなどの後の出力でも同じ仮名を使えるように戻す
+    if let Some(anonymizer) = anonymizer {
+        *ANONYMIZER.lock().unwrap() = anonymizer;
+    }
+    save_learned_allowlist();
+    save_graph();
+    if output_filter.is_some() {
+        println!(
+            "Detections excluded by the output filter: {}",
+            filtered_out_count
+        );
+        println!();
+    }
+    if let Some(suppressor) = rule_count_suppressor {
+        let (rules, detections) = suppressor.suppressed_counts();
+        if rules > 0 {
+            println!(
+                "Rules suppressed by the rule count thresholds: {} (Hidden detections: {})",
+                rules, detections
+            );
+            println!();
+        }
+    }
+    if let Some(collapser) = noise_collapser {
+        let collapsed = collapser.collapsed();
+        if !collapsed.is_empty() {
+            println!(
+                "Collapsed noisy detections: {} (Hidden detections: {})",
+                collapsed.len(),
+                collapser.hidden_count()
+            );
+            for (title, detail, count) in collapsed.iter() {
+                println!("  {}: {} ({})", title, count, detail);
+            }
+            println!();
+        }
+    }
+    let suppressed = suppression::suppressed_counts();
+    if !suppressed.is_empty() {
+        println!(
+            "Suppressed detections: {}",
+            suppressed.iter().map(|(_, count)| count).sum::<usize>()
+        );
+        for (title, count) in suppressed.iter() {
+            println!("  {}: {}", title, count);
+        }
+        println!();
+    }
+    if let Some(diff) = baseline_diff {
+        println!(
+            "New detections since the previous run: {} (Unchanged: {} Resolved: {})",
+            diff.new,
+            diff.unchanged,
+            diff.resolved().len()
+        );
+        if let Some(path) = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("diff-resolved")
+        {
+            match diff.write_resolved(path) {
+                Ok(written) => println!("Saved {} resolved detections to {}", written, path),
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to write the resolved detections. {}", err),
+                    )
+                    .ok();
+                }
+            }
+        }
+        println!();
+    }
+    if let Some(lookup) = hash_lookup {
+        println!("Hashes looked up on VirusTotal: {}", lookup.lookups);
+        if lookup.failed > 0 {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to look up {} hashes on VirusTotal.", lookup.failed),
+            )
+            .ok();
+        }
+        if let Err(err) = lookup.save_cache() {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to save the VirusTotal cache. {}", err),
+            )
+            .ok();
+        }
+        println!();
+    }
+    if let Some(sqlite) = sqlite {
+        if let Err(err) = sqlite.commit() {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write detections to the SQLite database. {}", err),
+            )
+            .ok();
+        } else {
+            println!(
+                "Detections saved to the SQLite database: {}",
+                sqlite.inserted
+            );
+            if sqlite.failed > 0 {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!(
+                        "Failed to write {} detections to the SQLite database.",
+                        sqlite.failed
+                    ),
+                )
+                .ok();
+            }
+        }
+        println!();
+    }
+    if let Some(output) = parquet {
+        match output.close() {
+            Ok(written) => {
+                println!("Detections saved to the Parquet file: {}", written);
+                println!();
+            }
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the Parquet file. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    if let Some(mut output) = jsonl {
+        match output.flush() {
+            Ok(written) => {
+                println!("Detections saved to the JSON file: {}", written);
+                println!();
+            }
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the JSON file. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    if let Some(output) = stix {
+        match output.finish() {
+            Ok(written) => {
+                println!("STIX objects saved to the STIX file: {}", written);
+                println!();
+            }
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the STIX file. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    if let Some(output) = xlsx {
+        let path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("output-xlsx")
+            .unwrap_or_default()
+            .to_string();
+        match output.save(&path) {
+            Ok(written) => {
+                println!("Detections saved to the xlsx file: {}", written);
+                println!();
+            }
+            Err(err) => {
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            }
+        }
+    }
+    if let Some(dir) = html_report_dir {
+        if let Err(err) = html_report.write(&dir) {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write the HTML report. {}", err),
+            )
+            .ok();
+        } else {
+            println!("HTML report saved to: {}", dir);
+            println!();
+        }
+    }
+    if let Some(exporter) = raw_xml {
+        match exporter.write() {
+            Ok(written) => {
+                println!("Raw XML of the detected records saved: {}", written);
+                println!();
+            }
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the raw XML. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    if let Some(csv_path) = host_scores_csv {
+        if let Err(err) = host_scores.write_csv(&csv_path) {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write host scores csv. {}", err),
+            )
+            .ok();
+        }
+    }
+    if let Some(csv_path) = rule_summary_csv {
+        match rule_summary.write_csv(&csv_path) {
+            Ok(_) => println!("Saved rule summary to {}\n", csv_path),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write rule summary csv. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
     Ok(())
 }
 
+/// --splunk-hec-urlが指定されている場合はSplunk HECへの送信を準備する
+fn create_splunk_hec() -> Option<SplunkHec> {
+    let config = configs::CONFIG.read().unwrap();
+    let url = config.args.value_of("splunk-hec-url")?;
+    match SplunkHec::new(url, config.args.value_of("splunk-hec-token")) {
+        Ok(hec) => Some(hec),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-mispと--misp-urlが指定されている場合は検知結果から抽出した指標をMISPのイベントとして保存、送信する
+fn export_misp(misp: &MispExporter) {
+    let config = configs::CONFIG.read().unwrap();
+    if let Some(path) = config.args.value_of("output-misp") {
+        match misp.write(path) {
+            Ok(count) => println!("Saved {} MISP attributes to {}\n", count, path),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write the MISP event file. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    if let Some(url) = config.args.value_of("misp-url") {
+        if misp.is_empty() {
+            println!("No indicators were found to push to MISP.\n");
+            return;
+        }
+        match misp.push(url, config.args.value_of("misp-key")) {
+            Ok(count) => println!("Pushed {} MISP attributes to {}\n", count, url),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to push the MISP event. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+}
+
+/// --learn-allowlistが指定されている場合は検知から学習した許可リストを保存する
+fn save_learned_allowlist() {
+    let path = match configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("learn-allowlist")
+    {
+        Some(path) => path.to_string(),
+        None => return,
+    };
+    match suppression::save_learned(&path) {
+        Ok(entries) => println!("Saved {} allowlist entries to {}\n", entries, path),
+        Err(err) => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write the allowlist. {}", err),
+            )
+            .ok();
+        }
+    }
+}
+
+/// --graphが指定されている場合は検知から作成したホスト、ユーザ、プロセスの関係のグラフを保存する
+fn save_graph() {
+    let path = match configs::CONFIG.read().unwrap().args.value_of("graph") {
+        Some(path) => path.to_string(),
+        None => return,
+    };
+    match graph::save(&path) {
+        Ok((nodes, edges)) => println!(
+            "Saved the graph with {} nodes and {} edges to {}\n",
+            nodes, edges, path
+        ),
+        Err(err) => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write the graph. {}", err),
+            )
+            .ok();
+        }
+    }
+}
+
+/// --diffが指定されている場合は解析の前に読み込んだ前回の実行結果を取り出す
+fn create_baseline_diff() -> Option<BaselineDiff> {
+    diff::take_baseline()
+}
+
+/// --redactが指定されている場合は置き換えの設定を読み込む
+fn create_redactor() -> Option<Redactor> {
+    if !configs::CONFIG.read().unwrap().args.is_present("redact") {
+        return None;
+    }
+    match Redactor::load(&configs::config_path(REDACTION_RULES_CONFIG)) {
+        Ok(redactor) => Some(redactor),
+        Err(err) => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to load the redaction rules. {}", err),
+            )
+            .ok();
+            None
+        }
+    }
+}
+
+/// --vt-api-keyが指定されている場合はハッシュ値の問い合わせを準備する
+fn create_hash_lookup() -> Option<HashLookup> {
+    let config = configs::CONFIG.read().unwrap();
+    let api_key = config.args.value_of("vt-api-key")?;
+    match HashLookup::new(
+        api_key,
+        config.args.value_of("hash-lookup-url"),
+        config.args.value_of("vt-rate-limit"),
+        config.args.value_of("vt-cache"),
+    ) {
+        Ok(lookup) => Some(lookup),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --syslogが指定されている場合はsyslogサーバーに接続する
+fn create_syslog_forwarder() -> Option<SyslogForwarder> {
+    let config = configs::CONFIG.read().unwrap();
+    let address = config.args.value_of("syslog")?;
+    match SyslogForwarder::new(
+        address,
+        config.args.value_of("syslog-protocol").unwrap_or("udp"),
+        config.args.value_of("syslog-format").unwrap_or("rfc5424"),
+    ) {
+        Ok(syslog) => Some(syslog),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --write-eventlogが指定されている場合はHayabusaのイベントログを開く
+fn create_eventlog_writer() -> Option<EventLogWriter> {
+    if !configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("write-eventlog")
+    {
+        return None;
+    }
+    match EventLogWriter::new() {
+        Ok(eventlog) => Some(eventlog),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --notify-webhookが指定されている場合は実行の終了時に検知結果のサマリを送信する準備をする
+fn create_webhook_notifier() -> Option<WebhookNotifier> {
+    let config = configs::CONFIG.read().unwrap();
+    let url = config.args.value_of("notify-webhook")?;
+    match WebhookNotifier::new(
+        url,
+        config.args.value_of("notify-level").unwrap_or("high"),
+        config.args.is_present("notify-each-critical"),
+    ) {
+        Ok(webhook) => Some(webhook),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-sqliteが指定されている場合はデータベースを開いて検知結果の書き込みを開始する
+fn create_sqlite_output() -> Option<SqliteOutput> {
+    let path = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("output-sqlite")?
+        .to_string();
+    match SqliteOutput::open(&path).and_then(|sqlite| sqlite.begin().map(|_| sqlite)) {
+        Ok(sqlite) => Some(sqlite),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-parquetが指定されている場合はParquetファイルを作成する
+fn create_parquet_output() -> Option<ParquetOutput> {
+    let path = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("output-parquet")?
+        .to_string();
+    match ParquetOutput::create(&path) {
+        Ok(output) => Some(output),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-jsonlが指定されている場合はJSON Lines形式のファイルを作成する
+fn create_json_output() -> Option<JsonOutput> {
+    let config = configs::CONFIG.read().unwrap();
+    let path = config.args.value_of("output-jsonl")?;
+    match JsonOutput::create(
+        path,
+        config.args.value_of("json-schema").unwrap_or("hayabusa"),
+        RuleMetaColumns::from_config().unwrap_or_default(),
+    ) {
+        Ok(output) => Some(output),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-stixが指定されている場合はSTIX 2.1のバンドルのファイルを作成する
+fn create_stix_output() -> Option<StixOutput> {
+    let path = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("output-stix")?
+        .to_string();
+    match StixOutput::create(&path) {
+        Ok(output) => Some(output),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
+/// --output-xlsxが指定されている場合は検知結果をシートに書き込む準備をする
+fn create_xlsx_output() -> Option<XlsxOutput> {
+    let config = configs::CONFIG.read().unwrap();
+    config.args.value_of("output-xlsx")?;
+    match XlsxOutput::new(config.args.value_of("xlsx-split").unwrap_or("level")) {
+        Ok(output) => Some(output),
+        Err(err) => {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            None
+        }
+    }
+}
+
 /// columnt position. in cell
 /// First: |<str> |
 /// Last: | <str>|
@@ -317,7 +1186,7 @@ fn _print_unique_results(
     buf_wtr.print(&wtr).ok();
 }
 
-fn format_time(time: &DateTime<Utc>) -> String {
+pub fn format_time(time: &DateTime<Utc>) -> String {
     if configs::CONFIG.read().unwrap().args.is_present("utc") {
         format_rfc(time)
     } else {
@@ -341,10 +1210,10 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::afterfact::DisplayFormat;
     use crate::afterfact::_get_serialized_disp_output;
     use crate::afterfact::emit_csv;
     use crate::afterfact::format_time;
+    use crate::afterfact::DisplayFormat;
     use crate::detections::print;
     use crate::detections::print::DetectInfo;
     use crate::detections::print::CH_CONFIG;
@@ -400,6 +1269,7 @@ mod tests {
                     level: test_level.to_string(),
                     computername: test_computername.to_string(),
                     eventid: test_eventid.to_string(),
+                    record_id: "1234".to_string(),
                     channel: CH_CONFIG
                         .get("Security")
                         .unwrap_or(&String::default())
@@ -416,7 +1286,7 @@ mod tests {
             .unwrap();
         let expect_tz = expect_time.with_timezone(&Local);
         let expect =
-            "Timestamp,Computer,Channel,EventID,Level,MitreAttack,RuleTitle,Details,RecordInformation,RulePath,FilePath\n"
+            "Timestamp,Computer,Channel,EventID,RecordID,Level,MitreAttack,RuleTitle,Details,RecordInformation,RulePath,FilePath\n"
                 .to_string()
                 + &expect_tz
                     .clone()
@@ -429,6 +1299,8 @@ mod tests {
                 + ","
                 + test_eventid
                 + ","
+                + "1234"
+                + ","
                 + test_level
                 + ","
                 + test_attack
@@ -497,6 +1369,7 @@ mod tests {
                     timestamp: &format_time(&test_timestamp),
                     level: test_level,
                     computer: test_computername,
+                    triage_host: None,
                     event_i_d: test_eventid,
                     channel: test_channel,
                     rule_title: test_title,
@@ -513,6 +1386,7 @@ mod tests {
                     timestamp: &format_time(&test_timestamp),
                     level: test_level,
                     computer: test_computername,
+                    triage_host: None,
                     event_i_d: test_eventid,
                     channel: test_channel,
                     rule_title: test_title,
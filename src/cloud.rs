@@ -0,0 +1,290 @@
+use crate::detections::utils;
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+/// Azure ADのサインインログのチャンネル。Sigmaのlogsourceのproduct: azure、service: signinlogsに対応する
+pub const SIGNIN_CHANNEL: &str = "AzureAD/SignInLogs";
+/// Azure ADの監査ログのチャンネル。service: auditlogsに対応する
+pub const AUDIT_CHANNEL: &str = "AzureAD/AuditLogs";
+/// Azureのアクティビティログのチャンネル。service: activitylogsに対応する
+pub const ACTIVITY_CHANNEL: &str = "Azure/ActivityLogs";
+/// Microsoft 365の統合監査ログ(UAL)のチャンネル。product: m365に対応する
+pub const UAL_CHANNEL: &str = "M365/UnifiedAuditLog";
+
+// エクスポートの形式を判定する時に読み込むサイズ
+const SNIFF_LEN: u64 = 4096;
+// 各ログの形式を判定するための、ファイルの先頭に含まれるキー
+const FORMAT_MARKERS: [&[&str]; 4] = [
+    &["\"CreationTime\"", "\"Operation\""],
+    &["\"createdDateTime\"", "\"userPrincipalName\""],
+    &["\"activityDateTime\"", "\"activityDisplayName\""],
+    &["\"time\"", "\"operationName\"", "\"category\""],
+];
+// 監査ログの検索結果をCSVでエクスポートした時に、UALのレコードのJSONが入っている列
+const AUDIT_DATA_COLUMN: &str = "AuditData";
+// Azure Monitorの診断設定でエクスポートしたログのcategoryとチャンネル
+const DIAGNOSTIC_CATEGORIES: [(&str, &str); 4] = [
+    ("SignInLogs", SIGNIN_CHANNEL),
+    ("NonInteractiveUserSignInLogs", SIGNIN_CHANNEL),
+    ("ServicePrincipalSignInLogs", SIGNIN_CHANNEL),
+    ("AuditLogs", AUDIT_CHANNEL),
+];
+// Graph APIのキーと、SigmaのルールがSentinelのテーブルの列名として使うキーが先頭の大文字以外でも異なるもの
+const FIELD_ALIASES: [(&str, &str); 1] = [("ipAddress", "IPAddress")];
+
+/**
+* Azure AD(サインインログ、監査ログ)、Azureのアクティビティログ、Microsoft 365の統合監査ログをエクスポートしたファイルかを判定する。
+* 拡張子がjson、jsonl、csvで、ファイルの先頭に各ログのキー(CSVの場合はAuditDataの列)があるかを確認する。
+*/
+pub fn is_cloud_log_file(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    if !["json", "jsonl", "csv"].contains(&ext.as_str()) {
+        return false;
+    }
+    let mut head = String::new();
+    if File::open(path)
+        .and_then(|file| file.take(SNIFF_LEN).read_to_string(&mut head))
+        .is_err()
+    {
+        return false;
+    }
+    if ext == "csv" {
+        return head
+            .lines()
+            .next()
+            .map_or(false, |header| header.contains(AUDIT_DATA_COLUMN));
+    }
+    FORMAT_MARKERS
+        .iter()
+        .any(|markers| markers.iter().all(|marker| head.contains(marker)))
+}
+
+/**
+* クラウドのログのエクスポートを読み込んで、evtxファイルのレコードと同じ形式のJSONで返す。
+* JSONの配列、Graph APIのvalue、Azure Monitorのrecordsの配列、1行1レコードのJSON Lines、UALのCSVに対応する。
+* どのログにも当てはまらないレコードは読み飛ばす。
+*/
+pub fn read_cloud_log(path: &Path) -> Result<Vec<Value>, String> {
+    let is_csv = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("csv"));
+    let records = if is_csv {
+        read_audit_data_csv(path)?
+    } else {
+        read_json_records(&fs::read_to_string(path).map_err(|e| e.to_string())?)?
+    };
+    Ok(records.into_iter().filter_map(convert_record).collect())
+}
+
+fn read_json_records(contents: &str) -> Result<Vec<Value>, String> {
+    let json = match serde_json::from_str::<Value>(contents) {
+        Ok(json) => json,
+        // JSON Lines
+        Err(_) => {
+            return contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+                .collect()
+        }
+    };
+    Ok(match json {
+        Value::Array(records) => records,
+        Value::Object(mut obj) => match obj.remove("value").or_else(|| obj.remove("records")) {
+            Some(Value::Array(records)) => records,
+            _ => vec![Value::Object(obj)],
+        },
+        _ => vec![],
+    })
+}
+
+fn read_audit_data_csv(path: &Path) -> Result<Vec<Value>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    let column = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .position(|header| header == AUDIT_DATA_COLUMN)
+        .ok_or_else(|| format!("{} does not have the AuditData column.", path.display()))?;
+    let mut records = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        if let Some(audit_data) = record.get(column) {
+            records.push(serde_json::from_str(audit_data).map_err(|e| e.to_string())?);
+        }
+    }
+    Ok(records)
+}
+
+fn get_str<'a>(record: &'a Map<String, Value>, key: &str) -> Option<&'a str> {
+    record.get(key).and_then(|value| value.as_str())
+}
+
+/**
+* 1件のレコードをチャンネル、日時、Computerを決めてevtxファイルのレコードと同じ形式にする。
+* Azure Monitorの形式はpropertiesの中身も最上位に展開し、Graph APIのキーにはSentinelの列名と同じ先頭が大文字の別名を付ける。
+*/
+fn convert_record(record: Value) -> Option<Value> {
+    let mut record = match record {
+        Value::Object(record) => record,
+        _ => return None,
+    };
+    let (channel, time, computer) = if record.contains_key("CreationTime") {
+        let workload = get_str(&record, "Workload").unwrap_or("M365");
+        (UAL_CHANNEL, get_str(&record, "CreationTime")?, workload)
+    } else if record.contains_key("createdDateTime") {
+        (
+            SIGNIN_CHANNEL,
+            get_str(&record, "createdDateTime")?,
+            "AzureAD",
+        )
+    } else if record.contains_key("activityDateTime") {
+        (
+            AUDIT_CHANNEL,
+            get_str(&record, "activityDateTime")?,
+            "AzureAD",
+        )
+    } else if record.contains_key("operationName") {
+        let category = get_str(&record, "category").unwrap_or_default();
+        match DIAGNOSTIC_CATEGORIES.iter().find(|(c, _)| *c == category) {
+            Some((_, channel)) => (*channel, get_str(&record, "time")?, "AzureAD"),
+            None => (ACTIVITY_CHANNEL, get_str(&record, "time")?, "Azure"),
+        }
+    } else {
+        return None;
+    };
+    let system = json!({
+        "Provider_attributes": { "Name": channel.split('/').next().unwrap_or_default() },
+        "Channel": channel,
+        "Computer": computer,
+        "TimeCreated_attributes": { "SystemTime": utils::to_system_time(time) },
+    });
+
+    if let Some(Value::Object(properties)) = record.get("properties").cloned() {
+        for (key, value) in properties {
+            record.entry(key).or_insert(value);
+        }
+    }
+    if channel != UAL_CHANNEL {
+        add_sentinel_fields(&mut record);
+    }
+    Some(json!({ "Event": { "System": system, "EventData": record } }))
+}
+
+// SigmaのAzureのルールが使うSentinelの列名(ResultType、IPAddressなど)のフィールドを追加する
+fn add_sentinel_fields(record: &mut Map<String, Value>) {
+    let status = record
+        .get("status")
+        .and_then(|status| status.as_object())
+        .cloned();
+    if let Some(status) = status {
+        if let Some(result_type) = status.get("errorCode") {
+            record.entry("ResultType").or_insert(result_type.clone());
+        }
+        if let Some(result_description) = status.get("failureReason") {
+            record
+                .entry("ResultDescription")
+                .or_insert(result_description.clone());
+        }
+    }
+    // propertiesのようなオブジェクトの値は別名を付けない
+    let aliases: Vec<(String, Value)> = record
+        .iter()
+        .filter(|(_, value)| !value.is_object())
+        .filter_map(|(key, value)| {
+            let alias = match FIELD_ALIASES.iter().find(|(from, _)| *from == key.as_str()) {
+                Some((_, to)) => to.to_string(),
+                None => {
+                    let mut chars = key.chars();
+                    let first = chars.next().filter(|c| c.is_ascii_lowercase())?;
+                    format!("{}{}", first.to_ascii_uppercase(), chars.as_str())
+                }
+            };
+            Some((alias, value.clone()))
+        })
+        .collect();
+    for (alias, value) in aliases {
+        record.entry(alias).or_insert(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud::{convert_record, is_cloud_log_file, read_json_records};
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn test_convert_signin() {
+        let record = convert_record(json!({
+            "id": "abc",
+            "createdDateTime": "2022-05-20T10:00:00Z",
+            "userPrincipalName": "taro@example.com",
+            "ipAddress": "203.0.113.10",
+            "status": { "errorCode": 50126, "failureReason": "Invalid username or password." },
+        }))
+        .unwrap();
+        let system = &record["Event"]["System"];
+        assert_eq!(system["Channel"], "AzureAD/SignInLogs");
+        assert_eq!(system["Computer"], "AzureAD");
+        assert_eq!(
+            system["TimeCreated_attributes"]["SystemTime"],
+            "2022-05-20T10:00:00.000000Z"
+        );
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(event_data["userPrincipalName"], "taro@example.com");
+        assert_eq!(event_data["UserPrincipalName"], "taro@example.com");
+        assert_eq!(event_data["IPAddress"], "203.0.113.10");
+        assert_eq!(event_data["ResultType"], 50126);
+        assert_eq!(event_data["status"]["errorCode"], 50126);
+    }
+
+    #[test]
+    fn test_convert_ual_and_diagnostic() {
+        let record = convert_record(json!({
+            "CreationTime": "2022-05-20T10:00:00",
+            "Operation": "New-InboxRule",
+            "Workload": "Exchange",
+            "UserId": "taro@example.com",
+        }))
+        .unwrap();
+        assert_eq!(record["Event"]["System"]["Channel"], "M365/UnifiedAuditLog");
+        assert_eq!(record["Event"]["System"]["Computer"], "Exchange");
+        assert_eq!(record["Event"]["EventData"]["Operation"], "New-InboxRule");
+
+        let record = convert_record(json!({
+            "time": "2022-05-20T10:00:00.0000000Z",
+            "operationName": "Add member to role",
+            "category": "AuditLogs",
+            "properties": { "activityDisplayName": "Add member to role" },
+        }))
+        .unwrap();
+        assert_eq!(record["Event"]["System"]["Channel"], "AzureAD/AuditLogs");
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(event_data["ActivityDisplayName"], "Add member to role");
+        assert_eq!(
+            event_data["properties"]["activityDisplayName"],
+            "Add member to role"
+        );
+        assert!(convert_record(json!({ "foo": "bar" })).is_none());
+    }
+
+    #[test]
+    fn test_read_json_records() {
+        let records = read_json_records(r#"{"value": [{"id": "1"}, {"id": "2"}]}"#).unwrap();
+        assert_eq!(records.len(), 2);
+        let records = read_json_records("{\"id\": \"1\"}\n{\"id\": \"2\"}\n").unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(read_json_records("{\"id\": \n").is_err());
+        assert!(!is_cloud_log_file(Path::new("test_files/evtx/test1.evtx")));
+    }
+}
@@ -3,13 +3,68 @@ use crate::detections::pivot::PIVOT_KEYWORD;
 use crate::detections::print::AlertMessage;
 use crate::detections::utils;
 use chrono::{DateTime, Utc};
-use clap::{App, AppSettings, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use hashbrown::HashMap;
 use hashbrown::HashSet;
 use lazy_static::lazy_static;
 use regex::Regex;
+use std::env;
 use std::io::BufWriter;
+use std::path::PathBuf;
 use std::sync::RwLock;
+
+/// Hayabusaのディレクトリ内の設定ファイルのディレクトリ名
+pub const CONFIG_DIR_NAME: &str = "config";
+// ユーザーの設定ディレクトリ内のHayabusaの設定ファイルのディレクトリ名
+const USER_CONFIG_DIR_NAME: &str = "hayabusa";
+
+/**
+* ファイルやフォルダを出力するオプションと、指定がない場合にデフォルトのパスに出力する条件のオプションとそのパス。
+* --clobberを指定しない場合に既存のファイルを上書きしないように、解析の前にこの一覧の全ての出力先を確認する。
+* --vt-cacheと--anonymize-mapは次の実行でも読み込むファイルなので含めない。--log-fileはログの設定時に確認する。
+*/
+pub const OUTPUT_FILE_OPTIONS: &[(&str, Option<(&str, &str)>)] = &[
+    ("output", None),
+    ("output-sqlite", None),
+    ("output-parquet", None),
+    ("output-xlsx", None),
+    ("output-jsonl", None),
+    ("output-stix", None),
+    ("output-misp", None),
+    ("html-report", None),
+    ("raw-xml", None),
+    ("save-store", None),
+    ("graph", None),
+    ("activity-matrix", None),
+    ("network-summary", None),
+    ("dns-summary", None),
+    ("service-summary", None),
+    ("task-summary", None),
+    ("account-summary", None),
+    ("kerberos-analytics", None),
+    ("lateral-movement", None),
+    ("lateral-movement-dot", None),
+    ("registry-persistence", None),
+    ("wmi-summary", None),
+    ("bits-summary", None),
+    ("defender-summary", None),
+    ("firewall-summary", None),
+    ("adcs-analytics", None),
+    ("time-integrity", None),
+    (
+        "user-timeline-output",
+        Some(("user-timeline", "user_timeline.csv")),
+    ),
+    ("ioc-output", None),
+    ("host-scores-csv", None),
+    ("rule-summary", None),
+    ("context-output", Some(("context", "context.jsonl"))),
+    ("run-metadata", None),
+    ("custody-log", None),
+    ("diff-resolved", None),
+    ("learn-allowlist", None),
+];
+
 lazy_static! {
     pub static ref CONFIG: RwLock<ConfigReader> = RwLock::new(ConfigReader::new());
     pub static ref LEVELMAP: HashMap<String, u128> = {
@@ -27,12 +82,44 @@ lazy_static! {
     ));
     pub static ref IDS_REGEX: Regex =
         Regex::new(r"^[0-9a-z]{8}-[0-9a-z]{4}-[0-9a-z]{4}-[0-9a-z]{4}-[0-9a-z]{12}$").unwrap();
+    /// --exact-levelで指定したレベル。指定した場合はこのレベルのルールだけを読み込む。不正な値は解析の前にexact_levels_from_configでエラーにする
+    pub static ref EXACT_LEVELS: Option<HashSet<String>> =
+        exact_levels_from_config().ok().flatten();
+}
+
+pub fn exact_levels_from_config() -> Result<Option<HashSet<String>>, String> {
+    parse_exact_levels(CONFIG.read().unwrap().args.value_of("exact-level"))
+}
+
+/// カンマ区切りのレベルを大文字にして返す。infoはinformationalとして扱い、不正なレベルはエラーにする
+fn parse_exact_levels(value: Option<&str>) -> Result<Option<HashSet<String>>, String> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let mut levels = HashSet::new();
+    for level in value.split(',').map(|level| level.trim().to_uppercase()) {
+        let level = if level == "INFO" {
+            "INFORMATIONAL".to_string()
+        } else {
+            level
+        };
+        if !LEVELMAP.contains_key(&level) {
+            return Err(format!(
+                "Invalid --exact-level: {} is not a valid level.",
+                level.to_lowercase()
+            ));
+        }
+        levels.insert(level);
+    }
+    Ok(Some(levels))
 }
 
 #[derive(Clone)]
 pub struct ConfigReader {
     pub args: ArgMatches<'static>,
     pub folder_path: String,
+    pub config_dir: PathBuf,
     pub event_timeline_config: EventInfoConfig,
     pub target_eventids: TargetEventIds,
 }
@@ -47,16 +134,109 @@ impl ConfigReader {
     pub fn new() -> Self {
         let arg = build_app();
         let folder_path_str = arg.value_of("config").unwrap_or("rules/config").to_string();
+        // 見つからない場合は従来通りカレントディレクトリのconfigを使い、exec時にエラーにする
+        let config_dir = find_config_dir(arg.value_of("config-dir"))
+            .unwrap_or_else(|| PathBuf::from(CONFIG_DIR_NAME));
+        let event_timeline_config = load_eventcode_info(
+            &config_dir
+                .join("statistics_event_info.txt")
+                .display()
+                .to_string(),
+        );
+        let target_eventids =
+            load_target_ids(&config_dir.join("target_eventids.txt").display().to_string());
         ConfigReader {
             args: arg,
             folder_path: folder_path_str,
-            event_timeline_config: load_eventcode_info("config/statistics_event_info.txt"),
-            target_eventids: load_target_ids("config/target_eventids.txt"),
+            config_dir,
+            event_timeline_config,
+            target_eventids,
         }
     }
 }
 
+/// 設定ファイルのディレクトリ内のファイルのパスを返す
+pub fn config_path(filename: &str) -> String {
+    CONFIG
+        .read()
+        .unwrap()
+        .config_dir
+        .join(filename)
+        .display()
+        .to_string()
+}
+
+/**
+* 設定ファイルのディレクトリを探す。--config-dirで指定したディレクトリがあればそれを使い、
+* なければカレントディレクトリ、実行ファイルのディレクトリ、ユーザーの設定ディレクトリの順に探して最初に見つかったディレクトリを返す。
+* PATHの通ったディレクトリに置いた実行ファイルをどこからでも実行できるようにするため。
+*/
+pub fn find_config_dir(config_dir: Option<&str>) -> Option<PathBuf> {
+    if let Some(config_dir) = config_dir {
+        return Some(PathBuf::from(config_dir));
+    }
+    config_dir_candidates()
+        .into_iter()
+        .find(|candidate| candidate.is_dir())
+}
+
+/// 設定ファイルのディレクトリを探す場所を探す順番で返す
+fn config_dir_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(CONFIG_DIR_NAME)];
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        candidates.push(exe_dir.join(CONFIG_DIR_NAME));
+    }
+    if let Some(user_config_dir) = user_config_dir() {
+        candidates.push(user_config_dir.join(USER_CONFIG_DIR_NAME));
+    }
+    candidates
+}
+
+// ユーザーの設定ディレクトリ。Windowsは%APPDATA%、それ以外は$XDG_CONFIG_HOMEか~/.config
+fn user_config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(PathBuf::from);
+    }
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// 出力先のオプションで指定されたパス。指定がない場合はOUTPUT_FILE_OPTIONSのデフォルトのパスを返す
+pub fn output_path(args: &ArgMatches, option: &str) -> Option<String> {
+    if let Some(path) = args.value_of(option) {
+        return Some(path.to_string());
+    }
+    OUTPUT_FILE_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == option)
+        .and_then(|(_, default)| *default)
+        .filter(|(condition, _)| args.is_present(condition))
+        .map(|(_, path)| path.to_string())
+}
+
+/// 実行した時に出力されるファイルとフォルダのパスを、オプションの名前と一緒に返す
+pub fn output_paths(args: &ArgMatches) -> Vec<(&'static str, String)> {
+    OUTPUT_FILE_OPTIONS
+        .iter()
+        .filter_map(|(option, _)| output_path(args, option).map(|path| (*option, path)))
+        .collect()
+}
+
 fn build_app<'a>() -> ArgMatches<'a> {
+    if is_test_mode() {
+        return ArgMatches::default();
+    }
+
+    build_cli().get_matches()
+}
+
+/// コマンドラインオプションの定義。シェルの補完スクリプトやmanページの生成にも使う
+pub fn build_cli() -> App<'static, 'static> {
     let program = std::env::args()
         .next()
         .and_then(|s| {
@@ -66,34 +246,136 @@ fn build_app<'a>() -> ArgMatches<'a> {
         })
         .unwrap();
 
-    if is_test_mode() {
-        return ArgMatches::default();
-    }
-
-    let usages = "-d --directory=[DIRECTORY] 'Directory of multiple .evtx files.'
-    -f --filepath=[FILEPATH] 'File path to one .evtx file.'
+    let usages = "-d --directory=[DIRECTORY] 'Directory of multiple .evtx files, legacy .evt files and Linux audit.log and syslog files.'
+    --max-depth=[NUMBER] 'Maximum depth of subdirectories to scan with -d. (Default: no limit)'
+    --follow-symlinks 'Follow symbolic links and junctions when scanning directories. (Default: not followed)'
+    --exclude-path=[GLOB]... 'Skip the files and directories matching the glob pattern when scanning directories. (Example: --exclude-path '*\\Backup*')'
+    --triage=[DIRECTORY] 'Directory of KAPE or Velociraptor triage collections. Event logs are located per host and detections are tagged with the hostname inferred from the directory structure.'
+    --remote-hosts=[HOST_LIST] 'Text file listing the remote hosts to collect the .evtx files from over the ADMIN$ share (SMB) and analyze. Detections are tagged with the hostname. (Windows Only. One host per line. Lines starting with # are ignored.)'
+    --remote-work-dir=[DIRECTORY] 'Directory to save the .evtx files collected with --remote-hosts in. (Default: ./remote-evtx)'
+    -f --filepath=[FILEPATH]... 'File path to one .evtx file, legacy .evt file, .etl trace file, Linux audit.log or syslog file, Defender for Endpoint Advanced Hunting CSV/JSON export, or Azure AD sign-in/audit log or Microsoft 365 Unified Audit Log JSON export. .etl files are converted with tracerpt (Windows only). Can be specified multiple times and accepts glob patterns. (Example: -f 'C:\\logs\\DC*\\Security.evtx')'
+    --file-list=[FILE_LIST] 'Text file listing the .evtx files to analyze. (One path or glob pattern per line. Lines starting with # are ignored.)'
+    --save-store=[FILE] 'Save the parsed records to a compressed record store so that they can be analyzed again with --from-store without parsing the .evtx files. (Example: records.jsonl.gz)'
+    --from-store=[FILE] 'Analyze the records saved with --save-store instead of .evtx files. Use this to rerun the analysis with different levels, filters or profiles.'
+    --no-ext-check 'Identify evtx files by their file signature instead of the .evtx extension. (Example: Security.evtx.bak)'
     -F --full-data 'Print all field information.'
-    -r --rules=[RULEDIRECTORY/RULEFILE] 'Rule file or directory (default: ./rules)'
+    -r --rules=[RULEDIRECTORY/RULEFILE/URL] 'Rule file or directory, or the HTTPS URL of a .tar.gz ruleset (default: ./rules)'
+    --rules-sha256=[HASH] 'SHA256 hash of the rules archive when -r is an HTTPS URL. (Default: the hash in the URL with .sha256)'
+    --sigma-rules=[DIRECTORY] 'Directory of upstream Sigma rules to convert and load at runtime.'
     -C --config=[RULECONFIGDIRECTORY] 'Rule config folder. (Default: ./rules/config)'
+    --use-embedded-rules 'Use the rules embedded into the binary when it was built with the embedded-rules feature.'
+    --config-dir=[DIRECTORY] 'Hayabusa config folder. (Default: ./config, the config folder next to the executable or the hayabusa folder in the user config folder)'
     -o --output=[CSV_TIMELINE] 'Save the timeline in CSV format. (Example: results.csv)'
-    -v --verbose 'Output verbose information.'
+    --output-auto=[TEMPLATE] 'Save the timeline in CSV format with a file name made from a template. {hostname} is the computer name of the detections (multiple if there are several), {start} and {end} are the first and last detection times in UTC and {now} is the run time. A number is appended if the file already exists unless --clobber is used. (Example: {hostname}_{start}_{end}_hayabusa.csv)'
+    --clobber 'Overwrite the output files and folders of all options (including the pivot keyword files and the log file) if they already exist.'
+    --output-sqlite=[DB_FILE] 'Save the detections, rule metadata and run metadata to a SQLite database. (Example: results.db)'
+    --output-parquet=[PARQUET_FILE] 'Save the timeline in Parquet format. (Example: results.parquet)'
+    --html-report=[DIRECTORY] 'Save a summary report of the detections in HTML format. (Example: report)'
+    --output-xlsx=[XLSX_FILE] 'Save the timeline in Excel format. (Example: results.xlsx)'
+    --xlsx-split=[level/computer] 'Split the Excel output into sheets by level or computer. (Default: level)'
+    --csv-delimiter=[comma/tab/pipe/semicolon] 'Delimiter of the CSV timeline. (Default: comma)'
+    --csv-quote=[necessary/always/non-numeric] 'When to quote the fields of the CSV timeline. (Default: necessary)'
+    --csv-bom 'Write a UTF-8 BOM at the start of the CSV timeline.'
+    --csv-encoding=[utf-8/shift_jis] 'Character encoding of the CSV timeline. (Default: utf-8)'
+    --output-jsonl=[JSONL_FILE] 'Save the timeline in JSON Lines format. (Example: results.jsonl)'
+    --json-schema=[hayabusa/ecs/ocsf] 'Field names of the JSON Lines timeline. ecs uses the Elastic Common Schema and ocsf the OCSF Security Finding class. (Default: hayabusa)'
+    --rule-metadata=[FIELDS] 'Add rule metadata columns to the CSV and JSON Lines timelines: all or a comma-separated list of description, references, falsepositives, author, date and modified.'
+    --raw-xml=[DIRECTORY] 'Save the original XML of each detected record to a directory with an index.csv of the detection IDs. (Example: raw_xml)'
+    --output-stix=[JSON_FILE] 'Save the detections as a STIX 2.1 bundle with an indicator per rule and observed-data and sightings per detection. (Example: results.stix.json)'
+    -v --verbose... 'Output verbose information. Use -vv to also output debug information.'
+    --log-file=[FILE] 'Save the errors, warnings and verbose information to a file instead of stderr. (Example: hayabusa.log)'
+    --log-format=[text/json] 'Format of the errors, warnings and verbose information. (Default: text)'
     -D --enable-deprecated-rules 'Enable rules marked as deprecated.'
     -n --enable-noisy-rules 'Enable rules marked as noisy.'
     -u --update-rules 'Update to the latest rules in the hayabusa-rules github repository.'
     -m --min-level=[LEVEL] 'Minimum level for rules. (Default: informational)'
+    --exact-level=[LEVELS] 'Only load the rules with the specified levels separated by commas. (Example: high,critical)'
+    --filter-output=[EXPRESSION] 'Only output the detections matching the expression. Fields: Computer, Channel, EventID, RecordID, Level, RuleTitle, Details, MitreAttack, RulePath, FilePath. (Example: \"Computer == DC01 and Level >= high\")'
     -l --live-analysis 'Analyze the local C:\\Windows\\System32\\winevt\\Logs folder (Windows Only. Administrator privileges required.)'
+    --low-priority 'Lower the CPU and I/O priority and use at most 2 threads by default so that --live-analysis does not compete with the workload on production servers.'
     --start-timeline=[STARTTIMELINE] 'Start time of the event logs to load. (Example: '2018/11/28 12:00:00 +09:00')'
     --end-timeline=[ENDTIMELINE] 'End time of the event logs to load. (Example: '2018/11/28 12:00:00 +09:00')'
     --rfc-2822 'Output date and time in RFC 2822 format. (Example: Mon, 07 Aug 2006 12:34:56 -0600)'
     --rfc-3339 'Output date and time in RFC 3339 format. (Example: 2006-08-07T12:34:56.485214 -06:00)'
     -U --utc 'Output time in UTC format. (Default: local time)'
     --no-color 'Disable color output'
+    --progress=[bar/json] 'Format of the progress. json prints the progress events to stderr for wrapper tools. (Default: bar)'
     -t --thread-number=[NUMBER] 'Thread number. (Default: Optimal number for performance.)'
+    --chunk-size=[NUMBER] 'Number of records to analyze at once. (Default: 1000 per thread, between 5000 and 64000)'
+    --parser-threads=[NUMBER] 'Number of threads to parse the evtx files with. (Default: Number of CPUs)'
     -s --statistics 'Prints statistics of event IDs.'
     -L --logon-summary 'Successful and failed logons summary.'
     -q --quiet 'Quiet mode. Do not display the launch banner.'
     -Q --quiet-errors 'Quiet errors mode. Do not save error logs.'
+    --recover-corrupted 'Repair the chunks of partially corrupted or truncated evtx files that failed to parse by scanning for record signatures, reparse them without validating the checksums, and show the recovered and lost records and skipped chunks per file.'
+    --scan-all-files 'Analyze all the evtx files even if their channel is not used by any of the loaded rules.'
+    --add-rule-eids 'Add the event IDs used by the loaded rules to the event IDs in config/target_eventids.txt.'
+    --deep-scan 'Disable the event ID filter and analyze all records. (Slower)'
     -p --pivot-keywords-list 'Create a list of pivot keywords.'
+    --merge-records 'Merge records from all evtx files in chronological order before detection.'
+    --sort 'Sort the timeline with an on-disk merge sort to limit memory usage on large results.'
+    --log-metrics 'Prints metrics (record count, timeframe, channels, file size, computer names) of each event file.'
+    --provider-metrics 'Prints statistics of events per channel and provider name.'
+    --activity-matrix=[CSV_FILE] 'Count the events per computer and hour and save them as a matrix in CSV format to check activity patterns and gaps. (Example: activity.csv)'
+    --coverage-gaps 'Reports the channels and event IDs needed by the loaded rules that were not found in the logs.'
+    --network-summary=[CSV_FILE] 'Summarize the outbound Sysmon network connections per host, process and destination and save them in CSV format. (Example: network.csv)'
+    --dns-summary=[CSV_FILE] 'Summarize the DNS queries per host, highlight rare domains and save them in CSV format. (Example: dns.csv)'
+    --service-summary=[CSV_FILE] 'Summarize the service installations and driver loads per host and save them in CSV format. (Example: services.csv)'
+    --task-summary=[CSV_FILE] 'List the scheduled task creations, updates and deletions per host with their command lines and save them in CSV format. (Example: tasks.csv)'
+    --account-summary=[CSV_FILE] 'List the account creations, enables, password resets and group additions per domain and save them in CSV format. (Example: accounts.csv)'
+    --kerberos-analytics=[CSV_FILE] 'Detect Kerberoasting, AS-REP roasting and excessive pre-authentication failures and save them in CSV format. Thresholds are set in config/kerberos_analytics.txt. (Example: kerberos.csv)'
+    --lateral-movement=[CSV_FILE] 'Correlate remote logons, explicit credentials, privileged logons, share access and service creations into source to destination host edges and save them in CSV format. (Example: lateral.csv)'
+    --lateral-movement-dot=[DOT_FILE] 'Save the lateral movement edges as a Graphviz DOT graph. (Example: lateral.dot)'
+    --registry-persistence=[CSV_FILE] 'Summarize the Sysmon registry writes to autorun locations per host and save them in CSV format. The registry paths are set in config/persistence_registry_paths.txt. (Example: registry.csv)'
+    --wmi-summary=[CSV_FILE] 'List the WMI event filter, consumer and binding creations and temporary consumers per host and save them in CSV format. (Example: wmi.csv)'
+    --bits-summary=[CSV_FILE] 'List the BITS jobs per host with their URLs and transferred bytes and save them in CSV format. Allowed domains are set in config/bits_allowlist.txt. (Example: bits.csv)'
+    --defender-summary=[CSV_FILE] 'List the Windows Defender detections, actions taken and exclusion changes per host and save them in CSV format. (Example: defender.csv)'
+    --firewall-summary=[CSV_FILE] 'List the Windows Firewall rule additions, modifications and deletions per host with the responsible process and save them in CSV format. (Example: firewall.csv)'
+    --adcs-analytics=[CSV_FILE] 'Detect certificate requests with a subject alternative name of another account (ESC1) and logons with those certificates and save them in CSV format. (Example: adcs.csv)'
+    --time-integrity=[CSV_FILE] 'Flag events with future timestamps, large backward time jumps and system time changes (Security 4616, System 1) and save them in CSV format. (Example: time_integrity.csv)'
+    --user-timeline=[USER] 'Extract the authentication, process and object access events of an account (name, DOMAIN\\name or SID) from all files into a timeline in CSV format.'
+    --user-timeline-output=[CSV_FILE] 'File to save the --user-timeline events in. (Default: user_timeline.csv)'
+    --ioc-file=[FILE] 'Flag the events containing the IPs, domains, hashes or filenames in an IOC list (CSV with Type,Value,Description columns or a STIX 2.x JSON bundle), independent of the rules.'
+    --ioc-output=[CSV_FILE] 'Save the events matching the IOCs in CSV format. (Example: ioc.csv)'
+    --output-misp=[JSON_FILE] 'Extract the IPs, domains, hashes and file names in the detection details and save them as a MISP event in JSON format. (Example: misp.json)'
+    --misp-url=[URL] 'Push the extracted indicators to a MISP instance as a new event. (Example: https://misp.example.com)'
+    --misp-key=[API_KEY] 'MISP API key. (Default: MISP_API_KEY environment variable)'
+    --scan-decoded-commands 'Also scan the decoded PowerShell -EncodedCommand payloads against the rules.'
+    --host-scores 'Prints a ranking of the most suspicious hosts scored by detection level and unique rules.'
+    --host-scores-csv=[CSV_FILE] 'Save the host scores ranking in CSV format. (Example: host-scores.csv)'
+    --rule-summary=[CSV_FILE] 'Save each rule that fired with its hit count, number of computers and first and last timestamps in CSV format. (Example: rule_summary.csv)'
+    --context=[NUMBER] 'Save the NUMBER events before and after each detection on the same computer and channel.'
+    --context-output=[JSONL_FILE] 'File to save the context events in. (Default: context.jsonl)'
+    --search=[KEYWORD] 'Search all fields of all records for a keyword without using rules. (Case-insensitive)'
+    --regex 'Treat the --search keyword as a regular expression.'
+    --run-metadata=[JSON_FILE] 'Save the run metadata (versions, command line, file hashes, record and error counts, duration) in JSON format.'
+    --custody-log=[CSV_FILE] 'Save the SHA-256 hash, size and timestamps of each input file, computed before the analysis, with the analyst machine and hayabusa version to a CSV file for chain of custody.'
+    --json-error-log 'Save the error log in JSON format with the error class, file path and record ID.'
+    --test-rules 'Test the rules against the positive and negative sample events in their samples field.'
+    --splunk-hec-url=[URL] 'Send the detections to a Splunk HTTP Event Collector. (Example: https://splunk.example.com:8088)'
+    --splunk-hec-token=[TOKEN] 'Splunk HEC token. (Default: SPLUNK_HEC_TOKEN environment variable)'
+    --syslog=[HOST:PORT] 'Send the detections to a syslog server. (Example: siem.example.com:514)'
+    --syslog-protocol=[PROTOCOL] 'Protocol to send syslog messages with: udp, tcp or tls. (Default: udp)'
+    --syslog-format=[FORMAT] 'Format of the syslog messages: rfc5424 or cef. (Default: rfc5424)'
+    --write-eventlog 'Write the critical and high detections to the Hayabusa event log on the local computer so that the SIEM agents on the host pick them up. (Windows Only. Administrator privileges required.)'
+    --notify-webhook=[URL] 'Post a summary of the detections as JSON to a Slack, Teams or generic webhook when the scan finishes.'
+    --notify-level=[LEVEL] 'Minimum level of the detections to notify with --notify-webhook. (Default: high)'
+    --notify-each-critical 'Also post each critical detection to the webhook. (Up to 20 per run)'
+    --vt-api-key=[API_KEY] 'Look up the hashes of the detected Sysmon events on VirusTotal and add the VTHash and VTDetections columns to the CSV output. (Offline by default)'
+    --vt-rate-limit=[NUMBER] 'Maximum number of hash lookups per minute. (Default: 4)'
+    --vt-cache=[CSV_FILE] 'File to cache the hash lookup results in between runs. (Example: vt_cache.csv)'
+    --hash-lookup-url=[URL] 'VirusTotal API v3 compatible URL to look up the hashes with. {hash} is replaced with the hash. (Default: VirusTotal)'
+    --anonymize 'Replace the usernames, hostnames, domains and internal IP addresses in all outputs with consistent pseudonyms.'
+    --anonymize-map=[CSV_FILE] 'File to save the pseudonym mapping in to de-anonymize the results later. Reused on the next run if it exists. (Example: anonymize_map.csv)'
+    --redact 'Replace the text in the details matching the regexes in config/redaction_rules.txt (e.g. email addresses or employee IDs).'
+    --diff=[JSONL_FILE] 'Only output the detections that are not in the results of a previous run saved with --output-jsonl. The --output-jsonl file still has all the detections to compare with next time.'
+    --diff-resolved=[JSONL_FILE] 'Save the detections of the previous run that are no longer detected with --diff. (Example: resolved.jsonl)'
+    --learn-allowlist=[YAML_FILE] 'Learn an allowlist of the detections (rule ID and key field values) on a known-clean reference system. (Example: allowlist.yaml)'
+    --allowlist=[YAML_FILE] 'Suppress the detections matching an allowlist learned with --learn-allowlist.'
+    --graph=[FILE] 'Save a graph of the hosts, users, processes and IP addresses in the detections and their relationships (logon from, spawned, connected to) for Gephi or Maltego. The format is chosen by the extension: .dot, .gexf or .json. (Example: graph.gexf)'
+    --auto-tune-noise=[NUMBER] 'Collapse the detections with the same rule and details that fired more than the number of times into a single row with the count. (Example: 1000)'
+    --min-rule-count=[NUMBER] 'Do not output the rules that fired fewer than the number of times. Set per level with level:number separated by commas. (Example: 5 or low:10,medium:3)'
+    --max-rule-count=[NUMBER] 'Do not output the rules that fired more than the number of times. Set per level with level:number separated by commas. (Example: 10000 or informational:1000)'
     --contributors 'Prints the list of contributors.'";
     App::new(&program)
         .about("Hayabusa: Aiming to be the world's greatest Windows event log analysis tool!")
@@ -107,7 +389,40 @@ fn build_app<'a>() -> ArgMatches<'a> {
         )
         .usage(usages)
         .args_from_usage(usages)
-        .get_matches()
+        .arg(
+            Arg::with_name("generate-completion")
+                .long("generate-completion")
+                .value_name("SHELL")
+                .possible_values(&["bash", "zsh", "fish", "powershell", "man"])
+                .help("Print a shell completion script or a man page.")
+                .hidden(true),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measure parsing and rule evaluation throughput with synthetic records or an .evtx file.")
+                .args_from_usage(
+                    "-f --filepath=[FILEPATH] 'Replay an .evtx file instead of generating synthetic records.'
+                    -r --rules=[RULEDIRECTORY/RULEFILE] 'Rule file or directory (default: ./rules)'
+                    --records=[NUMBER] 'Number of synthetic records to generate. (Default: 100000)'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert-sigma")
+                .about("Convert upstream Sigma rules to hayabusa rules with the logsource and field mapping.")
+                .args_from_usage(
+                    "<DIRECTORY> 'Directory or file of the Sigma rules to convert.'
+                    -o --output=[DIRECTORY] 'Directory to save the converted rules in. (Default: ./rules/sigma-converted)'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Browse the detections of a results CSV and pivot to the related events in a record store.")
+                .args_from_usage(
+                    "<RESULTS> 'CSV timeline saved with -o.'
+                    -s --store=<FILE> 'Record store saved with --save-store during the same scan.'
+                    --minutes=[MINUTES] 'Minutes before and after the detection to pivot on. (Default: 10)'",
+                ),
+        )
 }
 
 fn is_test_mode() -> bool {
@@ -138,6 +453,16 @@ impl TargetEventIds {
         }
     }
 
+    /// 空の場合は全てのイベントIDが対象になる
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// 読み込んだルールが使うイベントIDを対象に加える
+    pub fn extend(&mut self, ids: HashSet<String>) {
+        self.ids.extend(ids);
+    }
+
     pub fn is_target(&self, id: &str) -> bool {
         // 中身が空の場合は全EventIdを対象とする。
         if self.ids.is_empty() {
@@ -425,6 +750,7 @@ fn load_eventcode_info(path: &str) -> EventInfoConfig {
 mod tests {
     use crate::detections::configs;
     use chrono::{DateTime, Utc};
+    use std::path::PathBuf;
 
     //     #[test]
     //     #[ignore]
@@ -468,4 +794,86 @@ mod tests {
         assert!(time_filter.is_target(&start_time));
         assert!(time_filter.is_target(&end_time));
     }
+
+    #[test]
+    fn test_find_config_dir() {
+        assert_eq!(
+            configs::find_config_dir(Some("test_files/config")),
+            Some(PathBuf::from("test_files/config"))
+        );
+        // テストはリポジトリのルートで実行するのでカレントディレクトリのconfigが見つかる
+        assert_eq!(
+            configs::find_config_dir(None),
+            Some(PathBuf::from(configs::CONFIG_DIR_NAME))
+        );
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let args = configs::build_cli().get_matches_from(vec![
+            "hayabusa",
+            "-d",
+            "logs",
+            "--output-misp",
+            "misp.json",
+            "--html-report",
+            "report",
+            "--user-timeline",
+            "alice",
+        ]);
+        assert_eq!(
+            configs::output_paths(&args),
+            vec![
+                ("output-misp", "misp.json".to_string()),
+                ("html-report", "report".to_string()),
+                ("user-timeline-output", "user_timeline.csv".to_string()),
+            ]
+        );
+        assert_eq!(configs::output_path(&args, "context-output"), None);
+
+        // 一覧の全てのオプションがパスを受け取るオプションとして定義されている
+        for (option, _) in configs::OUTPUT_FILE_OPTIONS {
+            let args = configs::build_cli().get_matches_from_safe(vec![
+                "hayabusa".to_string(),
+                format!("--{}", option),
+                "out".to_string(),
+            ]);
+            assert_eq!(
+                args.ok()
+                    .and_then(|args| args.value_of(option).map(|path| path.to_string())),
+                Some("out".to_string()),
+                "{}",
+                option
+            );
+        }
+    }
+
+    #[test]
+    fn test_target_eventids_extend() {
+        let mut target_eventids = configs::TargetEventIds::new();
+        assert!(target_eventids.is_empty());
+        assert!(target_eventids.is_target("4625"));
+
+        target_eventids.extend(["4624".to_string()].into_iter().collect());
+        target_eventids.extend(["4625".to_string()].into_iter().collect());
+        assert!(!target_eventids.is_empty());
+        assert!(target_eventids.is_target("4624"));
+        assert!(target_eventids.is_target("4625"));
+        assert!(!target_eventids.is_target("4688"));
+    }
+
+    #[test]
+    fn test_parse_exact_levels() {
+        let levels = configs::parse_exact_levels(Some("High, info"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(levels.len(), 2);
+        assert!(levels.contains("HIGH") && levels.contains("INFORMATIONAL"));
+        assert_eq!(configs::parse_exact_levels(None).unwrap(), None);
+        // 不正なレベルが1つでもあれば全てのルールを読み込まないようにエラーにする
+        assert_eq!(
+            configs::parse_exact_levels(Some("high,severe")).unwrap_err(),
+            "Invalid --exact-level: severe is not a valid level."
+        );
+    }
 }
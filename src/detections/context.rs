@@ -0,0 +1,243 @@
+use crate::detections::configs;
+use crate::detections::detection::EvtxRecordInfo;
+use crate::detections::print::AlertMessage;
+use crate::detections::utils;
+use crate::output::anonymize;
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+lazy_static! {
+    /// --contextで指定された、検知したレコードの前後に出力するレコード数
+    pub static ref CONTEXT_NUM: Option<usize> = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("context")
+        .and_then(|num| num.parse::<usize>().ok());
+    pub static ref CONTEXT_COLLECTOR: Mutex<ContextCollector> = Mutex::new(ContextCollector::new(
+        CONTEXT_NUM.unwrap_or(0),
+        &configs::output_path(&configs::CONFIG.read().unwrap().args, "context-output")
+            .unwrap_or_default(),
+    ));
+}
+
+// 検知したレコードの後ろのレコードを出力し終わっていない検知
+struct PendingContext {
+    detection_id: usize,
+    key: (String, String),
+    remaining: usize,
+}
+
+/**
+* 検知したレコードの前後N件の同じComputer、Channelのレコードを別ファイルにJSON Lines形式で出力する
+*/
+pub struct ContextCollector {
+    num: usize,
+    output_path: String,
+    writer: Option<BufWriter<File>>,
+    detection_count: usize,
+    // 現在解析中のレコードのまとまりで検知したレコードのインデックスとルールのパス
+    hits: Vec<(usize, String)>,
+    history: HashMap<(String, String), VecDeque<Value>>,
+    pending: Vec<PendingContext>,
+}
+
+impl ContextCollector {
+    pub fn new(num: usize, output_path: &str) -> ContextCollector {
+        ContextCollector {
+            num,
+            output_path: output_path.to_string(),
+            writer: None,
+            detection_count: 0,
+            hits: vec![],
+            history: HashMap::new(),
+            pending: vec![],
+        }
+    }
+
+    /// ルールに合致したレコードを記録する
+    pub fn add_hit(&mut self, record_idx: usize, rulepath: &str) {
+        self.hits.push((record_idx, rulepath.to_string()));
+    }
+
+    fn get_key(record: &Value) -> (String, String) {
+        let get_value = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        (
+            get_value("Event.System.Computer"),
+            get_value("Event.System.Channel"),
+        )
+    }
+
+    /// 1まとまり分のレコードについて、検知したレコードとその前後のレコードを出力する
+    pub fn process_records(&mut self, records: &[EvtxRecordInfo]) -> Result<(), String> {
+        let mut hits = std::mem::take(&mut self.hits);
+        hits.sort();
+        let mut hits = hits.into_iter().peekable();
+        for (idx, record_info) in records.iter().enumerate() {
+            let key = ContextCollector::get_key(&record_info.record);
+
+            // 直前の検知の後ろのレコードとして出力する
+            let mut after_records = vec![];
+            for pending in self.pending.iter_mut() {
+                if pending.remaining > 0 && pending.key == key {
+                    after_records.push((pending.detection_id, self.num - pending.remaining + 1));
+                    pending.remaining -= 1;
+                }
+            }
+            self.pending.retain(|pending| pending.remaining > 0);
+            for (detection_id, offset) in after_records {
+                self.write_line(detection_id, None, offset as i64, &record_info.record)?;
+            }
+
+            while let Some((_, rulepath)) = hits.next_if(|(hit_idx, _)| *hit_idx == idx) {
+                self.detection_count += 1;
+                let detection_id = self.detection_count;
+                let before_records: Vec<Value> = self
+                    .history
+                    .get(&key)
+                    .map(|history| history.iter().cloned().collect())
+                    .unwrap_or_default();
+                let before_count = before_records.len() as i64;
+                for (i, record) in before_records.iter().enumerate() {
+                    self.write_line(detection_id, None, i as i64 - before_count, record)?;
+                }
+                self.write_line(detection_id, Some(&rulepath), 0, &record_info.record)?;
+                if self.num > 0 {
+                    self.pending.push(PendingContext {
+                        detection_id,
+                        key: key.clone(),
+                        remaining: self.num,
+                    });
+                }
+            }
+
+            if self.num > 0 {
+                let history = self.history.entry(key).or_insert_with(VecDeque::new);
+                if history.len() == self.num {
+                    history.pop_front();
+                }
+                history.push_back(record_info.record.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn write_line(
+        &mut self,
+        detection_id: usize,
+        rulepath: Option<&str>,
+        offset: i64,
+        record: &Value,
+    ) -> Result<(), String> {
+        if self.writer.is_none() {
+            let file = File::create(&self.output_path).map_err(|e| e.to_string())?;
+            self.writer = Some(BufWriter::new(file));
+        }
+        let line = json!({
+            "DetectionId": detection_id,
+            "RulePath": rulepath,
+            "Offset": offset,
+            "Record": record,
+        });
+        writeln!(
+            self.writer.as_mut().unwrap(),
+            "{}",
+            anonymize::anonymize_output(&line.to_string())
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writer.flush() {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write context events. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::context::ContextCollector;
+    use crate::detections::detection::EvtxRecordInfo;
+    use serde_json::Value;
+    use std::fs;
+
+    fn create_record(computer: &str, record_id: usize) -> EvtxRecordInfo {
+        let record: Value = serde_json::from_str(&format!(
+            r#"{{"Event": {{"System": {{"Computer": "{}", "Channel": "Security", "EventRecordID": {}}}}}}}"#,
+            computer, record_id
+        ))
+        .unwrap();
+        EvtxRecordInfo {
+            evtx_filepath: "a.evtx".to_string(),
+            record,
+            data_string: String::default(),
+            key_2_value: hashbrown::HashMap::new(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_context_records() {
+        let output_path = "./test_files/context-test.jsonl";
+        let mut collector = ContextCollector::new(2, output_path);
+        let records: Vec<EvtxRecordInfo> = vec![
+            create_record("PC01", 1),
+            create_record("PC02", 2),
+            create_record("PC01", 3),
+            create_record("PC01", 4),
+        ];
+        collector.process_records(&records).unwrap();
+        collector.add_hit(1, "rule.yml");
+        collector.add_hit(0, "rule.yml");
+        // 後ろのレコードは次のまとまりから出力される
+        let records: Vec<EvtxRecordInfo> = vec![
+            create_record("PC01", 5),
+            create_record("PC02", 6),
+            create_record("PC01", 7),
+        ];
+        collector.process_records(&records).unwrap();
+        collector.flush();
+
+        let lines: Vec<(u64, i64, u64)> = fs::read_to_string(output_path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: Value = serde_json::from_str(line).unwrap();
+                (
+                    value["DetectionId"].as_u64().unwrap(),
+                    value["Offset"].as_i64().unwrap(),
+                    value["Record"]["Event"]["System"]["EventRecordID"]
+                        .as_u64()
+                        .unwrap(),
+                )
+            })
+            .collect();
+        fs::remove_file(output_path).ok();
+        assert_eq!(
+            lines,
+            vec![
+                (1, -2, 3),
+                (1, -1, 4),
+                (1, 0, 5),
+                (2, -1, 2),
+                (2, 0, 6),
+                (1, 1, 7),
+            ]
+        );
+    }
+}
@@ -1,6 +1,9 @@
 extern crate csv;
 
 use crate::detections::configs;
+use crate::detections::context::{CONTEXT_COLLECTOR, CONTEXT_NUM};
+use crate::detections::graph::{self, GRAPH_FLAG};
+use crate::detections::hash_lookup::{register_detection_hash, HASH_LOOKUP_FLAG};
 use crate::detections::pivot::insert_pivot_keyword;
 use crate::detections::print::AlertMessage;
 use crate::detections::print::DetectInfo;
@@ -9,12 +12,15 @@ use crate::detections::print::MESSAGES;
 use crate::detections::print::PIVOT_KEYWORD_LIST_FLAG;
 use crate::detections::print::QUIET_ERRORS_FLAG;
 use crate::detections::print::STATISTICS_FLAG;
+use crate::detections::print::{ErrorClass, ErrorLog};
 use crate::detections::print::{CH_CONFIG, TAGS_CONFIG};
 use crate::detections::rule;
 use crate::detections::rule::AggResult;
 use crate::detections::rule::RuleNode;
+use crate::detections::suppression::{self, LEARN_ALLOWLIST_FLAG};
 use crate::detections::utils::get_serde_number_to_string;
 use crate::filter;
+use crate::logging;
 use crate::yaml::ParseYaml;
 use hashbrown;
 use hashbrown::HashMap;
@@ -64,18 +70,27 @@ impl Detection {
     ) -> Vec<RuleNode> {
         // ルールファイルのパースを実行
         let mut rulefile_loader = ParseYaml::new();
-        let result_readdir =
+        let mut result_readdir =
             rulefile_loader.read_dir(rulespath.unwrap_or(DIRPATH_RULES), &level, exclude_ids);
-        if result_readdir.is_err() {
-            let errmsg = format!("{}", result_readdir.unwrap_err());
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
-            }
+        // --sigma-rulesで指定したSigmaHQのルールは読み込み時に変換する
+        let sigma_rules = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("sigma-rules")
+            .map(|path| path.to_string());
+        if let Some(sigma_rules) = sigma_rules.filter(|_| result_readdir.is_ok()) {
+            rulefile_loader.convert_sigma = true;
+            result_readdir = rulefile_loader.read_dir(sigma_rules, &level, exclude_ids);
+        }
+        if let Err(err) = &result_readdir {
+            let errmsg = format!("{}", err);
+            logging::verbose_error(&errmsg);
             if !*QUIET_ERRORS_FLAG {
                 ERROR_LOG_STACK
                     .lock()
                     .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
+                    .push(ErrorLog::error(ErrorClass::from_io_error(err), &errmsg));
             }
             return vec![];
         }
@@ -90,23 +105,20 @@ impl Detection {
             err_msgs_result.err().iter().for_each(|err_msgs| {
                 let errmsg_body =
                     format!("Failed to parse rule file. (FilePath : {})", rule.rulepath);
-                if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                    AlertMessage::warn(&mut std::io::stdout().lock(), &errmsg_body).ok();
-
-                    err_msgs.iter().for_each(|err_msg| {
-                        AlertMessage::warn(&mut std::io::stdout().lock(), err_msg).ok();
-                    });
-                }
+                logging::verbose_warn(&errmsg_body);
+                err_msgs.iter().for_each(|err_msg| {
+                    logging::verbose_warn(err_msg);
+                });
                 if !*QUIET_ERRORS_FLAG {
-                    ERROR_LOG_STACK
-                        .lock()
-                        .unwrap()
-                        .push(format!("[WARN] {}", errmsg_body));
+                    ERROR_LOG_STACK.lock().unwrap().push(
+                        ErrorLog::warn(ErrorClass::RuleParse, &errmsg_body)
+                            .with_file_path(&rule.rulepath),
+                    );
                     err_msgs.iter().for_each(|err_msg| {
-                        ERROR_LOG_STACK
-                            .lock()
-                            .unwrap()
-                            .push(format!("[WARN] {}", err_msg));
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::warn(ErrorClass::RuleParse, err_msg)
+                                .with_file_path(&rule.rulepath),
+                        );
                     });
                 }
                 parseerror_count += 1;
@@ -156,6 +168,21 @@ impl Detection {
             rules.push(ret_rule);
         }
 
+        // 検知したレコードの前後のレコードを出力する
+        if CONTEXT_NUM.is_some() {
+            if let Err(err) = CONTEXT_COLLECTOR
+                .lock()
+                .unwrap()
+                .process_records(&records_arc)
+            {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write context events. {}", err),
+                )
+                .ok();
+            }
+        }
+
         // この関数の先頭でrules.into_iter()を呼び出している。それにより所有権がmapのruleを経由し、execute_ruleの引数に渡しているruleに移っているので、self.rulesには所有権が無くなっている。
         // 所有権を失ったメンバー変数を持つオブジェクトをreturnするコードを書くと、コンパイラが怒になるので(E0382という番号のコンパイルエラー)、ここでself.rulesに所有権を戻している。
         // self.rulesが再度所有権を取り戻せるように、Detection::execute_ruleで引数に渡したruleを戻り値として返すようにしている。
@@ -184,7 +211,7 @@ impl Detection {
     // 複数のイベントレコードに対して、ルールを1個実行します。
     fn execute_rule(mut rule: RuleNode, records: Arc<Vec<EvtxRecordInfo>>) -> RuleNode {
         let agg_condition = rule.has_agg_condition();
-        for record_info in records.as_ref() {
+        for (idx, record_info) in records.iter().enumerate() {
             let result = rule.select(record_info);
             if !result {
                 continue;
@@ -198,6 +225,12 @@ impl Detection {
             // aggregation conditionが存在しない場合はそのまま出力対応を行う
             if !agg_condition {
                 Detection::insert_message(&rule, record_info);
+                if CONTEXT_NUM.is_some() {
+                    CONTEXT_COLLECTOR
+                        .lock()
+                        .unwrap()
+                        .add_hit(idx, &rule.rulepath);
+                }
             }
         }
 
@@ -206,6 +239,18 @@ impl Detection {
 
     /// 条件に合致したレコードを表示するための関数
     fn insert_message(rule: &RuleNode, record_info: &EvtxRecordInfo) {
+        // 抑制の設定や許可リストの条件に一致する検知は出力しない
+        let title = rule.yaml["title"].as_str().unwrap_or("");
+        let rule_id = rule.yaml["id"].as_str().unwrap_or(title);
+        if suppression::is_suppressed(rule_id, title, &record_info.record) {
+            return;
+        }
+        if *LEARN_ALLOWLIST_FLAG {
+            suppression::learn(rule_id, title, &record_info.record);
+        }
+        if *GRAPH_FLAG {
+            graph::insert(&record_info.record, title);
+        }
         let tag_info: Vec<String> = rule.yaml["tags"]
             .as_vec()
             .unwrap_or(&Vec::default())
@@ -227,6 +272,10 @@ impl Detection {
                 .replace('\"', ""),
             eventid: get_serde_number_to_string(&record_info.record["Event"]["System"]["EventID"])
                 .unwrap_or_else(|| "-".to_owned()),
+            record_id: get_serde_number_to_string(
+                &record_info.record["Event"]["System"]["EventRecordID"],
+            )
+            .unwrap_or_else(|| "-".to_owned()),
             channel: CH_CONFIG
                 .get(
                     &get_serde_number_to_string(&record_info.record["Event"]["System"]["Channel"])
@@ -234,11 +283,18 @@ impl Detection {
                 )
                 .unwrap_or(&String::default())
                 .to_string(),
-            alert: rule.yaml["title"].as_str().unwrap_or("").to_string(),
+            alert: title.to_string(),
             detail: String::default(),
             tag_info: tag_info.join(" | "),
             record_information: recinfo,
         };
+        if *HASH_LOOKUP_FLAG {
+            register_detection_hash(
+                &detect_info.filepath,
+                &detect_info.record_id,
+                &record_info.record,
+            );
+        }
         MESSAGES.lock().unwrap().insert(
             &record_info.record,
             rule.yaml["details"].as_str().unwrap_or("").to_string(),
@@ -267,6 +323,7 @@ impl Detection {
             level: rule.yaml["level"].as_str().unwrap_or("").to_owned(),
             computername: "-".to_owned(),
             eventid: "-".to_owned(),
+            record_id: "-".to_owned(),
             channel: "-".to_owned(),
             alert: rule.yaml["title"].as_str().unwrap_or("").to_owned(),
             detail: output,
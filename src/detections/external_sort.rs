@@ -0,0 +1,528 @@
+use crate::detections::configs;
+use crate::detections::print::DetectInfo;
+use crate::detections::utils;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+// メモリ上に保持する検知結果の件数の上限。これを超えるとソート済みのランとしてディスクに書き出す。
+const MAX_BUFFERED_DETECTIONS: usize = 100_000;
+
+// ランファイルの1行のカラム数
+const RUN_COLUMN_COUNT: usize = 13;
+
+// --merge-recordsで並べ替えるレコードのうち、メモリ上に保持する件数の上限
+const MAX_BUFFERED_RECORDS: usize = 100_000;
+
+// 一度にマージするランファイルの数の上限。これを超える場合は何段階かに分けてマージし、同時に開くファイルの数を抑える
+const MAX_OPEN_RUNS: usize = 64;
+
+lazy_static! {
+    pub static ref SORT_FLAG: bool = configs::CONFIG.read().unwrap().args.is_present("sort");
+    pub static ref EXTERNAL_SORTER: Mutex<ExternalSorter> = Mutex::new(ExternalSorter::new());
+}
+
+/// 検知結果を一定件数ごとにソート済みのランとしてディスクへ書き出し、出力時にマージする構造体
+pub struct ExternalSorter {
+    work_dir: PathBuf,
+    runs: Vec<PathBuf>,
+    buffered: usize,
+}
+
+impl Default for ExternalSorter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExternalSorter {
+    pub fn new() -> ExternalSorter {
+        ExternalSorter {
+            work_dir: std::env::temp_dir().join(format!("hayabusa-sort-{}", process::id())),
+            runs: vec![],
+            buffered: 0,
+        }
+    }
+
+    /// 検知結果が1件追加されたことを記録し、メモリ上の件数が上限に達した場合はtrueを返す
+    pub fn count_up(&mut self) -> bool {
+        self.buffered += 1;
+        self.buffered >= MAX_BUFFERED_DETECTIONS
+    }
+
+    pub fn has_runs(&self) -> bool {
+        !self.runs.is_empty()
+    }
+
+    /// メモリ上の検知結果(タイムスタンプ順にソート済み)をランファイルとして書き出す
+    pub fn spill(&mut self, map: &BTreeMap<DateTime<Utc>, Vec<DetectInfo>>) -> Result<(), String> {
+        self.buffered = 0;
+        fs::create_dir_all(&self.work_dir).map_err(|e| e.to_string())?;
+        let path = self.work_dir.join(format!("run-{}.csv", self.runs.len()));
+        write_run(&path, map)?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// 書き出したランをタイムスタンプ順にマージしながら読み出すイテレータを返す
+    pub fn merge(&mut self) -> Result<SortedRunMerger, String> {
+        SortedRunMerger::new(std::mem::take(&mut self.runs), self.work_dir.clone())
+    }
+
+    /// メモリ上の検知結果とディスクに書き出したランを合わせて、タイムスタンプ順に読み出すイテレータを返す
+    pub fn sorted_detections<'a>(
+        &'a mut self,
+        map: &'a BTreeMap<DateTime<Utc>, Vec<DetectInfo>>,
+    ) -> Result<Box<dyn Iterator<Item = (DateTime<Utc>, Cow<'a, DetectInfo>)> + 'a>, String> {
+        if !self.has_runs() {
+            return Ok(Box::new(map.iter().flat_map(|(time, detect_infos)| {
+                detect_infos
+                    .iter()
+                    .map(move |detect_info| (*time, Cow::Borrowed(detect_info)))
+            })));
+        }
+        // --sortでディスクに書き出した検知結果がある場合は、残りも書き出してからマージする
+        self.spill(map)?;
+        let merger = self.merge()?;
+        Ok(Box::new(merger.map(|(time, detect_info)| {
+            (time, Cow::Owned(detect_info))
+        })))
+    }
+}
+
+fn write_run(path: &Path, map: &BTreeMap<DateTime<Utc>, Vec<DetectInfo>>) -> Result<(), String> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    for (time, detect_infos) in map.iter() {
+        let time = time.to_rfc3339();
+        for detect_info in detect_infos {
+            let has_recinfo = if detect_info.record_information.is_some() {
+                "1"
+            } else {
+                "0"
+            };
+            wtr.write_record(&[
+                time.as_str(),
+                detect_info.filepath.as_str(),
+                detect_info.rulepath.as_str(),
+                detect_info.level.as_str(),
+                detect_info.computername.as_str(),
+                detect_info.eventid.as_str(),
+                detect_info.channel.as_str(),
+                detect_info.alert.as_str(),
+                detect_info.detail.as_str(),
+                detect_info.tag_info.as_str(),
+                has_recinfo,
+                detect_info.record_information.as_deref().unwrap_or(""),
+                detect_info.record_id.as_str(),
+            ])
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    wtr.flush().map_err(|e| e.to_string())
+}
+
+fn parse_run_record(record: &csv::StringRecord) -> Option<(DateTime<Utc>, DetectInfo)> {
+    if record.len() != RUN_COLUMN_COUNT {
+        return None;
+    }
+    let time = utils::str_time_to_datetime(&record[0])?;
+    let record_information = if &record[10] == "1" {
+        Some(record[11].to_string())
+    } else {
+        None
+    };
+    Some((
+        time,
+        DetectInfo {
+            filepath: record[1].to_string(),
+            rulepath: record[2].to_string(),
+            level: record[3].to_string(),
+            computername: record[4].to_string(),
+            eventid: record[5].to_string(),
+            record_id: record[12].to_string(),
+            channel: record[6].to_string(),
+            alert: record[7].to_string(),
+            detail: record[8].to_string(),
+            tag_info: record[9].to_string(),
+            record_information,
+        },
+    ))
+}
+
+/// 複数のランファイルをk-way mergeで読み出すイテレータ。同じタイムスタンプの場合は先に書き出したランの検知結果を先に返す。
+pub struct SortedRunMerger {
+    work_dir: PathBuf,
+    paths: Vec<PathBuf>,
+    readers: Vec<csv::StringRecordsIntoIter<File>>,
+    heads: Vec<Option<DetectInfo>>,
+    heap: BinaryHeap<Reverse<(DateTime<Utc>, usize)>>,
+}
+
+impl SortedRunMerger {
+    fn new(paths: Vec<PathBuf>, work_dir: PathBuf) -> Result<SortedRunMerger, String> {
+        let mut readers = vec![];
+        for path in &paths {
+            let reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_path(path)
+                .map_err(|e| e.to_string())?;
+            readers.push(reader.into_records());
+        }
+        let mut merger = SortedRunMerger {
+            work_dir,
+            heads: vec![None; paths.len()],
+            paths,
+            readers,
+            heap: BinaryHeap::new(),
+        };
+        for idx in 0..merger.readers.len() {
+            merger.read_head(idx);
+        }
+        Ok(merger)
+    }
+
+    fn read_head(&mut self, idx: usize) {
+        for record in self.readers[idx].by_ref().flatten() {
+            if let Some((time, detect_info)) = parse_run_record(&record) {
+                self.heads[idx] = Some(detect_info);
+                self.heap.push(Reverse((time, idx)));
+                return;
+            }
+        }
+    }
+}
+
+impl Iterator for SortedRunMerger {
+    type Item = (DateTime<Utc>, DetectInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((time, idx)) = self.heap.pop()?;
+        let detect_info = self.heads[idx].take();
+        self.read_head(idx);
+        detect_info.map(|detect_info| (time, detect_info))
+    }
+}
+
+impl Drop for SortedRunMerger {
+    fn drop(&mut self) {
+        // Windowsでは開いているファイルを削除できないので、先にreaderを閉じる
+        self.readers.clear();
+        for path in &self.paths {
+            fs::remove_file(path).ok();
+        }
+        fs::remove_dir(&self.work_dir).ok();
+    }
+}
+
+// レコードを並べ替えるキー。タイムスタンプが同じ場合は追加した順にする
+type RecordKey = (i64, u32, u64);
+
+/**
+* --merge-recordsで複数のファイルのレコードをタイムスタンプ順に並べ替える構造体。
+* evtxファイルのレコードはレコードIDの順に保存されていて、時刻の変更や転送されたログではタイムスタンプの順にならないので、
+* ファイル毎の順序に頼らずに、一定件数ごとにソート済みのランとしてディスクへ書き出してからマージする。
+*/
+pub struct RecordSorter {
+    work_dir: PathBuf,
+    runs: Vec<PathBuf>,
+    next_run: usize,
+    buffer: Vec<(RecordKey, usize, Value)>,
+    pushed: u64,
+    max_buffered: usize,
+    max_open_runs: usize,
+}
+
+impl Default for RecordSorter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordSorter {
+    pub fn new() -> RecordSorter {
+        RecordSorter {
+            work_dir: std::env::temp_dir().join(format!("hayabusa-merge-{}", process::id())),
+            runs: vec![],
+            next_run: 0,
+            buffer: vec![],
+            pushed: 0,
+            max_buffered: MAX_BUFFERED_RECORDS,
+            max_open_runs: MAX_OPEN_RUNS,
+        }
+    }
+
+    /// レコードを追加する。file_idxはレコードを読み込んだファイルの番号で、並べ替えた後にレコードと一緒に返す
+    pub fn push(
+        &mut self,
+        time: DateTime<Utc>,
+        file_idx: usize,
+        record: Value,
+    ) -> Result<(), String> {
+        let key = (time.timestamp(), time.timestamp_subsec_nanos(), self.pushed);
+        self.pushed += 1;
+        self.buffer.push((key, file_idx, record));
+        if self.buffer.len() >= self.max_buffered {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // メモリ上のレコードをソートしてランファイルとして書き出す
+    fn spill(&mut self) -> Result<(), String> {
+        self.buffer.sort_unstable_by_key(|(key, _, _)| *key);
+        let path = self.new_run_path()?;
+        let mut wtr = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+        for entry in self.buffer.drain(..) {
+            write_record_line(&mut wtr, &entry)?;
+        }
+        wtr.flush().map_err(|e| e.to_string())?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    fn new_run_path(&mut self) -> Result<PathBuf, String> {
+        fs::create_dir_all(&self.work_dir).map_err(|e| e.to_string())?;
+        let path = self.work_dir.join(format!("run-{}.jsonl", self.next_run));
+        self.next_run += 1;
+        Ok(path)
+    }
+
+    /// 追加したレコードをタイムスタンプ順に、ファイルの番号と一緒に読み出すイテレータを返す
+    pub fn into_sorted(mut self) -> Result<Box<dyn Iterator<Item = (usize, Value)>>, String> {
+        if self.runs.is_empty() {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.sort_unstable_by_key(|(key, _, _)| *key);
+            return Ok(Box::new(
+                buffer
+                    .into_iter()
+                    .map(|(_, file_idx, record)| (file_idx, record)),
+            ));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        // ランが多い場合は、同時に開くファイルの数が上限を超えないように、まとめてマージしたランに置き換える
+        while self.runs.len() > self.max_open_runs {
+            let runs: Vec<PathBuf> = self.runs.drain(..self.max_open_runs).collect();
+            let path = self.new_run_path()?;
+            let mut wtr = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+            for entry in RecordRunMerger::new(runs, None)? {
+                write_record_line(&mut wtr, &entry)?;
+            }
+            wtr.flush().map_err(|e| e.to_string())?;
+            self.runs.push(path);
+        }
+        let runs = std::mem::take(&mut self.runs);
+        let merger = RecordRunMerger::new(runs, Some(self.work_dir.clone()))?;
+        Ok(Box::new(
+            merger.map(|(_, file_idx, record)| (file_idx, record)),
+        ))
+    }
+}
+
+impl Drop for RecordSorter {
+    fn drop(&mut self) {
+        // 並べ替えの途中でエラーになった場合に、書き出したランを残さない
+        for path in &self.runs {
+            fs::remove_file(path).ok();
+        }
+        if !self.runs.is_empty() {
+            fs::remove_dir(&self.work_dir).ok();
+        }
+    }
+}
+
+fn write_record_line(
+    wtr: &mut impl Write,
+    entry: &(RecordKey, usize, Value),
+) -> Result<(), String> {
+    serde_json::to_writer(&mut *wtr, entry).map_err(|e| e.to_string())?;
+    wtr.write_all(b"\n").map_err(|e| e.to_string())
+}
+
+/// レコードのランファイルをk-way mergeで読み出すイテレータ。読み終わったランファイルは削除する
+struct RecordRunMerger {
+    work_dir: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+    readers: Vec<Lines<BufReader<File>>>,
+    heads: Vec<Option<(usize, Value)>>,
+    heap: BinaryHeap<Reverse<(RecordKey, usize)>>,
+}
+
+impl RecordRunMerger {
+    fn new(paths: Vec<PathBuf>, work_dir: Option<PathBuf>) -> Result<RecordRunMerger, String> {
+        let mut readers = vec![];
+        for path in &paths {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            readers.push(BufReader::new(file).lines());
+        }
+        let mut merger = RecordRunMerger {
+            work_dir,
+            heads: vec![None; paths.len()],
+            paths,
+            readers,
+            heap: BinaryHeap::new(),
+        };
+        for idx in 0..merger.readers.len() {
+            merger.read_head(idx);
+        }
+        Ok(merger)
+    }
+
+    fn read_head(&mut self, idx: usize) {
+        for line in self.readers[idx].by_ref().flatten() {
+            if let Ok((key, file_idx, record)) =
+                serde_json::from_str::<(RecordKey, usize, Value)>(&line)
+            {
+                self.heads[idx] = Some((file_idx, record));
+                self.heap.push(Reverse((key, idx)));
+                return;
+            }
+        }
+    }
+}
+
+impl Iterator for RecordRunMerger {
+    type Item = (RecordKey, usize, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((key, idx)) = self.heap.pop()?;
+        let head = self.heads[idx].take();
+        self.read_head(idx);
+        head.map(|(file_idx, record)| (key, file_idx, record))
+    }
+}
+
+impl Drop for RecordRunMerger {
+    fn drop(&mut self) {
+        // Windowsでは開いているファイルを削除できないので、先にreaderを閉じる
+        self.readers.clear();
+        for path in &self.paths {
+            fs::remove_file(path).ok();
+        }
+        if let Some(work_dir) = &self.work_dir {
+            fs::remove_dir(work_dir).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::external_sort::{ExternalSorter, RecordSorter};
+    use crate::detections::print::DetectInfo;
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn create_detect_info(rulepath: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "test.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: "high".to_string(),
+            computername: "testcomputer".to_string(),
+            eventid: "1".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "test".to_string(),
+            detail: "CommandLine: a,\"b\"".to_string(),
+            tag_info: "Exec".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_spill_and_merge_runs() {
+        let time1 = "1996-02-27T01:05:01Z".parse::<DateTime<Utc>>().unwrap();
+        let time2 = "2000-01-21T09:06:01Z".parse::<DateTime<Utc>>().unwrap();
+        let time3 = "2010-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        let mut sorter = ExternalSorter::new();
+        let mut run1 = BTreeMap::new();
+        run1.insert(time1, vec![create_detect_info("rule1")]);
+        run1.insert(time3, vec![create_detect_info("rule3")]);
+        sorter.spill(&run1).unwrap();
+
+        let mut run2 = BTreeMap::new();
+        let mut with_recinfo = create_detect_info("rule2");
+        with_recinfo.record_information = Some("a:b | c:d".to_string());
+        run2.insert(time1, vec![create_detect_info("rule1-2")]);
+        run2.insert(time2, vec![with_recinfo]);
+        sorter.spill(&run2).unwrap();
+
+        let merged: Vec<(DateTime<Utc>, DetectInfo)> = sorter.merge().unwrap().collect();
+        let rulepaths: Vec<&str> = merged.iter().map(|(_, d)| d.rulepath.as_str()).collect();
+        assert_eq!(rulepaths, vec!["rule1", "rule1-2", "rule2", "rule3"]);
+        assert_eq!(merged[0].0, time1);
+        assert_eq!(merged[0].1.detail, "CommandLine: a,\"b\"");
+        assert_eq!(merged[0].1.record_id, "12345");
+        assert_eq!(
+            merged[2].1.record_information,
+            Some("a:b | c:d".to_string())
+        );
+        assert!(merged[3].1.record_information.is_none());
+        assert!(!sorter.has_runs());
+    }
+
+    fn sort_records(mut sorter: RecordSorter) -> Vec<(usize, Value)> {
+        let base = "2022-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        // 2つのファイルのレコードを、それぞれレコードIDの順でタイムスタンプが前後する順番で追加する
+        for (file_idx, offsets) in [(0, [30, 10, 20, 0, 50]), (1, [40, 0, 5, 60, 25])] {
+            for (record_id, offset) in offsets.iter().enumerate() {
+                let time = base + Duration::seconds(*offset);
+                let record =
+                    json!({"Event": {"System": {"EventRecordID": record_id, "Offset": offset}}});
+                sorter.push(time, file_idx, record).unwrap();
+            }
+        }
+        sorter.into_sorted().unwrap().collect()
+    }
+
+    #[test]
+    fn test_sort_records() {
+        let expected = vec![
+            (0, 0),
+            (1, 0),
+            (1, 5),
+            (0, 10),
+            (0, 20),
+            (1, 25),
+            (0, 30),
+            (1, 40),
+            (0, 50),
+            (1, 60),
+        ];
+        let offsets = |records: Vec<(usize, Value)>| -> Vec<(usize, i64)> {
+            records
+                .iter()
+                .map(|(file_idx, record)| {
+                    (
+                        *file_idx,
+                        record["Event"]["System"]["Offset"].as_i64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        // メモリ上だけで並べ替える
+        assert_eq!(offsets(sort_records(RecordSorter::new())), expected);
+
+        // ランファイルに書き出して、同時に開くランの数を抑えながら何段階かに分けてマージする
+        let mut sorter = RecordSorter::new();
+        sorter.work_dir = std::env::temp_dir().join("hayabusa-merge-test");
+        sorter.max_buffered = 2;
+        sorter.max_open_runs = 2;
+        assert_eq!(offsets(sort_records(sorter)), expected);
+        assert!(!std::env::temp_dir().join("hayabusa-merge-test").exists());
+    }
+}
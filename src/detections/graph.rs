@@ -0,0 +1,411 @@
+use crate::detections::{configs, utils};
+use crate::output::anonymize;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+// ログオンしたユーザのフィールド。先にあるものを優先する
+const USER_FIELDS: [&str; 3] = ["TargetUserName", "User", "SubjectUserName"];
+// ログオン元のフィールド
+const SOURCE_FIELDS: [&str; 3] = ["IpAddress", "SourceAddress", "WorkstationName"];
+// 実行されたプロセスのフィールド(Sysmonと4688)
+const IMAGE_FIELDS: [&str; 2] = ["Image", "NewProcessName"];
+// 親プロセスのフィールド(Sysmonと4688)
+const PARENT_IMAGE_FIELDS: [&str; 2] = ["ParentImage", "ParentProcessName"];
+// 通信先のフィールド(Sysmonの3と5156)
+const DESTINATION_FIELDS: [&str; 2] = ["DestinationIp", "DestAddress"];
+// ノードにしない値
+const IGNORED_VALUES: [&str; 5] = ["", "-", "127.0.0.1", "::1", "0.0.0.0"];
+
+lazy_static! {
+    pub static ref GRAPH_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("graph");
+    /// 検知から作成したホスト、ユーザ、プロセス、IPアドレスの関係のグラフ
+    static ref GRAPH: Mutex<DetectionGraph> = Mutex::new(DetectionGraph::default());
+}
+
+/// グラフのノードの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeKind {
+    Host,
+    User,
+    Process,
+    Ip,
+}
+
+impl NodeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            NodeKind::Host => "host",
+            NodeKind::User => "user",
+            NodeKind::Process => "process",
+            NodeKind::Ip => "ip",
+        }
+    }
+}
+
+// ノードを識別するキー。ホスト名やユーザ名は大文字小文字を区別しない
+type NodeKey = (NodeKind, String);
+
+#[derive(Debug, Clone)]
+struct Node {
+    label: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Edge {
+    count: usize,
+    rules: BTreeSet<String>,
+}
+
+/**
+* 検知したレコードのホスト、ユーザ、プロセス、IPアドレスをノードにし、ログオン、プロセスの起動、通信をエッジにしたグラフ。
+* 同じノードと同じ関係は1つにまとめて、検知の件数とルールのタイトルを記録する。
+*/
+#[derive(Debug, Default)]
+pub struct DetectionGraph {
+    nodes: BTreeMap<NodeKey, Node>,
+    edges: BTreeMap<(NodeKey, NodeKey, &'static str), Edge>,
+}
+
+impl DetectionGraph {
+    fn add_node(&mut self, kind: NodeKind, label: &str) -> NodeKey {
+        let key = (kind, label.to_lowercase());
+        self.nodes
+            .entry(key.clone())
+            .or_insert_with(|| Node {
+                label: label.to_string(),
+                count: 0,
+            })
+            .count += 1;
+        key
+    }
+
+    fn add_edge(&mut self, source: &NodeKey, target: &NodeKey, relation: &'static str, rule: &str) {
+        let edge = self
+            .edges
+            .entry((source.clone(), target.clone(), relation))
+            .or_default();
+        edge.count += 1;
+        edge.rules.insert(rule.to_string());
+    }
+
+    /**
+     * 検知したレコードからノードとエッジを追加する。
+     * ログオン元→ホスト(logon from)、ユーザ→ホスト(logged on to)、親プロセス→プロセス(spawned)、
+     * ユーザ→プロセス(ran)、プロセス→通信先(connected to)の関係を記録する。
+     */
+    pub fn insert(&mut self, record: &Value, rule: &str) {
+        let computer = match field(record, &["Event.System.Computer"]) {
+            Some(computer) => computer,
+            None => return,
+        };
+        let host = self.add_node(NodeKind::Host, &computer);
+        let user = field(record, &USER_FIELDS).map(|user| {
+            // DOMAIN\userはユーザ名だけにして、4624と4688などで同じノードにする
+            let name = user.rsplit('\\').next().unwrap_or_default().to_string();
+            self.add_node(NodeKind::User, &name)
+        });
+        // プロセスは別のホストの同じパスと区別するため、ホスト名を付ける
+        let mut process =
+            |path: String| self.add_node(NodeKind::Process, &format!("{} ({})", path, computer));
+        let image = field(record, &IMAGE_FIELDS).map(&mut process);
+        let parent = field(record, &PARENT_IMAGE_FIELDS).map(&mut process);
+
+        match (&parent, &image) {
+            (Some(parent), Some(image)) => self.add_edge(parent, image, "spawned", rule),
+            (None, Some(image)) => self.add_edge(&host, image, "ran", rule),
+            _ => {}
+        }
+        if let (Some(user), Some(image)) = (&user, &image) {
+            self.add_edge(user, image, "ran", rule);
+        }
+        if let Some(destination) = field(record, &DESTINATION_FIELDS) {
+            let destination = self.add_node(address_kind(&destination), &destination);
+            let process = image.as_ref().unwrap_or(&host);
+            // Sysmonの3のInitiatedがfalseの場合は外部からの通信
+            if field(record, &["Initiated"]).as_deref() == Some("false") {
+                self.add_edge(&destination, process, "connected to", rule);
+            } else {
+                self.add_edge(process, &destination, "connected to", rule);
+            }
+        }
+        if let Some(source) = field(record, &SOURCE_FIELDS) {
+            let source = self.add_node(address_kind(&source), &source);
+            if source != host {
+                self.add_edge(&source, &host, "logon from", rule);
+            }
+        }
+        if let (Some(user), None) = (&user, &image) {
+            self.add_edge(user, &host, "logged on to", rule);
+        }
+    }
+
+    /// ノードのキーと出力するID(n0、n1...)の対応
+    fn node_ids(&self) -> BTreeMap<&NodeKey, String> {
+        self.nodes
+            .keys()
+            .enumerate()
+            .map(|(idx, key)| (key, format!("n{}", idx)))
+            .collect()
+    }
+
+    /// JSON形式のグラフ。nodesとedgesの配列を出力する
+    pub fn to_json(&self) -> String {
+        let ids = self.node_ids();
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|(key, node)| {
+                json!({
+                    "id": ids[key],
+                    "label": node.label,
+                    "type": key.0.as_str(),
+                    "count": node.count,
+                })
+            })
+            .collect();
+        let edges: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|((source, target, relation), edge)| {
+                json!({
+                    "source": ids[source],
+                    "target": ids[target],
+                    "relation": relation,
+                    "count": edge.count,
+                    "rules": edge.rules,
+                })
+            })
+            .collect();
+        serde_json::to_string_pretty(&json!({ "nodes": nodes, "edges": edges })).unwrap_or_default()
+    }
+
+    /// Graphvizで読み込めるDOT形式のグラフ
+    pub fn to_dot(&self) -> String {
+        let ids = self.node_ids();
+        let mut dot = "digraph hayabusa {\n".to_string();
+        for (key, node) in self.nodes.iter() {
+            let shape = match key.0 {
+                NodeKind::Host => "box",
+                NodeKind::User => "ellipse",
+                NodeKind::Process => "component",
+                NodeKind::Ip => "diamond",
+            };
+            writeln!(
+                dot,
+                "  {} [label=\"{}\", type=\"{}\", shape={}];",
+                ids[key],
+                escape_dot(&node.label),
+                key.0.as_str(),
+                shape
+            )
+            .ok();
+        }
+        for ((source, target, relation), edge) in self.edges.iter() {
+            writeln!(
+                dot,
+                "  {} -> {} [label=\"{}\", weight={}];",
+                ids[source], ids[target], relation, edge.count
+            )
+            .ok();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Gephiで読み込めるGEXF形式のグラフ。ノードの種類とエッジのルールを属性にする
+    pub fn to_gexf(&self) -> String {
+        let ids = self.node_ids();
+        let mut gexf = String::new();
+        gexf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        gexf.push_str("<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n");
+        gexf.push_str("  <graph defaultedgetype=\"directed\">\n");
+        gexf.push_str("    <attributes class=\"node\">\n      <attribute id=\"0\" title=\"type\" type=\"string\"/>\n      <attribute id=\"1\" title=\"count\" type=\"integer\"/>\n    </attributes>\n");
+        gexf.push_str("    <attributes class=\"edge\">\n      <attribute id=\"0\" title=\"rules\" type=\"string\"/>\n    </attributes>\n");
+        gexf.push_str("    <nodes>\n");
+        for (key, node) in self.nodes.iter() {
+            writeln!(
+                gexf,
+                "      <node id=\"{}\" label=\"{}\"><attvalues><attvalue for=\"0\" value=\"{}\"/><attvalue for=\"1\" value=\"{}\"/></attvalues></node>",
+                ids[key],
+                escape_xml(&node.label),
+                key.0.as_str(),
+                node.count
+            )
+            .ok();
+        }
+        gexf.push_str("    </nodes>\n    <edges>\n");
+        for (idx, ((source, target, relation), edge)) in self.edges.iter().enumerate() {
+            let rules: Vec<&str> = edge.rules.iter().map(|rule| rule.as_str()).collect();
+            writeln!(
+                gexf,
+                "      <edge id=\"e{}\" source=\"{}\" target=\"{}\" label=\"{}\" weight=\"{}\"><attvalues><attvalue for=\"0\" value=\"{}\"/></attvalues></edge>",
+                idx,
+                ids[source],
+                ids[target],
+                relation,
+                edge.count,
+                escape_xml(&rules.join(" | "))
+            )
+            .ok();
+        }
+        gexf.push_str("    </edges>\n  </graph>\n</gexf>\n");
+        gexf
+    }
+}
+
+// 最初に値があるフィールドの値を返す
+fn field(record: &Value, fields: &[&str]) -> Option<String> {
+    fields.iter().find_map(|field| {
+        utils::get_event_value(field, record)
+            .and_then(utils::value_to_string)
+            .filter(|value| !IGNORED_VALUES.contains(&value.as_str()))
+    })
+}
+
+// ログオン元と通信先は、IPアドレスでなければホスト名として扱う
+fn address_kind(address: &str) -> NodeKind {
+    if address.parse::<IpAddr>().is_ok() {
+        NodeKind::Ip
+    } else {
+        NodeKind::Host
+    }
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// --graphが指定されている場合に、検知したレコードをグラフに追加する
+pub fn insert(record: &Value, rule: &str) {
+    GRAPH.lock().unwrap().insert(record, rule);
+}
+
+/// グラフを拡張子(.dot、.gexf、それ以外はJSON)の形式で保存し、ノードとエッジの数を返す
+pub fn save(path: &str) -> Result<(usize, usize), String> {
+    let graph = GRAPH.lock().unwrap();
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    let contents = match ext.as_str() {
+        "dot" | "gv" => graph.to_dot(),
+        "gexf" => graph.to_gexf(),
+        _ => graph.to_json(),
+    };
+    let contents = anonymize::anonymize_output(&contents);
+    fs::write(path, contents).map_err(|e| format!("{} [path:{}]", e, path))?;
+    Ok((graph.nodes.len(), graph.edges.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::graph::DetectionGraph;
+    use serde_json::json;
+
+    fn sample_graph() -> DetectionGraph {
+        let mut graph = DetectionGraph::default();
+        graph.insert(
+            &json!({"Event": {
+                "System": {"Computer": "DC01", "EventID": 4624},
+                "EventData": {"TargetUserName": "taro", "IpAddress": "10.0.0.5", "LogonType": 3},
+            }}),
+            "Logon (Network)",
+        );
+        graph.insert(
+            &json!({"Event": {
+                "System": {"Computer": "dc01", "EventID": 1},
+                "EventData": {
+                    "User": "EXAMPLE\\Taro",
+                    "Image": "C:\\Windows\\System32\\cmd.exe",
+                    "ParentImage": "C:\\Windows\\System32\\wsmprovhost.exe",
+                },
+            }}),
+            "Suspicious \"cmd\" <child>",
+        );
+        graph.insert(
+            &json!({"Event": {
+                "System": {"Computer": "DC01", "EventID": 3},
+                "EventData": {
+                    "Image": "C:\\Windows\\System32\\cmd.exe",
+                    "DestinationIp": "203.0.113.10",
+                    "Initiated": "true",
+                },
+            }}),
+            "Outbound Connection",
+        );
+        graph
+    }
+
+    #[test]
+    fn test_insert() {
+        let graph = sample_graph();
+        let labels: Vec<(&str, &str)> = graph
+            .nodes
+            .iter()
+            .map(|(key, node)| (key.0.as_str(), node.label.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("host", "DC01"),
+                ("user", "taro"),
+                ("process", "C:\\Windows\\System32\\cmd.exe (dc01)"),
+                ("process", "C:\\Windows\\System32\\wsmprovhost.exe (dc01)"),
+                ("ip", "10.0.0.5"),
+                ("ip", "203.0.113.10"),
+            ]
+        );
+        let relations: Vec<&str> = graph
+            .edges
+            .keys()
+            .map(|(_, _, relation)| *relation)
+            .collect();
+        assert_eq!(relations.len(), 6);
+        for relation in [
+            "logon from",
+            "logged on to",
+            "spawned",
+            "ran",
+            "connected to",
+        ] {
+            assert!(relations.contains(&relation));
+        }
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let graph = sample_graph();
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 6);
+        assert_eq!(json["edges"].as_array().unwrap().len(), 6);
+        assert_eq!(json["nodes"][0]["type"], "host");
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph hayabusa {\n"));
+        assert!(dot.contains("label=\"C:\\\\Windows\\\\System32\\\\cmd.exe (dc01)\""));
+        assert!(dot.contains("[label=\"logon from\", weight=1];"));
+
+        let gexf = graph.to_gexf();
+        assert!(gexf.contains("<node id=\"n0\" label=\"DC01\">"));
+        assert!(gexf.contains("Suspicious &quot;cmd&quot; &lt;child&gt;"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::detections::configs;
+use crate::detections::utils;
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::fs;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// VirusTotal API v3のファイルの情報を取得するエンドポイント。{hash}をハッシュ値に置き換える
+pub const DEFAULT_HASH_LOOKUP_URL: &str = "https://www.virustotal.com/api/v3/files/{hash}";
+// VirusTotalの無料のAPIキーは1分間に4回まで
+const DEFAULT_REQUESTS_PER_MINUTE: u64 = 4;
+// レート制限(HTTP 429)で失敗した時の再試行回数
+const MAX_RETRIES: u32 = 2;
+// 問い合わせるハッシュの種類。先にあるものを優先する
+const HASH_TYPES: [&str; 3] = ["SHA256", "SHA1", "MD5"];
+
+lazy_static! {
+    /// --vt-api-keyが指定されている場合だけ検知したレコードのハッシュ値を記録する
+    pub static ref HASH_LOOKUP_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("vt-api-key");
+    /// 検知したレコードの(ファイルパス, EventRecordID)とハッシュ値の対応
+    static ref DETECTION_HASHES: RwLock<HashMap<(String, String), String>> =
+        RwLock::new(HashMap::new());
+}
+
+// 検知したレコードを識別するキー。EventRecordIDがないレコード("-")は別のレコードと区別できないため対象外にする
+fn detection_key(filepath: &str, record_id: &str) -> Option<(String, String)> {
+    let record_id = record_id.trim();
+    if record_id.is_empty() || record_id == "-" {
+        return None;
+    }
+    Some((filepath.to_string(), record_id.to_string()))
+}
+
+/// 検知したレコードのSysmonのHashesフィールドからハッシュ値を登録する
+pub fn register_detection_hash(filepath: &str, record_id: &str, record: &Value) {
+    let key = match detection_key(filepath, record_id) {
+        Some(key) => key,
+        None => return,
+    };
+    let hashes = ["Hashes", "Hash"]
+        .iter()
+        .find_map(|key| utils::get_event_value(key, record).and_then(utils::value_to_string));
+    if let Some(hash) = hashes.and_then(|hashes| extract_hash(&hashes)) {
+        DETECTION_HASHES.write().unwrap().insert(key, hash);
+    }
+}
+
+/// 検知したレコードのハッシュ値を取得する
+pub fn get_detection_hash(filepath: &str, record_id: &str) -> Option<String> {
+    let key = detection_key(filepath, record_id)?;
+    DETECTION_HASHES.read().unwrap().get(&key).cloned()
+}
+
+/// SHA1=...,MD5=...,SHA256=...,IMPHASH=...の形式からSHA256、SHA1、MD5の順にハッシュ値を1つ取り出す
+pub fn extract_hash(hashes: &str) -> Option<String> {
+    let hashes: HashMap<String, &str> = hashes
+        .split(',')
+        .filter_map(|hash| hash.split_once('='))
+        .map(|(kind, value)| (kind.trim().to_uppercase(), value.trim()))
+        .collect();
+    HASH_TYPES
+        .iter()
+        .find_map(|kind| hashes.get(*kind).filter(|value| !value.is_empty()))
+        .map(|hash| hash.to_lowercase())
+}
+
+/**
+* 検知したレコードのハッシュ値をVirusTotal(またはVirusTotal API v3と同じ形式のAPI)に問い合わせて判定結果を取得する。
+* 同じハッシュ値は1回だけ問い合わせ、--vt-cacheが指定されている場合は結果をファイルに保存して次回も使う。
+* APIの制限に合わせて1分間あたりの問い合わせ回数を制限する。
+*/
+pub struct HashLookup {
+    url: String,
+    api_key: String,
+    client: reqwest::blocking::Client,
+    interval: Duration,
+    last_request: Option<Instant>,
+    // ハッシュ値と判定結果の対応
+    cache: HashMap<String, String>,
+    cache_path: Option<String>,
+    pub lookups: usize,
+    pub failed: usize,
+}
+
+impl HashLookup {
+    pub fn new(
+        api_key: &str,
+        url: Option<&str>,
+        requests_per_minute: Option<&str>,
+        cache_path: Option<&str>,
+    ) -> Result<HashLookup, String> {
+        let requests_per_minute = match requests_per_minute {
+            Some(rate) => rate
+                .parse::<u64>()
+                .ok()
+                .filter(|rate| *rate > 0)
+                .ok_or_else(|| format!("Invalid --vt-rate-limit: {}", rate))?,
+            None => DEFAULT_REQUESTS_PER_MINUTE,
+        };
+        let mut lookup = HashLookup {
+            url: url.unwrap_or(DEFAULT_HASH_LOOKUP_URL).to_string(),
+            api_key: api_key.to_string(),
+            client: reqwest::blocking::Client::new(),
+            interval: Duration::from_millis(60_000 / requests_per_minute),
+            last_request: None,
+            cache: HashMap::new(),
+            cache_path: cache_path.map(|path| path.to_string()),
+            lookups: 0,
+            failed: 0,
+        };
+        if let Some(path) = cache_path {
+            // キャッシュファイルがまだない場合は空のキャッシュから始める
+            if let Ok(lines) = utils::read_csv(path) {
+                for line in lines.into_iter().filter(|line| line.len() >= 2) {
+                    lookup.cache.insert(line[0].to_lowercase(), line[1].clone());
+                }
+            }
+        }
+        Ok(lookup)
+    }
+
+    /// ハッシュ値の判定結果を返す。問い合わせに失敗した場合は"Error"を返し、キャッシュしない
+    pub fn lookup(&mut self, hash: &str) -> String {
+        let hash = hash.to_lowercase();
+        if let Some(verdict) = self.cache.get(&hash) {
+            return verdict.to_string();
+        }
+        match self.request(&hash) {
+            Ok(verdict) => {
+                self.lookups += 1;
+                self.cache.insert(hash, verdict.to_string());
+                verdict
+            }
+            Err(_) => {
+                self.failed += 1;
+                "Error".to_string()
+            }
+        }
+    }
+
+    fn request(&mut self, hash: &str) -> Result<String, String> {
+        let url = self.url.replace("{hash}", hash);
+        for retry in 0..=MAX_RETRIES {
+            self.wait();
+            let res = self
+                .client
+                .get(&url)
+                .header("x-apikey", &self.api_key)
+                .send()
+                .map_err(|e| e.to_string())?;
+            match res.status().as_u16() {
+                200 => {
+                    let body = res.text().map_err(|e| e.to_string())?;
+                    let body: Value = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+                    return Ok(parse_verdict(&body));
+                }
+                404 => return Ok("NotFound".to_string()),
+                429 if retry < MAX_RETRIES => continue,
+                status => return Err(format!("HTTP status: {}", status)),
+            }
+        }
+        Err("Too many requests".to_string())
+    }
+
+    // 前回の問い合わせから間隔が空くまで待つ
+    fn wait(&mut self) {
+        if let Some(last_request) = self.last_request {
+            let elapsed = last_request.elapsed();
+            if elapsed < self.interval {
+                thread::sleep(self.interval - elapsed);
+            }
+        }
+        self.last_request = Some(Instant::now());
+    }
+
+    /// --vt-cacheが指定されている場合は判定結果をファイルに保存する
+    pub fn save_cache(&self) -> Result<(), String> {
+        let path = match &self.cache_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut entries: Vec<(&String, &String)> = self.cache.iter().collect();
+        entries.sort();
+        wtr.write_record(&["Hash", "Verdict"])
+            .map_err(|e| e.to_string())?;
+        for (hash, verdict) in entries {
+            wtr.write_record(&[hash, verdict])
+                .map_err(|e| e.to_string())?;
+        }
+        let data = wtr.into_inner().map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| e.to_string())
+    }
+}
+
+/// VirusTotal API v3の応答から悪性と判定したエンジンの数/全エンジンの数を取り出す
+fn parse_verdict(body: &Value) -> String {
+    let stats = &body["data"]["attributes"]["last_analysis_stats"];
+    let stats = match stats.as_object() {
+        Some(stats) => stats,
+        None => return "Unknown".to_string(),
+    };
+    let malicious = stats
+        .get("malicious")
+        .and_then(|count| count.as_u64())
+        .unwrap_or(0);
+    let total: u64 = stats.values().filter_map(|count| count.as_u64()).sum();
+    format!("{}/{}", malicious, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::hash_lookup::{
+        extract_hash, get_detection_hash, parse_verdict, register_detection_hash,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_hash() {
+        assert_eq!(
+            extract_hash("SHA1=AAAA,MD5=BBBB,SHA256=CCCC,IMPHASH=DDDD"),
+            Some("cccc".to_string())
+        );
+        assert_eq!(
+            extract_hash("MD5=BBBB,IMPHASH=DDDD"),
+            Some("bbbb".to_string())
+        );
+        assert_eq!(extract_hash("IMPHASH=DDDD"), None);
+        assert_eq!(extract_hash(""), None);
+    }
+
+    #[test]
+    fn test_detection_hash_without_record_id() {
+        let record = json!({
+            "Event": {
+                "EventData": {
+                    "Hashes": "SHA256=AAAA"
+                }
+            }
+        });
+        register_detection_hash("hash_lookup_test.evtx", "10", &record);
+        assert_eq!(
+            get_detection_hash("hash_lookup_test.evtx", "10"),
+            Some("aaaa".to_string())
+        );
+
+        // EventRecordIDがないレコードは別のレコードのハッシュ値と混同しないように登録しない
+        register_detection_hash("hash_lookup_test.evtx", "-", &record);
+        let other = json!({
+            "Event": {
+                "EventData": {
+                    "Hashes": "SHA256=BBBB"
+                }
+            }
+        });
+        register_detection_hash("hash_lookup_test.evtx", "-", &other);
+        assert_eq!(get_detection_hash("hash_lookup_test.evtx", "-"), None);
+        assert_eq!(get_detection_hash("hash_lookup_test.evtx", ""), None);
+    }
+
+    #[test]
+    fn test_parse_verdict() {
+        let body = json!({
+            "data": {
+                "attributes": {
+                    "last_analysis_stats": {
+                        "harmless": 0,
+                        "malicious": 52,
+                        "suspicious": 0,
+                        "undetected": 18,
+                        "timeout": 0,
+                    },
+                },
+            }
+        });
+        assert_eq!(parse_verdict(&body), "52/70");
+        assert_eq!(parse_verdict(&json!({})), "Unknown");
+    }
+}
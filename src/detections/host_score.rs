@@ -0,0 +1,177 @@
+use crate::detections::print::DetectInfo;
+use hashbrown::{HashMap, HashSet};
+use prettytable::{Cell, Row, Table};
+use std::error::Error;
+
+/// 同じホストで初めて検知したルールは、繰り返し検知した場合に比べてこの倍率で重み付けする
+const UNIQUE_RULE_MULTIPLIER: u64 = 10;
+
+/// ルールのlevelに応じた重み
+fn level_weight(level: &str) -> u64 {
+    match level.to_lowercase().as_str() {
+        "critical" => 10,
+        "high" => 5,
+        "medium" => 3,
+        "low" => 1,
+        _ => 0,
+    }
+}
+
+/// ホスト1台分のスコア
+#[derive(Debug, Default)]
+pub struct HostScore {
+    pub computer: String,
+    pub score: u64,
+    /// critical, high, medium, low, informational, その他の順の検知件数
+    pub counts_by_level: [u64; 6],
+    pub rules: HashSet<String>,
+}
+
+impl HostScore {
+    fn add(&mut self, detect_info: &DetectInfo) {
+        let weight = level_weight(&detect_info.level);
+        if self.rules.insert(detect_info.rulepath.to_string()) {
+            self.score += weight * UNIQUE_RULE_MULTIPLIER;
+        } else {
+            self.score += weight;
+        }
+        let idx = match detect_info.level.to_lowercase().as_str() {
+            "critical" => 0,
+            "high" => 1,
+            "medium" => 2,
+            "low" => 3,
+            "informational" => 4,
+            _ => 5,
+        };
+        self.counts_by_level[idx] += 1;
+    }
+}
+
+/**
+* 検知結果をComputer毎に集計し、levelと検知したルールの種類で重み付けしたスコアを算出する
+*/
+#[derive(Debug, Default)]
+pub struct HostScores {
+    scores: HashMap<String, HostScore>,
+}
+
+impl HostScores {
+    pub fn new() -> HostScores {
+        HostScores::default()
+    }
+
+    pub fn add(&mut self, detect_info: &DetectInfo) {
+        self.scores
+            .entry(detect_info.computername.to_string())
+            .or_insert_with(|| HostScore {
+                computer: detect_info.computername.to_string(),
+                ..Default::default()
+            })
+            .add(detect_info);
+    }
+
+    /// スコアの高い順に並べたホストの一覧を返す
+    pub fn ranking(&self) -> Vec<&HostScore> {
+        let mut ret: Vec<&HostScore> = self.scores.values().collect();
+        ret.sort_by(|x, y| {
+            y.score
+                .cmp(&x.score)
+                .then_with(|| x.computer.cmp(&y.computer))
+        });
+        ret
+    }
+
+    pub fn print(&self) {
+        println!();
+        println!("Most Suspicious Hosts");
+        let mut scores_tb = Table::new();
+        scores_tb.set_titles(row![
+            "Rank", "Computer", "Score", "Rules", "Critical", "High", "Medium", "Low", "Info"
+        ]);
+        for (i, host) in self.ranking().iter().enumerate() {
+            let mut cells = vec![
+                Cell::new(&(i + 1).to_string()),
+                Cell::new(&host.computer),
+                Cell::new(&host.score.to_string()),
+                Cell::new(&host.rules.len().to_string()),
+            ];
+            cells.extend(
+                host.counts_by_level[..5]
+                    .iter()
+                    .map(|count| Cell::new(&count.to_string())),
+            );
+            scores_tb.add_row(Row::new(cells));
+        }
+        scores_tb.printstd();
+    }
+
+    pub fn write_csv(&self, csv_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_path(csv_path)?;
+        wtr.write_record(&[
+            "Rank",
+            "Computer",
+            "Score",
+            "Rules",
+            "Critical",
+            "High",
+            "Medium",
+            "Low",
+            "Informational",
+        ])?;
+        for (i, host) in self.ranking().iter().enumerate() {
+            let mut record = vec![
+                (i + 1).to_string(),
+                host.computer.to_string(),
+                host.score.to_string(),
+                host.rules.len().to_string(),
+            ];
+            record.extend(host.counts_by_level[..5].iter().map(|c| c.to_string()));
+            wtr.write_record(&record)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::host_score::HostScores;
+    use crate::detections::print::DetectInfo;
+
+    fn create_detect_info(computer: &str, rulepath: &str, level: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "a.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: level.to_string(),
+            computername: computer.to_string(),
+            eventid: "4624".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Security".to_string(),
+            alert: "alert".to_string(),
+            detail: "detail".to_string(),
+            tag_info: "".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_host_ranking() {
+        let mut scores = HostScores::new();
+        // 同じルールの繰り返し検知は重みが小さい
+        for _ in 0..20 {
+            scores.add(&create_detect_info("PC01", "noisy.yml", "low"));
+        }
+        scores.add(&create_detect_info("PC02", "mimikatz.yml", "critical"));
+        scores.add(&create_detect_info("PC02", "logon.yml", "informational"));
+
+        let ranking = scores.ranking();
+        assert_eq!(ranking.len(), 2);
+        assert_eq!(ranking[0].computer, "PC02");
+        assert_eq!(ranking[0].score, 100);
+        assert_eq!(ranking[0].rules.len(), 2);
+        assert_eq!(ranking[0].counts_by_level, [1, 0, 0, 0, 1, 0]);
+        assert_eq!(ranking[1].computer, "PC01");
+        assert_eq!(ranking[1].score, 10 + 19);
+        assert_eq!(ranking[1].counts_by_level, [0, 0, 0, 20, 0, 0]);
+    }
+}
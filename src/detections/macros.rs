@@ -0,0 +1,218 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, utils};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::io::BufWriter;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlLoader};
+
+pub const DETECTION_MACROS_CONFIG: &str = "detection_macros.yaml";
+// マクロを展開するフィールドの修飾子
+const EXPAND_MODIFIER: &str = "expand";
+
+lazy_static! {
+    /// config/detection_macros.yamlで定義したマクロ名と値のリスト
+    static ref DETECTION_MACROS: HashMap<String, Vec<Yaml>> = load_macros();
+}
+
+// config/detection_macros.yamlを読み込む。ファイルがない場合はバイナリに埋め込んだものを使う
+fn load_macros() -> HashMap<String, Vec<Yaml>> {
+    let path = configs::config_path(DETECTION_MACROS_CONFIG);
+    match utils::read_txt(&path).and_then(|lines| parse_macros(&lines.join("\n"))) {
+        Ok(macros) => macros,
+        Err(err) => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!(
+                    "Failed to load the detection macros. {} [file:{}]",
+                    err, path
+                ),
+            )
+            .ok();
+            HashMap::new()
+        }
+    }
+}
+
+/// macrosにマクロ名と値のリストを並べたYAMLを読み込む。値が1つの場合はリストでなくてもよい
+fn parse_macros(contents: &str) -> Result<HashMap<String, Vec<Yaml>>, String> {
+    let docs = YamlLoader::load_from_str(contents).map_err(|e| e.to_string())?;
+    let mut macros = HashMap::new();
+    let entries = match docs.first().and_then(|doc| doc["macros"].as_hash()) {
+        Some(entries) => entries,
+        None => return Ok(macros),
+    };
+    for (name, values) in entries.iter() {
+        let name = name
+            .as_str()
+            .ok_or_else(|| format!("Invalid macro name: {:?}", name))?;
+        let values = match values {
+            Yaml::Array(values) => values.to_vec(),
+            value => vec![value.clone()],
+        };
+        macros.insert(name.to_string(), values);
+    }
+    Ok(macros)
+}
+
+/**
+* ルールのdetectionでexpand修飾子を付けたフィールドの%マクロ名%を、config/detection_macros.yamlの値のリストに展開する。
+* 不審なパスやLOLBinsのように多くのローカルルールで使うリストを1か所で管理できるようにするため。
+* expand修飾子を使っていないルールはそのまま返す。
+*/
+pub fn expand_rule_macros(rule: Yaml) -> Result<Yaml, String> {
+    expand_macros(rule, &DETECTION_MACROS)
+}
+
+fn expand_macros(rule: Yaml, macros: &HashMap<String, Vec<Yaml>>) -> Result<Yaml, String> {
+    let detection = match rule["detection"].as_hash() {
+        Some(detection) if detection.values().any(uses_expand) => detection.clone(),
+        _ => return Ok(rule),
+    };
+    let mut new_detection = Hash::new();
+    for (key, value) in detection.into_iter() {
+        let value = match key.as_str() {
+            Some("condition") | Some("timeframe") => value,
+            _ => expand_selection(value, macros)?,
+        };
+        new_detection.insert(key, value);
+    }
+    let mut rule = match rule {
+        Yaml::Hash(rule) => rule,
+        _ => return Ok(rule),
+    };
+    rule.insert(
+        Yaml::String("detection".to_string()),
+        Yaml::Hash(new_detection),
+    );
+    Ok(Yaml::Hash(rule))
+}
+
+fn uses_expand(value: &Yaml) -> bool {
+    match value {
+        Yaml::Hash(hash) => hash.iter().any(|(key, value)| {
+            key.as_str().map_or(false, |key| {
+                key.split('|')
+                    .skip(1)
+                    .any(|modifier| modifier == EXPAND_MODIFIER)
+            }) || uses_expand(value)
+        }),
+        Yaml::Array(values) => values.iter().any(uses_expand),
+        _ => false,
+    }
+}
+
+fn expand_selection(selection: Yaml, macros: &HashMap<String, Vec<Yaml>>) -> Result<Yaml, String> {
+    match selection {
+        Yaml::Hash(hash) => {
+            let mut new_hash = Hash::new();
+            for (key, value) in hash.into_iter() {
+                let (key, value) = match key.as_str() {
+                    Some(key_str) if key_str.split('|').any(|m| m == EXPAND_MODIFIER) => {
+                        let key_str = key_str
+                            .split('|')
+                            .filter(|m| *m != EXPAND_MODIFIER)
+                            .collect::<Vec<&str>>()
+                            .join("|");
+                        (Yaml::String(key_str), expand_values(value, macros)?)
+                    }
+                    _ => (key, value),
+                };
+                new_hash.insert(key, value);
+            }
+            Ok(Yaml::Hash(new_hash))
+        }
+        Yaml::Array(values) => Ok(Yaml::Array(
+            values
+                .into_iter()
+                .map(|value| expand_selection(value, macros))
+                .collect::<Result<Vec<Yaml>, String>>()?,
+        )),
+        value => Ok(value),
+    }
+}
+
+/// %マクロ名%の値をマクロの値のリストに置き換える。マクロでない値はそのまま残す
+fn expand_values(value: Yaml, macros: &HashMap<String, Vec<Yaml>>) -> Result<Yaml, String> {
+    let values = match value {
+        Yaml::Array(values) => values,
+        value => vec![value],
+    };
+    let mut expanded = vec![];
+    for value in values {
+        let name = value
+            .as_str()
+            .and_then(|s| s.strip_prefix('%'))
+            .and_then(|s| s.strip_suffix('%'));
+        match name {
+            Some(name) => match macros.get(name) {
+                Some(macro_values) => expanded.extend(macro_values.iter().cloned()),
+                None => return Err(format!("Unknown detection macro: {}", name)),
+            },
+            None => expanded.push(value),
+        }
+    }
+    Ok(Yaml::Array(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::macros::{expand_macros, parse_macros};
+    use std::fs;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_expand_macros() {
+        let macros = parse_macros(
+            "macros:\n    lolbins:\n        - '\\certutil.exe'\n        - '\\mshta.exe'\n    temp: '\\Temp\\'\n",
+        )
+        .unwrap();
+        let rule = YamlLoader::load_from_str(
+            r#"
+title: LOLBin From Temp
+detection:
+    selection:
+        Image|endswith|expand: '%lolbins%'
+        CommandLine|contains|expand:
+            - '%temp%'
+            - '\Downloads\'
+    condition: selection
+"#,
+        )
+        .unwrap()
+        .remove(0);
+        let expanded = expand_macros(rule, &macros).unwrap();
+        let selection = &expanded["detection"]["selection"];
+        assert_eq!(selection["Image|endswith"][1].as_str(), Some("\\mshta.exe"));
+        assert_eq!(
+            selection["CommandLine|contains"][0].as_str(),
+            Some("\\Temp\\")
+        );
+        assert_eq!(
+            selection["CommandLine|contains"][1].as_str(),
+            Some("\\Downloads\\")
+        );
+        assert_eq!(
+            expanded["detection"]["condition"].as_str(),
+            Some("selection")
+        );
+
+        let unknown = YamlLoader::load_from_str(
+            "detection:\n    selection:\n        Image|expand: '%unknown%'\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            expand_macros(unknown, &macros).unwrap_err(),
+            "Unknown detection macro: unknown"
+        );
+    }
+
+    #[test]
+    fn test_default_macros() {
+        let macros =
+            parse_macros(&fs::read_to_string("config/detection_macros.yaml").unwrap()).unwrap();
+        assert!(macros.contains_key("suspicious_paths"));
+        assert!(macros.contains_key("lolbins"));
+    }
+}
@@ -1,6 +1,15 @@
 pub mod configs;
+pub mod context;
 pub mod detection;
+pub mod external_sort;
+pub mod graph;
+pub mod hash_lookup;
+pub mod host_score;
+pub mod macros;
 pub mod pivot;
+pub mod powershell;
 pub mod print;
 pub mod rule;
+pub mod search;
+pub mod suppression;
 pub mod utils;
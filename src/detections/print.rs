@@ -1,19 +1,28 @@
 extern crate lazy_static;
 use crate::detections::configs;
+use crate::detections::external_sort::{EXTERNAL_SORTER, SORT_FLAG};
+use crate::detections::powershell;
 use crate::detections::utils;
 use crate::detections::utils::get_serde_number_to_string;
+use crate::logging;
+use crate::output::auto_name::{DETECTION_RANGE, OUTPUT_AUTO_TEMPLATE};
+use crate::output::noise::{AUTO_TUNE_NOISE_THRESHOLD, NOISE_COUNTER};
+use crate::output::rule_count::{RULE_COUNT_FILTER, RULE_HIT_COUNTER};
 use chrono::{DateTime, Local, TimeZone, Utc};
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
 use std::fs::create_dir;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::{self, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 
 #[derive(Debug)]
@@ -28,6 +37,7 @@ pub struct DetectInfo {
     pub level: String,
     pub computername: String,
     pub eventid: String,
+    pub record_id: String,
     pub channel: String,
     pub alert: String,
     pub detail: String,
@@ -35,21 +45,100 @@ pub struct DetectInfo {
     pub record_information: Option<String>,
 }
 
+/// エラーログに出力するエラーの分類
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    FileNotFound,
+    FileRead,
+    EvtxParse,
+    RuleParse,
+    RuleCount,
+    Other,
+}
+
+impl ErrorClass {
+    /// ファイル操作のエラーを、ファイルが存在しないエラーとそれ以外の読み込みエラーに分類する
+    pub fn from_io_error(err: &io::Error) -> ErrorClass {
+        if err.kind() == io::ErrorKind::NotFound {
+            ErrorClass::FileNotFound
+        } else {
+            ErrorClass::FileRead
+        }
+    }
+}
+
+/// エラーログに出力する1件分のエラー
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorLog {
+    pub level: &'static str,
+    pub class: ErrorClass,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<u64>,
+    pub message: String,
+}
+
+impl ErrorLog {
+    pub fn error(class: ErrorClass, message: &str) -> ErrorLog {
+        ErrorLog {
+            level: "ERROR",
+            class,
+            file_path: None,
+            record_id: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn warn(class: ErrorClass, message: &str) -> ErrorLog {
+        ErrorLog {
+            level: "WARN",
+            ..ErrorLog::error(class, message)
+        }
+    }
+
+    pub fn with_file_path(mut self, file_path: &str) -> ErrorLog {
+        self.file_path = Some(file_path.to_string());
+        self
+    }
+
+    pub fn with_record_id(mut self, record_id: Option<u64>) -> ErrorLog {
+        self.record_id = record_id;
+        self
+    }
+}
+
+impl fmt::Display for ErrorLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.level, self.message)
+    }
+}
+
 pub struct AlertMessage {}
 
+/// 検知した件数。--sortでディスクに書き出した検知も数える
+pub static DETECTION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 lazy_static! {
     pub static ref MESSAGES: Mutex<Message> = Mutex::new(Message::new());
     pub static ref ALIASREGEX: Regex = Regex::new(r"%[a-zA-Z0-9-_]+%").unwrap();
+    pub static ref JSON_ERROR_LOG_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("json-error-log");
     pub static ref ERROR_LOG_PATH: String = format!(
-        "./logs/errorlog-{}.log",
-        Local::now().format("%Y%m%d_%H%M%S")
+        "./logs/errorlog-{}.{}",
+        Local::now().format("%Y%m%d_%H%M%S"),
+        if *JSON_ERROR_LOG_FLAG { "json" } else { "log" }
     );
     pub static ref QUIET_ERRORS_FLAG: bool = configs::CONFIG
         .read()
         .unwrap()
         .args
         .is_present("quiet-errors");
-    pub static ref ERROR_LOG_STACK: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    pub static ref ERROR_LOG_STACK: Mutex<Vec<ErrorLog>> = Mutex::new(Vec::new());
     pub static ref STATISTICS_FLAG: bool = configs::CONFIG
         .read()
         .unwrap()
@@ -60,10 +149,20 @@ lazy_static! {
         .unwrap()
         .args
         .is_present("logon-summary");
+    pub static ref LOG_METRICS_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("log-metrics");
+    pub static ref PROVIDER_METRICS_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("provider-metrics");
     pub static ref TAGS_CONFIG: HashMap<String, String> =
-        Message::create_output_filter_config("config/output_tag.txt");
+        Message::create_output_filter_config(&configs::config_path("output_tag.txt"));
     pub static ref CH_CONFIG: HashMap<String, String> =
-        Message::create_output_filter_config("config/channel_abbreviations.txt");
+        Message::create_output_filter_config(&configs::config_path("channel_abbreviations.txt"));
     pub static ref PIVOT_KEYWORD_LIST_FLAG: bool = configs::CONFIG
         .read()
         .unwrap()
@@ -112,17 +211,56 @@ impl Message {
 
     /// メッセージの設定を行う関数。aggcondition対応のためrecordではなく出力をする対象時間がDatetime形式での入力としている
     pub fn insert_message(&mut self, detect_info: DetectInfo, event_time: DateTime<Utc>) {
+        DETECTION_COUNT.fetch_add(1, Ordering::Relaxed);
+        if AUTO_TUNE_NOISE_THRESHOLD.is_some() {
+            NOISE_COUNTER.lock().unwrap().count(&detect_info);
+        }
+        if RULE_COUNT_FILTER.is_some() {
+            RULE_HIT_COUNTER.lock().unwrap().count(&detect_info);
+        }
+        if OUTPUT_AUTO_TEMPLATE.is_some() {
+            DETECTION_RANGE
+                .lock()
+                .unwrap()
+                .add(&detect_info.computername, &event_time);
+        }
         if let Some(v) = self.map.get_mut(&event_time) {
             v.push(detect_info);
         } else {
             let m = vec![detect_info; 1];
             self.map.insert(event_time, m);
         }
+
+        // --sortが指定されている場合、メモリ上の件数が上限に達したらディスクに書き出す
+        if *SORT_FLAG {
+            let mut sorter = EXTERNAL_SORTER.lock().unwrap();
+            if sorter.count_up() {
+                match sorter.spill(&self.map) {
+                    Ok(_) => self.map.clear(),
+                    Err(err) => {
+                        AlertMessage::alert(
+                            &mut BufWriter::new(std::io::stderr().lock()),
+                            &format!("Failed to write sorted detections to disk. {}", err),
+                        )
+                        .ok();
+                    }
+                }
+            }
+        }
     }
 
     /// メッセージを設定
     pub fn insert(&mut self, event_record: &Value, output: String, mut detect_info: DetectInfo) {
         detect_info.detail = self.parse_message(event_record, output);
+        // エンコードされたPowerShellのコマンドはデコードした結果も出力する
+        if let Some(decoded) = powershell::decoded_command(event_record) {
+            let decoded: Vec<&str> = decoded.split_whitespace().collect();
+            detect_info.detail = format!(
+                "{} : DecodedCommand: {}",
+                detect_info.detail,
+                decoded.join(" ")
+            );
+        }
         let default_time = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
         let time = Message::get_event_time(event_record).unwrap_or(default_time);
         self.insert_message(detect_info, time)
@@ -226,17 +364,22 @@ impl AlertMessage {
             create_dir(path.parent().unwrap()).ok();
         }
         let mut error_log_writer = BufWriter::new(File::create(path).unwrap());
-        error_log_writer
-            .write_all(
-                format!(
-                    "user input: {:?}\n",
-                    format_args!("{}", env::args().collect::<Vec<String>>().join(" "))
-                )
-                .as_bytes(),
-            )
-            .ok();
-        for error_log in ERROR_LOG_STACK.lock().unwrap().iter() {
-            writeln!(error_log_writer, "{}", error_log).ok();
+        let user_input = env::args().collect::<Vec<String>>().join(" ");
+        if *JSON_ERROR_LOG_FLAG {
+            // パイプラインで自動的に分類できるように、エラーの分類やファイルパスを含めたJSON形式で出力する
+            let error_log = json!({
+                "user_input": user_input,
+                "errors": *ERROR_LOG_STACK.lock().unwrap(),
+            });
+            serde_json::to_writer_pretty(&mut error_log_writer, &error_log).ok();
+            writeln!(error_log_writer).ok();
+        } else {
+            error_log_writer
+                .write_all(format!("user input: {}\n", user_input).as_bytes())
+                .ok();
+            for error_log in ERROR_LOG_STACK.lock().unwrap().iter() {
+                writeln!(error_log_writer, "{}", error_log).ok();
+            }
         }
         println!(
             "Errors were generated. Please check {} for details.",
@@ -245,13 +388,21 @@ impl AlertMessage {
         println!();
     }
 
-    /// ERRORメッセージを表示する関数
+    /// ERRORメッセージを表示する関数。ログの出力を設定済みの場合はtracingで出力する
     pub fn alert<W: Write>(w: &mut W, contents: &str) -> io::Result<()> {
+        if logging::is_enabled() {
+            tracing::error!("{}", contents);
+            return Ok(());
+        }
         writeln!(w, "[ERROR] {}", contents)
     }
 
-    /// WARNメッセージを表示する関数
+    /// WARNメッセージを表示する関数。ログの出力を設定済みの場合はtracingで出力する
     pub fn warn<W: Write>(w: &mut W, contents: &str) -> io::Result<()> {
+        if logging::is_enabled() {
+            tracing::warn!("{}", contents);
+            return Ok(());
+        }
         writeln!(w, "[WARN] {}", contents)
     }
 }
@@ -260,6 +411,7 @@ impl AlertMessage {
 mod tests {
     use crate::detections::print::DetectInfo;
     use crate::detections::print::{AlertMessage, Message};
+    use crate::detections::print::{ErrorClass, ErrorLog};
     use hashbrown::HashMap;
     use serde_json::Value;
     use std::io::BufWriter;
@@ -291,6 +443,7 @@ mod tests {
                 level: "high".to_string(),
                 computername: "testcomputer1".to_string(),
                 eventid: "1".to_string(),
+                record_id: "1".to_string(),
                 channel: String::default(),
                 alert: "test1".to_string(),
                 detail: String::default(),
@@ -323,6 +476,7 @@ mod tests {
                 level: "high".to_string(),
                 computername: "testcomputer2".to_string(),
                 eventid: "2".to_string(),
+                record_id: "2".to_string(),
                 channel: String::default(),
                 alert: "test2".to_string(),
                 detail: String::default(),
@@ -355,6 +509,7 @@ mod tests {
                 level: "high".to_string(),
                 computername: "testcomputer3".to_string(),
                 eventid: "3".to_string(),
+                record_id: "3".to_string(),
                 channel: String::default(),
                 alert: "test3".to_string(),
                 detail: String::default(),
@@ -382,6 +537,7 @@ mod tests {
                 level: "medium".to_string(),
                 computername: "testcomputer4".to_string(),
                 eventid: "4".to_string(),
+                record_id: "4".to_string(),
                 channel: String::default(),
                 alert: "test4".to_string(),
                 detail: String::default(),
@@ -392,7 +548,7 @@ mod tests {
 
         let display = format!("{}", format_args!("{:?}", message));
         println!("display::::{}", display);
-        let expect = "Message { map: {1970-01-01T00:00:00Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule4\", level: \"medium\", computername: \"testcomputer4\", eventid: \"4\", channel: \"\", alert: \"test4\", detail: \"CommandLine4: hoge\", tag_info: \"txxx.004\", record_information: Some(\"record_information4\") }], 1996-02-27T01:05:01Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule\", level: \"high\", computername: \"testcomputer1\", eventid: \"1\", channel: \"\", alert: \"test1\", detail: \"CommandLine1: hoge\", tag_info: \"txxx.001\", record_information: Some(\"record_information1\") }, DetectInfo { filepath: \"a\", rulepath: \"test_rule2\", level: \"high\", computername: \"testcomputer2\", eventid: \"2\", channel: \"\", alert: \"test2\", detail: \"CommandLine2: hoge\", tag_info: \"txxx.002\", record_information: Some(\"record_information2\") }], 2000-01-21T09:06:01Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule3\", level: \"high\", computername: \"testcomputer3\", eventid: \"3\", channel: \"\", alert: \"test3\", detail: \"CommandLine3: hoge\", tag_info: \"txxx.003\", record_information: Some(\"record_information3\") }]} }";
+        let expect = "Message { map: {1970-01-01T00:00:00Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule4\", level: \"medium\", computername: \"testcomputer4\", eventid: \"4\", record_id: \"4\", channel: \"\", alert: \"test4\", detail: \"CommandLine4: hoge\", tag_info: \"txxx.004\", record_information: Some(\"record_information4\") }], 1996-02-27T01:05:01Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule\", level: \"high\", computername: \"testcomputer1\", eventid: \"1\", record_id: \"1\", channel: \"\", alert: \"test1\", detail: \"CommandLine1: hoge\", tag_info: \"txxx.001\", record_information: Some(\"record_information1\") }, DetectInfo { filepath: \"a\", rulepath: \"test_rule2\", level: \"high\", computername: \"testcomputer2\", eventid: \"2\", record_id: \"2\", channel: \"\", alert: \"test2\", detail: \"CommandLine2: hoge\", tag_info: \"txxx.002\", record_information: Some(\"record_information2\") }], 2000-01-21T09:06:01Z: [DetectInfo { filepath: \"a\", rulepath: \"test_rule3\", level: \"high\", computername: \"testcomputer3\", eventid: \"3\", record_id: \"3\", channel: \"\", alert: \"test3\", detail: \"CommandLine3: hoge\", tag_info: \"txxx.003\", record_information: Some(\"record_information3\") }]} }";
         assert_eq!(display, expect);
     }
 
@@ -527,4 +683,25 @@ mod tests {
             assert!(actual.get(k).unwrap_or(&String::default()) == v);
         }
     }
+
+    #[test]
+    fn test_error_log_format() {
+        let error_log = ErrorLog::error(ErrorClass::EvtxParse, "Failed to parse event file.")
+            .with_file_path("test.evtx")
+            .with_record_id(Some(10));
+        assert_eq!(error_log.to_string(), "[ERROR] Failed to parse event file.");
+        assert_eq!(
+            serde_json::to_string(&error_log).unwrap(),
+            r#"{"level":"ERROR","class":"evtx_parse","file_path":"test.evtx","record_id":10,"message":"Failed to parse event file."}"#
+        );
+
+        let error_log = ErrorLog::warn(
+            ErrorClass::from_io_error(&std::io::Error::from(std::io::ErrorKind::NotFound)),
+            "not found",
+        );
+        assert_eq!(
+            serde_json::to_string(&error_log).unwrap(),
+            r#"{"level":"WARN","class":"file_not_found","message":"not found"}"#
+        );
+    }
 }
@@ -1,20 +1,19 @@
-use crate::detections::configs;
-use crate::detections::print::AlertMessage;
 use crate::detections::print::ERROR_LOG_STACK;
 use crate::detections::print::QUIET_ERRORS_FLAG;
+use crate::detections::print::{ErrorClass, ErrorLog};
 use crate::detections::rule::AggResult;
 use crate::detections::rule::Message;
 use crate::detections::rule::RuleNode;
 use chrono::{DateTime, TimeZone, Utc};
 use hashbrown::HashMap;
 use serde_json::Value;
-use std::io::BufWriter;
 use std::num::ParseIntError;
 use std::path::Path;
 
 use crate::detections::rule::aggregation_parser::AggregationConditionToken;
 
 use crate::detections::utils;
+use crate::logging;
 
 /// 検知された際にカウント情報を投入する関数
 pub fn count(rule: &mut RuleNode, record: &Value) {
@@ -87,14 +86,11 @@ fn get_alias_value_in_record(
           utils::get_event_value(&utils::get_event_id_key(), record).unwrap()
         ),
             };
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
-            }
+            logging::verbose_error(&errmsg);
             if !*QUIET_ERRORS_FLAG {
-                ERROR_LOG_STACK
-                    .lock()
-                    .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
+                ERROR_LOG_STACK.lock().unwrap().push(
+                    ErrorLog::error(ErrorClass::RuleCount, &errmsg).with_file_path(&rule.rulepath),
+                );
             }
             None
         }
@@ -189,14 +185,12 @@ impl TimeFrameInfo {
             tnum.retain(|c| c != 'd');
         } else {
             let errmsg = format!("Timeframe is invalid. Input value:{}", value);
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
-            }
+            logging::verbose_error(&errmsg);
             if !*QUIET_ERRORS_FLAG {
                 ERROR_LOG_STACK
                     .lock()
                     .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
+                    .push(ErrorLog::error(ErrorClass::RuleParse, &errmsg));
             }
         }
         TimeFrameInfo {
@@ -225,14 +219,12 @@ pub fn get_sec_timeframe(rule: &RuleNode) -> Option<i64> {
         }
         Err(err) => {
             let errmsg = format!("Timeframe number is invalid. timeframe. {}", err);
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
-            }
+            logging::verbose_error(&errmsg);
             if !*QUIET_ERRORS_FLAG {
                 ERROR_LOG_STACK
                     .lock()
                     .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
+                    .push(ErrorLog::error(ErrorClass::RuleParse, &errmsg));
             }
             Option::None
         }
@@ -1,4 +1,6 @@
+use hashbrown::HashMap;
 use regex::Regex;
+use std::sync::{Arc, Mutex};
 use std::{cmp::Ordering, collections::VecDeque};
 use yaml_rust::Yaml;
 
@@ -8,6 +10,20 @@ use downcast_rs::Downcast;
 use lazy_static::lazy_static;
 lazy_static! {
     pub static ref STR_DEFAULT: String = String::default();
+    // パターンとパイプから作った正規表現の文字列毎のコンパイル済みの正規表現。
+    // 多くのルールが同じパターンを使うので、ルール間で共有して読み込み時間とメモリを削減する
+    static ref REGEX_CACHE: Mutex<HashMap<String, Arc<Regex>>> = Mutex::new(HashMap::new());
+}
+
+/// 正規表現をコンパイルする。同じ正規表現の文字列は1度だけコンパイルして共有する
+pub fn compile_regex(pattern: &str) -> Result<Arc<Regex>, regex::Error> {
+    let mut cache = REGEX_CACHE.lock().unwrap();
+    if let Some(re) = cache.get(pattern) {
+        return Ok(Arc::clone(re));
+    }
+    let re = Arc::new(Regex::new(pattern)?);
+    cache.insert(pattern.to_string(), Arc::clone(&re));
+    Ok(re)
 }
 
 // 末端ノードがEventLogの値を比較するロジックを表す。
@@ -189,7 +205,7 @@ impl LeafMatcher for AllowlistFileMatcher {
 /// デフォルトのマッチクラス
 /// ワイルドカードの処理やパイプ
 pub struct DefaultMatcher {
-    re: Option<Regex>,
+    re: Option<Arc<Regex>>,
     pipes: Vec<PipeElement>,
     key_list: Vec<String>,
     eqfield_key: Option<String>,
@@ -313,7 +329,7 @@ impl LeafMatcher for DefaultMatcher {
 
             let pattern = DefaultMatcher::from_pattern_to_regex_str(pattern, &self.pipes);
             // Pipeで処理されたパターンを正規表現に変換
-            let re_result = Regex::new(&pattern);
+            let re_result = compile_regex(&pattern);
             if re_result.is_err() {
                 let errmsg = format!(
                     "Cannot parse regex. [regex:{}, key:{}]",
@@ -503,6 +519,7 @@ mod tests {
     };
     use crate::detections::rule::tests::parse_rule_from_str;
     use crate::detections::{self, utils};
+    use std::sync::Arc;
 
     #[test]
     fn test_rule_parse() {
@@ -703,6 +720,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compile_regex_shared() {
+        // 同じパターンと修飾子の正規表現はルール間で共有する
+        let get_re = |rule_str: &str| {
+            let rule_node = parse_rule_from_str(rule_str);
+            let selection_node = &rule_node.detection.name_to_selection["selection"];
+            let child_node = selection_node.get_childs()[0] as &dyn SelectionNode;
+            let child_node = child_node.downcast_ref::<LeafSelectionNode>().unwrap();
+            let matcher = child_node.matcher.as_ref().unwrap();
+            let matcher = matcher.downcast_ref::<DefaultMatcher>().unwrap();
+            Arc::clone(matcher.re.as_ref().unwrap())
+        };
+        let re1 = get_re(
+            r#"
+        enabled: true
+        detection:
+            selection:
+                Image|endswith: '\shared_cache_test.exe'
+        details: 'command=%CommandLine%'
+        "#,
+        );
+        let re2 = get_re(
+            r#"
+        enabled: true
+        detection:
+            selection:
+                Image|endswith: '\shared_cache_test.exe'
+        details: 'image=%Image%'
+        "#,
+        );
+        let re3 = get_re(
+            r#"
+        enabled: true
+        detection:
+            selection:
+                Image|startswith: '\shared_cache_test.exe'
+        details: 'image=%Image%'
+        "#,
+        );
+        assert!(Arc::ptr_eq(&re1, &re2));
+        assert!(!Arc::ptr_eq(&re1, &re3));
+    }
+
     #[test]
     fn test_notdetect_regex_eventid() {
         // 完全一致なので、前方一致で検知しないことを確認
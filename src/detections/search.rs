@@ -0,0 +1,200 @@
+use crate::detections::configs::{self, TargetEventTime};
+use crate::detections::detection::EvtxRecordInfo;
+use crate::detections::print::{AlertMessage, DetectInfo, Message, CH_CONFIG, MESSAGES};
+use crate::detections::utils::get_serde_number_to_string;
+use chrono::{TimeZone, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use std::io::BufWriter;
+
+lazy_static! {
+    /// --searchが指定されている場合の検索条件
+    pub static ref SEARCHER: Option<Searcher> = Searcher::from_config();
+}
+
+enum SearchPattern {
+    // 大文字小文字を区別しない文字列検索。小文字に変換して保持する。
+    Keyword(String),
+    Regex(Regex),
+}
+
+/**
+* ルールを使わずに、全てのフィールドの値をキーワードまたは正規表現で検索する
+*/
+pub struct Searcher {
+    pattern: SearchPattern,
+    title: String,
+    target_time: TargetEventTime,
+}
+
+impl Searcher {
+    pub fn new(
+        keyword: &str,
+        is_regex: bool,
+        target_time: TargetEventTime,
+    ) -> Result<Self, String> {
+        let pattern = if is_regex {
+            SearchPattern::Regex(Regex::new(keyword).map_err(|e| e.to_string())?)
+        } else {
+            SearchPattern::Keyword(keyword.to_lowercase())
+        };
+        Ok(Searcher {
+            pattern,
+            title: format!("Search: {}", keyword),
+            target_time,
+        })
+    }
+
+    fn from_config() -> Option<Self> {
+        let (keyword, is_regex) = {
+            let config = configs::CONFIG.read().unwrap();
+            (
+                config.args.value_of("search")?.to_string(),
+                config.args.is_present("regex"),
+            )
+        };
+        match Searcher::new(&keyword, is_regex, TargetEventTime::new()) {
+            Ok(searcher) => Some(searcher),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to parse the search regex. {}", err),
+                )
+                .ok();
+                None
+            }
+        }
+    }
+
+    fn is_match_str(&self, value: &str) -> bool {
+        match &self.pattern {
+            SearchPattern::Keyword(keyword) => value.to_lowercase().contains(keyword),
+            SearchPattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+
+    /// 条件に合致した最初のフィールドのキーと値を返す
+    pub fn find_match(&self, record: &Value) -> Option<(String, String)> {
+        let mut keys = vec![];
+        self.find_match_in(&mut keys, record)
+    }
+
+    fn find_match_in(&self, keys: &mut Vec<String>, value: &Value) -> Option<(String, String)> {
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map.iter() {
+                    keys.push(key.to_string());
+                    let ret = self.find_match_in(keys, child);
+                    keys.pop();
+                    if ret.is_some() {
+                        return ret;
+                    }
+                }
+                None
+            }
+            Value::Array(array) => array
+                .iter()
+                .find_map(|child| self.find_match_in(keys, child)),
+            Value::Null => None,
+            _ => {
+                let value = get_serde_number_to_string(value)?;
+                if self.is_match_str(&value) {
+                    Some((keys.join("."), value))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// 条件に合致したレコードをタイムラインに出力する
+    pub fn search(&self, records: &[EvtxRecordInfo]) {
+        let default_time = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
+        for record_info in records.iter() {
+            let time = Message::get_event_time(&record_info.record);
+            if !self.target_time.is_target(&time) {
+                continue;
+            }
+            let (key, value) = match self.find_match(&record_info.record) {
+                Some(matched) => matched,
+                None => continue,
+            };
+            let system = &record_info.record["Event"]["System"];
+            let detect_info = DetectInfo {
+                filepath: record_info.evtx_filepath.to_string(),
+                rulepath: "-".to_string(),
+                level: "-".to_string(),
+                computername: get_serde_number_to_string(&system["Computer"])
+                    .unwrap_or_else(|| "-".to_owned()),
+                eventid: get_serde_number_to_string(&system["EventID"])
+                    .unwrap_or_else(|| "-".to_owned()),
+                record_id: get_serde_number_to_string(&system["EventRecordID"])
+                    .unwrap_or_else(|| "-".to_owned()),
+                channel: CH_CONFIG
+                    .get(&get_serde_number_to_string(&system["Channel"]).unwrap_or_default())
+                    .unwrap_or(&String::default())
+                    .to_string(),
+                alert: self.title.to_string(),
+                detail: format!(
+                    "{}: {}",
+                    key,
+                    value.split_whitespace().collect::<Vec<_>>().join(" ")
+                ),
+                tag_info: String::default(),
+                record_information: record_info.record_information.clone(),
+            };
+            MESSAGES
+                .lock()
+                .unwrap()
+                .insert_message(detect_info, time.unwrap_or(default_time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::configs::TargetEventTime;
+    use crate::detections::search::Searcher;
+    use serde_json::Value;
+
+    fn create_record() -> Value {
+        serde_json::from_str(
+            r#"{"Event": {"System": {"EventID": 4688, "Channel": "Security"}, "EventData": {"NewProcessName": "C:\\Tools\\Mimikatz.exe", "Hashes": ["A", "B"]}}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_keyword_search() {
+        let searcher = Searcher::new("mimikatz", false, TargetEventTime::set(None, None)).unwrap();
+        assert_eq!(
+            searcher.find_match(&create_record()),
+            Some((
+                "Event.EventData.NewProcessName".to_string(),
+                "C:\\Tools\\Mimikatz.exe".to_string()
+            ))
+        );
+        let searcher = Searcher::new("4688", false, TargetEventTime::set(None, None)).unwrap();
+        assert_eq!(
+            searcher.find_match(&create_record()),
+            Some(("Event.System.EventID".to_string(), "4688".to_string()))
+        );
+        let searcher = Searcher::new("psexec", false, TargetEventTime::set(None, None)).unwrap();
+        assert_eq!(searcher.find_match(&create_record()), None);
+    }
+
+    #[test]
+    fn test_regex_search() {
+        let searcher = Searcher::new(r"^[AB]$", true, TargetEventTime::set(None, None)).unwrap();
+        assert_eq!(
+            searcher.find_match(&create_record()),
+            Some(("Event.EventData.Hashes".to_string(), "A".to_string()))
+        );
+        // 正規表現は大文字小文字を区別する
+        let searcher =
+            Searcher::new(r"mimikatz\.exe$", true, TargetEventTime::set(None, None)).unwrap();
+        assert_eq!(searcher.find_match(&create_record()), None);
+        assert!(Searcher::new(r"(", true, TargetEventTime::set(None, None)).is_err());
+    }
+}
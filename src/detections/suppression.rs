@@ -0,0 +1,440 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, utils};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use linked_hash_map::LinkedHashMap;
+use regex::Regex;
+use serde_json::Value;
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Mutex;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+pub const SUPPRESSIONS_CONFIG: &str = "suppressions.yaml";
+
+// 許可リストを学習する時に記録するフィールド。レコードにあるものだけを記録する
+const ALLOWLIST_KEY_FIELDS: [&str; 9] = [
+    "Image",
+    "ParentImage",
+    "CommandLine",
+    "TargetFilename",
+    "TargetObject",
+    "ServiceName",
+    "ImagePath",
+    "TaskName",
+    "QueryName",
+];
+
+lazy_static! {
+    pub static ref LEARN_ALLOWLIST_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("learn-allowlist");
+    /// config/suppressions.yamlと--allowlistで指定した抑制する検知の条件
+    static ref SUPPRESSIONS: Suppressions = load_suppressions();
+    /// ルールのタイトル毎の抑制した検知の件数
+    static ref SUPPRESSED: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+    /// 学習した抑制する検知の条件。同じ条件は1つにまとめる
+    static ref LEARNED: Mutex<HashMap<String, LearnedEntry>> = Mutex::new(HashMap::new());
+}
+
+/// フィールドの値の比較方法。Sigmaルールと同じ修飾子を使い、re以外は大文字小文字を区別しない
+#[derive(Debug, Clone)]
+enum Matcher {
+    Equals(String),
+    Contains(String),
+    StartsWith(String),
+    EndsWith(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(modifier: &str, value: &str) -> Result<Matcher, String> {
+        match modifier {
+            "" => Ok(Matcher::Equals(value.to_string())),
+            "contains" => Ok(Matcher::Contains(value.to_string())),
+            "startswith" => Ok(Matcher::StartsWith(value.to_string())),
+            "endswith" => Ok(Matcher::EndsWith(value.to_string())),
+            "re" => Regex::new(value)
+                .map(Matcher::Regex)
+                .map_err(|e| e.to_string()),
+            _ => Err(format!("Unknown modifier: {}", modifier)),
+        }
+    }
+
+    fn is_match(&self, actual: &str) -> bool {
+        let actual_lower = actual.to_lowercase();
+        match self {
+            Matcher::Equals(value) => actual_lower == value.to_lowercase(),
+            Matcher::Contains(value) => actual_lower.contains(&value.to_lowercase()),
+            Matcher::StartsWith(value) => actual_lower.starts_with(&value.to_lowercase()),
+            Matcher::EndsWith(value) => actual_lower.ends_with(&value.to_lowercase()),
+            Matcher::Regex(regex) => regex.is_match(actual),
+        }
+    }
+}
+
+/// フィールドの値の条件。いずれかの値に一致すれば条件を満たす
+#[derive(Debug, Clone)]
+struct FieldCondition {
+    field: String,
+    matchers: Vec<Matcher>,
+}
+
+impl FieldCondition {
+    /// Image|endswithのようなフィールド名と修飾子、値または値のリストから条件を作る
+    fn parse(key: &str, value: &Yaml) -> Result<FieldCondition, String> {
+        let (field, modifier) = key.split_once('|').unwrap_or((key, ""));
+        let values: Vec<String> = match value {
+            Yaml::Array(values) => values.iter().filter_map(yaml_to_string).collect(),
+            value => yaml_to_string(value).into_iter().collect(),
+        };
+        let matchers = values
+            .iter()
+            .map(|value| Matcher::new(&modifier.to_lowercase(), value))
+            .collect::<Result<Vec<Matcher>, String>>()
+            .map_err(|e| format!("{} [field:{}]", e, key))?;
+        Ok(FieldCondition {
+            field: field.to_string(),
+            matchers,
+        })
+    }
+
+    fn is_match(&self, record: &Value) -> bool {
+        utils::get_event_value(&self.field, record)
+            .and_then(utils::value_to_string)
+            .map_or(false, |actual| {
+                self.matchers
+                    .iter()
+                    .any(|matcher| matcher.is_match(&actual))
+            })
+    }
+}
+
+/// ルールのIDとフィールドの値の条件。全てのフィールドの条件を満たす検知を抑制する
+#[derive(Debug, Clone)]
+pub struct SuppressionEntry {
+    pub rule: String,
+    pub title: String,
+    conditions: Vec<FieldCondition>,
+}
+
+impl SuppressionEntry {
+    fn is_match(&self, record: &Value) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.is_match(record))
+    }
+}
+
+/// ルールのID毎の抑制する検知の条件
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    entries: HashMap<String, Vec<SuppressionEntry>>,
+}
+
+impl Suppressions {
+    pub fn load(path: &str) -> Result<Suppressions, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{} [file:{}]", e, path))?;
+        Suppressions::parse(&contents).map_err(|e| format!("{} [file:{}]", e, path))
+    }
+
+    /// suppressionsにrule、title、fieldsを持つ条件を並べたYAMLを読み込む
+    fn parse(contents: &str) -> Result<Suppressions, String> {
+        let docs = YamlLoader::load_from_str(contents).map_err(|e| e.to_string())?;
+        let mut suppressions = Suppressions::default();
+        let entries = match docs.first().and_then(|doc| doc["suppressions"].as_vec()) {
+            Some(entries) => entries,
+            None => return Ok(suppressions),
+        };
+        for entry in entries.iter() {
+            let rule = match entry["rule"].as_str() {
+                Some(rule) => rule.to_string(),
+                None => continue,
+            };
+            let mut conditions = vec![];
+            if let Some(fields) = entry["fields"].as_hash() {
+                for (key, value) in fields.iter() {
+                    if let Some(key) = key.as_str() {
+                        conditions.push(FieldCondition::parse(key, value)?);
+                    }
+                }
+            }
+            suppressions
+                .entries
+                .entry(rule.to_string())
+                .or_default()
+                .push(SuppressionEntry {
+                    rule,
+                    title: entry["title"].as_str().unwrap_or_default().to_string(),
+                    conditions,
+                });
+        }
+        Ok(suppressions)
+    }
+
+    /// 別のファイルから読み込んだ条件を追加する
+    pub fn extend(&mut self, other: Suppressions) {
+        for (rule, entries) in other.entries {
+            self.entries.entry(rule).or_default().extend(entries);
+        }
+    }
+
+    pub fn is_suppressed(&self, rule: &str, record: &Value) -> bool {
+        self.entries.get(rule).map_or(false, |entries| {
+            entries.iter().any(|entry| entry.is_match(record))
+        })
+    }
+}
+
+fn yaml_to_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(value) => Some(value.to_string()),
+        Yaml::Integer(value) => Some(value.to_string()),
+        Yaml::Real(value) => Some(value.to_string()),
+        Yaml::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// config/suppressions.yamlと--allowlistで指定したファイルを読み込む
+fn load_suppressions() -> Suppressions {
+    let mut suppressions = Suppressions::default();
+    let allowlist = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("allowlist")
+        .map(|path| path.to_string());
+    let config_path = configs::config_path(SUPPRESSIONS_CONFIG);
+    let paths = Path::new(&config_path)
+        .exists()
+        .then(|| config_path.clone())
+        .into_iter()
+        .chain(allowlist);
+    for path in paths {
+        match Suppressions::load(&path) {
+            Ok(loaded) => suppressions.extend(loaded),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to load the suppressions. {}", err),
+                )
+                .ok();
+            }
+        }
+    }
+    suppressions
+}
+
+/// 検知を抑制する場合はtrueを返し、ルール毎に抑制した件数を数える
+pub fn is_suppressed(rule: &str, title: &str, record: &Value) -> bool {
+    if SUPPRESSIONS.is_suppressed(rule, record) {
+        *SUPPRESSED
+            .lock()
+            .unwrap()
+            .entry(title.to_string())
+            .or_insert(0) += 1;
+        return true;
+    }
+    false
+}
+
+/// 抑制した検知の件数をルールのタイトル毎に件数の多い順で返す
+pub fn suppressed_counts() -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = SUPPRESSED
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(title, count)| (title.to_string(), *count))
+        .collect();
+    counts.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+    counts
+}
+
+/// 許可リストを学習した検知のルールのIDとフィールドの値
+#[derive(Debug, Clone, PartialEq)]
+pub struct LearnedEntry {
+    pub rule: String,
+    pub title: String,
+    pub fields: Vec<(String, String)>,
+}
+
+/// 検知したレコードからルールのIDとフィールドの値を許可リストの条件として記録する
+pub fn learn(rule: &str, title: &str, record: &Value) {
+    let fields: Vec<(String, String)> = ALLOWLIST_KEY_FIELDS
+        .iter()
+        .filter_map(|field| {
+            utils::get_event_value(field, record)
+                .and_then(utils::value_to_string)
+                .filter(|value| !value.is_empty() && value != "-")
+                .map(|value| (field.to_string(), value))
+        })
+        .collect();
+    // フィールドの値がない場合は全ての検知を抑制してしまうので記録しない
+    if fields.is_empty() {
+        return;
+    }
+    let key = format!("{}\t{:?}", rule, fields).to_lowercase();
+    LEARNED
+        .lock()
+        .unwrap()
+        .entry(key)
+        .or_insert_with(|| LearnedEntry {
+            rule: rule.to_string(),
+            title: title.to_string(),
+            fields,
+        });
+}
+
+/// 学習した許可リストをYAMLファイルに保存する
+pub fn save_learned(path: &str) -> Result<usize, String> {
+    let mut entries: Vec<LearnedEntry> = LEARNED.lock().unwrap().values().cloned().collect();
+    entries.sort_by(|x, y| x.title.cmp(&y.title).then_with(|| x.fields.cmp(&y.fields)));
+    let contents = to_yaml(&entries)?;
+    fs::write(path, contents).map_err(|e| e.to_string())?;
+    Ok(entries.len())
+}
+
+fn to_yaml(entries: &[LearnedEntry]) -> Result<String, String> {
+    let entries: Vec<Yaml> = entries
+        .iter()
+        .map(|entry| {
+            let mut fields = LinkedHashMap::new();
+            for (field, value) in entry.fields.iter() {
+                fields.insert(
+                    Yaml::String(field.to_string()),
+                    Yaml::String(value.to_string()),
+                );
+            }
+            let mut yaml = LinkedHashMap::new();
+            yaml.insert(
+                Yaml::String("rule".to_string()),
+                Yaml::String(entry.rule.to_string()),
+            );
+            yaml.insert(
+                Yaml::String("title".to_string()),
+                Yaml::String(entry.title.to_string()),
+            );
+            yaml.insert(Yaml::String("fields".to_string()), Yaml::Hash(fields));
+            Yaml::Hash(yaml)
+        })
+        .collect();
+    let mut doc = LinkedHashMap::new();
+    doc.insert(
+        Yaml::String("suppressions".to_string()),
+        Yaml::Array(entries),
+    );
+    let mut contents = String::new();
+    YamlEmitter::new(&mut contents)
+        .dump(&Yaml::Hash(doc))
+        .map_err(|e| format!("{:?}", e))?;
+    contents.push('\n');
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::suppression::{to_yaml, LearnedEntry, Suppressions};
+    use serde_json::json;
+
+    #[test]
+    fn test_learned_suppressions() {
+        let entries = vec![LearnedEntry {
+            rule: "a1b2".to_string(),
+            title: "Suspicious Script".to_string(),
+            fields: vec![
+                (
+                    "Image".to_string(),
+                    "C:\\Windows\\System32\\cscript.exe".to_string(),
+                ),
+                (
+                    "CommandLine".to_string(),
+                    "cscript.exe \"C:\\Scripts\\backup: daily.vbs\"".to_string(),
+                ),
+            ],
+        }];
+        // 保存したYAMLを読み込んで同じ条件になる
+        let suppressions = Suppressions::parse(&to_yaml(&entries).unwrap()).unwrap();
+        assert_eq!(suppressions.entries["a1b2"].len(), 1);
+        assert_eq!(suppressions.entries["a1b2"][0].title, "Suspicious Script");
+
+        let record = json!({
+            "Event": {
+                "System": { "EventID": 1 },
+                "EventData": {
+                    "Image": "c:\\windows\\system32\\cscript.exe",
+                    "CommandLine": "cscript.exe \"C:\\Scripts\\backup: daily.vbs\"",
+                },
+            }
+        });
+        assert!(suppressions.is_suppressed("a1b2", &record));
+        assert!(!suppressions.is_suppressed("c3d4", &record));
+        let record = json!({
+            "Event": {
+                "System": { "EventID": 1 },
+                "EventData": {
+                    "Image": "C:\\Windows\\System32\\cscript.exe",
+                    "CommandLine": "cscript.exe evil.vbs",
+                },
+            }
+        });
+        assert!(!suppressions.is_suppressed("a1b2", &record));
+    }
+
+    #[test]
+    fn test_suppressions_with_modifiers() {
+        let suppressions = Suppressions::parse(
+            r#"
+suppressions:
+  - rule: a1b2
+    title: Suspicious Process
+    fields:
+      Image|endswith: '\our_agent.exe'
+      CommandLine|contains:
+        - '--update'
+        - '--scan'
+      User|re: '^CORP\\svc_'
+"#,
+        )
+        .unwrap();
+        let record = |image: &str, command_line: &str| {
+            json!({
+                "Event": {
+                    "System": { "EventID": 1 },
+                    "EventData": {
+                        "Image": image,
+                        "CommandLine": command_line,
+                        "User": "CORP\\svc_agent",
+                    },
+                }
+            })
+        };
+        assert!(suppressions.is_suppressed(
+            "a1b2",
+            &record(
+                "C:\\Program Files\\Agent\\OUR_AGENT.exe",
+                "our_agent.exe --scan"
+            )
+        ));
+        assert!(!suppressions.is_suppressed(
+            "a1b2",
+            &record("C:\\Temp\\our_agent.exe.bak", "our_agent.exe --scan")
+        ));
+        assert!(!suppressions.is_suppressed(
+            "a1b2",
+            &record("C:\\Program Files\\Agent\\our_agent.exe", "our_agent.exe")
+        ));
+        // 不明な修飾子や正しくない正規表現はエラーにする
+        assert!(Suppressions::parse(
+            "suppressions:\n  - rule: a1b2\n    fields:\n      Image|ends: a\n"
+        )
+        .is_err());
+        assert!(Suppressions::parse(
+            "suppressions:\n  - rule: a1b2\n    fields:\n      Image|re: '('\n"
+        )
+        .is_err());
+    }
+}
@@ -3,23 +3,63 @@ extern crate csv;
 extern crate regex;
 
 use crate::detections::configs;
+use crate::detections::print::AlertMessage;
+use crate::options::embedded_rules;
+use crate::options::low_priority::{low_priority_thread_num, LOW_PRIORITY_FLAG};
+use crate::resources;
 
 use tokio::runtime::Builder;
 use tokio::runtime::Runtime;
 
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use regex::Regex;
 use serde_json::Value;
 use std::cmp::Ordering;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::Path;
 use std::str;
 use std::string::String;
+use std::sync::Arc;
 use std::vec;
 
 use super::detection::EvtxRecordInfo;
 
+// レコード情報を作成する時に1スレッドがまとめて処理する最小のレコード数。
+// 1件ずつ分割するとスケジューリングのコストの方が大きくなる
+const MIN_REC_INFO_CHUNK: usize = 64;
+// 処理時間が偏ってもスレッドが空かないように、1スレッドあたりこの数のチャンクに分ける
+const REC_INFO_CHUNKS_PER_THREAD: usize = 4;
+// 1度に解析するレコード数をスレッド数から決める時の1スレッドあたりのレコード数と下限、上限
+const CHUNK_RECORDS_PER_THREAD: usize = 1000;
+const MIN_CHUNK_SIZE: usize = 5000;
+const MAX_CHUNK_SIZE: usize = 64000;
+
+lazy_static! {
+    // レコード情報の作成に使うスレッドプール。スレッド数は--thread-numberに合わせる
+    static ref REC_INFO_POOL: ThreadPool = ThreadPoolBuilder::new()
+        .num_threads(get_thread_num())
+        .thread_name(|idx| format!("rec-info-{}", idx))
+        .build()
+        .unwrap();
+    /// 1度にtimelineやdetectionを実行するレコード数。--chunk-sizeがない場合はスレッド数から決める
+    pub static ref CHUNK_SIZE: usize = get_count_option("chunk-size").unwrap_or_else(|| {
+        (get_thread_num() * CHUNK_RECORDS_PER_THREAD).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+    });
+    /// evtxのパーサーのスレッド数。0の場合はevtxクレートがCPU数から決める。--low-priorityの場合は解析のスレッド数に合わせる
+    pub static ref PARSER_THREAD_NUM: usize = get_count_option("parser-threads").unwrap_or_else(|| {
+        if *LOW_PRIORITY_FLAG {
+            get_thread_num()
+        } else {
+            0
+        }
+    });
+}
+
 pub fn concat_selection_key(key_list: &[String]) -> String {
     return key_list
         .iter()
@@ -65,6 +105,14 @@ pub fn value_to_string(value: &Value) -> Option<String> {
 pub fn read_txt(filename: &str) -> Result<Vec<String>, String> {
     let f = File::open(filename);
     if f.is_err() {
+        // 設定ファイルがない場合はバイナリに埋め込んだデフォルトの設定を使う
+        if let Some(contents) = resources::embedded_config(filename) {
+            return Result::Ok(contents.lines().map(|line| line.to_string()).collect());
+        }
+        // ルールが参照するファイルは埋め込んだルールを展開したフォルダから読み込む
+        if let Some(path) = embedded_rules::embedded_rule_file(filename) {
+            return read_txt(&path.display().to_string());
+        }
         let errmsg = format!("Cannot open file. [file:{}]", filename);
         return Result::Err(errmsg);
     }
@@ -77,16 +125,39 @@ pub fn read_txt(filename: &str) -> Result<Vec<String>, String> {
     )
 }
 
+/// ファイルの先頭がevtxファイルのシグネチャ(ElfFile\0)かを判定する
+pub fn has_evtx_signature(path: &Path) -> bool {
+    let mut signature = [0u8; 8];
+    match File::open(path) {
+        Ok(mut f) => f.read_exact(&mut signature).is_ok() && &signature == b"ElfFile\0",
+        Err(_) => false,
+    }
+}
+
+/// 解析対象のファイルパスを1行に1つ記載したファイルを読み込む。空行と#から始まるコメント行は無視する
+pub fn read_file_list(filename: &str) -> Result<Vec<String>, String> {
+    Ok(read_txt(filename)?
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
 pub fn read_csv(filename: &str) -> Result<Vec<Vec<String>>, String> {
     let f = File::open(filename);
-    if f.is_err() {
-        return Result::Err(format!("Cannot open file. [file:{}]", filename));
-    }
     let mut contents: String = String::new();
     let mut ret = vec![];
-    let read_res = f.unwrap().read_to_string(&mut contents);
-    if let Err(e) = read_res {
-        return Result::Err(e.to_string());
+    match f {
+        Ok(mut f) => {
+            if let Err(e) = f.read_to_string(&mut contents) {
+                return Result::Err(e.to_string());
+            }
+        }
+        // 設定ファイルがない場合はバイナリに埋め込んだデフォルトの設定を使う
+        Err(_) => match resources::embedded_config(filename) {
+            Some(embedded) => contents = embedded.to_string(),
+            None => return Result::Err(format!("Cannot open file. [file:{}]", filename)),
+        },
     }
 
     let mut rdr = csv::ReaderBuilder::new().from_reader(contents.as_bytes());
@@ -129,6 +200,24 @@ pub fn str_time_to_datetime(system_time_str: &str) -> Option<DateTime<Utc>> {
         .single()
 }
 
+/**
+* evtxファイル以外から変換したレコードの日時を、evtxファイルのSystemTimeと同じRFC3339の形式にする。
+* タイムゾーンのない日時はUTCとみなす。変換できない場合はそのまま返す。
+*/
+pub fn to_system_time(time_str: &str) -> String {
+    let time = match DateTime::parse_from_rfc3339(time_str) {
+        Ok(time) => Some(time.with_timezone(&Utc)),
+        Err(_) => ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(time_str, format).ok())
+            .map(|time| Utc.from_utc_datetime(&time)),
+    };
+    time.map_or_else(
+        || time_str.to_string(),
+        |time| time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string(),
+    )
+}
+
 /// serde:Valueの型を確認し、文字列を返します。
 pub fn get_serde_number_to_string(value: &serde_json::Value) -> Option<String> {
     if value.is_string() {
@@ -182,7 +271,12 @@ pub fn get_event_value<'a>(key: &str, event_value: &'a Value) -> Option<&'a Valu
 }
 
 pub fn get_thread_num() -> usize {
-    let def_thread_num_str = num_cpus::get().to_string();
+    // --low-priorityの場合はデフォルトのスレッド数を抑える
+    let def_thread_num_str = if *LOW_PRIORITY_FLAG {
+        low_priority_thread_num().to_string()
+    } else {
+        num_cpus::get().to_string()
+    };
     let conf = configs::CONFIG.read().unwrap();
     conf.args
         .value_of("thread-number")
@@ -191,6 +285,27 @@ pub fn get_thread_num() -> usize {
         .unwrap()
 }
 
+/// 1以上の数を指定するオプションの値を返す。正しくない値の場合はエラーを表示してNoneを返す
+fn get_count_option(name: &str) -> Option<usize> {
+    let value = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of(name)?
+        .to_string();
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Some(count),
+        _ => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Invalid --{}: {}. The default value is used.", name, value),
+            )
+            .ok();
+            None
+        }
+    }
+}
+
 pub fn create_tokio_runtime() -> Runtime {
     Builder::new_multi_thread()
         .worker_threads(get_thread_num())
@@ -199,6 +314,26 @@ pub fn create_tokio_runtime() -> Runtime {
         .unwrap()
 }
 
+/// 複数のレコードのEvtxRecordInfoをチャンク単位で並列に作成する。結果はレコードの順番のまま返す
+pub fn create_rec_infos(
+    records: Vec<(Arc<String>, Value)>,
+    keys: &[String],
+) -> Vec<EvtxRecordInfo> {
+    let chunk_len = rec_info_chunk_len(records.len(), REC_INFO_POOL.current_num_threads());
+    REC_INFO_POOL.install(|| {
+        records
+            .into_par_iter()
+            .with_min_len(chunk_len)
+            .map(|(path, data)| create_rec_info(data, path.to_string(), keys))
+            .collect()
+    })
+}
+
+/// 1スレッドがまとめて処理するレコード数
+fn rec_info_chunk_len(records: usize, threads: usize) -> usize {
+    (records / (threads.max(1) * REC_INFO_CHUNKS_PER_THREAD)).max(MIN_REC_INFO_CHUNK)
+}
+
 // EvtxRecordInfoを作成します。
 pub fn create_rec_info(data: Value, path: String, keys: &[String]) -> EvtxRecordInfo {
     // 高速化のための処理
@@ -264,7 +399,8 @@ fn create_recordinfos(record: &Value) -> String {
         .collect();
 
     // 標準出力する時はセルがハイプ区切りになるので、パイプ区切りにしない
-    if configs::CONFIG.read().unwrap().args.is_present("output") {
+    let args = &configs::CONFIG.read().unwrap().args;
+    if args.is_present("output") || args.is_present("output-auto") {
         summary.join(" | ")
     } else {
         summary.join(" ")
@@ -331,6 +467,78 @@ mod tests {
     use crate::detections::utils;
     use regex::Regex;
     use serde_json::Value;
+    use std::path::Path;
+
+    #[test]
+    fn test_to_system_time() {
+        assert_eq!(
+            utils::to_system_time("2022-05-20T10:00:00.1234567Z"),
+            "2022-05-20T10:00:00.123456Z"
+        );
+        assert_eq!(
+            utils::to_system_time("2022-05-20 10:00:00.5"),
+            "2022-05-20T10:00:00.500000Z"
+        );
+        assert_eq!(
+            utils::to_system_time("2022-05-20T19:00:00+09:00"),
+            "2022-05-20T10:00:00.000000Z"
+        );
+        assert_eq!(utils::to_system_time("-"), "-");
+    }
+
+    #[test]
+    fn test_create_rec_infos() {
+        let path = std::sync::Arc::new("test.evtx".to_string());
+        let records = (0..200)
+            .map(|id| {
+                let record: Value = serde_json::from_str(&format!(
+                    r#"{{"Event": {{"System": {{"EventRecordID": {}}}}}}}"#,
+                    id
+                ))
+                .unwrap();
+                (std::sync::Arc::clone(&path), record)
+            })
+            .collect();
+        let keys = vec!["Event.System.EventRecordID".to_string()];
+        let rec_infos = utils::create_rec_infos(records, &keys);
+        // 並列に作成してもレコードの順番は変わらない
+        let ids: Vec<String> = rec_infos
+            .iter()
+            .map(|rec_info| rec_info.key_2_value["Event.System.EventRecordID"].to_string())
+            .collect();
+        let expected: Vec<String> = (0..200).map(|id| id.to_string()).collect();
+        assert_eq!(ids, expected);
+        assert_eq!(utils::rec_info_chunk_len(100, 8), 64);
+        assert_eq!(utils::rec_info_chunk_len(5000 * 8, 8), 1250);
+    }
+
+    #[test]
+    fn test_has_evtx_signature() {
+        assert!(utils::has_evtx_signature(Path::new(
+            "test_files/signature/Security.evtx.bak"
+        )));
+        // 拡張子が.evtxでも中身が空の場合はevtxファイルとみなさない
+        assert!(!utils::has_evtx_signature(Path::new(
+            "test_files/evtx/test1.evtx"
+        )));
+        assert!(!utils::has_evtx_signature(Path::new(
+            "test_files/signature/not_exist.evtx"
+        )));
+    }
+
+    #[test]
+    fn test_read_file_list() {
+        let file_list = utils::read_file_list("test_files/config/file_list.txt").unwrap();
+        assert_eq!(
+            file_list,
+            vec![
+                "test_files/evtx/test1.evtx".to_string(),
+                "/mnt/image1/Windows/System32/winevt/Logs/Security.evtx".to_string(),
+                "/mnt/image2/Windows/System32/winevt/Logs/*.evtx".to_string(),
+            ]
+        );
+        assert!(utils::read_file_list("test_files/config/not_exist.txt").is_err());
+    }
 
     #[test]
     fn test_create_recordinfos() {
@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Hayabusaの処理で発生するエラー。パニックせずに、対処方法が分かるメッセージを表示するために使う
+#[derive(Debug)]
+pub enum HayabusaError {
+    /// gitの操作のエラー
+    Git(git2::Error),
+    /// ファイルやフォルダの操作のエラー
+    Io { path: String, source: io::Error },
+    /// ルールの更新のエラー。対処方法を含むメッセージを持つ
+    RulesUpdate(String),
+}
+
+impl HayabusaError {
+    pub fn io(path: &str, source: io::Error) -> HayabusaError {
+        HayabusaError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for HayabusaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HayabusaError::Git(err) => write!(f, "{}", err.message()),
+            HayabusaError::Io { path, source } => match source.kind() {
+                io::ErrorKind::NotFound => write!(f, "{} does not exist.", path),
+                io::ErrorKind::PermissionDenied => write!(
+                    f,
+                    "Permission denied: {}. Please check the permissions or run as an administrator.",
+                    path
+                ),
+                _ => write!(f, "{} [path:{}]", source, path),
+            },
+            HayabusaError::RulesUpdate(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for HayabusaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HayabusaError::Git(err) => Some(err),
+            HayabusaError::Io { source, .. } => Some(source),
+            HayabusaError::RulesUpdate(_) => None,
+        }
+    }
+}
+
+impl From<git2::Error> for HayabusaError {
+    fn from(err: git2::Error) -> HayabusaError {
+        HayabusaError::Git(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::HayabusaError;
+    use std::io;
+
+    #[test]
+    fn test_error_message() {
+        let err = HayabusaError::io("rules", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(err.to_string(), "rules does not exist.");
+        let err = HayabusaError::io("rules", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(
+            err.to_string(),
+            "Permission denied: rules. Please check the permissions or run as an administrator."
+        );
+        let err: HayabusaError = git2::Error::from_str("reference not found").into();
+        assert_eq!(err.to_string(), "reference not found");
+    }
+}
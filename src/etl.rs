@@ -0,0 +1,160 @@
+use crate::event_xml;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Channelが記録されていないETWのイベントに補うチャンネル。ルールはevtxファイルのチャンネル名を条件にしているため
+const PROVIDER_CHANNELS: [(&str, &str); 2] = [
+    (
+        "Microsoft-Windows-Sysmon",
+        "Microsoft-Windows-Sysmon/Operational",
+    ),
+    ("Microsoft-Windows-Security-Auditing", "Security"),
+];
+
+// tracerptで変換したXMLファイルの連番
+static CONVERTED_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// 拡張子が.etlのETW(Event Tracing for Windows)のトレースファイルかを判定する
+pub fn is_etl_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("etl"))
+}
+
+/**
+* ETLファイルをWindows標準のtracerptでイベントのXMLに変換して、evtxファイルのレコードと同じ形式のJSONで返す。
+* EventDataを展開するには、解析するPCにプロバイダのマニフェストが登録されている(Sysmonがインストールされているなど)必要がある。
+*/
+pub fn read_etl(path: &Path) -> Result<Vec<Value>, String> {
+    if !cfg!(target_os = "windows") {
+        return Err("ETL files can only be converted on Windows with tracerpt.".to_string());
+    }
+    let xml_path = std::env::temp_dir().join(format!(
+        "hayabusa-etl-{}-{}.xml",
+        process::id(),
+        CONVERTED_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = convert_with_tracerpt(path, &xml_path).and_then(|_| {
+        let bytes = fs::read(&xml_path).map_err(|e| e.to_string())?;
+        // tracerptの出力はBOMでエンコーディングを判定する
+        let (xml, _, _) = encoding_rs::UTF_8.decode(&bytes);
+        parse_tracerpt_xml(&xml)
+    });
+    fs::remove_file(&xml_path).ok();
+    result
+}
+
+fn convert_with_tracerpt(etl_path: &Path, xml_path: &Path) -> Result<(), String> {
+    let output = Command::new("tracerpt")
+        .arg(etl_path)
+        .arg("-o")
+        .arg(xml_path)
+        .args(["-of", "XML", "-y"])
+        .output()
+        .map_err(|e| format!("Failed to run tracerpt. {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to convert the ETL file with tracerpt. {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        ));
+    }
+    Ok(())
+}
+
+// tracerptが出力したXMLをJSONに変換して、Channelがないイベントにチャンネルを補う
+fn parse_tracerpt_xml(xml: &str) -> Result<Vec<Value>, String> {
+    let mut events = event_xml::parse_events_xml(xml)?;
+    events.iter_mut().for_each(fill_channel);
+    Ok(events)
+}
+
+// ETWのイベントにはChannelがない場合があるので、プロバイダ名から補う
+fn fill_channel(event: &mut Value) {
+    let system = match event["Event"]["System"].as_object_mut() {
+        Some(system) => system,
+        None => return,
+    };
+    if system
+        .get("Channel")
+        .and_then(|channel| channel.as_str())
+        .map_or(false, |channel| !channel.is_empty())
+    {
+        return;
+    }
+    let provider = system
+        .get("Provider_attributes")
+        .and_then(|provider| provider["Name"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    if let Some((_, channel)) = PROVIDER_CHANNELS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&provider))
+    {
+        system.insert("Channel".to_string(), Value::from(*channel));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::etl::{is_etl_file, parse_tracerpt_xml};
+    use std::path::Path;
+
+    #[test]
+    fn test_is_etl_file() {
+        assert!(is_etl_file(Path::new("C:\\traces\\sysmon.ETL")));
+        assert!(!is_etl_file(Path::new("Security.evtx")));
+    }
+
+    #[test]
+    fn test_parse_tracerpt_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Events>
+<Event xmlns="http://schemas.microsoft.com/win/2004/08/events/event">
+  <System>
+    <Provider Name="Microsoft-Windows-Sysmon" Guid="{5770385f-c22a-43e0-bf4c-06f5698ffbd9}" />
+    <EventID>1</EventID>
+    <TimeCreated SystemTime="2022-05-20T10:00:00.000000000Z" />
+    <Computer>PC01</Computer>
+  </System>
+  <EventData>
+    <Data Name="Image">C:\Windows\System32\cmd.exe</Data>
+    <Data Name="CommandLine">cmd.exe /c &quot;whoami&quot;</Data>
+  </EventData>
+</Event>
+<Event xmlns="http://schemas.microsoft.com/win/2004/08/events/event">
+  <System>
+    <Provider Name="Microsoft-Windows-Security-Auditing" />
+    <EventID>4624</EventID>
+    <Channel>Security</Channel>
+  </System>
+  <EventData>
+    <Data>first</Data>
+    <Data>second</Data>
+  </EventData>
+</Event>
+</Events>"#;
+        let events = parse_tracerpt_xml(xml).unwrap();
+        assert_eq!(events.len(), 2);
+        let system = &events[0]["Event"]["System"];
+        assert_eq!(system["EventID"], 1);
+        assert_eq!(system["Channel"], "Microsoft-Windows-Sysmon/Operational");
+        assert_eq!(system["Computer"], "PC01");
+        assert_eq!(
+            system["TimeCreated_attributes"]["SystemTime"],
+            "2022-05-20T10:00:00.000000000Z"
+        );
+        assert_eq!(
+            events[0]["Event"]["EventData"]["CommandLine"],
+            "cmd.exe /c \"whoami\""
+        );
+        assert_eq!(events[1]["Event"]["System"]["Channel"], "Security");
+        assert_eq!(
+            events[1]["Event"]["EventData"]["Data"],
+            serde_json::json!(["first", "second"])
+        );
+        assert!(parse_tracerpt_xml("<Events><Event></System></Events>").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::{Map, Value};
+
+// 数値に変換するSystemの要素。evtxファイルのレコードと同じ型にする
+const NUMBER_ELEMENTS: [&str; 6] = [
+    "EventID",
+    "Version",
+    "Level",
+    "Task",
+    "Opcode",
+    "EventRecordID",
+];
+
+// 変換中の要素
+struct Element {
+    key: String,
+    attributes: Map<String, Value>,
+    children: Map<String, Value>,
+    text: String,
+}
+
+impl Element {
+    fn new<B: std::io::BufRead>(e: &BytesStart, reader: &Reader<B>) -> Element {
+        let name = String::from_utf8_lossy(e.local_name()).to_string();
+        let mut attributes = Map::new();
+        for attr in e.attributes().flatten() {
+            let key = String::from_utf8_lossy(attr.key).to_string();
+            let value = attr.unescape_and_decode_value(reader).unwrap_or_default();
+            attributes.insert(key, Value::String(value));
+        }
+        // <Data Name="Image">...</Data>はevtxファイルのレコードと同じくNameの値をキーにする
+        let key = match attributes.get("Name").and_then(|name| name.as_str()) {
+            Some(data_name) if name == "Data" => {
+                let key = data_name.to_string();
+                attributes.clear();
+                key
+            }
+            _ => name,
+        };
+        Element {
+            key,
+            attributes,
+            children: Map::new(),
+            text: String::new(),
+        }
+    }
+
+    // 要素名(Dataの場合はNameの値)、属性、値に分ける
+    fn into_parts(self) -> (String, Map<String, Value>, Value) {
+        let value = if !self.children.is_empty() {
+            Value::Object(self.children)
+        } else {
+            match self.text.parse::<u64>() {
+                Ok(num) if NUMBER_ELEMENTS.contains(&self.key.as_str()) => Value::from(num),
+                _ => Value::String(self.text),
+            }
+        };
+        (self.key, self.attributes, value)
+    }
+}
+
+// 同じ名前の要素が複数ある場合は配列にする
+fn insert_child(children: &mut Map<String, Value>, key: String, value: Value) {
+    match children.get_mut(&key) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None => {
+            children.insert(key, value);
+        }
+    }
+}
+
+/// Windowsのイベントと同じスキーマのXMLの<Event>要素を、evtxファイルのレコードと同じ形式のJSONに変換する
+pub fn parse_events_xml(xml: &str) -> Result<Vec<Value>, String> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut stack: Vec<Element> = vec![];
+    let mut events = vec![];
+    loop {
+        let (element, is_end) = match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) => (Some(Element::new(&e, &reader)), false),
+            Ok(Event::Empty(e)) => (Some(Element::new(&e, &reader)), true),
+            Ok(Event::Text(e)) => {
+                if let Some(element) = stack.last_mut() {
+                    element
+                        .text
+                        .push_str(&e.unescape_and_decode(&reader).unwrap_or_default());
+                }
+                (None, false)
+            }
+            Ok(Event::End(_)) => (None, true),
+            Ok(Event::Eof) => break,
+            Err(err) => {
+                return Err(format!(
+                    "Failed to parse the event XML at position {}. {}",
+                    reader.buffer_position(),
+                    err
+                ))
+            }
+            _ => (None, false),
+        };
+        if let Some(element) = element {
+            stack.push(element);
+        }
+        if !is_end {
+            buf.clear();
+            continue;
+        }
+        // 要素の終わり
+        let element = match stack.pop() {
+            Some(element) => element,
+            None => continue,
+        };
+        let (key, attributes, value) = element.into_parts();
+        if key == "Event" {
+            let mut event = Map::new();
+            event.insert(key, value);
+            events.push(Value::Object(event));
+        } else if let Some(parent) = stack.last_mut() {
+            if !attributes.is_empty() {
+                parent
+                    .children
+                    .insert(format!("{}_attributes", key), Value::Object(attributes));
+            }
+            insert_child(&mut parent.children, key, value);
+        }
+        buf.clear();
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event_xml::parse_events_xml;
+
+    #[test]
+    fn test_parse_events_xml() {
+        let xml = r#"<Event><System><Provider Name="Linux-Sysmon" /><EventID>1</EventID><Channel>Linux-Sysmon/Operational</Channel></System><EventData><Data Name="Image">/usr/bin/curl</Data><Data Name="CommandLine">curl -o &quot;a b&quot;</Data></EventData></Event>"#;
+        let events = parse_events_xml(xml).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["Event"]["System"]["EventID"], 1);
+        assert_eq!(
+            events[0]["Event"]["System"]["Provider_attributes"]["Name"],
+            "Linux-Sysmon"
+        );
+        assert_eq!(
+            events[0]["Event"]["EventData"]["CommandLine"],
+            "curl -o \"a b\""
+        );
+        assert!(parse_events_xml("<Event></System>").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+// レコードの先頭4バイト(Length)の後にあるシグネチャ
+const EVT_SIGNATURE: &[u8; 4] = b"LfLe";
+// EVENTLOGRECORDの固定長部分のサイズ
+const RECORD_HEADER_LEN: usize = 56;
+// ファイルヘッダーのサイズ
+const FILE_HEADER_LEN: usize = 48;
+
+/// 拡張子が.evtのWindows XP/2003のイベントログファイルかを判定する
+pub fn is_evt_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("evt"))
+}
+
+/**
+* Windows XP/2003のEVT形式のイベントログファイルを読み込んで、evtxファイルのレコードと同じ形式のJSONで返す。
+* EVTファイルは循環バッファなので、シグネチャからレコードを探してレコード番号の順に並べる。
+*/
+pub fn read_evt(path: &Path) -> Result<Vec<Value>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    if data.len() < FILE_HEADER_LEN || &data[4..8] != EVT_SIGNATURE {
+        return Err(format!("{} is not an evt file.", path.display()));
+    }
+    let channel = channel_name(path);
+    let mut records: Vec<(u32, Value)> = vec![];
+    let mut offset = FILE_HEADER_LEN;
+    while offset + RECORD_HEADER_LEN <= data.len() {
+        match parse_record(&data[offset..], &channel) {
+            Some((len, record_number, record)) => {
+                records.push((record_number, record));
+                offset += len;
+            }
+            None => offset += 4,
+        }
+    }
+    records.sort_by_key(|(record_number, _)| *record_number);
+    Ok(records.into_iter().map(|(_, record)| record).collect())
+}
+
+// SecEvent.Evtのような標準のファイル名から、evtxファイルと同じチャンネル名にする
+fn channel_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    match stem.to_lowercase().as_str() {
+        "secevent" => "Security".to_string(),
+        "sysevent" => "System".to_string(),
+        "appevent" => "Application".to_string(),
+        _ => stem.to_string(),
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+// NULL終端のUTF-16LEの文字列を読み込み、文字列と読み込んだバイト数を返す
+fn read_utf16(data: &[u8], offset: usize) -> (String, usize) {
+    let units: Vec<u16> = data
+        .get(offset..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    (String::from_utf16_lossy(&units), (units.len() + 1) * 2)
+}
+
+// バイナリのSIDをS-1-5-21-...の形式にする
+fn sid_to_string(sid: &[u8]) -> Option<String> {
+    if sid.len() < 8 {
+        return None;
+    }
+    let count = sid[1] as usize;
+    if sid.len() < 8 + count * 4 {
+        return None;
+    }
+    let authority = sid[2..8]
+        .iter()
+        .fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+    let mut ret = format!("S-{}-{}", sid[0], authority);
+    for idx in 0..count {
+        ret.push_str(&format!("-{}", read_u32(sid, 8 + idx * 4)));
+    }
+    Some(ret)
+}
+
+// EventTypeをevtxファイルのLevelとKeywordsにする
+fn level_and_keywords(event_type: u16) -> (u8, &'static str) {
+    match event_type {
+        0x01 => (2, "0x80000000000000"),
+        0x02 => (3, "0x80000000000000"),
+        0x08 => (0, "0x8020000000000000"),
+        0x10 => (0, "0x8010000000000000"),
+        _ => (4, "0x80000000000000"),
+    }
+}
+
+/**
+* EVENTLOGRECORDを1件読み込み、レコード長、レコード番号、JSONに変換したレコードを返す。
+* 先頭と末尾のレコード長が一致しない場合はレコードではないとみなす。
+*/
+fn parse_record(data: &[u8], channel: &str) -> Option<(usize, u32, Value)> {
+    if data.len() < RECORD_HEADER_LEN || &data[4..8] != EVT_SIGNATURE {
+        return None;
+    }
+    let len = read_u32(data, 0) as usize;
+    if len < RECORD_HEADER_LEN || len > data.len() || read_u32(data, len - 4) as usize != len {
+        return None;
+    }
+    let record = &data[..len];
+    let record_number = read_u32(record, 8);
+    let time_generated = read_u32(record, 12);
+    let event_id = read_u32(record, 20) & 0xFFFF;
+    let event_type = read_u16(record, 24);
+    let num_strings = read_u16(record, 26) as usize;
+    let category = read_u16(record, 28);
+    let string_offset = read_u32(record, 36) as usize;
+    let sid_len = read_u32(record, 40) as usize;
+    let sid_offset = read_u32(record, 44) as usize;
+    let data_len = read_u32(record, 48) as usize;
+    let data_offset = read_u32(record, 52) as usize;
+
+    let (source, source_len) = read_utf16(record, RECORD_HEADER_LEN);
+    let (computer, _) = read_utf16(record, RECORD_HEADER_LEN + source_len);
+    let sid = record
+        .get(sid_offset..sid_offset + sid_len)
+        .filter(|_| sid_len > 0)
+        .and_then(sid_to_string);
+    let mut strings = vec![];
+    let mut offset = string_offset;
+    for _ in 0..num_strings {
+        if offset >= len {
+            break;
+        }
+        let (string, string_len) = read_utf16(record, offset);
+        strings.push(string);
+        offset += string_len;
+    }
+    let binary = record
+        .get(data_offset..data_offset + data_len)
+        .filter(|_| data_len > 0)
+        .map(hex::encode_upper);
+
+    let time = Utc
+        .timestamp_opt(time_generated as i64, 0)
+        .single()?
+        .format("%Y-%m-%dT%H:%M:%S%.6fZ")
+        .to_string();
+    let (level, keywords) = level_and_keywords(event_type);
+    let mut event_data = json!({ "Data": strings });
+    if let Some(binary) = binary {
+        event_data["Binary"] = Value::from(binary);
+    }
+    let mut system = json!({
+        "Provider_attributes": { "Name": source },
+        "EventID": event_id,
+        "Level": level,
+        "Task": category,
+        "Keywords": keywords,
+        "TimeCreated_attributes": { "SystemTime": time },
+        "EventRecordID": record_number,
+        "Channel": channel,
+        "Computer": computer,
+    });
+    if let Some(sid) = sid {
+        system["Security_attributes"] = json!({ "UserID": sid });
+    }
+    Some((
+        len,
+        record_number,
+        json!({ "Event": { "System": system, "EventData": event_data } }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evt::{channel_name, is_evt_file, parse_record, sid_to_string};
+    use std::path::Path;
+
+    fn utf16(value: &str) -> Vec<u8> {
+        value
+            .encode_utf16()
+            .chain([0])
+            .flat_map(|c| c.to_le_bytes())
+            .collect()
+    }
+
+    // EVENTLOGRECORDを1件作る
+    fn create_record(record_number: u32, strings: &[&str]) -> Vec<u8> {
+        let mut body = utf16("Security");
+        body.extend(utf16("SERVER2003"));
+        let sid_offset = 56 + body.len();
+        let sid = [1u8, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+        body.extend(sid);
+        let string_offset = 56 + body.len();
+        for string in strings {
+            body.extend(utf16(string));
+        }
+        let len = 56 + body.len() + 4;
+        let mut record = vec![];
+        for value in [
+            len as u32,
+            0x654c_664c,
+            record_number,
+            1_116_000_000,
+            1_116_000_000,
+            528,
+        ] {
+            record.extend(value.to_le_bytes());
+        }
+        for value in [8u16, strings.len() as u16, 2, 0] {
+            record.extend(value.to_le_bytes());
+        }
+        for value in [
+            0u32,
+            string_offset as u32,
+            sid.len() as u32,
+            sid_offset as u32,
+            0,
+            0,
+        ] {
+            record.extend(value.to_le_bytes());
+        }
+        record.extend(body);
+        record.extend((len as u32).to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let data = create_record(7, &["Administrator", "SERVER2003"]);
+        let (len, record_number, record) = parse_record(&data, "Security").unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(record_number, 7);
+        let system = &record["Event"]["System"];
+        assert_eq!(system["EventID"], 528);
+        assert_eq!(system["Provider_attributes"]["Name"], "Security");
+        assert_eq!(system["Computer"], "SERVER2003");
+        assert_eq!(system["Channel"], "Security");
+        assert_eq!(system["Keywords"], "0x8020000000000000");
+        assert_eq!(system["Security_attributes"]["UserID"], "S-1-5-18");
+        assert_eq!(
+            system["TimeCreated_attributes"]["SystemTime"],
+            "2005-05-13T16:00:00.000000Z"
+        );
+        assert_eq!(
+            record["Event"]["EventData"]["Data"],
+            serde_json::json!(["Administrator", "SERVER2003"])
+        );
+
+        // 末尾のレコード長が一致しない場合はレコードとして扱わない
+        let mut broken = data.clone();
+        broken.pop();
+        assert!(parse_record(&broken, "Security").is_none());
+    }
+
+    #[test]
+    fn test_evt_file_names() {
+        assert!(is_evt_file(Path::new("C:\\archive\\SecEvent.Evt")));
+        assert!(!is_evt_file(Path::new("Security.evtx")));
+        assert_eq!(channel_name(Path::new("SysEvent.Evt")), "System");
+        assert_eq!(channel_name(Path::new("DNS Server.evt")), "DNS Server");
+        assert_eq!(sid_to_string(&[1, 0]), None);
+    }
+}
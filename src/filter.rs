@@ -1,12 +1,32 @@
 use crate::detections::configs;
-use crate::detections::print::AlertMessage;
 use crate::detections::print::ERROR_LOG_STACK;
 use crate::detections::print::QUIET_ERRORS_FLAG;
+use crate::detections::print::{ErrorClass, ErrorLog};
+use crate::detections::rule::RuleNode;
+use crate::logging;
+use crate::timeline::timelines;
 use hashbrown::HashSet;
+use lazy_static::lazy_static;
 use regex::Regex;
 use std::fs::File;
-use std::io::BufWriter;
 use std::io::{BufRead, BufReader};
+use std::sync::atomic::AtomicUsize;
+use yaml_rust::Yaml;
+
+// サマリー以外で、全てのレコードを解析する必要があるオプション。サマリーのオプションはtimelines::SUMMARIESなどに登録する
+const FULL_SCAN_OPTIONS: [&str; 4] = ["run-metadata", "output-sqlite", "save-store", "search"];
+
+lazy_static! {
+    /// --deep-scanの場合はイベントIDのフィルタを無効にして全てのレコードを解析する
+    pub static ref DEEP_SCAN_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("deep-scan");
+}
+
+/// --deep-scanで解析した、イベントIDのフィルタでは読み飛ばしていたレコードの件数
+pub static DEEP_SCAN_EXTRA_RECORDS: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug)]
 pub struct DataFilterRule {
@@ -54,18 +74,15 @@ impl RuleExclude {
     fn insert_ids(&mut self, filename: &str) {
         let f = File::open(filename);
         if f.is_err() {
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::warn(
-                    &mut BufWriter::new(std::io::stderr().lock()),
-                    &format!("{} does not exist", filename),
-                )
-                .ok();
-            }
+            logging::verbose_warn(&format!("{} does not exist", filename));
             if !*QUIET_ERRORS_FLAG {
-                ERROR_LOG_STACK
-                    .lock()
-                    .unwrap()
-                    .push(format!("{} does not exist", filename));
+                ERROR_LOG_STACK.lock().unwrap().push(
+                    ErrorLog::error(
+                        ErrorClass::FileNotFound,
+                        &format!("{} does not exist", filename),
+                    )
+                    .with_file_path(filename),
+                );
             }
             return;
         }
@@ -82,3 +99,271 @@ impl RuleExclude {
         }
     }
 }
+
+/// ルールで使わないチャンネルのevtxファイルの解析を省略するかを判定する。統計やサマリのオプションが指定されている場合は全てのファイルを解析する
+pub fn is_channel_prescan_enabled() -> bool {
+    !configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("scan-all-files")
+        && !is_full_scan_required()
+}
+
+/// 統計やサマリーなど、検知結果以外の出力のために全てのレコードを解析する必要があるオプションが指定されているかを判定する
+pub fn is_full_scan_required() -> bool {
+    let config = configs::CONFIG.read().unwrap();
+    FULL_SCAN_OPTIONS
+        .iter()
+        .copied()
+        .chain(timelines::summary_options())
+        .any(|option| config.args.is_present(option))
+}
+
+/**
+* 読み込んだルールが条件に使っているチャンネルを小文字で返す。
+* Channelの条件がない、ワイルドカードや修飾子を使っているなど、どのチャンネルでも検知しうるルールが1つでもある場合はNoneを返す。
+*/
+pub fn rule_channels(rules: &[RuleNode]) -> Option<HashSet<String>> {
+    rule_field_values(rules, "Channel")
+}
+
+/**
+* 読み込んだルールが条件に使っているイベントIDを返す。--add-rule-eidsでconfig/target_eventids.txtに加える。
+* 静的なリストにないイベントIDを使うルールがあっても、そのイベントを読み飛ばさないようにするため。
+* EventIDの条件がないなど、どのイベントIDでも検知しうるルールが1つでもある場合はNoneを返す。
+*/
+pub fn rule_event_ids(rules: &[RuleNode]) -> Option<HashSet<String>> {
+    rule_field_values(rules, "EventID")
+}
+
+fn rule_field_values(rules: &[RuleNode], field: &str) -> Option<HashSet<String>> {
+    let mut values = HashSet::new();
+    for rule in rules {
+        values.extend(detection_values(&rule.yaml["detection"], field)?);
+    }
+    Some(values)
+}
+
+// 否定されていない全てのselectionにフィールドの条件がある場合だけ、ルールのフィールドの値を限定できる
+fn detection_values(detection: &Yaml, field: &str) -> Option<HashSet<String>> {
+    let mut values = HashSet::new();
+    let mut has_selection = false;
+    for (_, selection) in positive_selections(detection) {
+        values.extend(selection_values(selection, field)?);
+        has_selection = true;
+    }
+    if has_selection {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+fn selection_values(selection: &Yaml, field: &str) -> Option<Vec<String>> {
+    match selection {
+        Yaml::Hash(hash) => field_values(hash.get(&Yaml::String(field.to_string()))?),
+        // mapのリストはいずれかのmapに一致すればよいので、全てのmapにフィールドの条件が必要
+        Yaml::Array(selections) => {
+            let mut values = vec![];
+            for selection in selections {
+                match selection {
+                    Yaml::Hash(_) => values.extend(selection_values(selection, field)?),
+                    _ => return None,
+                }
+            }
+            Some(values)
+        }
+        _ => None,
+    }
+}
+
+fn field_values(value: &Yaml) -> Option<Vec<String>> {
+    match value {
+        Yaml::String(value) if !value.contains('*') && !value.contains('?') => {
+            Some(vec![value.to_lowercase()])
+        }
+        Yaml::Integer(value) => Some(vec![value.to_string()]),
+        Yaml::Array(values) => {
+            let mut ret = vec![];
+            for value in values {
+                ret.extend(field_values(value)?);
+            }
+            Some(ret)
+        }
+        _ => None,
+    }
+}
+
+/**
+* conditionで否定されずに参照されているselectionを返す。
+* "not (filter1 or filter2)"のように括弧ごと否定されている場合や"not 1 of filter*"のような指定も否定として扱う。
+* conditionがない場合は全てのselectionを返す。
+*/
+pub fn positive_selections(detection: &Yaml) -> Vec<(&str, &Yaml)> {
+    let references = detection["condition"].as_str().map(condition_references);
+    let mut selections = vec![];
+    for (name, selection) in detection.as_hash().into_iter().flatten() {
+        let name = match name.as_str() {
+            Some(name) if name != "condition" && name != "timeframe" => name,
+            _ => continue,
+        };
+        let is_positive = references.as_ref().map_or(true, |references| {
+            references
+                .iter()
+                .any(|(pattern, negated)| !negated && is_selection_match(pattern, name))
+        });
+        if is_positive {
+            selections.push((name, selection));
+        }
+    }
+    selections
+}
+
+// conditionが参照しているselectionの名前と、それが否定されているかどうか。集計条件("|"以降)は対象外
+fn condition_references(condition: &str) -> Vec<(String, bool)> {
+    let condition = condition.split('|').next().unwrap_or_default();
+    let tokens = condition.replace('(', " ( ").replace(')', " ) ");
+    let mut references = vec![];
+    // 括弧の深さごとの否定状態
+    let mut negations = vec![false];
+    let mut pending_not = false;
+    for token in tokens.split_whitespace() {
+        let negated = *negations.last().unwrap_or(&false);
+        match token.to_lowercase().as_str() {
+            "(" => {
+                negations.push(negated ^ pending_not);
+                pending_not = false;
+            }
+            ")" => {
+                if negations.len() > 1 {
+                    negations.pop();
+                }
+            }
+            "not" => pending_not = !pending_not,
+            "and" | "or" | "1" | "all" | "of" => {}
+            _ => {
+                references.push((token.to_string(), negated ^ pending_not));
+                pending_not = false;
+            }
+        }
+    }
+    references
+}
+
+fn is_selection_match(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == "them" || pattern == name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::rule::RuleNode;
+    use crate::filter::{rule_channels, rule_event_ids};
+    use hashbrown::HashSet;
+    use yaml_rust::YamlLoader;
+
+    fn parse_rule_from_str(rule_str: &str) -> RuleNode {
+        let rule_yaml = YamlLoader::load_from_str(rule_str).unwrap();
+        RuleNode::new("testpath".to_string(), rule_yaml[0].clone())
+    }
+
+    #[test]
+    fn test_rule_channels() {
+        let security = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+                EventID: 4624
+            filter:
+                LogonType: 3
+            condition: selection and not filter
+        "#,
+        );
+        let sysmon = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                - Channel: Microsoft-Windows-Sysmon/Operational
+                  EventID: 1
+                - Channel:
+                    - Microsoft-Windows-Sysmon/Operational
+                    - Security
+                  EventID: 4688
+            condition: selection
+        "#,
+        );
+        let channels = rule_channels(&[security, sysmon]).unwrap();
+        let expected: HashSet<String> = ["security", "microsoft-windows-sysmon/operational"]
+            .iter()
+            .map(|channel| channel.to_string())
+            .collect();
+        assert_eq!(channels, expected);
+
+        // Channelの条件がないselectionをorでつなぐルールはどのチャンネルでも検知しうる
+        let any_channel = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+                EventID: 4624
+            keywords:
+                CommandLine|contains: mimikatz
+            condition: selection or keywords
+        "#,
+        );
+        assert!(rule_channels(&[any_channel]).is_none());
+        let wildcard = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Microsoft-Windows-*
+            condition: selection
+        "#,
+        );
+        assert!(rule_channels(&[wildcard]).is_none());
+    }
+
+    #[test]
+    fn test_rule_event_ids() {
+        let logon = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+                EventID:
+                    - 4624
+                    - 4625
+            condition: selection
+        "#,
+        );
+        let sysmon = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Microsoft-Windows-Sysmon/Operational
+                EventID: 1
+            condition: selection
+        "#,
+        );
+        let event_ids = rule_event_ids(&[logon, sysmon]).unwrap();
+        let expected: HashSet<String> = ["4624", "4625", "1"]
+            .iter()
+            .map(|event_id| event_id.to_string())
+            .collect();
+        assert_eq!(event_ids, expected);
+
+        let any_event_id = parse_rule_from_str(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+            condition: selection
+        "#,
+        );
+        assert!(rule_event_ids(&[any_event_id]).is_none());
+    }
+}
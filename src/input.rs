@@ -0,0 +1,543 @@
+use crate::detections::utils;
+use crate::wef::ForwardedDetector;
+use evtx::{EvtxChunkData, EvtxParser, ParserSettings};
+use flate2::read::GzDecoder;
+use hashbrown::HashMap;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 標準入力から読み込む場合に指定するパス
+pub const STDIN_PATH: &str = "-";
+
+/// evtxファイルのシグネチャ
+pub const EVTX_SIGNATURE: &[u8; 8] = b"ElfFile\0";
+
+// ファイルヘッダーのうち、次に書き込まれるレコードのIDまでの長さ
+const EVTX_HEADER_LEN: usize = 32;
+// ファイルヘッダー内の次に書き込まれるレコードのIDの位置
+const NEXT_RECORD_ID_OFFSET: usize = 24;
+/// evtxファイルのファイルヘッダーの大きさ。最初のチャンクはこの位置から始まる
+pub const EVTX_FILE_HEADER_SIZE: u64 = 4096;
+/// evtxファイルのチャンクの大きさ
+pub const EVTX_CHUNK_SIZE: usize = 65536;
+/// チャンクのシグネチャ
+pub const EVTX_CHUNK_SIGNATURE: &[u8; 8] = b"ElfChnk\0";
+/// レコードのシグネチャ
+pub const EVTX_RECORD_SIGNATURE: &[u8; 4] = b"**\0\0";
+/// チャンク内で最初のレコードが書き込まれる位置
+pub const CHUNK_RECORDS_OFFSET: usize = 0x200;
+/// レコードヘッダー(シグネチャ、サイズ、レコードID、タイムスタンプ)の大きさ
+pub const RECORD_HEADER_SIZE: usize = 24;
+// チャンクヘッダー内の最初と最後のレコードIDの位置
+const CHUNK_FIRST_RECORD_ID_OFFSET: usize = 24;
+const CHUNK_LAST_RECORD_ID_OFFSET: usize = 32;
+/// チャンクヘッダー内の空き領域の開始位置(最後のレコードの終わり)の位置
+pub const CHUNK_FREE_SPACE_OFFSET: usize = 48;
+// 事前の確認でチャンネルを読み取るチャンクの数と、1チャンクあたりに読み込むレコード数の上限
+const PRESCAN_MAX_CHUNKS: u64 = 8;
+const PRESCAN_MAX_RECORDS: usize = 10;
+
+/// 解析の前にevtxファイルのヘッダーとファイル全体から間引いたチャンクを読み取った情報
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EvtxPrescan {
+    // 読み込めたレコードのチャンネル
+    pub channels: BTreeSet<String>,
+    // ヘッダーから求めたおおよそのレコード数
+    pub records: u64,
+    // 読み込めたレコードからWEFで転送されたイベントのファイルだと分かったか
+    pub forwarded: bool,
+}
+
+/**
+* evtxのパーサーに渡す入力。通常のファイルはそのまま読み込み、
+* zipやgzで圧縮されたファイルは展開したデータをメモリ上で読み込む。
+*/
+pub enum EvtxReader {
+    File(File),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Read for EvtxReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EvtxReader::File(f) => f.read(buf),
+            EvtxReader::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for EvtxReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            EvtxReader::File(f) => f.seek(pos),
+            EvtxReader::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// evtxファイルを開いてパーサーを作成する。zipファイル内のevtxファイルは"<zipファイルのパス>/<エントリ名>"で指定する。
+/// パスが"-"の場合は標準入力から読み込む
+pub fn open_evtx(path: &Path) -> Result<EvtxParser<EvtxReader>, String> {
+    let parse_config = ParserSettings::default()
+        .separate_json_attributes(true) // XMLのattributeをJSONに変換する時のルールを設定
+        .num_threads(*utils::PARSER_THREAD_NUM); // 設定しないと遅かったので、設定しておく。0の場合はCPU数から決める
+    EvtxParser::from_read_seek(open_reader(path)?)
+        .map(|parser| parser.with_configuration(parse_config))
+        .map_err(|e| format!("{} {}", path.display(), e))
+}
+
+/**
+* ファイル全体をパースする前に、ヘッダーからレコード数を、ファイル全体から等間隔に選んだチャンクのレコードからチャンネルを読み取る。
+* 転送されたログのように1つのファイルに複数のチャンネルが含まれることがあるので、最初のチャンクだけでは判断しない。
+* 標準入力や圧縮されたファイルは全体を展開し直すことになるので確認せずにNoneを返す。
+*/
+pub fn prescan_evtx(path: &Path) -> Result<Option<EvtxPrescan>, String> {
+    if is_stdin(path) || is_gzip(path) || split_zip_path(path).is_some() {
+        return Ok(None);
+    }
+    let mut reader = EvtxReader::File(File::open(path).map_err(|e| e.to_string())?);
+    let mut header = [0u8; EVTX_HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .map_err(|e| format!("{} {}", path.display(), e))?;
+    let records = header_record_count(&header)
+        .ok_or_else(|| format!("{} is not an evtx file.", path.display()))?;
+    let file_size = reader
+        .seek(SeekFrom::End(0))
+        .map_err(|e| format!("{} {}", path.display(), e))?;
+    let chunk_count = file_size.saturating_sub(EVTX_FILE_HEADER_SIZE) / EVTX_CHUNK_SIZE as u64;
+
+    let settings = Arc::new(
+        ParserSettings::default()
+            .separate_json_attributes(true)
+            .num_threads(1),
+    );
+    let mut channels = BTreeSet::new();
+    let mut forwarded = ForwardedDetector::new();
+    for index in prescan_chunk_indexes(chunk_count) {
+        let chunk = match read_chunk(&mut reader, index) {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+        for record in chunk_records(chunk, Arc::clone(&settings)) {
+            forwarded.observe(&record);
+            if let Some(channel) = utils::get_event_value("Event.System.Channel", &record)
+                .and_then(|channel| channel.as_str())
+            {
+                channels.insert(channel.to_string());
+            }
+        }
+    }
+    Ok(Some(EvtxPrescan {
+        channels,
+        records,
+        forwarded: forwarded.is_forwarded(),
+    }))
+}
+
+// 事前の確認で読み込むチャンクの番号を、最初と最後のチャンクを含めてファイル全体から等間隔に選ぶ
+fn prescan_chunk_indexes(chunk_count: u64) -> Vec<u64> {
+    if chunk_count <= PRESCAN_MAX_CHUNKS {
+        return (0..chunk_count).collect();
+    }
+    let mut indexes: Vec<u64> = (0..PRESCAN_MAX_CHUNKS)
+        .map(|i| i * (chunk_count - 1) / (PRESCAN_MAX_CHUNKS - 1))
+        .collect();
+    indexes.dedup();
+    indexes
+}
+
+// チャンクの先頭からいくつかのレコードを読み込んで返す。使われていないチャンクや壊れたチャンクは空を返す
+fn chunk_records(chunk: Vec<u8>, settings: Arc<ParserSettings>) -> Vec<Value> {
+    let mut chunk_data = match EvtxChunkData::new(chunk, false) {
+        Ok(chunk_data) => chunk_data,
+        Err(_) => return vec![],
+    };
+    let mut chunk = match chunk_data.parse(settings) {
+        Ok(chunk) => chunk,
+        Err(_) => return vec![],
+    };
+    chunk
+        .iter()
+        .take(PRESCAN_MAX_RECORDS)
+        .filter_map(|record| Some(record.ok()?.into_json_value().ok()?.data))
+        .collect()
+}
+
+// ファイルヘッダーの次に書き込まれるレコードのIDから、おおよそのレコード数を求める
+fn header_record_count(header: &[u8]) -> Option<u64> {
+    if header.len() < EVTX_HEADER_LEN || &header[..EVTX_SIGNATURE.len()] != EVTX_SIGNATURE {
+        return None;
+    }
+    let mut next_record_id = [0u8; 8];
+    next_record_id.copy_from_slice(&header[NEXT_RECORD_ID_OFFSET..NEXT_RECORD_ID_OFFSET + 8]);
+    Some(u64::from_le_bytes(next_record_id).saturating_sub(1))
+}
+
+/**
+* 検知したレコードがevtxファイルのどこにあるか(ファイル先頭からのバイトオフセット)を求める。
+* ファイルごとに各チャンクのレコードIDの範囲を覚えておき、レコードが含まれるチャンクだけを読み込む。
+* 標準入力から読み込んだファイルは読み直せないので求めない。
+*/
+#[derive(Default)]
+pub struct RecordOffsetIndex {
+    chunks: HashMap<String, Vec<(u64, u64)>>,
+    reader: Option<(String, EvtxReader)>,
+}
+
+impl RecordOffsetIndex {
+    pub fn new() -> RecordOffsetIndex {
+        RecordOffsetIndex::default()
+    }
+
+    /// レコードのファイル先頭からのバイトオフセットを返す。evtxファイルでない場合や見つからない場合はNoneを返す
+    pub fn offset(&mut self, filepath: &str, record_id: &str) -> Option<u64> {
+        let record_id = record_id.parse::<u64>().ok()?;
+        let path = Path::new(filepath);
+        if is_stdin(path) {
+            return None;
+        }
+        // 同じファイルの検知結果が続くことが多いので、直前に開いたファイルは開き直さない
+        if self
+            .reader
+            .as_ref()
+            .map_or(true, |(opened, _)| opened != filepath)
+        {
+            self.reader = Some((filepath.to_string(), open_reader(path).ok()?));
+        }
+        let reader = &mut self.reader.as_mut()?.1;
+        let ranges = self
+            .chunks
+            .entry(filepath.to_string())
+            .or_insert_with(|| chunk_record_ranges(reader));
+        let index = ranges
+            .iter()
+            .position(|(first, last)| (*first..=*last).contains(&record_id))?;
+        let chunk = read_chunk(reader, index as u64)?;
+        let (_, offset) = chunk_record_offsets(&chunk)
+            .into_iter()
+            .find(|(id, _)| *id == record_id)?;
+        Some(EVTX_FILE_HEADER_SIZE + index as u64 * EVTX_CHUNK_SIZE as u64 + offset as u64)
+    }
+}
+
+// 各チャンクのヘッダーから最初と最後のレコードIDを読み取る。シグネチャが壊れたチャンクはどのIDにも一致しない範囲にする
+fn chunk_record_ranges<R: Read + Seek>(reader: &mut R) -> Vec<(u64, u64)> {
+    let mut ranges = vec![];
+    let mut header = [0u8; CHUNK_LAST_RECORD_ID_OFFSET + 8];
+    loop {
+        let pos = EVTX_FILE_HEADER_SIZE + ranges.len() as u64 * EVTX_CHUNK_SIZE as u64;
+        if reader.seek(SeekFrom::Start(pos)).is_err() || reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        if &header[..EVTX_CHUNK_SIGNATURE.len()] != EVTX_CHUNK_SIGNATURE {
+            ranges.push((u64::MAX, 0));
+            continue;
+        }
+        ranges.push((
+            read_u64(&header, CHUNK_FIRST_RECORD_ID_OFFSET).unwrap_or(u64::MAX),
+            read_u64(&header, CHUNK_LAST_RECORD_ID_OFFSET).unwrap_or(0),
+        ));
+    }
+    ranges
+}
+
+/// index番目のチャンクを読み込む。ファイルの終わりに達した場合はNoneを返す
+pub fn read_chunk<R: Read + Seek>(reader: &mut R, index: u64) -> Option<Vec<u8>> {
+    reader
+        .seek(SeekFrom::Start(
+            EVTX_FILE_HEADER_SIZE + index * EVTX_CHUNK_SIZE as u64,
+        ))
+        .ok()?;
+    let mut chunk = vec![0u8; EVTX_CHUNK_SIZE];
+    reader.read_exact(&mut chunk).ok()?;
+    Some(chunk)
+}
+
+/// チャンク内のレコードのIDとチャンク先頭からの位置を先頭から順に返す。壊れたレコードがあった所で止める
+pub fn chunk_record_offsets(chunk: &[u8]) -> Vec<(u64, usize)> {
+    let end = read_u32(chunk, CHUNK_FREE_SPACE_OFFSET)
+        .map(|end| end as usize)
+        .filter(|end| *end <= chunk.len())
+        .unwrap_or(chunk.len());
+    let mut ret = vec![];
+    let mut offset = CHUNK_RECORDS_OFFSET;
+    while offset + RECORD_HEADER_SIZE <= end {
+        if &chunk[offset..offset + EVTX_RECORD_SIGNATURE.len()] != EVTX_RECORD_SIGNATURE {
+            break;
+        }
+        let size = read_u32(chunk, offset + 4).unwrap_or_default() as usize;
+        if size < RECORD_HEADER_SIZE || offset + size > chunk.len() {
+            break;
+        }
+        if let Some(record_id) = read_u64(chunk, offset + 8) {
+            ret.push((record_id, offset));
+        }
+        offset += size;
+    }
+    ret
+}
+
+/// リトルエンディアンのu32を読み取る
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// リトルエンディアンのu64を読み取る
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// evtxファイルを読み込むReaderを開く。標準入力、zipファイルのエントリ、gzファイルは展開してメモリに読み込む
+pub fn open_reader(path: &Path) -> Result<EvtxReader, String> {
+    let reader = if is_stdin(path) {
+        EvtxReader::Memory(Cursor::new(read_stdin()?))
+    } else if let Some((zip_path, entry_name)) = split_zip_path(path) {
+        EvtxReader::Memory(Cursor::new(read_zip_entry(&zip_path, &entry_name)?))
+    } else if is_gzip(path) {
+        EvtxReader::Memory(Cursor::new(read_gzip(path)?))
+    } else {
+        EvtxReader::File(File::open(path).map_err(|e| e.to_string())?)
+    };
+    Ok(reader)
+}
+
+/// 標準入力から読み込むことを表すパス("-")かを判定する
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+/// zipファイルかを拡張子で判定する
+pub fn is_zip(path: &Path) -> bool {
+    has_extension(path, "zip") && path.is_file()
+}
+
+/// gzファイルかを拡張子で判定する
+pub fn is_gzip(path: &Path) -> bool {
+    has_extension(path, "gz")
+}
+
+/// zipファイルに含まれるevtxファイルの一覧を"<zipファイルのパス>/<エントリ名>"の形式で返す
+pub fn list_zip_evtx(zip_path: &Path, check_signature: bool) -> Result<Vec<PathBuf>, String> {
+    let file = File::open(zip_path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut ret = vec![];
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(|e| e.to_string())?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let is_evtx = if check_signature {
+            let mut signature = [0u8; 8];
+            entry.read_exact(&mut signature).is_ok() && &signature == EVTX_SIGNATURE
+        } else {
+            name.ends_with(".evtx")
+        };
+        if is_evtx {
+            ret.push(zip_path.join(name));
+        }
+    }
+    Ok(ret)
+}
+
+/// gzファイルを展開した先頭がevtxファイルのシグネチャかを判定する
+pub fn has_gzip_evtx_signature(path: &Path) -> bool {
+    let mut signature = [0u8; 8];
+    match File::open(path) {
+        Ok(f) => {
+            GzDecoder::new(f).read_exact(&mut signature).is_ok() && &signature == EVTX_SIGNATURE
+        }
+        Err(_) => false,
+    }
+}
+
+/// パスの途中にzipファイルがある場合は、zipファイルのパスとエントリ名に分割する
+pub fn split_zip_path(path: &Path) -> Option<(PathBuf, String)> {
+    let ancestor = path.ancestors().skip(1).find(|ancestor| is_zip(ancestor))?;
+    let entry_name = path
+        .strip_prefix(ancestor)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("/");
+    Some((ancestor.to_path_buf(), entry_name))
+}
+
+fn read_zip_entry(zip_path: &Path, entry_name: &str) -> Result<Vec<u8>, String> {
+    let file = File::open(zip_path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut entry = archive.by_name(entry_name).map_err(|e| e.to_string())?;
+    let mut buf = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+// evtxのパーサーはSeekが必要なので、標準入力の内容はメモリ上に全て読み込む
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut buf = vec![];
+    io::stdin()
+        .lock()
+        .read_to_end(&mut buf)
+        .map_err(|e| format!("Failed to read from stdin. {}", e))?;
+    Ok(buf)
+}
+
+fn read_gzip(path: &Path) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut buf = vec![];
+    GzDecoder::new(file)
+        .read_to_end(&mut buf)
+        .map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.eq_ignore_ascii_case(ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{
+        chunk_record_offsets, has_gzip_evtx_signature, header_record_count, is_stdin,
+        list_zip_evtx, prescan_chunk_indexes, split_zip_path, RecordOffsetIndex,
+        CHUNK_FREE_SPACE_OFFSET, CHUNK_RECORDS_OFFSET, EVTX_CHUNK_SIGNATURE, EVTX_CHUNK_SIZE,
+        EVTX_FILE_HEADER_SIZE, EVTX_RECORD_SIGNATURE, EVTX_SIGNATURE, PRESCAN_MAX_CHUNKS,
+    };
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // レコードIDと大きさを指定したレコードを並べたチャンクを作る。レコードの中身は空にする
+    fn create_chunk(records: &[(u64, usize)]) -> Vec<u8> {
+        let mut chunk = vec![0u8; EVTX_CHUNK_SIZE];
+        chunk[..8].copy_from_slice(EVTX_CHUNK_SIGNATURE);
+        chunk[24..32].copy_from_slice(&records[0].0.to_le_bytes());
+        chunk[32..40].copy_from_slice(&records[records.len() - 1].0.to_le_bytes());
+        let mut offset = CHUNK_RECORDS_OFFSET;
+        for (record_id, size) in records {
+            chunk[offset..offset + 4].copy_from_slice(EVTX_RECORD_SIGNATURE);
+            chunk[offset + 4..offset + 8].copy_from_slice(&(*size as u32).to_le_bytes());
+            chunk[offset + 8..offset + 16].copy_from_slice(&record_id.to_le_bytes());
+            chunk[offset + size - 4..offset + size].copy_from_slice(&(*size as u32).to_le_bytes());
+            offset += size;
+        }
+        chunk[CHUNK_FREE_SPACE_OFFSET..CHUNK_FREE_SPACE_OFFSET + 4]
+            .copy_from_slice(&(offset as u32).to_le_bytes());
+        chunk
+    }
+
+    #[test]
+    fn test_chunk_record_offsets() {
+        let chunk = create_chunk(&[(1, 0x40), (2, 0x80), (3, 0x40)]);
+        assert_eq!(
+            chunk_record_offsets(&chunk),
+            vec![(1, 0x200), (2, 0x240), (3, 0x2c0)]
+        );
+    }
+
+    #[test]
+    fn test_record_offset_index() {
+        let mut data = vec![0u8; EVTX_FILE_HEADER_SIZE as usize];
+        data[..8].copy_from_slice(EVTX_SIGNATURE);
+        data.extend(create_chunk(&[(1, 0x40), (2, 0x40)]));
+        data.extend(create_chunk(&[(3, 0x40), (4, 0x100), (5, 0x40)]));
+        let path = std::env::temp_dir().join(format!(
+            "hayabusa-test-record-offset-{}.evtx",
+            std::process::id()
+        ));
+        fs::write(&path, &data).unwrap();
+        let filepath = path.display().to_string();
+
+        let mut index = RecordOffsetIndex::new();
+        assert_eq!(index.offset(&filepath, "1"), Some(0x1200));
+        assert_eq!(index.offset(&filepath, "5"), Some(0x1000 + 0x10000 + 0x340));
+        assert_eq!(index.offset(&filepath, "6"), None);
+        assert_eq!(index.offset(&filepath, "-"), None);
+        assert_eq!(index.offset("-", "1"), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_list_zip_evtx() {
+        let files = list_zip_evtx(Path::new("test_files/compressed/logs.zip"), false).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("test_files/compressed/logs.zip/Security.evtx"),
+                PathBuf::from("test_files/compressed/logs.zip/sub/System.evtx"),
+            ]
+        );
+        // シグネチャで判定する場合は中身が空のSystem.evtxは対象外
+        let files = list_zip_evtx(Path::new("test_files/compressed/logs.zip"), true).unwrap();
+        assert_eq!(
+            files,
+            vec![PathBuf::from(
+                "test_files/compressed/logs.zip/Security.evtx"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_split_zip_path() {
+        assert_eq!(
+            split_zip_path(Path::new("test_files/compressed/logs.zip/sub/System.evtx")),
+            Some((
+                PathBuf::from("test_files/compressed/logs.zip"),
+                "sub/System.evtx".to_string()
+            ))
+        );
+        assert_eq!(
+            split_zip_path(Path::new("test_files/evtx/test1.evtx")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prescan_chunk_indexes() {
+        assert_eq!(prescan_chunk_indexes(0), Vec::<u64>::new());
+        assert_eq!(prescan_chunk_indexes(3), vec![0, 1, 2]);
+        assert_eq!(prescan_chunk_indexes(15), vec![0, 2, 4, 6, 8, 10, 12, 14]);
+        let indexes = prescan_chunk_indexes(1000);
+        assert_eq!(indexes.len(), PRESCAN_MAX_CHUNKS as usize);
+        assert_eq!(indexes.first(), Some(&0));
+        assert_eq!(indexes.last(), Some(&999));
+    }
+
+    #[test]
+    fn test_is_stdin() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("./-")));
+        assert!(!is_stdin(Path::new("test_files/evtx/test1.evtx")));
+    }
+
+    #[test]
+    fn test_has_gzip_evtx_signature() {
+        assert!(has_gzip_evtx_signature(Path::new(
+            "test_files/compressed/Security.evtx.gz"
+        )));
+        assert!(!has_gzip_evtx_signature(Path::new(
+            "test_files/evtx/test1.evtx"
+        )));
+    }
+
+    #[test]
+    fn test_header_record_count() {
+        let mut header = vec![0u8; 32];
+        header[..8].copy_from_slice(EVTX_SIGNATURE);
+        header[24..32].copy_from_slice(&1501u64.to_le_bytes());
+        assert_eq!(header_record_count(&header), Some(1500));
+        // シグネチャがない場合やヘッダーが短い場合はevtxファイルではない
+        assert_eq!(header_record_count(&header[..16]), None);
+        header[0] = b'X';
+        assert_eq!(header_record_count(&header), None);
+    }
+}
@@ -1,10 +1,27 @@
 pub mod afterfact;
+pub mod cloud;
 pub mod detections;
+pub mod error;
+pub mod etl;
+pub mod event_xml;
+pub mod evt;
 pub mod filter;
+pub mod input;
+pub mod linux;
+pub mod logging;
+pub mod mde;
 pub mod notify;
 pub mod omikuji;
 pub mod options;
+pub mod output;
+pub mod progress;
+pub mod record_store;
+pub mod recovery;
+pub mod remote;
+pub mod resources;
 pub mod timeline;
+pub mod triage;
+pub mod wef;
 pub mod yaml;
 #[macro_use]
 extern crate prettytable;
@@ -0,0 +1,227 @@
+use crate::event_xml;
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// auditdのレコードに付けるチャンネル。Sigmaのlogsourceのservice: auditdに対応する
+pub const AUDITD_CHANNEL: &str = "auditd";
+/// Sysmon for Linuxのイベントのチャンネル
+pub const SYSMON_CHANNEL: &str = "Linux-Sysmon/Operational";
+
+// auditdとSysmon for Linuxのログが保存される標準のファイル名。audit.log.1のようにローテートしたファイルも対象とする
+const LOG_FILE_PREFIXES: [&str; 3] = ["audit.log", "syslog", "messages"];
+
+// 値が16進数でエンコードされることがあるauditdのフィールド。スペースなどを含む値はクォートせずにエンコードされる
+const HEX_ENCODED_FIELDS: [&str; 7] = ["proctitle", "name", "comm", "exe", "cwd", "cmd", "data"];
+
+/// auditdのログ(audit.log)かSysmon for Linuxのログを含むsyslogのファイルかを判定する
+pub fn is_linux_log_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| {
+            let name = name.to_lowercase();
+            LOG_FILE_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        })
+}
+
+/**
+* auditdのログとSysmon for Linuxのログを読み込んで、evtxファイルのレコードと同じ形式のJSONで返す。
+* auditdは1行を1レコードとしてフィールドをEventDataにし、Sysmon for Linuxは行に含まれる<Event>要素のXMLを変換する。
+* どちらでもない行は読み飛ばす。
+*/
+pub fn read_linux_log(path: &Path) -> Result<Vec<Value>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let text = String::from_utf8_lossy(&bytes);
+    let mut records = vec![];
+    for line in text.lines() {
+        if line.contains("<Event>") {
+            records.extend(parse_sysmon_line(line));
+        } else if let Some(record) = parse_auditd_line(line) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+// syslogの行に含まれる<Event>...</Event>を変換する。壊れたXMLは読み飛ばす
+fn parse_sysmon_line(line: &str) -> Vec<Value> {
+    let mut events = vec![];
+    let mut rest = line;
+    while let Some(start) = rest.find("<Event>") {
+        let end = match rest[start..].find("</Event>") {
+            Some(end) => start + end + "</Event>".len(),
+            None => break,
+        };
+        if let Ok(parsed) = event_xml::parse_events_xml(&rest[start..end]) {
+            events.extend(parsed);
+        }
+        rest = &rest[end..];
+    }
+    events
+}
+
+/**
+* type=SYSCALL msg=audit(1652000000.123:456): key=value ...の形式のauditdの行を変換する。
+* msg=audit(...)のタイムスタンプとシリアル番号を日時とEventRecordIDに、node=があればComputerにする。
+* syslog経由で転送された行のように、行の途中からauditdのレコードが始まる場合も対象とする。
+*/
+fn parse_auditd_line(line: &str) -> Option<Value> {
+    let msg_start = line.find("msg=audit(")?;
+    let msg = &line[msg_start + "msg=audit(".len()..];
+    let msg_end = msg.find("):")?;
+    let (timestamp, serial) = msg[..msg_end].split_once(':')?;
+    let (secs, millis) = timestamp.split_once('.').unwrap_or((timestamp, "0"));
+    let time = Utc
+        .timestamp_opt(secs.parse().ok()?, millis.parse::<u32>().ok()? * 1_000_000)
+        .single()?
+        .format("%Y-%m-%dT%H:%M:%S%.6fZ")
+        .to_string();
+
+    let mut fields = Map::new();
+    parse_fields(&line[..msg_start], "", &mut fields);
+    let record_type = fields.get("type")?.as_str()?.to_string();
+    // ENRICHED形式のログは、解釈した値の前に0x1dの区切り文字がある
+    parse_fields(
+        &msg[msg_end + 2..].replace('\x1d', " "),
+        &record_type,
+        &mut fields,
+    );
+
+    let computer = fields
+        .get("node")
+        .and_then(|node| node.as_str())
+        .unwrap_or("-")
+        .to_string();
+    Some(json!({
+        "Event": {
+            "System": {
+                "Provider_attributes": { "Name": "auditd" },
+                "TimeCreated_attributes": { "SystemTime": time },
+                "EventRecordID": serial.parse::<u64>().ok()?,
+                "Channel": AUDITD_CHANNEL,
+                "Computer": computer,
+            },
+            "EventData": fields,
+        }
+    }))
+}
+
+/**
+* key=valueをスペース区切りで並べた文字列をフィールドにする。
+* ダブルクォートの値はクォートを外し、USER_LOGINなどのmsg='...'のシングルクォートの値は中のkey=valueを展開する。
+* クォートされていない値は、16進数でエンコードされるフィールドであれば文字列に戻す。
+*/
+fn parse_fields(text: &str, record_type: &str, fields: &mut Map<String, Value>) {
+    let mut rest = text.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].split_whitespace().last().unwrap_or_default();
+        rest = &rest[eq + 1..];
+        let (value, next, quote) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => match rest[1..].find(quote) {
+                Some(end) => (&rest[1..end + 1], &rest[end + 2..], Some(quote)),
+                None => (&rest[1..], "", Some(quote)),
+            },
+            _ => match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..], None),
+                None => (rest, "", None),
+            },
+        };
+        match quote {
+            Some('\'') => parse_fields(value, record_type, fields),
+            _ if key.is_empty() => {}
+            Some(_) => {
+                fields.insert(key.to_string(), Value::from(value));
+            }
+            None => {
+                let value =
+                    decode_hex(record_type, key, value).unwrap_or_else(|| value.to_string());
+                fields.insert(key.to_string(), Value::from(value));
+            }
+        }
+        rest = next.trim_start();
+    }
+}
+
+// 16進数でエンコードされた値を文字列に戻す。proctitleは引数がNULL区切りなのでスペースにする
+fn decode_hex(record_type: &str, key: &str, value: &str) -> Option<String> {
+    let is_execve_arg = record_type == "EXECVE"
+        && key.len() > 1
+        && key.starts_with('a')
+        && key[1..].chars().all(|c| c.is_ascii_digit());
+    if (!is_execve_arg && !HEX_ENCODED_FIELDS.contains(&key)) || value.len() < 2 {
+        return None;
+    }
+    let bytes = hex::decode(value).ok()?;
+    Some(
+        String::from_utf8_lossy(&bytes)
+            .replace('\0', " ")
+            .trim_end()
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linux::{decode_hex, is_linux_log_file, parse_auditd_line, parse_sysmon_line};
+    use std::path::Path;
+
+    #[test]
+    fn test_is_linux_log_file() {
+        assert!(is_linux_log_file(Path::new("/var/log/audit/audit.log")));
+        assert!(is_linux_log_file(Path::new("logs/audit.log.1")));
+        assert!(is_linux_log_file(Path::new("web01/syslog")));
+        assert!(!is_linux_log_file(Path::new("Security.evtx")));
+    }
+
+    #[test]
+    fn test_parse_auditd_line() {
+        let record = parse_auditd_line(
+            "node=web01 type=EXECVE msg=audit(1652000000.123:456): argc=3 a0=\"curl\" a1=\"-o\" a2=2F746D702F612062",
+        )
+        .unwrap();
+        let system = &record["Event"]["System"];
+        assert_eq!(system["Channel"], "auditd");
+        assert_eq!(system["Computer"], "web01");
+        assert_eq!(system["EventRecordID"], 456);
+        assert_eq!(
+            system["TimeCreated_attributes"]["SystemTime"],
+            "2022-05-08T08:53:20.123000Z"
+        );
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(event_data["type"], "EXECVE");
+        assert_eq!(event_data["a0"], "curl");
+        assert_eq!(event_data["a2"], "/tmp/a b");
+
+        let record = parse_auditd_line(
+            "type=USER_LOGIN msg=audit(1652000000.500:7): pid=100 uid=0 msg='op=login acct=\"root\" exe=\"/usr/sbin/sshd\" res=failed'\x1dUID=\"root\"",
+        )
+        .unwrap();
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(record["Event"]["System"]["Computer"], "-");
+        assert_eq!(event_data["acct"], "root");
+        assert_eq!(event_data["res"], "failed");
+        assert_eq!(event_data["UID"], "root");
+        assert_eq!(
+            decode_hex("PROCTITLE", "proctitle", "6375726C002D6F").unwrap(),
+            "curl -o"
+        );
+        assert_eq!(decode_hex("SYSCALL", "a0", "7ffd"), None);
+        assert!(parse_auditd_line("May 20 10:00:00 web01 sshd[100]: Accepted password").is_none());
+    }
+
+    #[test]
+    fn test_parse_sysmon_line() {
+        let line = r#"May 20 10:00:00 web01 sysmon: <Event><System><Provider Name="Linux-Sysmon" /><EventID>1</EventID><Channel>Linux-Sysmon/Operational</Channel><Computer>web01</Computer></System><EventData><Data Name="Image">/usr/bin/curl</Data></EventData></Event>"#;
+        let events = parse_sysmon_line(line);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0]["Event"]["System"]["Channel"],
+            "Linux-Sysmon/Operational"
+        );
+        assert_eq!(events[0]["Event"]["EventData"]["Image"], "/usr/bin/curl");
+        assert!(parse_sysmon_line("sysmon: <Event><System></Event>").is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::detections::configs;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+
+/// -vを指定した場合だけ出力するエラーや警告のtarget
+pub const VERBOSE_TARGET: &str = "verbose";
+
+// ログの出力を設定したかどうか。設定前やテストではAlertMessageは渡されたWriterに直接書き込む
+static LOGGING_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 診断メッセージの出力形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Result<LogFormat, String> {
+        match format.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid --log-format: {}", format)),
+        }
+    }
+}
+
+/// -vの数に応じて出力するログのレベルを返す。指定なしはWARN以上、-vはINFO以上、-vvはDEBUG以上
+pub fn verbosity_level(occurrences: u64) -> Level {
+    match occurrences {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        _ => Level::DEBUG,
+    }
+}
+
+/// -vを指定しない場合はVERBOSE_TARGETのログを出力しないフィルタを返す
+pub fn verbose_filter(occurrences: u64) -> Targets {
+    let verbose = if occurrences == 0 {
+        LevelFilter::OFF
+    } else {
+        LevelFilter::TRACE
+    };
+    Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_target(VERBOSE_TARGET, verbose)
+}
+
+/// -vを指定した場合だけ出力するエラー。エラーログのファイルには-vに関係なく記録する
+pub fn verbose_error(contents: &str) {
+    tracing::error!(target: VERBOSE_TARGET, "{}", contents);
+}
+
+/// -vを指定した場合だけ出力する警告
+pub fn verbose_warn(contents: &str) {
+    tracing::warn!(target: VERBOSE_TARGET, "{}", contents);
+}
+
+/// ログの出力を設定済みかどうか
+pub fn is_enabled() -> bool {
+    LOGGING_ENABLED.load(Ordering::Relaxed)
+}
+
+/**
+* エラーや警告、-vの詳細情報などの診断メッセージを出力するtracingのsubscriberを設定する。
+* --log-fileを指定した場合は標準エラー出力ではなくファイルに出力するので、自動化する時に検知結果と診断メッセージを分けられる。
+*/
+pub fn init() -> Result<(), String> {
+    let config = configs::CONFIG.read().unwrap();
+    let occurrences = config.args.occurrences_of("verbose");
+    let format = match config.args.value_of("log-format") {
+        Some(format) => LogFormat::parse(format)?,
+        None => LogFormat::Text,
+    };
+    match config.args.value_of("log-file") {
+        Some(path) => {
+            // 他の出力ファイルと同じく、--clobberを指定しない場合は既存のファイルを上書きしない
+            if !config.args.is_present("clobber") && Path::new(path).exists() {
+                return Err(format!(
+                    "The file {} already exists. Please specify a different filename or use --clobber to overwrite it.",
+                    path
+                ));
+            }
+            let file = File::create(path).map_err(|e| format!("{} [file:{}]", e, path))?;
+            set_subscriber(occurrences, format, Mutex::new(file))?;
+        }
+        None => set_subscriber(occurrences, format, std::io::stderr)?,
+    }
+    LOGGING_ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn set_subscriber<W>(occurrences: u64, format: LogFormat, writer: W) -> Result<(), String>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(verbosity_level(occurrences))
+        .with_ansi(false)
+        .with_writer(writer);
+    let result = match format {
+        LogFormat::Text => tracing::subscriber::set_global_default(
+            builder
+                .event_format(TextFormat)
+                .finish()
+                .with(verbose_filter(occurrences)),
+        ),
+        LogFormat::Json => tracing::subscriber::set_global_default(
+            builder.json().finish().with(verbose_filter(occurrences)),
+        ),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// 従来のAlertMessageと同じ"[ERROR] メッセージ"の形式で出力する
+struct TextFormat;
+
+impl<S, N> FormatEvent<S, N> for TextFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        write!(writer, "[{}] ", event.metadata().level())?;
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{verbose_filter, verbosity_level, LogFormat, VERBOSE_TARGET};
+    use tracing::Level;
+
+    #[test]
+    fn test_verbosity_level() {
+        assert_eq!(verbosity_level(0), Level::WARN);
+        assert_eq!(verbosity_level(1), Level::INFO);
+        assert_eq!(verbosity_level(2), Level::DEBUG);
+        assert_eq!(verbosity_level(3), Level::DEBUG);
+    }
+
+    #[test]
+    fn test_verbose_filter() {
+        assert!(!verbose_filter(0).would_enable(VERBOSE_TARGET, &Level::ERROR));
+        assert!(verbose_filter(0).would_enable("hayabusa", &Level::ERROR));
+        assert!(verbose_filter(1).would_enable(VERBOSE_TARGET, &Level::ERROR));
+        assert!(verbose_filter(1).would_enable(VERBOSE_TARGET, &Level::WARN));
+    }
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!(LogFormat::parse("text"), Ok(LogFormat::Text));
+        assert_eq!(LogFormat::parse("JSON"), Ok(LogFormat::Json));
+        assert!(LogFormat::parse("xml").is_err());
+    }
+}
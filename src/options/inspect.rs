@@ -0,0 +1,465 @@
+use crate::record_store::RecordStoreReader;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// --minutesを指定しない場合に、選択した検知の前後で表示するイベントの範囲(分)
+pub const DEFAULT_PIVOT_MINUTES: i64 = 10;
+// listで1ページに表示する検知の数
+const PAGE_SIZE: usize = 20;
+// pivotの結果で、1イベントに表示するEventDataのフィールドの数
+const SUMMARY_FIELDS: usize = 6;
+// ログオンIDが入っているフィールド
+const LOGON_ID_FIELDS: [&str; 3] = ["TargetLogonId", "SubjectLogonId", "LogonId"];
+// ProcessGuidが入っているフィールド
+const PROCESS_GUID_FIELDS: [&str; 4] = [
+    "ProcessGuid",
+    "ParentProcessGuid",
+    "SourceProcessGuid",
+    "TargetProcessGuid",
+];
+// ほぼ全てのイベントに記録されるため、pivotに使わないログオンID(なしとSYSTEM)
+const IGNORED_LOGON_IDS: [&str; 2] = ["0x0", "0x3e7"];
+
+const HELP: &str = "Commands:
+  list [PAGE]                            List the detections.
+  show <NUMBER>                          Show the detection and the original event.
+  pivot <NUMBER> logon|process|computer [MINUTES]
+                                         Show the events with the same LogonId, ProcessGuid or Computer around the detection.
+  help                                   Show this help.
+  quit                                   Exit.";
+
+/// 何を基準に関連するイベントを集めるか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PivotKind {
+    Logon,
+    Process,
+    Computer,
+}
+
+impl PivotKind {
+    fn parse(kind: &str) -> Option<PivotKind> {
+        match kind.to_lowercase().as_str() {
+            "logon" | "logonid" => Some(PivotKind::Logon),
+            "process" | "processguid" => Some(PivotKind::Process),
+            "computer" => Some(PivotKind::Computer),
+            _ => None,
+        }
+    }
+}
+
+/// 結果のCSVの1行分の検知
+#[derive(Debug)]
+pub struct InspectDetection {
+    columns: Vec<(String, String)>,
+    time: Option<DateTime<Utc>>,
+}
+
+impl InspectDetection {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.columns
+            .iter()
+            .find(|(column, _)| column == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/**
+* -oで保存した結果のCSVの検知を選んで、--save-storeで保存したレコードストアから関連するイベントを探す。
+* 検知の元のイベントはFilePathとRecordIDの列で探し、これらの列がない場合はComputer、EventID、Timestampで探す。
+*/
+pub struct Inspector {
+    detections: Vec<InspectDetection>,
+    store: PathBuf,
+    minutes: i64,
+}
+
+impl Inspector {
+    pub fn new(results: &Path, store: &Path, minutes: i64) -> Result<Inspector, String> {
+        // 結果のCSVはoutput profileで区切り文字が変わるので、ヘッダーで最も多い区切り文字を使う
+        let header = std::fs::read_to_string(results)
+            .map_err(|e| format!("{} [path:{}]", e, results.display()))?
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let delimiter = [b',', b'\t', b'|', b';']
+            .into_iter()
+            .max_by_key(|delimiter| header.matches(*delimiter as char).count())
+            .unwrap_or(b',');
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_path(results)
+            .map_err(|e| e.to_string())?;
+        let headers: Vec<String> = reader
+            .headers()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+        let mut detections = vec![];
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            let columns: Vec<(String, String)> = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, value)| (header.to_string(), value.trim().to_string()))
+                .collect();
+            let mut detection = InspectDetection {
+                columns,
+                time: None,
+            };
+            detection.time = detection.get("Timestamp").and_then(parse_timestamp);
+            detections.push(detection);
+        }
+        // レコードストアを開けるかを最初に確認する
+        RecordStoreReader::open(store)?;
+        Ok(Inspector {
+            detections,
+            store: store.to_path_buf(),
+            minutes,
+        })
+    }
+
+    /// コマンドを1行ずつ読み込んで実行する。標準入力をパイプで渡せばスクリプトからも使える
+    pub fn run<R: BufRead, W: Write>(&self, input: R, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "Loaded {} detections. Type help to show the commands.",
+            self.detections.len()
+        )?;
+        write!(out, "inspect> ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.execute(&line?, out)? {
+                return Ok(());
+            }
+            write!(out, "inspect> ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    // コマンドを実行する。終了する場合はfalseを返す
+    fn execute<W: Write>(&self, line: &str, out: &mut W) -> io::Result<bool> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let number = |idx: usize| args.get(idx).and_then(|arg| arg.parse::<usize>().ok());
+        match args.first().copied() {
+            None => {}
+            Some("quit") | Some("exit") | Some("q") => return Ok(false),
+            Some("help") | Some("?") => writeln!(out, "{}", HELP)?,
+            Some("list") | Some("ls") => self.list(number(1).unwrap_or(1), out)?,
+            Some("show") => match number(1) {
+                Some(num) => self.show(num, out)?,
+                None => writeln!(out, "Usage: show <NUMBER>")?,
+            },
+            Some("pivot") => match (number(1), args.get(2).and_then(|k| PivotKind::parse(k))) {
+                (Some(num), Some(kind)) => {
+                    let minutes = args
+                        .get(3)
+                        .and_then(|arg| arg.parse::<i64>().ok())
+                        .unwrap_or(self.minutes);
+                    match self.pivot(num, kind, minutes) {
+                        Ok(events) => {
+                            for (file, record) in events.iter() {
+                                writeln!(out, "{}", summarize(file, record))?;
+                            }
+                            writeln!(out, "{} events", events.len())?;
+                        }
+                        Err(err) => writeln!(out, "{}", err)?,
+                    }
+                }
+                _ => writeln!(
+                    out,
+                    "Usage: pivot <NUMBER> logon|process|computer [MINUTES]"
+                )?,
+            },
+            Some(command) => writeln!(out, "Unknown command: {}. Type help.", command)?,
+        }
+        Ok(true)
+    }
+
+    fn list<W: Write>(&self, page: usize, out: &mut W) -> io::Result<()> {
+        let start = (page.max(1) - 1) * PAGE_SIZE;
+        for (idx, detection) in self
+            .detections
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(PAGE_SIZE)
+        {
+            writeln!(
+                out,
+                "{:>5}  {}  {}  {}  {}",
+                idx + 1,
+                detection.get("Timestamp").unwrap_or("-"),
+                detection.get("Computer").unwrap_or("-"),
+                detection.get("Level").unwrap_or("-"),
+                detection.get("RuleTitle").unwrap_or("-")
+            )?;
+        }
+        let pages = (self.detections.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+        writeln!(out, "Page {}/{}", page.max(1), pages.max(1))
+    }
+
+    // 検知の列と、レコードストアにある元のイベントを表示する
+    fn show<W: Write>(&self, num: usize, out: &mut W) -> io::Result<()> {
+        let detection = match self.detection(num) {
+            Ok(detection) => detection,
+            Err(err) => return writeln!(out, "{}", err),
+        };
+        for (column, value) in detection.columns.iter() {
+            writeln!(out, "{}: {}", column, value)?;
+        }
+        match self.find_record(detection) {
+            Ok(Some((file, record))) => {
+                writeln!(out, "--- {} ---", file)?;
+                writeln!(
+                    out,
+                    "{}",
+                    serde_json::to_string_pretty(&record).unwrap_or_default()
+                )
+            }
+            Ok(None) => writeln!(out, "The original event was not found in the record store."),
+            Err(err) => writeln!(out, "{}", err),
+        }
+    }
+
+    fn detection(&self, num: usize) -> Result<&InspectDetection, String> {
+        num.checked_sub(1)
+            .and_then(|idx| self.detections.get(idx))
+            .ok_or_else(|| format!("There is no detection number {}.", num))
+    }
+
+    // 検知の元のイベントをレコードストアから探す
+    fn find_record(&self, detection: &InspectDetection) -> Result<Option<(String, Value)>, String> {
+        let file_path = detection.get("FilePath");
+        let record_id = detection.get("RecordID");
+        for entry in RecordStoreReader::open(&self.store)? {
+            let (file, record) = entry?;
+            let system = &record["Event"]["System"];
+            let is_match = match (file_path, record_id) {
+                (Some(file_path), Some(record_id)) => {
+                    file == file_path && value_to_string(&system["EventRecordID"]) == record_id
+                }
+                _ => {
+                    detection.get("Computer") == system["Computer"].as_str()
+                        && detection.get("EventID")
+                            == Some(value_to_string(&system["EventID"]).as_str())
+                        && match (detection.time, record_time(&record)) {
+                            (Some(time), Some(record_time)) => {
+                                (time - record_time).num_milliseconds().abs() < 1000
+                            }
+                            _ => false,
+                        }
+                }
+            };
+            if is_match {
+                return Ok(Some((file, record)));
+            }
+        }
+        Ok(None)
+    }
+
+    /**
+     * 選択した検知の前後minutes分のイベントのうち、同じログオンID、ProcessGuid、Computerのイベントを時刻順に返す。
+     * ログオンIDとProcessGuidは元のイベントから取得するので、元のイベントがレコードストアにある必要がある。
+     */
+    pub fn pivot(
+        &self,
+        num: usize,
+        kind: PivotKind,
+        minutes: i64,
+    ) -> Result<Vec<(String, Value)>, String> {
+        let detection = self.detection(num)?;
+        let time = detection
+            .time
+            .ok_or_else(|| "The timestamp of the detection could not be parsed.".to_string())?;
+        let record = self.find_record(detection)?.map(|(_, record)| record);
+        let computer = detection.get("Computer").unwrap_or_default().to_string();
+        let values: Vec<String> = match kind {
+            PivotKind::Computer => vec![],
+            PivotKind::Logon | PivotKind::Process => {
+                let record = record.as_ref().ok_or_else(|| {
+                    "The original event was not found in the record store.".to_string()
+                })?;
+                let values = event_data_values(record, pivot_fields(kind));
+                if values.is_empty() {
+                    return Err(
+                        "The original event does not have the field to pivot on.".to_string()
+                    );
+                }
+                values
+            }
+        };
+        let window = Duration::minutes(minutes);
+        let mut events = vec![];
+        for entry in RecordStoreReader::open(&self.store)? {
+            let (file, record) = entry?;
+            if record["Event"]["System"]["Computer"].as_str() != Some(computer.as_str()) {
+                continue;
+            }
+            match record_time(&record) {
+                Some(record_time)
+                    if (record_time - time).num_seconds().abs() <= window.num_seconds() => {}
+                _ => continue,
+            }
+            let is_related = kind == PivotKind::Computer
+                || event_data_values(&record, pivot_fields(kind))
+                    .iter()
+                    .any(|value| values.contains(value));
+            if is_related {
+                events.push((file, record));
+            }
+        }
+        events.sort_by_key(|(_, record)| record_time(record));
+        Ok(events)
+    }
+}
+
+fn pivot_fields(kind: PivotKind) -> &'static [&'static str] {
+    match kind {
+        PivotKind::Logon => &LOGON_ID_FIELDS,
+        PivotKind::Process => &PROCESS_GUID_FIELDS,
+        PivotKind::Computer => &[],
+    }
+}
+
+// EventDataのフィールドの値を集める。ログオンIDは大文字小文字を区別しない
+fn event_data_values(record: &Value, fields: &[&str]) -> Vec<String> {
+    fields
+        .iter()
+        .filter_map(|field| record["Event"]["EventData"][field].as_str())
+        .map(|value| value.to_lowercase())
+        .filter(|value| !value.is_empty() && !IGNORED_LOGON_IDS.contains(&value.as_str()))
+        .collect()
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn record_time(record: &Value) -> Option<DateTime<Utc>> {
+    record["Event"]["System"]["TimeCreated_attributes"]["SystemTime"]
+        .as_str()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// 結果のCSVのTimestampは--rfc-3339、--rfc-2822と既定の形式のいずれか
+fn parse_timestamp(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.f %:z")
+        .or_else(|_| DateTime::parse_from_rfc3339(time))
+        .or_else(|_| DateTime::parse_from_rfc2822(time))
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// pivotの結果の1行。日時、Computer、チャンネル、イベントID、レコードID、EventDataの先頭のフィールド
+fn summarize(file: &str, record: &Value) -> String {
+    let system = &record["Event"]["System"];
+    let fields = record["Event"]["EventData"]
+        .as_object()
+        .map(|data| {
+            data.iter()
+                .take(SUMMARY_FIELDS)
+                .map(|(key, value)| format!("{}: {}", key, value_to_string(value)))
+                .collect::<Vec<String>>()
+                .join(" ¦ ")
+        })
+        .unwrap_or_default();
+    format!(
+        "{}  {}  {}  {}  {}  {}  [{}]",
+        system["TimeCreated_attributes"]["SystemTime"]
+            .as_str()
+            .unwrap_or("-"),
+        system["Computer"].as_str().unwrap_or("-"),
+        system["Channel"].as_str().unwrap_or("-"),
+        value_to_string(&system["EventID"]),
+        value_to_string(&system["EventRecordID"]),
+        fields,
+        file
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::inspect::{parse_timestamp, Inspector, PivotKind};
+    use crate::record_store::RecordStoreWriter;
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    fn event(record_id: u64, time: &str, event_id: u64, logon_id: &str) -> serde_json::Value {
+        json!({"Event": {
+            "System": {
+                "EventID": event_id,
+                "EventRecordID": record_id,
+                "Channel": "Security",
+                "Computer": "DC01",
+                "TimeCreated_attributes": {"SystemTime": time},
+            },
+            "EventData": {"TargetLogonId": logon_id},
+        }})
+    }
+
+    #[test]
+    fn test_pivot() {
+        let store = Path::new("./test_files/inspect-test.jsonl.gz");
+        let results = Path::new("./test_files/inspect-test.csv");
+        let mut writer = RecordStoreWriter::create(store.to_str().unwrap()).unwrap();
+        for record in [
+            event(1, "2022-05-20T10:00:00Z", 4624, "0x1234"),
+            event(2, "2022-05-20T10:05:00Z", 4672, "0x1234"),
+            event(3, "2022-05-20T10:06:00Z", 4624, "0x5678"),
+            event(4, "2022-05-20T12:00:00Z", 4634, "0x1234"),
+        ] {
+            writer.write("Security.evtx", &record).unwrap();
+        }
+        writer.finish().unwrap();
+        fs::write(
+            results,
+            "Timestamp,Computer,Channel,EventID,RecordID,Level,RuleTitle,Details,FilePath\n2022-05-20 10:00:00.000 +00:00,DC01,Sec,4624,1,low,Logon,-,Security.evtx\n",
+        )
+        .unwrap();
+
+        let inspector = Inspector::new(results, store, 10).unwrap();
+        let events = inspector.pivot(1, PivotKind::Logon, 10).unwrap();
+        let record_ids: Vec<u64> = events
+            .iter()
+            .map(|(_, record)| record["Event"]["System"]["EventRecordID"].as_u64().unwrap())
+            .collect();
+        assert_eq!(record_ids, vec![1, 2]);
+        assert_eq!(
+            inspector.pivot(1, PivotKind::Computer, 10).unwrap().len(),
+            3
+        );
+        assert!(inspector.pivot(2, PivotKind::Logon, 10).is_err());
+
+        let mut out = vec![];
+        inspector
+            .run("show 1\npivot 1 logon\nquit\n".as_bytes(), &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("RuleTitle: Logon"));
+        assert!(out.contains("\"TargetLogonId\": \"0x1234\""));
+        assert!(out.contains("2 events"));
+        assert!(Inspector::new(Path::new("./test_files/not_exist.csv"), store, 10).is_err());
+        fs::remove_file(store).ok();
+        fs::remove_file(results).ok();
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expect = parse_timestamp("2022-05-20T10:00:00Z");
+        assert!(expect.is_some());
+        assert_eq!(parse_timestamp("2022-05-20 19:00:00.000 +09:00"), expect);
+        assert_eq!(parse_timestamp("Fri, 20 May 2022 10:00:00 +0000"), expect);
+        assert_eq!(parse_timestamp("-"), None);
+    }
+}
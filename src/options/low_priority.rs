@@ -0,0 +1,83 @@
+use crate::detections::configs;
+use lazy_static::lazy_static;
+
+/// --low-priorityの場合のデフォルトのスレッド数の上限
+pub const LOW_PRIORITY_MAX_THREADS: usize = 2;
+
+// niceの最も低い優先度
+#[cfg(unix)]
+const LOWEST_NICE: libc::c_int = 19;
+// ioprio_setの引数。プロセスを対象にしてIOのスケジューリングクラスをidleにする
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+lazy_static! {
+    /// --low-priorityが指定されている場合は優先度を下げてスレッド数を抑える
+    pub static ref LOW_PRIORITY_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("low-priority");
+}
+
+/// --low-priorityでスレッド数が指定されていない場合のスレッド数
+pub fn low_priority_thread_num() -> usize {
+    num_cpus::get().clamp(1, LOW_PRIORITY_MAX_THREADS)
+}
+
+/**
+* 本番サーバーで--live-analysisを実行する時に業務の処理と競合しないように、プロセスのCPUとIOの優先度を下げる。
+* Linuxではスレッド毎に優先度を持つので、解析用のスレッドを作る前に呼び出して作成するスレッドに引き継がせる。
+*/
+#[cfg(target_os = "windows")]
+pub fn lower_priority() -> Result<(), String> {
+    use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+    use winapi::um::winbase::PROCESS_MODE_BACKGROUND_BEGIN;
+
+    // バックグラウンドモードではCPU、IO、メモリの優先度がまとめて下がる
+    let ret = unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) };
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn lower_priority() -> Result<(), String> {
+    let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, LOWEST_NICE) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    lower_io_priority()
+}
+
+#[cfg(target_os = "linux")]
+fn lower_io_priority() -> Result<(), String> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn lower_io_priority() -> Result<(), String> {
+    // macOSではniceだけを下げる
+    Ok(())
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+pub fn lower_priority() -> Result<(), String> {
+    Err("Lowering the priority is not supported on this platform.".to_string())
+}
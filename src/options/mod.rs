@@ -1 +1,12 @@
+pub mod bench;
+pub mod completion;
+pub mod custody_log;
+pub mod embedded_rules;
+pub mod inspect;
 pub mod level_tuning;
+pub mod low_priority;
+pub mod private_cache;
+pub mod remote_rules;
+pub mod rule_test;
+pub mod run_metadata;
+pub mod sigma_convert;
@@ -0,0 +1,128 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// ユーザーのキャッシュディレクトリの下に作るhayabusaのディレクトリ名
+const CACHE_DIR_NAME: &str = "hayabusa";
+
+/**
+* ユーザーのキャッシュディレクトリの下に、実行したユーザーだけが読み書きできるディレクトリを作成して返す。
+* 展開したルールや設定ファイルを他のユーザーが事前に用意したもので差し替えられないように、
+* 共有の一時フォルダは使わず、既にあるディレクトリは所有者と権限を確認する。
+*/
+pub fn private_cache_dir(name: &str) -> Result<PathBuf, String> {
+    let base = user_cache_dir().ok_or_else(|| {
+        "Could not find the user cache directory. Please set the HOME environment variable."
+            .to_string()
+    })?;
+    fs::create_dir_all(&base).map_err(|e| format!("{} [dir:{}]", e, base.display()))?;
+    let dir = base.join(CACHE_DIR_NAME);
+    create_private_dir(&dir)?;
+    let dir = dir.join(name);
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+// ユーザーのキャッシュディレクトリ。Windowsは%LOCALAPPDATA%、それ以外は$XDG_CACHE_HOMEか~/.cache
+fn user_cache_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    }
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+}
+
+/// 実行したユーザーだけが読み書きできるディレクトリを作成する。既にある場合は所有者と権限を確認する
+pub fn create_private_dir(dir: &Path) -> Result<(), String> {
+    match create_dir(dir) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(format!("{} [dir:{}]", e, dir.display())),
+    }
+    check_private_dir(dir)
+}
+
+#[cfg(unix)]
+fn create_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().mode(0o700).create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir(dir)
+}
+
+// シンボリックリンクや他のユーザーが所有するディレクトリは使わない。グループや他のユーザーの権限は外す
+#[cfg(unix)]
+fn check_private_dir(dir: &Path) -> Result<(), String> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let metadata =
+        fs::symlink_metadata(dir).map_err(|e| format!("{} [dir:{}]", e, dir.display()))?;
+    if !metadata.is_dir() {
+        return Err(format!("{} is not a directory.", dir.display()));
+    }
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(format!(
+            "{} is owned by another user. Please remove it and run again.",
+            dir.display()
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .map_err(|e| format!("{} [dir:{}]", e, dir.display()))?;
+    }
+    Ok(())
+}
+
+// Windowsのユーザーのキャッシュディレクトリは他のユーザーが書き込めないので、ディレクトリであることだけを確認する
+#[cfg(not(unix))]
+fn check_private_dir(dir: &Path) -> Result<(), String> {
+    let metadata =
+        fs::symlink_metadata(dir).map_err(|e| format!("{} [dir:{}]", e, dir.display()))?;
+    if !metadata.is_dir() {
+        return Err(format!("{} is not a directory.", dir.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::private_cache::create_private_dir;
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_private_dir() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let base = std::env::temp_dir().join("hayabusa-private-cache-test");
+        fs::remove_dir_all(&base).ok();
+        fs::create_dir_all(&base).unwrap();
+
+        // 新しく作成したディレクトリは所有者だけが読み書きできる
+        let dir = base.join("rules");
+        create_private_dir(&dir).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // 既にあるディレクトリの権限は所有者だけに戻す
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        create_private_dir(&dir).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // シンボリックリンクやファイルは使わない
+        let link = base.join("link");
+        symlink(&dir, &link).unwrap();
+        assert!(create_private_dir(&link).is_err());
+        let file = base.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(create_private_dir(&file).is_err());
+
+        fs::remove_dir_all(&base).ok();
+    }
+}
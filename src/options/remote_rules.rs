@@ -0,0 +1,206 @@
+use crate::detections::configs;
+use crate::detections::print::AlertMessage;
+use crate::options::private_cache;
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+// キャッシュするダウンロードしたアーカイブのファイル名と展開先のフォルダ名
+const CACHED_ARCHIVE: &str = "rules.tar.gz";
+const EXTRACT_DIR_NAME: &str = "rules";
+
+lazy_static! {
+    /// -rで指定したURLからダウンロードして展開したrulesフォルダ
+    static ref REMOTE_RULES_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// -rでルールのURLが指定されているか
+pub fn is_remote_rules(rules: &str) -> bool {
+    let rules = rules.to_lowercase();
+    rules.starts_with("https://") || rules.starts_with("http://")
+}
+
+/// ダウンロードして展開したrulesフォルダ。ダウンロードしていない場合はNone
+pub fn remote_rules_dir() -> Option<PathBuf> {
+    REMOTE_RULES_DIR.read().unwrap().clone()
+}
+
+/**
+* -rで指定したURLからtar.gzのルールセットをダウンロードし、SHA256で検証してからユーザーのキャッシュディレクトリに展開する。
+* 永続的なルールのチェックアウトを持たないクラウドの一時的な解析環境で使うため。
+* ハッシュ値は--rules-sha256か、URLに.sha256を付けたファイルから取得する。ダウンロード済みのアーカイブは検証し直してから展開し直す。
+*/
+pub fn download_rules(url: &str) -> Result<PathBuf, String> {
+    if !url.to_lowercase().starts_with("https://") {
+        return Err("Rules can only be downloaded over HTTPS.".to_string());
+    }
+    let client = https_client()?;
+    let expected = match configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("rules-sha256")
+    {
+        Some(hash) => parse_checksum(hash).ok_or_else(|| {
+            format!(
+                "Invalid --rules-sha256: {}. Please specify a SHA256 hash.",
+                hash
+            )
+        })?,
+        None => {
+            let checksum = fetch_checksum(&client, url)?;
+            // 同じサーバーのハッシュ値ではサーバーが改ざんされた場合に検知できないので、ハッシュ値を指定するように促す
+            AlertMessage::warn(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!(
+                    "The rules were verified with {}.sha256 from the same server. Please specify --rules-sha256 {} to pin the rules.",
+                    url, checksum
+                ),
+            )
+            .ok();
+            checksum
+        }
+    };
+    let cache_dir =
+        private_cache::private_cache_dir("remote-rules")?.join(&expected[..16.min(expected.len())]);
+    private_cache::create_private_dir(&cache_dir)?;
+    let archive_path = cache_dir.join(CACHED_ARCHIVE);
+    // キャッシュしたアーカイブはハッシュ値が一致する場合だけ使う
+    let archive = match fs::read(&archive_path) {
+        Ok(archive) if hex::encode(Sha256::digest(&archive)) == expected => archive,
+        _ => {
+            let archive = fetch(&client, url)?;
+            let digest = hex::encode(Sha256::digest(&archive));
+            if digest != expected {
+                return Err(format!(
+                    "The SHA256 hash of the downloaded rules does not match. (Expected: {}, Actual: {})",
+                    expected, digest
+                ));
+            }
+            fs::write(&archive_path, &archive)
+                .map_err(|e| format!("{} [file:{}]", e, archive_path.display()))?;
+            archive
+        }
+    };
+    // 展開したファイルは検証できないので、検証したアーカイブから毎回展開し直す
+    let extract_dir = cache_dir.join(EXTRACT_DIR_NAME);
+    if extract_dir.exists() {
+        fs::remove_dir_all(&extract_dir)
+            .map_err(|e| format!("{} [dir:{}]", e, extract_dir.display()))?;
+    }
+    tar::Archive::new(GzDecoder::new(archive.as_slice()))
+        .unpack(&extract_dir)
+        .map_err(|e| {
+            format!(
+                "Failed to extract the rules. {} [dir:{}]",
+                e,
+                extract_dir.display()
+            )
+        })?;
+    let rules_dir = rules_root(&extract_dir).map_err(|e| e.to_string())?;
+    *REMOTE_RULES_DIR.write().unwrap() = Some(rules_dir.to_path_buf());
+    Ok(rules_dir)
+}
+
+// HTTPSでないURLへのリダイレクトには従わないクライアント
+fn https_client() -> Result<reqwest::blocking::Client, String> {
+    let policy = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.url().scheme() != "https" {
+            attempt.error("Rules can only be downloaded over HTTPS.")
+        } else if attempt.previous().len() > 10 {
+            attempt.error("Too many redirects.")
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::blocking::Client::builder()
+        .redirect(policy)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn fetch(client: &reqwest::blocking::Client, url: &str) -> Result<Vec<u8>, String> {
+    let res = client
+        .get(url)
+        .send()
+        .map_err(|e| format!("Failed to download {}. {}", url, e))?;
+    if !res.status().is_success() {
+        return Err(format!(
+            "Failed to download {}. HTTP status: {}",
+            url,
+            res.status()
+        ));
+    }
+    res.bytes()
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| format!("Failed to download {}. {}", url, e))
+}
+
+/// URLに.sha256を付けたファイルからハッシュ値を取得する。sha256sumの出力形式にも対応する
+fn fetch_checksum(client: &reqwest::blocking::Client, url: &str) -> Result<String, String> {
+    let checksum_url = format!("{}.sha256", url);
+    let body = fetch(client, &checksum_url).map_err(|e| {
+        format!(
+            "{} Please specify the SHA256 hash of the rules with --rules-sha256.",
+            e
+        )
+    })?;
+    parse_checksum(&String::from_utf8_lossy(&body))
+        .ok_or_else(|| format!("Invalid SHA256 hash in {}.", checksum_url))
+}
+
+fn parse_checksum(body: &str) -> Option<String> {
+    let hash = body.split_whitespace().next()?.to_lowercase();
+    if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hash)
+    } else {
+        None
+    }
+}
+
+/// アーカイブのトップに1つのフォルダだけがある場合(hayabusa-rules-main/など)はそのフォルダをrulesフォルダにする
+fn rules_root(extract_dir: &Path) -> std::io::Result<PathBuf> {
+    let entries: Vec<PathBuf> = fs::read_dir(extract_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    match entries.as_slice() {
+        [dir] if dir.is_dir() => Ok(dir.to_path_buf()),
+        _ => Ok(extract_dir.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::remote_rules::{is_remote_rules, parse_checksum, rules_root};
+    use std::fs;
+
+    #[test]
+    fn test_parse_checksum() {
+        let hash = "a3c2e1f4b5d6a7c8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2";
+        assert_eq!(parse_checksum(hash), Some(hash.to_string()));
+        assert_eq!(
+            parse_checksum(&format!("{}  rules.tar.gz\n", hash.to_uppercase())),
+            Some(hash.to_string())
+        );
+        assert_eq!(parse_checksum("<html>Not Found</html>"), None);
+
+        assert!(is_remote_rules("https://example.com/rules.tar.gz"));
+        assert!(!is_remote_rules("./rules"));
+    }
+
+    #[test]
+    fn test_rules_root() {
+        let extract_dir = std::env::temp_dir().join("hayabusa-remote-rules-test");
+        fs::remove_dir_all(&extract_dir).ok();
+        fs::create_dir_all(extract_dir.join("hayabusa-rules-main")).unwrap();
+        assert_eq!(
+            rules_root(&extract_dir).unwrap(),
+            extract_dir.join("hayabusa-rules-main")
+        );
+        fs::remove_dir_all(&extract_dir).ok();
+    }
+}
@@ -0,0 +1,270 @@
+use crate::detections::rule::{get_detection_keys, RuleNode};
+use crate::detections::utils;
+use evtx::{EvtxParser, ParserSettings};
+use serde_json::{Map, Number, Value};
+use std::fs;
+use std::path::Path;
+use yaml_rust::Yaml;
+
+/// サンプルイベント1件分の検証結果
+#[derive(Debug, PartialEq)]
+pub enum SampleResult {
+    Pass,
+    /// positiveのサンプルで検知しなかった、またはnegativeのサンプルで検知した
+    Fail(String),
+    /// サンプルを読み込めなかった
+    Error(String),
+}
+
+/// 1ルール分の検証結果
+#[derive(Debug)]
+pub struct RuleTestResult {
+    pub rulepath: String,
+    pub results: Vec<SampleResult>,
+}
+
+impl RuleTestResult {
+    pub fn is_pass(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| *result == SampleResult::Pass)
+    }
+}
+
+/**
+* ルールのsamplesフィールドに書かれたサンプルイベントでルールを検証する。
+* positiveのサンプルでは検知すること、negativeのサンプルでは検知しないことを確認する
+*
+* samples:
+*     positive:
+*         - Event: {System: {EventID: 4624, Channel: Security}, EventData: {LogonType: 3}}
+*         - ./samples/4624_type3.json
+*     negative:
+*         - ./samples/4624_type2.evtx
+*/
+pub struct RuleTester {}
+
+impl RuleTester {
+    /// samplesフィールドを持つルールだけを検証して結果を返す
+    pub fn run(rules: Vec<RuleNode>) -> Vec<RuleTestResult> {
+        rules
+            .into_iter()
+            .filter(|rule| !rule.yaml["samples"].is_badvalue())
+            .map(RuleTester::test_rule)
+            .collect()
+    }
+
+    pub fn test_rule(mut rule: RuleNode) -> RuleTestResult {
+        let keys = get_detection_keys(&rule);
+        let mut results = vec![];
+        for (kind, expected) in [("positive", true), ("negative", false)] {
+            let samples = match rule.yaml["samples"][kind].as_vec() {
+                Some(samples) => samples.to_vec(),
+                None => continue,
+            };
+            for (idx, sample) in samples.iter().enumerate() {
+                let events = match RuleTester::load_sample(sample, &rule.rulepath) {
+                    Ok(events) => events,
+                    Err(err) => {
+                        results.push(SampleResult::Error(format!(
+                            "Failed to load {} sample {}. {}",
+                            kind,
+                            idx + 1,
+                            err
+                        )));
+                        continue;
+                    }
+                };
+                // サンプルファイルに複数のイベントが含まれる場合はどれか1件で検知すれば検知したとみなす
+                let matched = events.into_iter().any(|event| {
+                    let record = utils::create_rec_info(event, rule.rulepath.to_string(), &keys);
+                    rule.select(&record)
+                });
+                if matched == expected {
+                    results.push(SampleResult::Pass);
+                } else if expected {
+                    results.push(SampleResult::Fail(format!(
+                        "The rule did not match positive sample {}.",
+                        idx + 1
+                    )));
+                } else {
+                    results.push(SampleResult::Fail(format!(
+                        "The rule matched negative sample {}.",
+                        idx + 1
+                    )));
+                }
+            }
+        }
+        RuleTestResult {
+            rulepath: rule.rulepath,
+            results,
+        }
+    }
+
+    /// 検証結果を出力し、全て成功した場合はtrueを返す
+    pub fn print(results: &[RuleTestResult]) -> bool {
+        println!("Rule Test Results");
+        let mut failed_rules = 0;
+        let mut sample_count = 0;
+        for result in results.iter() {
+            sample_count += result.results.len();
+            if result.is_pass() {
+                tracing::info!("Passed rule: {}", result.rulepath);
+                continue;
+            }
+            failed_rules += 1;
+            for sample_result in result.results.iter() {
+                match sample_result {
+                    SampleResult::Pass => {}
+                    SampleResult::Fail(msg) => println!("[FAIL] {} {}", result.rulepath, msg),
+                    SampleResult::Error(msg) => println!("[ERROR] {} {}", result.rulepath, msg),
+                }
+            }
+        }
+        println!();
+        println!(
+            "Tested rules: {} Passed: {} Failed: {} Samples: {}",
+            results.len(),
+            results.len() - failed_rules,
+            failed_rules,
+            sample_count
+        );
+        println!();
+        failed_rules == 0
+    }
+
+    // サンプルはイベントを直接書くか、ルールファイルからの相対パスでjson, jsonl, evtxファイルを指定する
+    fn load_sample(sample: &Yaml, rulepath: &str) -> Result<Vec<Value>, String> {
+        let path = match sample.as_str() {
+            Some(path) => path,
+            None => return Ok(vec![yaml_to_json(sample)]),
+        };
+        let path = Path::new(rulepath)
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(path);
+        if path.extension().and_then(|ext| ext.to_str()) == Some("evtx") {
+            let parser = EvtxParser::from_path(&path).map_err(|e| e.to_string())?;
+            let parse_config = ParserSettings::default().separate_json_attributes(true);
+            let mut parser = parser.with_configuration(parse_config);
+            return parser
+                .records_json_value()
+                .map(|record| record.map(|record| record.data).map_err(|e| e.to_string()))
+                .collect();
+        }
+
+        let content = fs::read_to_string(&path).map_err(|e| format!("{} {}", path.display(), e))?;
+        if path.extension().and_then(|ext| ext.to_str()) == Some("jsonl") {
+            return content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+                .collect();
+        }
+        match serde_json::from_str(&content).map_err(|e| e.to_string())? {
+            Value::Array(events) => Ok(events),
+            event => Ok(vec![event]),
+        }
+    }
+}
+
+/// ルールファイルに直接書かれたサンプルイベントをevtxのJSONと同じ形式に変換する
+pub fn yaml_to_json(yaml: &Yaml) -> Value {
+    match yaml {
+        Yaml::Hash(hash) => {
+            let mut map = Map::new();
+            for (key, value) in hash.iter() {
+                let key = match key {
+                    Yaml::String(s) => s.to_string(),
+                    Yaml::Integer(i) => i.to_string(),
+                    Yaml::Real(r) => r.to_string(),
+                    Yaml::Boolean(b) => b.to_string(),
+                    _ => continue,
+                };
+                map.insert(key, yaml_to_json(value));
+            }
+            Value::Object(map)
+        }
+        Yaml::Array(array) => Value::Array(array.iter().map(yaml_to_json).collect()),
+        Yaml::String(s) => Value::String(s.to_string()),
+        Yaml::Integer(i) => Value::Number(Number::from(*i)),
+        Yaml::Real(r) => r
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(r.to_string())),
+        Yaml::Boolean(b) => Value::Bool(*b),
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::rule::create_rule;
+    use crate::options::rule_test::{yaml_to_json, RuleTester, SampleResult};
+    use yaml_rust::YamlLoader;
+
+    fn test_rule(rule_str: &str) -> Vec<SampleResult> {
+        let rule_yaml = YamlLoader::load_from_str(rule_str).unwrap();
+        let mut rule = create_rule("testpath".to_string(), rule_yaml[0].clone());
+        assert!(rule.init().is_ok());
+        RuleTester::test_rule(rule).results
+    }
+
+    #[test]
+    fn test_yaml_to_json() {
+        let yaml = YamlLoader::load_from_str(
+            r#"
+            Event:
+                System:
+                    EventID: 4624
+                    Channel: Security
+                EventData:
+                    Elevated: true
+            "#,
+        )
+        .unwrap();
+        let expected = serde_json::json!({
+            "Event": {
+                "System": {"EventID": 4624, "Channel": "Security"},
+                "EventData": {"Elevated": true}
+            }
+        });
+        assert_eq!(yaml_to_json(&yaml[0]), expected);
+    }
+
+    #[test]
+    fn test_rule_samples() {
+        let rule_str = r#"
+        enabled: true
+        detection:
+            selection:
+                Event.System.Channel: Security
+                Event.System.EventID: 4624
+                Event.EventData.LogonType: 3
+            condition: selection
+        samples:
+            positive:
+                - Event: {System: {EventID: 4624, Channel: Security}, EventData: {LogonType: 3}}
+                - Event: {System: {EventID: 4624, Channel: Security}, EventData: {LogonType: 2}}
+            negative:
+                - Event: {System: {EventID: 4624, Channel: Security}, EventData: {LogonType: 2}}
+                - Event: {System: {EventID: 4624, Channel: Security}, EventData: {LogonType: 3}}
+                - ./not_exist.json
+        "#;
+        let results = test_rule(rule_str);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0], SampleResult::Pass);
+        assert_eq!(
+            results[1],
+            SampleResult::Fail("The rule did not match positive sample 2.".to_string())
+        );
+        assert_eq!(results[2], SampleResult::Pass);
+        assert_eq!(
+            results[3],
+            SampleResult::Fail("The rule matched negative sample 2.".to_string())
+        );
+        assert!(matches!(results[4], SampleResult::Error(_)));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::timeline::metrics::FileMetrics;
+use chrono::{DateTime, Local};
+use git2::Repository;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// 解析したevtxファイル1ファイル分の情報
+#[derive(Debug, Serialize)]
+pub struct RunFileInfo {
+    pub path: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub records: usize,
+}
+
+/**
+* 実行した環境と解析対象を記録するためのメタデータ。Chain of Custodyや再現性の確認に使う
+*/
+#[derive(Debug, Serialize)]
+pub struct RunMetadata {
+    pub hayabusa_version: String,
+    pub rules_commit: Option<String>,
+    pub command_line: Vec<String>,
+    pub start_time: String,
+    pub end_time: String,
+    pub duration_seconds: f64,
+    pub files: Vec<RunFileInfo>,
+    pub total_records: usize,
+    pub error_count: usize,
+}
+
+impl RunMetadata {
+    pub fn new(
+        rules_path: &str,
+        files: &[FileMetrics],
+        start_time: &DateTime<Local>,
+        end_time: &DateTime<Local>,
+        error_count: usize,
+    ) -> RunMetadata {
+        let files: Vec<RunFileInfo> = files
+            .iter()
+            .map(|file| RunFileInfo {
+                path: file.filepath.to_string(),
+                size: file.filesize,
+                sha256: RunMetadata::sha256(&file.filepath).ok(),
+                records: file.total,
+            })
+            .collect();
+        RunMetadata {
+            hayabusa_version: env!("CARGO_PKG_VERSION").to_string(),
+            rules_commit: RunMetadata::rules_commit(rules_path),
+            command_line: std::env::args().collect(),
+            start_time: start_time.to_rfc3339(),
+            end_time: end_time.to_rfc3339(),
+            duration_seconds: end_time
+                .signed_duration_since(*start_time)
+                .num_milliseconds() as f64
+                / 1000.0,
+            total_records: files.iter().map(|file| file.records).sum(),
+            files,
+            error_count,
+        }
+    }
+
+    /// rulesフォルダがgitリポジトリの場合、HEADのコミットハッシュを返す
+    fn rules_commit(rules_path: &str) -> Option<String> {
+        let repo = Repository::discover(Path::new(rules_path)).ok()?;
+        let commit = repo.head().ok()?.peel_to_commit().ok()?;
+        Some(commit.id().to_string())
+    }
+
+    pub fn sha256(filepath: &str) -> io::Result<String> {
+        let mut file = File::open(filepath)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::run_metadata::RunMetadata;
+    use crate::timeline::metrics::FileMetrics;
+    use chrono::{Duration, Local};
+    use std::fs;
+
+    #[test]
+    fn test_sha256() {
+        let path = "./test_files/sha256-test.txt";
+        fs::write(path, "hayabusa").unwrap();
+        let hash = RunMetadata::sha256(path);
+        fs::remove_file(path).ok();
+        assert_eq!(
+            hash.unwrap(),
+            "704920010b8fa885144b4df0dcc3650c74c9cb384e06a8c534f912a1c2f89169"
+        );
+    }
+
+    #[test]
+    fn test_run_metadata() {
+        let start_time = Local::now();
+        let end_time = start_time + Duration::milliseconds(1500);
+        let mut file = FileMetrics::new("./test_files/evtx/test_metadata.evtx");
+        file.total = 10;
+        let metadata = RunMetadata::new("./rules", &[file], &start_time, &end_time, 2);
+        assert_eq!(metadata.duration_seconds, 1.5);
+        assert_eq!(metadata.total_records, 10);
+        assert_eq!(metadata.files[0].sha256, None);
+        assert_eq!(metadata.error_count, 2);
+    }
+}
@@ -0,0 +1,858 @@
+use crate::cloud;
+use crate::linux;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+/// 変換したルールを保存するデフォルトのディレクトリ
+pub const DEFAULT_CONVERTED_RULES_DIR: &str = "./rules/sigma-converted";
+// 変換したルールのdetectionに追加する、チャンネルとイベントIDのselectionの名前
+const LOGSOURCE_SELECTION: &str = "hayabusa_logsource";
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+
+// Sysmonのprocess_creationのフィールドとSecurityの4688のフィールドの対応
+const PROCESS_CREATION_BUILTIN_FIELDS: &[(&str, &str)] = &[
+    ("Image", "NewProcessName"),
+    ("ParentImage", "ParentProcessName"),
+    ("CommandLine", "CommandLine"),
+    ("User", "SubjectUserName"),
+    ("LogonId", "SubjectLogonId"),
+];
+
+// logsourceのcategoryとSysmonのイベントID
+const SYSMON_CATEGORIES: &[(&str, &[i64])] = &[
+    ("process_creation", &[1]),
+    ("network_connection", &[3]),
+    ("sysmon_status", &[4, 16]),
+    ("process_termination", &[5]),
+    ("driver_load", &[6]),
+    ("image_load", &[7]),
+    ("create_remote_thread", &[8]),
+    ("raw_access_thread", &[9]),
+    ("process_access", &[10]),
+    ("file_event", &[11]),
+    ("registry_add", &[12]),
+    ("registry_delete", &[12]),
+    ("registry_set", &[13]),
+    ("registry_rename", &[14]),
+    ("registry_event", &[12, 13, 14]),
+    ("create_stream_hash", &[15]),
+    ("pipe_created", &[17, 18]),
+    ("wmi_event", &[19, 20, 21]),
+    ("dns_query", &[22]),
+    ("file_delete", &[23, 26]),
+    ("clipboard_capture", &[24]),
+    ("process_tampering", &[25]),
+    ("file_block_executable", &[27]),
+    ("file_block_shredding", &[28]),
+    ("sysmon_error", &[255]),
+];
+
+// logsourceのcategoryと、Sysmon以外のチャンネルとイベントID
+const BUILTIN_CATEGORIES: &[(&str, &str, &[i64])] = &[
+    (
+        "ps_module",
+        "Microsoft-Windows-PowerShell/Operational",
+        &[4103],
+    ),
+    (
+        "ps_script",
+        "Microsoft-Windows-PowerShell/Operational",
+        &[4104],
+    ),
+    ("ps_classic_start", "Windows PowerShell", &[400]),
+    ("ps_classic_provider_start", "Windows PowerShell", &[600]),
+];
+
+// product: linuxのlogsourceのcategoryとSysmon for LinuxのイベントID
+const LINUX_SYSMON_CATEGORIES: &[(&str, &[i64])] = &[
+    ("process_creation", &[1]),
+    ("network_connection", &[3]),
+    ("sysmon_status", &[4, 16]),
+    ("process_termination", &[5]),
+    ("raw_access_thread", &[9]),
+    ("file_event", &[11]),
+    ("file_create", &[11]),
+    ("file_delete", &[23]),
+    ("sysmon_error", &[255]),
+];
+
+// product: azureのlogsourceのserviceとチャンネル
+const AZURE_SERVICES: &[(&str, &str)] = &[
+    ("signinlogs", cloud::SIGNIN_CHANNEL),
+    ("auditlogs", cloud::AUDIT_CHANNEL),
+    ("activitylogs", cloud::ACTIVITY_CHANNEL),
+];
+
+// logsourceのserviceとチャンネル
+const SERVICES: &[(&str, &str)] = &[
+    ("security", "Security"),
+    ("system", "System"),
+    ("application", "Application"),
+    ("sysmon", SYSMON_CHANNEL),
+    ("powershell", "Microsoft-Windows-PowerShell/Operational"),
+    ("powershell-classic", "Windows PowerShell"),
+    (
+        "taskscheduler",
+        "Microsoft-Windows-TaskScheduler/Operational",
+    ),
+    ("wmi", "Microsoft-Windows-WMI-Activity/Operational"),
+    (
+        "windefend",
+        "Microsoft-Windows-Windows Defender/Operational",
+    ),
+    ("dns-server", "DNS Server"),
+    (
+        "driver-framework",
+        "Microsoft-Windows-DriverFrameworks-UserMode/Operational",
+    ),
+    (
+        "firewall-as",
+        "Microsoft-Windows-Windows Firewall With Advanced Security/Firewall",
+    ),
+    ("bits-client", "Microsoft-Windows-Bits-Client/Operational"),
+    (
+        "codeintegrity-operational",
+        "Microsoft-Windows-CodeIntegrity/Operational",
+    ),
+    ("ntlm", "Microsoft-Windows-NTLM/Operational"),
+    ("msexchange-management", "MSExchange Management"),
+    ("printservice-admin", "Microsoft-Windows-PrintService/Admin"),
+    (
+        "printservice-operational",
+        "Microsoft-Windows-PrintService/Operational",
+    ),
+    (
+        "terminalservices-localsessionmanager",
+        "Microsoft-Windows-TerminalServices-LocalSessionManager/Operational",
+    ),
+    ("openssh", "OpenSSH/Operational"),
+    ("smbclient-security", "Microsoft-Windows-SmbClient/Security"),
+];
+
+lazy_static! {
+    // 1 of selection*、all of themなどの条件
+    static ref OF_REGEX: Regex = Regex::new(r"\b(1|all) of ([\w*]+)").unwrap();
+}
+
+/// 変換したルールの種類。Sysmonのイベントを対象にするルールと、Windows標準のイベントを対象にするルールと、Linuxとクラウドのルール
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleVariant {
+    Sysmon,
+    Builtin,
+    Linux,
+    Cloud,
+}
+
+impl RuleVariant {
+    /// 変換したルールを保存するサブディレクトリ名
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            RuleVariant::Sysmon => "sysmon",
+            RuleVariant::Builtin => "builtin",
+            RuleVariant::Linux => "linux",
+            RuleVariant::Cloud => "cloud",
+        }
+    }
+}
+
+/// logsourceに対応するチャンネル、イベントID、フィールド名の対応
+struct LogSourceMapping {
+    variant: RuleVariant,
+    channel: &'static str,
+    event_ids: &'static [i64],
+    // Noneの場合はフィールド名を変換しない
+    fields: Option<&'static [(&'static str, &'static str)]>,
+}
+
+/// 変換の結果
+#[derive(Debug, Default)]
+pub struct ConvertResult {
+    pub converted: usize,
+    pub written: usize,
+    // 変換できなかったルールのファイルパスと理由
+    pub skipped: Vec<(String, String)>,
+}
+
+impl ConvertResult {
+    pub fn print(&self) {
+        println!("Converted rules: {}", self.converted);
+        println!("Written rule files: {}", self.written);
+        println!("Skipped rules: {}", self.skipped.len());
+        for (path, reason) in self.skipped.iter() {
+            tracing::info!("Skipped {}: {}", path, reason);
+        }
+    }
+}
+
+/**
+* SigmaHQのルールを、hayabusa-rulesと同じようにlogsourceをチャンネルとイベントIDの条件に、
+* process_creationのルールはSecurityの4688用にフィールド名も変換して、hayabusaのルールにする。
+* Sysmonのルールは出力先のsysmon、Linuxのルールはlinux、AzureとMicrosoft 365のルールはcloud、それ以外はbuiltinのディレクトリに元のディレクトリ構成のまま保存する。
+*/
+pub struct SigmaConverter {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+impl SigmaConverter {
+    pub fn new(input: &str, output: Option<&str>) -> SigmaConverter {
+        SigmaConverter {
+            input: PathBuf::from(input),
+            output: PathBuf::from(output.unwrap_or(DEFAULT_CONVERTED_RULES_DIR)),
+        }
+    }
+
+    pub fn run(&self) -> Result<ConvertResult, String> {
+        if !self.input.exists() {
+            return Err(format!("{} does not exist.", self.input.display()));
+        }
+        let mut result = ConvertResult::default();
+        for path in collect_rule_files(&self.input).map_err(|e| e.to_string())? {
+            let display_path = path.display().to_string();
+            let docs = match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| YamlLoader::load_from_str(&s).map_err(|e| e.to_string()))
+            {
+                Ok(docs) => docs,
+                Err(err) => {
+                    result.skipped.push((display_path, err));
+                    continue;
+                }
+            };
+            let mut outputs: Vec<(RuleVariant, String)> = vec![];
+            for doc in docs.iter() {
+                match convert_rule(doc) {
+                    Ok(rules) => {
+                        result.converted += 1;
+                        for (variant, rule) in rules {
+                            let yaml = emit_yaml(&rule)?;
+                            match outputs.iter_mut().find(|(v, _)| *v == variant) {
+                                Some((_, contents)) => contents.push_str(&format!("\n{}", yaml)),
+                                None => outputs.push((variant, yaml)),
+                            }
+                        }
+                    }
+                    Err(err) => result.skipped.push((display_path.to_string(), err)),
+                }
+            }
+            let relative = path.strip_prefix(&self.input).unwrap_or(&path);
+            let relative = if relative.as_os_str().is_empty() {
+                Path::new(path.file_name().unwrap_or_default())
+            } else {
+                relative
+            };
+            for (variant, contents) in outputs {
+                let out_path = self.output.join(variant.dir_name()).join(relative);
+                if let Some(parent) = out_path.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("{} [path:{}]", e, parent.display()))?;
+                }
+                fs::write(&out_path, format!("{}\n", contents))
+                    .map_err(|e| format!("{} [path:{}]", e, out_path.display()))?;
+                result.written += 1;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// ディレクトリ内のymlファイルを再帰的に集める。ファイルを指定した場合はそのファイルを返す
+fn collect_rule_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            if entry.file_name() != Some(OsStr::new(".git")) {
+                files.extend(collect_rule_files(&entry)?);
+            }
+        } else if entry.extension() == Some(OsStr::new("yml")) {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+/**
+* Sigmaのルールをhayabusaのルールに変換する。
+* detectionにlogsourceに対応するチャンネルとイベントIDのselectionを追加し、conditionで元の条件とandで結ぶ。
+* hayabusaが対応していない1 of selection*のような条件は、selectionを列挙した条件に展開する。
+*/
+pub fn convert_rule(rule: &Yaml) -> Result<Vec<(RuleVariant, Yaml)>, String> {
+    let rule_hash = rule
+        .as_hash()
+        .ok_or_else(|| "The rule is not a YAML mapping.".to_string())?;
+    let detection = rule["detection"]
+        .as_hash()
+        .ok_or_else(|| "The rule does not have a detection.".to_string())?;
+    if is_converted(rule) {
+        return Err("The rule is already converted.".to_string());
+    }
+    let mut converted = vec![];
+    for mapping in logsource_mappings(&rule["logsource"])? {
+        if let Some(fields) = mapping.fields {
+            // 変換先のイベントにないフィールドを使うルールは変換しない
+            if !detection_fields(detection)
+                .iter()
+                .all(|field| fields.iter().any(|(from, _)| from == field))
+            {
+                continue;
+            }
+        }
+        let mut new_rule = Hash::new();
+        for (key, value) in rule_hash.iter() {
+            let new_value = match key.as_str() {
+                Some("detection") => convert_detection(detection, &mapping)?,
+                Some("id") if mapping.fields.is_some() => {
+                    Yaml::String(derived_id(value.as_str().unwrap_or_default()))
+                }
+                _ => value.clone(),
+            };
+            new_rule.insert(key.clone(), new_value);
+        }
+        new_rule.insert(
+            Yaml::String("ruletype".to_string()),
+            Yaml::String("Sigma".to_string()),
+        );
+        converted.push((mapping.variant, Yaml::Hash(new_rule)));
+    }
+    if converted.is_empty() {
+        return Err("The rule uses fields that do not exist in the mapped events.".to_string());
+    }
+    Ok(converted)
+}
+
+/// detectionでChannelを指定しているルールは、変換済みのルールかhayabusaのルールとして扱う
+pub fn is_converted(rule: &Yaml) -> bool {
+    rule["detection"].as_hash().map_or(false, |detection| {
+        detection_fields(detection)
+            .iter()
+            .any(|field| field == "Channel")
+    })
+}
+
+fn logsource_mappings(logsource: &Yaml) -> Result<Vec<LogSourceMapping>, String> {
+    let product = logsource["product"].as_str().unwrap_or_default();
+    if product == "linux" {
+        return linux_logsource_mappings(logsource);
+    }
+    if product == "azure" || product == "m365" {
+        return cloud_logsource_mappings(logsource);
+    }
+    if product != "windows" {
+        return Err(format!("Unsupported logsource product: {}", product));
+    }
+    if let Some(category) = logsource["category"].as_str() {
+        if let Some((_, event_ids)) = SYSMON_CATEGORIES.iter().find(|(c, _)| *c == category) {
+            let mut mappings = vec![LogSourceMapping {
+                variant: RuleVariant::Sysmon,
+                channel: SYSMON_CHANNEL,
+                event_ids,
+                fields: None,
+            }];
+            if category == "process_creation" {
+                mappings.push(LogSourceMapping {
+                    variant: RuleVariant::Builtin,
+                    channel: "Security",
+                    event_ids: &[4688],
+                    fields: Some(PROCESS_CREATION_BUILTIN_FIELDS),
+                });
+            }
+            return Ok(mappings);
+        }
+        if let Some((_, channel, event_ids)) =
+            BUILTIN_CATEGORIES.iter().find(|(c, _, _)| *c == category)
+        {
+            return Ok(vec![LogSourceMapping {
+                variant: RuleVariant::Builtin,
+                channel,
+                event_ids,
+                fields: None,
+            }]);
+        }
+        return Err(format!("Unsupported logsource category: {}", category));
+    }
+    let service = logsource["service"].as_str().unwrap_or_default();
+    match SERVICES.iter().find(|(s, _)| *s == service) {
+        Some((_, channel)) => Ok(vec![LogSourceMapping {
+            variant: if *channel == SYSMON_CHANNEL {
+                RuleVariant::Sysmon
+            } else {
+                RuleVariant::Builtin
+            },
+            channel,
+            event_ids: &[],
+            fields: None,
+        }]),
+        None => Err(format!("Unsupported logsource service: {}", service)),
+    }
+}
+
+// Linuxのルールは、categoryをSysmon for LinuxのイベントIDに、service: auditdをauditdのレコードにする
+fn linux_logsource_mappings(logsource: &Yaml) -> Result<Vec<LogSourceMapping>, String> {
+    if let Some(category) = logsource["category"].as_str() {
+        return match LINUX_SYSMON_CATEGORIES.iter().find(|(c, _)| *c == category) {
+            Some((_, event_ids)) => Ok(vec![LogSourceMapping {
+                variant: RuleVariant::Linux,
+                channel: linux::SYSMON_CHANNEL,
+                event_ids,
+                fields: None,
+            }]),
+            None => Err(format!("Unsupported logsource category: {}", category)),
+        };
+    }
+    match logsource["service"].as_str().unwrap_or_default() {
+        "auditd" => Ok(vec![LogSourceMapping {
+            variant: RuleVariant::Linux,
+            channel: linux::AUDITD_CHANNEL,
+            event_ids: &[],
+            fields: None,
+        }]),
+        service => Err(format!("Unsupported logsource service: {}", service)),
+    }
+}
+
+// Azureのルールはserviceに対応するチャンネルに、Microsoft 365のルールは統合監査ログのチャンネルにする
+fn cloud_logsource_mappings(logsource: &Yaml) -> Result<Vec<LogSourceMapping>, String> {
+    let channel = if logsource["product"].as_str() == Some("m365") {
+        cloud::UAL_CHANNEL
+    } else {
+        let service = logsource["service"].as_str().unwrap_or_default();
+        match AZURE_SERVICES.iter().find(|(s, _)| *s == service) {
+            Some((_, channel)) => channel,
+            None => return Err(format!("Unsupported logsource service: {}", service)),
+        }
+    };
+    Ok(vec![LogSourceMapping {
+        variant: RuleVariant::Cloud,
+        channel,
+        event_ids: &[],
+        fields: None,
+    }])
+}
+
+fn convert_detection(detection: &Hash, mapping: &LogSourceMapping) -> Result<Yaml, String> {
+    let names: Vec<String> = detection
+        .keys()
+        .filter_map(|key| key.as_str())
+        .filter(|key| *key != "condition" && *key != "timeframe")
+        .map(|key| key.to_string())
+        .collect();
+    let mut logsource = Hash::new();
+    logsource.insert(
+        Yaml::String("Channel".to_string()),
+        Yaml::String(mapping.channel.to_string()),
+    );
+    match mapping.event_ids {
+        [] => {}
+        [event_id] => {
+            logsource.insert(
+                Yaml::String("EventID".to_string()),
+                Yaml::Integer(*event_id),
+            );
+        }
+        event_ids => {
+            logsource.insert(
+                Yaml::String("EventID".to_string()),
+                Yaml::Array(event_ids.iter().map(|id| Yaml::Integer(*id)).collect()),
+            );
+        }
+    }
+    let mut new_detection = Hash::new();
+    new_detection.insert(
+        Yaml::String(LOGSOURCE_SELECTION.to_string()),
+        Yaml::Hash(logsource),
+    );
+    for (key, value) in detection.iter() {
+        let new_value = match key.as_str() {
+            Some("condition") => {
+                let conditions = match value {
+                    Yaml::String(condition) => vec![condition.as_str()],
+                    Yaml::Array(conditions) => {
+                        conditions.iter().filter_map(|c| c.as_str()).collect()
+                    }
+                    _ => return Err("The condition is not a string.".to_string()),
+                };
+                Yaml::String(convert_condition(&conditions, &names, mapping.fields)?)
+            }
+            Some("timeframe") => value.clone(),
+            _ if mapping.variant == RuleVariant::Cloud => map_fields(value, &nested_field),
+            _ => rename_fields(value, mapping.fields),
+        };
+        new_detection.insert(key.clone(), new_value);
+    }
+    Ok(Yaml::Hash(new_detection))
+}
+
+/// conditionの前にチャンネルとイベントIDの条件を追加する。複数のconditionはorで結ぶ
+/// 集計はルール全体に1つしか書けないので、集計を含むconditionのリストは変換しない
+fn convert_condition(
+    conditions: &[&str],
+    names: &[String],
+    fields: Option<&[(&str, &str)]>,
+) -> Result<String, String> {
+    let mut aggregation = None;
+    let mut exprs: Vec<String> = vec![];
+    for condition in conditions.iter() {
+        let (expr, agg) = match condition.split_once('|') {
+            Some((expr, agg)) => (expr, Some(agg.trim())),
+            None => (*condition, None),
+        };
+        if agg.is_some() {
+            if conditions.len() > 1 {
+                return Err(
+                    "A condition list that contains an aggregation is not supported.".to_string(),
+                );
+            }
+            aggregation = agg;
+        }
+        exprs.push(expand_of(expr.trim(), names));
+    }
+    let expr = if exprs.len() == 1 {
+        exprs[0].to_string()
+    } else {
+        exprs
+            .iter()
+            .map(|expr| format!("({})", expr))
+            .collect::<Vec<String>>()
+            .join(" or ")
+    };
+    let mut condition = format!("{} and ({})", LOGSOURCE_SELECTION, expr);
+    if let Some(agg) = aggregation {
+        let mut agg = agg.to_string();
+        for (from, to) in fields.unwrap_or_default() {
+            agg = Regex::new(&format!(r"\b{}\b", from))
+                .unwrap()
+                .replace_all(&agg, *to)
+                .to_string();
+        }
+        condition = format!("{} | {}", condition, agg);
+    }
+    Ok(condition)
+}
+
+/// 1 of selection*、all of themを、selectionをorかandで列挙した条件にする
+fn expand_of(expr: &str, names: &[String]) -> String {
+    OF_REGEX
+        .replace_all(expr, |caps: &Captures| {
+            let target = &caps[2];
+            let targets: Vec<&str> = names
+                .iter()
+                .filter(|name| match target {
+                    "them" => true,
+                    _ if target.ends_with('*') => name.starts_with(target.trim_end_matches('*')),
+                    _ => *name == target,
+                })
+                .map(|name| name.as_str())
+                .collect();
+            let op = if &caps[1] == "all" { " and " } else { " or " };
+            format!("({})", targets.join(op))
+        })
+        .to_string()
+}
+
+/// selectionで使われているフィールド名。修飾子(|endswithなど)は除く
+fn detection_fields(detection: &Hash) -> Vec<String> {
+    let mut fields = vec![];
+    for (key, value) in detection.iter() {
+        if matches!(key.as_str(), Some("condition") | Some("timeframe")) {
+            continue;
+        }
+        collect_fields(value, &mut fields);
+    }
+    fields
+}
+
+fn collect_fields(value: &Yaml, fields: &mut Vec<String>) {
+    match value {
+        Yaml::Hash(hash) => {
+            for key in hash.keys().filter_map(|key| key.as_str()) {
+                let field = key.split('|').next().unwrap_or_default().to_string();
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+        }
+        Yaml::Array(values) => values.iter().for_each(|v| collect_fields(v, fields)),
+        _ => {}
+    }
+}
+
+fn rename_fields(value: &Yaml, fields: Option<&[(&str, &str)]>) -> Yaml {
+    match fields {
+        Some(fields) => map_fields(value, &|field: &str| {
+            fields
+                .iter()
+                .find(|(from, _)| *from == field)
+                .map_or(field, |(_, to)| *to)
+                .to_string()
+        }),
+        None => value.clone(),
+    }
+}
+
+/// クラウドのルールのproperties.messageのようなフィールドは、EventDataの中のオブジェクトのフィールドとして参照する
+fn nested_field(field: &str) -> String {
+    if field.contains('.') {
+        format!("Event.EventData.{}", field)
+    } else {
+        field.to_string()
+    }
+}
+
+/// selectionのフィールド名を変換する。修飾子(|endswithなど)はそのまま残す
+fn map_fields(value: &Yaml, rename: &dyn Fn(&str) -> String) -> Yaml {
+    match value {
+        Yaml::Hash(hash) => Yaml::Hash(
+            hash.iter()
+                .map(|(key, value)| {
+                    let key = match key.as_str() {
+                        Some(key) => {
+                            let (field, modifiers) = match key.split_once('|') {
+                                Some((field, modifiers)) => (field, format!("|{}", modifiers)),
+                                None => (key, String::default()),
+                            };
+                            Yaml::String(format!("{}{}", rename(field), modifiers))
+                        }
+                        None => key.clone(),
+                    };
+                    (key, value.clone())
+                })
+                .collect(),
+        ),
+        Yaml::Array(values) => Yaml::Array(
+            values
+                .iter()
+                .map(|value| map_fields(value, rename))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// フィールド名を変換したルールのid。元のidから決まるUUIDにする
+fn derived_id(id: &str) -> String {
+    let hash = hex::encode(Sha256::digest(format!("{}-builtin", id).as_bytes()));
+    format!(
+        "{}-{}-5{}-{}-{}",
+        &hash[..8],
+        &hash[8..12],
+        &hash[13..16],
+        &hash[16..20],
+        &hash[20..32]
+    )
+}
+
+fn emit_yaml(rule: &Yaml) -> Result<String, String> {
+    let mut out = String::new();
+    YamlEmitter::new(&mut out)
+        .dump(rule)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::sigma_convert::{convert_rule, expand_of, RuleVariant};
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_expand_of() {
+        let names = vec![
+            "selection_img".to_string(),
+            "selection_cli".to_string(),
+            "filter".to_string(),
+        ];
+        assert_eq!(
+            expand_of("all of selection_* and not filter", &names),
+            "(selection_img and selection_cli) and not filter"
+        );
+        assert_eq!(
+            expand_of("1 of them", &names),
+            "(selection_img or selection_cli or filter)"
+        );
+    }
+
+    #[test]
+    fn test_convert_rule() {
+        let rule = YamlLoader::load_from_str(
+            r#"
+title: Suspicious Certutil Download
+id: 19b08b1c-861d-4e75-a1ef-ea0c1baf202b
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection_img:
+        Image|endswith: '\certutil.exe'
+    selection_cli:
+        CommandLine|contains: 'urlcache'
+    condition: all of selection_*
+level: high
+"#,
+        )
+        .unwrap()
+        .remove(0);
+        let converted = convert_rule(&rule).unwrap();
+        assert_eq!(converted.len(), 2);
+
+        let (variant, sysmon) = &converted[0];
+        assert_eq!(*variant, RuleVariant::Sysmon);
+        let detection = &sysmon["detection"];
+        assert_eq!(
+            detection["hayabusa_logsource"]["Channel"].as_str(),
+            Some("Microsoft-Windows-Sysmon/Operational")
+        );
+        assert_eq!(detection["hayabusa_logsource"]["EventID"].as_i64(), Some(1));
+        assert_eq!(
+            detection["condition"].as_str(),
+            Some("hayabusa_logsource and ((selection_img and selection_cli))")
+        );
+        assert_eq!(
+            sysmon["id"].as_str(),
+            Some("19b08b1c-861d-4e75-a1ef-ea0c1baf202b")
+        );
+        assert_eq!(sysmon["ruletype"].as_str(), Some("Sigma"));
+
+        let (variant, builtin) = &converted[1];
+        assert_eq!(*variant, RuleVariant::Builtin);
+        let detection = &builtin["detection"];
+        assert_eq!(
+            detection["hayabusa_logsource"]["EventID"].as_i64(),
+            Some(4688)
+        );
+        assert_eq!(
+            detection["selection_img"]["NewProcessName|endswith"].as_str(),
+            Some("\\certutil.exe")
+        );
+        assert_ne!(
+            builtin["id"].as_str(),
+            Some("19b08b1c-861d-4e75-a1ef-ea0c1baf202b")
+        );
+    }
+
+    #[test]
+    fn test_convert_rule_linux() {
+        let rule = YamlLoader::load_from_str(
+            "title: Linux\nlogsource:\n    product: linux\n    category: process_creation\ndetection:\n    selection:\n        Image|endswith: '/curl'\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        let converted = convert_rule(&rule).unwrap();
+        assert_eq!(converted.len(), 1);
+        let (variant, linux) = &converted[0];
+        assert_eq!(*variant, RuleVariant::Linux);
+        assert_eq!(
+            linux["detection"]["hayabusa_logsource"]["Channel"].as_str(),
+            Some("Linux-Sysmon/Operational")
+        );
+        assert_eq!(
+            linux["detection"]["hayabusa_logsource"]["EventID"].as_i64(),
+            Some(1)
+        );
+
+        let rule = YamlLoader::load_from_str(
+            "title: Auditd\nlogsource:\n    product: linux\n    service: auditd\ndetection:\n    selection:\n        type: EXECVE\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        let (_, auditd) = &convert_rule(&rule).unwrap()[0];
+        assert_eq!(
+            auditd["detection"]["hayabusa_logsource"]["Channel"].as_str(),
+            Some("auditd")
+        );
+        assert!(auditd["detection"]["hayabusa_logsource"]["EventID"].is_badvalue());
+    }
+
+    #[test]
+    fn test_convert_rule_cloud() {
+        let rule = YamlLoader::load_from_str(
+            "title: Azure\nlogsource:\n    product: azure\n    service: auditlogs\ndetection:\n    selection:\n        properties.message: Add member to role\n        Category: RoleManagement\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        let (variant, cloud) = &convert_rule(&rule).unwrap()[0];
+        assert_eq!(*variant, RuleVariant::Cloud);
+        let detection = &cloud["detection"];
+        assert_eq!(
+            detection["hayabusa_logsource"]["Channel"].as_str(),
+            Some("AzureAD/AuditLogs")
+        );
+        assert_eq!(
+            detection["selection"]["Event.EventData.properties.message"].as_str(),
+            Some("Add member to role")
+        );
+        assert_eq!(
+            detection["selection"]["Category"].as_str(),
+            Some("RoleManagement")
+        );
+
+        let rule = YamlLoader::load_from_str(
+            "title: M365\nlogsource:\n    product: m365\n    service: exchange\ndetection:\n    selection:\n        Operation: New-InboxRule\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        let (_, m365) = &convert_rule(&rule).unwrap()[0];
+        assert_eq!(
+            m365["detection"]["hayabusa_logsource"]["Channel"].as_str(),
+            Some("M365/UnifiedAuditLog")
+        );
+    }
+
+    #[test]
+    fn test_convert_rule_unsupported() {
+        let rule = YamlLoader::load_from_str(
+            "title: Linux\nlogsource:\n    product: linux\n    service: sshd\ndetection:\n    selection:\n        a: b\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            convert_rule(&rule).unwrap_err(),
+            "Unsupported logsource service: sshd"
+        );
+        let rule = YamlLoader::load_from_str(
+            "title: macOS\nlogsource:\n    product: macos\ndetection:\n    selection:\n        a: b\n    condition: selection\n",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            convert_rule(&rule).unwrap_err(),
+            "Unsupported logsource product: macos"
+        );
+    }
+
+    #[test]
+    fn test_convert_rule_aggregation_list() {
+        // 集計はルール全体に1つしか書けないので、複数のconditionのどれかに集計がある場合は変換しない
+        let rule = YamlLoader::load_from_str(
+            "title: Aggregations\nlogsource:\n    product: windows\n    service: security\ndetection:\n    selection1:\n        EventID: 4625\n    selection2:\n        EventID: 4771\n    condition:\n        - selection1 | count() by IpAddress > 10\n        - selection2 | count() by TargetUserName > 5\n",
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            convert_rule(&rule).unwrap_err(),
+            "A condition list that contains an aggregation is not supported."
+        );
+        let rule = YamlLoader::load_from_str(
+            "title: Aggregation\nlogsource:\n    product: windows\n    service: security\ndetection:\n    selection:\n        EventID: 4625\n    condition: selection | count() by IpAddress > 10\n",
+        )
+        .unwrap()
+        .remove(0);
+        let (_, converted) = &convert_rule(&rule).unwrap()[0];
+        assert!(converted["detection"]["condition"]
+            .as_str()
+            .unwrap()
+            .ends_with("| count() by IpAddress > 10"));
+    }
+}
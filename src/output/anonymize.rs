@@ -0,0 +1,540 @@
+use crate::detections::print::{AlertMessage, DetectInfo};
+use crate::detections::{configs, utils};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex, RegexBuilder};
+use serde_json::Value;
+use std::io::BufWriter;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+// ユーザー名が入るフィールド
+const USER_FIELDS: [&str; 8] = [
+    "TargetUserName",
+    "SubjectUserName",
+    "User",
+    "AccountName",
+    "TargetUser",
+    "SourceUser",
+    "UserName",
+    "SamAccountName",
+];
+// ホスト名が入るフィールド
+const HOST_FIELDS: [&str; 6] = [
+    "WorkstationName",
+    "Workstation",
+    "SourceHostname",
+    "DestinationHostname",
+    "TargetServerName",
+    "ClientName",
+];
+// IPアドレスが入るフィールド
+const IP_FIELDS: [&str; 7] = [
+    "IpAddress",
+    "SourceIp",
+    "DestinationIp",
+    "SourceAddress",
+    "DestAddress",
+    "ClientAddress",
+    "Address",
+];
+// ドメイン名が入るフィールド
+const DOMAIN_FIELDS: [&str; 4] = [
+    "SubjectDomainName",
+    "TargetDomainName",
+    "DomainName",
+    "AccountDomain",
+];
+// 匿名化しないOSの組み込みのアカウント。小文字で比較する
+const WELL_KNOWN_USERS: [&str; 6] = [
+    "-",
+    "system",
+    "localsystem",
+    "local service",
+    "network service",
+    "anonymous logon",
+];
+// 匿名化しない組み込みのアカウントの接頭辞(DWM-1、UMFD-0など)
+const WELL_KNOWN_USER_PREFIXES: [&str; 2] = ["dwm-", "umfd-"];
+// 匿名化しないOSの組み込みのドメイン。小文字で比較する
+const WELL_KNOWN_DOMAINS: [&str; 9] = [
+    "-",
+    "nt authority",
+    "nt service",
+    "builtin",
+    "window manager",
+    "font driver host",
+    "nt virtual machine",
+    "iis apppool",
+    "workgroup",
+];
+// 匿名化しないユーザープロファイルのフォルダ。小文字で比較する
+const WELL_KNOWN_PROFILES: [&str; 4] = ["public", "default", "default user", "all users"];
+
+lazy_static! {
+    pub static ref ANONYMIZE_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("anonymize");
+    /// 検知したレコードから集めた匿名化する値。--anonymize-mapのファイルがある場合は以前の対応を引き継ぐ
+    pub static ref ANONYMIZER: Mutex<Anonymizer> = {
+        let mut anonymizer = Anonymizer::new();
+        if let Some(path) = configs::CONFIG.read().unwrap().args.value_of("anonymize-map") {
+            if Path::new(path).exists() {
+                if let Err(err) = anonymizer.load_mapping(path) {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to load the anonymization mapping file. {}", err),
+                    )
+                    .ok();
+                }
+            }
+        }
+        Mutex::new(anonymizer)
+    };
+    static ref IPV4_REGEX: Regex = Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}\b").unwrap();
+    // C:\Users\<ユーザー名>、/home/<ユーザー名>などのパスのユーザー名
+    static ref PROFILE_PATH_REGEX: Regex =
+        Regex::new(r#"(?i)(?:^|[\\/])(?:users|documents and settings|home)[\\/]([^\\/:*?"<>|]+)"#)
+            .unwrap();
+    // FQDNの形式の文字列
+    static ref FQDN_REGEX: Regex =
+        Regex::new(r"(?i)\b([a-z0-9][a-z0-9-]*)\.((?:[a-z0-9-]+\.)*[a-z0-9-]+)\b").unwrap();
+}
+
+/**
+* --anonymizeが指定されている場合に、検知結果のユーザー名、ホスト名、ドメイン名、内部のIPアドレスをUSER-0001のような仮名に置き換える。
+* 同じ値は出力全体で同じ仮名にし、--anonymize-mapで指定したファイルに対応を保存して後で元に戻せるようにする。
+* 検知結果だけでなく、サマリーや--raw-xmlなどの出力にも同じ仮名を使うため、解析した全てのレコードの値を登録する。
+*/
+#[derive(Debug, Default)]
+pub struct Anonymizer {
+    // 小文字の元の値と(種類, 元の値, 仮名)の対応
+    pseudonyms: HashMap<String, (String, String, String)>,
+    // 種類毎に割り当てた仮名の数
+    counts: HashMap<String, usize>,
+    // 登録したユーザー名とホスト名のいずれかに一致する正規表現
+    names_regex: Option<Regex>,
+}
+
+impl Anonymizer {
+    pub fn new() -> Anonymizer {
+        Anonymizer::default()
+    }
+
+    /// Type,Original,Pseudonymの形式のファイルから以前の実行の対応を読み込む
+    pub fn load_mapping(&mut self, path: &str) -> Result<(), String> {
+        for row in utils::read_csv(path)?.into_iter() {
+            if let [kind, original, pseudonym] = row.as_slice() {
+                let count = pseudonym
+                    .rsplit('-')
+                    .next()
+                    .and_then(|num| num.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let max_count = self.counts.entry(kind.to_string()).or_insert(0);
+                *max_count = (*max_count).max(count);
+                self.pseudonyms.insert(
+                    original.to_lowercase(),
+                    (
+                        kind.to_string(),
+                        original.to_string(),
+                        pseudonym.to_string(),
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// 元の値と仮名の対応をType,Original,Pseudonymの形式で保存する
+    pub fn save_mapping(&self, path: &str) -> Result<(), String> {
+        let mut wtr = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+        let mut rows: Vec<&(String, String, String)> = self.pseudonyms.values().collect();
+        rows.sort_by(|x, y| x.0.cmp(&y.0).then_with(|| x.2.cmp(&y.2)));
+        wtr.write_record(&["Type", "Original", "Pseudonym"])
+            .map_err(|e| e.to_string())?;
+        for (kind, original, pseudonym) in rows {
+            wtr.write_record(&[kind, original, pseudonym])
+                .map_err(|e| e.to_string())?;
+        }
+        wtr.flush().map_err(|e| e.to_string())
+    }
+
+    /// 値に仮名を割り当てて返す。既に割り当てている場合は同じ仮名を返す
+    fn pseudonym(&mut self, kind: &str, value: &str) -> String {
+        if let Some((_, _, pseudonym)) = self.pseudonyms.get(&value.to_lowercase()) {
+            return pseudonym.to_string();
+        }
+        let count = self.counts.entry(kind.to_string()).or_insert(0);
+        *count += 1;
+        let pseudonym = format!("{}-{:04}", kind.to_uppercase(), count);
+        self.pseudonyms.insert(
+            value.to_lowercase(),
+            (kind.to_string(), value.to_string(), pseudonym.to_string()),
+        );
+        // 新しい名前を登録したら正規表現を作り直す
+        if kind != "IP" {
+            self.names_regex = None;
+        }
+        pseudonym
+    }
+
+    /// レコードのユーザー名、ホスト名、ドメイン名、内部のIPアドレスを登録する
+    pub fn register(&mut self, record: &Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        self.register_host(&get("Event.System.Computer"));
+        for field in DOMAIN_FIELDS.iter() {
+            self.register_domain(&get(field));
+        }
+        for field in USER_FIELDS.iter() {
+            // DOMAIN\userやuser@domainの形式の場合はドメイン名も登録する
+            let user = get(field);
+            let user = match user.rsplit_once('\\') {
+                Some((domain, user)) => {
+                    self.register_domain(domain);
+                    user
+                }
+                None => user.as_str(),
+            };
+            let user = match user.split_once('@') {
+                Some((user, domain)) => {
+                    self.register_domain(domain);
+                    user
+                }
+                None => user,
+            };
+            self.register_user(user.trim());
+        }
+        for field in HOST_FIELDS.iter() {
+            self.register_host(get(field).trim_start_matches('\\'));
+        }
+        for field in IP_FIELDS.iter() {
+            let ip = get(field);
+            let ip = ip.trim().trim_start_matches("::ffff:");
+            if is_internal_ip(ip) {
+                self.pseudonym("IP", ip);
+            }
+        }
+        // 全ての値からユーザープロファイルのパスのユーザー名と、登録したドメインのFQDNのホスト名を登録する
+        self.register_strings(record);
+    }
+
+    fn register_strings(&mut self, value: &Value) {
+        match value {
+            Value::String(text) => {
+                for caps in PROFILE_PATH_REGEX.captures_iter(text) {
+                    let user = caps[1].trim();
+                    if !WELL_KNOWN_PROFILES.contains(&user.to_lowercase().as_str()) {
+                        self.register_user(user);
+                    }
+                }
+                let hosts: Vec<String> = FQDN_REGEX
+                    .captures_iter(text)
+                    .filter(|caps| self.is_domain(&caps[2]))
+                    .map(|caps| caps[1].to_string())
+                    .collect();
+                for host in hosts.iter() {
+                    self.register_host(host);
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|v| self.register_strings(v)),
+            Value::Object(map) => map.values().for_each(|v| self.register_strings(v)),
+            _ => {}
+        }
+    }
+
+    fn register_user(&mut self, user: &str) {
+        match user.strip_suffix('$') {
+            // コンピュータアカウント
+            Some(host) => self.register_host(host),
+            None => {
+                let lowercase = user.to_lowercase();
+                if user.is_empty()
+                    || WELL_KNOWN_USERS.contains(&lowercase.as_str())
+                    || WELL_KNOWN_USER_PREFIXES
+                        .iter()
+                        .any(|prefix| lowercase.starts_with(prefix))
+                {
+                    return;
+                }
+                self.pseudonym("User", user);
+            }
+        }
+    }
+
+    // FQDNの場合は最初のラベルをホスト名、残りをドメイン名として登録する
+    fn register_host(&mut self, host: &str) {
+        let host = host.trim();
+        if host.parse::<IpAddr>().is_ok() {
+            return;
+        }
+        let (host, domain) = match host.split_once('.') {
+            Some((host, domain)) => (host, Some(domain)),
+            None => (host, None),
+        };
+        // ".local"のような1つのラベルのDNSサフィックスは一般的な単語のため対象外にする
+        if let Some(domain) = domain.filter(|domain| domain.contains('.')) {
+            self.register_domain(domain);
+        }
+        if host.is_empty() || host == "-" || host.eq_ignore_ascii_case("localhost") {
+            return;
+        }
+        self.pseudonym("Host", host);
+    }
+
+    fn register_domain(&mut self, domain: &str) {
+        let domain = domain.trim().trim_end_matches('.');
+        if domain.is_empty()
+            || WELL_KNOWN_DOMAINS.contains(&domain.to_lowercase().as_str())
+            || domain.parse::<IpAddr>().is_ok()
+        {
+            return;
+        }
+        self.pseudonym("Domain", domain);
+    }
+
+    fn is_domain(&self, value: &str) -> bool {
+        matches!(self.pseudonyms.get(&value.to_lowercase()), Some((kind, _, _)) if kind == "Domain")
+    }
+
+    /// 文字列の登録済みのユーザー名、ホスト名、ドメイン名と内部のIPアドレスを仮名に置き換える
+    pub fn anonymize_text(&mut self, text: &str) -> String {
+        if self.names_regex.is_none() {
+            self.names_regex = self.build_names_regex();
+        }
+        let text = match &self.names_regex {
+            Some(regex) => regex
+                .replace_all(text, |caps: &Captures| {
+                    self.pseudonyms
+                        .get(&caps[0].to_lowercase())
+                        .map_or(caps[0].to_string(), |(_, _, pseudonym)| {
+                            pseudonym.to_string()
+                        })
+                })
+                .to_string(),
+            None => text.to_string(),
+        };
+        // IPアドレスは登録していないものも内部のアドレスであれば置き換える
+        let ips: Vec<String> = IPV4_REGEX
+            .find_iter(&text)
+            .map(|ip| ip.as_str().to_string())
+            .filter(|ip| is_internal_ip(ip))
+            .collect();
+        for ip in ips.iter() {
+            self.pseudonym("IP", ip);
+        }
+        let text = IPV4_REGEX.replace_all(&text, |caps: &Captures| {
+            match self.pseudonyms.get(&caps[0].to_string()) {
+                Some((kind, _, pseudonym)) if kind == "IP" => pseudonym.to_string(),
+                _ => caps[0].to_string(),
+            }
+        });
+        // IPv6アドレスは登録したものだけを置き換える
+        let mut text = text.to_string();
+        for (original, (kind, _, pseudonym)) in self.pseudonyms.iter() {
+            if kind == "IP" && original.contains(':') {
+                text = replace_ignore_case(&text, original, pseudonym);
+            }
+        }
+        text
+    }
+
+    /// 検知結果のコンピュータ名、詳細、レコードの情報を匿名化する。
+    /// evtxファイルのパスは解析した環境のパスで、検知したレコードを読み直すためにも使うため対象外にする
+    pub fn anonymize(&mut self, detect_info: &DetectInfo) -> DetectInfo {
+        let mut detect_info = detect_info.clone();
+        detect_info.computername = self.anonymize_text(&detect_info.computername);
+        detect_info.detail = self.anonymize_text(&detect_info.detail);
+        detect_info.record_information = detect_info
+            .record_information
+            .map(|recinfo| self.anonymize_text(&recinfo));
+        detect_info
+    }
+
+    // 長い名前を優先して一致させる。英数字で始まる/終わる名前は単語の区切りでだけ一致させる
+    fn build_names_regex(&self) -> Option<Regex> {
+        let mut names: Vec<&String> = self
+            .pseudonyms
+            .iter()
+            .filter(|(_, (kind, _, _))| kind != "IP")
+            .map(|(name, _)| name)
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        names.sort_by(|x, y| y.len().cmp(&x.len()).then_with(|| x.cmp(y)));
+        let word = |c: Option<char>| c.map_or(false, |c| c.is_alphanumeric() || c == '_');
+        let pattern = names
+            .iter()
+            .map(|name| {
+                format!(
+                    "{}{}{}",
+                    if word(name.chars().next()) { r"\b" } else { "" },
+                    regex::escape(name),
+                    if word(name.chars().last()) { r"\b" } else { "" }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("|");
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(1 << 28)
+            .build()
+            .ok()
+    }
+}
+
+/// --anonymizeが指定されている場合に、サマリーなどの検知結果以外の出力の文字列を匿名化する
+pub fn anonymize_output(text: &str) -> String {
+    if !*ANONYMIZE_FLAG {
+        return text.to_string();
+    }
+    ANONYMIZER.lock().unwrap().anonymize_text(text)
+}
+
+/// --anonymize-mapが指定されている場合は元の値と仮名の対応を保存する。全ての出力を終えた後に呼び出す
+pub fn save_anonymize_mapping() {
+    let path = match configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("anonymize-map")
+    {
+        Some(path) => path.to_string(),
+        None => return,
+    };
+    match ANONYMIZER.lock().unwrap().save_mapping(&path) {
+        Ok(_) => println!("Saved the anonymization mapping to {}\n", path),
+        Err(err) => {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Failed to write the anonymization mapping file. {}", err),
+            )
+            .ok();
+        }
+    }
+}
+
+/// プライベートアドレス、リンクローカルアドレス、IPv6のユニークローカルアドレスを内部のIPアドレスとする
+fn is_internal_ip(ip: &str) -> bool {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_private() || ip.is_link_local(),
+        Ok(IpAddr::V6(ip)) => {
+            let segment = ip.segments()[0];
+            (segment & 0xfe00) == 0xfc00 || (segment & 0xffc0) == 0xfe80
+        }
+        Err(_) => false,
+    }
+}
+
+// 小文字の値を大文字小文字を区別せずに置き換える
+fn replace_ignore_case(text: &str, lowercase: &str, replacement: &str) -> String {
+    let lower_text = text.to_ascii_lowercase();
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (idx, _) in lower_text.match_indices(lowercase) {
+        result.push_str(&text[last..idx]);
+        result.push_str(replacement);
+        last = idx + lowercase.len();
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::anonymize::{is_internal_ip, Anonymizer};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_internal_ip() {
+        assert!(is_internal_ip("10.0.0.1"));
+        assert!(is_internal_ip("192.168.1.10"));
+        assert!(is_internal_ip("fe80::1"));
+        assert!(!is_internal_ip("8.8.8.8"));
+        assert!(!is_internal_ip("PC01"));
+    }
+
+    #[test]
+    fn test_anonymize_text() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.register(&json!({
+            "Event": {
+                "System": { "Computer": "PC01.corp.local" },
+                "EventData": {
+                    "SubjectDomainName": "CORP",
+                    "TargetUserName": "alice",
+                    "SubjectUserName": "PC02$",
+                    "WorkstationName": "-",
+                    "IpAddress": "::ffff:192.168.1.10",
+                },
+            }
+        }));
+        anonymizer.register(&json!({
+            "Event": {
+                "System": { "Computer": "pc01" },
+                "EventData": {
+                    "TargetUserName": "SYSTEM",
+                    "TargetDomainName": "NT AUTHORITY",
+                    "IpAddress": "8.8.8.8",
+                },
+            }
+        }));
+        assert_eq!(
+            anonymizer.anonymize_text(
+                "User: CORP\\Alice : Computer: PC01.corp.local : Src: PC02 (192.168.1.10) : Dst: 10.0.0.5 : DNS: 8.8.8.8 : NT AUTHORITY\\SYSTEM"
+            ),
+            "User: DOMAIN-0002\\USER-0001 : Computer: HOST-0001.DOMAIN-0001 : Src: HOST-0002 (IP-0001) : Dst: IP-0002 : DNS: 8.8.8.8 : NT AUTHORITY\\SYSTEM"
+        );
+        // 同じ値は同じ仮名にする
+        assert_eq!(
+            anonymizer.anonymize_text("alice@10.0.0.5"),
+            "USER-0001@IP-0002"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_record() {
+        let record = json!({
+            "Event": {
+                "System": { "Computer": "WS01.example.corp" },
+                "EventData": {
+                    "SubjectUserName": "bob",
+                    "SubjectDomainName": "EXAMPLE",
+                    "TargetUserName": "carol@example.corp",
+                    "CommandLine": "\"C:\\Users\\dave\\AppData\\Local\\Temp\\a.exe\" \\\\fs01.example.corp\\share",
+                    "ParentImage": "/home/erin/bin/sh",
+                    "TargetFilename": "C:\\Users\\Public\\a.txt",
+                    "IpAddress": "10.1.2.3",
+                },
+            }
+        });
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.register(&record);
+        let xml = r#"<Event><System><Computer>WS01.example.corp</Computer></System><EventData><Data Name="SubjectUserName">bob</Data><Data Name="SubjectDomainName">EXAMPLE</Data><Data Name="TargetUserName">carol@example.corp</Data><Data Name="CommandLine">"C:\Users\dave\AppData\Local\Temp\a.exe" \\fs01.example.corp\share</Data><Data Name="ParentImage">/home/erin/bin/sh</Data><Data Name="IpAddress">10.1.2.3</Data></EventData></Event>"#;
+        // JSON、XMLのどちらの形式でも元のユーザー名、ホスト名、ドメイン名、IPアドレスが残らない
+        for text in [record.to_string(), xml.to_string()].iter() {
+            let anonymized = anonymizer.anonymize_text(text).to_lowercase();
+            for original in [
+                "ws01", "example", "bob", "carol", "dave", "erin", "fs01", "10.1.2.3",
+            ]
+            .iter()
+            {
+                assert!(
+                    !anonymized.contains(original),
+                    "{} remains in {}",
+                    original,
+                    anonymized
+                );
+            }
+            // 組み込みのプロファイルのフォルダは置き換えない
+            assert_eq!(anonymized.contains("public"), text.contains("Public"));
+        }
+    }
+}
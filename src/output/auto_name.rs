@@ -0,0 +1,160 @@
+use crate::detections::configs;
+use chrono::{DateTime, Local, Utc};
+use hashbrown::HashSet;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::sync::Mutex;
+
+// ファイル名に使う日時の形式
+const FILE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+lazy_static! {
+    /// --output-autoで指定したタイムラインのファイル名のテンプレート
+    pub static ref OUTPUT_AUTO_TEMPLATE: Option<String> = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("output-auto")
+        .map(|template| template.to_string());
+    /// ファイル名に使うために、スキャン中に記録した検知のComputerと日時の範囲
+    pub static ref DETECTION_RANGE: Mutex<DetectionRange> =
+        Mutex::new(DetectionRange::default());
+}
+
+/**
+* --output-autoのファイル名に使う、検知したComputerと最初と最後の検知日時。
+* --sortでディスクに書き出す検知結果も含められるように、検知を登録する時に記録する。
+*/
+#[derive(Debug, Default)]
+pub struct DetectionRange {
+    computers: HashSet<String>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+}
+
+impl DetectionRange {
+    pub fn add(&mut self, computer: &str, time: &DateTime<Utc>) {
+        if !self.computers.contains(computer) {
+            self.computers.insert(computer.to_string());
+        }
+        if self.start.map_or(true, |start| *time < start) {
+            self.start = Some(*time);
+        }
+        if self.end.map_or(true, |end| *time > end) {
+            self.end = Some(*time);
+        }
+    }
+
+    /// 検知したComputerが1台の場合はその名前、複数の場合はmultiple、検知がない場合はunknownを返す
+    fn hostname(&self) -> String {
+        match self.computers.len() {
+            0 => "unknown".to_string(),
+            1 => self.computers.iter().next().unwrap().to_string(),
+            _ => "multiple".to_string(),
+        }
+    }
+
+    /**
+     * テンプレートの{hostname}、{start}、{end}、{now}を置き換えたファイル名を返す。
+     * {start}と{end}は最初と最後の検知日時(UTC)で、検知がない場合はnoneになる。{now}は実行した日時。
+     */
+    pub fn file_name(&self, template: &str, now: &DateTime<Local>) -> String {
+        let format_time = |time: Option<DateTime<Utc>>| {
+            time.map_or_else(
+                || "none".to_string(),
+                |time| time.format(FILE_TIME_FORMAT).to_string(),
+            )
+        };
+        template
+            .replace("{hostname}", &sanitize(&self.hostname()))
+            .replace("{start}", &format_time(self.start))
+            .replace("{end}", &format_time(self.end))
+            .replace("{now}", &now.format(FILE_TIME_FORMAT).to_string())
+    }
+}
+
+// ファイル名に使えない文字を_に置き換える
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() || c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/**
+* --output-autoのテンプレートから出力するファイルのパスを決める。
+* 同じ名前のファイルが既にある場合は、--clobberが指定されていなければ_1、_2のように連番を付ける。
+*/
+pub fn output_path(template: &str, clobber: bool) -> String {
+    let file_name = DETECTION_RANGE
+        .lock()
+        .unwrap()
+        .file_name(template, &Local::now());
+    if clobber {
+        return file_name;
+    }
+    unique_path(&file_name, |path| Path::new(path).exists())
+}
+
+fn unique_path(file_name: &str, exists: impl Fn(&str) -> bool) -> String {
+    if !exists(file_name) {
+        return file_name.to_string();
+    }
+    let path = Path::new(file_name);
+    let stem = path.with_extension("").display().to_string();
+    let ext = path
+        .extension()
+        .map_or_else(String::default, |ext| format!(".{}", ext.to_string_lossy()));
+    (1..)
+        .map(|num| format!("{}_{}{}", stem, num, ext))
+        .find(|path| !exists(path))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::auto_name::{unique_path, DetectionRange};
+    use chrono::{Local, TimeZone, Utc};
+
+    #[test]
+    fn test_file_name() {
+        let template = "{hostname}_{start}_{end}_hayabusa.csv";
+        let now = Local.ymd(2022, 6, 1).and_hms(9, 0, 0);
+        let mut range = DetectionRange::default();
+        assert_eq!(
+            range.file_name(template, &now),
+            "unknown_none_none_hayabusa.csv"
+        );
+
+        range.add(
+            "DC01.example.local",
+            &Utc.ymd(2022, 5, 20).and_hms(12, 0, 0),
+        );
+        range.add(
+            "DC01.example.local",
+            &Utc.ymd(2022, 5, 19).and_hms(8, 30, 0),
+        );
+        assert_eq!(
+            range.file_name(template, &now),
+            "DC01.example.local_20220519T083000_20220520T120000_hayabusa.csv"
+        );
+
+        range.add("PC 01", &Utc.ymd(2022, 5, 20).and_hms(13, 0, 0));
+        assert_eq!(
+            range.file_name("results/{hostname}_{now}.csv", &now),
+            "results/multiple_20220601T090000.csv"
+        );
+    }
+
+    #[test]
+    fn test_unique_path() {
+        let existing = ["a.csv", "a_1.csv"];
+        let exists = |path: &str| existing.contains(&path);
+        assert_eq!(unique_path("b.csv", exists), "b.csv");
+        assert_eq!(unique_path("a.csv", exists), "a_2.csv");
+    }
+}
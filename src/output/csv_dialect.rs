@@ -0,0 +1,189 @@
+use crate::detections::configs;
+use csv::QuoteStyle;
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8};
+use std::io::{self, Write};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/**
+* --csv-delimiter、--csv-quote、--csv-bom、--csv-encodingで指定したCSVの書式。
+* 日本語版のExcelで文字化けしないように、BOM付きのUTF-8やShift_JISで出力できるようにする。
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote_style: QuoteStyle,
+    pub bom: bool,
+    pub encoding: &'static Encoding,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            quote_style: QuoteStyle::Necessary,
+            bom: false,
+            encoding: UTF_8,
+        }
+    }
+}
+
+impl CsvDialect {
+    pub fn new(
+        delimiter: Option<&str>,
+        quote: Option<&str>,
+        bom: bool,
+        encoding: Option<&str>,
+    ) -> Result<CsvDialect, String> {
+        let delimiter = match delimiter.unwrap_or("comma").to_lowercase().as_str() {
+            "comma" | "," => b',',
+            "tab" | "\\t" => b'\t',
+            "pipe" | "|" => b'|',
+            "semicolon" | ";" => b';',
+            other => {
+                return Err(format!(
+                    "Invalid CSV delimiter: {}. Please specify comma, tab, pipe or semicolon.",
+                    other
+                ))
+            }
+        };
+        let quote_style = match quote.unwrap_or("necessary").to_lowercase().as_str() {
+            "necessary" => QuoteStyle::Necessary,
+            "always" => QuoteStyle::Always,
+            "non-numeric" => QuoteStyle::NonNumeric,
+            other => {
+                return Err(format!(
+                    "Invalid CSV quoting: {}. Please specify necessary, always or non-numeric.",
+                    other
+                ))
+            }
+        };
+        let encoding = match encoding.unwrap_or("utf-8").to_lowercase().as_str() {
+            "utf-8" | "utf8" => UTF_8,
+            "shift_jis" | "shift-jis" | "sjis" | "cp932" => SHIFT_JIS,
+            other => {
+                return Err(format!(
+                    "Invalid CSV encoding: {}. Please specify utf-8 or shift_jis.",
+                    other
+                ))
+            }
+        };
+        if bom && encoding != UTF_8 {
+            return Err("--csv-bom can only be used with UTF-8 encoding.".to_string());
+        }
+        Ok(CsvDialect {
+            delimiter,
+            quote_style,
+            bom,
+            encoding,
+        })
+    }
+
+    pub fn from_config() -> Result<CsvDialect, String> {
+        let config = configs::CONFIG.read().unwrap();
+        CsvDialect::new(
+            config.args.value_of("csv-delimiter"),
+            config.args.value_of("csv-quote"),
+            config.args.is_present("csv-bom"),
+            config.args.value_of("csv-encoding"),
+        )
+    }
+
+    pub fn writer_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote_style(self.quote_style);
+        builder
+    }
+
+    /// BOMを書き込み、指定した文字コードに変換して書き込むWriterを返す
+    pub fn wrap<W: Write + 'static>(&self, mut inner: W) -> io::Result<Box<dyn Write>> {
+        if self.bom {
+            inner.write_all(UTF8_BOM)?;
+        }
+        if self.encoding == UTF_8 {
+            Ok(Box::new(inner))
+        } else {
+            Ok(Box::new(EncodingWriter::new(inner, self.encoding)))
+        }
+    }
+}
+
+/// UTF-8で書き込まれた内容を指定した文字コードに変換して書き込む
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    encoding: &'static Encoding,
+    // 文字の途中で区切られたUTF-8のバイト列
+    pending: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(inner: W, encoding: &'static Encoding) -> EncodingWriter<W> {
+        EncodingWriter {
+            inner,
+            encoding,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_len = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid_len]).unwrap();
+        // 変換できない文字は数値文字参照に置き換えられる
+        let (encoded, _, _) = self.encoding.encode(text);
+        self.inner.write_all(&encoded)?;
+        self.pending.drain(..valid_len);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::csv_dialect::{CsvDialect, EncodingWriter};
+    use encoding_rs::SHIFT_JIS;
+    use std::io::Write;
+
+    #[test]
+    fn test_csv_dialect() {
+        let dialect = CsvDialect::new(Some("tab"), Some("always"), false, None).unwrap();
+        let mut wtr = dialect.writer_builder().from_writer(vec![]);
+        wtr.write_record(&["a", "b c"]).unwrap();
+        assert_eq!(
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap(),
+            "\"a\"\t\"b c\"\n"
+        );
+        assert!(CsvDialect::new(Some("colon"), None, false, None).is_err());
+        assert!(CsvDialect::new(None, Some("never"), false, None).is_err());
+        assert!(CsvDialect::new(None, None, true, Some("shift_jis")).is_err());
+        assert!(
+            CsvDialect::new(None, None, true, Some("utf-8"))
+                .unwrap()
+                .bom
+        );
+    }
+
+    #[test]
+    fn test_encoding_writer() {
+        let text = "検知,ルール\n";
+        let mut wtr = EncodingWriter::new(vec![], SHIFT_JIS);
+        // 文字の途中で区切って書き込んでも正しく変換できること
+        let bytes = text.as_bytes();
+        wtr.write_all(&bytes[..4]).unwrap();
+        wtr.write_all(&bytes[4..]).unwrap();
+        let (expect, _, _) = SHIFT_JIS.encode(text);
+        assert_eq!(wtr.inner, expect.into_owned());
+        assert!(wtr.pending.is_empty());
+    }
+}
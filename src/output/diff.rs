@@ -0,0 +1,188 @@
+use crate::detections::print::DetectInfo;
+use crate::detections::utils::get_serde_number_to_string;
+use hashbrown::{HashMap, HashSet};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+/// 前回と今回の検知結果を同じ検知とみなすキー(Computer, EventID, RecordID, RuleTitle)
+type DetectionKey = (String, String, String, String);
+
+// 前回の実行結果の各行に必要なフィールド。--json-schema ecs/ocsfで保存した結果にはない
+const KEY_FIELDS: [&str; 4] = ["Computer", "EventID", "RecordID", "RuleTitle"];
+
+lazy_static! {
+    // 解析の前に読み込んだ--diffの前回の実行結果
+    static ref BASELINE_DIFF: Mutex<Option<BaselineDiff>> = Mutex::new(None);
+}
+
+/// --diffで指定した前回の実行結果を解析の前に読み込む。比較できない形式の場合は解析を始めずに終了できるようにエラーを返す
+pub fn init_baseline(path: &str) -> Result<(), String> {
+    *BASELINE_DIFF.lock().unwrap() = Some(BaselineDiff::load(path)?);
+    Ok(())
+}
+
+/// init_baselineで読み込んだ前回の実行結果を取り出す
+pub fn take_baseline() -> Option<BaselineDiff> {
+    BASELINE_DIFF.lock().unwrap().take()
+}
+
+/**
+* --diffで指定した前回の実行結果(--output-jsonlのhayabusa形式)と今回の検知結果を比較する。
+* 前回にない検知だけを出力し、前回にあって今回なくなった検知は--diff-resolvedで指定したファイルに保存する。
+*/
+#[derive(Debug, Default)]
+pub struct BaselineDiff {
+    // 前回の検知結果とJSONの行
+    previous: HashMap<DetectionKey, Value>,
+    // 今回も検知した前回の検知結果
+    seen: HashSet<DetectionKey>,
+    pub new: usize,
+    pub unchanged: usize,
+}
+
+impl BaselineDiff {
+    /// JSON Linesか、検知結果の配列のJSONファイルを読み込む
+    pub fn load(path: &str) -> Result<BaselineDiff, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{} [file:{}]", e, path))?;
+        let detections: Vec<Value> = match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Array(detections)) => detections,
+            _ => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str::<Value>)
+                .collect::<Result<Vec<Value>, _>>()
+                .map_err(|e| format!("{} [file:{}]", e, path))?,
+        };
+        BaselineDiff::from_detections(detections).map_err(|e| format!("{} [file:{}]", e, path))
+    }
+
+    /// 前回の実行結果を読み込む。キーのフィールドがない行があると全ての検知が新しい検知になってしまうので、エラーにする
+    fn from_detections(detections: Vec<Value>) -> Result<BaselineDiff, String> {
+        let mut diff = BaselineDiff::default();
+        for (idx, detection) in detections.into_iter().enumerate() {
+            if let Some(field) = KEY_FIELDS
+                .iter()
+                .find(|field| detection.get(**field).map_or(true, Value::is_null))
+            {
+                return Err(format!(
+                    "Detection {} of the previous results does not have the {} field. --diff needs results saved with --output-jsonl in the hayabusa JSON schema (not --json-schema ecs or ocsf) that have the {} fields.",
+                    idx + 1,
+                    field,
+                    KEY_FIELDS.join(", ")
+                ));
+            }
+            let get = |key: &str| get_serde_number_to_string(&detection[key]).unwrap_or_default();
+            let key = (
+                get("Computer"),
+                get("EventID"),
+                get("RecordID"),
+                get("RuleTitle"),
+            );
+            diff.previous.insert(key, detection);
+        }
+        Ok(diff)
+    }
+
+    /// 前回の実行結果にない検知の場合はtrueを返す
+    pub fn is_new(&mut self, detect_info: &DetectInfo) -> bool {
+        let key = (
+            detect_info.computername.to_string(),
+            detect_info.eventid.to_string(),
+            detect_info.record_id.to_string(),
+            detect_info.alert.to_string(),
+        );
+        if self.previous.contains_key(&key) {
+            self.unchanged += 1;
+            self.seen.insert(key);
+            false
+        } else {
+            self.new += 1;
+            true
+        }
+    }
+
+    /// 前回の実行結果にあって今回検知しなかった検知結果を返す
+    pub fn resolved(&self) -> Vec<&Value> {
+        let mut resolved: Vec<&Value> = self
+            .previous
+            .iter()
+            .filter(|(key, _)| !self.seen.contains(*key))
+            .map(|(_, detection)| detection)
+            .collect();
+        resolved.sort_by_key(|detection| {
+            get_serde_number_to_string(&detection["Timestamp"]).unwrap_or_default()
+        });
+        resolved
+    }
+
+    /// 解消された検知結果をJSON Lines形式で保存する
+    pub fn write_resolved(&self, path: &str) -> Result<usize, String> {
+        let file = fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut wtr = BufWriter::new(file);
+        let resolved = self.resolved();
+        for detection in resolved.iter() {
+            serde_json::to_writer(&mut wtr, detection).map_err(|e| e.to_string())?;
+            wtr.write_all(b"\n").map_err(|e| e.to_string())?;
+        }
+        wtr.flush().map_err(|e| e.to_string())?;
+        Ok(resolved.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::diff::BaselineDiff;
+    use serde_json::json;
+
+    fn detect_info(record_id: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "test.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4688".to_string(),
+            record_id: record_id.to_string(),
+            channel: "Sec".to_string(),
+            alert: "Suspicious Process".to_string(),
+            detail: String::default(),
+            tag_info: String::default(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_baseline_diff() {
+        let mut diff = BaselineDiff::from_detections(vec![
+            json!({"Timestamp": "2021-12-12 10:00:00.000 +00:00", "Computer": "PC01", "EventID": "4688", "RecordID": "1", "RuleTitle": "Suspicious Process"}),
+            json!({"Timestamp": "2021-12-12 09:00:00.000 +00:00", "Computer": "PC01", "EventID": 4688, "RecordID": 2, "RuleTitle": "Suspicious Process"}),
+        ])
+        .unwrap();
+        assert!(!diff.is_new(&detect_info("1")));
+        assert!(diff.is_new(&detect_info("3")));
+        assert_eq!(diff.new, 1);
+        assert_eq!(diff.unchanged, 1);
+        let resolved = diff.resolved();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0]["RecordID"], 2);
+    }
+
+    #[test]
+    fn test_baseline_diff_without_key_fields() {
+        // --json-schema ecsで保存した結果やRecordIDがない結果は比較できない
+        let ecs = BaselineDiff::from_detections(vec![
+            json!({"@timestamp": "2021-12-12T10:00:00Z", "host": {"name": "PC01"}, "event": {"code": "4688"}}),
+        ]);
+        assert!(ecs.unwrap_err().contains("Computer"));
+        let without_record_id = BaselineDiff::from_detections(vec![
+            json!({"Computer": "PC01", "EventID": "4688", "RecordID": "1", "RuleTitle": "Suspicious Process"}),
+            json!({"Computer": "PC01", "EventID": "4688", "RuleTitle": "Suspicious Process"}),
+        ]);
+        assert!(without_record_id
+            .unwrap_err()
+            .starts_with("Detection 2 of the previous results does not have the RecordID field."));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, Utc};
+use hashbrown::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// 集計表に表示するlevelの順番。HostScoreのcounts_by_levelと同じ並びにする
+const LEVELS: [&str; 6] = [
+    "critical",
+    "high",
+    "medium",
+    "low",
+    "informational",
+    "undefined",
+];
+const LEVEL_COLORS: [&str; 6] = [
+    "#d32f2f", "#f57c00", "#fbc02d", "#388e3c", "#1976d2", "#757575",
+];
+
+// config/output_tag.txtで変換した後のタクティクスの略称をATT&CKの並び順で並べたもの
+const TACTICS: [&str; 14] = [
+    "Recon",
+    "ResDev",
+    "InitAccess",
+    "Exec",
+    "Persis",
+    "PrivEsc",
+    "Evas",
+    "CredAccess",
+    "Disc",
+    "LatMov",
+    "Collect",
+    "C2",
+    "Exfil",
+    "Impact",
+];
+
+const TOP_RULES: usize = 20;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #212121; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }
+th { background: #f5f5f5; }
+td.name { text-align: left; }
+.bar { fill: #1976d2; }
+.axis { font-size: 10px; fill: #616161; }
+";
+
+fn level_index(level: &str) -> usize {
+    LEVELS
+        .iter()
+        .position(|l| *l == level.to_lowercase())
+        .unwrap_or(5)
+}
+
+#[derive(Debug, Default)]
+struct RuleSummary {
+    title: String,
+    level: String,
+    count: u64,
+    computers: Vec<String>,
+}
+
+/**
+* --html-reportで指定したディレクトリに検知結果の概要をまとめたHTMLのレポートを出力する。
+* 外部のスクリプトやスタイルシートを読み込まず、1つのファイルで表示できるようにする。
+*/
+#[derive(Debug, Default)]
+pub struct HtmlReport {
+    counts_by_level: [u64; 6],
+    rules: HashMap<String, RuleSummary>,
+    computers: BTreeMap<String, [u64; 6]>,
+    timeline: BTreeMap<String, u64>,
+    tactics: BTreeMap<String, HashMap<String, u64>>,
+    first_time: Option<DateTime<Utc>>,
+    last_time: Option<DateTime<Utc>>,
+}
+
+impl HtmlReport {
+    pub fn new() -> HtmlReport {
+        HtmlReport::default()
+    }
+
+    pub fn add(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        let idx = level_index(&detect_info.level);
+        self.counts_by_level[idx] += 1;
+        let rule = self
+            .rules
+            .entry(detect_info.rulepath.to_string())
+            .or_insert_with(|| RuleSummary {
+                title: detect_info.alert.to_string(),
+                level: detect_info.level.to_string(),
+                ..Default::default()
+            });
+        rule.count += 1;
+        if !rule.computers.contains(&detect_info.computername) {
+            rule.computers.push(detect_info.computername.to_string());
+        }
+        self.computers
+            .entry(detect_info.computername.to_string())
+            .or_insert([0; 6])[idx] += 1;
+        *self
+            .timeline
+            .entry(time.format("%Y-%m-%d").to_string())
+            .or_insert(0) += 1;
+        let tactics = self
+            .tactics
+            .entry(detect_info.computername.to_string())
+            .or_default();
+        // 集計条件のルールは" : "、通常のルールは" | "で区切られている
+        for tag in detect_info
+            .tag_info
+            .split(['|', ':'])
+            .map(|tag| tag.trim())
+            .filter(|tag| TACTICS.contains(tag))
+        {
+            *tactics.entry(tag.to_string()).or_insert(0) += 1;
+        }
+        if self.first_time.map_or(true, |first| *time < first) {
+            self.first_time = Some(*time);
+        }
+        if self.last_time.map_or(true, |last| *time > last) {
+            self.last_time = Some(*time);
+        }
+    }
+
+    /// ディレクトリを作成してindex.htmlを書き込む
+    pub fn write(&self, dir: &str) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        fs::write(Path::new(dir).join("index.html"), self.render()).map_err(|e| e.to_string())
+    }
+
+    fn render(&self) -> String {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str("<title>Hayabusa Report</title>\n<style>");
+        html.push_str(STYLE);
+        html.push_str("</style>\n</head>\n<body>\n<h1>Hayabusa Report</h1>\n");
+        let format_time = |time: Option<DateTime<Utc>>| {
+            time.map_or("-".to_string(), |t| {
+                t.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+            })
+        };
+        writeln!(
+            html,
+            "<p>Version: {}<br>First detection: {}<br>Last detection: {}</p>",
+            env!("CARGO_PKG_VERSION"),
+            format_time(self.first_time),
+            format_time(self.last_time)
+        )
+        .ok();
+        self.render_levels(&mut html);
+        self.render_timeline(&mut html);
+        self.render_top_rules(&mut html);
+        self.render_computers(&mut html);
+        self.render_heatmap(&mut html);
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    fn render_levels(&self, html: &mut String) {
+        html.push_str(
+            "<h2>Detections by Level</h2>\n<table>\n<tr><th>Level</th><th>Detections</th></tr>\n",
+        );
+        for (i, level) in LEVELS.iter().enumerate() {
+            writeln!(
+                html,
+                "<tr><td class=\"name\" style=\"color:{}\">{}</td><td>{}</td></tr>",
+                LEVEL_COLORS[i], level, self.counts_by_level[i]
+            )
+            .ok();
+        }
+        writeln!(
+            html,
+            "<tr><th>Total</th><th>{}</th></tr>\n</table>",
+            self.counts_by_level.iter().sum::<u64>()
+        )
+        .ok();
+    }
+
+    /// 日毎の検知件数をSVGの棒グラフで表示する
+    fn render_timeline(&self, html: &mut String) {
+        html.push_str("<h2>Detection Timeline</h2>\n");
+        if self.timeline.is_empty() {
+            html.push_str("<p>No detections.</p>\n");
+            return;
+        }
+        let max = *self.timeline.values().max().unwrap_or(&1) as f64;
+        let bar_width = 12;
+        let height = 200;
+        let width = self.timeline.len() * bar_width + 40;
+        writeln!(
+            html,
+            "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+            width,
+            height + 20
+        )
+        .ok();
+        for (i, (day, count)) in self.timeline.iter().enumerate() {
+            let bar_height = ((*count as f64 / max) * height as f64).max(1.0);
+            writeln!(
+                html,
+                "<rect class=\"bar\" x=\"{}\" y=\"{:.1}\" width=\"{}\" height=\"{:.1}\"><title>{}: {}</title></rect>",
+                i * bar_width,
+                height as f64 - bar_height,
+                bar_width - 2,
+                bar_height,
+                day,
+                count
+            )
+            .ok();
+        }
+        let first = self.timeline.keys().next().unwrap();
+        let last = self.timeline.keys().next_back().unwrap();
+        writeln!(
+            html,
+            "<text class=\"axis\" x=\"0\" y=\"{}\">{}</text><text class=\"axis\" x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n</svg>",
+            height + 15,
+            first,
+            width,
+            height + 15,
+            last
+        )
+        .ok();
+    }
+
+    fn render_top_rules(&self, html: &mut String) {
+        let mut rules: Vec<&RuleSummary> = self.rules.values().collect();
+        rules.sort_by(|x, y| y.count.cmp(&x.count).then_with(|| x.title.cmp(&y.title)));
+        writeln!(
+            html,
+            "<h2>Top {} Rules</h2>\n<table>\n<tr><th>Rule Title</th><th>Level</th><th>Detections</th><th>Computers</th></tr>",
+            TOP_RULES
+        )
+        .ok();
+        for rule in rules.iter().take(TOP_RULES) {
+            writeln!(
+                html,
+                "<tr><td class=\"name\">{}</td><td class=\"name\" style=\"color:{}\">{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&rule.title),
+                LEVEL_COLORS[level_index(&rule.level)],
+                escape_html(&rule.level),
+                rule.count,
+                rule.computers.len()
+            )
+            .ok();
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn render_computers(&self, html: &mut String) {
+        html.push_str("<h2>Computers</h2>\n<table>\n<tr><th>Computer</th>");
+        for level in LEVELS.iter() {
+            write!(html, "<th>{}</th>", level).ok();
+        }
+        html.push_str("<th>Total</th></tr>\n");
+        for (computer, counts) in self.computers.iter() {
+            write!(
+                html,
+                "<tr><td class=\"name\">{}</td>",
+                escape_html(computer)
+            )
+            .ok();
+            for count in counts.iter() {
+                write!(html, "<td>{}</td>", count).ok();
+            }
+            writeln!(html, "<td>{}</td></tr>", counts.iter().sum::<u64>()).ok();
+        }
+        html.push_str("</table>\n");
+    }
+
+    /// Computer毎のタクティクス別の検知件数を件数に応じた濃さで色付けして表示する
+    fn render_heatmap(&self, html: &mut String) {
+        html.push_str("<h2>MITRE ATT&amp;CK Heatmap</h2>\n<table>\n<tr><th>Computer</th>");
+        for tactic in TACTICS.iter() {
+            write!(html, "<th>{}</th>", tactic).ok();
+        }
+        html.push_str("</tr>\n");
+        let max = self
+            .tactics
+            .values()
+            .flat_map(|counts| counts.values())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        for (computer, counts) in self.tactics.iter() {
+            write!(
+                html,
+                "<tr><td class=\"name\">{}</td>",
+                escape_html(computer)
+            )
+            .ok();
+            for tactic in TACTICS.iter() {
+                let count = counts.get(*tactic).copied().unwrap_or(0);
+                if count == 0 {
+                    html.push_str("<td></td>");
+                    continue;
+                }
+                let alpha = 0.15 + 0.85 * (count as f64 / max as f64);
+                write!(
+                    html,
+                    "<td style=\"background:rgba(211,47,47,{:.2})\">{}</td>",
+                    alpha, count
+                )
+                .ok();
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::html::HtmlReport;
+    use chrono::{TimeZone, Utc};
+
+    fn detect_info(computer: &str, level: &str, tag_info: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: format!("rules/{}.yml", level),
+            level: level.to_string(),
+            computername: computer.to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "<Logon Failure>".to_string(),
+            detail: "".to_string(),
+            tag_info: tag_info.to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_html_report() {
+        let mut report = HtmlReport::new();
+        let time = Utc.ymd(2022, 5, 20).and_hms(1, 2, 3);
+        report.add(&time, &detect_info("PC01", "high", "CredAccess | LatMov"));
+        report.add(&time, &detect_info("PC01", "high", "CredAccess"));
+        report.add(
+            &Utc.ymd(2022, 5, 21).and_hms(1, 2, 3),
+            &detect_info("PC02", "low", "Exec : Persis"),
+        );
+        assert_eq!(report.counts_by_level, [0, 2, 0, 1, 0, 0]);
+        assert_eq!(report.timeline.len(), 2);
+        assert_eq!(report.tactics["PC01"]["CredAccess"], 2);
+        assert_eq!(report.tactics["PC02"]["Persis"], 1);
+        assert_eq!(report.rules["rules/high.yml"].computers, vec!["PC01"]);
+
+        let html = report.render();
+        assert!(html.contains("&lt;Logon Failure&gt;"));
+        assert!(!html.contains("<Logon Failure>"));
+        assert!(html.contains("First detection: 2022-05-20 01:02:03 UTC"));
+    }
+}
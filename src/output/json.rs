@@ -0,0 +1,418 @@
+use crate::detections::print::DetectInfo;
+use crate::input::RecordOffsetIndex;
+use crate::output::rule_meta::{RuleMeta, RuleMetaCache, RuleMetaColumns, SelectedRuleMeta};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// 出力するJSONのフィールド名の形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JsonSchema {
+    Hayabusa,
+    Ecs,
+    Ocsf,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonDetection<'a> {
+    timestamp: &'a str,
+    computer: &'a str,
+    channel: &'a str,
+    event_i_d: &'a str,
+    record_i_d: &'a str,
+    level: &'a str,
+    mitre_attack: &'a str,
+    rule_title: &'a str,
+    details: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record_information: Option<&'a str>,
+    rule_path: &'a str,
+    file_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record_offset: Option<u64>,
+    #[serde(flatten)]
+    rule_meta: SelectedRuleMeta<'a>,
+}
+
+/// levelをElastic Securityの検知ルールと同じ数値のseverityに変換する
+fn ecs_severity(level: &str) -> u64 {
+    match level.to_lowercase().as_str() {
+        "critical" => 99,
+        "high" => 73,
+        "medium" => 47,
+        "low" => 21,
+        _ => 0,
+    }
+}
+
+/// levelをOCSFのseverity_idと名前に変換する
+fn ocsf_severity(level: &str) -> (u64, &'static str) {
+    match level.to_lowercase().as_str() {
+        "critical" => (5, "Critical"),
+        "high" => (4, "High"),
+        "medium" => (3, "Medium"),
+        "low" => (2, "Low"),
+        "informational" => (1, "Informational"),
+        _ => (0, "Unknown"),
+    }
+}
+
+/**
+* --output-jsonlで指定したファイルに検知結果を1行1件のJSONで書き込む。
+* --json-schema ecsを指定した場合はElastic Common Schema(ECS)のフィールド名、
+* --json-schema ocsfを指定した場合はOCSFのSecurity Findingクラスの形式で出力する。
+* 元のevtxファイルでレコードを確認できるように、ファイル先頭からのレコードの位置も出力する。
+*/
+pub struct JsonOutput {
+    writer: BufWriter<File>,
+    schema: JsonSchema,
+    rules: RuleMetaCache,
+    rule_meta_columns: RuleMetaColumns,
+    offsets: RecordOffsetIndex,
+    pub written: usize,
+}
+
+impl JsonOutput {
+    pub fn create(
+        path: &str,
+        schema: &str,
+        rule_meta_columns: RuleMetaColumns,
+    ) -> Result<JsonOutput, String> {
+        let schema = match schema.to_lowercase().as_str() {
+            "hayabusa" => JsonSchema::Hayabusa,
+            "ecs" => JsonSchema::Ecs,
+            "ocsf" => JsonSchema::Ocsf,
+            _ => {
+                return Err(format!(
+                    "Invalid JSON schema: {}. Please specify hayabusa, ecs or ocsf.",
+                    schema
+                ))
+            }
+        };
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create the JSON file {}. {}", path, e))?;
+        Ok(JsonOutput {
+            writer: BufWriter::new(file),
+            schema,
+            rules: RuleMetaCache::new(),
+            rule_meta_columns,
+            offsets: RecordOffsetIndex::new(),
+            written: 0,
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        time: &DateTime<Utc>,
+        timestamp: &str,
+        level: &str,
+        detect_info: &DetectInfo,
+    ) -> Result<(), String> {
+        let offset = self
+            .offsets
+            .offset(&detect_info.filepath, &detect_info.record_id);
+        let line = match self.schema {
+            JsonSchema::Hayabusa => serde_json::to_string(&JsonDetection {
+                timestamp,
+                computer: &detect_info.computername,
+                channel: &detect_info.channel,
+                event_i_d: &detect_info.eventid,
+                record_i_d: &detect_info.record_id,
+                level,
+                mitre_attack: &detect_info.tag_info,
+                rule_title: &detect_info.alert,
+                details: &detect_info.detail,
+                record_information: detect_info.record_information.as_deref(),
+                rule_path: &detect_info.rulepath,
+                file_path: &detect_info.filepath,
+                record_offset: offset,
+                rule_meta: if self.rule_meta_columns.is_empty() {
+                    SelectedRuleMeta::default()
+                } else {
+                    self.rule_meta_columns
+                        .select(self.rules.get(&detect_info.rulepath))
+                },
+            }),
+            JsonSchema::Ecs => {
+                let rule = self.rules.get(&detect_info.rulepath);
+                serde_json::to_string(&to_ecs(time, detect_info, offset, rule))
+            }
+            JsonSchema::Ocsf => {
+                let rule = self.rules.get(&detect_info.rulepath);
+                serde_json::to_string(&to_ocsf(time, detect_info, offset, rule))
+            }
+        }
+        .map_err(|e| e.to_string())?;
+        writeln!(self.writer, "{}", line).map_err(|e| e.to_string())?;
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<usize, String> {
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.written)
+    }
+}
+
+fn to_ecs(
+    time: &DateTime<Utc>,
+    detect_info: &DetectInfo,
+    offset: Option<u64>,
+    rule: &RuleMeta,
+) -> Value {
+    let mut doc = json!({
+        "@timestamp": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "message": detect_info.detail,
+        "event": {
+            "kind": "alert",
+            "module": "hayabusa",
+            "code": detect_info.eventid,
+            "severity": ecs_severity(&detect_info.level),
+        },
+        "host": {
+            "name": detect_info.computername,
+        },
+        "winlog": {
+            "channel": detect_info.channel,
+            "computer_name": detect_info.computername,
+            "record_id": detect_info.record_id,
+        },
+        "log": {
+            "level": detect_info.level,
+            "file": {
+                "path": detect_info.filepath,
+            },
+        },
+        "rule": {
+            "name": detect_info.alert,
+            "ruleset": "hayabusa",
+            "reference": detect_info.rulepath,
+        },
+    });
+    if let Some(offset) = offset {
+        doc["log"]["offset"] = json!(offset);
+    }
+    if let Some(id) = &rule.id {
+        doc["rule"]["id"] = json!(id);
+    }
+    if !rule.tactics.is_empty() || !rule.techniques.is_empty() {
+        doc["threat"] = json!({
+            "framework": "MITRE ATT&CK",
+            "tactic": { "name": rule.tactics },
+            "technique": { "id": rule.techniques },
+        });
+    }
+    if let Some(recinfo) = &detect_info.record_information {
+        doc["hayabusa"] = json!({ "record_information": recinfo });
+    }
+    doc
+}
+
+// OCSF 1.0のSecurity Finding(class_uid: 2001)のCreateアクティビティとして出力する
+fn to_ocsf(
+    time: &DateTime<Utc>,
+    detect_info: &DetectInfo,
+    offset: Option<u64>,
+    rule: &RuleMeta,
+) -> Value {
+    let (severity_id, severity) = ocsf_severity(&detect_info.level);
+    // 同じ検知結果からは同じIDになるように検知結果の内容から生成する
+    let mut hasher = Sha256::new();
+    for value in [
+        time.to_rfc3339().as_str(),
+        &detect_info.computername,
+        &detect_info.rulepath,
+        &detect_info.eventid,
+        &detect_info.filepath,
+        &detect_info.detail,
+    ] {
+        hasher.update(value.as_bytes());
+        hasher.update([0]);
+    }
+    let uid = hex::encode(hasher.finalize());
+    let mut attacks: Vec<Value> = rule
+        .techniques
+        .iter()
+        .map(|technique| {
+            json!({
+                "technique": { "uid": technique },
+                "tactics": rule.tactics.iter().map(|name| json!({ "name": name })).collect::<Vec<Value>>(),
+            })
+        })
+        .collect();
+    if attacks.is_empty() && !rule.tactics.is_empty() {
+        attacks.push(json!({
+            "tactics": rule.tactics.iter().map(|name| json!({ "name": name })).collect::<Vec<Value>>(),
+        }));
+    }
+    let mut doc = json!({
+        "activity_id": 1,
+        "activity_name": "Create",
+        "category_uid": 2,
+        "category_name": "Findings",
+        "class_uid": 2001,
+        "class_name": "Security Finding",
+        "type_uid": 200101,
+        "type_name": "Security Finding: Create",
+        "time": time.timestamp_millis(),
+        "severity_id": severity_id,
+        "severity": severity,
+        "state_id": 1,
+        "state": "New",
+        "message": detect_info.alert,
+        "metadata": {
+            "version": "1.0.0",
+            "product": {
+                "name": "Hayabusa",
+                "vendor_name": "Yamato Security",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        },
+        "finding": {
+            "uid": uid,
+            "title": detect_info.alert,
+            "desc": detect_info.detail,
+            "types": ["Windows Event Log"],
+        },
+        "analytic": {
+            "name": detect_info.alert,
+            "type_id": 1,
+            "type": "Rule",
+        },
+        "resources": [{
+            "type": "Windows Host",
+            "name": detect_info.computername,
+        }],
+        "unmapped": {
+            "channel": detect_info.channel,
+            "event_id": detect_info.eventid,
+            "record_id": detect_info.record_id,
+            "rule_path": detect_info.rulepath,
+            "file_path": detect_info.filepath,
+        },
+    });
+    if let Some(offset) = offset {
+        doc["unmapped"]["record_offset"] = json!(offset);
+    }
+    if let Some(id) = &rule.id {
+        doc["analytic"]["uid"] = json!(id);
+    }
+    if !attacks.is_empty() {
+        doc["attacks"] = json!(attacks);
+    }
+    if let Some(recinfo) = &detect_info.record_information {
+        doc["unmapped"]["record_information"] = json!(recinfo);
+    }
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::json::{to_ecs, to_ocsf, JsonOutput};
+    use crate::output::rule_meta::{RuleMeta, RuleMetaColumns};
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::fs;
+
+    fn detect_info(rulepath: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_to_ecs() {
+        let rule = RuleMeta {
+            id: Some("rule-id".to_string()),
+            tactics: vec!["Credential Access".to_string()],
+            techniques: vec!["T1110".to_string()],
+            ..Default::default()
+        };
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        let doc = to_ecs(&time, &detect_info("rules/test.yml"), Some(0x1200), &rule);
+        assert_eq!(doc["@timestamp"], "2022-05-20T01:02:03.500Z");
+        assert_eq!(doc["event"]["code"], "4625");
+        assert_eq!(doc["event"]["severity"], 73);
+        assert_eq!(doc["host"]["name"], "PC01");
+        assert_eq!(doc["winlog"]["record_id"], "12345");
+        assert_eq!(doc["log"]["offset"], 0x1200);
+        assert_eq!(doc["rule"]["name"], "Logon Failure");
+        assert_eq!(doc["rule"]["id"], "rule-id");
+        assert_eq!(doc["threat"]["technique"]["id"][0], "T1110");
+        assert!(doc.get("hayabusa").is_none());
+    }
+
+    #[test]
+    fn test_to_ocsf() {
+        let rule = RuleMeta {
+            id: Some("rule-id".to_string()),
+            tactics: vec!["Credential Access".to_string()],
+            techniques: vec!["T1110".to_string(), "T1110.001".to_string()],
+            ..Default::default()
+        };
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        let doc = to_ocsf(&time, &detect_info("rules/test.yml"), None, &rule);
+        assert_eq!(doc["class_uid"], 2001);
+        assert_eq!(doc["type_uid"], 200101);
+        assert_eq!(doc["time"], 1653008523500i64);
+        assert_eq!(doc["severity_id"], 4);
+        assert_eq!(doc["finding"]["title"], "Logon Failure");
+        assert_eq!(doc["analytic"]["uid"], "rule-id");
+        assert!(doc["unmapped"].get("record_offset").is_none());
+        assert_eq!(doc["resources"][0]["name"], "PC01");
+        assert_eq!(doc["attacks"].as_array().unwrap().len(), 2);
+        assert_eq!(doc["attacks"][1]["technique"]["uid"], "T1110.001");
+        assert_eq!(doc["attacks"][0]["tactics"][0]["name"], "Credential Access");
+        // 同じ検知結果のIDは同じになる
+        assert_eq!(
+            doc["finding"]["uid"],
+            to_ocsf(&time, &detect_info("rules/test.yml"), None, &rule)["finding"]["uid"]
+        );
+        assert!(to_ocsf(&time, &detect_info("-"), None, &RuleMeta::default())
+            .get("attacks")
+            .is_none());
+    }
+
+    #[test]
+    fn test_json_output() {
+        let path = "./test_files/test_json_output.jsonl";
+        assert!(JsonOutput::create(path, "splunk", RuleMetaColumns::default()).is_err());
+        let mut output =
+            JsonOutput::create(path, "hayabusa", RuleMetaColumns::parse("author").unwrap())
+                .unwrap();
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        output
+            .add(
+                &time,
+                "2022-05-20 01:02:03.500 +00:00",
+                "high",
+                &detect_info("-"),
+            )
+            .unwrap();
+        assert_eq!(output.flush().unwrap(), 1);
+        let content = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).ok();
+        let line: Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(line["EventID"], "4625");
+        assert_eq!(line["RecordID"], "12345");
+        assert_eq!(line["Timestamp"], "2022-05-20 01:02:03.500 +00:00");
+        assert!(line.get("RecordInformation").is_none());
+        assert_eq!(line["RuleAuthor"], "");
+        assert!(line.get("RuleDescription").is_none());
+    }
+}
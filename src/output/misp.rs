@@ -0,0 +1,314 @@
+use crate::detections::configs::LEVELMAP;
+use crate::detections::print::DetectInfo;
+use chrono::Local;
+use dotenv::dotenv;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::net::Ipv4Addr;
+
+// 属性のコメントに載せるルールのタイトルの件数
+const MAX_COMMENT_RULES: usize = 3;
+
+lazy_static! {
+    static ref IPV4_REGEX: Regex = Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}\b").unwrap();
+    static ref HASH_REGEX: Regex =
+        Regex::new(r"(?i)(?:\b(imphash)=)?\b([0-9a-f]{64}|[0-9a-f]{40}|[0-9a-f]{32})\b").unwrap();
+    static ref DOMAIN_REGEX: Regex =
+        Regex::new(r"(?i)(?:^|[^\w\\/.-])((?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z]{2,63})\b")
+            .unwrap();
+    static ref FILEPATH_REGEX: Regex =
+        Regex::new(r#"(?i)(?:[a-z]:\\|\\\\)[^"'|<>*?\r\n]*?\.[a-z0-9]{1,8}\b"#).unwrap();
+    // ドメインと区別するファイルの拡張子
+    static ref FILE_EXTENSIONS: BTreeSet<&'static str> = [
+        "exe", "dll", "sys", "ps1", "psm1", "bat", "cmd", "vbs", "js", "jse", "hta", "scr", "cpl",
+        "msi", "lnk", "txt", "log", "evtx", "tmp", "dat", "ini", "xml", "json", "zip", "bin",
+        "dmp", "csv", "yml", "yaml", "doc", "docx", "xls", "xlsx", "pdf", "ps", "py", "sh",
+    ]
+    .into_iter()
+    .collect();
+    // ドメインとして扱うトップレベルドメイン。.NETの型名(System.Net.WebClientなど)や社内のドメインを除くため
+    static ref KNOWN_TLDS: BTreeSet<&'static str> = concat!(
+        "com net org info biz edu gov mil int arpa io co me tv cc ws su app dev xyz top site online ",
+        "club shop store live tech cloud pro mobi name asia tel icu vip work link click buzz fun ",
+        "space website host press news email today world life win bid loan download stream ",
+        "ac ad ae af ag ai al am ao aq ar as at au aw ax az ba bb bd be bf bg bh bi bj bm bn bo br ",
+        "bs bt bw by bz ca cd cf cg ch ci ck cl cm cn cr cu cv cw cx cy cz de dj dk dm do dz ec ee ",
+        "eg er es et eu fi fj fk fm fo fr ga gd ge gf gg gh gi gl gm gn gp gq gr gs gt gu gw gy hk ",
+        "hm hn hr ht hu id ie il im in iq ir is it je jm jo jp ke kg kh ki km kn kp kr kw ky kz la ",
+        "lb lc li lk lr ls lt lu lv ly ma mc md mg mh mk ml mm mn mo mp mq mr ms mt mu mv mw mx my ",
+        "mz na nc ne nf ng ni nl no np nr nu nz om pa pe pf pg ph pk pl pm pn pr pt pw py qa re ro ",
+        "rs ru rw sa sb sc sd se sg sh si sk sl sm sn so sr ss st sv sx sy sz tc td tf tg th tj tk ",
+        "tl tm tn to tr tt tw tz ua ug uk us uy uz va vc ve vg vi vn vu wf ye yt za zm zw"
+    )
+    .split_whitespace()
+    .collect();
+}
+
+/// MISPの属性の種類と値
+type AttributeKey = (&'static str, String);
+
+/**
+* 検知結果の詳細からIPアドレス、ドメイン、ハッシュ値、ファイル名を抽出してMISPのイベントのJSONを作成する。
+* ハンティングの結果を脅威インテリジェンスのプラットフォームに登録するために使う。
+* 組織内の情報を共有しないように、インターネットで使われないIPアドレスやフォルダ名は含めない。検証していない値なので、属性のto_idsはfalseにする。
+*/
+#[derive(Debug, Default)]
+pub struct MispExporter {
+    // 属性と、その値を検知したルールのタイトル
+    attributes: BTreeMap<AttributeKey, BTreeSet<String>>,
+    max_level: u128,
+}
+
+impl MispExporter {
+    pub fn add(&mut self, detect_info: &DetectInfo) {
+        let level = *LEVELMAP
+            .get(&detect_info.level.to_uppercase())
+            .unwrap_or(&0);
+        self.max_level = self.max_level.max(level);
+        for key in extract_indicators(&detect_info.detail) {
+            self.attributes
+                .entry(key)
+                .or_default()
+                .insert(detect_info.alert.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    /// MISPのイベントのJSON。threat_level_idは検知の最も高いレベルから決める
+    pub fn event(&self) -> Value {
+        let threat_level_id = match self.max_level {
+            4 | 5 => "1",
+            3 => "2",
+            2 => "3",
+            _ => "4",
+        };
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|((attr_type, value), rules)| {
+                let mut comment: Vec<&str> = rules
+                    .iter()
+                    .take(MAX_COMMENT_RULES)
+                    .map(|rule| rule.as_str())
+                    .collect();
+                if rules.len() > MAX_COMMENT_RULES {
+                    comment.push("...");
+                }
+                json!({
+                    "type": attr_type,
+                    "category": category(attr_type),
+                    "value": value,
+                    "to_ids": false,
+                    "comment": format!("Hayabusa: {}", comment.join(", ")),
+                })
+            })
+            .collect();
+        json!({
+            "Event": {
+                "info": format!("Hayabusa detections {}", Local::now().format("%Y-%m-%d %H:%M:%S")),
+                "date": Local::now().format("%Y-%m-%d").to_string(),
+                "threat_level_id": threat_level_id,
+                // 0: Initial, 0: Your organisation only
+                "analysis": "0",
+                "distribution": "0",
+                "Tag": [{"name": "tool:hayabusa"}],
+                "Attribute": attributes,
+            }
+        })
+    }
+
+    /// MISPのイベントをJSONファイルに保存する。保存した属性の件数を返す
+    pub fn write(&self, path: &str) -> Result<usize, String> {
+        let event = serde_json::to_string_pretty(&self.event()).map_err(|e| e.to_string())?;
+        fs::write(path, event).map_err(|e| format!("{} [file:{}]", e, path))?;
+        Ok(self.attributes.len())
+    }
+
+    /// MISPのAPIでイベントを作成する。APIキーは--misp-keyまたは環境変数(.envファイル)のMISP_API_KEYから取得する
+    pub fn push(&self, url: &str, key: Option<&str>) -> Result<usize, String> {
+        dotenv().ok();
+        let key = match key {
+            Some(key) => key.to_string(),
+            None => env::var("MISP_API_KEY").map_err(|_| {
+                "MISP API key not found. Please specify --misp-key or MISP_API_KEY.".to_string()
+            })?,
+        };
+        let res = reqwest::blocking::Client::new()
+            .post(format!("{}/events/add", url.trim_end_matches('/')))
+            .header("Authorization", key)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .body(self.event().to_string())
+            .send()
+            .map_err(|e| e.to_string())?;
+        if res.status().is_success() {
+            Ok(self.attributes.len())
+        } else {
+            Err(format!("HTTP status: {}", res.status()))
+        }
+    }
+}
+
+/// 詳細からMISPの属性を抽出する
+fn extract_indicators(detail: &str) -> Vec<AttributeKey> {
+    let mut indicators = vec![];
+    for m in IPV4_REGEX.find_iter(detail) {
+        if let Ok(ip) = m.as_str().parse::<Ipv4Addr>() {
+            if is_routable(&ip) {
+                indicators.push((ip_type(&detail[..m.start()]), ip.to_string()));
+            }
+        }
+    }
+    for caps in HASH_REGEX.captures_iter(detail) {
+        let hash = caps[2].to_lowercase();
+        let attr_type = match (caps.get(1), hash.len()) {
+            (Some(_), _) => "imphash",
+            (None, 64) => "sha256",
+            (None, 40) => "sha1",
+            _ => "md5",
+        };
+        indicators.push((attr_type, hash));
+    }
+    let mut filepaths = vec![];
+    for m in FILEPATH_REGEX.find_iter(detail) {
+        filepaths.push(m.range());
+        // フォルダ名にはユーザー名などの組織内の情報が含まれるので、ファイル名だけを共有する
+        let filename = m.as_str().rsplit('\\').next().unwrap_or_default();
+        if !filename.is_empty() {
+            indicators.push(("filename", filename.to_string()));
+        }
+    }
+    for caps in DOMAIN_REGEX.captures_iter(detail) {
+        let domain = caps.get(1).unwrap();
+        // ファイルパスの一部とファイル名はドメインとして扱わない
+        if filepaths
+            .iter()
+            .any(|range| range.contains(&domain.start()))
+        {
+            continue;
+        }
+        if is_domain(domain.as_str()) {
+            indicators.push(("domain", domain.as_str().to_lowercase()));
+        }
+    }
+    indicators
+}
+
+// プライベート、ループバック、リンクローカル、ドキュメント用などのインターネットで使われないアドレスは共有しない
+fn is_routable(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || octets[0] == 0
+        || octets[0] >= 240
+        // 100.64.0.0/10 (Shared Address Space)
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        // 198.18.0.0/15 (ベンチマーク用)
+        || (octets[0] == 198 && (octets[1] & 0xfe) == 18))
+}
+
+// IPアドレスの直前のフィールド名から、送信元か宛先かを決める。分からない場合は宛先にする
+fn ip_type(before: &str) -> &'static str {
+    let field = before.rsplit(" : ").next().unwrap_or_default();
+    let name = field.split(':').next().unwrap_or_default().to_lowercase();
+    if name.contains("src") || name.contains("source") || name.contains("client") {
+        "ip-src"
+    } else {
+        "ip-dst"
+    }
+}
+
+// 既知のトップレベルドメインで終わり、.NETの型名のような大文字と小文字が混ざったラベルがないものだけをドメインとして扱う
+fn is_domain(domain: &str) -> bool {
+    let lowercase = domain.to_lowercase();
+    let tld = lowercase.rsplit('.').next().unwrap_or_default();
+    if FILE_EXTENSIONS.contains(tld) || !KNOWN_TLDS.contains(tld) {
+        return false;
+    }
+    !domain.split('.').any(|label| {
+        label.chars().any(|c| c.is_ascii_uppercase())
+            && label.chars().any(|c| c.is_ascii_lowercase())
+    })
+}
+
+fn category(attr_type: &str) -> &'static str {
+    match attr_type {
+        "ip-src" | "ip-dst" | "domain" => "Network activity",
+        "filename" => "Artifacts dropped",
+        _ => "Payload delivery",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::misp::{extract_indicators, MispExporter};
+
+    #[test]
+    fn test_extract_indicators() {
+        let indicators = extract_indicators(
+            "Cmd: C:\\Users\\alice\\AppData\\Local\\Temp\\evil.exe -c evil.example.com : SrcIP: 93.184.216.34 : DstIP: 8.8.8.8 : Hashes: SHA256=A3C2E1F4B5D6A7C8E9F0A1B2C3D4E5F6A7B8C9D0E1F2A3B4C5D6E7F8A9B0C1D2,IMPHASH=00112233445566778899AABBCCDDEEFF",
+        );
+        assert_eq!(
+            indicators,
+            vec![
+                ("ip-src", "93.184.216.34".to_string()),
+                ("ip-dst", "8.8.8.8".to_string()),
+                (
+                    "sha256",
+                    "a3c2e1f4b5d6a7c8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2".to_string()
+                ),
+                ("imphash", "00112233445566778899aabbccddeeff".to_string()),
+                ("filename", "evil.exe".to_string()),
+                ("domain", "evil.example.com".to_string()),
+            ]
+        );
+        assert!(extract_indicators("Image: svchost.exe : Script: payload.ps1").is_empty());
+        // 内部のアドレスや.NETの型名、社内のドメインは抽出しない
+        assert!(extract_indicators(
+            "SrcIP: 10.0.0.5 : DstIP: 127.0.0.1 : IP: 169.254.1.1 : Dst: 192.168.1.10 : Doc: 203.0.113.5"
+        )
+        .is_empty());
+        assert!(extract_indicators(
+            "ScriptBlock: New-Object System.Net.WebClient : [System.IO.File]::ReadAllBytes : Computer: pc01.contoso.local"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_misp_event() {
+        let mut exporter = MispExporter::default();
+        exporter.add(&DetectInfo {
+            filepath: "Sysmon.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "3".to_string(),
+            record_id: "1".to_string(),
+            channel: "Sysmon".to_string(),
+            alert: "Suspicious Connection".to_string(),
+            detail: "Dst: 8.8.4.4".to_string(),
+            tag_info: String::default(),
+            record_information: None,
+        });
+        let event = exporter.event();
+        assert_eq!(event["Event"]["threat_level_id"], "1");
+        let attribute = &event["Event"]["Attribute"][0];
+        assert_eq!(attribute["type"], "ip-dst");
+        assert_eq!(attribute["value"], "8.8.4.4");
+        assert_eq!(attribute["to_ids"], false);
+        assert_eq!(attribute["comment"], "Hayabusa: Suspicious Connection");
+    }
+}
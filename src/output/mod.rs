@@ -0,0 +1,18 @@
+pub mod anonymize;
+pub mod auto_name;
+pub mod csv_dialect;
+pub mod diff;
+pub mod html;
+pub mod json;
+pub mod misp;
+pub mod noise;
+pub mod output_filter;
+pub mod parquet;
+pub mod raw_xml;
+pub mod redaction;
+pub mod rule_count;
+pub mod rule_meta;
+pub mod rule_summary;
+pub mod sqlite;
+pub mod stix;
+pub mod xlsx;
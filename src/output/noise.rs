@@ -0,0 +1,279 @@
+use crate::detections::configs;
+use crate::detections::print::{AlertMessage, DetectInfo};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::BufWriter;
+use std::sync::Mutex;
+
+/// 同じ検知とみなすキー(ルールのファイルパス, 詳細)
+type NoiseKey = (String, String);
+
+// 件数を数えるCount-Min Sketchの行数と1行あたりのカウンタの数
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1 << 16;
+// 閾値を超えた組として記録する最大数。超えた場合は件数の少ない組から外す
+const MAX_NOISY_KEYS: usize = 10_000;
+
+lazy_static! {
+    /// --auto-tune-noiseで指定した、同じ検知を1行にまとめる件数の閾値
+    pub static ref AUTO_TUNE_NOISE_THRESHOLD: Option<usize> = match configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("auto-tune-noise")
+    {
+        Some(threshold) => match threshold.parse::<usize>() {
+            Ok(threshold) if threshold > 0 => Some(threshold),
+            _ => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Invalid --auto-tune-noise: {}", threshold),
+                )
+                .ok();
+                None
+            }
+        },
+        None => None,
+    };
+    /// スキャン中に数えたルールと詳細の組毎の検知の件数
+    pub static ref NOISE_COUNTER: Mutex<NoiseCounter> =
+        Mutex::new(NoiseCounter::new(AUTO_TUNE_NOISE_THRESHOLD.unwrap_or(usize::MAX)));
+}
+
+/**
+* ルールと詳細の組毎に検知の件数を数える。
+* --sortでディスクに書き出す検知結果も数えられるように、検知を登録する時に数える。
+* 詳細が全て異なる大量の検知でもメモリを使い切らないように、件数はCount-Min Sketchで数え、
+* 閾値を超えた組だけを件数の多い順にMAX_NOISY_KEYS個まで記録する。件数は実際より多くなることがある。
+*/
+#[derive(Debug, Default)]
+pub struct NoiseCounter {
+    threshold: usize,
+    max_keys: usize,
+    // SKETCH_DEPTH行SKETCH_WIDTH列のカウンタ。最初に数える時に確保する
+    sketch: Vec<u32>,
+    noisy: HashMap<NoiseKey, usize>,
+}
+
+impl NoiseCounter {
+    pub fn new(threshold: usize) -> NoiseCounter {
+        NoiseCounter {
+            threshold,
+            max_keys: MAX_NOISY_KEYS,
+            sketch: vec![],
+            noisy: HashMap::new(),
+        }
+    }
+
+    pub fn count(&mut self, detect_info: &DetectInfo) {
+        let key = noise_key(detect_info);
+        let count = self.add_to_sketch(&key);
+        if count <= self.threshold {
+            return;
+        }
+        if let Some(noisy_count) = self.noisy.get_mut(&key) {
+            *noisy_count = count;
+            return;
+        }
+        // 上限に達している場合は件数が最も少ない組と入れ替える
+        if self.noisy.len() >= self.max_keys {
+            let least = self
+                .noisy
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(key, count)| (key.clone(), *count));
+            match least {
+                Some((least_key, least_count)) if least_count < count => {
+                    self.noisy.remove(&least_key);
+                }
+                _ => return,
+            }
+        }
+        self.noisy.insert(key, count);
+    }
+
+    // 全ての行の対応するカウンタのうち最小のものだけを増やし(conservative update)、増やした後の件数を返す
+    fn add_to_sketch(&mut self, key: &NoiseKey) -> usize {
+        if self.sketch.is_empty() {
+            self.sketch = vec![0; SKETCH_DEPTH * SKETCH_WIDTH];
+        }
+        let cells = sketch_cells(key);
+        let count = cells
+            .iter()
+            .map(|cell| self.sketch[*cell])
+            .min()
+            .unwrap_or(0)
+            .saturating_add(1);
+        for cell in cells.iter() {
+            if self.sketch[*cell] < count {
+                self.sketch[*cell] = count;
+            }
+        }
+        count as usize
+    }
+
+    /// 閾値を超えた組だけを残して、出力時に1行にまとめるNoiseCollapserを作る
+    pub fn into_collapser(self, threshold: usize) -> NoiseCollapser {
+        let noisy = self
+            .noisy
+            .into_iter()
+            .filter(|(_, count)| *count > threshold)
+            .collect();
+        NoiseCollapser {
+            noisy,
+            emitted: HashMap::new(),
+        }
+    }
+}
+
+/// 閾値を超えた検知を最初の1件だけ件数付きで出力し、残りを出力しない
+#[derive(Debug, Default)]
+pub struct NoiseCollapser {
+    noisy: HashMap<NoiseKey, usize>,
+    // まとめた行を出力した組とルールのタイトル
+    emitted: HashMap<NoiseKey, String>,
+}
+
+impl NoiseCollapser {
+    /// まとめる検知の最初の1件は詳細に件数を付けて返し、2件目以降はNoneを返す
+    pub fn collapse<'a>(
+        &mut self,
+        detect_info: Cow<'a, DetectInfo>,
+    ) -> Option<Cow<'a, DetectInfo>> {
+        let key = noise_key(&detect_info);
+        let count = match self.noisy.get(&key) {
+            Some(count) => *count,
+            None => return Some(detect_info),
+        };
+        if self.emitted.contains_key(&key) {
+            return None;
+        }
+        self.emitted.insert(key, detect_info.alert.to_string());
+        let mut collapsed = detect_info.into_owned();
+        collapsed.detail = format!("{} : Collapsed: {} detections", collapsed.detail, count);
+        Some(Cow::Owned(collapsed))
+    }
+
+    /// まとめた検知のルールのタイトル、詳細、件数を件数の多い順で返す
+    pub fn collapsed(&self) -> Vec<(&str, &str, usize)> {
+        let mut collapsed: Vec<(&str, &str, usize)> = self
+            .emitted
+            .iter()
+            .map(|(key, title)| (title.as_str(), key.1.as_str(), self.noisy[key]))
+            .collect();
+        collapsed.sort_by(|x, y| y.2.cmp(&x.2).then_with(|| x.0.cmp(y.0)));
+        collapsed
+    }
+
+    /// まとめたことで出力しなかった検知の件数
+    pub fn hidden_count(&self) -> usize {
+        self.emitted.keys().map(|key| self.noisy[key] - 1).sum()
+    }
+}
+
+// キーのハッシュ値から各行のカウンタの位置を求める
+fn sketch_cells(key: &NoiseKey) -> [usize; SKETCH_DEPTH] {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    let (h1, h2) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+    let mut cells = [0; SKETCH_DEPTH];
+    for (row, cell) in cells.iter_mut().enumerate() {
+        *cell = row * SKETCH_WIDTH + h1.wrapping_add(row.wrapping_mul(h2)) % SKETCH_WIDTH;
+    }
+    cells
+}
+
+fn noise_key(detect_info: &DetectInfo) -> NoiseKey {
+    (
+        detect_info.rulepath.to_string(),
+        detect_info.detail.to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::noise::NoiseCounter;
+    use std::borrow::Cow;
+
+    fn detect_info(detail: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "test.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "low".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4624".to_string(),
+            record_id: "1".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon".to_string(),
+            detail: detail.to_string(),
+            tag_info: String::default(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_collapse_noise() {
+        let mut counter = NoiseCounter::new(2);
+        for _ in 0..3 {
+            counter.count(&detect_info("User: svc_backup"));
+        }
+        counter.count(&detect_info("User: alice"));
+        let mut collapser = counter.into_collapser(2);
+
+        let collapsed = collapser.collapse(Cow::Owned(detect_info("User: svc_backup")));
+        assert_eq!(
+            collapsed.unwrap().detail,
+            "User: svc_backup : Collapsed: 3 detections"
+        );
+        assert!(collapser
+            .collapse(Cow::Owned(detect_info("User: svc_backup")))
+            .is_none());
+        assert_eq!(
+            collapser
+                .collapse(Cow::Owned(detect_info("User: alice")))
+                .unwrap()
+                .detail,
+            "User: alice"
+        );
+        assert_eq!(
+            collapser.collapsed(),
+            vec![("Logon", "User: svc_backup", 3)]
+        );
+        assert_eq!(collapser.hidden_count(), 2);
+    }
+
+    #[test]
+    fn test_noise_counter_bounded() {
+        let mut counter = NoiseCounter::new(1);
+        counter.max_keys = 2;
+        for (detail, count) in [("User: a", 5), ("User: b", 3), ("User: c", 2)].iter() {
+            for _ in 0..*count {
+                counter.count(&detect_info(detail));
+            }
+        }
+        // 上限に達しているため、記録している組より件数が少ない組は記録しない
+        assert_eq!(counter.noisy.len(), 2);
+        assert!(!counter
+            .noisy
+            .contains_key(&("rules/test.yml".to_string(), "User: c".to_string())));
+
+        // 件数が多くなった組は件数が最も少ない組と入れ替える
+        for _ in 0..2 {
+            counter.count(&detect_info("User: c"));
+        }
+        let collapser = counter.into_collapser(1);
+        assert_eq!(
+            collapser
+                .noisy
+                .values()
+                .copied()
+                .collect::<std::collections::BTreeSet<_>>(),
+            [4, 5].iter().copied().collect()
+        );
+    }
+}
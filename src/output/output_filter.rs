@@ -0,0 +1,393 @@
+use crate::detections::configs;
+use crate::detections::print::DetectInfo;
+
+/// 条件に使える検知結果の列
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Computer,
+    Channel,
+    EventId,
+    RecordId,
+    Level,
+    RuleTitle,
+    Details,
+    MitreAttack,
+    RulePath,
+    FilePath,
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Field, String> {
+        match name.to_lowercase().as_str() {
+            "computer" => Ok(Field::Computer),
+            "channel" => Ok(Field::Channel),
+            "eventid" => Ok(Field::EventId),
+            "recordid" => Ok(Field::RecordId),
+            "level" => Ok(Field::Level),
+            "ruletitle" => Ok(Field::RuleTitle),
+            "details" => Ok(Field::Details),
+            "mitreattack" => Ok(Field::MitreAttack),
+            "rulepath" => Ok(Field::RulePath),
+            "filepath" => Ok(Field::FilePath),
+            _ => Err(format!("Unknown field: {}", name)),
+        }
+    }
+
+    fn value<'a>(&self, detect_info: &'a DetectInfo) -> &'a str {
+        match self {
+            Field::Computer => &detect_info.computername,
+            Field::Channel => &detect_info.channel,
+            Field::EventId => &detect_info.eventid,
+            Field::RecordId => &detect_info.record_id,
+            Field::Level => &detect_info.level,
+            Field::RuleTitle => &detect_info.alert,
+            Field::Details => &detect_info.detail,
+            Field::MitreAttack => &detect_info.tag_info,
+            Field::RulePath => &detect_info.rulepath,
+            Field::FilePath => &detect_info.filepath,
+        }
+    }
+
+    /// 大小を比較できる列の値を数値にする。Levelはinformationalからcriticalの順に1から5にする
+    fn number(&self, value: &str) -> Option<u128> {
+        match self {
+            Field::Level => {
+                let level = value.to_uppercase();
+                let level = if level == "INFO" {
+                    "INFORMATIONAL".to_string()
+                } else {
+                    level
+                };
+                configs::LEVELMAP.get(&level).copied()
+            }
+            Field::EventId | Field::RecordId => value.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+impl Operator {
+    fn parse(token: &str) -> Option<Operator> {
+        match token.to_lowercase().as_str() {
+            "==" => Some(Operator::Equal),
+            "!=" => Some(Operator::NotEqual),
+            ">" => Some(Operator::Greater),
+            ">=" => Some(Operator::GreaterEqual),
+            "<" => Some(Operator::Less),
+            "<=" => Some(Operator::LessEqual),
+            "contains" => Some(Operator::Contains),
+            "startswith" => Some(Operator::StartsWith),
+            "endswith" => Some(Operator::EndsWith),
+            _ => None,
+        }
+    }
+
+    fn is_ordering(&self) -> bool {
+        matches!(
+            self,
+            Operator::Greater | Operator::GreaterEqual | Operator::Less | Operator::LessEqual
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        field: Field,
+        operator: Operator,
+        value: String,
+        // 大小を比較する場合の値
+        number: Option<u128>,
+    },
+}
+
+impl Expr {
+    fn is_match(&self, detect_info: &DetectInfo) -> bool {
+        match self {
+            Expr::And(left, right) => left.is_match(detect_info) && right.is_match(detect_info),
+            Expr::Or(left, right) => left.is_match(detect_info) || right.is_match(detect_info),
+            Expr::Not(expr) => !expr.is_match(detect_info),
+            Expr::Compare {
+                field,
+                operator,
+                value,
+                number,
+            } => {
+                let actual = field.value(detect_info);
+                if let Some(expected) = number {
+                    let actual = match field.number(actual) {
+                        Some(actual) => actual,
+                        None => return *operator == Operator::NotEqual,
+                    };
+                    return match operator {
+                        Operator::Equal => actual == *expected,
+                        Operator::NotEqual => actual != *expected,
+                        Operator::Greater => actual > *expected,
+                        Operator::GreaterEqual => actual >= *expected,
+                        Operator::Less => actual < *expected,
+                        Operator::LessEqual => actual <= *expected,
+                        _ => false,
+                    };
+                }
+                // 文字列の比較では大文字と小文字を区別しない
+                let actual = actual.to_lowercase();
+                match operator {
+                    Operator::Equal => actual == *value,
+                    Operator::NotEqual => actual != *value,
+                    Operator::Contains => actual.contains(value.as_str()),
+                    Operator::StartsWith => actual.starts_with(value.as_str()),
+                    Operator::EndsWith => actual.ends_with(value.as_str()),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    Word(String),
+    // 引用符で囲んだ値。and、orなどのキーワードとして扱わない
+    Quoted(String),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LeftParen),
+            ')' => tokens.push(Token::RightParen),
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(next) if next == c => break,
+                        Some(next) => value.push(next),
+                        None => return Err(format!("Unclosed quote: {}{}", c, value)),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '=' | '!' | '<' | '>' => {
+                let mut operator = c.to_string();
+                if chars.peek() == Some(&'=') {
+                    operator.push(chars.next().unwrap());
+                }
+                if operator == "=" || operator == "!" {
+                    return Err(format!("Unknown operator: {}", operator));
+                }
+                tokens.push(Token::Word(operator));
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "()\"'=!<>".contains(*next) {
+                        break;
+                    }
+                    word.push(chars.next().unwrap());
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.is_keyword("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.is_keyword("and") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.is_keyword("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        match self.next_token() {
+            Some(Token::LeftParen) => {
+                let expr = self.parse_or()?;
+                match self.next_token() {
+                    Some(Token::RightParen) => Ok(expr),
+                    _ => Err("Missing closing parenthesis.".to_string()),
+                }
+            }
+            Some(Token::Word(name)) => self.parse_compare(&name),
+            Some(token) => Err(format!("Unexpected token: {:?}", token)),
+            None => Err("Unexpected end of the expression.".to_string()),
+        }
+    }
+
+    fn parse_compare(&mut self, name: &str) -> Result<Expr, String> {
+        let field = Field::parse(name)?;
+        let operator = match self.next_token() {
+            Some(Token::Word(word)) => {
+                Operator::parse(&word).ok_or_else(|| format!("Unknown operator: {}", word))?
+            }
+            _ => return Err(format!("Missing operator after {}.", name)),
+        };
+        let value = match self.next_token() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+            _ => return Err(format!("Missing value after {} {:?}.", name, operator)),
+        };
+        let number = field.number(&value);
+        if operator.is_ordering() && number.is_none() {
+            return Err(format!(
+                "{} {} cannot be compared. Only Level, EventID and RecordID can be compared with <, <=, > and >=.",
+                name, value
+            ));
+        }
+        Ok(Expr::Compare {
+            field,
+            operator,
+            value: value.to_lowercase(),
+            number: if operator == Operator::Equal || operator == Operator::NotEqual {
+                // Levelはinfoとinformationalのように表記が異なっても同じレベルとして比較する
+                number.filter(|_| field == Field::Level)
+            } else {
+                number
+            },
+        })
+    }
+}
+
+/**
+* --filter-outputで指定した条件に一致する検知だけを出力する。
+* 全体を再スキャンせずに、特定のホストやレベルに絞った出力を作れるようにするため。
+* (例: Computer == "DC01" and Level >= high)
+*/
+#[derive(Debug, Clone)]
+pub struct OutputFilter {
+    expr: Expr,
+}
+
+impl OutputFilter {
+    pub fn parse(expression: &str) -> Result<OutputFilter, String> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            pos: 0,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.next_token() {
+            return Err(format!("Unexpected token: {:?}", token));
+        }
+        Ok(OutputFilter { expr })
+    }
+
+    pub fn from_config() -> Result<Option<OutputFilter>, String> {
+        match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("filter-output")
+        {
+            Some(expression) => OutputFilter::parse(expression)
+                .map(Some)
+                .map_err(|err| format!("Invalid --filter-output: {}", err)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn is_match(&self, detect_info: &DetectInfo) -> bool {
+        self.expr.is_match(detect_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::output_filter::OutputFilter;
+
+    fn detect_info(computer: &str, level: &str, eventid: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: level.to_string(),
+            computername: computer.to_string(),
+            eventid: eventid.to_string(),
+            record_id: "1".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Suspicious Logon".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: String::default(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_output_filter() {
+        let filter = OutputFilter::parse(r#"Computer == "DC01" and Level >= high"#).unwrap();
+        assert!(filter.is_match(&detect_info("dc01", "critical", "4624")));
+        assert!(!filter.is_match(&detect_info("DC01", "medium", "4624")));
+        assert!(!filter.is_match(&detect_info("PC01", "high", "4624")));
+
+        let filter = OutputFilter::parse(
+            "not (EventID == 4624 or EventID > 5000) and RuleTitle contains 'logon'",
+        )
+        .unwrap();
+        assert!(filter.is_match(&detect_info("PC01", "low", "4625")));
+        assert!(!filter.is_match(&detect_info("PC01", "low", "4624")));
+        assert!(!filter.is_match(&detect_info("PC01", "low", "5140")));
+
+        let filter = OutputFilter::parse("Level == info").unwrap();
+        assert!(filter.is_match(&detect_info("PC01", "informational", "4624")));
+    }
+
+    #[test]
+    fn test_invalid_output_filter() {
+        assert!(OutputFilter::parse("Host == DC01").is_err());
+        assert!(OutputFilter::parse("Computer = DC01").is_err());
+        assert!(OutputFilter::parse("Computer >= DC01").is_err());
+        assert!(OutputFilter::parse("(Level == high").is_err());
+        assert!(OutputFilter::parse("Level == high DC01").is_err());
+        assert!(OutputFilter::parse("Computer == 'DC01").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::detections::print::DetectInfo;
+use arrow_array::builder::{StringBuilder, TimestampMillisecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::sync::Arc;
+
+// 1つの行グループにまとめる検知結果の件数。メモリ使用量を抑えるためこの件数ごとにファイルへ書き出す
+const ROW_GROUP_SIZE: usize = 65536;
+
+// CSVの列と同じ名前にする。Timestampだけは範囲指定で絞り込めるようにタイムスタンプ型で保存する
+const STRING_COLUMNS: [&str; 11] = [
+    "Computer",
+    "Channel",
+    "EventID",
+    "RecordID",
+    "Level",
+    "MitreAttack",
+    "RuleTitle",
+    "Details",
+    "RecordInformation",
+    "RulePath",
+    "FilePath",
+];
+
+/**
+* --output-parquetで指定したファイルに検知結果をParquet形式で書き込む。
+* DuckDB、Spark、Athenaなどで大量の検知結果を扱えるように列指向で圧縮して保存する。
+*/
+pub struct ParquetOutput {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    timestamps: TimestampMillisecondBuilder,
+    columns: Vec<StringBuilder>,
+    buffered: usize,
+    pub written: usize,
+}
+
+impl ParquetOutput {
+    pub fn create(path: &str) -> Result<ParquetOutput, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create the Parquet file {}. {}", path, e))?;
+        let mut fields = vec![Field::new(
+            "Timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        )];
+        fields.extend(STRING_COLUMNS.iter().map(|name| {
+            // RecordInformationは-Fを指定した場合だけ値があるのでnullを許容する
+            Field::new(*name, DataType::Utf8, *name == "RecordInformation")
+        }));
+        let schema = Arc::new(Schema::new(fields));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let writer =
+            ArrowWriter::try_new(file, schema.clone(), Some(props)).map_err(|e| e.to_string())?;
+        Ok(ParquetOutput {
+            writer,
+            schema,
+            timestamps: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            columns: STRING_COLUMNS
+                .iter()
+                .map(|_| StringBuilder::new())
+                .collect(),
+            buffered: 0,
+            written: 0,
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        time: &DateTime<Utc>,
+        level: &str,
+        detect_info: &DetectInfo,
+    ) -> Result<(), String> {
+        self.timestamps.append_value(time.timestamp_millis());
+        let values = [
+            Some(detect_info.computername.as_str()),
+            Some(detect_info.channel.as_str()),
+            Some(detect_info.eventid.as_str()),
+            Some(detect_info.record_id.as_str()),
+            Some(level),
+            Some(detect_info.tag_info.as_str()),
+            Some(detect_info.alert.as_str()),
+            Some(detect_info.detail.as_str()),
+            detect_info.record_information.as_deref(),
+            Some(detect_info.rulepath.as_str()),
+            Some(detect_info.filepath.as_str()),
+        ];
+        for (column, value) in self.columns.iter_mut().zip(values) {
+            column.append_option(value);
+        }
+        self.buffered += 1;
+        if self.buffered >= ROW_GROUP_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// 残りの検知結果を書き込んでファイルを閉じる
+    pub fn close(mut self) -> Result<usize, String> {
+        self.write_batch()?;
+        self.writer.close().map_err(|e| e.to_string())?;
+        Ok(self.written)
+    }
+
+    fn write_batch(&mut self) -> Result<(), String> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(self.timestamps.finish())];
+        arrays.extend(
+            self.columns
+                .iter_mut()
+                .map(|column| Arc::new(column.finish()) as ArrayRef),
+        );
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays).map_err(|e| e.to_string())?;
+        self.writer.write(&batch).map_err(|e| e.to_string())?;
+        self.written += self.buffered;
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::parquet::ParquetOutput;
+    use arrow_array::{Array, StringArray, TimestampMillisecondArray};
+    use chrono::{TimeZone, Utc};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_write_parquet() {
+        let path = "./test_files/test_write_parquet.parquet";
+        let mut output = ParquetOutput::create(path).unwrap();
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        for computer in ["PC01", "PC02"] {
+            let detect_info = DetectInfo {
+                filepath: "Security.evtx".to_string(),
+                rulepath: "rules/test.yml".to_string(),
+                level: "informational".to_string(),
+                computername: computer.to_string(),
+                eventid: "4624".to_string(),
+                record_id: "12345".to_string(),
+                channel: "Sec".to_string(),
+                alert: "Logon".to_string(),
+                detail: "User: admin".to_string(),
+                tag_info: "".to_string(),
+                record_information: None,
+            };
+            output.add(&time, "info", &detect_info).unwrap();
+        }
+        assert_eq!(output.close().unwrap(), 2);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        fs::remove_file(path).ok();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let timestamps = batch
+            .column_by_name("Timestamp")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(timestamps.value(0), time.timestamp_millis());
+        let computers = batch
+            .column_by_name("Computer")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(computers.value(1), "PC02");
+        let levels = batch
+            .column_by_name("Level")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(levels.value(0), "info");
+        assert!(batch
+            .column_by_name("RecordInformation")
+            .unwrap()
+            .is_null(0));
+    }
+}
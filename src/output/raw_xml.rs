@@ -0,0 +1,165 @@
+use crate::detections::print::DetectInfo;
+use crate::input;
+use crate::output::anonymize;
+use hashbrown::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 検知結果とXMLファイルの対応を書き込むファイル
+const INDEX_FILE_NAME: &str = "index.csv";
+
+/**
+* --raw-xmlで指定したディレクトリに、検知したレコードのXMLをevtxファイルから取り出して保存する。
+* 同じレコードを複数のルールで検知した場合も、XMLファイルは1つだけ保存する。
+*/
+pub struct RawXmlExporter {
+    dir: PathBuf,
+    // evtxファイル毎の保存するレコードのEventRecordID
+    targets: HashMap<String, HashSet<u64>>,
+    index: Vec<[String; 7]>,
+}
+
+impl RawXmlExporter {
+    pub fn new(dir: &str) -> RawXmlExporter {
+        RawXmlExporter {
+            dir: PathBuf::from(dir),
+            targets: HashMap::new(),
+            index: vec![],
+        }
+    }
+
+    /// evtxファイルのパスとEventRecordIDから検知IDを作成する。ファイル名に使えるようにパスはハッシュ値にする
+    pub fn detection_id(filepath: &str, record_id: u64) -> String {
+        let hash = hex::encode(Sha256::digest(filepath.as_bytes()));
+        format!("{}-{}", &hash[..12], record_id)
+    }
+
+    /// 集計条件のルールや標準入力から読み込んだレコードなど、evtxファイルから読み直せないものは対象外にする
+    pub fn add(&mut self, timestamp: &str, detect_info: &DetectInfo) {
+        let record_id = match detect_info.record_id.parse::<u64>() {
+            Ok(record_id) => record_id,
+            Err(_) => return,
+        };
+        if input::is_stdin(Path::new(&detect_info.filepath)) {
+            return;
+        }
+        self.targets
+            .entry(detect_info.filepath.to_string())
+            .or_default()
+            .insert(record_id);
+        self.index.push([
+            RawXmlExporter::detection_id(&detect_info.filepath, record_id),
+            timestamp.to_string(),
+            detect_info.computername.to_string(),
+            detect_info.alert.to_string(),
+            detect_info.rulepath.to_string(),
+            detect_info.filepath.to_string(),
+            record_id.to_string(),
+        ]);
+    }
+
+    /// evtxファイルを読み直して対象のレコードのXMLを保存し、保存したファイル数を返す
+    pub fn write(&self) -> Result<usize, String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let mut written = 0;
+        let mut errors = vec![];
+        for (filepath, record_ids) in self.targets.iter() {
+            match self.write_file_records(filepath, record_ids) {
+                Ok(count) => written += count,
+                Err(err) => errors.push(err),
+            }
+        }
+        self.write_index().map_err(|e| e.to_string())?;
+        if errors.is_empty() {
+            Ok(written)
+        } else {
+            Err(errors.join(" "))
+        }
+    }
+
+    fn write_file_records(
+        &self,
+        filepath: &str,
+        record_ids: &HashSet<u64>,
+    ) -> Result<usize, String> {
+        let mut parser = input::open_evtx(Path::new(filepath))?;
+        let mut written = 0;
+        for record in parser.records().flatten() {
+            if !record_ids.contains(&record.event_record_id) {
+                continue;
+            }
+            let path = self.dir.join(format!(
+                "{}.xml",
+                RawXmlExporter::detection_id(filepath, record.event_record_id)
+            ));
+            // --anonymizeが指定されている場合はXMLのユーザー名なども仮名に置き換える
+            fs::write(path, anonymize::anonymize_output(&record.data))
+                .map_err(|e| e.to_string())?;
+            written += 1;
+            if written == record_ids.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn write_index(&self) -> Result<(), csv::Error> {
+        let mut wtr = csv::Writer::from_path(self.dir.join(INDEX_FILE_NAME))?;
+        wtr.write_record(&[
+            "DetectionID",
+            "Timestamp",
+            "Computer",
+            "RuleTitle",
+            "RulePath",
+            "FilePath",
+            "RecordID",
+        ])?;
+        for row in self.index.iter() {
+            wtr.write_record(row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::raw_xml::RawXmlExporter;
+
+    fn detect_info(filepath: &str, record_id: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: filepath.to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4625".to_string(),
+            record_id: record_id.to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut exporter = RawXmlExporter::new("./test_files/raw_xml");
+        exporter.add("2022-05-20", &detect_info("Security.evtx", "100"));
+        exporter.add("2022-05-20", &detect_info("Security.evtx", "100"));
+        exporter.add("2022-05-20", &detect_info("Security.evtx", "101"));
+        exporter.add("2022-05-20", &detect_info("-", "-"));
+        exporter.add("2022-05-20", &detect_info("-", "102"));
+        assert_eq!(exporter.targets.len(), 1);
+        assert_eq!(exporter.targets["Security.evtx"].len(), 2);
+        assert_eq!(exporter.index.len(), 3);
+        assert_eq!(exporter.index[0][0], exporter.index[1][0]);
+        assert!(exporter.index[0][0].ends_with("-100"));
+        assert_ne!(
+            RawXmlExporter::detection_id("Security.evtx", 100),
+            RawXmlExporter::detection_id("System.evtx", 100)
+        );
+    }
+}
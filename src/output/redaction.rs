@@ -0,0 +1,88 @@
+use crate::detections::print::DetectInfo;
+use crate::detections::utils;
+use regex::Regex;
+
+pub const REDACTION_RULES_CONFIG: &str = "redaction_rules.txt";
+
+/// 正規表現と置換後の文字列。置換後の文字列では$1のようにキャプチャグループを使える
+#[derive(Debug)]
+pub struct RedactionRule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+/**
+* --redactが指定されている場合に、config/redaction_rules.txtの正規表現に一致する検知結果の詳細とレコードの情報を置き換える。
+* メールアドレスや社員番号のような個人情報を隠すためのもので、--anonymizeとは別に適用する。
+*/
+#[derive(Debug, Default)]
+pub struct Redactor {
+    rules: Vec<RedactionRule>,
+}
+
+impl Redactor {
+    pub fn new(rules: Vec<RedactionRule>) -> Redactor {
+        Redactor { rules }
+    }
+
+    /// 設定ファイルを読み込む。正規表現が不正な行があった場合はエラーにする
+    pub fn load(path: &str) -> Result<Redactor, String> {
+        let mut rules = vec![];
+        for (idx, line) in utils::read_csv(path)?.into_iter().enumerate() {
+            if line.is_empty() || line[0].trim().is_empty() {
+                continue;
+            }
+            let pattern = Regex::new(&line[0]).map_err(|e| {
+                // ヘッダー行の次が2行目
+                format!("Invalid regex at line {}. {} [file:{}]", idx + 2, e, path)
+            })?;
+            rules.push(RedactionRule {
+                pattern,
+                replacement: line.get(1).cloned().unwrap_or_default(),
+            });
+        }
+        Ok(Redactor::new(rules))
+    }
+
+    pub fn redact_text(&self, text: &str) -> String {
+        self.rules.iter().fold(text.to_string(), |text, rule| {
+            rule.pattern
+                .replace_all(&text, rule.replacement.as_str())
+                .to_string()
+        })
+    }
+
+    /// 検知結果の詳細とレコードの情報を置き換える
+    pub fn redact(&self, detect_info: &DetectInfo) -> DetectInfo {
+        let mut detect_info = detect_info.clone();
+        detect_info.detail = self.redact_text(&detect_info.detail);
+        detect_info.record_information = detect_info
+            .record_information
+            .map(|recinfo| self.redact_text(&recinfo));
+        detect_info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::redaction::{RedactionRule, Redactor};
+    use regex::Regex;
+
+    #[test]
+    fn test_redact_text() {
+        let redactor = Redactor::new(vec![
+            RedactionRule {
+                pattern: Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(),
+                replacement: "<email>".to_string(),
+            },
+            RedactionRule {
+                pattern: Regex::new(r"EMP(\d{2})\d{4}").unwrap(),
+                replacement: "EMP${1}XXXX".to_string(),
+            },
+        ]);
+        assert_eq!(
+            redactor.redact_text("User: alice@corp.example : ID: EMP123456 : Path: C:\\Temp"),
+            "User: <email> : ID: EMP12XXXX : Path: C:\\Temp"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::detections::configs;
+use crate::detections::print::DetectInfo;
+use hashbrown::{HashMap, HashSet};
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+const LEVELS: [&str; 5] = ["critical", "high", "medium", "low", "informational"];
+
+lazy_static! {
+    /// --min-rule-countと--max-rule-countで指定したルール毎の検知件数の閾値。不正な値は解析の前にfrom_configでエラーにする
+    pub static ref RULE_COUNT_FILTER: Option<RuleCountFilter> =
+        RuleCountFilter::from_config().ok().flatten();
+    /// スキャン中に数えたルール毎の検知の件数
+    pub static ref RULE_HIT_COUNTER: Mutex<RuleHitCounter> =
+        Mutex::new(RuleHitCounter::default());
+}
+
+/**
+* ルール毎の検知件数の閾値。"5"のように全てのlevelに同じ閾値を指定するか、
+* "low:10,medium:3"のようにlevel毎に指定する。"5,informational:100"のように組み合わせることもできる。
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct RuleCountThreshold {
+    default: Option<usize>,
+    levels: HashMap<String, usize>,
+}
+
+impl RuleCountThreshold {
+    pub fn parse(value: &str) -> Result<RuleCountThreshold, String> {
+        let mut threshold = RuleCountThreshold::default();
+        for item in value.split(',').map(|item| item.trim()) {
+            let (level, count) = match item.split_once(':') {
+                Some((level, count)) => (Some(normalize_level(level)?), count),
+                None => (None, item),
+            };
+            let count = count
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("{} is not a number.", count.trim()))?;
+            match level {
+                Some(level) => {
+                    threshold.levels.insert(level, count);
+                }
+                None => threshold.default = Some(count),
+            }
+        }
+        Ok(threshold)
+    }
+
+    /// levelの閾値。levelの指定がない場合は全てのlevelの閾値を返す
+    fn get(&self, level: &str) -> Option<usize> {
+        self.levels
+            .get(&level.to_lowercase())
+            .copied()
+            .or(self.default)
+    }
+}
+
+// infoはinformationalとして扱う
+fn normalize_level(level: &str) -> Result<String, String> {
+    let level = level.trim().to_lowercase();
+    let level = if level == "info" {
+        "informational".to_string()
+    } else {
+        level
+    };
+    if LEVELS.contains(&level.as_str()) {
+        Ok(level)
+    } else {
+        Err(format!("{} is not a valid level.", level))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RuleCountFilter {
+    min: Option<RuleCountThreshold>,
+    max: Option<RuleCountThreshold>,
+}
+
+impl RuleCountFilter {
+    pub fn from_config() -> Result<Option<RuleCountFilter>, String> {
+        let args = &configs::CONFIG.read().unwrap().args;
+        let parse = |option: &str| match args.value_of(option) {
+            Some(value) => RuleCountThreshold::parse(value)
+                .map(Some)
+                .map_err(|err| format!("Invalid --{}: {}", option, err)),
+            None => Ok(None),
+        };
+        let filter = RuleCountFilter {
+            min: parse("min-rule-count")?,
+            max: parse("max-rule-count")?,
+        };
+        if filter.min.is_none() && filter.max.is_none() {
+            Ok(None)
+        } else {
+            Ok(Some(filter))
+        }
+    }
+
+    /// ルールの検知件数が最小値以上かつ最大値以下の場合だけ出力する
+    fn is_reported(&self, level: &str, count: usize) -> bool {
+        let min = self.min.as_ref().and_then(|min| min.get(level));
+        let max = self.max.as_ref().and_then(|max| max.get(level));
+        min.map_or(true, |min| count >= min) && max.map_or(true, |max| count <= max)
+    }
+}
+
+/**
+* ルール毎に検知の件数を数える。
+* --sortでディスクに書き出す検知結果も数えられるように、検知を登録する時に数える。
+*/
+#[derive(Debug, Default)]
+pub struct RuleHitCounter {
+    counts: HashMap<String, usize>,
+}
+
+impl RuleHitCounter {
+    pub fn count(&mut self, detect_info: &DetectInfo) {
+        *self
+            .counts
+            .entry(detect_info.rulepath.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// 数えた件数と閾値で、出力時に検知を除外するRuleCountSuppressorを作る
+    pub fn into_suppressor(self, filter: &RuleCountFilter) -> RuleCountSuppressor<'_> {
+        RuleCountSuppressor {
+            filter,
+            counts: self.counts,
+            suppressed_rules: HashSet::new(),
+            suppressed: 0,
+        }
+    }
+}
+
+/// 閾値の範囲外のルールの検知を出力しない
+#[derive(Debug)]
+pub struct RuleCountSuppressor<'a> {
+    filter: &'a RuleCountFilter,
+    counts: HashMap<String, usize>,
+    suppressed_rules: HashSet<String>,
+    suppressed: usize,
+}
+
+impl<'a> RuleCountSuppressor<'a> {
+    pub fn is_reported(&mut self, detect_info: &DetectInfo) -> bool {
+        let count = self.counts.get(&detect_info.rulepath).copied().unwrap_or(0);
+        if self.filter.is_reported(&detect_info.level, count) {
+            return true;
+        }
+        self.suppressed_rules
+            .insert(detect_info.rulepath.to_string());
+        self.suppressed += 1;
+        false
+    }
+
+    /// 閾値の範囲外で出力しなかったルールの数と検知の件数
+    pub fn suppressed_counts(&self) -> (usize, usize) {
+        (self.suppressed_rules.len(), self.suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::rule_count::{RuleCountFilter, RuleCountThreshold, RuleHitCounter};
+
+    fn detect_info(rulepath: &str, level: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "test.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: level.to_string(),
+            computername: "PC01".to_string(),
+            eventid: "4624".to_string(),
+            record_id: "1".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon".to_string(),
+            detail: "detail".to_string(),
+            tag_info: String::default(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_threshold() {
+        let threshold = RuleCountThreshold::parse("5, info:100,Low:10").unwrap();
+        assert_eq!(threshold.get("informational"), Some(100));
+        assert_eq!(threshold.get("low"), Some(10));
+        assert_eq!(threshold.get("high"), Some(5));
+        assert_eq!(
+            RuleCountThreshold::parse("low:10").unwrap().get("high"),
+            None
+        );
+        assert!(RuleCountThreshold::parse("severe:1").is_err());
+        assert!(RuleCountThreshold::parse("low:many").is_err());
+    }
+
+    #[test]
+    fn test_rule_count_suppressor() {
+        let filter = RuleCountFilter {
+            min: Some(RuleCountThreshold::parse("low:2").unwrap()),
+            max: Some(RuleCountThreshold::parse("informational:3").unwrap()),
+        };
+        let mut counter = RuleHitCounter::default();
+        counter.count(&detect_info("rare_low.yml", "low"));
+        for _ in 0..2 {
+            counter.count(&detect_info("low.yml", "low"));
+        }
+        for _ in 0..4 {
+            counter.count(&detect_info("noisy.yml", "informational"));
+        }
+        counter.count(&detect_info("high.yml", "high"));
+        let mut suppressor = counter.into_suppressor(&filter);
+
+        assert!(!suppressor.is_reported(&detect_info("rare_low.yml", "low")));
+        assert!(suppressor.is_reported(&detect_info("low.yml", "low")));
+        assert!(!suppressor.is_reported(&detect_info("noisy.yml", "informational")));
+        assert!(!suppressor.is_reported(&detect_info("noisy.yml", "informational")));
+        assert!(suppressor.is_reported(&detect_info("high.yml", "high")));
+        assert_eq!(suppressor.suppressed_counts(), (2, 3));
+    }
+}
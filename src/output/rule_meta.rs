@@ -0,0 +1,257 @@
+use crate::detections::configs;
+use hashbrown::HashMap;
+use serde::Serialize;
+use std::fs;
+use yaml_rust::{Yaml, YamlLoader};
+
+// --rule-metadataで指定できる列。all を指定した場合は全て出力する
+const RULE_METADATA_FIELDS: [&str; 6] = [
+    "description",
+    "references",
+    "falsepositives",
+    "author",
+    "date",
+    "modified",
+];
+
+/// 検知結果のDetectInfoに含まれないルールの情報
+#[derive(Debug, Default, Clone)]
+pub struct RuleMeta {
+    pub id: Option<String>,
+    pub tactics: Vec<String>,
+    pub techniques: Vec<String>,
+    pub description: String,
+    pub references: String,
+    pub falsepositives: String,
+    pub author: String,
+    pub date: String,
+    pub modified: String,
+}
+
+impl RuleMeta {
+    /// ルールファイルを読み込み、idとtagsからATT&CKのタクティクス名とテクニックIDを取り出す
+    pub fn load(rulepath: &str) -> RuleMeta {
+        let yaml = match fs::read_to_string(rulepath)
+            .ok()
+            .and_then(|s| YamlLoader::load_from_str(&s).ok())
+        {
+            Some(docs) if !docs.is_empty() => docs[0].clone(),
+            _ => return RuleMeta::default(),
+        };
+        let mut meta = RuleMeta {
+            id: yaml["id"].as_str().map(|id| id.to_string()),
+            description: yaml_to_string(&yaml["description"]),
+            references: yaml_to_string(&yaml["references"]),
+            falsepositives: yaml_to_string(&yaml["falsepositives"]),
+            author: yaml_to_string(&yaml["author"]),
+            date: yaml_to_string(&yaml["date"]),
+            modified: yaml_to_string(&yaml["modified"]),
+            ..Default::default()
+        };
+        for tag in yaml["tags"].as_vec().unwrap_or(&Vec::default()) {
+            let name = match tag.as_str().and_then(|tag| tag.strip_prefix("attack.")) {
+                Some(name) => name,
+                None => continue,
+            };
+            // t1110のようなテクニックの他に、g0007(グループ)やs0002(ソフトウェア)のIDがある
+            let is_id = name.chars().nth(1).map_or(false, |c| c.is_ascii_digit());
+            if is_id && name.starts_with('t') {
+                meta.techniques.push(name.to_uppercase());
+            } else if !is_id {
+                // credential_access -> Credential Access
+                meta.tactics.push(
+                    name.split('_')
+                        .map(|word| {
+                            let mut chars = word.chars();
+                            chars.next().map_or(String::default(), |c| {
+                                c.to_uppercase().collect::<String>() + chars.as_str()
+                            })
+                        })
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                );
+            }
+        }
+        meta
+    }
+}
+
+/// 文字列と数値はそのまま、リストはtag_infoと同じく" | "で連結して1つの値にする
+fn yaml_to_string(yaml: &Yaml) -> String {
+    match yaml {
+        Yaml::String(s) => s.trim().to_string(),
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Real(r) => r.to_string(),
+        Yaml::Array(values) => values
+            .iter()
+            .map(yaml_to_string)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<String>>()
+            .join(" | "),
+        _ => String::default(),
+    }
+}
+
+/// 同じルールファイルを何度も読み込まないようにルールのパス毎にキャッシュする
+#[derive(Debug, Default)]
+pub struct RuleMetaCache {
+    rules: HashMap<String, RuleMeta>,
+}
+
+impl RuleMetaCache {
+    pub fn new() -> RuleMetaCache {
+        RuleMetaCache::default()
+    }
+
+    pub fn get(&mut self, rulepath: &str) -> &RuleMeta {
+        self.rules
+            .entry(rulepath.to_string())
+            .or_insert_with(|| RuleMeta::load(rulepath))
+    }
+}
+
+/**
+* --rule-metadataで指定したルールの情報の列。
+* タイムラインを見た人がルールを調べなくても検知結果を評価できるように、ルールの説明や作成者などを出力する。
+*/
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuleMetaColumns {
+    pub description: bool,
+    pub references: bool,
+    pub falsepositives: bool,
+    pub author: bool,
+    pub date: bool,
+    pub modified: bool,
+}
+
+impl RuleMetaColumns {
+    /// カンマ区切りの列名を読み込む
+    pub fn parse(fields: &str) -> Result<RuleMetaColumns, String> {
+        let mut columns = RuleMetaColumns::default();
+        for field in fields.split(',').map(|f| f.trim().to_lowercase()) {
+            match field.as_str() {
+                "all" => {
+                    return Ok(RuleMetaColumns {
+                        description: true,
+                        references: true,
+                        falsepositives: true,
+                        author: true,
+                        date: true,
+                        modified: true,
+                    })
+                }
+                "description" => columns.description = true,
+                "references" => columns.references = true,
+                "falsepositives" => columns.falsepositives = true,
+                "author" => columns.author = true,
+                "date" => columns.date = true,
+                "modified" => columns.modified = true,
+                _ => {
+                    return Err(format!(
+                        "Invalid rule metadata field: {}. Please specify all or one or more of {}.",
+                        field,
+                        RULE_METADATA_FIELDS.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(columns)
+    }
+
+    pub fn from_config() -> Result<RuleMetaColumns, String> {
+        match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("rule-metadata")
+        {
+            Some(fields) => RuleMetaColumns::parse(fields),
+            None => Ok(RuleMetaColumns::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RuleMetaColumns::default()
+    }
+
+    /// 出力しない列はNoneにする
+    pub fn select<'a>(&self, meta: &'a RuleMeta) -> SelectedRuleMeta<'a> {
+        SelectedRuleMeta {
+            rule_description: self.description.then_some(meta.description.as_str()),
+            rule_references: self.references.then_some(meta.references.as_str()),
+            rule_false_positives: self.falsepositives.then_some(meta.falsepositives.as_str()),
+            rule_author: self.author.then_some(meta.author.as_str()),
+            rule_date: self.date.then_some(meta.date.as_str()),
+            rule_modified: self.modified.then_some(meta.modified.as_str()),
+        }
+    }
+}
+
+/// CSVやJSONの出力に追加するルールの情報
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SelectedRuleMeta<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_references: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_false_positives: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_date: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_modified: Option<&'a str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::rule_meta::{RuleMeta, RuleMetaColumns};
+    use std::fs;
+
+    #[test]
+    fn test_rule_meta() {
+        let rulepath = "./test_files/test_rule_meta.yml";
+        fs::write(
+            rulepath,
+            "title: Logon Failure\nid: 8a8c1a2b-0000-4000-8000-000000000001\nauthor: Zach Mathis\ndate: 2022/05/20\ndescription: Failed logon.\nreferences:\n  - https://example.com/a\n  - https://example.com/b\nfalsepositives:\n  - unknown\ntags:\n  - attack.credential_access\n  - attack.t1110.001\n  - attack.s0002\n  - car.2013-05-003\n",
+        )
+        .unwrap();
+        let meta = RuleMeta::load(rulepath);
+        fs::remove_file(rulepath).ok();
+        assert_eq!(
+            meta.id.as_deref(),
+            Some("8a8c1a2b-0000-4000-8000-000000000001")
+        );
+        assert_eq!(meta.tactics, vec!["Credential Access"]);
+        assert_eq!(meta.techniques, vec!["T1110.001"]);
+        assert_eq!(meta.author, "Zach Mathis");
+        assert_eq!(meta.date, "2022/05/20");
+        assert_eq!(
+            meta.references,
+            "https://example.com/a | https://example.com/b"
+        );
+        assert_eq!(meta.falsepositives, "unknown");
+        assert_eq!(meta.modified, "");
+        assert!(RuleMeta::load("./test_files/not_exist.yml").id.is_none());
+    }
+
+    #[test]
+    fn test_rule_meta_columns() {
+        let columns = RuleMetaColumns::parse("author, Date").unwrap();
+        assert!(columns.author && columns.date);
+        assert!(!columns.description);
+        assert!(RuleMetaColumns::parse("all").unwrap().modified);
+        assert!(RuleMetaColumns::parse("title").is_err());
+
+        let meta = RuleMeta {
+            author: "Zach Mathis".to_string(),
+            ..Default::default()
+        };
+        let selected = columns.select(&meta);
+        assert_eq!(selected.rule_author, Some("Zach Mathis"));
+        assert_eq!(selected.rule_date, Some(""));
+        assert_eq!(selected.rule_description, None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::afterfact::format_time;
+use crate::detections::configs;
+use crate::detections::print::DetectInfo;
+use chrono::{DateTime, Utc};
+use hashbrown::{HashMap, HashSet};
+use std::error::Error;
+
+/// 検知したルール1件分の集計結果
+#[derive(Debug, Clone)]
+pub struct RuleHits {
+    pub title: String,
+    pub level: String,
+    pub rulepath: String,
+    pub count: usize,
+    pub computers: HashSet<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/**
+* 検知結果をルール毎に集計して、検知件数、検知したComputerの数、最初と最後の検知日時を一覧にする。
+* 大量の検知をトリアージする際に、どのルールから確認するか優先順位を付けるため。
+*/
+#[derive(Debug, Default)]
+pub struct RuleSummary {
+    rules: HashMap<String, RuleHits>,
+}
+
+impl RuleSummary {
+    pub fn new() -> RuleSummary {
+        RuleSummary::default()
+    }
+
+    pub fn add(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        let hits = self
+            .rules
+            .entry(detect_info.rulepath.to_string())
+            .or_insert_with(|| RuleHits {
+                title: detect_info.alert.to_string(),
+                level: detect_info.level.to_string(),
+                rulepath: detect_info.rulepath.to_string(),
+                count: 0,
+                computers: HashSet::new(),
+                first_seen: *time,
+                last_seen: *time,
+            });
+        hits.count += 1;
+        hits.computers.insert(detect_info.computername.to_string());
+        if *time < hits.first_seen {
+            hits.first_seen = *time;
+        }
+        if *time > hits.last_seen {
+            hits.last_seen = *time;
+        }
+    }
+
+    /// levelの高い順、検知件数の多い順に並べたルールの一覧を返す
+    pub fn sorted_rules(&self) -> Vec<&RuleHits> {
+        let level_num = |level: &str| *configs::LEVELMAP.get(&level.to_uppercase()).unwrap_or(&0);
+        let mut ret: Vec<&RuleHits> = self.rules.values().collect();
+        ret.sort_by(|x, y| {
+            level_num(&y.level)
+                .cmp(&level_num(&x.level))
+                .then_with(|| y.count.cmp(&x.count))
+                .then_with(|| x.title.cmp(&y.title))
+                .then_with(|| x.rulepath.cmp(&y.rulepath))
+        });
+        ret
+    }
+
+    pub fn write_csv(&self, csv_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_path(csv_path)?;
+        wtr.write_record(&[
+            "RuleTitle",
+            "Level",
+            "Count",
+            "Computers",
+            "FirstTimestamp",
+            "LastTimestamp",
+            "RulePath",
+        ])?;
+        for rule in self.sorted_rules() {
+            wtr.write_record(&[
+                rule.title.as_str(),
+                rule.level.as_str(),
+                rule.count.to_string().as_str(),
+                rule.computers.len().to_string().as_str(),
+                format_time(&rule.first_seen).as_str(),
+                format_time(&rule.last_seen).as_str(),
+                rule.rulepath.as_str(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::rule_summary::RuleSummary;
+    use chrono::{TimeZone, Utc};
+
+    fn create_detect_info(computer: &str, rulepath: &str, level: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "a.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: level.to_string(),
+            computername: computer.to_string(),
+            eventid: "4624".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Security".to_string(),
+            alert: rulepath.trim_end_matches(".yml").to_string(),
+            detail: "detail".to_string(),
+            tag_info: "".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_rule_summary() {
+        let mut summary = RuleSummary::new();
+        let time = |hour| Utc.ymd(2022, 5, 20).and_hms(hour, 0, 0);
+        summary.add(&time(12), &create_detect_info("PC01", "logon.yml", "low"));
+        summary.add(&time(9), &create_detect_info("PC02", "logon.yml", "low"));
+        summary.add(&time(15), &create_detect_info("PC01", "logon.yml", "low"));
+        summary.add(
+            &time(10),
+            &create_detect_info("PC01", "mimikatz.yml", "high"),
+        );
+
+        let rules = summary.sorted_rules();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].title, "mimikatz");
+        assert_eq!(rules[1].count, 3);
+        assert_eq!(rules[1].computers.len(), 2);
+        assert_eq!(rules[1].first_seen, time(9));
+        assert_eq!(rules[1].last_seen, time(15));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::detections::print::DetectInfo;
+use crate::detections::rule::RuleNode;
+use crate::options::run_metadata::RunMetadata;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use yaml_rust::Yaml;
+
+// 検知結果、ルール、実行時のメタデータを正規化したテーブルに保存する
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS rules (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    rule_id TEXT,
+    title TEXT,
+    level TEXT,
+    status TEXT,
+    author TEXT,
+    date TEXT,
+    modified TEXT,
+    description TEXT,
+    tags TEXT
+);
+CREATE TABLE IF NOT EXISTS computers (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS detections (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    computer_id INTEGER NOT NULL REFERENCES computers(id),
+    rule_id INTEGER NOT NULL REFERENCES rules(id),
+    channel TEXT,
+    event_id TEXT,
+    record_id TEXT,
+    level TEXT,
+    mitre_attack TEXT,
+    details TEXT,
+    record_information TEXT,
+    file_path TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_detections_timestamp ON detections(timestamp);
+CREATE INDEX IF NOT EXISTS idx_detections_computer ON detections(computer_id);
+CREATE INDEX IF NOT EXISTS idx_detections_rule ON detections(rule_id);
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    hayabusa_version TEXT,
+    rules_commit TEXT,
+    command_line TEXT,
+    start_time TEXT,
+    end_time TEXT,
+    duration_seconds REAL,
+    total_records INTEGER,
+    error_count INTEGER
+);
+CREATE TABLE IF NOT EXISTS run_files (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    path TEXT,
+    size INTEGER,
+    sha256 TEXT,
+    records INTEGER
+);
+CREATE VIEW IF NOT EXISTS detection_view AS
+    SELECT d.id, d.timestamp, c.name AS computer, d.channel, d.event_id, d.record_id, d.level, d.mitre_attack,
+        r.title AS rule_title, d.details, d.record_information, d.file_path, r.path AS rule_path, r.rule_id
+    FROM detections d
+    JOIN computers c ON d.computer_id = c.id
+    JOIN rules r ON d.rule_id = r.id;
+";
+
+/**
+* --output-sqliteで指定したSQLiteのデータベースに検知結果を書き込む。
+* ルールの読み込み後にルールの情報、検知結果の出力時に検知結果、解析の終了後に実行時のメタデータを書き込む。
+*/
+pub struct SqliteOutput {
+    conn: Connection,
+    pub inserted: usize,
+    pub failed: usize,
+}
+
+impl SqliteOutput {
+    /// データベースを開き、テーブルがなければ作成する
+    pub fn open(path: &str) -> Result<SqliteOutput, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Failed to open the SQLite database {}. {}", path, e))?;
+        conn.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        Ok(SqliteOutput {
+            conn,
+            inserted: 0,
+            failed: 0,
+        })
+    }
+
+    /// 読み込んだルールの情報を書き込む
+    pub fn write_rules(&mut self, rules: &[RuleNode]) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT OR IGNORE INTO rules (path, rule_id, title, level, status, author, date, modified, description, tags)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                )
+                .map_err(|e| e.to_string())?;
+            for rule in rules {
+                let yaml = &rule.yaml;
+                let tags = yaml["tags"].as_vec().map(|tags| {
+                    tags.iter()
+                        .filter_map(|tag| tag.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(",")
+                });
+                stmt.execute(params![
+                    rule.rulepath,
+                    yaml_str(&yaml["id"]),
+                    yaml_str(&yaml["title"]),
+                    yaml_str(&yaml["level"]),
+                    yaml_str(&yaml["status"]),
+                    yaml_str(&yaml["author"]),
+                    yaml_str(&yaml["date"]),
+                    yaml_str(&yaml["modified"]),
+                    yaml_str(&yaml["description"]),
+                    tags,
+                ])
+                .map_err(|e| e.to_string())?;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// 検知結果の書き込みを開始する。件数が多いので1つのトランザクションでまとめて書き込む
+    pub fn begin(&self) -> Result<(), String> {
+        self.conn.execute_batch("BEGIN").map_err(|e| e.to_string())
+    }
+
+    pub fn add(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) {
+        match self.insert_detection(time, detect_info) {
+            Ok(_) => self.inserted += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    pub fn commit(&self) -> Result<(), String> {
+        self.conn.execute_batch("COMMIT").map_err(|e| e.to_string())
+    }
+
+    /// 実行時のメタデータと解析したファイルの一覧を書き込む
+    pub fn write_run(&mut self, metadata: &RunMetadata) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO runs (hayabusa_version, rules_commit, command_line, start_time, end_time, duration_seconds, total_records, error_count)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                metadata.hayabusa_version,
+                metadata.rules_commit,
+                metadata.command_line.join(" "),
+                metadata.start_time,
+                metadata.end_time,
+                metadata.duration_seconds,
+                metadata.total_records as i64,
+                metadata.error_count as i64,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        let run_id = tx.last_insert_rowid();
+        for file in metadata.files.iter() {
+            tx.execute(
+                "INSERT INTO run_files (run_id, path, size, sha256, records) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    run_id,
+                    file.path,
+                    file.size as i64,
+                    file.sha256,
+                    file.records as i64
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    // 集計条件のルールや--searchの結果などrulesテーブルにないルールはパスとタイトルだけ登録する
+    fn insert_detection(
+        &self,
+        time: &DateTime<Utc>,
+        detect_info: &DetectInfo,
+    ) -> rusqlite::Result<()> {
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO rules (path, title, level) VALUES (?1, ?2, ?3)")?
+            .execute(params![
+                detect_info.rulepath,
+                detect_info.alert,
+                detect_info.level
+            ])?;
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO computers (name) VALUES (?1)")?
+            .execute(params![detect_info.computername])?;
+        self.conn
+            .prepare_cached(
+                "INSERT INTO detections (timestamp, computer_id, rule_id, channel, event_id, record_id, level, mitre_attack, details, record_information, file_path)
+                VALUES (?1, (SELECT id FROM computers WHERE name = ?2), (SELECT id FROM rules WHERE path = ?3), ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?
+            .execute(params![
+                // 文字列で比較しても時系列順になるようにUTCのRFC 3339形式で保存する
+                time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                detect_info.computername,
+                detect_info.rulepath,
+                detect_info.channel,
+                detect_info.eventid,
+                detect_info.record_id,
+                detect_info.level,
+                detect_info.tag_info,
+                detect_info.detail,
+                detect_info.record_information,
+                detect_info.filepath,
+            ])?;
+        Ok(())
+    }
+}
+
+fn yaml_str(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::String(s) => Some(s.to_string()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Real(r) => Some(r.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::detections::rule::create_rule;
+    use crate::output::sqlite::SqliteOutput;
+    use chrono::{TimeZone, Utc};
+    use yaml_rust::YamlLoader;
+
+    fn detect_info(rulepath: &str, computer: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: rulepath.to_string(),
+            level: "high".to_string(),
+            computername: computer.to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_write_detections() {
+        let rule_yaml = YamlLoader::load_from_str(
+            r#"
+            title: Logon Failure
+            id: 8a8c1a2b-0000-4000-8000-000000000001
+            level: high
+            status: stable
+            tags:
+                - attack.credential_access
+                - attack.t1110
+            "#,
+        )
+        .unwrap();
+        let rule = create_rule("rules/test.yml".to_string(), rule_yaml[0].clone());
+        let mut output = SqliteOutput::open(":memory:").unwrap();
+        output.write_rules(&[rule]).unwrap();
+
+        let time = Utc.ymd(2022, 5, 20).and_hms_milli(1, 2, 3, 500);
+        output.begin().unwrap();
+        output.add(&time, &detect_info("rules/test.yml", "PC01"));
+        output.add(&time, &detect_info("rules/test.yml", "PC02"));
+        output.add(&time, &detect_info("rules/aggregation.yml", "PC01"));
+        output.commit().unwrap();
+        assert_eq!(output.inserted, 3);
+        assert_eq!(output.failed, 0);
+
+        let count =
+            |sql: &str| -> i64 { output.conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM rules"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM computers"), 2);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM detection_view WHERE rule_id = '8a8c1a2b-0000-4000-8000-000000000001' AND computer = 'PC01'"),
+            1
+        );
+        let (timestamp, tags): (String, String) = output
+            .conn
+            .query_row(
+                "SELECT timestamp, tags FROM detections JOIN rules ON detections.rule_id = rules.id LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(timestamp, "2022-05-20T01:02:03.500Z");
+        assert_eq!(tags, "attack.credential_access,attack.t1110");
+    }
+}
@@ -0,0 +1,298 @@
+use crate::detections::print::DetectInfo;
+use crate::output::rule_meta::{RuleMeta, RuleMetaCache};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+// 検知したレコードを表すカスタムのSTIX Cyber-observable Object
+const EVENT_OBJECT_TYPE: &str = "x-hayabusa-event";
+
+/// 検知したルール毎の情報。indicatorの作成に使う
+#[derive(Debug)]
+struct StixRule {
+    title: String,
+    level: String,
+    first_seen: DateTime<Utc>,
+}
+
+/**
+* --output-stixで指定したファイルに検知結果をSTIX 2.1のバンドルとして書き込む。
+* ルール毎にSigmaのパターンを持つindicatorを作り、検知結果毎に検知したレコード(x-hayabusa-event)、
+* observed-data、indicatorとobserved-dataを結ぶsightingを作る。
+* 同じ検知結果から同じIDになるように、IDは検知結果の内容から生成する。
+*/
+pub struct StixOutput {
+    writer: BufWriter<File>,
+    created: String,
+    identity_id: String,
+    rules: BTreeMap<String, StixRule>,
+    pub written: usize,
+}
+
+impl StixOutput {
+    pub fn create(path: &str) -> Result<StixOutput, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create the STIX file {}. {}", path, e))?;
+        let created = stix_time(&Utc::now());
+        let mut writer = BufWriter::new(file);
+        // 検知結果が多い場合にメモリに溜めないように、バンドルのobjectsは1件ずつ書き込む
+        write!(
+            writer,
+            "{{\"type\":\"bundle\",\"id\":\"{}\",\"objects\":[",
+            stix_id("bundle", &[&created])
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(StixOutput {
+            writer,
+            created,
+            identity_id: stix_id("identity", &["Hayabusa"]),
+            rules: BTreeMap::new(),
+            written: 0,
+        })
+    }
+
+    pub fn add(&mut self, time: &DateTime<Utc>, detect_info: &DetectInfo) -> Result<(), String> {
+        let seed = [
+            time.to_rfc3339(),
+            detect_info.filepath.to_string(),
+            detect_info.record_id.to_string(),
+            detect_info.rulepath.to_string(),
+            detect_info.computername.to_string(),
+        ];
+        let seed: Vec<&str> = seed.iter().map(|s| s.as_str()).collect();
+        let event_id = stix_id(EVENT_OBJECT_TYPE, &seed);
+        let observed_data_id = stix_id("observed-data", &seed);
+        let indicator_id = stix_id("indicator", &[&detect_info.rulepath]);
+        let observed = stix_time(time);
+
+        self.rules
+            .entry(detect_info.rulepath.to_string())
+            .and_modify(|rule| rule.first_seen = rule.first_seen.min(*time))
+            .or_insert_with(|| StixRule {
+                title: detect_info.alert.to_string(),
+                level: detect_info.level.to_string(),
+                first_seen: *time,
+            });
+
+        self.write_object(&json!({
+            "type": EVENT_OBJECT_TYPE,
+            "spec_version": "2.1",
+            "id": event_id,
+            "computer": detect_info.computername,
+            "channel": detect_info.channel,
+            "event_id": detect_info.eventid,
+            "record_id": detect_info.record_id,
+            "details": detect_info.detail,
+            "file_path": detect_info.filepath,
+        }))?;
+        self.write_object(&json!({
+            "type": "observed-data",
+            "spec_version": "2.1",
+            "id": observed_data_id,
+            "created_by_ref": self.identity_id,
+            "created": self.created,
+            "modified": self.created,
+            "first_observed": observed,
+            "last_observed": observed,
+            "number_observed": 1,
+            "object_refs": [event_id],
+        }))?;
+        let sighting_id = stix_id("sighting", &[&indicator_id, &observed_data_id]);
+        self.write_object(&json!({
+            "type": "sighting",
+            "spec_version": "2.1",
+            "id": sighting_id,
+            "created_by_ref": self.identity_id,
+            "created": self.created,
+            "modified": self.created,
+            "first_seen": observed,
+            "last_seen": observed,
+            "count": 1,
+            "sighting_of_ref": indicator_id,
+            "observed_data_refs": [observed_data_id],
+        }))
+    }
+
+    /// 検知したルールのindicatorとHayabusaのidentityを書き込んでバンドルを閉じる。書き込んだオブジェクト数を返す
+    pub fn finish(mut self) -> Result<usize, String> {
+        let mut rule_metas = RuleMetaCache::new();
+        let rules = std::mem::take(&mut self.rules);
+        for (rulepath, rule) in rules.iter() {
+            let indicator = to_indicator(
+                rulepath,
+                rule,
+                rule_metas.get(rulepath),
+                &self.created,
+                &self.identity_id,
+            );
+            self.write_object(&indicator)?;
+        }
+        self.write_object(&json!({
+            "type": "identity",
+            "spec_version": "2.1",
+            "id": self.identity_id,
+            "created": self.created,
+            "modified": self.created,
+            "name": "Hayabusa",
+            "identity_class": "system",
+        }))?;
+        self.writer.write_all(b"]}").map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.written)
+    }
+
+    fn write_object(&mut self, object: &Value) -> Result<(), String> {
+        if self.written > 0 {
+            self.writer.write_all(b",").map_err(|e| e.to_string())?;
+        }
+        serde_json::to_writer(&mut self.writer, object).map_err(|e| e.to_string())?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+/// ルールをpattern_typeがsigmaのindicatorにする。ルールファイルを読めない場合はタイトルをパターンにする
+fn to_indicator(
+    rulepath: &str,
+    rule: &StixRule,
+    meta: &RuleMeta,
+    created: &str,
+    identity_id: &str,
+) -> Value {
+    let pattern = fs::read_to_string(rulepath).unwrap_or_else(|_| format!("title: {}", rule.title));
+    let mut indicator = json!({
+        "type": "indicator",
+        "spec_version": "2.1",
+        "id": stix_id("indicator", &[rulepath]),
+        "created_by_ref": identity_id,
+        "created": created,
+        "modified": created,
+        "name": rule.title,
+        "indicator_types": ["malicious-activity"],
+        "pattern": pattern,
+        "pattern_type": "sigma",
+        "valid_from": stix_time(&rule.first_seen),
+        "labels": [rule.level],
+    });
+    if !meta.description.is_empty() {
+        indicator["description"] = json!(meta.description);
+    }
+    if !meta.tactics.is_empty() {
+        indicator["kill_chain_phases"] = meta
+            .tactics
+            .iter()
+            .map(|tactic| {
+                json!({
+                    "kill_chain_name": "mitre-attack",
+                    "phase_name": tactic.to_lowercase().replace(' ', "-"),
+                })
+            })
+            .collect();
+    }
+    if !meta.techniques.is_empty() {
+        indicator["external_references"] = meta
+            .techniques
+            .iter()
+            .map(|technique| json!({"source_name": "mitre-attack", "external_id": technique}))
+            .collect();
+    }
+    indicator
+}
+
+/// 種類と内容から決まるSTIXのID。UUIDの形式にするためにバージョンとバリアントのビットを設定する
+fn stix_id(object_type: &str, seed: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(object_type.as_bytes());
+    for value in seed {
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+    }
+    let mut bytes = hasher.finalize()[..16].to_vec();
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}--{}-{}-{}-{}-{}",
+        object_type,
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn stix_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::stix::{stix_id, StixOutput};
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_stix_id() {
+        let id = stix_id("indicator", &["rules/test.yml"]);
+        assert_eq!(id, stix_id("indicator", &["rules/test.yml"]));
+        assert_ne!(id, stix_id("indicator", &["rules/test2.yml"]));
+        let uuid: Vec<&str> = id.trim_start_matches("indicator--").split('-').collect();
+        assert_eq!(
+            uuid.iter().map(|part| part.len()).collect::<Vec<usize>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(uuid[2].starts_with('5'));
+    }
+
+    #[test]
+    fn test_stix_bundle() {
+        let path = "test_files/stix_test.json";
+        let mut output = StixOutput::create(path).unwrap();
+        output
+            .add(
+                &Utc.ymd(2021, 12, 12).and_hms(1, 2, 3),
+                &DetectInfo {
+                    filepath: "Security.evtx".to_string(),
+                    rulepath: "test_files/rules/yaml/1.yml".to_string(),
+                    level: "high".to_string(),
+                    computername: "PC01".to_string(),
+                    eventid: "4625".to_string(),
+                    record_id: "1".to_string(),
+                    channel: "Sec".to_string(),
+                    alert: "Logon Failure".to_string(),
+                    detail: "User: admin".to_string(),
+                    tag_info: String::default(),
+                    record_information: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(output.finish().unwrap(), 5);
+        let bundle: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        fs::remove_file(path).ok();
+        assert_eq!(bundle["type"], "bundle");
+        let objects = bundle["objects"].as_array().unwrap();
+        let types: Vec<&str> = objects
+            .iter()
+            .map(|object| object["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                "x-hayabusa-event",
+                "observed-data",
+                "sighting",
+                "indicator",
+                "identity"
+            ]
+        );
+        assert_eq!(objects[2]["sighting_of_ref"], objects[3]["id"]);
+        assert_eq!(objects[1]["first_observed"], "2021-12-12T01:02:03.000Z");
+        assert_eq!(objects[3]["pattern_type"], "sigma");
+    }
+}
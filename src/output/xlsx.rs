@@ -0,0 +1,289 @@
+use crate::detections::print::DetectInfo;
+use hashbrown::HashMap;
+use rust_xlsxwriter::{Color, Format, Workbook, Worksheet, XlsxError};
+
+// Excelの1シートの最大行数。超えた場合は続きのシートを作成する
+const MAX_ROWS: u32 = 1_048_576;
+// シート名に使える最大文字数
+const MAX_SHEET_NAME_LEN: usize = 31;
+// セルに書き込める最大文字数。超えた分は切り捨てる
+const MAX_CELL_LEN: usize = 32767;
+
+const HEADERS: [&str; 12] = [
+    "Timestamp",
+    "Computer",
+    "Channel",
+    "EventID",
+    "RecordID",
+    "Level",
+    "MitreAttack",
+    "RuleTitle",
+    "Details",
+    "RecordInformation",
+    "RulePath",
+    "FilePath",
+];
+const COLUMN_WIDTHS: [f64; 12] = [
+    24.0, 20.0, 10.0, 8.0, 10.0, 8.0, 16.0, 40.0, 60.0, 30.0, 30.0, 30.0,
+];
+
+// シートの並び順と行の背景色。afterfactで変換した後のlevelを使う
+const LEVELS: [(&str, u32); 6] = [
+    ("critical", 0xFFC7CE),
+    ("high", 0xFFD8B1),
+    ("medium", 0xFFF2CC),
+    ("low", 0xE2EFDA),
+    ("info", 0xDDEBF7),
+    ("undefined", 0xFFFFFF),
+];
+
+/// シートを分ける単位
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum XlsxSplit {
+    Level,
+    Computer,
+}
+
+struct Sheet {
+    worksheet: Worksheet,
+    rows: u32,
+}
+
+/**
+* --output-xlsxで指定したファイルに検知結果をExcel形式で書き込む。
+* levelまたはComputer毎にシートを分け、見出し行を固定してlevelに応じて行を色付けする。
+*/
+pub struct XlsxOutput {
+    split: XlsxSplit,
+    // グループ毎のシートの一覧。最後の要素が書き込み中のシート
+    groups: HashMap<String, Vec<Sheet>>,
+    header_format: Format,
+    level_formats: HashMap<&'static str, Format>,
+    pub written: usize,
+}
+
+impl XlsxOutput {
+    pub fn new(split: &str) -> Result<XlsxOutput, String> {
+        let split = match split.to_lowercase().as_str() {
+            "level" => XlsxSplit::Level,
+            "computer" => XlsxSplit::Computer,
+            _ => {
+                return Err(format!(
+                    "Invalid xlsx sheet split: {}. Please specify level or computer.",
+                    split
+                ))
+            }
+        };
+        Ok(XlsxOutput {
+            split,
+            groups: HashMap::new(),
+            header_format: Format::new()
+                .set_bold()
+                .set_background_color(Color::RGB(0xD9D9D9)),
+            level_formats: LEVELS
+                .iter()
+                .map(|(level, color)| {
+                    (
+                        *level,
+                        Format::new().set_background_color(Color::RGB(*color)),
+                    )
+                })
+                .collect(),
+            written: 0,
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        timestamp: &str,
+        level: &str,
+        detect_info: &DetectInfo,
+    ) -> Result<(), String> {
+        let group = match self.split {
+            XlsxSplit::Level => level.to_string(),
+            XlsxSplit::Computer => detect_info.computername.to_string(),
+        };
+        let sheets = self.groups.entry(group.to_string()).or_default();
+        if sheets.last().map_or(true, |sheet| sheet.rows >= MAX_ROWS) {
+            let name = sheet_name(&group, sheets.len());
+            sheets.push(new_sheet(name, &self.header_format).map_err(|e| e.to_string())?);
+        }
+        let sheet = sheets.last_mut().unwrap();
+        let format = self
+            .level_formats
+            .get(level)
+            .unwrap_or(&self.level_formats["undefined"]);
+        let values = [
+            timestamp,
+            &detect_info.computername,
+            &detect_info.channel,
+            &detect_info.eventid,
+            &detect_info.record_id,
+            level,
+            &detect_info.tag_info,
+            &detect_info.alert,
+            &detect_info.detail,
+            detect_info.record_information.as_deref().unwrap_or(""),
+            &detect_info.rulepath,
+            &detect_info.filepath,
+        ];
+        for (col, value) in values.iter().enumerate() {
+            sheet
+                .worksheet
+                .write_string_with_format(sheet.rows, col as u16, truncate(value), format)
+                .map_err(|e| e.to_string())?;
+        }
+        sheet.rows += 1;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// levelの高い順またはComputerの名前順にシートを並べて保存する
+    pub fn save(self, path: &str) -> Result<usize, String> {
+        let mut groups: Vec<(String, Vec<Sheet>)> = self.groups.into_iter().collect();
+        match self.split {
+            XlsxSplit::Level => groups.sort_by_key(|(level, _)| {
+                LEVELS
+                    .iter()
+                    .position(|(l, _)| l == level)
+                    .unwrap_or(LEVELS.len())
+            }),
+            XlsxSplit::Computer => groups.sort_by(|x, y| x.0.cmp(&y.0)),
+        }
+        let mut workbook = Workbook::new();
+        for (_, sheets) in groups {
+            for sheet in sheets {
+                workbook.push_worksheet(sheet.worksheet);
+            }
+        }
+        if self.written == 0 {
+            workbook.push_worksheet(
+                new_sheet("Results".to_string(), &self.header_format)
+                    .map_err(|e| e.to_string())?
+                    .worksheet,
+            );
+        }
+        workbook
+            .save(path)
+            .map_err(|e| format!("Failed to save the xlsx file {}. {}", path, e))?;
+        Ok(self.written)
+    }
+}
+
+fn new_sheet(name: String, header_format: &Format) -> Result<Sheet, XlsxError> {
+    let mut worksheet = Worksheet::new();
+    worksheet.set_name(&name)?;
+    for (col, header) in HEADERS.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as u16, *header, header_format)?;
+        worksheet.set_column_width(col as u16, COLUMN_WIDTHS[col])?;
+    }
+    worksheet.set_freeze_panes(1, 0)?;
+    Ok(Sheet { worksheet, rows: 1 })
+}
+
+fn truncate(value: &str) -> &str {
+    match value.char_indices().nth(MAX_CELL_LEN) {
+        Some((idx, _)) => &value[..idx],
+        None => value,
+    }
+}
+
+/// シート名に使えない文字を置き換え、続きのシートには番号を付ける
+fn sheet_name(group: &str, index: usize) -> String {
+    let suffix = if index == 0 {
+        String::default()
+    } else {
+        format!(" ({})", index + 1)
+    };
+    let name: String = group
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            _ => c,
+        })
+        .take(MAX_SHEET_NAME_LEN - suffix.chars().count())
+        .collect();
+    let name = name.trim_matches('\'');
+    if name.is_empty() {
+        format!("-{}", suffix)
+    } else {
+        format!("{}{}", name, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::print::DetectInfo;
+    use crate::output::xlsx::{sheet_name, truncate, XlsxOutput};
+    use std::fs;
+
+    fn detect_info(computer: &str) -> DetectInfo {
+        DetectInfo {
+            filepath: "Security.evtx".to_string(),
+            rulepath: "rules/test.yml".to_string(),
+            level: "high".to_string(),
+            computername: computer.to_string(),
+            eventid: "4625".to_string(),
+            record_id: "12345".to_string(),
+            channel: "Sec".to_string(),
+            alert: "Logon Failure".to_string(),
+            detail: "User: admin".to_string(),
+            tag_info: "CredAccess".to_string(),
+            record_information: None,
+        }
+    }
+
+    #[test]
+    fn test_sheet_name() {
+        assert_eq!(sheet_name("high", 0), "high");
+        assert_eq!(sheet_name("high", 1), "high (2)");
+        assert_eq!(sheet_name("WORKGROUP\\PC01", 0), "WORKGROUP_PC01");
+        assert_eq!(
+            sheet_name("a-very-long-computer-name.example.local", 2),
+            "a-very-long-computer-name.e (3)"
+        );
+        assert_eq!(sheet_name("", 0), "-");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hayabusa"), "hayabusa");
+        assert_eq!(truncate(&"あ".repeat(40000)).chars().count(), 32767);
+    }
+
+    #[test]
+    fn test_write_xlsx() {
+        let path = "./test_files/test_write_xlsx.xlsx";
+        assert!(XlsxOutput::new("channel").is_err());
+        let mut output = XlsxOutput::new("computer").unwrap();
+        output
+            .add(
+                "2022-05-20 10:02:03.500 +09:00",
+                "high",
+                &detect_info("PC02"),
+            )
+            .unwrap();
+        output
+            .add(
+                "2022-05-20 10:02:04.500 +09:00",
+                "critical",
+                &detect_info("PC01"),
+            )
+            .unwrap();
+        output
+            .add(
+                "2022-05-20 10:02:05.500 +09:00",
+                "low",
+                &detect_info("PC02"),
+            )
+            .unwrap();
+        assert_eq!(output.groups.len(), 2);
+        assert_eq!(output.groups["PC02"][0].rows, 3);
+        assert_eq!(output.groups["PC01"].len(), 1);
+        let written = output.save(path);
+        let saved = fs::metadata(path).is_ok();
+        fs::remove_file(path).ok();
+        assert_eq!(written.unwrap(), 3);
+        assert!(saved);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::detections::configs;
+use crate::detections::print::{AlertMessage, DETECTION_COUNT};
+use hhmmss::Hhmmss;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+// TTYに出力する場合の再描画の間隔
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+// --progress jsonの場合の進捗のイベントを出力する間隔
+const JSON_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// --progressで指定した進捗の出力形式
+    pub static ref PROGRESS_FORMAT: ProgressFormat = match configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .value_of("progress")
+    {
+        Some(format) => ProgressFormat::parse(format).unwrap_or_else(|| {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!("Invalid --progress: {}. Please specify bar or json.", format),
+            )
+            .ok();
+            ProgressFormat::Bar
+        }),
+        None => ProgressFormat::Bar,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressFormat {
+    Bar,
+    Json,
+}
+
+impl ProgressFormat {
+    pub fn parse(format: &str) -> Option<ProgressFormat> {
+        match format {
+            "bar" => Some(ProgressFormat::Bar),
+            "json" => Some(ProgressFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/**
+* 解析の進捗(ファイル毎のレコード数、全体の残り時間、1秒あたりのイベント数)を表示する。
+* 標準出力がTTYでない場合は、ファイル毎に1行ずつ出力する。
+* --progress jsonの場合は、ラッパーツールが進捗を表示できるように、進捗のイベントをJSONで1行ずつ標準エラー出力に出力する。
+*/
+pub struct Progress {
+    is_tty: bool,
+    format: ProgressFormat,
+    start_time: Instant,
+    last_draw: Option<Instant>,
+    total_files: usize,
+    done_files: usize,
+    total_bytes: u64,
+    done_bytes: u64,
+    total_records: u64,
+    current_file: String,
+    current_records: u64,
+}
+
+impl Progress {
+    pub fn new(evtx_files: &[PathBuf]) -> Progress {
+        let mut progress = Progress::with_tty(evtx_files, atty::is(atty::Stream::Stdout));
+        progress.format = *PROGRESS_FORMAT;
+        progress
+    }
+
+    pub fn with_tty(evtx_files: &[PathBuf], is_tty: bool) -> Progress {
+        Progress {
+            is_tty,
+            format: ProgressFormat::Bar,
+            start_time: Instant::now(),
+            last_draw: None,
+            total_files: evtx_files.len(),
+            done_files: 0,
+            total_bytes: evtx_files
+                .iter()
+                .map(|path| Progress::file_size(path))
+                .sum(),
+            done_bytes: 0,
+            total_records: 0,
+            current_file: String::default(),
+            current_records: 0,
+        }
+    }
+
+    fn file_size(path: &Path) -> u64 {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// 解析を開始したファイルを設定する
+    pub fn start_file(&mut self, path: &str) {
+        self.current_file = path.to_string();
+        self.current_records = 0;
+        self.draw(false);
+    }
+
+    /// 解析したレコード数を追加する
+    pub fn add_records(&mut self, count: usize) {
+        self.current_records += count as u64;
+        self.total_records += count as u64;
+        self.draw(false);
+    }
+
+    /// ファイルの解析が完了したことを記録する
+    pub fn finish_file(&mut self, path: &str) {
+        self.done_files += 1;
+        self.done_bytes += Progress::file_size(Path::new(path));
+        if !self.is_tty && self.format == ProgressFormat::Bar {
+            println!(
+                "[{}/{}] {}: {} records | {}",
+                self.done_files,
+                self.total_files,
+                path,
+                self.current_records,
+                self.summary()
+            );
+        }
+        // --merge-recordsの場合は複数のファイルを同時に読むので、ファイル毎のレコード数はリセットしない
+        if self.current_file == path {
+            self.current_records = 0;
+        }
+        self.draw(true);
+    }
+
+    /// 進捗表示を終了する
+    pub fn finish(&mut self) {
+        if self.format == ProgressFormat::Json {
+            self.write_json_event("finish");
+        } else if self.is_tty {
+            self.draw(true);
+            println!();
+        }
+    }
+
+    /// 1秒あたりのイベント数
+    pub fn events_per_sec(&self) -> u64 {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0;
+        }
+        (self.total_records as f64 / elapsed) as u64
+    }
+
+    /// 解析済みのファイルサイズの割合から見積もった残り時間
+    pub fn eta(&self) -> Option<Duration> {
+        Progress::estimate(self.start_time.elapsed(), self.done_bytes, self.total_bytes)
+    }
+
+    fn estimate(elapsed: Duration, done_bytes: u64, total_bytes: u64) -> Option<Duration> {
+        if done_bytes == 0 || total_bytes < done_bytes {
+            return None;
+        }
+        let remaining = (total_bytes - done_bytes) as f64 / done_bytes as f64;
+        Some(Duration::from_secs_f64(elapsed.as_secs_f64() * remaining))
+    }
+
+    fn summary(&self) -> String {
+        let eta = match self.eta() {
+            Some(eta) => eta.hhmmss(),
+            None => "--:--:--".to_string(),
+        };
+        format!("{} events/sec | ETA {}", self.events_per_sec(), eta)
+    }
+
+    fn draw(&mut self, force: bool) {
+        let interval = match self.format {
+            ProgressFormat::Json => JSON_EVENT_INTERVAL,
+            ProgressFormat::Bar if self.is_tty => REDRAW_INTERVAL,
+            ProgressFormat::Bar => return,
+        };
+        if !force {
+            if let Some(last_draw) = self.last_draw {
+                if last_draw.elapsed() < interval {
+                    return;
+                }
+            }
+        }
+        self.last_draw = Some(Instant::now());
+        if self.format == ProgressFormat::Json {
+            self.write_json_event("progress");
+            return;
+        }
+        let filename = Path::new(&self.current_file)
+            .file_name()
+            .map(|name| format!("{}: ", name.to_string_lossy()))
+            .unwrap_or_default();
+        let mut stdout = io::stdout();
+        write!(
+            stdout,
+            "\r\x1b[2K[{}/{} files] {}{} records | {}",
+            self.done_files,
+            self.total_files,
+            filename,
+            self.current_records,
+            self.summary()
+        )
+        .ok();
+        stdout.flush().ok();
+    }
+
+    fn write_json_event(&self, event: &str) {
+        let mut stderr = io::stderr();
+        writeln!(stderr, "{}", self.json_event(event)).ok();
+        stderr.flush().ok();
+    }
+
+    /// 進捗のイベント。eventはファイルの解析中はprogress、全ファイルの解析が終わった時はfinish
+    fn json_event(&self, event: &str) -> Value {
+        json!({
+            "event": event,
+            "files_done": self.done_files,
+            "files_total": self.total_files,
+            "current_file": self.current_file,
+            "records": self.total_records,
+            "detections": DETECTION_COUNT.load(Ordering::Relaxed),
+            "events_per_sec": self.events_per_sec(),
+            "eta_secs": self.eta().map(|eta| eta.as_secs()),
+            "elapsed_secs": self.start_time.elapsed().as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{Progress, ProgressFormat};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(Progress::estimate(Duration::from_secs(10), 0, 100), None);
+        assert_eq!(
+            Progress::estimate(Duration::from_secs(10), 25, 100),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            Progress::estimate(Duration::from_secs(10), 100, 100),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn test_count_records() {
+        let files = vec![PathBuf::from("./test_files/evtx/test_progress.evtx")];
+        let mut progress = Progress::with_tty(&files, false);
+        progress.start_file("./test_files/evtx/test_progress.evtx");
+        progress.add_records(5000);
+        progress.add_records(10);
+        assert_eq!(progress.current_records, 5010);
+        progress.finish_file("./test_files/evtx/test_progress.evtx");
+        assert_eq!(progress.done_files, 1);
+        assert_eq!(progress.total_records, 5010);
+        assert_eq!(progress.current_records, 0);
+    }
+
+    #[test]
+    fn test_json_event() {
+        let files = vec![PathBuf::from("./test_files/evtx/test_progress.evtx")];
+        let mut progress = Progress::with_tty(&files, false);
+        progress.format = ProgressFormat::Json;
+        progress.start_file("./test_files/evtx/test_progress.evtx");
+        progress.add_records(100);
+        progress.finish_file("./test_files/evtx/test_progress.evtx");
+        let event = progress.json_event("finish");
+        assert_eq!(event["event"], "finish");
+        assert_eq!(event["files_done"], 1);
+        assert_eq!(event["files_total"], 1);
+        assert_eq!(event["records"], 100);
+        assert!(event["detections"].is_u64());
+
+        assert_eq!(ProgressFormat::parse("json"), Some(ProgressFormat::Json));
+        assert_eq!(ProgressFormat::parse("pbr"), None);
+    }
+}
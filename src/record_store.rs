@@ -0,0 +1,306 @@
+use crate::detections::configs;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// レコードストアの1行目に書き込む形式の名前とバージョン
+const STORE_FORMAT: &str = "hayabusa-record-store";
+const STORE_VERSION: u64 = 1;
+
+lazy_static! {
+    /// --save-storeで指定したレコードストアの書き込み先
+    pub static ref RECORD_STORE_WRITER: Mutex<Option<RecordStoreWriter>> = Mutex::new(None);
+    /// 書き込みに失敗して削除したレコードストアのエラー。解析の後のサマリで表示する
+    static ref RECORD_STORE_ERROR: Mutex<Option<String>> = Mutex::new(None);
+}
+
+// レコードストアに書き込み中の場合はtrue。--save-storeを指定していない場合にレコードをシリアライズしないようにする
+static RECORD_STORE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// レコードストアの1行分のレコード。レコードをコピーせずにシリアライズする
+#[derive(Serialize)]
+struct StoredRecord<'a> {
+    file: &'a str,
+    record: &'a Value,
+}
+
+fn serialize_record(evtx_filepath: &str, record: &Value) -> Result<Vec<u8>, String> {
+    let mut line = serde_json::to_vec(&StoredRecord {
+        file: evtx_filepath,
+        record,
+    })
+    .map_err(|e| e.to_string())?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/**
+* パースしたレコードを、元のevtxファイルのパスと一緒にgzip圧縮したJSON Linesで保存する。
+* 保存したレコードストアを--from-storeで読み込むと、evtxファイルをパースし直さずにlevelやフィルタを変えて何度でも解析できる。
+*/
+pub struct RecordStoreWriter {
+    path: String,
+    encoder: GzEncoder<BufWriter<File>>,
+    records: usize,
+}
+
+impl RecordStoreWriter {
+    pub fn create(path: &str) -> Result<RecordStoreWriter, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create the record store {}. {}", path, e))?;
+        let mut writer = RecordStoreWriter {
+            path: path.to_string(),
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            records: 0,
+        };
+        writer.write_line(&json!({ "format": STORE_FORMAT, "version": STORE_VERSION }))?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, evtx_filepath: &str, record: &Value) -> Result<(), String> {
+        let line = serialize_record(evtx_filepath, record)
+            .map_err(|e| format!("Failed to write to the record store {}. {}", self.path, e))?;
+        self.write_serialized(&line)
+    }
+
+    /// serialize_recordでシリアライズしたレコードを1件書き込む
+    fn write_serialized(&mut self, line: &[u8]) -> Result<(), String> {
+        self.encoder
+            .write_all(line)
+            .map_err(|e| format!("Failed to write to the record store {}. {}", self.path, e))?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &Value) -> Result<(), String> {
+        serde_json::to_writer(&mut self.encoder, line)
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.encoder.write_all(b"\n").map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to write to the record store {}. {}", self.path, e))
+    }
+
+    /// gzipの末尾を書き込んで、保存したレコードストアのパスとレコード数を返す。失敗した場合は途中までのファイルを削除する
+    pub fn finish(self) -> Result<(String, usize), String> {
+        let path = self.path;
+        if let Err(e) = self.encoder.finish().and_then(|mut writer| writer.flush()) {
+            return Err(remove_incomplete_store(
+                &path,
+                format!("Failed to write to the record store {}. {}", path, e),
+            ));
+        }
+        Ok((path, self.records))
+    }
+
+    /// 書き込みに失敗したレコードストアを閉じて、途中までのファイルを削除する
+    fn discard(self, err: String) -> String {
+        let path = self.path;
+        drop(self.encoder);
+        remove_incomplete_store(&path, err)
+    }
+}
+
+// 途中までのレコードストアを--from-storeで読み込まないように削除し、エラーメッセージに削除した結果を追加する
+fn remove_incomplete_store(path: &str, err: String) -> String {
+    match fs::remove_file(path) {
+        Ok(_) => format!("{} The incomplete record store was removed.", err),
+        Err(e) => format!(
+            "{} Failed to remove the incomplete record store {}. {}",
+            err, path, e
+        ),
+    }
+}
+
+/// --save-storeが指定されている場合は、レコードストアの書き込み先を作成する
+pub fn init_writer() -> Result<(), String> {
+    let path = match configs::CONFIG.read().unwrap().args.value_of("save-store") {
+        Some(path) => path.to_string(),
+        None => return Ok(()),
+    };
+    *RECORD_STORE_WRITER.lock().unwrap() = Some(RecordStoreWriter::create(&path)?);
+    RECORD_STORE_ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/**
+* レコードストアに1件書き込む。共有のロックを持つ時間を短くするため、シリアライズはロックの外で行う。
+* 書き込みに失敗した場合は以降の書き込みをやめて途中までのファイルを削除し、解析の後にfinish_writerでエラーを返す。
+*/
+pub fn save_record(evtx_filepath: &str, record: &Value) {
+    if !RECORD_STORE_ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    let line = serialize_record(evtx_filepath, record);
+    let mut writer = RECORD_STORE_WRITER.lock().unwrap();
+    let store = match writer.as_mut() {
+        Some(store) => store,
+        None => return,
+    };
+    let err = match line {
+        Ok(line) => match store.write_serialized(&line) {
+            Ok(_) => return,
+            Err(err) => err,
+        },
+        Err(err) => format!(
+            "Failed to write to the record store {}. {}",
+            store.path, err
+        ),
+    };
+    RECORD_STORE_ACTIVE.store(false, Ordering::Relaxed);
+    *RECORD_STORE_ERROR.lock().unwrap() = Some(writer.take().unwrap().discard(err));
+}
+
+/// レコードストアの書き込みを終える。--save-storeが指定されていない場合はNoneを返す
+pub fn finish_writer() -> Option<Result<(String, usize), String>> {
+    RECORD_STORE_ACTIVE.store(false, Ordering::Relaxed);
+    if let Some(err) = RECORD_STORE_ERROR.lock().unwrap().take() {
+        return Some(Err(err));
+    }
+    RECORD_STORE_WRITER
+        .lock()
+        .unwrap()
+        .take()
+        .map(|writer| writer.finish())
+}
+
+/// 保存したレコードストアから(元のevtxファイルのパス, レコード)を順に読み出す
+pub struct RecordStoreReader {
+    lines: Lines<BufReader<GzDecoder<File>>>,
+}
+
+impl RecordStoreReader {
+    pub fn open(path: &Path) -> Result<RecordStoreReader, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open the record store {}. {}", path.display(), e))?;
+        let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+        let header: Option<Value> = lines
+            .next()
+            .and_then(|line| line.ok())
+            .and_then(|line| serde_json::from_str(&line).ok());
+        let header = match header {
+            Some(header) if header["format"] == STORE_FORMAT => header,
+            _ => {
+                return Err(format!(
+                    "{} is not a record store saved with --save-store.",
+                    path.display()
+                ))
+            }
+        };
+        if header["version"] != STORE_VERSION {
+            return Err(format!(
+                "{} was saved with an unsupported record store version: {}",
+                path.display(),
+                header["version"]
+            ));
+        }
+        Ok(RecordStoreReader { lines })
+    }
+}
+
+impl Iterator for RecordStoreReader {
+    type Item = Result<(String, Value), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(format!("Failed to read the record store. {}", err))),
+        };
+        let mut line: Value = match serde_json::from_str(&line) {
+            Ok(line) => line,
+            Err(err) => return Some(Err(format!("Invalid record in the record store. {}", err))),
+        };
+        let file = match line["file"].as_str() {
+            Some(file) => file.to_string(),
+            None => {
+                return Some(Err(
+                    "Invalid record in the record store. The file path is missing.".to_string(),
+                ))
+            }
+        };
+        Some(Ok((file, line["record"].take())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::record_store::{RecordStoreReader, RecordStoreWriter};
+    use serde_json::json;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn test_record_store_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("hayabusa-store-{}.jsonl.gz", std::process::id()));
+        let path_str = path.display().to_string();
+        let records = vec![
+            (
+                "Security.evtx",
+                json!({"Event": {"System": {"EventID": 4624}}}),
+            ),
+            (
+                "System.evtx",
+                json!({"Event": {"System": {"EventID": 7045}}}),
+            ),
+        ];
+        let mut writer = RecordStoreWriter::create(&path_str).unwrap();
+        for (file, record) in records.iter() {
+            writer.write(file, record).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), (path_str.clone(), 2));
+
+        let loaded: Vec<(String, serde_json::Value)> = RecordStoreReader::open(&path)
+            .unwrap()
+            .map(|record| record.unwrap())
+            .collect();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].0, "Security.evtx");
+        assert_eq!(loaded[1].1, records[1].1);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_open_invalid_record_store() {
+        assert!(RecordStoreReader::open(Path::new("test_files/not_exist.jsonl.gz")).is_err());
+
+        let path =
+            std::env::temp_dir().join(format!("hayabusa-not-store-{}.gz", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"{\"file\": \"a.evtx\"}\n").unwrap();
+        encoder.finish().unwrap();
+        assert!(RecordStoreReader::open(&path).is_err());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_discard_record_store() {
+        let path = std::env::temp_dir().join(format!(
+            "hayabusa-store-discard-{}.jsonl.gz",
+            std::process::id()
+        ));
+        let mut writer = RecordStoreWriter::create(&path.display().to_string()).unwrap();
+        writer
+            .write(
+                "Security.evtx",
+                &json!({"Event": {"System": {"EventID": 4624}}}),
+            )
+            .unwrap();
+        // 書き込みに失敗したレコードストアは--from-storeで読み込まないように削除する
+        let err = writer.discard("Failed to write to the record store.".to_string());
+        assert_eq!(
+            err,
+            "Failed to write to the record store. The incomplete record store was removed."
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::detections::configs;
+use crate::input::{
+    self, CHUNK_FREE_SPACE_OFFSET, CHUNK_RECORDS_OFFSET, EVTX_CHUNK_SIGNATURE, EVTX_CHUNK_SIZE,
+    EVTX_FILE_HEADER_SIZE, EVTX_RECORD_SIGNATURE, RECORD_HEADER_SIZE,
+};
+use evtx::err::EvtxError;
+use evtx::{EvtxChunkData, ParserSettings, SerializedEvtxRecord};
+use hashbrown::{HashMap, HashSet};
+use lazy_static::lazy_static;
+use prettytable::{Cell, Row, Table};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// チャンクヘッダーの各フィールドの位置
+const CHUNK_FIRST_RECORD_NUMBER_OFFSET: usize = 8;
+const CHUNK_LAST_RECORD_NUMBER_OFFSET: usize = 16;
+const CHUNK_FIRST_RECORD_ID_OFFSET: usize = 24;
+const CHUNK_LAST_RECORD_ID_OFFSET: usize = 32;
+const CHUNK_HEADER_SIZE_OFFSET: usize = 40;
+const CHUNK_LAST_RECORD_OFFSET: usize = 44;
+// チャンクヘッダーのサイズと、文字列とテンプレートのオフセットのテーブルの位置
+const CHUNK_HEADER_SIZE: u32 = 128;
+const CHUNK_STRING_TABLE_OFFSET: usize = 0x80;
+// レコードは8バイト単位で並んでいる
+const RECORD_ALIGNMENT: usize = 8;
+// BinXMLのストリームの終わりを表すトークン
+const BINXML_END_OF_STREAM: u8 = 0x00;
+
+lazy_static! {
+    /// --recover-corruptedが指定されている場合は読み込めなかったチャンクを読み直す
+    pub static ref RECOVER_CORRUPTED_FLAG: bool = configs::CONFIG
+        .read()
+        .unwrap()
+        .args
+        .is_present("recover-corrupted");
+    /// 解析したevtxファイル毎のパースの状況
+    pub static ref PARSE_HEALTH: Mutex<ParseHealth> = Mutex::new(ParseHealth::default());
+}
+
+/// evtxファイル1ファイル分のパースの状況
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileHealth {
+    pub filepath: String,
+    // 読み込めなかったレコード数
+    pub failed_records: usize,
+    // 読み込めなかったチャンクの番号
+    pub failed_chunks: BTreeSet<u64>,
+    // 読み直して読み込めたレコード数
+    pub recovered: usize,
+    // 読み直しても読み込めなかったレコード数
+    pub lost: usize,
+    // 修復できずに読み直せなかったチャンク数
+    pub skipped_chunks: usize,
+    // パーサーが異常終了して途中で読み込みをやめた場合はtrue
+    pub aborted: bool,
+}
+
+/**
+* 破損したevtxファイルのパースの状況をファイル毎に記録する。
+* 1つのファイルが壊れていても他のファイルの解析を続け、最後にファイル毎の読み込めたレコード数と失ったレコード数を表示する。
+*/
+#[derive(Debug, Default)]
+pub struct ParseHealth {
+    files: Vec<FileHealth>,
+    index: HashMap<String, usize>,
+}
+
+impl ParseHealth {
+    fn file_mut(&mut self, filepath: &str) -> &mut FileHealth {
+        let idx = match self.index.get(filepath) {
+            Some(idx) => *idx,
+            None => {
+                self.files.push(FileHealth {
+                    filepath: filepath.to_string(),
+                    ..Default::default()
+                });
+                self.index
+                    .insert(filepath.to_string(), self.files.len() - 1);
+                self.files.len() - 1
+            }
+        };
+        &mut self.files[idx]
+    }
+
+    /// パースのエラーを記録する。チャンク単位のエラーの場合はチャンクの番号を記録する
+    pub fn add_error(&mut self, filepath: &str, chunk_id: Option<u64>) {
+        let file = self.file_mut(filepath);
+        match chunk_id {
+            Some(chunk_id) => {
+                file.failed_chunks.insert(chunk_id);
+            }
+            None => file.failed_records += 1,
+        }
+    }
+
+    pub fn add_abort(&mut self, filepath: &str) {
+        self.file_mut(filepath).aborted = true;
+    }
+
+    pub fn add_recovery(&mut self, filepath: &str, recovery: &ChunkRecovery) {
+        let file = self.file_mut(filepath);
+        file.recovered += recovery.records.len();
+        file.lost += recovery.lost;
+        file.skipped_chunks += recovery.skipped_chunks;
+    }
+
+    /// 読み直す対象のチャンクの番号
+    pub fn failed_chunks(&self, filepath: &str) -> Vec<u64> {
+        self.index
+            .get(filepath)
+            .map(|idx| self.files[*idx].failed_chunks.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// パースのエラーがあったファイル
+    pub fn files(&self) -> &[FileHealth] {
+        &self.files
+    }
+}
+
+/// 次のレコードを読み込む。パーサーがパニックした場合は他のファイルの解析を続けられるようにErrを返す
+pub fn next_record(
+    records: &mut impl Iterator<Item = Result<SerializedEvtxRecord<Value>, EvtxError>>,
+) -> Result<Option<Result<SerializedEvtxRecord<Value>, EvtxError>>, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| records.next()))
+        .map_err(|_| "The evtx parser stopped unexpectedly.".to_string())
+}
+
+/// 読み込めなかったチャンクを読み直した結果
+#[derive(Debug, Default)]
+pub struct ChunkRecovery {
+    // 読み込めたレコード
+    pub records: Vec<Value>,
+    // シグネチャは見つかったが読み込めなかったレコード数
+    pub lost: usize,
+    // レコードが1つも見つからない、または修復しても読み込めなかったチャンク数
+    pub skipped_chunks: usize,
+}
+
+/**
+* 読み込めなかったチャンクを修復して読み直す。
+* チャンク内のレコードのシグネチャを探してヘッダーとレコードのサイズを修復し、チェックサムを検証せずに1レコードずつ読み込む。
+* ファイルが途中で切れている場合は、残っている部分だけを読み込む。
+*/
+pub fn recover_chunks(path: &Path, chunk_ids: &[u64]) -> Result<ChunkRecovery, String> {
+    let settings = Arc::new(
+        ParserSettings::default()
+            .separate_json_attributes(true)
+            .validate_checksums(false)
+            .num_threads(1),
+    );
+    let mut reader = input::open_reader(path)?;
+    let mut recovery = ChunkRecovery::default();
+    for chunk_id in chunk_ids.iter() {
+        let mut chunk = match read_partial_chunk(&mut reader, *chunk_id) {
+            Some(chunk) => chunk,
+            None => {
+                recovery.skipped_chunks += 1;
+                continue;
+            }
+        };
+        let records = repair_chunk(&mut chunk);
+        if records.is_empty() {
+            recovery.skipped_chunks += 1;
+            continue;
+        }
+        let record_ids: HashSet<u64> = records.iter().map(|(record_id, _, _)| *record_id).collect();
+        // パーサーが異常終了しても他のチャンクを読み直せるようにする
+        let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+            parse_repaired_chunk(chunk, &record_ids, Arc::clone(&settings))
+        }))
+        .ok()
+        .flatten();
+        match parsed {
+            Some(parsed) => {
+                recovery.lost += record_ids.len() - parsed.len().min(record_ids.len());
+                recovery.records.extend(parsed);
+            }
+            None => {
+                recovery.lost += record_ids.len();
+                recovery.skipped_chunks += 1;
+            }
+        }
+    }
+    Ok(recovery)
+}
+
+// チャンクを読み込む。ファイルが途中で切れている場合は残りを0で埋める
+fn read_partial_chunk<R: Read + Seek>(reader: &mut R, chunk_id: u64) -> Option<Vec<u8>> {
+    reader
+        .seek(SeekFrom::Start(
+            EVTX_FILE_HEADER_SIZE + chunk_id * EVTX_CHUNK_SIZE as u64,
+        ))
+        .ok()?;
+    let mut chunk = vec![];
+    reader
+        .take(EVTX_CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)
+        .ok()?;
+    if chunk.len() <= CHUNK_RECORDS_OFFSET {
+        return None;
+    }
+    chunk.resize(EVTX_CHUNK_SIZE, 0);
+    Some(chunk)
+}
+
+/**
+* チャンク内のレコードのシグネチャを探して、(レコードID, チャンク先頭からの位置, サイズ)を先頭から順に返す。
+* レコードの末尾にあるサイズのコピーが一致するものだけをレコードとし、他のレコードの中に見つかったシグネチャは無視する。
+*/
+pub fn scan_record_signatures(chunk: &[u8]) -> Vec<(u64, usize, usize)> {
+    let mut records = vec![];
+    let mut offset = CHUNK_RECORDS_OFFSET;
+    while offset + RECORD_HEADER_SIZE <= chunk.len() {
+        if &chunk[offset..offset + EVTX_RECORD_SIGNATURE.len()] != EVTX_RECORD_SIGNATURE {
+            offset += RECORD_ALIGNMENT;
+            continue;
+        }
+        let size = input::read_u32(chunk, offset + 4).unwrap_or_default() as usize;
+        let is_record = size > RECORD_HEADER_SIZE
+            && offset + size <= chunk.len()
+            && input::read_u32(chunk, offset + size - 4) == Some(size as u32);
+        match input::read_u64(chunk, offset + 8) {
+            Some(record_id) if is_record => {
+                records.push((record_id, offset, size));
+                offset += size;
+            }
+            _ => offset += RECORD_ALIGNMENT,
+        }
+    }
+    records
+}
+
+/**
+* 見つかったレコードに合わせてチャンクを修復し、見つかったレコードを返す。
+* ヘッダーのシグネチャとレコードIDの範囲、空き領域の位置を直し、壊れた部分を直前のレコードに含めて読み飛ばせるようにする。
+* 先頭のレコードが壊れている場合は、壊れた部分を中身のないレコードにする。
+*/
+pub fn repair_chunk(chunk: &mut [u8]) -> Vec<(u64, usize, usize)> {
+    let records = scan_record_signatures(chunk);
+    let (first, last) = match (records.first(), records.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return records,
+    };
+    chunk[..EVTX_CHUNK_SIGNATURE.len()].copy_from_slice(EVTX_CHUNK_SIGNATURE);
+    let first_id = records
+        .iter()
+        .map(|(id, _, _)| *id)
+        .min()
+        .unwrap_or(first.0);
+    let last_id = records.iter().map(|(id, _, _)| *id).max().unwrap_or(last.0);
+    write_u64(chunk, CHUNK_FIRST_RECORD_NUMBER_OFFSET, first_id);
+    write_u64(chunk, CHUNK_LAST_RECORD_NUMBER_OFFSET, last_id);
+    write_u64(chunk, CHUNK_FIRST_RECORD_ID_OFFSET, first_id);
+    write_u64(chunk, CHUNK_LAST_RECORD_ID_OFFSET, last_id);
+    write_u32(chunk, CHUNK_HEADER_SIZE_OFFSET, CHUNK_HEADER_SIZE);
+    write_u32(chunk, CHUNK_LAST_RECORD_OFFSET, last.1 as u32);
+    write_u32(chunk, CHUNK_FREE_SPACE_OFFSET, (last.1 + last.2) as u32);
+
+    // 先頭のレコードの前の壊れた部分は、BinXMLの終わりだけを持つレコードにする
+    if first.1 > CHUNK_RECORDS_OFFSET {
+        write_record_header(
+            chunk,
+            CHUNK_RECORDS_OFFSET,
+            first.1 - CHUNK_RECORDS_OFFSET,
+            0,
+        );
+        chunk[CHUNK_RECORDS_OFFSET + RECORD_HEADER_SIZE] = BINXML_END_OF_STREAM;
+    }
+    // レコードの間の壊れた部分は直前のレコードのサイズに含める
+    for pair in records.windows(2) {
+        let ((record_id, offset, size), (_, next_offset, _)) = (pair[0], pair[1]);
+        if offset + size < next_offset {
+            write_record_header(chunk, offset, next_offset - offset, record_id);
+        }
+    }
+    records
+}
+
+// レコードのシグネチャとサイズ、レコードIDを書き込み、レコードの末尾にサイズのコピーを書き込む
+fn write_record_header(chunk: &mut [u8], offset: usize, size: usize, record_id: u64) {
+    chunk[offset..offset + EVTX_RECORD_SIGNATURE.len()].copy_from_slice(EVTX_RECORD_SIGNATURE);
+    write_u32(chunk, offset + 4, size as u32);
+    write_u64(chunk, offset + 8, record_id);
+    write_u32(chunk, offset + size - 4, size as u32);
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/**
+* 修復したチャンクを1レコードずつ読み込み、見つかったレコードのうち読み込めたものを返す。
+* 文字列とテンプレートのオフセットのテーブルが壊れていて読み込めない場合は、テーブルを消して読み直す。
+*/
+fn parse_repaired_chunk(
+    mut chunk: Vec<u8>,
+    record_ids: &HashSet<u64>,
+    settings: Arc<ParserSettings>,
+) -> Option<Vec<Value>> {
+    let mut records = parse_chunk_records(chunk.clone(), record_ids, Arc::clone(&settings));
+    if records.is_none() {
+        chunk[CHUNK_STRING_TABLE_OFFSET..CHUNK_RECORDS_OFFSET].fill(0);
+        records = parse_chunk_records(chunk, record_ids, settings);
+    }
+    records
+}
+
+fn parse_chunk_records(
+    chunk: Vec<u8>,
+    record_ids: &HashSet<u64>,
+    settings: Arc<ParserSettings>,
+) -> Option<Vec<Value>> {
+    let mut chunk_data = EvtxChunkData::new(chunk, false).ok()?;
+    let mut chunk = chunk_data.parse(settings).ok()?;
+    let mut records = vec![];
+    // 読み込めないレコードで先に進めなくなった場合に止められるように、見つかったレコード数より多くは読まない
+    for record in chunk.iter().take(record_ids.len() + 1) {
+        let record = match record {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        if !record_ids.contains(&record.event_record_id) {
+            continue;
+        }
+        if let Ok(record) = record.into_json_value() {
+            records.push(record.data);
+        }
+    }
+    Some(records)
+}
+
+/// パースのエラーがあったファイル毎に、読み込めなかったレコード数と読み直した結果を表示する
+pub fn print_parse_health() {
+    let health = PARSE_HEALTH.lock().unwrap();
+    if health.files().is_empty() {
+        return;
+    }
+    println!();
+    println!("Corrupted Event Files");
+    let mut table = Table::new();
+    table.set_titles(row![
+        "File",
+        "Failed Records",
+        "Failed Chunks",
+        "Recovered",
+        "Lost",
+        "Skipped Chunks",
+        "Aborted"
+    ]);
+    for file in health.files() {
+        table.add_row(Row::new(vec![
+            Cell::new(&file.filepath),
+            Cell::new(&file.failed_records.to_string()),
+            Cell::new(&file.failed_chunks.len().to_string()),
+            Cell::new(&file.recovered.to_string()),
+            Cell::new(&file.lost.to_string()),
+            Cell::new(&file.skipped_chunks.to_string()),
+            Cell::new(if file.aborted { "Yes" } else { "No" }),
+        ]));
+    }
+    table.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{
+        chunk_record_offsets, read_u32, read_u64, CHUNK_FREE_SPACE_OFFSET, CHUNK_RECORDS_OFFSET,
+        EVTX_CHUNK_SIGNATURE, EVTX_CHUNK_SIZE, EVTX_FILE_HEADER_SIZE, EVTX_RECORD_SIGNATURE,
+    };
+    use crate::recovery::{
+        recover_chunks, repair_chunk, scan_record_signatures, ChunkRecovery, ParseHealth,
+    };
+    use std::fs;
+    use std::path::Path;
+
+    // 中身が読み込めない(BinXMLのトークンが不正な)レコードを持つチャンクを作る
+    fn create_chunk(record_sizes: &[usize]) -> Vec<u8> {
+        let mut chunk = vec![0u8; EVTX_CHUNK_SIZE];
+        chunk[..EVTX_CHUNK_SIGNATURE.len()].copy_from_slice(EVTX_CHUNK_SIGNATURE);
+        let mut offset = CHUNK_RECORDS_OFFSET;
+        for (idx, size) in record_sizes.iter().enumerate() {
+            chunk[offset..offset + 4].copy_from_slice(EVTX_RECORD_SIGNATURE);
+            chunk[offset + 4..offset + 8].copy_from_slice(&(*size as u32).to_le_bytes());
+            chunk[offset + 8..offset + 16].copy_from_slice(&(idx as u64 + 1).to_le_bytes());
+            chunk[offset + 24..offset + size - 4].fill(0xff);
+            chunk[offset + size - 4..offset + size].copy_from_slice(&(*size as u32).to_le_bytes());
+            offset += size;
+        }
+        chunk[CHUNK_FREE_SPACE_OFFSET..CHUNK_FREE_SPACE_OFFSET + 4]
+            .copy_from_slice(&(offset as u32).to_le_bytes());
+        chunk
+    }
+
+    #[test]
+    fn test_parse_health() {
+        let mut health = ParseHealth::default();
+        health.add_error("a.evtx", None);
+        health.add_error("a.evtx", Some(3));
+        health.add_error("a.evtx", Some(1));
+        health.add_error("a.evtx", Some(3));
+        health.add_abort("b.evtx");
+        health.add_recovery(
+            "a.evtx",
+            &ChunkRecovery {
+                records: vec![serde_json::Value::Null; 90],
+                lost: 10,
+                skipped_chunks: 2,
+            },
+        );
+
+        assert_eq!(health.failed_chunks("a.evtx"), vec![1, 3]);
+        assert!(health.failed_chunks("c.evtx").is_empty());
+        let files = health.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].failed_records, 1);
+        assert_eq!(files[0].recovered, 90);
+        assert_eq!(files[0].lost, 10);
+        assert_eq!(files[0].skipped_chunks, 2);
+        assert!(!files[0].aborted);
+        assert!(files[1].aborted);
+    }
+
+    #[test]
+    fn test_repair_chunk() {
+        let mut chunk = create_chunk(&[64, 80, 72]);
+        // チャンクのシグネチャと2つ目のレコードのサイズを壊す
+        chunk[..8].fill(0);
+        chunk[CHUNK_RECORDS_OFFSET + 64 + 4..CHUNK_RECORDS_OFFSET + 64 + 8].fill(0xff);
+        assert_eq!(chunk_record_offsets(&chunk).len(), 1);
+        assert_eq!(
+            scan_record_signatures(&chunk),
+            vec![
+                (1, CHUNK_RECORDS_OFFSET, 64),
+                (3, CHUNK_RECORDS_OFFSET + 144, 72)
+            ]
+        );
+
+        let records = repair_chunk(&mut chunk);
+        assert_eq!(records.len(), 2);
+        assert_eq!(&chunk[..8], EVTX_CHUNK_SIGNATURE);
+        assert_eq!(read_u64(&chunk, 24), Some(1));
+        assert_eq!(read_u64(&chunk, 32), Some(3));
+        assert_eq!(
+            read_u32(&chunk, CHUNK_FREE_SPACE_OFFSET),
+            Some((CHUNK_RECORDS_OFFSET + 216) as u32)
+        );
+        // 壊れたレコードは直前のレコードに含めて読み飛ばす
+        assert_eq!(
+            chunk_record_offsets(&chunk),
+            vec![(1, CHUNK_RECORDS_OFFSET), (3, CHUNK_RECORDS_OFFSET + 144)]
+        );
+    }
+
+    #[test]
+    fn test_repair_chunk_corrupted_first_record() {
+        let mut chunk = create_chunk(&[64, 80]);
+        chunk[CHUNK_RECORDS_OFFSET..CHUNK_RECORDS_OFFSET + 4].fill(0);
+        let records = repair_chunk(&mut chunk);
+        assert_eq!(records, vec![(2, CHUNK_RECORDS_OFFSET + 64, 80)]);
+        // 先頭の壊れた部分は中身のないレコードにする
+        assert_eq!(
+            chunk_record_offsets(&chunk),
+            vec![(0, CHUNK_RECORDS_OFFSET), (2, CHUNK_RECORDS_OFFSET + 64)]
+        );
+        assert_eq!(chunk[CHUNK_RECORDS_OFFSET + 24], 0);
+    }
+
+    #[test]
+    fn test_recover_truncated_chunks() {
+        // 1つ目のチャンクはレコードが見つからず、2つ目のチャンクは3つ目のレコードの途中で切れている
+        let mut data = vec![0u8; EVTX_FILE_HEADER_SIZE as usize];
+        data.extend(vec![0u8; EVTX_CHUNK_SIZE]);
+        let chunk = create_chunk(&[64, 80, 72]);
+        data.extend(&chunk[..CHUNK_RECORDS_OFFSET + 64 + 80 + 40]);
+        let path = std::env::temp_dir().join("hayabusa_test_recover_truncated.evtx");
+        fs::write(&path, &data).unwrap();
+
+        let recovery = recover_chunks(&path, &[0, 1, 2]).unwrap();
+        fs::remove_file(&path).ok();
+        // 読み込めないレコードは失ったレコードとして数え、読み直せなかったチャンクも数える
+        assert!(recovery.records.is_empty());
+        assert_eq!(recovery.lost, 2);
+        assert!(recovery.skipped_chunks >= 2);
+        assert!(recover_chunks(Path::new("not_exist.evtx"), &[0]).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// --remote-work-dirを指定しない場合に、リモートのホストから収集したevtxファイルを保存するディレクトリ
+pub const DEFAULT_WORK_DIR: &str = "./remote-evtx";
+
+/// リモートのホストのイベントログのフォルダ(管理共有のADMIN$はWindowsフォルダを指す)
+pub fn remote_log_dir(host: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\{}\ADMIN$\System32\winevt\Logs", host))
+}
+
+/**
+* リモートのホストのイベントログのフォルダから、管理共有(SMB)経由でevtxファイルを作業ディレクトリにコピーする。
+* ホスト毎に<作業ディレクトリ>\<ホスト名>に保存して、コピーしたevtxファイルのパスを返す。
+*/
+pub fn collect_host(host: &str, work_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    if !is_valid_host(host) {
+        return Err(format!("{} is not a valid hostname or IP address.", host));
+    }
+    copy_evtx_files(&remote_log_dir(host), &work_dir.join(host))
+}
+
+// UNCパスとしてそのまま使えるホスト名かIPv4アドレスかを判定する
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && !host.starts_with('.')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+// フォルダ内の空ではない.evtxファイルをコピーする。ログが無効なチャンネルの空のファイルはコピーしない
+fn copy_evtx_files(src_dir: &Path, dest_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(src_dir)
+        .map_err(|e| format!("Failed to open {}. {}", src_dir.display(), e))?;
+    fs::create_dir_all(dest_dir)
+        .map_err(|e| format!("Failed to create {}. {}", dest_dir.display(), e))?;
+    let mut ret = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_evtx = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("evtx"));
+        let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if !is_evtx || size == 0 {
+            continue;
+        }
+        let dest = dest_dir.join(entry.file_name());
+        fs::copy(&path, &dest).map_err(|e| format!("Failed to copy {}. {}", path.display(), e))?;
+        ret.push(dest);
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote::{collect_host, copy_evtx_files, is_valid_host, remote_log_dir};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_remote_log_dir() {
+        assert_eq!(
+            remote_log_dir("DC01"),
+            PathBuf::from(r"\\DC01\ADMIN$\System32\winevt\Logs")
+        );
+        assert!(is_valid_host("fs01.example.local"));
+        assert!(is_valid_host("192.168.0.10"));
+        assert!(!is_valid_host(r"DC01\C$"));
+        assert!(!is_valid_host("../DC01"));
+        assert!(collect_host("DC01/..", Path::new("remote-evtx")).is_err());
+    }
+
+    #[test]
+    fn test_copy_evtx_files() {
+        let dest_dir = std::env::temp_dir().join(format!("hayabusa-remote-{}", std::process::id()));
+        // test_files/evtx/test1.evtxは空のファイルなのでコピーしない
+        let files = copy_evtx_files(Path::new("test_files/evtx"), &dest_dir).unwrap();
+        assert!(files.is_empty());
+        assert!(copy_evtx_files(Path::new("test_files/not_exist"), &dest_dir).is_err());
+        std::fs::remove_dir_all(&dest_dir).ok();
+    }
+}
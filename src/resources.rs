@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+/// バイナリに埋め込んだデフォルトの設定ファイル(ファイル名, 内容)。設定ディレクトリにファイルがない場合に使う
+const EMBEDDED_CONFIGS: [(&str, &str); 13] = [
+    (
+        "bits_allowlist.txt",
+        include_str!("../config/bits_allowlist.txt"),
+    ),
+    (
+        "channel_abbreviations.txt",
+        include_str!("../config/channel_abbreviations.txt"),
+    ),
+    (
+        "failed_logon_analytics.txt",
+        include_str!("../config/failed_logon_analytics.txt"),
+    ),
+    (
+        "kerberos_analytics.txt",
+        include_str!("../config/kerberos_analytics.txt"),
+    ),
+    (
+        "detection_macros.yaml",
+        include_str!("../config/detection_macros.yaml"),
+    ),
+    ("level_color.txt", include_str!("../config/level_color.txt")),
+    ("output_tag.txt", include_str!("../config/output_tag.txt")),
+    (
+        "persistence_registry_paths.txt",
+        include_str!("../config/persistence_registry_paths.txt"),
+    ),
+    (
+        "pivot_keywords.txt",
+        include_str!("../config/pivot_keywords.txt"),
+    ),
+    (
+        "redaction_rules.txt",
+        include_str!("../config/redaction_rules.txt"),
+    ),
+    (
+        "statistics_event_info.txt",
+        include_str!("../config/statistics_event_info.txt"),
+    ),
+    (
+        "suppressions.yaml",
+        include_str!("../config/suppressions.yaml"),
+    ),
+    (
+        "target_eventids.txt",
+        include_str!("../config/target_eventids.txt"),
+    ),
+];
+
+/// バイナリに埋め込んだロゴとイースターエッグのアート(パス, 内容)
+const EMBEDDED_ARTS: [(&str, &str); 5] = [
+    ("art/logo.txt", include_str!("../art/logo.txt")),
+    (
+        "art/happynewyear.txt",
+        include_str!("../art/happynewyear.txt"),
+    ),
+    ("art/ninja.txt", include_str!("../art/ninja.txt")),
+    ("art/takoyaki.txt", include_str!("../art/takoyaki.txt")),
+    ("art/christmas.txt", include_str!("../art/christmas.txt")),
+];
+
+/**
+* 設定ファイルのパスのファイル名に対応する、バイナリに埋め込んだデフォルトの設定を返す。
+* ディレクトリ構成を持ち運ばずに実行ファイルだけで動かせるようにするため、ディスク上のファイルがない場合に使う。
+*/
+pub fn embedded_config(path: &str) -> Option<&'static str> {
+    let filename = Path::new(path).file_name()?.to_str()?;
+    EMBEDDED_CONFIGS
+        .iter()
+        .find(|(name, _)| *name == filename)
+        .map(|(_, contents)| *contents)
+}
+
+/// アートを読み込む。ディスク上のファイルがない場合はバイナリに埋め込んだアートを返す
+pub fn read_art(path: &str) -> String {
+    fs::read_to_string(path)
+        .ok()
+        .or_else(|| {
+            EMBEDDED_ARTS
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, contents)| contents.to_string())
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resources::{embedded_config, read_art};
+    use std::fs;
+
+    #[test]
+    fn test_embedded_config() {
+        assert_eq!(
+            embedded_config("not_exist/level_color.txt").unwrap(),
+            fs::read_to_string("config/level_color.txt").unwrap()
+        );
+        assert!(embedded_config("config/target_eventids_sample.txt").is_none());
+        assert_eq!(
+            read_art("art/logo.txt"),
+            fs::read_to_string("art/logo.txt").unwrap()
+        );
+        assert!(read_art("art/not_exist.txt").is_empty());
+    }
+}
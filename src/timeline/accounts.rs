@@ -0,0 +1,215 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use std::collections::BTreeMap;
+
+const SECURITY_CHANNEL: &str = "Security";
+// 特権を持つグループ。小文字で比較する
+const PRIVILEGED_GROUPS: [&str; 11] = [
+    "administrators",
+    "domain admins",
+    "enterprise admins",
+    "schema admins",
+    "account operators",
+    "backup operators",
+    "server operators",
+    "print operators",
+    "dnsadmins",
+    "group policy creator owners",
+    "enterprise key admins",
+];
+
+/// アカウントの管理操作1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountEvent {
+    pub timestamp: String,
+    pub computer: String,
+    pub domain: String,
+    pub action: &'static str,
+    pub actor: String,
+    pub target: String,
+    /// グループへの追加の場合は追加先のグループ名
+    pub group: String,
+    pub privileged: bool,
+    pub eventid: String,
+}
+
+/**
+* SecurityログのイベントID 4720/4722/4724/4728/4732/4756から、アカウントの作成、有効化、パスワードのリセット、
+* グループへの追加をドメイン毎に一覧にする。特権を持つグループへの追加を強調して出力する。
+*/
+#[derive(Debug, Default)]
+pub struct AccountSummary {
+    pub events: Vec<AccountEvent>,
+}
+
+impl AccountSummary {
+    pub fn new() -> AccountSummary {
+        AccountSummary::default()
+    }
+
+    pub fn account_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でaccount-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("account-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SECURITY_CHANNEL {
+            return;
+        }
+        let eventid = get("Event.System.EventID");
+        let action = match eventid.as_str() {
+            "4720" => "Account Created",
+            "4722" => "Account Enabled",
+            "4724" => "Password Reset",
+            "4728" => "Added to Global Group",
+            "4732" => "Added to Local Group",
+            "4756" => "Added to Universal Group",
+            _ => return,
+        };
+        let actor = join_domain(&get("SubjectDomainName"), &get("SubjectUserName"));
+        let (target, group) = if eventid == "4720" || eventid == "4722" || eventid == "4724" {
+            (
+                join_domain(&get("TargetDomainName"), &get("TargetUserName")),
+                String::default(),
+            )
+        } else {
+            // グループへの追加ではTargetUserNameがグループ名になり、追加したアカウントはMemberNameに入る
+            let member = match get("MemberName").as_str() {
+                "" | "-" => get("MemberSid"),
+                name => name.to_string(),
+            };
+            (member, get("TargetUserName"))
+        };
+        let privileged = PRIVILEGED_GROUPS.contains(&group.to_lowercase().as_str());
+        self.events.push(AccountEvent {
+            timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+            computer: get("Event.System.Computer"),
+            domain: get("TargetDomainName"),
+            action,
+            actor,
+            target,
+            group,
+            privileged,
+            eventid,
+        });
+    }
+
+    /// 別のAccountSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: AccountSummary) {
+        self.events.extend(other.events);
+    }
+
+    /// ドメイン毎に時系列順に並べたイベントを返す
+    pub fn sorted_events(&self) -> Vec<&AccountEvent> {
+        let mut events: Vec<&AccountEvent> = self.events.iter().collect();
+        events.sort_by(|x, y| {
+            x.domain
+                .cmp(&y.domain)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.computer.cmp(&y.computer))
+        });
+        events
+    }
+
+    /// ドメインと操作毎の件数
+    pub fn counts(&self) -> BTreeMap<(&str, &str), usize> {
+        let mut counts = BTreeMap::new();
+        for event in self.events.iter() {
+            *counts
+                .entry((event.domain.as_str(), event.action))
+                .or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+fn join_domain(domain: &str, user: &str) -> String {
+    if domain.is_empty() || domain == "-" {
+        user.to_string()
+    } else {
+        format!("{}\\{}", domain, user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::accounts::AccountSummary;
+    use serde_json::json;
+
+    fn account_record(eventid: u64, time: &str, target: &str, member: &str) -> serde_json::Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Security",
+                    "Computer": "DC01.corp.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": {
+                    "SubjectUserName": "admin",
+                    "SubjectDomainName": "CORP",
+                    "TargetUserName": target,
+                    "TargetDomainName": "CORP",
+                    "MemberName": member,
+                    "MemberSid": "S-1-5-21-1-2-3-1104",
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_account_summary() {
+        let mut summary = AccountSummary::new();
+        summary.add(&account_record(
+            4728,
+            "2021-12-12T10:00:02Z",
+            "Domain Admins",
+            "CN=backdoor,CN=Users,DC=corp,DC=local",
+        ));
+        summary.add(&account_record(
+            4720,
+            "2021-12-12T10:00:00Z",
+            "backdoor",
+            "",
+        ));
+        summary.add(&account_record(
+            4732,
+            "2021-12-12T10:00:03Z",
+            "Remote Desktop Users",
+            "-",
+        ));
+        summary.add(&account_record(
+            4625,
+            "2021-12-12T10:00:04Z",
+            "backdoor",
+            "",
+        ));
+
+        let events = summary.sorted_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].action, "Account Created");
+        assert_eq!(events[0].actor, "CORP\\admin");
+        assert_eq!(events[0].target, "CORP\\backdoor");
+        assert!(!events[0].privileged);
+        assert_eq!(events[1].group, "Domain Admins");
+        assert_eq!(events[1].target, "CN=backdoor,CN=Users,DC=corp,DC=local");
+        assert!(events[1].privileged);
+        assert_eq!(events[2].target, "S-1-5-21-1-2-3-1104");
+        assert!(!events[2].privileged);
+        assert_eq!(summary.counts()[&("CORP", "Account Created")], 1);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use hashbrown::HashMap;
+use serde_json::Value;
+
+/// イベントがない時間帯も行にする期間の上限(時間)。1601年や遠い未来の不正なタイムスタンプで行が膨大にならないようにする
+pub const MAX_FILLED_HOURS: i64 = 24 * 366;
+
+/// Computer毎の時間帯別のイベント数のまとめ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputerActivity {
+    pub computer: String,
+    pub total: usize,
+    pub first_hour: DateTime<Utc>,
+    pub last_hour: DateTime<Utc>,
+    /// 最初と最後の時間帯の間でイベントが1件もない時間帯の数
+    pub empty_hours: usize,
+    /// イベントが1件もない時間帯が連続した最長の時間数
+    pub longest_gap: usize,
+}
+
+/**
+* 全てのイベントをComputerと1時間毎の時間帯で集計して、行が時間帯、列がComputerの表にする。
+* 活動のパターン、ログの削除の可能性があるイベントの空白、業務時間外の急増を目視で確認するため。
+*/
+#[derive(Debug, Default)]
+pub struct ActivityMatrix {
+    // (Computer, 時間帯の開始時刻)毎のイベント数
+    counts: HashMap<(String, DateTime<Utc>), usize>,
+}
+
+impl ActivityMatrix {
+    pub fn new() -> ActivityMatrix {
+        ActivityMatrix::default()
+    }
+
+    pub fn activity_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でactivity-matrixオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("activity-matrix")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &Value) {
+        let timestamp = match utils::get_event_value(&utils::get_event_time(), record)
+            .and_then(utils::value_to_string)
+            .and_then(|time| utils::str_time_to_datetime(&time))
+        {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let computer = utils::get_event_value("Event.System.Computer", record)
+            .and_then(utils::value_to_string)
+            .unwrap_or_else(|| "-".to_string());
+        *self
+            .counts
+            .entry((computer, truncate_to_hour(&timestamp)))
+            .or_insert(0) += 1;
+    }
+
+    /// 別のActivityMatrixの集計結果を追加する
+    pub fn merge(&mut self, other: ActivityMatrix) {
+        for (key, count) in other.counts {
+            *self.counts.entry(key).or_insert(0) += count;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// 名前の順に並べたComputerの一覧
+    pub fn computers(&self) -> Vec<String> {
+        let mut computers: Vec<String> = self
+            .counts
+            .keys()
+            .map(|(computer, _)| computer.to_string())
+            .collect();
+        computers.sort();
+        computers.dedup();
+        computers
+    }
+
+    /// 最初の時間帯から最後の時間帯までがMAX_FILLED_HOURS以内で、イベントがない時間帯も行にするか
+    pub fn fills_empty_hours(&self) -> bool {
+        let hours = self.event_hours();
+        match (hours.first(), hours.last()) {
+            (Some(first), Some(last)) => (*last - *first).num_hours() <= MAX_FILLED_HOURS,
+            _ => true,
+        }
+    }
+
+    /**
+     * 最初の時間帯から最後の時間帯までの、イベントがない時間帯も含めた全ての時間帯。
+     * 期間がMAX_FILLED_HOURSより長い場合は、イベントがある時間帯だけを返す。
+     */
+    pub fn hours(&self) -> Vec<DateTime<Utc>> {
+        let hours = self.event_hours();
+        if !self.fills_empty_hours() {
+            return hours;
+        }
+        match (hours.first(), hours.last()) {
+            (Some(first), Some(last)) => hour_range(*first, *last),
+            _ => vec![],
+        }
+    }
+
+    // イベントがある時間帯を古い順に返す
+    fn event_hours(&self) -> Vec<DateTime<Utc>> {
+        let mut hours: Vec<DateTime<Utc>> = self.counts.keys().map(|(_, hour)| *hour).collect();
+        hours.sort();
+        hours.dedup();
+        hours
+    }
+
+    pub fn count(&self, computer: &str, hour: &DateTime<Utc>) -> usize {
+        self.counts
+            .get(&(computer.to_string(), *hour))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Computer毎のイベント数とイベントがない時間帯をまとめたものを、名前の順に返す
+    pub fn summaries(&self) -> Vec<ComputerActivity> {
+        let mut summaries = vec![];
+        for computer in self.computers() {
+            let mut hours: Vec<DateTime<Utc>> = self
+                .counts
+                .keys()
+                .filter(|(name, _)| *name == computer)
+                .map(|(_, hour)| *hour)
+                .collect();
+            hours.sort();
+            let first_hour = hours[0];
+            let last_hour = hours[hours.len() - 1];
+            // 期間が長くても時間帯を1つずつ数えないように、イベントがある時間帯の間隔から求める
+            let (mut empty_hours, mut longest_gap) = (0, 0);
+            for pair in hours.windows(2) {
+                let gap = (pair[1] - pair[0]).num_hours() as usize - 1;
+                empty_hours += gap;
+                longest_gap = longest_gap.max(gap);
+            }
+            summaries.push(ComputerActivity {
+                total: hours.iter().map(|hour| self.count(&computer, hour)).sum(),
+                computer,
+                first_hour,
+                last_hour,
+                empty_hours,
+                longest_gap,
+            });
+        }
+        summaries
+    }
+}
+
+fn truncate_to_hour(timestamp: &DateTime<Utc>) -> DateTime<Utc> {
+    timestamp.date().and_hms(timestamp.hour(), 0, 0)
+}
+
+fn hour_range(first: DateTime<Utc>, last: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+    let mut hours = vec![];
+    let mut hour = first;
+    while hour <= last {
+        hours.push(hour);
+        hour = hour + Duration::hours(1);
+    }
+    hours
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::activity::ActivityMatrix;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn test_activity_matrix() {
+        let mut matrix = ActivityMatrix::new();
+        for (computer, time) in [
+            ("PC01", "2022-05-20T10:05:00Z"),
+            ("PC01", "2022-05-20T10:59:59Z"),
+            ("PC01", "2022-05-20T14:00:00Z"),
+            ("DC01", "2022-05-20T11:30:00Z"),
+        ] {
+            matrix.add(&json!({
+                "Event": {
+                    "System": {
+                        "Computer": computer,
+                        "TimeCreated_attributes": { "SystemTime": time },
+                    }
+                }
+            }));
+        }
+
+        let hours = matrix.hours();
+        assert_eq!(hours.len(), 5);
+        assert_eq!(hours[0], Utc.ymd(2022, 5, 20).and_hms(10, 0, 0));
+        assert_eq!(matrix.computers(), vec!["DC01", "PC01"]);
+        assert_eq!(matrix.count("PC01", &hours[0]), 2);
+        assert_eq!(matrix.count("PC01", &hours[1]), 0);
+
+        let summaries = matrix.summaries();
+        assert_eq!(summaries[0].computer, "DC01");
+        assert_eq!(summaries[0].empty_hours, 0);
+        assert_eq!(summaries[1].total, 3);
+        assert_eq!(summaries[1].empty_hours, 3);
+        assert_eq!(summaries[1].longest_gap, 3);
+    }
+
+    #[test]
+    fn test_activity_matrix_invalid_timestamp() {
+        let mut matrix = ActivityMatrix::new();
+        for time in [
+            "1601-01-01T00:00:00Z",
+            "2022-05-20T10:05:00Z",
+            "2022-05-20T12:05:00Z",
+        ] {
+            matrix.add(&json!({
+                "Event": {
+                    "System": {
+                        "Computer": "PC01",
+                        "TimeCreated_attributes": { "SystemTime": time },
+                    }
+                }
+            }));
+        }
+
+        // 1601年のタイムスタンプがあっても、イベントがある時間帯だけを行にする
+        assert!(!matrix.fills_empty_hours());
+        assert_eq!(
+            matrix.hours(),
+            vec![
+                Utc.ymd(1601, 1, 1).and_hms(0, 0, 0),
+                Utc.ymd(2022, 5, 20).and_hms(10, 0, 0),
+                Utc.ymd(2022, 5, 20).and_hms(12, 0, 0),
+            ]
+        );
+        let summaries = matrix.summaries();
+        assert_eq!(summaries[0].total, 3);
+        assert_eq!(
+            summaries[0].longest_gap as i64,
+            (Utc.ymd(2022, 5, 20).and_hms(10, 0, 0) - Utc.ymd(1601, 1, 1).and_hms(0, 0, 0))
+                .num_hours()
+                - 1
+        );
+    }
+}
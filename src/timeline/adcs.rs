@@ -0,0 +1,327 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::{HashMap, HashSet};
+
+const SECURITY_CHANNEL: &str = "Security";
+
+/// 証明書の要求または証明書を使ったログオン1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateActivity {
+    pub timestamp: String,
+    pub computer: String,
+    pub activity: &'static str,
+    pub request_id: String,
+    /// 証明書を要求したアカウント。証明書を使ったログオンの場合はログオンしたアカウント
+    pub requester: String,
+    pub template: String,
+    /// 要求で指定されたサブジェクトの別名(SAN)
+    pub subject_alt_names: Vec<String>,
+    pub status: &'static str,
+    pub cert_issuer: String,
+    pub cert_serial_number: String,
+    /// 不審な点。空の場合は不審な点はない
+    pub reasons: Vec<&'static str>,
+}
+
+/**
+* SecurityログのイベントID 4886(証明書の要求)、4887(発行)、4888(拒否)と証明書を使ったTGTの要求(4768)から、
+* ESC1のように要求したアカウントと異なるアカウントをSANに指定した証明書の要求と、その証明書を使ったログオンを検知する。
+*/
+#[derive(Debug, Default)]
+pub struct AdcsAnalytics {
+    // (Computer, RequestId)毎の証明書の要求
+    requests: HashMap<(String, String), CertificateActivity>,
+    logons: Vec<CertificateActivity>,
+}
+
+impl AdcsAnalytics {
+    pub fn new() -> AdcsAnalytics {
+        AdcsAnalytics::default()
+    }
+
+    pub fn adcs_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でadcs-analyticsオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("adcs-analytics")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SECURITY_CHANNEL {
+            return;
+        }
+        let status = match get("Event.System.EventID").as_str() {
+            "4886" => "Received",
+            "4887" => "Issued",
+            "4888" => "Denied",
+            "4768" => {
+                // 証明書を使わないTGTの要求は対象外
+                let cert_issuer = get("CertIssuerName");
+                if cert_issuer.is_empty() || cert_issuer == "-" {
+                    return;
+                }
+                self.logons.push(CertificateActivity {
+                    timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+                    computer: get("Event.System.Computer"),
+                    activity: "Certificate Logon",
+                    request_id: String::default(),
+                    requester: get("TargetUserName"),
+                    template: String::default(),
+                    subject_alt_names: vec![],
+                    status: if get("Status") == "0x0" {
+                        "Success"
+                    } else {
+                        "Failure"
+                    },
+                    cert_issuer,
+                    cert_serial_number: get("CertSerialNumber"),
+                    reasons: vec![],
+                });
+                return;
+            }
+            _ => return,
+        };
+        let (template, subject_alt_names) = parse_attributes(&get("Attributes"));
+        let key = (get("Event.System.Computer"), get("RequestId"));
+        let timestamp = get("Event.System.TimeCreated_attributes.SystemTime");
+        let request = self
+            .requests
+            .entry(key.clone())
+            .or_insert_with(|| CertificateActivity {
+                timestamp: timestamp.to_string(),
+                computer: key.0,
+                activity: "Certificate Request",
+                request_id: key.1,
+                requester: String::default(),
+                template: String::default(),
+                subject_alt_names: vec![],
+                status,
+                cert_issuer: String::default(),
+                cert_serial_number: String::default(),
+                reasons: vec![],
+            });
+        // 4886の後に4887か4888が記録されるので、要求を受け付けた日時を残して結果を更新する
+        if timestamp < request.timestamp {
+            request.timestamp = timestamp;
+        }
+        if status != "Received" {
+            request.status = status;
+        }
+        if request.requester.is_empty() {
+            request.requester = get("Requester");
+        }
+        if request.template.is_empty() {
+            request.template = template;
+        }
+        if request.subject_alt_names.is_empty() {
+            request.subject_alt_names = subject_alt_names;
+        }
+    }
+
+    /// 別のAdcsAnalyticsの集計結果を追加する
+    pub fn merge(&mut self, other: AdcsAnalytics) {
+        for (key, request) in other.requests {
+            match self.requests.get_mut(&key) {
+                Some(summary) => {
+                    if request.timestamp < summary.timestamp {
+                        summary.timestamp = request.timestamp;
+                    }
+                    if request.status != "Received" {
+                        summary.status = request.status;
+                    }
+                    if summary.requester.is_empty() {
+                        summary.requester = request.requester;
+                    }
+                    if summary.template.is_empty() {
+                        summary.template = request.template;
+                    }
+                    if summary.subject_alt_names.is_empty() {
+                        summary.subject_alt_names = request.subject_alt_names;
+                    }
+                }
+                None => {
+                    self.requests.insert(key, request);
+                }
+            }
+        }
+        self.logons.extend(other.logons);
+    }
+
+    /// 不審な点を判定して、時系列順に並べた証明書の要求とログオンを返す
+    pub fn activities(&self) -> Vec<CertificateActivity> {
+        let mut activities: Vec<CertificateActivity> = vec![];
+        // SANに別のアカウントを指定して発行された証明書のアカウント
+        let mut impersonated: HashSet<String> = HashSet::new();
+        for request in self.requests.values() {
+            let mut request = request.clone();
+            let requester = account_name(&request.requester);
+            for san in request.subject_alt_names.iter() {
+                let (kind, value) = san.split_once('=').unwrap_or(("", san.as_str()));
+                let name = match kind {
+                    "upn" => value.split('@').next().unwrap_or_default(),
+                    // コンピュータアカウントの場合はホスト名と比較する
+                    "dns" if request.requester.ends_with('$') => {
+                        value.split('.').next().unwrap_or_default()
+                    }
+                    _ => continue,
+                };
+                if !name.is_empty() && name != requester {
+                    request.reasons.push(if kind == "upn" {
+                        "SAN UPN differs from the requester"
+                    } else {
+                        "SAN DNS name differs from the requester"
+                    });
+                    if request.status == "Issued" {
+                        impersonated.insert(name.to_string());
+                    }
+                }
+            }
+            if request.status == "Denied" && !request.reasons.is_empty() {
+                request.reasons.push("Request denied");
+            }
+            request.reasons.dedup();
+            activities.push(request);
+        }
+        for logon in self.logons.iter() {
+            let mut logon = logon.clone();
+            if impersonated.contains(&account_name(&logon.requester)) {
+                logon
+                    .reasons
+                    .push("Logon with a certificate issued to another account");
+            }
+            activities.push(logon);
+        }
+        activities.sort_by(|x, y| {
+            x.timestamp
+                .cmp(&y.timestamp)
+                .then_with(|| x.computer.cmp(&y.computer))
+                .then_with(|| x.request_id.cmp(&y.request_id))
+        });
+        activities
+    }
+}
+
+/// 要求の属性(例: CertificateTemplate:User\nSAN:upn=administrator@corp.local&dns=dc01.corp.local)から
+/// テンプレート名と小文字にしたSANを取り出す
+fn parse_attributes(attributes: &str) -> (String, Vec<String>) {
+    let mut template = String::default();
+    let mut subject_alt_names = vec![];
+    for line in attributes.split(&['\r', '\n'][..]) {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            "certificatetemplate" => template = value.to_string(),
+            "san" => subject_alt_names.extend(
+                value
+                    .split('&')
+                    .filter(|san| !san.is_empty())
+                    .map(|san| san.to_lowercase()),
+            ),
+            _ => {}
+        }
+    }
+    (template, subject_alt_names)
+}
+
+/// DOMAIN\userやuser@domainの形式からアカウント名を小文字で取り出す。コンピュータアカウントの$は除く
+fn account_name(account: &str) -> String {
+    let account = account.rsplit('\\').next().unwrap_or_default();
+    let account = account.split('@').next().unwrap_or_default();
+    account.trim_end_matches('$').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::adcs::{parse_attributes, AdcsAnalytics};
+    use serde_json::{json, Value};
+
+    fn security_record(eventid: u64, time: &str, data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Security",
+                    "Computer": "CA01.corp.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": data,
+            }
+        })
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        assert_eq!(
+            parse_attributes("CertificateTemplate:ESC1\r\nccm:PC01.corp.local\r\nSAN:upn=Administrator@corp.local&dns=dc01.corp.local"),
+            (
+                "ESC1".to_string(),
+                vec![
+                    "upn=administrator@corp.local".to_string(),
+                    "dns=dc01.corp.local".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_adcs_analytics() {
+        let mut analytics = AdcsAnalytics::new();
+        let attributes = "CertificateTemplate:ESC1\nSAN:upn=administrator@corp.local";
+        analytics.add(&security_record(
+            4886,
+            "2021-12-12T10:00:00Z",
+            json!({"RequestId": "12", "Requester": "CORP\\bob", "Attributes": attributes}),
+        ));
+        analytics.add(&security_record(
+            4887,
+            "2021-12-12T10:00:01Z",
+            json!({"RequestId": "12", "Requester": "CORP\\bob", "Attributes": attributes}),
+        ));
+        analytics.add(&security_record(
+            4887,
+            "2021-12-12T10:01:00Z",
+            json!({"RequestId": "13", "Requester": "CORP\\PC01$", "Attributes": "CertificateTemplate:Machine\nSAN:dns=pc01.corp.local"}),
+        ));
+        analytics.add(&security_record(
+            4768,
+            "2021-12-12T10:05:00Z",
+            json!({"TargetUserName": "Administrator", "Status": "0x0", "CertIssuerName": "corp-CA01-CA", "CertSerialNumber": "6100000012"}),
+        ));
+        // 証明書を使わないTGTの要求は対象外
+        analytics.add(&security_record(
+            4768,
+            "2021-12-12T10:06:00Z",
+            json!({"TargetUserName": "bob", "Status": "0x0", "CertIssuerName": ""}),
+        ));
+
+        let activities = analytics.activities();
+        assert_eq!(activities.len(), 3);
+        assert_eq!(activities[0].request_id, "12");
+        assert_eq!(activities[0].status, "Issued");
+        assert_eq!(activities[0].template, "ESC1");
+        assert_eq!(
+            activities[0].reasons,
+            vec!["SAN UPN differs from the requester"]
+        );
+        assert!(activities[1].reasons.is_empty());
+        assert_eq!(activities[2].activity, "Certificate Logon");
+        assert_eq!(
+            activities[2].reasons,
+            vec!["Logon with a certificate issued to another account"]
+        );
+    }
+}
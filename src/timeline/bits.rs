@@ -0,0 +1,301 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::io::BufWriter;
+
+pub const BITS_ALLOWLIST_CONFIG: &str = "bits_allowlist.txt";
+const BITS_CHANNEL: &str = "Microsoft-Windows-Bits-Client/Operational";
+
+lazy_static! {
+    /// config/bits_allowlist.txtで設定した正規の転送先のドメイン
+    static ref BITS_ALLOWLIST: Vec<String> =
+        match load_bits_allowlist(&configs::config_path(BITS_ALLOWLIST_CONFIG)) {
+            Ok(domains) => domains,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to load the BITS allowlist. {}", err),
+                )
+                .ok();
+                vec![]
+            }
+        };
+}
+
+/// ホストとBITSのジョブ毎の集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitsJob {
+    pub computer: String,
+    pub job_id: String,
+    pub title: String,
+    pub owner: String,
+    /// ジョブを作成したプロセス
+    pub process: String,
+    pub urls: BTreeSet<String>,
+    pub bytes_total: u64,
+    pub bytes_transferred: u64,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// 許可リストにないドメインとの転送があるか
+    pub non_allowlisted: bool,
+}
+
+/**
+* Bits-ClientのOperationalログのイベントID 3(ジョブの作成)、4(転送の完了)、59(転送の開始)、60(転送の停止)を
+* ホストとジョブ毎にまとめて、URLと転送したバイト数を一覧にする。許可リストにないドメインとの転送を強調して出力する。
+*/
+#[derive(Debug, Default)]
+pub struct BitsSummary {
+    // (Computer, ジョブのID)毎の集計結果
+    pub jobs: HashMap<(String, String), BitsJob>,
+}
+
+impl BitsSummary {
+    pub fn new() -> BitsSummary {
+        BitsSummary::default()
+    }
+
+    pub fn bits_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でbits-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("bits-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record, &BITS_ALLOWLIST);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value, allowlist: &[String]) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != BITS_CHANNEL {
+            return;
+        }
+        // 59と60はジョブのIDがId、ジョブ名がnameに入る
+        let (job_id, title) = match get("Event.System.EventID").as_str() {
+            "3" | "4" => (get("jobId"), get("jobTitle")),
+            "59" | "60" => (get("Id"), get("name")),
+            _ => return,
+        };
+        let evttime = get("Event.System.TimeCreated_attributes.SystemTime");
+        let key = (get("Event.System.Computer"), job_id);
+        let job = self.jobs.entry(key.clone()).or_insert_with(|| BitsJob {
+            computer: key.0,
+            job_id: key.1,
+            title: String::default(),
+            owner: String::default(),
+            process: String::default(),
+            urls: BTreeSet::new(),
+            bytes_total: 0,
+            bytes_transferred: 0,
+            first_seen: evttime.to_string(),
+            last_seen: evttime.to_string(),
+            non_allowlisted: false,
+        });
+        if job.title.is_empty() {
+            job.title = title;
+        }
+        let owner = get("jobOwner");
+        if !owner.is_empty() {
+            job.owner = owner;
+        }
+        let process = get("processPath");
+        if !process.is_empty() {
+            job.process = process;
+        }
+        let url = get("url");
+        if !url.is_empty() {
+            if !is_allowlisted(&url, allowlist) {
+                job.non_allowlisted = true;
+            }
+            job.urls.insert(url);
+        }
+        job.bytes_total = job.bytes_total.max(get("bytesTotal").parse().unwrap_or(0));
+        job.bytes_transferred = job
+            .bytes_transferred
+            .max(get("bytesTransferred").parse().unwrap_or(0));
+        if evttime < job.first_seen {
+            job.first_seen = evttime.to_string();
+        }
+        if evttime > job.last_seen {
+            job.last_seen = evttime;
+        }
+    }
+
+    /// 別のBitsSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: BitsSummary) {
+        for (key, job) in other.jobs {
+            match self.jobs.get_mut(&key) {
+                Some(summary) => {
+                    if summary.title.is_empty() {
+                        summary.title = job.title;
+                    }
+                    if summary.owner.is_empty() {
+                        summary.owner = job.owner;
+                    }
+                    if summary.process.is_empty() {
+                        summary.process = job.process;
+                    }
+                    summary.urls.extend(job.urls);
+                    summary.bytes_total = summary.bytes_total.max(job.bytes_total);
+                    summary.bytes_transferred =
+                        summary.bytes_transferred.max(job.bytes_transferred);
+                    summary.non_allowlisted |= job.non_allowlisted;
+                    if job.first_seen < summary.first_seen {
+                        summary.first_seen = job.first_seen;
+                    }
+                    if job.last_seen > summary.last_seen {
+                        summary.last_seen = job.last_seen;
+                    }
+                }
+                None => {
+                    self.jobs.insert(key, job);
+                }
+            }
+        }
+    }
+
+    /// ホスト毎に時系列順に並べた集計結果を返す
+    pub fn sorted_jobs(&self) -> Vec<&BitsJob> {
+        let mut jobs: Vec<&BitsJob> = self.jobs.values().collect();
+        jobs.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.first_seen.cmp(&y.first_seen))
+                .then_with(|| x.job_id.cmp(&y.job_id))
+        });
+        jobs
+    }
+}
+
+/// 許可リストを読み込む。空行と#から始まるコメント行は無視する
+pub fn load_bits_allowlist(path: &str) -> Result<Vec<String>, String> {
+    Ok(utils::read_txt(path)?
+        .into_iter()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+/// URLのホストが許可リストのドメインかそのサブドメインかを判定する
+fn is_allowlisted(url: &str, allowlist: &[String]) -> bool {
+    let host = url_host(url);
+    allowlist.iter().any(|domain| {
+        host == *domain
+            || host
+                .strip_suffix(domain.as_str())
+                .map_or(false, |sub| sub.ends_with('.'))
+    })
+}
+
+/// URLからスキーム、ユーザー情報、ポート番号、パスを除いたホスト名を小文字で取り出す
+fn url_host(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = url.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        // IPv6アドレス
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    host.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::bits::{is_allowlisted, url_host, BitsSummary};
+    use serde_json::{json, Value};
+
+    fn bits_record(eventid: u64, time: &str, data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Microsoft-Windows-Bits-Client/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": data,
+            }
+        })
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(
+            url_host("http://download.windowsupdate.com/c/msdownload/a.cab"),
+            "download.windowsupdate.com"
+        );
+        assert_eq!(
+            url_host("https://user@Evil.example:8443/x?y"),
+            "evil.example"
+        );
+        assert_eq!(url_host("http://[::1]:80/a"), "::1");
+        let allowlist = vec!["windowsupdate.com".to_string()];
+        assert!(is_allowlisted(
+            "http://download.windowsupdate.com/a.cab",
+            &allowlist
+        ));
+        assert!(!is_allowlisted(
+            "http://evilwindowsupdate.com/a.cab",
+            &allowlist
+        ));
+    }
+
+    #[test]
+    fn test_bits_summary() {
+        let allowlist = vec!["windowsupdate.com".to_string()];
+        let mut summary = BitsSummary::new();
+        summary.add(
+            &bits_record(
+                3,
+                "2021-12-12T10:00:00Z",
+                json!({"jobTitle": "update", "jobId": "{A}", "jobOwner": "CORP\\user", "processPath": "C:\\Windows\\System32\\bitsadmin.exe"}),
+            ),
+            &allowlist,
+        );
+        summary.add(
+            &bits_record(
+                59,
+                "2021-12-12T10:00:01Z",
+                json!({"name": "update", "Id": "{A}", "url": "http://203.0.113.5/payload.exe", "bytesTotal": 0}),
+            ),
+            &allowlist,
+        );
+        summary.add(
+            &bits_record(
+                4,
+                "2021-12-12T10:00:05Z",
+                json!({"jobTitle": "update", "jobId": "{A}", "jobOwner": "CORP\\user", "bytesTotal": 73802, "bytesTransferred": 73802}),
+            ),
+            &allowlist,
+        );
+        summary.add(
+            &bits_record(
+                60,
+                "2021-12-12T11:00:00Z",
+                json!({"name": "Windows Update", "Id": "{B}", "url": "http://download.windowsupdate.com/a.cab", "bytesTransferred": 100}),
+            ),
+            &allowlist,
+        );
+
+        let jobs = summary.sorted_jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].title, "update");
+        assert_eq!(jobs[0].process, "C:\\Windows\\System32\\bitsadmin.exe");
+        assert_eq!(jobs[0].bytes_transferred, 73802);
+        assert_eq!(jobs[0].last_seen, "2021-12-12T10:00:05Z");
+        assert!(jobs[0].non_allowlisted);
+        assert!(!jobs[1].non_allowlisted);
+    }
+}
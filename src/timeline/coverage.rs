@@ -0,0 +1,273 @@
+use crate::detections::rule::RuleNode;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use crate::filter;
+use hashbrown::{HashMap, HashSet};
+use yaml_rust::Yaml;
+
+/// ルールが検知に必要とするChannelとEventID
+#[derive(Debug, PartialEq)]
+pub struct RuleRequirement {
+    pub rulepath: String,
+    pub channels: Vec<String>,
+    pub eventids: Vec<String>,
+}
+
+/// 解析したログに存在しないために検知できないルールの集計結果
+#[derive(Debug, PartialEq)]
+pub struct CoverageGap {
+    pub channel: String,
+    /// 空の場合はChannel自体のイベントが存在しないことを表す
+    pub eventids: Vec<String>,
+    pub rule_count: usize,
+}
+
+impl RuleRequirement {
+    pub fn new(rule: &RuleNode) -> RuleRequirement {
+        let mut channels = vec![];
+        let mut eventids = vec![];
+        // notで否定されているselectionのChannelやEventIDはルールの検知に必要なものではないので数えない
+        for (_, selection) in filter::positive_selections(&rule.yaml["detection"]) {
+            RuleRequirement::collect_values(selection, &mut channels, &mut eventids);
+        }
+        channels.sort();
+        channels.dedup();
+        eventids.sort();
+        eventids.dedup();
+        RuleRequirement {
+            rulepath: rule.rulepath.to_string(),
+            channels,
+            eventids,
+        }
+    }
+
+    // selectionに含まれるChannelとEventIDの値を再帰的に集める
+    fn collect_values(yaml: &Yaml, channels: &mut Vec<String>, eventids: &mut Vec<String>) {
+        match yaml {
+            Yaml::Hash(hash) => {
+                for (key, value) in hash.iter() {
+                    match key.as_str() {
+                        Some("Channel") => channels.extend(RuleRequirement::to_strings(value)),
+                        Some("EventID") => eventids.extend(RuleRequirement::to_strings(value)),
+                        _ => RuleRequirement::collect_values(value, channels, eventids),
+                    }
+                }
+            }
+            Yaml::Array(array) => {
+                for value in array.iter() {
+                    RuleRequirement::collect_values(value, channels, eventids);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn to_strings(yaml: &Yaml) -> Vec<String> {
+        match yaml {
+            Yaml::String(s) => vec![s.to_string()],
+            Yaml::Integer(i) => vec![i.to_string()],
+            Yaml::Array(array) => array.iter().flat_map(RuleRequirement::to_strings).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/**
+* 解析したログに存在するChannelとEventIDの組み合わせを集計し、ルールが検知できるかどうかを判定する
+*/
+#[derive(Debug, Default)]
+pub struct EventCoverage {
+    pub channels: HashSet<String>,
+    pub observed: HashSet<(String, String)>,
+    /// 読み込んだルールが検知に必要とするChannelとEventID。解析を始める前に設定する
+    pub requirements: Vec<RuleRequirement>,
+}
+
+impl EventCoverage {
+    pub fn new() -> EventCoverage {
+        EventCoverage::default()
+    }
+
+    pub fn coverage_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でcoverage-gapsオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("coverage-gaps")
+        {
+            return;
+        }
+        for record in records.iter() {
+            let channel = utils::get_event_value("Event.System.Channel", &record.record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default();
+            let eventid = utils::get_event_value("EventID", &record.record)
+                .and_then(utils::get_serde_number_to_string)
+                .unwrap_or_default();
+            self.channels.insert(channel.to_string());
+            self.observed.insert((channel, eventid));
+        }
+    }
+
+    pub fn merge(&mut self, other: EventCoverage) {
+        self.channels.extend(other.channels);
+        self.observed.extend(other.observed);
+    }
+
+    /// ルールが検知に必要とするChannelとEventIDのうち、ログに存在しないものを件数の多い順に返す
+    pub fn find_gaps(&self, requirements: &[RuleRequirement]) -> Vec<CoverageGap> {
+        let mut gaps: HashMap<(String, Vec<String>), usize> = HashMap::new();
+        for requirement in requirements.iter() {
+            // Channelが指定されていて、どのChannelのイベントも存在しない場合
+            if !requirement.channels.is_empty()
+                && !requirement
+                    .channels
+                    .iter()
+                    .any(|channel| self.channels.contains(channel))
+            {
+                *gaps
+                    .entry((requirement.channels.join(" | "), vec![]))
+                    .or_insert(0) += 1;
+                continue;
+            }
+            if requirement.eventids.is_empty() {
+                continue;
+            }
+            // 対象のChannelに、EventIDのイベントが1件も存在しない場合
+            let found = self.observed.iter().any(|(channel, eventid)| {
+                (requirement.channels.is_empty() || requirement.channels.contains(channel))
+                    && requirement.eventids.contains(eventid)
+            });
+            if !found {
+                let channel = if requirement.channels.is_empty() {
+                    "*".to_string()
+                } else {
+                    requirement.channels.join(" | ")
+                };
+                *gaps
+                    .entry((channel, requirement.eventids.clone()))
+                    .or_insert(0) += 1;
+            }
+        }
+
+        let mut ret: Vec<CoverageGap> = gaps
+            .into_iter()
+            .map(|((channel, eventids), rule_count)| CoverageGap {
+                channel,
+                eventids,
+                rule_count,
+            })
+            .collect();
+        ret.sort_by(|x, y| {
+            y.rule_count
+                .cmp(&x.rule_count)
+                .then_with(|| x.channel.cmp(&y.channel))
+                .then_with(|| x.eventids.cmp(&y.eventids))
+        });
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::rule::create_rule;
+    use crate::timeline::coverage::{CoverageGap, EventCoverage, RuleRequirement};
+    use yaml_rust::YamlLoader;
+
+    fn create_requirement(rule_str: &str) -> RuleRequirement {
+        let rule_yaml = YamlLoader::load_from_str(rule_str).unwrap();
+        let rule_node = create_rule("testpath".to_string(), rule_yaml[0].clone());
+        RuleRequirement::new(&rule_node)
+    }
+
+    #[test]
+    fn test_rule_requirement() {
+        let requirement = create_requirement(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+                EventID:
+                    - 4625
+                    - 4624
+            filter:
+                - EventID: 4648
+                - LogonType: 3
+            condition: selection and not filter
+        "#,
+        );
+        assert_eq!(requirement.channels, vec!["Security".to_string()]);
+        assert_eq!(
+            requirement.eventids,
+            vec!["4624".to_string(), "4625".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_rule_requirement_negated_parenthesis() {
+        let requirement = create_requirement(
+            r#"
+        detection:
+            selection:
+                Channel: Security
+                EventID: 4688
+            filter_sysmon:
+                Channel: Microsoft-Windows-Sysmon/Operational
+            filter_logon:
+                EventID: 4624
+            condition: selection and not (filter_sysmon or filter_logon)
+        "#,
+        );
+        assert_eq!(requirement.channels, vec!["Security".to_string()]);
+        assert_eq!(requirement.eventids, vec!["4688".to_string()]);
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let sysmon = |eventid: &str| RuleRequirement {
+            rulepath: "sysmon.yml".to_string(),
+            channels: vec!["Microsoft-Windows-Sysmon/Operational".to_string()],
+            eventids: vec![eventid.to_string()],
+        };
+        let requirements = vec![
+            sysmon("1"),
+            sysmon("3"),
+            RuleRequirement {
+                rulepath: "security.yml".to_string(),
+                channels: vec!["Security".to_string()],
+                eventids: vec!["4688".to_string()],
+            },
+            RuleRequirement {
+                rulepath: "logon.yml".to_string(),
+                channels: vec!["Security".to_string()],
+                eventids: vec!["4624".to_string()],
+            },
+            RuleRequirement {
+                rulepath: "any.yml".to_string(),
+                channels: vec![],
+                eventids: vec![],
+            },
+        ];
+        let mut coverage = EventCoverage::new();
+        coverage.channels.insert("Security".to_string());
+        coverage
+            .observed
+            .insert(("Security".to_string(), "4624".to_string()));
+
+        assert_eq!(
+            coverage.find_gaps(&requirements),
+            vec![
+                CoverageGap {
+                    channel: "Microsoft-Windows-Sysmon/Operational".to_string(),
+                    eventids: vec![],
+                    rule_count: 2,
+                },
+                CoverageGap {
+                    channel: "Security".to_string(),
+                    eventids: vec!["4688".to_string()],
+                    rule_count: 1,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+
+const DEFENDER_CHANNEL: &str = "Microsoft-Windows-Windows Defender/Operational";
+// 除外設定のレジストリのパス。小文字で比較する
+const EXCLUSIONS_KEY: &str = "\\exclusions\\";
+
+/// Windows Defenderの検知、対処、除外設定の変更のイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefenderEvent {
+    pub timestamp: String,
+    pub computer: String,
+    pub activity: &'static str,
+    pub threat: String,
+    pub severity: String,
+    /// 検知したファイルのパス。除外設定の変更の場合は除外したパスなど
+    pub path: String,
+    pub user: String,
+    pub process: String,
+    pub action: String,
+    pub eventid: String,
+}
+
+/**
+* Windows DefenderのOperationalログのイベントID 1116/1006(マルウェアの検知)、1117(対処)、5007(設定の変更)から、
+* ホスト毎の検知、対処、除外設定の変更を一覧にする。Sysmonが導入されていない環境でもほぼ必ず記録されている。
+*/
+#[derive(Debug, Default)]
+pub struct DefenderSummary {
+    pub events: Vec<DefenderEvent>,
+}
+
+impl DefenderSummary {
+    pub fn new() -> DefenderSummary {
+        DefenderSummary::default()
+    }
+
+    pub fn defender_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でdefender-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("defender-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != DEFENDER_CHANNEL {
+            return;
+        }
+        let eventid = get("Event.System.EventID");
+        let (activity, path, action) = match eventid.as_str() {
+            "1116" | "1006" => ("Detection", get("Path"), String::default()),
+            "1117" => ("Action Taken", get("Path"), get("Action Name")),
+            "5007" => {
+                let old_value = get("Old Value");
+                let new_value = get("New Value");
+                match (exclusion(&old_value), exclusion(&new_value)) {
+                    (_, Some(exclusion)) => ("Exclusion Added", exclusion, String::default()),
+                    (Some(exclusion), None) => ("Exclusion Removed", exclusion, String::default()),
+                    // 除外設定以外の設定の変更は対象外
+                    (None, None) => return,
+                }
+            }
+            _ => return,
+        };
+        // 1006は古い形式のイベントで、脅威の名前がThreat Nameではない場合がある
+        let threat = match get("Threat Name").as_str() {
+            "" => get("Malware Name"),
+            name => name.to_string(),
+        };
+        self.events.push(DefenderEvent {
+            timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+            computer: get("Event.System.Computer"),
+            activity,
+            threat,
+            severity: get("Severity Name"),
+            path,
+            user: get("Detection User"),
+            process: get("Process Name"),
+            action,
+            eventid,
+        });
+    }
+
+    /// 別のDefenderSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: DefenderSummary) {
+        self.events.extend(other.events);
+    }
+
+    /// ホスト毎に時系列順に並べたイベントを返す
+    pub fn sorted_events(&self) -> Vec<&DefenderEvent> {
+        let mut events: Vec<&DefenderEvent> = self.events.iter().collect();
+        events.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.eventid.cmp(&y.eventid))
+        });
+        events
+    }
+}
+
+/// 5007のOld ValueとNew Value(例: HKLM\SOFTWARE\Microsoft\Windows Defender\Exclusions\Paths\C:\Temp = 0x0)から、
+/// 除外設定の種類と除外した値を取り出す
+fn exclusion(value: &str) -> Option<String> {
+    let idx = value.to_ascii_lowercase().find(EXCLUSIONS_KEY)?;
+    let exclusion = &value[idx + EXCLUSIONS_KEY.len()..];
+    let exclusion = exclusion
+        .rsplit_once(" = ")
+        .map_or(exclusion, |(exclusion, _)| exclusion);
+    Some(exclusion.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::defender::{exclusion, DefenderSummary};
+    use serde_json::{json, Value};
+
+    fn defender_record(eventid: u64, time: &str, data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Microsoft-Windows-Windows Defender/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": data,
+            }
+        })
+    }
+
+    #[test]
+    fn test_exclusion() {
+        assert_eq!(
+            exclusion(
+                "HKLM\\SOFTWARE\\Microsoft\\Windows Defender\\Exclusions\\Paths\\C:\\Temp = 0x0"
+            ),
+            Some("Paths\\C:\\Temp".to_string())
+        );
+        assert_eq!(
+            exclusion("HKLM\\SOFTWARE\\Microsoft\\Windows Defender\\Real-Time Protection\\DisableRealtimeMonitoring = 0x1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_defender_summary() {
+        let mut summary = DefenderSummary::new();
+        summary.add(&defender_record(
+            1116,
+            "2021-12-12T10:00:00Z",
+            json!({"Threat Name": "HackTool:Win32/Mimikatz", "Severity Name": "High", "Path": "file:_C:\\Users\\Public\\m.exe", "Detection User": "CORP\\user", "Process Name": "C:\\Windows\\explorer.exe"}),
+        ));
+        summary.add(&defender_record(
+            1117,
+            "2021-12-12T10:00:01Z",
+            json!({"Threat Name": "HackTool:Win32/Mimikatz", "Path": "file:_C:\\Users\\Public\\m.exe", "Action Name": "Quarantine"}),
+        ));
+        summary.add(&defender_record(
+            5007,
+            "2021-12-12T09:00:00Z",
+            json!({"Old Value": "", "New Value": "HKLM\\SOFTWARE\\Microsoft\\Windows Defender\\Exclusions\\Paths\\C:\\Users\\Public = 0x0"}),
+        ));
+        summary.add(&defender_record(
+            5007,
+            "2021-12-12T09:00:01Z",
+            json!({"Old Value": "HKLM\\SOFTWARE\\Microsoft\\Windows Defender\\Signature Updates\\SignatureType = 0x1", "New Value": "HKLM\\SOFTWARE\\Microsoft\\Windows Defender\\Signature Updates\\SignatureType = 0x2"}),
+        ));
+
+        let events = summary.sorted_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].activity, "Exclusion Added");
+        assert_eq!(events[0].path, "Paths\\C:\\Users\\Public");
+        assert_eq!(events[1].activity, "Detection");
+        assert_eq!(events[1].threat, "HackTool:Win32/Mimikatz");
+        assert_eq!(events[1].severity, "High");
+        assert_eq!(events[2].activity, "Action Taken");
+        assert_eq!(events[2].action, "Quarantine");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+use std::collections::BTreeSet;
+
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+const DNS_CLIENT_CHANNEL: &str = "Microsoft-Windows-DNS-Client/Operational";
+// 全ホストでの問い合わせ回数がこの回数以下で、1台のホストからしか問い合わせのないドメインを珍しいドメインとする
+const RARE_THRESHOLD: usize = 3;
+
+/// ホストとドメイン毎の問い合わせの集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsQuerySummary {
+    pub computer: String,
+    pub query_name: String,
+    pub count: usize,
+    pub images: BTreeSet<String>,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/**
+* SysmonのイベントID 22とDNS-ClientのOperationalログのイベントID 3006から、ホスト毎のDNSの問い合わせを集計する。
+* 問い合わせの少ないドメインを珍しいドメインとして出力する(least frequency analysis)。
+*/
+#[derive(Debug, Default)]
+pub struct DnsSummary {
+    // (Computer, QueryName)毎の集計結果
+    pub queries: HashMap<(String, String), DnsQuerySummary>,
+}
+
+impl DnsSummary {
+    pub fn new() -> DnsSummary {
+        DnsSummary::default()
+    }
+
+    pub fn dns_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でdns-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("dns-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        if !((channel == SYSMON_CHANNEL && eventid == "22")
+            || (channel == DNS_CLIENT_CHANNEL && eventid == "3006"))
+        {
+            return;
+        }
+        // 大文字小文字と末尾のドットの違いは同じドメインとして扱う
+        let query_name = get("QueryName").trim_end_matches('.').to_lowercase();
+        if query_name.is_empty() {
+            return;
+        }
+        let evttime = get("Event.System.TimeCreated_attributes.SystemTime");
+        let key = (get("Event.System.Computer"), query_name);
+        let summary = self
+            .queries
+            .entry(key.clone())
+            .or_insert_with(|| DnsQuerySummary {
+                computer: key.0,
+                query_name: key.1,
+                count: 0,
+                images: BTreeSet::new(),
+                first_seen: evttime.to_string(),
+                last_seen: evttime.to_string(),
+            });
+        summary.count += 1;
+        // DNS-Clientのログにはプロセスの情報がない
+        let image = get("Image");
+        if !image.is_empty() {
+            summary.images.insert(image);
+        }
+        if evttime < summary.first_seen {
+            summary.first_seen = evttime.to_string();
+        }
+        if evttime > summary.last_seen {
+            summary.last_seen = evttime;
+        }
+    }
+
+    /// 別のDnsSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: DnsSummary) {
+        for (key, query) in other.queries {
+            match self.queries.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += query.count;
+                    summary.images.extend(query.images);
+                    if query.first_seen < summary.first_seen {
+                        summary.first_seen = query.first_seen;
+                    }
+                    if query.last_seen > summary.last_seen {
+                        summary.last_seen = query.last_seen;
+                    }
+                }
+                None => {
+                    self.queries.insert(key, query);
+                }
+            }
+        }
+    }
+
+    /// ドメイン毎の全ホストでの問い合わせ回数とホスト数
+    fn domain_totals(&self) -> HashMap<&str, (usize, usize)> {
+        let mut totals: HashMap<&str, (usize, usize)> = HashMap::new();
+        for query in self.queries.values() {
+            let total = totals.entry(&query.query_name).or_default();
+            total.0 += query.count;
+            total.1 += 1;
+        }
+        totals
+    }
+
+    /// ホスト毎に問い合わせの少ない順に並べた集計結果と、珍しいドメインかどうかを返す
+    pub fn sorted_queries(&self) -> Vec<(&DnsQuerySummary, bool)> {
+        let totals = self.domain_totals();
+        let mut queries: Vec<(&DnsQuerySummary, bool)> = self
+            .queries
+            .values()
+            .map(|query| {
+                let (count, hosts) = totals[query.query_name.as_str()];
+                (query, hosts == 1 && count <= RARE_THRESHOLD)
+            })
+            .collect();
+        queries.sort_by(|x, y| {
+            x.0.computer
+                .cmp(&y.0.computer)
+                .then_with(|| x.0.count.cmp(&y.0.count))
+                .then_with(|| x.0.query_name.cmp(&y.0.query_name))
+        });
+        queries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::dns::DnsSummary;
+    use serde_json::json;
+
+    fn dns_record(channel: &str, eventid: u64, computer: &str, query: &str) -> serde_json::Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": channel,
+                    "Computer": computer,
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "QueryName": query,
+                    "Image": "C:\\Windows\\System32\\svchost.exe",
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_dns_summary() {
+        let sysmon = "Microsoft-Windows-Sysmon/Operational";
+        let dns_client = "Microsoft-Windows-DNS-Client/Operational";
+        let mut summary = DnsSummary::new();
+        for _ in 0..5 {
+            summary.add(&dns_record(sysmon, 22, "PC01", "www.example.com"));
+        }
+        summary.add(&dns_record(sysmon, 22, "PC02", "WWW.example.com."));
+        summary.add(&dns_record(sysmon, 22, "PC01", "c2.evil.example"));
+        summary.add(&dns_record(dns_client, 3006, "PC02", "update.example.net"));
+        // 問い合わせ完了のイベントは重複するので集計しない
+        summary.add(&dns_record(dns_client, 3008, "PC02", "update.example.net"));
+        summary.add(&dns_record(sysmon, 3, "PC02", "other.example.net"));
+
+        let queries = summary.sorted_queries();
+        assert_eq!(queries.len(), 4);
+        assert_eq!(queries[0].0.query_name, "c2.evil.example");
+        assert!(queries[0].1);
+        assert_eq!(queries[1].0.query_name, "www.example.com");
+        assert_eq!(queries[1].0.count, 5);
+        assert!(!queries[1].1);
+        // 問い合わせが1回でも複数のホストから問い合わせがあれば珍しいドメインとしない
+        assert_eq!(queries[2].0.computer, "PC02");
+        assert!(!queries
+            .iter()
+            .any(|(q, rare)| q.query_name == "www.example.com" && *rare));
+        assert!(queries
+            .iter()
+            .any(|(q, rare)| q.query_name == "update.example.net" && *rare && q.count == 1));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+
+const FIREWALL_CHANNEL: &str = "Microsoft-Windows-Windows Firewall With Advanced Security/Firewall";
+
+/// ファイアウォールのルールの追加、変更、削除のイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRuleChange {
+    pub timestamp: String,
+    pub computer: String,
+    pub change: &'static str,
+    pub rule_id: String,
+    pub rule_name: String,
+    pub direction: String,
+    pub action: String,
+    pub application: String,
+    pub protocol: String,
+    pub local_ports: String,
+    pub remote_addresses: String,
+    /// ルールを変更したプロセス
+    pub modifying_application: String,
+    pub modifying_user: String,
+    pub eventid: String,
+}
+
+/**
+* Windows FirewallのログのイベントID 2004(追加)、2005(変更)、2006(削除)、2033(全ルールの削除)から、
+* ホスト毎のファイアウォールのルールの変更と変更したプロセスを一覧にする。
+*/
+#[derive(Debug, Default)]
+pub struct FirewallSummary {
+    pub changes: Vec<FirewallRuleChange>,
+}
+
+impl FirewallSummary {
+    pub fn new() -> FirewallSummary {
+        FirewallSummary::default()
+    }
+
+    pub fn firewall_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でfirewall-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("firewall-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != FIREWALL_CHANNEL {
+            return;
+        }
+        let eventid = get("Event.System.EventID");
+        let change = match eventid.as_str() {
+            "2004" => "Rule Added",
+            "2005" => "Rule Modified",
+            "2006" => "Rule Deleted",
+            "2033" => "All Rules Deleted",
+            _ => return,
+        };
+        self.changes.push(FirewallRuleChange {
+            timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+            computer: get("Event.System.Computer"),
+            change,
+            rule_id: get("RuleId"),
+            rule_name: get("RuleName"),
+            direction: direction_name(&get("Direction")),
+            action: action_name(&get("Action")),
+            application: get("ApplicationPath"),
+            protocol: get("Protocol"),
+            local_ports: get("LocalPorts"),
+            remote_addresses: get("RemoteAddresses"),
+            modifying_application: get("ModifyingApplication"),
+            modifying_user: get("ModifyingUser"),
+            eventid,
+        });
+    }
+
+    /// 別のFirewallSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: FirewallSummary) {
+        self.changes.extend(other.changes);
+    }
+
+    /// ホスト毎に時系列順に並べた変更を返す
+    pub fn sorted_changes(&self) -> Vec<&FirewallRuleChange> {
+        let mut changes: Vec<&FirewallRuleChange> = self.changes.iter().collect();
+        changes.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.rule_id.cmp(&y.rule_id))
+        });
+        changes
+    }
+}
+
+/// FW_DIRECTIONの値を名前にする
+fn direction_name(direction: &str) -> String {
+    match direction {
+        "1" => "Inbound".to_string(),
+        "2" => "Outbound".to_string(),
+        _ => direction.to_string(),
+    }
+}
+
+/// FW_RULE_ACTIONの値を名前にする
+fn action_name(action: &str) -> String {
+    match action {
+        "1" => "Block".to_string(),
+        "2" => "Allow".to_string(),
+        "3" => "Allow Bypass".to_string(),
+        _ => action.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::firewall::FirewallSummary;
+    use serde_json::json;
+
+    #[test]
+    fn test_firewall_summary() {
+        let mut summary = FirewallSummary::new();
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 2006,
+                    "Channel": "Microsoft-Windows-Windows Firewall With Advanced Security/Firewall",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:01Z" },
+                },
+                "EventData": {
+                    "RuleId": "{B}",
+                    "RuleName": "Block SMB",
+                    "ModifyingUser": "S-1-5-21-1-2-3-500",
+                    "ModifyingApplication": "C:\\Windows\\System32\\netsh.exe",
+                },
+            }
+        }));
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 2004,
+                    "Channel": "Microsoft-Windows-Windows Firewall With Advanced Security/Firewall",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "RuleId": "{A}",
+                    "RuleName": "RDP",
+                    "Direction": 1,
+                    "Action": 2,
+                    "Protocol": 6,
+                    "LocalPorts": "3389",
+                    "RemoteAddresses": "*",
+                    "ModifyingUser": "S-1-5-21-1-2-3-500",
+                    "ModifyingApplication": "C:\\Windows\\System32\\netsh.exe",
+                },
+            }
+        }));
+
+        let changes = summary.sorted_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].change, "Rule Added");
+        assert_eq!(changes[0].direction, "Inbound");
+        assert_eq!(changes[0].action, "Allow");
+        assert_eq!(changes[0].local_ports, "3389");
+        assert_eq!(changes[1].change, "Rule Deleted");
+        assert_eq!(changes[1].direction, "");
+        assert_eq!(
+            changes[1].modifying_application,
+            "C:\\Windows\\System32\\netsh.exe"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::{HashMap, HashSet};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use std::fs;
+use std::io::BufWriter;
+
+lazy_static! {
+    /// --ioc-fileで指定したIOCのリスト
+    static ref IOC_LIST: IocList = match configs::CONFIG.read().unwrap().args.value_of("ioc-file") {
+        Some(path) => match load_iocs(path) {
+            Ok(iocs) => iocs,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to load the IOC file. {}", err),
+                )
+                .ok();
+                IocList::default()
+            }
+        },
+        None => IocList::default(),
+    };
+    // STIX 2.xのパターンの比較式(例: [ipv4-addr:value = '198.51.100.1'])
+    static ref STIX_COMPARISON: Regex = Regex::new(
+        r"(ipv4-addr|ipv6-addr|domain-name|file)\s*:\s*(value|name|hashes\.(?:'[^']+'|[\w-]+))\s*=\s*'([^']*)'"
+    )
+    .unwrap();
+}
+
+/// IOCの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IocType {
+    Ip,
+    Domain,
+    Hash,
+    Filename,
+}
+
+impl IocType {
+    /// CSVのType列の値からIOCの種類を判定する
+    fn parse(ioc_type: &str) -> Option<IocType> {
+        match ioc_type.trim().to_lowercase().as_str() {
+            "ip" | "ipv4" | "ipv6" => Some(IocType::Ip),
+            "domain" | "hostname" => Some(IocType::Domain),
+            "hash" | "md5" | "sha1" | "sha256" => Some(IocType::Hash),
+            "filename" | "file" => Some(IocType::Filename),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IocType::Ip => "IP",
+            IocType::Domain => "Domain",
+            IocType::Hash => "Hash",
+            IocType::Filename => "Filename",
+        }
+    }
+}
+
+/// IOC1件分。値は小文字で保持する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ioc {
+    pub ioc_type: IocType,
+    pub value: String,
+    pub description: String,
+}
+
+/// 読み込んだIOCのリスト
+#[derive(Debug, Default)]
+pub struct IocList {
+    iocs: HashMap<String, Ioc>,
+}
+
+impl IocList {
+    fn insert(&mut self, ioc_type: IocType, value: &str, description: &str) {
+        let value = value.trim().trim_end_matches('.').to_lowercase();
+        if value.is_empty() {
+            return;
+        }
+        self.iocs.insert(
+            value.to_string(),
+            Ioc {
+                ioc_type,
+                value,
+                description: description.trim().to_string(),
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.iocs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iocs.is_empty()
+    }
+
+    /// Type,Value,Descriptionの形式のCSVの行からIOCを読み込む。種類が不明な行は件数を返す
+    fn from_csv_rows(rows: &[Vec<String>]) -> (IocList, usize) {
+        let mut list = IocList::default();
+        let mut skipped = 0;
+        for row in rows.iter() {
+            match (row.first().and_then(|t| IocType::parse(t)), row.get(1)) {
+                (Some(ioc_type), Some(value)) => list.insert(
+                    ioc_type,
+                    value,
+                    row.get(2).map(|d| d.as_str()).unwrap_or_default(),
+                ),
+                _ => skipped += 1,
+            }
+        }
+        (list, skipped)
+    }
+
+    /// STIX 2.xのバンドルのindicatorのパターンからIOCを読み込む
+    fn from_stix(bundle: &Value) -> IocList {
+        let mut list = IocList::default();
+        let objects = match bundle["objects"].as_array() {
+            Some(objects) => objects,
+            None => return list,
+        };
+        for indicator in objects
+            .iter()
+            .filter(|object| object["type"].as_str() == Some("indicator"))
+        {
+            let pattern = indicator["pattern"].as_str().unwrap_or_default();
+            let description = indicator["name"]
+                .as_str()
+                .or_else(|| indicator["description"].as_str())
+                .unwrap_or_default();
+            for caps in STIX_COMPARISON.captures_iter(pattern) {
+                let ioc_type = match (&caps[1], &caps[2]) {
+                    ("ipv4-addr", _) | ("ipv6-addr", _) => IocType::Ip,
+                    ("domain-name", _) => IocType::Domain,
+                    ("file", "name") => IocType::Filename,
+                    ("file", property) if property.starts_with("hashes.") => IocType::Hash,
+                    _ => continue,
+                };
+                list.insert(ioc_type, &caps[3], description);
+            }
+        }
+        list
+    }
+
+    /// 値がIOCに一致するか判定する。IPアドレスはポート番号付きの場合、ドメインはサブドメインの場合も一致とする
+    fn find(&self, token: &str) -> Option<&Ioc> {
+        if let Some(ioc) = self.iocs.get(token) {
+            return Some(ioc);
+        }
+        let ip = token.strip_prefix("::ffff:").unwrap_or(token);
+        let ip = match ip.rsplit_once(':') {
+            Some((addr, port))
+                if !addr.contains(':')
+                    && !port.is_empty()
+                    && port.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                addr
+            }
+            _ => ip,
+        };
+        if ip != token {
+            if let Some(ioc) = self.iocs.get(ip).filter(|ioc| ioc.ioc_type == IocType::Ip) {
+                return Some(ioc);
+            }
+        }
+        let mut domain = token;
+        while let Some((_, parent)) = domain.split_once('.') {
+            if let Some(ioc) = self
+                .iocs
+                .get(parent)
+                .filter(|ioc| ioc.ioc_type == IocType::Domain)
+            {
+                return Some(ioc);
+            }
+            domain = parent;
+        }
+        None
+    }
+}
+
+/// IOCを読み込む。拡張子が.jsonの場合はSTIX 2.xのバンドル、それ以外はCSVとして読み込む
+pub fn load_iocs(path: &str) -> Result<IocList, String> {
+    if path.to_lowercase().ends_with(".json") {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{} [file:{}]", e, path))?;
+        let bundle: Value =
+            serde_json::from_str(&contents).map_err(|e| format!("{} [file:{}]", e, path))?;
+        return Ok(IocList::from_stix(&bundle));
+    }
+    let (list, skipped) = IocList::from_csv_rows(&utils::read_csv(path)?);
+    if skipped > 0 {
+        AlertMessage::warn(
+            &mut BufWriter::new(std::io::stderr().lock()),
+            &format!("Skipped {} IOCs of unknown type. [file:{}]", skipped, path),
+        )
+        .ok();
+    }
+    Ok(list)
+}
+
+/// IOCに一致したイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct IocMatch {
+    pub timestamp: String,
+    pub computer: String,
+    pub channel: String,
+    pub eventid: String,
+    pub record_id: String,
+    pub filepath: String,
+    /// IOCが含まれていたフィールド名と値
+    pub field: String,
+    pub value: String,
+    pub ioc: Ioc,
+}
+
+/**
+* --ioc-fileで指定したIOC(IPアドレス、ドメイン、ハッシュ値、ファイル名)を含むイベントを、Sigmaルールとは別に全て抽出する。
+*/
+#[derive(Debug, Default)]
+pub struct IocMatcher {
+    pub matches: Vec<IocMatch>,
+}
+
+impl IocMatcher {
+    pub fn new() -> IocMatcher {
+        IocMatcher::default()
+    }
+
+    pub fn ioc_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でioc-fileオプションが指定されている時だけ照合する。
+        if !configs::CONFIG.read().unwrap().args.is_present("ioc-file") || IOC_LIST.is_empty() {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record, &record.evtx_filepath, &IOC_LIST);
+        }
+    }
+
+    fn add(&mut self, record: &Value, filepath: &str, iocs: &IocList) {
+        let mut fields = vec![];
+        collect_fields("", &record["Event"], &mut fields);
+        // 1つのイベントで同じIOCは1回だけ記録する
+        let mut matched: HashSet<&str> = HashSet::new();
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        for (field, value) in fields {
+            let lowercase = value.to_lowercase();
+            // パスの最後の要素をファイル名として照合する。ファイル名に空白を含む場合がある
+            let basename = lowercase
+                .rsplit(&['\\', '/'][..])
+                .next()
+                .unwrap_or_default();
+            let found = iocs
+                .iocs
+                .get(basename)
+                .filter(|ioc| ioc.ioc_type == IocType::Filename)
+                .into_iter()
+                .chain(tokens(&lowercase).filter_map(|token| iocs.find(token)));
+            for ioc in found {
+                if !matched.insert(ioc.value.as_str()) {
+                    continue;
+                }
+                self.matches.push(IocMatch {
+                    timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+                    computer: get("Event.System.Computer"),
+                    channel: get("Event.System.Channel"),
+                    eventid: get("Event.System.EventID"),
+                    record_id: get("Event.System.EventRecordID"),
+                    filepath: filepath.to_string(),
+                    field: field.to_string(),
+                    value: value.to_string(),
+                    ioc: ioc.clone(),
+                });
+            }
+        }
+    }
+
+    /// 別のIocMatcherの照合結果を追加する
+    pub fn merge(&mut self, other: IocMatcher) {
+        self.matches.extend(other.matches);
+    }
+
+    /// 時系列順に並べた一致したイベントを返す
+    pub fn sorted_matches(&self) -> Vec<&IocMatch> {
+        let mut matches: Vec<&IocMatch> = self.matches.iter().collect();
+        matches.sort_by(|x, y| {
+            x.timestamp
+                .cmp(&y.timestamp)
+                .then_with(|| x.computer.cmp(&y.computer))
+                .then_with(|| x.record_id.cmp(&y.record_id))
+        });
+        matches
+    }
+
+    /// 読み込んだIOCの件数
+    pub fn ioc_count(&self) -> usize {
+        IOC_LIST.len()
+    }
+}
+
+/// Systemを除くイベントの全ての文字列のフィールドをフィールド名と値の組にする
+fn collect_fields<'a>(name: &'a str, value: &'a Value, fields: &mut Vec<(&'a str, &'a str)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter().filter(|(key, _)| key.as_str() != "System") {
+                collect_fields(key, value, fields);
+            }
+        }
+        Value::Array(values) => {
+            for value in values.iter() {
+                collect_fields(name, value, fields);
+            }
+        }
+        Value::String(value) => fields.push((name, value)),
+        _ => {}
+    }
+}
+
+/// フィールドの値を区切り文字で分割する。SHA256=...のようなハッシュ値、URLのホスト名、パスのファイル名もそれぞれ1つの値になる
+fn tokens(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c.is_whitespace() || "\"'`,;=|()[]<>{}/\\".contains(c))
+        .map(|token| token.trim_end_matches('.'))
+        .filter(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::ioc::{IocList, IocMatcher, IocType};
+    use serde_json::json;
+
+    #[test]
+    fn test_load_iocs() {
+        let rows = vec![
+            vec![
+                "ip".to_string(),
+                "198.51.100.1".to_string(),
+                "C2".to_string(),
+            ],
+            vec!["Domain".to_string(), "Evil.example.".to_string()],
+            vec!["url".to_string(), "http://evil.example/".to_string()],
+        ];
+        let (list, skipped) = IocList::from_csv_rows(&rows);
+        assert_eq!(list.len(), 2);
+        assert_eq!(skipped, 1);
+        assert_eq!(list.iocs["evil.example"].ioc_type, IocType::Domain);
+
+        let bundle = json!({
+            "type": "bundle",
+            "objects": [
+                {
+                    "type": "indicator",
+                    "name": "Mimikatz",
+                    "pattern": "[file:hashes.'SHA-256' = 'AAAA'] OR [file:name = 'mimikatz.exe']",
+                },
+                {"type": "indicator", "pattern": "[ipv4-addr:value = '203.0.113.5']"},
+                {"type": "malware", "name": "x"},
+            ]
+        });
+        let list = IocList::from_stix(&bundle);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.iocs["aaaa"].ioc_type, IocType::Hash);
+        assert_eq!(list.iocs["mimikatz.exe"].description, "Mimikatz");
+        assert_eq!(list.iocs["203.0.113.5"].ioc_type, IocType::Ip);
+    }
+
+    #[test]
+    fn test_ioc_matcher() {
+        let rows = vec![
+            vec!["ip".to_string(), "198.51.100.1".to_string()],
+            vec!["domain".to_string(), "evil.example".to_string()],
+            vec!["sha256".to_string(), "CCCC".to_string()],
+            vec!["filename".to_string(), "bad tool.exe".to_string()],
+        ];
+        let (list, _) = IocList::from_csv_rows(&rows);
+        let record = |id: u64, data: serde_json::Value| {
+            json!({
+                "Event": {
+                    "System": {
+                        "EventID": 1,
+                        "EventRecordID": id,
+                        "Channel": "Microsoft-Windows-Sysmon/Operational",
+                        "Computer": "evil.example",
+                        "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                    },
+                    "EventData": data,
+                }
+            })
+        };
+        let mut matcher = IocMatcher::new();
+        matcher.add(
+            &record(
+                1,
+                json!({"Image": "C:\\Temp\\bad tool.exe", "Hashes": "SHA1=BBBB,SHA256=CCCC", "CommandLine": "\"C:\\Temp\\bad tool.exe\" http://cdn.evil.example/a"}),
+            ),
+            "test.evtx",
+            &list,
+        );
+        matcher.add(
+            &record(2, json!({"DestinationIp": "::ffff:198.51.100.1:443"})),
+            "test.evtx",
+            &list,
+        );
+        // Systemのフィールドと、IOCに部分的に一致するだけの値は照合しない
+        matcher.add(
+            &record(3, json!({"DestinationIp": "198.51.100.10"})),
+            "test.evtx",
+            &list,
+        );
+
+        let matches = matcher.sorted_matches();
+        assert_eq!(matches.len(), 4);
+        assert_eq!(matches[0].field, "CommandLine");
+        assert_eq!(matches[0].ioc.value, "evil.example");
+        assert_eq!(matches[1].ioc.value, "cccc");
+        assert_eq!(matches[2].field, "Image");
+        assert_eq!(matches[2].ioc.value, "bad tool.exe");
+        assert_eq!(matches[3].record_id, "2");
+        assert_eq!(matches[3].ioc.ioc_type, IocType::Ip);
+    }
+}
@@ -0,0 +1,492 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use crate::timeline::window::SlidingWindow;
+use chrono::{DateTime, Duration, Utc};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::io::BufWriter;
+
+pub const KERBEROS_ANALYTICS_CONFIG: &str = "kerberos_analytics.txt";
+const SECURITY_CHANNEL: &str = "Security";
+// RC4-HMACの暗号化タイプ
+const RC4_ENCRYPTION_TYPES: [&str; 2] = ["0x17", "0x18"];
+
+lazy_static! {
+    // 読み込めない場合はデフォルトの値を使う
+    static ref KERBEROS_THRESHOLDS: KerberosThresholds = {
+        let config_path = configs::config_path(KERBEROS_ANALYTICS_CONFIG);
+        KerberosThresholds::load(&config_path).unwrap_or_else(|err| {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!(
+                    "Failed to load the Kerberos analytics thresholds. Using the defaults. {}",
+                    err
+                ),
+            )
+            .ok();
+            KerberosThresholds::default()
+        })
+    };
+}
+
+/// config/kerberos_analytics.txtで設定する検知の閾値
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerberosThresholds {
+    /// 1つのアカウントからのRC4のサービスチケットの要求回数
+    pub kerberoast_rc4_tgs_requests: usize,
+    /// 1つのアカウントが要求したサービスの数
+    pub kerberoast_services: usize,
+    pub kerberoast_timeframe_minutes: i64,
+    /// 1つのアカウントの事前認証の失敗回数
+    pub preauth_failures: usize,
+    pub preauth_timeframe_minutes: i64,
+}
+
+impl Default for KerberosThresholds {
+    fn default() -> Self {
+        KerberosThresholds {
+            kerberoast_rc4_tgs_requests: 10,
+            kerberoast_services: 5,
+            kerberoast_timeframe_minutes: 60,
+            preauth_failures: 20,
+            preauth_timeframe_minutes: 60,
+        }
+    }
+}
+
+impl KerberosThresholds {
+    /// 設定ファイルを読み込む。設定されていない項目はデフォルトの値を使う
+    pub fn load(path: &str) -> Result<KerberosThresholds, String> {
+        let mut thresholds = KerberosThresholds::default();
+        for line in utils::read_csv(path)? {
+            if line.len() < 2 {
+                continue;
+            }
+            let value = line[1].trim();
+            let invalid = || format!("Invalid value of {} in {}: {}", line[0], path, value);
+            match line[0].trim() {
+                "kerberoast_rc4_tgs_requests" => {
+                    thresholds.kerberoast_rc4_tgs_requests = value.parse().map_err(|_| invalid())?
+                }
+                "kerberoast_services" => {
+                    thresholds.kerberoast_services = value.parse().map_err(|_| invalid())?
+                }
+                "kerberoast_timeframe_minutes" => {
+                    thresholds.kerberoast_timeframe_minutes =
+                        value.parse().map_err(|_| invalid())?
+                }
+                "preauth_failures" => {
+                    thresholds.preauth_failures = value.parse().map_err(|_| invalid())?
+                }
+                "preauth_timeframe_minutes" => {
+                    thresholds.preauth_timeframe_minutes = value.parse().map_err(|_| invalid())?
+                }
+                _ => {}
+            }
+        }
+        Ok(thresholds)
+    }
+}
+
+/// Kerberosの認証のイベントのうち、集計に使う値
+#[derive(Debug, Clone, PartialEq)]
+struct KerberosEvent {
+    computer: String,
+    source: String,
+    service: String,
+}
+
+/// 検知結果1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerberosFinding {
+    pub analytic: &'static str,
+    pub account: String,
+    pub computers: Vec<String>,
+    pub sources: Vec<String>,
+    pub count: usize,
+    /// Kerberoastingの場合は要求されたサービス
+    pub services: Vec<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// 検知したイベントをまとめた検知結果。イベントそのものは保持せずに値の種類と件数だけを集計する
+#[derive(Debug, Clone)]
+struct FindingStats {
+    computers: BTreeSet<String>,
+    sources: BTreeSet<String>,
+    services: BTreeSet<String>,
+    count: usize,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+impl FindingStats {
+    fn new(time: DateTime<Utc>) -> FindingStats {
+        FindingStats {
+            computers: BTreeSet::new(),
+            sources: BTreeSet::new(),
+            services: BTreeSet::new(),
+            count: 0,
+            first_seen: time,
+            last_seen: time,
+        }
+    }
+
+    fn add(&mut self, time: DateTime<Utc>, event: &KerberosEvent) {
+        for (values, value) in [
+            (&mut self.computers, &event.computer),
+            (&mut self.sources, &event.source),
+            (&mut self.services, &event.service),
+        ] {
+            if !value.is_empty() && value != "-" {
+                values.insert(value.to_string());
+            }
+        }
+        self.count += 1;
+        self.first_seen = self.first_seen.min(time);
+        self.last_seen = self.last_seen.max(time);
+    }
+
+    fn merge(&mut self, other: FindingStats) {
+        self.computers.extend(other.computers);
+        self.sources.extend(other.sources);
+        self.services.extend(other.services);
+        self.count += other.count;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+/// アカウント毎の期間内のイベント
+#[derive(Debug)]
+struct AccountWindow {
+    events: SlidingWindow<KerberosEvent>,
+    // 期間内のサービス毎の要求回数
+    services: HashMap<String, usize>,
+}
+
+impl AccountWindow {
+    fn new(timeframe_minutes: i64) -> AccountWindow {
+        AccountWindow {
+            events: SlidingWindow::new(Duration::minutes(timeframe_minutes)),
+            services: HashMap::new(),
+        }
+    }
+
+    // イベントを追加する。既に期間外の遅れたイベントの場合はfalseを返す
+    fn push(&mut self, time: DateTime<Utc>, event: KerberosEvent) -> bool {
+        let service = event.service.to_string();
+        let services = &mut self.services;
+        let pushed = self.events.push(time, event, |evicted| {
+            if let Some(count) = services.get_mut(&evicted.service) {
+                *count -= 1;
+                if *count == 0 {
+                    services.remove(&evicted.service);
+                }
+            }
+        });
+        if pushed {
+            *self.services.entry(service).or_insert(0) += 1;
+        }
+        pushed
+    }
+}
+
+/**
+* SecurityログのイベントID 4768/4769/4771から、Kerberoasting(1つのアカウントからの大量のRC4のサービスチケットの要求)、
+* AS-REP Roasting(事前認証が無効なアカウントへのRC4のチケットの発行)、事前認証の大量の失敗を検知する。
+* ドメインコントローラーのログでも全てのイベントをメモリに溜めないように、アカウント毎に閾値の期間内のイベントだけを保持して、
+* 閾値を超えた期間のイベントを検知結果に集計していく。
+*/
+#[derive(Debug, Default)]
+pub struct KerberosAnalytics {
+    // アカウント毎の期間内のRC4のサービスチケットの要求(4769)
+    rc4_tgs: HashMap<String, AccountWindow>,
+    // アカウント毎の期間内の事前認証の失敗(4771)
+    preauth_failures: HashMap<String, AccountWindow>,
+    // 検知の種類とアカウント毎の検知結果
+    findings: HashMap<(&'static str, String), FindingStats>,
+}
+
+impl KerberosAnalytics {
+    pub fn new() -> KerberosAnalytics {
+        KerberosAnalytics::default()
+    }
+
+    pub fn kerberos_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でkerberos-analyticsオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("kerberos-analytics")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record, &KERBEROS_THRESHOLDS);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value, thresholds: &KerberosThresholds) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SECURITY_CHANNEL {
+            return;
+        }
+        let eventid = get("Event.System.EventID");
+        match eventid.as_str() {
+            "4769" => {
+                let encryption = get("TicketEncryptionType").to_lowercase();
+                let service = get("ServiceName");
+                // コンピュータアカウントとkrbtgtのチケットはKerberoastingの対象にならない
+                if !RC4_ENCRYPTION_TYPES.contains(&encryption.as_str())
+                    || service.ends_with('$')
+                    || service.eq_ignore_ascii_case("krbtgt")
+                {
+                    return;
+                }
+            }
+            "4768" => {
+                let encryption = get("TicketEncryptionType").to_lowercase();
+                if get("PreAuthType") != "0" || !RC4_ENCRYPTION_TYPES.contains(&encryption.as_str())
+                {
+                    return;
+                }
+            }
+            "4771" => {}
+            _ => return,
+        };
+        let time = match utils::str_time_to_datetime(&get(
+            "Event.System.TimeCreated_attributes.SystemTime",
+        )) {
+            Some(time) => time,
+            None => return,
+        };
+        // 4769のTargetUserNameは要求したアカウントでuser@DOMAINの形式になる
+        let account = get("TargetUserName")
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let event = KerberosEvent {
+            computer: get("Event.System.Computer"),
+            source: get("IpAddress").trim_start_matches("::ffff:").to_string(),
+            service: get("ServiceName"),
+        };
+        let (analytic, window, detected) = match eventid.as_str() {
+            // 事前認証が無効なアカウントのRC4のチケットは1件でも検知する
+            "4768" => {
+                self.findings
+                    .entry(("AS-REP Roasting", account))
+                    .or_insert_with(|| FindingStats::new(time))
+                    .add(time, &event);
+                return;
+            }
+            "4769" => {
+                let window = self
+                    .rc4_tgs
+                    .entry(account.to_string())
+                    .or_insert_with(|| AccountWindow::new(thresholds.kerberoast_timeframe_minutes));
+                if !window.push(time, event) {
+                    return;
+                }
+                let detected = window.events.len() >= thresholds.kerberoast_rc4_tgs_requests
+                    && window.services.len() >= thresholds.kerberoast_services;
+                ("Kerberoasting", window, detected)
+            }
+            _ => {
+                let window = self
+                    .preauth_failures
+                    .entry(account.to_string())
+                    .or_insert_with(|| AccountWindow::new(thresholds.preauth_timeframe_minutes));
+                if !window.push(time, event) {
+                    return;
+                }
+                let detected = window.events.len() >= thresholds.preauth_failures;
+                ("Excessive Pre-Auth Failures", window, detected)
+            }
+        };
+        if !detected {
+            return;
+        }
+        // 閾値を超えた期間のイベントのうち、まだ検知結果に含めていないものを追加する
+        let finding = self.findings.entry((analytic, account));
+        let finding = match finding {
+            hashbrown::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                let mut stats = FindingStats::new(time);
+                for (event_time, event) in window.events.iter() {
+                    stats.add(*event_time, event);
+                }
+                entry.insert(stats);
+                return;
+            }
+        };
+        let last_seen = finding.last_seen;
+        for (event_time, event) in window.events.iter().rev() {
+            if *event_time <= last_seen {
+                break;
+            }
+            finding.add(*event_time, event);
+        }
+    }
+
+    /// 別のKerberosAnalyticsの集計結果を追加する。期間内のイベントはファイル毎に判定しているので引き継がない
+    pub fn merge(&mut self, other: KerberosAnalytics) {
+        for (key, stats) in other.findings {
+            match self.findings.get_mut(&key) {
+                Some(finding) => finding.merge(stats),
+                None => {
+                    self.findings.insert(key, stats);
+                }
+            }
+        }
+    }
+
+    /// 閾値を超えたアカウントを検知結果として返す
+    pub fn findings(&self) -> Vec<KerberosFinding> {
+        let mut findings: Vec<KerberosFinding> = self
+            .findings
+            .iter()
+            .map(|((analytic, account), stats)| KerberosFinding {
+                analytic,
+                account: account.to_string(),
+                computers: stats.computers.iter().cloned().collect(),
+                sources: stats.sources.iter().cloned().collect(),
+                count: stats.count,
+                services: if *analytic == "Kerberoasting" {
+                    stats.services.iter().cloned().collect()
+                } else {
+                    vec![]
+                },
+                first_seen: stats.first_seen,
+                last_seen: stats.last_seen,
+            })
+            .collect();
+        findings.sort_by(|x, y| {
+            x.first_seen
+                .cmp(&y.first_seen)
+                .then_with(|| x.analytic.cmp(y.analytic))
+                .then_with(|| x.account.cmp(&y.account))
+        });
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::kerberos::{KerberosAnalytics, KerberosThresholds};
+    use serde_json::{json, Value};
+    use std::fs;
+
+    fn kerberos_record(
+        eventid: u64,
+        time: &str,
+        account: &str,
+        service: &str,
+        encryption: &str,
+    ) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Security",
+                    "Computer": "DC01.corp.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": {
+                    "TargetUserName": account,
+                    "ServiceName": service,
+                    "TicketEncryptionType": encryption,
+                    "PreAuthType": "0",
+                    "IpAddress": "::ffff:10.0.0.5",
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_load_thresholds() {
+        let path = "./test_files/test_kerberos_analytics.txt";
+        fs::write(
+            path,
+            "setting,value\nkerberoast_services,3\npreauth_failures,5\n",
+        )
+        .unwrap();
+        let thresholds = KerberosThresholds::load(path);
+        fs::write(path, "setting,value\npreauth_failures,many\n").unwrap();
+        let invalid = KerberosThresholds::load(path);
+        fs::remove_file(path).ok();
+        let thresholds = thresholds.unwrap();
+        assert_eq!(thresholds.kerberoast_services, 3);
+        assert_eq!(thresholds.preauth_failures, 5);
+        assert_eq!(thresholds.kerberoast_rc4_tgs_requests, 10);
+        assert!(invalid.is_err());
+        assert!(KerberosThresholds::load("./test_files/not_exist.txt").is_err());
+    }
+
+    #[test]
+    fn test_kerberos_findings() {
+        let thresholds = KerberosThresholds {
+            kerberoast_rc4_tgs_requests: 3,
+            kerberoast_services: 3,
+            preauth_failures: 2,
+            ..Default::default()
+        };
+        let mut analytics = KerberosAnalytics::new();
+        for (idx, service) in ["MSSQLSvc", "HTTP", "CIFS"].iter().enumerate() {
+            let time = format!("2021-12-12T10:0{}:00Z", idx);
+            analytics.add(
+                &kerberos_record(4769, &time, "attacker@CORP.LOCAL", service, "0x17"),
+                &thresholds,
+            );
+            // AES のチケットとコンピュータアカウントのチケットは数えない
+            analytics.add(
+                &kerberos_record(4769, &time, "user@CORP.LOCAL", service, "0x12"),
+                &thresholds,
+            );
+            analytics.add(
+                &kerberos_record(4769, &time, "user@CORP.LOCAL", "PC01$", "0x17"),
+                &thresholds,
+            );
+        }
+        analytics.add(
+            &kerberos_record(4768, "2021-12-12T09:00:00Z", "nopreauth", "krbtgt", "0x17"),
+            &thresholds,
+        );
+        // 事前認証の失敗は期間内に閾値を超えた場合だけ検知する
+        analytics.add(
+            &kerberos_record(4771, "2021-12-12T08:00:00Z", "admin", "krbtgt", ""),
+            &thresholds,
+        );
+        analytics.add(
+            &kerberos_record(4771, "2021-12-12T11:00:00Z", "admin", "krbtgt", ""),
+            &thresholds,
+        );
+
+        let findings = analytics.findings();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].analytic, "AS-REP Roasting");
+        assert_eq!(findings[0].account, "nopreauth");
+        assert_eq!(findings[1].analytic, "Kerberoasting");
+        assert_eq!(findings[1].account, "attacker");
+        assert_eq!(findings[1].count, 3);
+        assert_eq!(findings[1].services, vec!["CIFS", "HTTP", "MSSQLSvc"]);
+        assert_eq!(findings[1].sources, vec!["10.0.0.5"]);
+
+        analytics.add(
+            &kerberos_record(4771, "2021-12-12T11:30:00Z", "admin", "krbtgt", ""),
+            &thresholds,
+        );
+        let findings = analytics.findings();
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[2].analytic, "Excessive Pre-Auth Failures");
+        assert_eq!(findings[2].count, 2);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use chrono::{DateTime, Duration, Utc};
+use hashbrown::HashMap;
+use std::collections::BTreeSet;
+
+const SECURITY_CHANNEL: &str = "Security";
+const SYSTEM_CHANNEL: &str = "System";
+// サービスのインストールをこの時間内に直前にネットワークログオンした送信元のものとみなす
+const SERVICE_CORRELATION_MINUTES: i64 = 5;
+// 送信元として扱わないアドレス
+const LOCAL_SOURCES: [&str; 5] = ["", "-", "127.0.0.1", "::1", "LOCALHOST"];
+
+/// 送信元から送信先へのホスト間の横展開の集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct LateralEdge {
+    pub source: String,
+    pub destination: String,
+    pub users: BTreeSet<String>,
+    pub activities: BTreeSet<&'static str>,
+    /// アクセスした共有フォルダとインストールされたサービス
+    pub details: BTreeSet<String>,
+    pub count: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// 送信元と紐付けてから集計するイベント
+#[derive(Debug, Clone)]
+struct PendingEvent {
+    destination: String,
+    time: DateTime<Utc>,
+    // 4672はSubjectLogonId、7045はサービス名
+    value: String,
+    user: String,
+}
+
+/**
+* SecurityログのイベントID 4624(ログオンタイプ3と10)、4648、4672、5140、5145とSystemログのイベントID 7045から、
+* 送信元ホストから送信先ホストへの横展開をホスト間の辺として集計する。
+* 4672は4624のTargetLogonIdで、7045は直前のネットワークログオンで送信元と紐付ける。
+*/
+#[derive(Debug, Default)]
+pub struct LateralMovementSummary {
+    // (送信元, 送信先)毎の集計結果
+    edges: HashMap<(String, String), LateralEdge>,
+    // (送信先, TargetLogonId)毎のログオンの送信元
+    logon_sources: HashMap<(String, String), String>,
+    // 送信先毎のネットワークログオンの日時と送信元
+    remote_logons: HashMap<String, Vec<(DateTime<Utc>, String)>>,
+    privileged_logons: Vec<PendingEvent>,
+    service_creations: Vec<PendingEvent>,
+}
+
+impl LateralMovementSummary {
+    pub fn new() -> LateralMovementSummary {
+        LateralMovementSummary::default()
+    }
+
+    pub fn lateral_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でlateral-movementかlateral-movement-dotオプションが指定されている時だけ集計する。
+        let enabled = {
+            let config = configs::CONFIG.read().unwrap();
+            config.args.is_present("lateral-movement")
+                || config.args.is_present("lateral-movement-dot")
+        };
+        if !enabled {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        if channel != SECURITY_CHANNEL && channel != SYSTEM_CHANNEL {
+            return;
+        }
+        let time = match utils::str_time_to_datetime(&get(
+            "Event.System.TimeCreated_attributes.SystemTime",
+        )) {
+            Some(time) => time,
+            None => return,
+        };
+        let computer = normalize_host(&get("Event.System.Computer"));
+        match (channel.as_str(), eventid.as_str()) {
+            (SECURITY_CHANNEL, "4624") => {
+                let activity = match get("LogonType").as_str() {
+                    "3" => "Network Logon",
+                    "10" => "RDP Logon",
+                    _ => return,
+                };
+                let source = match get("IpAddress").as_str() {
+                    "" | "-" => get("WorkstationName"),
+                    ip => ip.to_string(),
+                };
+                let source = normalize_host(&source);
+                if is_local(&source) || source == computer {
+                    return;
+                }
+                self.logon_sources.insert(
+                    (computer.to_string(), get("TargetLogonId")),
+                    source.to_string(),
+                );
+                self.remote_logons
+                    .entry(computer.to_string())
+                    .or_default()
+                    .push((time, source.to_string()));
+                let user = join_domain(&get("TargetDomainName"), &get("TargetUserName"));
+                add_edge(
+                    &mut self.edges,
+                    source,
+                    computer,
+                    time,
+                    activity,
+                    user,
+                    None,
+                );
+            }
+            (SECURITY_CHANNEL, "4648") => {
+                let destination = normalize_host(&get("TargetServerName"));
+                if is_local(&destination) || destination == computer {
+                    return;
+                }
+                let user = join_domain(&get("TargetDomainName"), &get("TargetUserName"));
+                add_edge(
+                    &mut self.edges,
+                    computer,
+                    destination,
+                    time,
+                    "Explicit Credentials",
+                    user,
+                    None,
+                );
+            }
+            (SECURITY_CHANNEL, "4672") => self.privileged_logons.push(PendingEvent {
+                destination: computer,
+                time,
+                value: get("SubjectLogonId"),
+                user: join_domain(&get("SubjectDomainName"), &get("SubjectUserName")),
+            }),
+            (SECURITY_CHANNEL, "5140") | (SECURITY_CHANNEL, "5145") => {
+                let source = normalize_host(&get("IpAddress"));
+                if is_local(&source) || source == computer {
+                    return;
+                }
+                let user = join_domain(&get("SubjectDomainName"), &get("SubjectUserName"));
+                let share = get("ShareName");
+                add_edge(
+                    &mut self.edges,
+                    source,
+                    computer,
+                    time,
+                    "Share Access",
+                    user,
+                    Some(share),
+                );
+            }
+            (SYSTEM_CHANNEL, "7045") => self.service_creations.push(PendingEvent {
+                destination: computer,
+                time,
+                value: get("ServiceName"),
+                user: get("AccountName"),
+            }),
+            _ => {}
+        }
+    }
+
+    /// 別のLateralMovementSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: LateralMovementSummary) {
+        for (key, edge) in other.edges {
+            match self.edges.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += edge.count;
+                    summary.users.extend(edge.users);
+                    summary.activities.extend(edge.activities);
+                    summary.details.extend(edge.details);
+                    summary.first_seen = summary.first_seen.min(edge.first_seen);
+                    summary.last_seen = summary.last_seen.max(edge.last_seen);
+                }
+                None => {
+                    self.edges.insert(key, edge);
+                }
+            }
+        }
+        self.logon_sources.extend(other.logon_sources);
+        for (destination, logons) in other.remote_logons {
+            self.remote_logons
+                .entry(destination)
+                .or_default()
+                .extend(logons);
+        }
+        self.privileged_logons.extend(other.privileged_logons);
+        self.service_creations.extend(other.service_creations);
+    }
+
+    /// 特権ログオンとサービスのインストールを送信元と紐付けて、送信元と送信先の順に並べた集計結果を返す
+    pub fn edges(&self) -> Vec<LateralEdge> {
+        let mut edges = self.edges.clone();
+        for logon in self.privileged_logons.iter() {
+            let key = (logon.destination.to_string(), logon.value.to_string());
+            if let Some(source) = self.logon_sources.get(&key) {
+                add_edge(
+                    &mut edges,
+                    source.to_string(),
+                    logon.destination.to_string(),
+                    logon.time,
+                    "Privileged Logon",
+                    logon.user.to_string(),
+                    None,
+                );
+            }
+        }
+        let timeframe = Duration::minutes(SERVICE_CORRELATION_MINUTES);
+        for service in self.service_creations.iter() {
+            let source = self
+                .remote_logons
+                .get(&service.destination)
+                .and_then(|logons| {
+                    logons
+                        .iter()
+                        .filter(|(time, _)| {
+                            *time <= service.time && service.time - *time <= timeframe
+                        })
+                        .max_by_key(|(time, _)| *time)
+                });
+            if let Some((_, source)) = source {
+                add_edge(
+                    &mut edges,
+                    source.to_string(),
+                    service.destination.to_string(),
+                    service.time,
+                    "Service Creation",
+                    service.user.to_string(),
+                    Some(service.value.to_string()),
+                );
+            }
+        }
+        let mut edges: Vec<LateralEdge> = edges.into_values().collect();
+        edges.sort_by(|x, y| {
+            x.source
+                .cmp(&y.source)
+                .then_with(|| x.destination.cmp(&y.destination))
+        });
+        edges
+    }
+}
+
+fn add_edge(
+    edges: &mut HashMap<(String, String), LateralEdge>,
+    source: String,
+    destination: String,
+    time: DateTime<Utc>,
+    activity: &'static str,
+    user: String,
+    detail: Option<String>,
+) {
+    let edge = edges
+        .entry((source.to_string(), destination.to_string()))
+        .or_insert_with(|| LateralEdge {
+            source,
+            destination,
+            users: BTreeSet::new(),
+            activities: BTreeSet::new(),
+            details: BTreeSet::new(),
+            count: 0,
+            first_seen: time,
+            last_seen: time,
+        });
+    edge.count += 1;
+    edge.activities.insert(activity);
+    if !user.is_empty() && user != "-" {
+        edge.users.insert(user);
+    }
+    if let Some(detail) = detail.filter(|detail| !detail.is_empty()) {
+        edge.details.insert(detail);
+    }
+    edge.first_seen = edge.first_seen.min(time);
+    edge.last_seen = edge.last_seen.max(time);
+}
+
+/// ホスト名を大文字のドメインを除いた名前にする。IPアドレスはそのまま使う
+fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_start_matches("\\\\");
+    let host = host.trim_start_matches("::ffff:");
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host.to_string();
+    }
+    host.split('.').next().unwrap_or_default().to_uppercase()
+}
+
+fn is_local(host: &str) -> bool {
+    LOCAL_SOURCES.contains(&host)
+}
+
+fn join_domain(domain: &str, user: &str) -> String {
+    if domain.is_empty() || domain == "-" {
+        user.to_string()
+    } else {
+        format!("{}\\{}", domain, user)
+    }
+}
+
+/// ホスト間の辺をGraphvizのDOT形式にする
+pub fn to_dot(edges: &[LateralEdge]) -> String {
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut dot =
+        String::from("digraph lateral_movement {\n    rankdir=LR;\n    node [shape=box];\n");
+    for edge in edges.iter() {
+        let activities: Vec<&str> = edge.activities.iter().copied().collect();
+        dot.push_str(&format!(
+            "    \"{}\" -> \"{}\" [label=\"{} ({})\"];\n",
+            escape(&edge.source),
+            escape(&edge.destination),
+            escape(&activities.join(", ")),
+            edge.count
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::lateral::{normalize_host, to_dot, LateralMovementSummary};
+    use serde_json::{json, Value};
+
+    fn record(channel: &str, eventid: u64, time: &str, data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": channel,
+                    "Computer": "FS01.corp.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": data,
+            }
+        })
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("fs01.corp.local"), "FS01");
+        assert_eq!(normalize_host("\\\\dc01"), "DC01");
+        assert_eq!(normalize_host("::ffff:10.0.0.5"), "10.0.0.5");
+        assert_eq!(normalize_host("fe80::1"), "fe80::1");
+    }
+
+    #[test]
+    fn test_lateral_movement_summary() {
+        let mut summary = LateralMovementSummary::new();
+        summary.add(&record(
+            "Security",
+            4672,
+            "2021-12-12T10:00:00Z",
+            json!({"SubjectUserName": "admin", "SubjectDomainName": "CORP", "SubjectLogonId": "0x1234"}),
+        ));
+        summary.add(&record(
+            "Security",
+            4624,
+            "2021-12-12T10:00:00Z",
+            json!({"LogonType": 3, "IpAddress": "10.0.0.5", "TargetUserName": "admin", "TargetDomainName": "CORP", "TargetLogonId": "0x1234"}),
+        ));
+        summary.add(&record(
+            "Security",
+            5140,
+            "2021-12-12T10:00:01Z",
+            json!({"IpAddress": "10.0.0.5", "SubjectUserName": "admin", "SubjectDomainName": "CORP", "ShareName": "\\\\*\\ADMIN$"}),
+        ));
+        summary.add(&record(
+            "Security",
+            4648,
+            "2021-12-12T10:05:00Z",
+            json!({"TargetServerName": "dc01.corp.local", "TargetUserName": "admin", "TargetDomainName": "CORP"}),
+        ));
+        // ローカルからのログオンは対象外
+        summary.add(&record(
+            "Security",
+            4624,
+            "2021-12-12T10:06:00Z",
+            json!({"LogonType": 3, "IpAddress": "127.0.0.1", "TargetUserName": "admin", "TargetLogonId": "0x5678"}),
+        ));
+        let mut other = LateralMovementSummary::new();
+        other.add(&record(
+            "System",
+            7045,
+            "2021-12-12T10:00:02Z",
+            json!({"ServiceName": "PSEXESVC", "AccountName": "LocalSystem"}),
+        ));
+        summary.merge(other);
+
+        let edges = summary.edges();
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].source, "10.0.0.5");
+        assert_eq!(edges[0].destination, "FS01");
+        assert_eq!(edges[0].count, 4);
+        assert_eq!(
+            edges[0].activities.iter().copied().collect::<Vec<&str>>(),
+            vec![
+                "Network Logon",
+                "Privileged Logon",
+                "Service Creation",
+                "Share Access"
+            ]
+        );
+        assert!(edges[0].details.contains("PSEXESVC"));
+        assert!(edges[0].details.contains("\\\\*\\ADMIN$"));
+        assert_eq!(edges[1].source, "FS01");
+        assert_eq!(edges[1].destination, "DC01");
+
+        let dot = to_dot(&edges);
+        assert!(dot.starts_with("digraph lateral_movement {"));
+        assert!(dot.contains("\"FS01\" -> \"DC01\" [label=\"Explicit Credentials (1)\"];"));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+use std::collections::BTreeSet;
+use std::fs;
+
+/// evtxファイル1ファイル分のメトリクス
+#[derive(Debug, Default, PartialEq)]
+pub struct FileMetrics {
+    pub filepath: String,
+    pub filesize: u64,
+    pub total: usize,
+    pub start_time: String,
+    pub end_time: String,
+    pub channels: BTreeSet<String>,
+    pub computers: BTreeSet<String>,
+}
+
+impl FileMetrics {
+    pub fn new(filepath: &str) -> FileMetrics {
+        FileMetrics {
+            filepath: filepath.to_string(),
+            filesize: fs::metadata(filepath).map(|m| m.len()).unwrap_or(0),
+            ..Default::default()
+        }
+    }
+
+    fn count_up(&mut self, record: &EvtxRecordInfo) {
+        self.total += 1;
+        if let Some(evttime) = utils::get_event_value(
+            "Event.System.TimeCreated_attributes.SystemTime",
+            &record.record,
+        )
+        .and_then(utils::value_to_string)
+        {
+            if self.start_time.is_empty() || evttime < self.start_time {
+                self.start_time = evttime.to_string();
+            }
+            if self.end_time.is_empty() || evttime > self.end_time {
+                self.end_time = evttime;
+            }
+        }
+        if let Some(channel) = utils::get_event_value("Event.System.Channel", &record.record)
+            .and_then(utils::value_to_string)
+        {
+            self.channels.insert(channel);
+        }
+        if let Some(computer) = utils::get_event_value("Event.System.Computer", &record.record)
+            .and_then(utils::value_to_string)
+        {
+            self.computers.insert(computer);
+        }
+    }
+}
+
+/**
+* 解析対象のevtxファイル毎のメトリクス(レコード数、期間、チャンネル、ファイルサイズ、コンピュータ名)を集計する
+*/
+#[derive(Debug, Default)]
+pub struct LogMetrics {
+    pub files: Vec<FileMetrics>,
+    index: HashMap<String, usize>,
+}
+
+impl LogMetrics {
+    pub fn new() -> LogMetrics {
+        LogMetrics::default()
+    }
+
+    // --run-metadataや--output-sqliteのファイル一覧でもレコード数を使うため、いずれかが指定されている時に集計する
+    fn is_enabled() -> bool {
+        let config = configs::CONFIG.read().unwrap();
+        config.args.is_present("log-metrics")
+            || config.args.is_present("run-metadata")
+            || config.args.is_present("output-sqlite")
+    }
+
+    /// 解析対象のファイルを登録する。レコードが1件もないファイルも一覧に出すため、レコードの集計前に呼び出す。
+    pub fn add_file(&mut self, filepath: &str) {
+        if !LogMetrics::is_enabled() || self.index.contains_key(filepath) {
+            return;
+        }
+        self.index.insert(filepath.to_string(), self.files.len());
+        self.files.push(FileMetrics::new(filepath));
+    }
+
+    pub fn metrics_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でlog-metricsかrun-metadataオプションが指定されている時だけ、メトリクスを集計する。
+        if !LogMetrics::is_enabled() {
+            return;
+        }
+        for record in records.iter() {
+            let idx = match self.index.get(&record.evtx_filepath) {
+                Some(idx) => *idx,
+                None => {
+                    self.index
+                        .insert(record.evtx_filepath.to_string(), self.files.len());
+                    self.files.push(FileMetrics::new(&record.evtx_filepath));
+                    self.files.len() - 1
+                }
+            };
+            self.files[idx].count_up(record);
+        }
+    }
+
+    /// 別のLogMetricsの集計結果を追加する
+    pub fn merge(&mut self, other: LogMetrics) {
+        for file in other.files {
+            match self.index.get(&file.filepath) {
+                Some(idx) => {
+                    let metrics = &mut self.files[*idx];
+                    metrics.total += file.total;
+                    if !file.start_time.is_empty()
+                        && (metrics.start_time.is_empty() || file.start_time < metrics.start_time)
+                    {
+                        metrics.start_time = file.start_time;
+                    }
+                    if !file.end_time.is_empty()
+                        && (metrics.end_time.is_empty() || file.end_time > metrics.end_time)
+                    {
+                        metrics.end_time = file.end_time;
+                    }
+                    metrics.channels.extend(file.channels);
+                    metrics.computers.extend(file.computers);
+                }
+                None => {
+                    self.index
+                        .insert(file.filepath.to_string(), self.files.len());
+                    self.files.push(file);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::metrics::{FileMetrics, LogMetrics};
+    use std::collections::BTreeSet;
+
+    fn create_metrics(filepath: &str, total: usize, start: &str, end: &str) -> FileMetrics {
+        FileMetrics {
+            filepath: filepath.to_string(),
+            filesize: 0,
+            total,
+            start_time: start.to_string(),
+            end_time: end.to_string(),
+            channels: BTreeSet::from(["Security".to_string()]),
+            computers: BTreeSet::from([format!("{}-PC", filepath)]),
+        }
+    }
+
+    #[test]
+    fn test_merge_metrics() {
+        let mut metrics = LogMetrics {
+            files: vec![create_metrics("a.evtx", 2, "2021-01-02", "2021-01-03")],
+            index: vec![("a.evtx".to_string(), 0)].into_iter().collect(),
+        };
+        let other = LogMetrics {
+            files: vec![
+                create_metrics("a.evtx", 3, "2021-01-01", "2021-01-02"),
+                create_metrics("b.evtx", 1, "2021-02-01", "2021-02-01"),
+            ],
+            index: vec![("a.evtx".to_string(), 0), ("b.evtx".to_string(), 1)]
+                .into_iter()
+                .collect(),
+        };
+        metrics.merge(other);
+
+        assert_eq!(
+            metrics.files,
+            vec![
+                create_metrics("a.evtx", 5, "2021-01-01", "2021-01-03"),
+                create_metrics("b.evtx", 1, "2021-02-01", "2021-02-01"),
+            ]
+        );
+    }
+}
@@ -1,2 +1,23 @@
+pub mod accounts;
+pub mod activity;
+pub mod adcs;
+pub mod bits;
+pub mod coverage;
+pub mod defender;
+pub mod dns;
+pub mod firewall;
+pub mod ioc;
+pub mod kerberos;
+pub mod lateral;
+pub mod metrics;
+pub mod network;
+pub mod registry;
+pub mod services;
+pub mod spray;
 pub mod statistics;
+pub mod tasks;
+pub mod time_integrity;
 pub mod timelines;
+pub mod user_timeline;
+pub mod window;
+pub mod wmi;
@@ -0,0 +1,260 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::{HashMap, HashSet};
+
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+
+/// 送信元のホスト、プロセス、送信先毎の通信の集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionSummary {
+    pub computer: String,
+    pub image: String,
+    pub destination_ip: String,
+    pub destination_port: String,
+    pub destination_hostname: String,
+    pub protocol: String,
+    pub count: usize,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// ホストとプロセス毎の通信件数と送信先の数
+#[derive(Debug, PartialEq)]
+pub struct TopTalker {
+    pub computer: String,
+    pub image: String,
+    pub connections: usize,
+    pub destinations: usize,
+}
+
+/**
+* SysmonのイベントID 3(ネットワーク接続)から、外向きの通信をホスト、プロセス、送信先毎に集計する
+*/
+#[derive(Debug, Default)]
+pub struct NetworkSummary {
+    // (Computer, Image, DestinationIp, DestinationPort)毎の集計結果
+    pub connections: HashMap<(String, String, String, String), ConnectionSummary>,
+}
+
+impl NetworkSummary {
+    pub fn new() -> NetworkSummary {
+        NetworkSummary::default()
+    }
+
+    pub fn network_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でnetwork-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("network-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SYSMON_CHANNEL || get("Event.System.EventID") != "3" {
+            return;
+        }
+        // 受信した通信は対象外にする
+        if !get("Initiated").eq_ignore_ascii_case("true") {
+            return;
+        }
+        let evttime = get("Event.System.TimeCreated_attributes.SystemTime");
+        let key = (
+            get("Event.System.Computer"),
+            get("Image"),
+            get("DestinationIp"),
+            get("DestinationPort"),
+        );
+        let summary = self
+            .connections
+            .entry(key.clone())
+            .or_insert_with(|| ConnectionSummary {
+                computer: key.0,
+                image: key.1,
+                destination_ip: key.2,
+                destination_port: key.3,
+                destination_hostname: String::default(),
+                protocol: get("Protocol"),
+                count: 0,
+                first_seen: evttime.to_string(),
+                last_seen: evttime.to_string(),
+            });
+        summary.count += 1;
+        let hostname = get("DestinationHostname");
+        if !hostname.is_empty() && hostname != "-" {
+            summary.destination_hostname = hostname;
+        }
+        if evttime < summary.first_seen {
+            summary.first_seen = evttime.to_string();
+        }
+        if evttime > summary.last_seen {
+            summary.last_seen = evttime;
+        }
+    }
+
+    /// 別のNetworkSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: NetworkSummary) {
+        for (key, conn) in other.connections {
+            match self.connections.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += conn.count;
+                    if summary.destination_hostname.is_empty() {
+                        summary.destination_hostname = conn.destination_hostname;
+                    }
+                    if conn.first_seen < summary.first_seen {
+                        summary.first_seen = conn.first_seen;
+                    }
+                    if conn.last_seen > summary.last_seen {
+                        summary.last_seen = conn.last_seen;
+                    }
+                }
+                None => {
+                    self.connections.insert(key, conn);
+                }
+            }
+        }
+    }
+
+    /// 通信件数の多い順に並べた集計結果を返す
+    pub fn sorted_connections(&self) -> Vec<&ConnectionSummary> {
+        let mut connections: Vec<&ConnectionSummary> = self.connections.values().collect();
+        connections.sort_by(|x, y| {
+            y.count
+                .cmp(&x.count)
+                .then_with(|| x.computer.cmp(&y.computer))
+                .then_with(|| x.image.cmp(&y.image))
+                .then_with(|| x.destination_ip.cmp(&y.destination_ip))
+                .then_with(|| x.destination_port.cmp(&y.destination_port))
+        });
+        connections
+    }
+
+    /// 通信件数の多いホストとプロセスを上位limit件返す
+    pub fn top_talkers(&self, limit: usize) -> Vec<TopTalker> {
+        let mut talkers: HashMap<(&str, &str), (usize, HashSet<(&str, &str)>)> = HashMap::new();
+        for conn in self.connections.values() {
+            let talker = talkers.entry((&conn.computer, &conn.image)).or_default();
+            talker.0 += conn.count;
+            talker
+                .1
+                .insert((&conn.destination_ip, &conn.destination_port));
+        }
+        let mut talkers: Vec<TopTalker> = talkers
+            .into_iter()
+            .map(
+                |((computer, image), (connections, destinations))| TopTalker {
+                    computer: computer.to_string(),
+                    image: image.to_string(),
+                    connections,
+                    destinations: destinations.len(),
+                },
+            )
+            .collect();
+        talkers.sort_by(|x, y| {
+            y.connections
+                .cmp(&x.connections)
+                .then_with(|| x.computer.cmp(&y.computer))
+                .then_with(|| x.image.cmp(&y.image))
+        });
+        talkers.truncate(limit);
+        talkers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::network::NetworkSummary;
+    use serde_json::json;
+
+    fn sysmon_record(time: &str, image: &str, dst: &str, initiated: &str) -> serde_json::Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": 3,
+                    "Channel": "Microsoft-Windows-Sysmon/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": {
+                    "Image": image,
+                    "Protocol": "tcp",
+                    "Initiated": initiated,
+                    "DestinationIp": dst,
+                    "DestinationHostname": "-",
+                    "DestinationPort": 443,
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_network_summary() {
+        let powershell = "C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\powershell.exe";
+        let mut summary = NetworkSummary::new();
+        summary.add(&sysmon_record(
+            "2021-12-12T10:00:02Z",
+            powershell,
+            "10.0.0.1",
+            "true",
+        ));
+        summary.add(&sysmon_record(
+            "2021-12-12T10:00:01Z",
+            powershell,
+            "10.0.0.1",
+            "true",
+        ));
+        summary.add(&sysmon_record(
+            "2021-12-12T10:00:03Z",
+            powershell,
+            "10.0.0.2",
+            "true",
+        ));
+        // 受信した通信は集計しない
+        summary.add(&sysmon_record(
+            "2021-12-12T10:00:04Z",
+            powershell,
+            "10.0.0.3",
+            "false",
+        ));
+
+        let mut other = NetworkSummary::new();
+        other.add(&sysmon_record(
+            "2021-12-12T11:00:00Z",
+            "C:\\chrome.exe",
+            "10.0.0.4",
+            "true",
+        ));
+        other.add(&sysmon_record(
+            "2021-12-12T12:00:00Z",
+            powershell,
+            "10.0.0.1",
+            "true",
+        ));
+        summary.merge(other);
+
+        let connections = summary.sorted_connections();
+        assert_eq!(connections.len(), 3);
+        assert_eq!(connections[0].destination_ip, "10.0.0.1");
+        assert_eq!(connections[0].destination_port, "443");
+        assert_eq!(connections[0].count, 3);
+        assert_eq!(connections[0].first_seen, "2021-12-12T10:00:01Z");
+        assert_eq!(connections[0].last_seen, "2021-12-12T12:00:00Z");
+        assert_eq!(connections[0].destination_hostname, "");
+
+        let talkers = summary.top_talkers(1);
+        assert_eq!(talkers.len(), 1);
+        assert_eq!(talkers[0].image, powershell);
+        assert_eq!(talkers[0].connections, 4);
+        assert_eq!(talkers[0].destinations, 2);
+    }
+}
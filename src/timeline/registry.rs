@@ -0,0 +1,247 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::io::BufWriter;
+
+pub const PERSISTENCE_REGISTRY_CONFIG: &str = "persistence_registry_paths.txt";
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+
+lazy_static! {
+    /// config/persistence_registry_paths.txtで設定した自動起動に使われるレジストリのパス
+    static ref PERSISTENCE_PATHS: Vec<PersistencePath> =
+        match load_persistence_paths(&configs::config_path(PERSISTENCE_REGISTRY_CONFIG)) {
+            Ok(paths) => paths,
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to load the persistence registry paths. {}", err),
+                )
+                .ok();
+                vec![]
+            }
+        };
+}
+
+/// 自動起動に使われるレジストリのパス。小文字で比較する
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistencePath {
+    pub category: String,
+    pub path: String,
+}
+
+/// ホストとレジストリのキー(値)毎の書き込みの集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistryWrite {
+    pub computer: String,
+    pub category: String,
+    pub event_type: String,
+    pub target_object: String,
+    /// 設定した値。キーの名前の変更の場合は新しい名前
+    pub details: String,
+    pub image: String,
+    pub count: usize,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/**
+* SysmonのイベントID 12/13/14(レジストリのキーの作成と削除、値の設定、名前の変更)のうち、
+* Runキー、サービス、IFEO、Winlogonなどの自動起動に使われるレジストリへの書き込みをホスト毎に集計する。
+*/
+#[derive(Debug, Default)]
+pub struct RegistryPersistenceSummary {
+    // (Computer, TargetObject, Details)毎の集計結果
+    pub writes: HashMap<(String, String, String), RegistryWrite>,
+}
+
+impl RegistryPersistenceSummary {
+    pub fn new() -> RegistryPersistenceSummary {
+        RegistryPersistenceSummary::default()
+    }
+
+    pub fn registry_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でregistry-persistenceオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("registry-persistence")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record, &PERSISTENCE_PATHS);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value, paths: &[PersistencePath]) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SYSMON_CHANNEL {
+            return;
+        }
+        let details = match get("Event.System.EventID").as_str() {
+            "12" => String::default(),
+            "13" => get("Details"),
+            "14" => get("NewName"),
+            _ => return,
+        };
+        let target_object = get("TargetObject");
+        let category = match match_persistence_path(&target_object, paths) {
+            Some(path) => path.category.to_string(),
+            None => return,
+        };
+        let evttime = get("Event.System.TimeCreated_attributes.SystemTime");
+        let key = (get("Event.System.Computer"), target_object, details);
+        let write = self
+            .writes
+            .entry(key.clone())
+            .or_insert_with(|| RegistryWrite {
+                computer: key.0,
+                category,
+                event_type: get("EventType"),
+                target_object: key.1,
+                details: key.2,
+                image: get("Image"),
+                count: 0,
+                first_seen: evttime.to_string(),
+                last_seen: evttime.to_string(),
+            });
+        write.count += 1;
+        if evttime < write.first_seen {
+            write.first_seen = evttime.to_string();
+        }
+        if evttime > write.last_seen {
+            write.last_seen = evttime;
+        }
+    }
+
+    /// 別のRegistryPersistenceSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: RegistryPersistenceSummary) {
+        for (key, write) in other.writes {
+            match self.writes.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += write.count;
+                    if write.first_seen < summary.first_seen {
+                        summary.first_seen = write.first_seen;
+                    }
+                    if write.last_seen > summary.last_seen {
+                        summary.last_seen = write.last_seen;
+                    }
+                }
+                None => {
+                    self.writes.insert(key, write);
+                }
+            }
+        }
+    }
+
+    /// ホスト、種類、最初に確認した日時の順に並べた集計結果を返す
+    pub fn sorted_writes(&self) -> Vec<&RegistryWrite> {
+        let mut writes: Vec<&RegistryWrite> = self.writes.values().collect();
+        writes.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.category.cmp(&y.category))
+                .then_with(|| x.first_seen.cmp(&y.first_seen))
+                .then_with(|| x.target_object.cmp(&y.target_object))
+        });
+        writes
+    }
+}
+
+/// 設定ファイルから種類とレジストリのパスを読み込む
+pub fn load_persistence_paths(path: &str) -> Result<Vec<PersistencePath>, String> {
+    Ok(utils::read_csv(path)?
+        .into_iter()
+        .filter(|line| line.len() >= 2 && !line[1].trim().is_empty())
+        .map(|line| PersistencePath {
+            category: line[0].trim().to_string(),
+            path: line[1].trim().to_lowercase(),
+        })
+        .collect())
+}
+
+/// レジストリのパスが自動起動に使われるパスを含む場合はその設定を返す
+fn match_persistence_path<'a>(
+    target_object: &str,
+    paths: &'a [PersistencePath],
+) -> Option<&'a PersistencePath> {
+    let target_object = target_object.to_lowercase();
+    paths
+        .iter()
+        .find(|path| target_object.contains(path.path.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detections::configs;
+    use crate::timeline::registry::{
+        load_persistence_paths, RegistryPersistenceSummary, PERSISTENCE_REGISTRY_CONFIG,
+    };
+    use serde_json::json;
+
+    fn registry_record(eventid: u64, target_object: &str, details: &str) -> serde_json::Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Microsoft-Windows-Sysmon/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "EventType": "SetValue",
+                    "Image": "C:\\Users\\Public\\evil.exe",
+                    "TargetObject": target_object,
+                    "Details": details,
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_registry_persistence_summary() {
+        let paths =
+            load_persistence_paths(&configs::config_path(PERSISTENCE_REGISTRY_CONFIG)).unwrap();
+        let mut summary = RegistryPersistenceSummary::new();
+        let run_key =
+            "HKU\\S-1-5-21-1-2-3-1001\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run\\Updater";
+        summary.add(
+            &registry_record(13, run_key, "C:\\Users\\Public\\evil.exe"),
+            &paths,
+        );
+        summary.add(
+            &registry_record(13, run_key, "C:\\Users\\Public\\evil.exe"),
+            &paths,
+        );
+        summary.add(
+            &registry_record(
+                13,
+                "HKLM\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Image File Execution Options\\sethc.exe\\Debugger",
+                "C:\\Windows\\System32\\cmd.exe",
+            ),
+            &paths,
+        );
+        // 自動起動に関係ないレジストリは対象外
+        summary.add(
+            &registry_record(
+                13,
+                "HKCU\\Software\\Microsoft\\Notepad\\lfFaceName",
+                "Consolas",
+            ),
+            &paths,
+        );
+
+        let writes = summary.sorted_writes();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0].category, "IFEO");
+        assert_eq!(writes[1].category, "Run Keys");
+        assert_eq!(writes[1].count, 2);
+        assert_eq!(writes[1].details, "C:\\Users\\Public\\evil.exe");
+    }
+}
@@ -0,0 +1,257 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+
+const SYSTEM_CHANNEL: &str = "System";
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+// 一般ユーザーが書き込めるパス。小文字で比較する
+const USER_WRITABLE_PATHS: [&str; 8] = [
+    "\\users\\",
+    "\\appdata\\",
+    "\\programdata\\",
+    "\\windows\\temp\\",
+    "\\temp\\",
+    "\\perflogs\\",
+    "\\$recycle.bin\\",
+    "%temp%",
+];
+
+/// サービスのインストールかドライバのロードか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ServiceEventKind {
+    Service,
+    Driver,
+}
+
+impl ServiceEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceEventKind::Service => "Service",
+            ServiceEventKind::Driver => "Driver",
+        }
+    }
+}
+
+/// ホストとサービス(ドライバ)毎の集計結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceSummary {
+    pub computer: String,
+    pub kind: ServiceEventKind,
+    pub name: String,
+    pub image_path: String,
+    /// サービスの場合は実行アカウント、ドライバの場合は署名者
+    pub account: String,
+    pub start_type: String,
+    pub signed: String,
+    pub count: usize,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub user_writable: bool,
+}
+
+/**
+* SystemログのイベントID 7045(サービスのインストール)とSysmonのイベントID 6(ドライバのロード)をホスト毎に集計する。
+* 一般ユーザーが書き込めるパスからインストールされたサービスを強調して出力する。
+*/
+#[derive(Debug, Default)]
+pub struct ServiceInstallSummary {
+    // (Computer, 種類, ImagePath)毎の集計結果
+    pub services: HashMap<(String, ServiceEventKind, String), ServiceSummary>,
+}
+
+impl ServiceInstallSummary {
+    pub fn new() -> ServiceInstallSummary {
+        ServiceInstallSummary::default()
+    }
+
+    pub fn service_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でservice-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("service-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        let (kind, name, image_path, account, start_type, signed) =
+            if channel == SYSTEM_CHANNEL && eventid == "7045" {
+                (
+                    ServiceEventKind::Service,
+                    get("ServiceName"),
+                    get("ImagePath"),
+                    get("AccountName"),
+                    get("StartType"),
+                    String::default(),
+                )
+            } else if channel == SYSMON_CHANNEL && eventid == "6" {
+                let image_loaded = get("ImageLoaded");
+                let name = image_loaded
+                    .rsplit('\\')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let signed = match get("SignatureStatus").as_str() {
+                    "" => get("Signed"),
+                    status => format!("{} ({})", get("Signed"), status),
+                };
+                (
+                    ServiceEventKind::Driver,
+                    name,
+                    image_loaded,
+                    get("Signature"),
+                    String::default(),
+                    signed,
+                )
+            } else {
+                return;
+            };
+        let evttime = get("Event.System.TimeCreated_attributes.SystemTime");
+        let key = (get("Event.System.Computer"), kind, image_path);
+        let summary = self
+            .services
+            .entry(key.clone())
+            .or_insert_with(|| ServiceSummary {
+                computer: key.0,
+                kind,
+                name,
+                user_writable: is_user_writable(&key.2),
+                image_path: key.2,
+                account,
+                start_type,
+                signed,
+                count: 0,
+                first_seen: evttime.to_string(),
+                last_seen: evttime.to_string(),
+            });
+        summary.count += 1;
+        if evttime < summary.first_seen {
+            summary.first_seen = evttime.to_string();
+        }
+        if evttime > summary.last_seen {
+            summary.last_seen = evttime;
+        }
+    }
+
+    /// 別のServiceInstallSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: ServiceInstallSummary) {
+        for (key, service) in other.services {
+            match self.services.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += service.count;
+                    if service.first_seen < summary.first_seen {
+                        summary.first_seen = service.first_seen;
+                    }
+                    if service.last_seen > summary.last_seen {
+                        summary.last_seen = service.last_seen;
+                    }
+                }
+                None => {
+                    self.services.insert(key, service);
+                }
+            }
+        }
+    }
+
+    /// ホスト、種類、最初に確認した日時の順に並べた集計結果を返す
+    pub fn sorted_services(&self) -> Vec<&ServiceSummary> {
+        let mut services: Vec<&ServiceSummary> = self.services.values().collect();
+        services.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.kind.cmp(&y.kind))
+                .then_with(|| x.first_seen.cmp(&y.first_seen))
+                .then_with(|| x.image_path.cmp(&y.image_path))
+        });
+        services
+    }
+}
+
+/// 一般ユーザーが書き込めるパスかを判定する
+fn is_user_writable(image_path: &str) -> bool {
+    let path = image_path.to_lowercase();
+    USER_WRITABLE_PATHS
+        .iter()
+        .any(|writable| path.contains(writable))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::services::{is_user_writable, ServiceEventKind, ServiceInstallSummary};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_user_writable() {
+        assert!(is_user_writable("C:\\Users\\Public\\svc.exe"));
+        assert!(is_user_writable("\"C:\\ProgramData\\update.exe\" -k"));
+        assert!(is_user_writable("%TEMP%\\psexesvc.exe"));
+        assert!(!is_user_writable(
+            "C:\\Windows\\System32\\svchost.exe -k netsvcs"
+        ));
+        assert!(!is_user_writable(
+            "\\SystemRoot\\System32\\drivers\\null.sys"
+        ));
+    }
+
+    #[test]
+    fn test_service_summary() {
+        let mut summary = ServiceInstallSummary::new();
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 7045,
+                    "Channel": "System",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "ServiceName": "PSEXESVC",
+                    "ImagePath": "C:\\Users\\admin\\AppData\\Local\\Temp\\PSEXESVC.exe",
+                    "StartType": "demand start",
+                    "AccountName": "LocalSystem",
+                },
+            }
+        }));
+        let mut other = ServiceInstallSummary::new();
+        other.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 6,
+                    "Channel": "Microsoft-Windows-Sysmon/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T09:00:00Z" },
+                },
+                "EventData": {
+                    "ImageLoaded": "C:\\Windows\\System32\\drivers\\evil.sys",
+                    "Signed": "false",
+                    "Signature": "",
+                    "SignatureStatus": "Unavailable",
+                },
+            }
+        }));
+        summary.merge(other);
+
+        let services = summary.sorted_services();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].kind, ServiceEventKind::Service);
+        assert_eq!(services[0].name, "PSEXESVC");
+        assert_eq!(services[0].account, "LocalSystem");
+        assert!(services[0].user_writable);
+        assert_eq!(services[1].kind, ServiceEventKind::Driver);
+        assert_eq!(services[1].name, "evil.sys");
+        assert_eq!(services[1].signed, "false (Unavailable)");
+        assert!(!services[1].user_writable);
+    }
+}
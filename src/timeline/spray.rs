@@ -0,0 +1,474 @@
+use crate::detections::print::AlertMessage;
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use crate::timeline::window::SlidingWindow;
+use chrono::{DateTime, Duration, Utc};
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::io::BufWriter;
+
+pub const FAILED_LOGON_ANALYTICS_CONFIG: &str = "failed_logon_analytics.txt";
+const SECURITY_CHANNEL: &str = "Security";
+
+lazy_static! {
+    // 読み込めない場合はデフォルトの値を使う
+    static ref FAILED_LOGON_THRESHOLDS: FailedLogonThresholds = {
+        let config_path = configs::config_path(FAILED_LOGON_ANALYTICS_CONFIG);
+        FailedLogonThresholds::load(&config_path).unwrap_or_else(|err| {
+            AlertMessage::alert(
+                &mut BufWriter::new(std::io::stderr().lock()),
+                &format!(
+                    "Failed to load the password spray thresholds. Using the defaults. {}",
+                    err
+                ),
+            )
+            .ok();
+            FailedLogonThresholds::default()
+        })
+    };
+}
+
+/// config/failed_logon_analytics.txtで設定するパスワードスプレーとブルートフォースの閾値
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedLogonThresholds {
+    /// 期間内に1つの送信元から認証に失敗したアカウントの数がこの数以上の場合はパスワードスプレーとする
+    pub spray_users: usize,
+    /// 期間内に1つの送信元から1つのアカウントへの認証の失敗がこの回数以上の場合はブルートフォースとする
+    pub brute_force_failures: usize,
+    pub timeframe_minutes: i64,
+}
+
+impl Default for FailedLogonThresholds {
+    fn default() -> Self {
+        FailedLogonThresholds {
+            spray_users: 5,
+            brute_force_failures: 10,
+            timeframe_minutes: 30,
+        }
+    }
+}
+
+impl FailedLogonThresholds {
+    /// 設定ファイルを読み込む。設定されていない項目はデフォルトの値を使う
+    pub fn load(path: &str) -> Result<FailedLogonThresholds, String> {
+        let mut thresholds = FailedLogonThresholds::default();
+        for line in utils::read_csv(path)? {
+            if line.len() < 2 {
+                continue;
+            }
+            let value = line[1].trim();
+            let invalid = || format!("Invalid value of {} in {}: {}", line[0], path, value);
+            match line[0].trim() {
+                "spray_users" => thresholds.spray_users = value.parse().map_err(|_| invalid())?,
+                "brute_force_failures" => {
+                    thresholds.brute_force_failures = value.parse().map_err(|_| invalid())?
+                }
+                "timeframe_minutes" => {
+                    thresholds.timeframe_minutes = value.parse().map_err(|_| invalid())?
+                }
+                _ => {}
+            }
+        }
+        Ok(thresholds)
+    }
+}
+
+/// 送信元とアカウント毎の認証の失敗回数
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedLogonCell {
+    pub source: String,
+    pub user: String,
+    pub count: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// パスワードスプレーまたはブルートフォースの検知結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SprayFinding {
+    pub pattern: &'static str,
+    pub source: String,
+    pub users: Vec<String>,
+    pub count: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// 検知した認証の失敗をまとめた検知結果。失敗そのものは保持せずにアカウントと件数だけを集計する
+#[derive(Debug, Clone)]
+struct SprayStats {
+    users: BTreeSet<String>,
+    count: usize,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+impl SprayStats {
+    fn new(time: DateTime<Utc>) -> SprayStats {
+        SprayStats {
+            users: BTreeSet::new(),
+            count: 0,
+            first_seen: time,
+            last_seen: time,
+        }
+    }
+
+    fn add(&mut self, time: DateTime<Utc>, user: &str) {
+        self.users.insert(user.to_string());
+        self.count += 1;
+        self.first_seen = self.first_seen.min(time);
+        self.last_seen = self.last_seen.max(time);
+    }
+
+    // 期間内の失敗のうち、まだ含めていないものを追加する
+    fn add_window<'a>(
+        &mut self,
+        failures: impl DoubleEndedIterator<Item = &'a (DateTime<Utc>, String)>,
+    ) {
+        let last_seen = self.last_seen;
+        let is_new = self.count == 0;
+        for (time, user) in failures.rev() {
+            if !is_new && *time <= last_seen {
+                break;
+            }
+            self.add(*time, user);
+        }
+    }
+
+    fn merge(&mut self, other: SprayStats) {
+        self.users.extend(other.users);
+        self.count += other.count;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+
+    fn to_finding(&self, pattern: &'static str, source: &str) -> SprayFinding {
+        SprayFinding {
+            pattern,
+            source: source.to_string(),
+            users: self.users.iter().cloned().collect(),
+            count: self.count,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        }
+    }
+}
+
+/// 送信元毎の期間内の認証の失敗
+#[derive(Debug)]
+struct SourceWindow {
+    failures: SlidingWindow<String>,
+    // 期間内のアカウント毎の失敗回数
+    users: HashMap<String, usize>,
+}
+
+/**
+* SecurityログのイベントID 4625(ログオンの失敗)と4776(NTLM認証の失敗)を送信元とアカウント毎に集計し、
+* Sigmaルールとは別に、1つの送信元から短時間に多数のアカウントへの認証に失敗するパスワードスプレーと、
+* 1つのアカウントへの認証に何度も失敗するブルートフォースを検知する。
+* 全ての失敗をメモリに溜めないように、送信元毎に閾値の期間内の失敗だけを保持して判定する。
+*/
+#[derive(Debug, Default)]
+pub struct FailedLogonAnalytics {
+    // 送信元とアカウント毎の失敗回数
+    cells: HashMap<(String, String), FailedLogonCell>,
+    // 送信元毎の期間内の失敗
+    windows: HashMap<String, SourceWindow>,
+    // 送信元毎のパスワードスプレーの検知結果
+    spray: HashMap<String, SprayStats>,
+    // 送信元とアカウント毎のブルートフォースの検知結果
+    brute_force: HashMap<(String, String), SprayStats>,
+}
+
+impl FailedLogonAnalytics {
+    pub fn new() -> FailedLogonAnalytics {
+        FailedLogonAnalytics::default()
+    }
+
+    pub fn spray_start(&mut self, records: &[EvtxRecordInfo]) {
+        // ログオンサマリーの一部として出力するので、logon-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("logon-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record, &FAILED_LOGON_THRESHOLDS);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value, thresholds: &FailedLogonThresholds) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        if get("Event.System.Channel") != SECURITY_CHANNEL {
+            return;
+        }
+        let source = match get("Event.System.EventID").as_str() {
+            "4625" => match get("IpAddress").as_str() {
+                "" | "-" => get("WorkstationName"),
+                ip => ip.trim_start_matches("::ffff:").to_string(),
+            },
+            "4776" if get("Status") != "0x0" => get("Workstation"),
+            _ => return,
+        };
+        let time = match utils::str_time_to_datetime(&get(
+            "Event.System.TimeCreated_attributes.SystemTime",
+        )) {
+            Some(time) => time,
+            None => return,
+        };
+        let source = if source.is_empty() || source == "-" {
+            "-".to_string()
+        } else {
+            source.to_uppercase()
+        };
+        let user = get("TargetUserName").to_lowercase();
+
+        let cell = self
+            .cells
+            .entry((source.to_string(), user.to_string()))
+            .or_insert_with(|| FailedLogonCell {
+                source: source.to_string(),
+                user: user.to_string(),
+                count: 0,
+                first_seen: time,
+                last_seen: time,
+            });
+        cell.count += 1;
+        cell.first_seen = cell.first_seen.min(time);
+        cell.last_seen = cell.last_seen.max(time);
+
+        let window = self
+            .windows
+            .entry(source.to_string())
+            .or_insert_with(|| SourceWindow {
+                failures: SlidingWindow::new(Duration::minutes(thresholds.timeframe_minutes)),
+                users: HashMap::new(),
+            });
+        let users = &mut window.users;
+        let pushed = window.failures.push(time, user.to_string(), |evicted| {
+            if let Some(count) = users.get_mut(&evicted) {
+                *count -= 1;
+                if *count == 0 {
+                    users.remove(&evicted);
+                }
+            }
+        });
+        // ブルートフォースは検知した後に続く失敗もまとめて1件にする
+        if let Some(stats) = self
+            .brute_force
+            .get_mut(&(source.to_string(), user.to_string()))
+        {
+            stats.add(time, &user);
+        }
+        if !pushed {
+            return;
+        }
+        let user_failures = window.users.entry(user.to_string()).or_insert(0);
+        *user_failures += 1;
+
+        if window.users.len() >= thresholds.spray_users {
+            self.spray
+                .entry(source.to_string())
+                .or_insert_with(|| SprayStats::new(time))
+                .add_window(window.failures.iter());
+        }
+        let key = (source, user);
+        if *window.users.get(&key.1).unwrap() >= thresholds.brute_force_failures
+            && !self.brute_force.contains_key(&key)
+        {
+            let mut stats = SprayStats::new(time);
+            stats.add_window(window.failures.iter().filter(|(_, user)| *user == key.1));
+            self.brute_force.insert(key, stats);
+        }
+    }
+
+    /// 別のFailedLogonAnalyticsの集計結果を追加する。期間内の失敗はファイル毎に判定しているので引き継がない
+    pub fn merge(&mut self, other: FailedLogonAnalytics) {
+        for (key, other_cell) in other.cells {
+            match self.cells.get_mut(&key) {
+                Some(cell) => {
+                    cell.count += other_cell.count;
+                    cell.first_seen = cell.first_seen.min(other_cell.first_seen);
+                    cell.last_seen = cell.last_seen.max(other_cell.last_seen);
+                }
+                None => {
+                    self.cells.insert(key, other_cell);
+                }
+            }
+        }
+        for (source, stats) in other.spray {
+            match self.spray.get_mut(&source) {
+                Some(spray) => spray.merge(stats),
+                None => {
+                    self.spray.insert(source, stats);
+                }
+            }
+        }
+        for (key, stats) in other.brute_force {
+            match self.brute_force.get_mut(&key) {
+                Some(brute_force) => brute_force.merge(stats),
+                None => {
+                    self.brute_force.insert(key, stats);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// 送信元とアカウント毎の失敗回数を多い順に返す
+    pub fn matrix(&self) -> Vec<FailedLogonCell> {
+        let mut cells: Vec<FailedLogonCell> = self.cells.values().cloned().collect();
+        cells.sort_by(|x, y| {
+            y.count
+                .cmp(&x.count)
+                .then_with(|| x.source.cmp(&y.source))
+                .then_with(|| x.user.cmp(&y.user))
+        });
+        cells
+    }
+
+    /// パスワードスプレーとブルートフォースの検知結果を返す。パスワードスプレーの送信元はブルートフォースとしては返さない
+    pub fn findings(&self) -> Vec<SprayFinding> {
+        let mut findings: Vec<SprayFinding> = self
+            .spray
+            .iter()
+            .map(|(source, stats)| stats.to_finding("Password Spray", source))
+            .collect();
+        findings.extend(
+            self.brute_force
+                .iter()
+                .filter(|((source, _), _)| !self.spray.contains_key(source))
+                .map(|((source, _), stats)| stats.to_finding("Brute Force", source)),
+        );
+        findings.sort_by(|x, y| {
+            x.first_seen
+                .cmp(&y.first_seen)
+                .then_with(|| x.source.cmp(&y.source))
+        });
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::spray::{FailedLogonAnalytics, FailedLogonThresholds};
+    use serde_json::{json, Value};
+    use std::fs;
+
+    fn failed_logon(eventid: u64, time: &str, user: &str, source: &str) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "Channel": "Security",
+                    "Computer": "DC01.corp.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": {
+                    "TargetUserName": user,
+                    "IpAddress": source,
+                    "WorkstationName": "WS01",
+                    "Workstation": source,
+                    "Status": "0xc000006a",
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn test_password_spray() {
+        let thresholds = FailedLogonThresholds::default();
+        let mut analytics = FailedLogonAnalytics::new();
+        for idx in 0..6 {
+            let time = format!("2021-12-12T10:0{}:00Z", idx);
+            analytics.add(
+                &failed_logon(4625, &time, &format!("user{}", idx), "10.0.0.5"),
+                &thresholds,
+            );
+        }
+        // 同じアカウントへの失敗が続く場合はブルートフォース
+        for idx in 0..10 {
+            let time = format!("2021-12-12T11:{:02}:00Z", idx);
+            analytics.add(
+                &failed_logon(4776, &time, "Administrator", "ATTACKER"),
+                &thresholds,
+            );
+        }
+        // ローカルからのログオンの失敗はWorkstationNameを送信元にする
+        analytics.add(
+            &failed_logon(4625, "2021-12-12T12:00:00Z", "user0", "-"),
+            &thresholds,
+        );
+
+        let findings = analytics.findings();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].pattern, "Password Spray");
+        assert_eq!(findings[0].source, "10.0.0.5");
+        assert_eq!(findings[0].users.len(), 6);
+        assert_eq!(findings[1].pattern, "Brute Force");
+        assert_eq!(findings[1].source, "ATTACKER");
+        assert_eq!(findings[1].users, vec!["administrator"]);
+        assert_eq!(findings[1].count, 10);
+
+        let matrix = analytics.matrix();
+        assert_eq!(matrix.len(), 8);
+        assert_eq!(matrix[0].source, "ATTACKER");
+        assert_eq!(matrix[0].count, 10);
+        assert!(matrix.iter().any(|cell| cell.source == "WS01"));
+    }
+
+    #[test]
+    fn test_failed_logon_window() {
+        let thresholds = FailedLogonThresholds {
+            spray_users: 3,
+            brute_force_failures: 3,
+            timeframe_minutes: 10,
+        };
+        let mut analytics = FailedLogonAnalytics::new();
+        // 10分より間隔が空いた失敗は同じ期間に数えず、期間外の失敗はメモリから取り除く
+        for idx in 0..5 {
+            let time = format!("2021-12-12T1{}:00:00Z", idx);
+            analytics.add(
+                &failed_logon(4625, &time, &format!("user{}", idx), "10.0.0.5"),
+                &thresholds,
+            );
+        }
+        assert!(analytics.findings().is_empty());
+        assert_eq!(analytics.windows["10.0.0.5"].failures.len(), 1);
+        assert_eq!(analytics.matrix().len(), 5);
+
+        // 閾値を超えた後に続く失敗も同じ検知結果にまとめる
+        for minute in [0, 2, 4, 6, 30] {
+            let time = format!("2021-12-12T20:{:02}:00Z", minute);
+            analytics.add(&failed_logon(4625, &time, "admin", "10.0.0.6"), &thresholds);
+        }
+        let findings = analytics.findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].pattern, "Brute Force");
+        assert_eq!(findings[0].count, 5);
+    }
+
+    #[test]
+    fn test_load_thresholds() {
+        let path = "./test_files/test_failed_logon_analytics.txt";
+        fs::write(path, "setting,value\nspray_users,3\n").unwrap();
+        let thresholds = FailedLogonThresholds::load(path);
+        fs::write(path, "setting,value\ntimeframe_minutes,long\n").unwrap();
+        let invalid = FailedLogonThresholds::load(path);
+        fs::remove_file(path).ok();
+        let thresholds = thresholds.unwrap();
+        assert_eq!(thresholds.spray_users, 3);
+        assert_eq!(thresholds.brute_force_failures, 10);
+        assert!(invalid.is_err());
+    }
+}
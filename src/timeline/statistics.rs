@@ -7,9 +7,44 @@ pub struct EventStatistics {
     pub filepath: String,
     pub start_time: String,
     pub end_time: String,
-    pub stats_list: HashMap<String, usize>,
+    pub stats_list: HashMap<(String, String, String), usize>,
     pub stats_login_list: HashMap<String, [usize; 2]>,
+    /// Channel、Provider名、EventID毎の件数。--provider-metricsで集計する
+    pub provider_list: HashMap<(String, String, String), usize>,
 }
+
+/// Channel毎のイベントIDの集計結果
+#[derive(Debug, PartialEq)]
+pub struct ChannelStatistics {
+    pub channel: String,
+    pub total: usize,
+    pub eventids: Vec<(String, usize)>,
+}
+
+/// Computer毎の集計結果
+#[derive(Debug, PartialEq)]
+pub struct ComputerStatistics {
+    pub computer: String,
+    pub total: usize,
+    pub channels: Vec<ChannelStatistics>,
+}
+
+/// Provider名毎の集計結果
+#[derive(Debug, PartialEq)]
+pub struct ProviderStatistics {
+    pub provider: String,
+    pub total: usize,
+    pub eventids: Vec<String>,
+}
+
+/// Channel毎のProvider名の集計結果
+#[derive(Debug, PartialEq)]
+pub struct ChannelProviders {
+    pub channel: String,
+    pub total: usize,
+    pub providers: Vec<ProviderStatistics>,
+}
+
 /**
 * Windows Event Logの統計情報を出力する
 */
@@ -19,7 +54,7 @@ impl EventStatistics {
         filepath: String,
         start_time: String,
         end_time: String,
-        stats_list: HashMap<String, usize>,
+        stats_list: HashMap<(String, String, String), usize>,
         stats_login_list: HashMap<String, [usize; 2]>,
     ) -> EventStatistics {
         EventStatistics {
@@ -29,6 +64,7 @@ impl EventStatistics {
             end_time,
             stats_list,
             stats_login_list,
+            provider_list: HashMap::new(),
         }
     }
 
@@ -47,8 +83,7 @@ impl EventStatistics {
         // _recordsから、EventIDを取り出す。
         self.stats_time_cnt(records);
 
-        // EventIDで集計
-        //let evtstat_map = HashMap::new();
+        // Computer、Channel、EventIDで集計
         self.stats_eventid(records);
     }
 
@@ -68,6 +103,27 @@ impl EventStatistics {
         self.stats_login_eventid(records);
     }
 
+    pub fn provider_stats_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でprovider-metricsオプションが指定されている時だけ、Provider名毎に集計する。
+        let (is_enabled, is_counted) = {
+            let args = &configs::CONFIG.read().unwrap().args;
+            (
+                args.is_present("provider-metrics"),
+                args.is_present("statistics") || args.is_present("logon-summary"),
+            )
+        };
+        if !is_enabled {
+            return;
+        }
+
+        // statisticsやlogon-summaryと同時に指定した場合はレコード数を二重に数えない
+        if !is_counted {
+            self.stats_time_cnt(records);
+        }
+
+        self.stats_provider(records);
+    }
+
     fn stats_time_cnt(&mut self, records: &[EvtxRecordInfo]) {
         if records.is_empty() {
             return;
@@ -97,21 +153,163 @@ impl EventStatistics {
         self.total += records.len();
     }
 
-    // EventIDで集計
+    // Computer、Channel、EventIDで集計
     fn stats_eventid(&mut self, records: &[EvtxRecordInfo]) {
-        //        let mut evtstat_map = HashMap::new();
         for record in records.iter() {
             let evtid = utils::get_event_value("EventID", &record.record);
             if evtid.is_none() {
                 continue;
             }
 
-            let idnum = evtid.unwrap();
-            let count: &mut usize = self.stats_list.entry(idnum.to_string()).or_insert(0);
+            let idnum = utils::get_serde_number_to_string(evtid.unwrap()).unwrap_or_default();
+            let computer = utils::get_event_value("Event.System.Computer", &record.record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_else(|| "-".to_string());
+            let channel = utils::get_event_value("Event.System.Channel", &record.record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_else(|| "-".to_string());
+            let count: &mut usize = self
+                .stats_list
+                .entry((computer, channel, idnum))
+                .or_insert(0);
             *count += 1;
         }
-        //        return evtstat_map;
     }
+
+    // Channel、Provider名、EventIDで集計
+    fn stats_provider(&mut self, records: &[EvtxRecordInfo]) {
+        for record in records.iter() {
+            let get = |key: &str| {
+                utils::get_event_value(key, &record.record)
+                    .and_then(utils::value_to_string)
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            let key = (
+                get("Event.System.Channel"),
+                get("Event.System.Provider_attributes.Name"),
+                get("Event.System.EventID"),
+            );
+            *self.provider_list.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    /// 別のファイルの統計情報を加算する
+    pub fn merge(&mut self, other: EventStatistics) {
+        if !other.start_time.is_empty()
+            && (self.start_time.is_empty() || other.start_time < self.start_time)
+        {
+            self.start_time = other.start_time;
+        }
+        if !other.end_time.is_empty()
+            && (self.end_time.is_empty() || other.end_time > self.end_time)
+        {
+            self.end_time = other.end_time;
+        }
+        self.total += other.total;
+        for (key, count) in other.stats_list {
+            *self.stats_list.entry(key).or_insert(0) += count;
+        }
+        for (key, count) in other.provider_list {
+            *self.provider_list.entry(key).or_insert(0) += count;
+        }
+    }
+
+    /// 集計結果をComputer毎、Channel毎にまとめて件数の多い順に並べたものを返す
+    pub fn group_by_computer_and_channel(&self) -> Vec<ComputerStatistics> {
+        let mut grouped: HashMap<&str, HashMap<&str, Vec<(String, usize)>>> = HashMap::new();
+        for ((computer, channel, eventid), count) in self.stats_list.iter() {
+            grouped
+                .entry(computer.as_str())
+                .or_insert_with(HashMap::new)
+                .entry(channel.as_str())
+                .or_insert_with(Vec::new)
+                .push((eventid.to_string(), *count));
+        }
+
+        let mut ret: Vec<ComputerStatistics> = grouped
+            .into_iter()
+            .map(|(computer, channels)| {
+                let mut channels: Vec<ChannelStatistics> = channels
+                    .into_iter()
+                    .map(|(channel, mut eventids)| {
+                        eventids.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+                        ChannelStatistics {
+                            channel: channel.to_string(),
+                            total: eventids.iter().map(|(_, count)| count).sum(),
+                            eventids,
+                        }
+                    })
+                    .collect();
+                channels.sort_by(|x, y| {
+                    y.total
+                        .cmp(&x.total)
+                        .then_with(|| x.channel.cmp(&y.channel))
+                });
+                ComputerStatistics {
+                    computer: computer.to_string(),
+                    total: channels.iter().map(|channel| channel.total).sum(),
+                    channels,
+                }
+            })
+            .collect();
+        ret.sort_by(|x, y| {
+            y.total
+                .cmp(&x.total)
+                .then_with(|| x.computer.cmp(&y.computer))
+        });
+        ret
+    }
+
+    /// Provider名毎の集計結果をChannel毎にまとめて件数の多い順に並べたものを返す
+    pub fn group_by_channel_and_provider(&self) -> Vec<ChannelProviders> {
+        let mut grouped: HashMap<&str, HashMap<&str, (usize, Vec<String>)>> = HashMap::new();
+        for ((channel, provider, eventid), count) in self.provider_list.iter() {
+            let provider = grouped
+                .entry(channel.as_str())
+                .or_insert_with(HashMap::new)
+                .entry(provider.as_str())
+                .or_insert_with(|| (0, vec![]));
+            provider.0 += count;
+            provider.1.push(eventid.to_string());
+        }
+
+        let mut ret: Vec<ChannelProviders> = grouped
+            .into_iter()
+            .map(|(channel, providers)| {
+                let mut providers: Vec<ProviderStatistics> = providers
+                    .into_iter()
+                    .map(|(provider, (total, mut eventids))| {
+                        eventids.sort_by(|x, y| {
+                            let num = |id: &str| id.parse::<u64>().unwrap_or(u64::MAX);
+                            num(x).cmp(&num(y)).then_with(|| x.cmp(y))
+                        });
+                        ProviderStatistics {
+                            provider: provider.to_string(),
+                            total,
+                            eventids,
+                        }
+                    })
+                    .collect();
+                providers.sort_by(|x, y| {
+                    y.total
+                        .cmp(&x.total)
+                        .then_with(|| x.provider.cmp(&y.provider))
+                });
+                ChannelProviders {
+                    channel: channel.to_string(),
+                    total: providers.iter().map(|provider| provider.total).sum(),
+                    providers,
+                }
+            })
+            .collect();
+        ret.sort_by(|x, y| {
+            y.total
+                .cmp(&x.total)
+                .then_with(|| x.channel.cmp(&y.channel))
+        });
+        ret
+    }
+
     // Login event
     fn stats_login_eventid(&mut self, records: &[EvtxRecordInfo]) {
         for record in records.iter() {
@@ -138,3 +336,123 @@ impl EventStatistics {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::statistics::{
+        ChannelProviders, ChannelStatistics, ComputerStatistics, EventStatistics,
+        ProviderStatistics,
+    };
+    use hashbrown::HashMap;
+
+    #[test]
+    fn test_group_by_computer_and_channel() {
+        let mut stats_list = HashMap::new();
+        let mut insert = |computer: &str, channel: &str, eventid: &str, count: usize| {
+            stats_list.insert(
+                (
+                    computer.to_string(),
+                    channel.to_string(),
+                    eventid.to_string(),
+                ),
+                count,
+            );
+        };
+        insert("PC01", "Security", "4624", 5);
+        insert("PC01", "Security", "4625", 7);
+        insert("PC01", "System", "7045", 1);
+        insert("DC01", "Security", "4624", 30);
+
+        let stats = EventStatistics::new(
+            43,
+            String::default(),
+            String::default(),
+            String::default(),
+            stats_list,
+            HashMap::new(),
+        );
+        let expected = vec![
+            ComputerStatistics {
+                computer: "DC01".to_string(),
+                total: 30,
+                channels: vec![ChannelStatistics {
+                    channel: "Security".to_string(),
+                    total: 30,
+                    eventids: vec![("4624".to_string(), 30)],
+                }],
+            },
+            ComputerStatistics {
+                computer: "PC01".to_string(),
+                total: 13,
+                channels: vec![
+                    ChannelStatistics {
+                        channel: "Security".to_string(),
+                        total: 12,
+                        eventids: vec![("4625".to_string(), 7), ("4624".to_string(), 5)],
+                    },
+                    ChannelStatistics {
+                        channel: "System".to_string(),
+                        total: 1,
+                        eventids: vec![("7045".to_string(), 1)],
+                    },
+                ],
+            },
+        ];
+        assert_eq!(stats.group_by_computer_and_channel(), expected);
+    }
+
+    #[test]
+    fn test_group_by_channel_and_provider() {
+        let mut stats = EventStatistics::new(
+            0,
+            String::default(),
+            String::default(),
+            String::default(),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let mut insert = |channel: &str, provider: &str, eventid: &str, count: usize| {
+            stats.provider_list.insert(
+                (
+                    channel.to_string(),
+                    provider.to_string(),
+                    eventid.to_string(),
+                ),
+                count,
+            );
+        };
+        insert("System", "Service Control Manager", "7045", 3);
+        insert("System", "Service Control Manager", "7036", 10);
+        insert("System", "Microsoft-Windows-Kernel-General", "1", 1);
+        insert("Security", "Microsoft-Windows-Security-Auditing", "4624", 5);
+
+        let expected = vec![
+            ChannelProviders {
+                channel: "System".to_string(),
+                total: 14,
+                providers: vec![
+                    ProviderStatistics {
+                        provider: "Service Control Manager".to_string(),
+                        total: 13,
+                        eventids: vec!["7036".to_string(), "7045".to_string()],
+                    },
+                    ProviderStatistics {
+                        provider: "Microsoft-Windows-Kernel-General".to_string(),
+                        total: 1,
+                        eventids: vec!["1".to_string()],
+                    },
+                ],
+            },
+            ChannelProviders {
+                channel: "Security".to_string(),
+                total: 5,
+                providers: vec![ProviderStatistics {
+                    provider: "Microsoft-Windows-Security-Auditing".to_string(),
+                    total: 5,
+                    eventids: vec!["4624".to_string()],
+                }],
+            },
+        ];
+        assert_eq!(stats.group_by_channel_and_provider(), expected);
+    }
+}
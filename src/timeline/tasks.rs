@@ -0,0 +1,208 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+const SECURITY_CHANNEL: &str = "Security";
+const TASK_SCHEDULER_CHANNEL: &str = "Microsoft-Windows-TaskScheduler/Operational";
+
+/// タスクの登録、更新、削除のイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskEvent {
+    pub timestamp: String,
+    pub computer: String,
+    pub action: &'static str,
+    pub task_name: String,
+    pub user: String,
+    /// タスクの定義のXMLから取り出した実行するコマンドライン
+    pub command: String,
+    pub channel: String,
+    pub eventid: String,
+}
+
+/**
+* SecurityログのイベントID 4698/4699/4702とTaskSchedulerのOperationalログのイベントID 106/140/141から、
+* ホスト毎のタスクスケジューラのタスクの登録、更新、削除を一覧にする。
+*/
+#[derive(Debug, Default)]
+pub struct TaskSummary {
+    pub events: Vec<TaskEvent>,
+}
+
+impl TaskSummary {
+    pub fn new() -> TaskSummary {
+        TaskSummary::default()
+    }
+
+    pub fn task_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でtask-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("task-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        let (action, user, command) = match (channel.as_str(), eventid.as_str()) {
+            (SECURITY_CHANNEL, "4698") => ("Created", subject_user(&get), get("TaskContent")),
+            (SECURITY_CHANNEL, "4699") => ("Deleted", subject_user(&get), String::default()),
+            (SECURITY_CHANNEL, "4702") => ("Updated", subject_user(&get), get("TaskContentNew")),
+            (TASK_SCHEDULER_CHANNEL, "106") => ("Created", get("UserContext"), String::default()),
+            (TASK_SCHEDULER_CHANNEL, "140") => ("Updated", get("UserName"), String::default()),
+            (TASK_SCHEDULER_CHANNEL, "141") => ("Deleted", get("UserName"), String::default()),
+            _ => return,
+        };
+        self.events.push(TaskEvent {
+            timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+            computer: get("Event.System.Computer"),
+            action,
+            task_name: get("TaskName"),
+            user,
+            command: task_commands(&command),
+            channel,
+            eventid,
+        });
+    }
+
+    /// 別のTaskSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: TaskSummary) {
+        self.events.extend(other.events);
+    }
+
+    /// ホスト毎に時系列順に並べたイベントを返す
+    pub fn sorted_events(&self) -> Vec<&TaskEvent> {
+        let mut events: Vec<&TaskEvent> = self.events.iter().collect();
+        events.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.task_name.cmp(&y.task_name))
+        });
+        events
+    }
+}
+
+fn subject_user(get: &dyn Fn(&str) -> String) -> String {
+    let domain = get("SubjectDomainName");
+    let user = get("SubjectUserName");
+    if domain.is_empty() {
+        user
+    } else {
+        format!("{}\\{}", domain, user)
+    }
+}
+
+/// タスクの定義のXMLからExecアクションのコマンドと引数、ComHandlerアクションのClassIdを取り出す
+fn task_commands(task_content: &str) -> String {
+    if task_content.is_empty() {
+        return String::default();
+    }
+    let mut reader = Reader::from_str(task_content);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut path: Vec<Vec<u8>> = vec![];
+    let mut commands: Vec<String> = vec![];
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) => path.push(e.local_name().to_vec()),
+            Ok(Event::End(_)) => {
+                path.pop();
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                let parent = path.len().checked_sub(2).map(|idx| path[idx].as_slice());
+                match (parent, path.last().map(|name| name.as_slice())) {
+                    (Some(b"Exec"), Some(b"Command")) | (Some(b"ComHandler"), Some(b"ClassId")) => {
+                        commands.push(text)
+                    }
+                    (Some(b"Exec"), Some(b"Arguments")) => match commands.last_mut() {
+                        Some(command) => {
+                            command.push(' ');
+                            command.push_str(&text);
+                        }
+                        None => commands.push(text),
+                    },
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    commands.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::tasks::{task_commands, TaskSummary};
+    use serde_json::json;
+
+    const TASK_CONTENT: &str = "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\r\n<Task version=\"1.2\" xmlns=\"http://schemas.microsoft.com/windows/2004/02/mit/task\">\r\n  <Actions Context=\"Author\">\r\n    <Exec>\r\n      <Command>C:\\Windows\\System32\\cmd.exe</Command>\r\n      <Arguments>/c \"C:\\Users\\Public\\a.bat\" &amp; exit</Arguments>\r\n    </Exec>\r\n    <ComHandler>\r\n      <ClassId>{8F1A5E8D-0000-4000-8000-000000000001}</ClassId>\r\n    </ComHandler>\r\n  </Actions>\r\n</Task>";
+
+    #[test]
+    fn test_task_commands() {
+        assert_eq!(
+            task_commands(TASK_CONTENT),
+            "C:\\Windows\\System32\\cmd.exe /c \"C:\\Users\\Public\\a.bat\" & exit | {8F1A5E8D-0000-4000-8000-000000000001}"
+        );
+        assert_eq!(task_commands(""), "");
+    }
+
+    #[test]
+    fn test_task_summary() {
+        let mut summary = TaskSummary::new();
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 4698,
+                    "Channel": "Security",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "SubjectUserName": "admin",
+                    "SubjectDomainName": "CORP",
+                    "TaskName": "\\Updater",
+                    "TaskContent": TASK_CONTENT,
+                },
+            }
+        }));
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 141,
+                    "Channel": "Microsoft-Windows-TaskScheduler/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T09:00:00Z" },
+                },
+                "EventData": {
+                    "TaskName": "\\Old",
+                    "UserName": "CORP\\admin",
+                },
+            }
+        }));
+        let events = summary.sorted_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, "Deleted");
+        assert_eq!(events[0].task_name, "\\Old");
+        assert_eq!(events[1].action, "Created");
+        assert_eq!(events[1].user, "CORP\\admin");
+        assert!(events[1]
+            .command
+            .starts_with("C:\\Windows\\System32\\cmd.exe /c"));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use chrono::{DateTime, Duration, Utc};
+use hashbrown::HashMap;
+use serde_json::Value;
+
+// 解析時刻より後のタイムスタンプを未来の日時とみなすまでの許容範囲(分)
+const FUTURE_TOLERANCE_MINUTES: i64 = 5;
+// レコード番号の順で、直前のレコードより後退したとみなす時間(分)
+const BACKWARD_JUMP_MINUTES: i64 = 60;
+// 時刻の変更の前後で、他の異常を時刻の変更に関連付ける時間(分)
+const CORRELATION_MINUTES: i64 = 60;
+const KERNEL_GENERAL_PROVIDER: &str = "Microsoft-Windows-Kernel-General";
+
+/// 時刻の異常の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimeAnomalyKind {
+    /// 解析時刻より後のタイムスタンプ
+    FutureTimestamp,
+    /// レコード番号の順で大きく後退したタイムスタンプ
+    BackwardJump,
+    /// システム時刻の変更(Security 4616、System 1)
+    TimeChange,
+}
+
+impl TimeAnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeAnomalyKind::FutureTimestamp => "Future Timestamp",
+            TimeAnomalyKind::BackwardJump => "Backward Jump",
+            TimeAnomalyKind::TimeChange => "System Time Change",
+        }
+    }
+}
+
+/// 時刻の異常1件
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeAnomaly {
+    pub kind: TimeAnomalyKind,
+    pub computer: String,
+    pub channel: String,
+    pub event_id: String,
+    pub record_id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub details: String,
+    /// 未来の日時のレコードは(Computer, Channel)毎にまとめるので、その件数
+    pub count: usize,
+    /// 同じComputerで前後にシステム時刻の変更があるか
+    pub near_time_change: bool,
+}
+
+/**
+* タイムスタンプが未来の日時のレコード、レコード番号の順でタイムスタンプが大きく後退したレコード、
+* システム時刻の変更(Security 4616、System 1)を検出する。
+* 攻撃者はタイムラインを混乱させるために時刻を操作するので、タイムラインの整合性の確認に使う。
+*/
+#[derive(Debug)]
+pub struct TimeIntegrity {
+    now: DateTime<Utc>,
+    // (Computer, Channel)毎の直前のレコード番号とタイムスタンプ
+    last_records: HashMap<(String, String), (u64, DateTime<Utc>)>,
+    // (Computer, Channel)毎の未来の日時のレコード
+    future: HashMap<(String, String), TimeAnomaly>,
+    anomalies: Vec<TimeAnomaly>,
+    // システム時刻を変更したComputerと、変更したイベントのタイムスタンプと変更後の時刻
+    time_changes: Vec<(String, DateTime<Utc>)>,
+}
+
+impl Default for TimeIntegrity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeIntegrity {
+    pub fn new() -> TimeIntegrity {
+        TimeIntegrity {
+            now: Utc::now(),
+            last_records: HashMap::new(),
+            future: HashMap::new(),
+            anomalies: vec![],
+            time_changes: vec![],
+        }
+    }
+
+    pub fn time_integrity_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でtime-integrityオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("time-integrity")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let timestamp = match utils::str_time_to_datetime(&get(&utils::get_event_time())) {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let computer = get("Event.System.Computer");
+        let channel = get("Event.System.Channel");
+        let event_id = get("Event.System.EventID");
+        let record_id: u64 = get("Event.System.EventRecordID").parse().unwrap_or(0);
+        let anomaly = |kind: TimeAnomalyKind, details: String| TimeAnomaly {
+            kind,
+            computer: computer.to_string(),
+            channel: channel.to_string(),
+            event_id: event_id.to_string(),
+            record_id,
+            timestamp,
+            details,
+            count: 1,
+            near_time_change: false,
+        };
+
+        if let Some((details, new_time)) = time_change_details(&channel, &event_id, &get) {
+            self.time_changes.push((computer.to_string(), timestamp));
+            if let Some(new_time) = new_time {
+                self.time_changes.push((computer.to_string(), new_time));
+            }
+            self.anomalies
+                .push(anomaly(TimeAnomalyKind::TimeChange, details));
+        }
+
+        if timestamp > self.now + Duration::minutes(FUTURE_TOLERANCE_MINUTES) {
+            let key = (computer.to_string(), channel.to_string());
+            match self.future.get_mut(&key) {
+                Some(future) => {
+                    future.count += 1;
+                    if timestamp > future.timestamp {
+                        future.timestamp = timestamp;
+                        future.event_id = event_id.to_string();
+                        future.record_id = record_id;
+                    }
+                }
+                None => {
+                    self.future.insert(
+                        key,
+                        anomaly(TimeAnomalyKind::FutureTimestamp, String::default()),
+                    );
+                }
+            }
+        }
+
+        if record_id == 0 {
+            return;
+        }
+        let key = (computer.to_string(), channel.to_string());
+        match self.last_records.get(&key) {
+            Some((last_record_id, last_timestamp)) if record_id > *last_record_id => {
+                if timestamp + Duration::minutes(BACKWARD_JUMP_MINUTES) < *last_timestamp {
+                    let details = format!(
+                        "{} earlier than record {} ({})",
+                        format_duration(*last_timestamp - timestamp),
+                        last_record_id,
+                        last_timestamp.to_rfc3339()
+                    );
+                    self.anomalies
+                        .push(anomaly(TimeAnomalyKind::BackwardJump, details));
+                }
+                self.last_records.insert(key, (record_id, timestamp));
+            }
+            Some(_) => {}
+            None => {
+                self.last_records.insert(key, (record_id, timestamp));
+            }
+        }
+    }
+
+    /// 別のTimeIntegrityの集計結果を追加する
+    pub fn merge(&mut self, other: TimeIntegrity) {
+        self.anomalies.extend(other.anomalies);
+        self.time_changes.extend(other.time_changes);
+        for (key, future) in other.future {
+            match self.future.get_mut(&key) {
+                Some(summary) => {
+                    summary.count += future.count;
+                    if future.timestamp > summary.timestamp {
+                        summary.timestamp = future.timestamp;
+                        summary.event_id = future.event_id;
+                        summary.record_id = future.record_id;
+                    }
+                }
+                None => {
+                    self.future.insert(key, future);
+                }
+            }
+        }
+    }
+
+    /**
+     * 全ての異常をComputer、タイムスタンプの順で返す。
+     * システム時刻を変更したイベントのタイムスタンプか変更後の時刻の前後にある異常にはnear_time_changeを付ける。
+     */
+    pub fn sorted_anomalies(&self) -> Vec<TimeAnomaly> {
+        let future = self.future.values().map(|future| TimeAnomaly {
+            details: format!(
+                "{} records. Latest timestamp is {} after the analysis time.",
+                future.count,
+                format_duration(future.timestamp - self.now)
+            ),
+            ..future.clone()
+        });
+        let mut anomalies: Vec<TimeAnomaly> =
+            self.anomalies.iter().cloned().chain(future).collect();
+        for anomaly in anomalies.iter_mut() {
+            if anomaly.kind == TimeAnomalyKind::TimeChange {
+                continue;
+            }
+            anomaly.near_time_change = self.time_changes.iter().any(|(computer, timestamp)| {
+                *computer == anomaly.computer
+                    && (*timestamp - anomaly.timestamp).num_minutes().abs() <= CORRELATION_MINUTES
+            });
+        }
+        anomalies.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.kind.cmp(&y.kind))
+        });
+        anomalies
+    }
+}
+
+/// システム時刻を変更したイベントの詳細と変更後の時刻。時刻の変更でない場合はNone
+fn time_change_details<F: Fn(&str) -> String>(
+    channel: &str,
+    event_id: &str,
+    get: &F,
+) -> Option<(String, Option<DateTime<Utc>>)> {
+    let (previous, new, changed_by) = match (channel, event_id) {
+        ("Security", "4616") => (
+            get("PreviousTime"),
+            get("NewTime"),
+            format!("{} ({})", get("SubjectUserName"), get("ProcessName")),
+        ),
+        ("System", "1")
+            if get("Event.System.Provider_attributes.Name") == KERNEL_GENERAL_PROVIDER =>
+        {
+            (get("OldTime"), get("NewTime"), get("Reason"))
+        }
+        _ => return None,
+    };
+    let new_time = utils::str_time_to_datetime(&new);
+    let shift = match (utils::str_time_to_datetime(&previous), new_time) {
+        (Some(previous), Some(new)) => {
+            let shift = new - previous;
+            let sign = if shift < Duration::zero() { "-" } else { "+" };
+            format!("{}{}", sign, format_duration(shift))
+        }
+        _ => "unknown".to_string(),
+    };
+    Some((
+        format!(
+            "{} -> {} (Shift: {}) By: {}",
+            previous, new, shift, changed_by
+        ),
+        new_time,
+    ))
+}
+
+/// 時間の長さを読みやすい形式にする。(例: 2d 3h 4m 5s)
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds().abs();
+    let (days, hours, mins, secs) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    let mut parts = vec![];
+    if days > 0 {
+        parts.push(format!("{}d", days));
+    }
+    if hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if mins > 0 {
+        parts.push(format!("{}m", mins));
+    }
+    if secs > 0 || parts.is_empty() {
+        parts.push(format!("{}s", secs));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::time_integrity::{format_duration, TimeAnomalyKind, TimeIntegrity};
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    fn record(channel: &str, eventid: u64, record_id: u64, time: &str, data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "EventID": eventid,
+                    "EventRecordID": record_id,
+                    "Channel": channel,
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": data,
+            }
+        })
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::seconds(0)), "0s");
+        assert_eq!(format_duration(Duration::seconds(-3725)), "1h 2m 5s");
+        assert_eq!(format_duration(Duration::days(3)), "3d");
+    }
+
+    #[test]
+    fn test_time_integrity() {
+        let mut integrity = TimeIntegrity::new();
+        integrity.now = Utc.ymd(2022, 6, 1).and_hms(0, 0, 0);
+        integrity.add(&record(
+            "Security",
+            4624,
+            10,
+            "2022-05-20T12:00:00Z",
+            json!({}),
+        ));
+        // 時刻を2日戻してからログオンする
+        integrity.add(&record(
+            "Security",
+            4616,
+            11,
+            "2022-05-20T12:01:00Z",
+            json!({"PreviousTime": "2022-05-20T12:01:00Z", "NewTime": "2022-05-18T12:01:00Z", "SubjectUserName": "attacker", "ProcessName": "C:\\Windows\\System32\\cmd.exe"}),
+        ));
+        integrity.add(&record(
+            "Security",
+            4624,
+            12,
+            "2022-05-18T12:02:00Z",
+            json!({}),
+        ));
+        integrity.add(&record(
+            "Security",
+            4624,
+            13,
+            "2030-01-01T00:00:00Z",
+            json!({}),
+        ));
+        integrity.add(&record(
+            "Security",
+            4624,
+            14,
+            "2029-12-31T23:50:00Z",
+            json!({}),
+        ));
+
+        let anomalies = integrity.sorted_anomalies();
+        let kinds: Vec<TimeAnomalyKind> = anomalies.iter().map(|anomaly| anomaly.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TimeAnomalyKind::BackwardJump,
+                TimeAnomalyKind::TimeChange,
+                TimeAnomalyKind::FutureTimestamp
+            ]
+        );
+        assert_eq!(anomalies[0].record_id, 12);
+        assert!(anomalies[0].near_time_change);
+        assert_eq!(
+            anomalies[1].details,
+            "2022-05-20T12:01:00Z -> 2022-05-18T12:01:00Z (Shift: -2d) By: attacker (C:\\Windows\\System32\\cmd.exe)"
+        );
+        assert_eq!(anomalies[2].count, 2);
+        assert_eq!(anomalies[2].record_id, 13);
+        assert!(!anomalies[2].near_time_change);
+    }
+}
@@ -1,12 +1,230 @@
+use crate::afterfact::format_time;
+use crate::detections::print::AlertMessage;
 use crate::detections::{configs, detection::EvtxRecordInfo};
+use crate::output::anonymize;
+use crate::wef;
 use prettytable::{Cell, Row, Table};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::BufWriter;
 
-use super::statistics::EventStatistics;
+use super::accounts::AccountSummary;
+use super::activity::{ActivityMatrix, MAX_FILLED_HOURS};
+use super::adcs::{AdcsAnalytics, CertificateActivity};
+use super::bits::BitsSummary;
+use super::coverage::EventCoverage;
+use super::defender::DefenderSummary;
+use super::dns::DnsSummary;
+use super::firewall::FirewallSummary;
+use super::ioc::{IocMatch, IocMatcher};
+use super::kerberos::{KerberosAnalytics, KerberosFinding};
+use super::lateral::{self, LateralEdge, LateralMovementSummary};
+use super::metrics::LogMetrics;
+use super::network::NetworkSummary;
+use super::registry::RegistryPersistenceSummary;
+use super::services::{ServiceEventKind, ServiceInstallSummary};
+use super::spray::FailedLogonAnalytics;
+use super::statistics::{ChannelProviders, ComputerStatistics, EventStatistics};
+use super::tasks::TaskSummary;
+use super::time_integrity::{TimeAnomaly, TimeAnomalyKind, TimeIntegrity};
+use super::user_timeline::{UserEvent, UserEventCategory, UserTimeline};
+use super::wmi::WmiSummary;
 use hashbrown::HashMap;
 
+// ログオンサマリーに表示する送信元とアカウント毎の認証の失敗の行数
+const FAILED_LOGON_MATRIX_ROWS: usize = 20;
+
+/// 検知結果とは別にレコードを集計して表示するサマリー
+pub struct Summary {
+    /// サマリーを出力するオプション
+    pub options: &'static [&'static str],
+    /// 集計結果を表示し、CSVファイルなどに出力する関数。オプションが指定されていない場合は何もしない
+    pub display: fn(&Timeline),
+}
+
+/// 検知結果より前に表示するサマリー。これらのオプションを指定した場合は検知結果を出力しない
+pub const PRE_DETECTION_SUMMARIES: &[Summary] = &[
+    Summary {
+        options: &["statistics"],
+        display: Timeline::tm_stats_dsp_msg,
+    },
+    Summary {
+        options: &["log-metrics"],
+        display: Timeline::tm_metrics_dsp_msg,
+    },
+    Summary {
+        options: &["provider-metrics"],
+        display: Timeline::tm_provider_dsp_msg,
+    },
+];
+
+/// 検知結果の後に表示するサマリー
+pub const SUMMARIES: &[Summary] = &[
+    Summary {
+        options: &["coverage-gaps"],
+        display: Timeline::tm_coverage_dsp_msg,
+    },
+    Summary {
+        options: &["activity-matrix"],
+        display: Timeline::tm_activity_dsp_msg,
+    },
+    Summary {
+        options: &["network-summary"],
+        display: Timeline::tm_network_dsp_msg,
+    },
+    Summary {
+        options: &["dns-summary"],
+        display: Timeline::tm_dns_dsp_msg,
+    },
+    Summary {
+        options: &["service-summary"],
+        display: Timeline::tm_service_dsp_msg,
+    },
+    Summary {
+        options: &["task-summary"],
+        display: Timeline::tm_task_dsp_msg,
+    },
+    Summary {
+        options: &["account-summary"],
+        display: Timeline::tm_account_dsp_msg,
+    },
+    Summary {
+        options: &["kerberos-analytics"],
+        display: Timeline::tm_kerberos_dsp_msg,
+    },
+    Summary {
+        options: &["lateral-movement", "lateral-movement-dot"],
+        display: Timeline::tm_lateral_dsp_msg,
+    },
+    Summary {
+        options: &["registry-persistence"],
+        display: Timeline::tm_registry_dsp_msg,
+    },
+    Summary {
+        options: &["wmi-summary"],
+        display: Timeline::tm_wmi_dsp_msg,
+    },
+    Summary {
+        options: &["bits-summary"],
+        display: Timeline::tm_bits_dsp_msg,
+    },
+    Summary {
+        options: &["defender-summary"],
+        display: Timeline::tm_defender_dsp_msg,
+    },
+    Summary {
+        options: &["firewall-summary"],
+        display: Timeline::tm_firewall_dsp_msg,
+    },
+    Summary {
+        options: &["adcs-analytics"],
+        display: Timeline::tm_adcs_dsp_msg,
+    },
+    Summary {
+        options: &["ioc-file"],
+        display: Timeline::tm_ioc_dsp_msg,
+    },
+    Summary {
+        options: &["time-integrity"],
+        display: Timeline::tm_time_integrity_dsp_msg,
+    },
+    Summary {
+        options: &["user-timeline"],
+        display: Timeline::tm_user_timeline_dsp_msg,
+    },
+];
+
+/// ファイル毎に表示するサマリーのオプション
+const PER_FILE_SUMMARY_OPTIONS: &[&str] = &["logon-summary"];
+
+/// サマリーを出力するオプションの一覧。これらのオプションが指定された場合は全てのレコードを集計する必要がある
+pub fn summary_options() -> impl Iterator<Item = &'static str> {
+    PRE_DETECTION_SUMMARIES
+        .iter()
+        .chain(SUMMARIES.iter())
+        .flat_map(|summary| summary.options.iter().copied())
+        .chain(PER_FILE_SUMMARY_OPTIONS.iter().copied())
+}
+
+/// サマリーの集計結果を表形式で標準出力に表示する。--anonymizeが指定されている場合は仮名に置き換える
+fn print_summary_table(titles: &[&str], rows: &[Vec<String>]) {
+    let mut tb = Table::new();
+    tb.set_titles(Row::new(
+        titles.iter().map(|title| Cell::new(title)).collect(),
+    ));
+    for row in rows.iter() {
+        tb.add_row(Row::new(
+            row.iter()
+                .map(|cell| Cell::new(&anonymize::anonymize_output(cell)))
+                .collect(),
+        ));
+    }
+    tb.printstd();
+}
+
+/// サマリーのCSVファイルに出力する内容
+struct SummaryCsv {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl SummaryCsv {
+    fn new(header: &[&str], rows: Vec<Vec<String>>) -> SummaryCsv {
+        SummaryCsv {
+            header: header.iter().map(|column| column.to_string()).collect(),
+            rows,
+        }
+    }
+
+    /// CSVファイルを書き出して結果を表示する。nameはメッセージに表示するサマリーの名前
+    fn save(&self, name: &str, csv_path: &str) {
+        match self.write(csv_path) {
+            Ok(_) => println!("Saved {} to {}\n", name, csv_path),
+            Err(err) => {
+                AlertMessage::alert(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!("Failed to write {} csv. {}", name, err),
+                )
+                .ok();
+            }
+        }
+    }
+
+    fn write(&self, csv_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_path(csv_path)?;
+        wtr.write_record(&self.header)?;
+        for row in self.rows.iter() {
+            wtr.write_record(row.iter().map(|cell| anonymize::anonymize_output(cell)))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Timeline {
     pub stats: EventStatistics,
+    pub metrics: LogMetrics,
+    pub coverage: EventCoverage,
+    pub activity: ActivityMatrix,
+    pub network: NetworkSummary,
+    pub dns: DnsSummary,
+    pub services: ServiceInstallSummary,
+    pub tasks: TaskSummary,
+    pub accounts: AccountSummary,
+    pub kerberos: KerberosAnalytics,
+    pub spray: FailedLogonAnalytics,
+    pub lateral: LateralMovementSummary,
+    pub registry: RegistryPersistenceSummary,
+    pub wmi: WmiSummary,
+    pub bits: BitsSummary,
+    pub defender: DefenderSummary,
+    pub firewall: FirewallSummary,
+    pub adcs: AdcsAnalytics,
+    pub ioc: IocMatcher,
+    pub time_integrity: TimeIntegrity,
+    pub user_timeline: UserTimeline,
 }
 
 impl Default for Timeline {
@@ -26,15 +244,97 @@ impl Timeline {
 
         let statistic =
             EventStatistics::new(totalcnt, filepath, starttm, endtm, statslst, statsloginlst);
-        Timeline { stats: statistic }
+        Timeline {
+            stats: statistic,
+            metrics: LogMetrics::new(),
+            coverage: EventCoverage::new(),
+            activity: ActivityMatrix::new(),
+            network: NetworkSummary::new(),
+            dns: DnsSummary::new(),
+            services: ServiceInstallSummary::new(),
+            tasks: TaskSummary::new(),
+            accounts: AccountSummary::new(),
+            kerberos: KerberosAnalytics::new(),
+            spray: FailedLogonAnalytics::new(),
+            lateral: LateralMovementSummary::new(),
+            registry: RegistryPersistenceSummary::new(),
+            wmi: WmiSummary::new(),
+            bits: BitsSummary::new(),
+            defender: DefenderSummary::new(),
+            firewall: FirewallSummary::new(),
+            adcs: AdcsAnalytics::new(),
+            ioc: IocMatcher::new(),
+            time_integrity: TimeIntegrity::new(),
+            user_timeline: UserTimeline::new(),
+        }
     }
 
     pub fn start(&mut self, records: &[EvtxRecordInfo]) {
         self.stats.evt_stats_start(records);
         self.stats.logon_stats_start(records);
+        self.stats.provider_stats_start(records);
+        self.metrics.metrics_start(records);
+        self.coverage.coverage_start(records);
+        self.activity.activity_start(records);
+        self.network.network_start(records);
+        self.dns.dns_start(records);
+        self.services.service_start(records);
+        self.tasks.task_start(records);
+        self.accounts.account_start(records);
+        self.kerberos.kerberos_start(records);
+        self.spray.spray_start(records);
+        self.lateral.lateral_start(records);
+        self.registry.registry_start(records);
+        self.wmi.wmi_start(records);
+        self.bits.bits_start(records);
+        self.defender.defender_start(records);
+        self.firewall.firewall_start(records);
+        self.adcs.adcs_start(records);
+        self.ioc.ioc_start(records);
+        self.time_integrity.time_integrity_start(records);
+        self.user_timeline.user_timeline_start(records);
+    }
+
+    /// 別のファイルを解析したTimelineの集計結果を追加する
+    pub fn merge(&mut self, other: Timeline) {
+        self.stats.merge(other.stats);
+        self.metrics.merge(other.metrics);
+        self.coverage.merge(other.coverage);
+        self.activity.merge(other.activity);
+        self.network.merge(other.network);
+        self.dns.merge(other.dns);
+        self.services.merge(other.services);
+        self.tasks.merge(other.tasks);
+        self.accounts.merge(other.accounts);
+        self.kerberos.merge(other.kerberos);
+        self.spray.merge(other.spray);
+        self.lateral.merge(other.lateral);
+        self.registry.merge(other.registry);
+        self.wmi.merge(other.wmi);
+        self.bits.merge(other.bits);
+        self.defender.merge(other.defender);
+        self.firewall.merge(other.firewall);
+        self.adcs.merge(other.adcs);
+        self.ioc.merge(other.ioc);
+        self.time_integrity.merge(other.time_integrity);
+        self.user_timeline.merge(other.user_timeline);
+    }
+
+    /// 検知結果より前に表示するサマリーを表示する
+    pub fn tm_pre_detection_summaries_dsp_msg(&self) {
+        for summary in PRE_DETECTION_SUMMARIES.iter() {
+            (summary.display)(self);
+        }
+    }
+
+    /// 検知結果の後に表示するサマリーを表示する
+    pub fn tm_summaries_dsp_msg(&self) {
+        for summary in SUMMARIES.iter() {
+            (summary.display)(self);
+        }
     }
 
-    pub fn tm_stats_dsp_msg(&mut self) {
+    pub fn tm_stats_dsp_msg(&self) {
         if !configs::CONFIG
             .read()
             .unwrap()
@@ -44,31 +344,1648 @@ impl Timeline {
             return;
         }
         // 出力メッセージ作成
-        //println!("map -> {:#?}", evtstat_map);
         let mut sammsges: Vec<String> = Vec::new();
         sammsges.push("---------------------------------------".to_string());
-        sammsges.push(format!("Evtx File Path: {}", self.stats.filepath));
         sammsges.push(format!("Total Event Records: {}\n", self.stats.total));
         sammsges.push(format!("First Timestamp: {}", self.stats.start_time));
         sammsges.push(format!("Last Timestamp: {}\n", self.stats.end_time));
-        sammsges.push("Count (Percent)\tID\tEvent\t".to_string());
-        sammsges.push("--------------- ------- ---------------".to_string());
 
-        // 集計件数でソート
-        let mut mapsorted: Vec<_> = self.stats.stats_list.iter().collect();
-        mapsorted.sort_by(|x, y| y.1.cmp(x.1));
+        // Computer毎、Channel毎に集計件数でソート
+        let grouped = self.stats.group_by_computer_and_channel();
 
         // イベントID毎の出力メッセージ生成
-        let stats_msges: Vec<String> = self.tm_stats_set_msg(mapsorted);
+        let stats_msges: Vec<String> = self.tm_stats_set_msg(&grouped);
 
         for msgprint in sammsges.iter() {
             println!("{}", msgprint);
         }
         for msgprint in stats_msges.iter() {
-            println!("{}", msgprint);
+            println!("{}", anonymize::anonymize_output(msgprint));
+        }
+
+        // outputオプションが指定されている場合はCSVファイルにも出力する
+        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
+            self.tm_stats_csv(&grouped).save("statistics", csv_path);
+        }
+    }
+
+    pub fn tm_provider_dsp_msg(&self) {
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("provider-metrics")
+        {
+            return;
+        }
+        println!("---------------------------------------");
+        println!("Total Event Records: {}\n", self.stats.total);
+        println!("First Timestamp: {}", self.stats.start_time);
+        println!("Last Timestamp: {}\n", self.stats.end_time);
+
+        // Channel毎、Provider名毎に集計件数でソート
+        let grouped = self.stats.group_by_channel_and_provider();
+        for channel in grouped.iter() {
+            println!(
+                "Channel: {} ({} / {:.1}%)",
+                channel.channel,
+                channel.total,
+                self.tm_stats_rate(channel.total),
+            );
+            let mut rows = vec![];
+            for provider in channel.providers.iter() {
+                rows.push(vec![
+                    provider.provider.to_string(),
+                    format!(
+                        "{} ({:.1}%)",
+                        provider.total,
+                        Timeline::tm_provider_rate(provider.total, channel.total)
+                    ),
+                    provider.eventids.join(", "),
+                ]);
+            }
+            print_summary_table(&["Provider", "Count (Percent)", "Event IDs"], &rows);
+            println!();
+        }
+
+        // outputオプションが指定されている場合はCSVファイルにも出力する
+        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
+            Timeline::tm_provider_csv(&grouped).save("provider metrics", csv_path);
+        }
+    }
+
+    // Channelの件数に対するProviderの件数の割合
+    fn tm_provider_rate(count: usize, channel_total: usize) -> f64 {
+        if channel_total == 0 {
+            0.0
+        } else {
+            (count as f64) / (channel_total as f64) * 100.0
+        }
+    }
+
+    // Channel毎、Provider名毎の集計結果をCSVファイルに出力する内容を作成する
+    fn tm_provider_csv(grouped: &[ChannelProviders]) -> SummaryCsv {
+        let mut rows = vec![];
+        for channel in grouped.iter() {
+            for provider in channel.providers.iter() {
+                rows.push(vec![
+                    channel.channel.to_string(),
+                    provider.provider.to_string(),
+                    provider.total.to_string(),
+                    format!(
+                        "{:.1}",
+                        Timeline::tm_provider_rate(provider.total, channel.total)
+                    ),
+                    provider.eventids.join(" | "),
+                ]);
+            }
+        }
+        SummaryCsv::new(
+            &["Channel", "Provider", "Count", "Percent", "EventIDs"],
+            rows,
+        )
+    }
+
+    pub fn tm_metrics_dsp_msg(&self) {
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("log-metrics")
+        {
+            return;
+        }
+        println!("Log Metrics");
+        let mut rows = vec![];
+        for file in self.metrics.files.iter() {
+            // WEFで転送されたイベントのファイルは、転送元のコンピュータの数も表示する
+            let filepath = if wef::is_forwarded_events_file(&file.filepath) {
+                format!(
+                    "{}\n(Forwarded from {} computers)",
+                    file.filepath,
+                    file.computers.len()
+                )
+            } else {
+                file.filepath.to_string()
+            };
+            rows.push(vec![
+                filepath.to_string(),
+                Timeline::tm_format_filesize(file.filesize).to_string(),
+                file.total.to_string(),
+                file.start_time.to_string(),
+                file.end_time.to_string(),
+                file.channels.iter().cloned().collect::<Vec<_>>().join("\n"),
+                file.computers
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ]);
+        }
+        print_summary_table(
+            &[
+                "File",
+                "Size",
+                "Records",
+                "First Timestamp",
+                "Last Timestamp",
+                "Channels",
+                "Computers",
+            ],
+            &rows,
+        );
+        println!();
+
+        // outputオプションが指定されている場合はCSVファイルにも出力する
+        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("output") {
+            self.tm_metrics_csv().save("log metrics", csv_path);
+        }
+    }
+
+    // ファイルサイズを読みやすい単位に変換する
+    fn tm_format_filesize(filesize: u64) -> String {
+        let units = ["B", "KB", "MB", "GB", "TB"];
+        let mut size = filesize as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < units.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{} {}", filesize, units[unit])
+        } else {
+            format!("{:.1} {}", size, units[unit])
         }
     }
 
+    // evtxファイル毎のメトリクスをCSVファイルに出力する内容を作成する
+    fn tm_metrics_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for file in self.metrics.files.iter() {
+            rows.push(vec![
+                file.filepath.to_string(),
+                file.filesize.to_string(),
+                file.total.to_string(),
+                file.start_time.to_string(),
+                file.end_time.to_string(),
+                file.channels
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+                file.computers
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "File",
+                "FileSize",
+                "Records",
+                "FirstTimestamp",
+                "LastTimestamp",
+                "Channels",
+                "Computers",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_coverage_dsp_msg(&self) {
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("coverage-gaps")
+        {
+            return;
+        }
+        let requirements = &self.coverage.requirements;
+        let gaps = self.coverage.find_gaps(requirements);
+        let gap_rule_count: usize = gaps.iter().map(|gap| gap.rule_count).sum();
+        println!("Coverage Gaps");
+        println!(
+            "{} of {} rules could not possibly fire because the events they need were not found in the analyzed logs.",
+            gap_rule_count,
+            requirements.len()
+        );
+        if gaps.is_empty() {
+            println!();
+            return;
+        }
+        for gap in gaps.iter().filter(|gap| gap.eventids.is_empty()) {
+            println!(
+                "No {} events found - {} rules could not possibly fire.",
+                gap.channel, gap.rule_count
+            );
+        }
+        let mut rows = vec![];
+        for gap in gaps.iter() {
+            let eventids = if gap.eventids.is_empty() {
+                "(no events)".to_string()
+            } else {
+                gap.eventids.join(", ")
+            };
+            rows.push(vec![
+                gap.channel.to_string(),
+                eventids.to_string(),
+                gap.rule_count.to_string(),
+            ]);
+        }
+        print_summary_table(&["Channel", "Missing EventIDs", "Rules"], &rows);
+        println!();
+    }
+
+    pub fn tm_activity_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("activity-matrix")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        println!("Activity Matrix");
+        if self.activity.is_empty() {
+            println!("No events with a valid timestamp were found.");
+        } else {
+            let mut rows = vec![];
+            for summary in self.activity.summaries().iter() {
+                rows.push(vec![
+                    summary.computer.to_string(),
+                    summary.total.to_string(),
+                    format_time(&summary.first_hour),
+                    format_time(&summary.last_hour),
+                    summary.empty_hours.to_string(),
+                    summary.longest_gap.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Computer",
+                    "Events",
+                    "First Hour",
+                    "Last Hour",
+                    "Empty Hours",
+                    "Longest Gap (Hours)",
+                ],
+                &rows,
+            );
+            if !self.activity.fills_empty_hours() {
+                AlertMessage::warn(
+                    &mut BufWriter::new(std::io::stderr().lock()),
+                    &format!(
+                        "The events span more than {} hours, possibly because of invalid timestamps. Only the hours with events are saved to the activity matrix.",
+                        MAX_FILLED_HOURS
+                    ),
+                )
+                .ok();
+            }
+        }
+        println!();
+
+        self.tm_activity_csv().save("activity matrix", &csv_path);
+    }
+
+    // 行が1時間毎の時間帯、列がComputerのイベント数の表をCSVファイルに出力する内容を作成する
+    fn tm_activity_csv(&self) -> SummaryCsv {
+        let computers = self.activity.computers();
+        let mut header = vec!["Hour".to_string()];
+        header.extend(computers.iter().cloned());
+        header.push("Total".to_string());
+        let mut rows = vec![];
+        for hour in self.activity.hours() {
+            let counts: Vec<usize> = computers
+                .iter()
+                .map(|computer| self.activity.count(computer, &hour))
+                .collect();
+            let mut row = vec![format_time(&hour)];
+            row.extend(counts.iter().map(|count| count.to_string()));
+            row.push(counts.iter().sum::<usize>().to_string());
+            rows.push(row);
+        }
+        SummaryCsv { header, rows }
+    }
+
+    pub fn tm_network_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("network-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        println!("Network Connection Summary (Top Talkers)");
+        let talkers = self.network.top_talkers(10);
+        if talkers.is_empty() {
+            println!("No outbound Sysmon network connection events (EventID 3) were found.");
+        } else {
+            let mut rows = vec![];
+            for talker in talkers.iter() {
+                rows.push(vec![
+                    talker.computer.to_string(),
+                    talker.image.to_string(),
+                    talker.connections.to_string(),
+                    talker.destinations.to_string(),
+                ]);
+            }
+            print_summary_table(&["Computer", "Image", "Connections", "Destinations"], &rows);
+        }
+        println!();
+
+        self.tm_network_csv()
+            .save("network connection summary", &csv_path);
+    }
+
+    // ホスト、プロセス、送信先毎の通信の集計結果をCSVファイルに出力する内容を作成する
+    fn tm_network_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for conn in self.network.sorted_connections() {
+            rows.push(vec![
+                conn.computer.to_string(),
+                conn.image.to_string(),
+                conn.destination_ip.to_string(),
+                conn.destination_port.to_string(),
+                conn.destination_hostname.to_string(),
+                conn.protocol.to_string(),
+                conn.count.to_string(),
+                conn.first_seen.to_string(),
+                conn.last_seen.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "Image",
+                "DestinationIp",
+                "DestinationPort",
+                "DestinationHostname",
+                "Protocol",
+                "Count",
+                "FirstSeen",
+                "LastSeen",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_dns_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG.read().unwrap().args.value_of("dns-summary") {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let queries = self.dns.sorted_queries();
+        println!("DNS Query Summary (Rare Domains)");
+        let rare: Vec<_> = queries.iter().filter(|(_, rare)| *rare).collect();
+        if queries.is_empty() {
+            println!(
+                "No DNS query events (Sysmon EventID 22 or DNS-Client EventID 3006) were found."
+            );
+        } else if rare.is_empty() {
+            println!("No rare domains were found.");
+        } else {
+            let mut rows = vec![];
+            for (query, _) in rare.iter() {
+                rows.push(vec![
+                    query.computer.to_string(),
+                    query.query_name.to_string(),
+                    query.count.to_string(),
+                    query.images.iter().cloned().collect::<Vec<_>>().join("\n"),
+                ]);
+            }
+            print_summary_table(&["Computer", "Domain", "Count", "Images"], &rows);
+        }
+        println!();
+
+        self.tm_dns_csv().save("DNS query summary", &csv_path);
+    }
+
+    // ホストとドメイン毎の問い合わせの集計結果をCSVファイルに出力する内容を作成する
+    fn tm_dns_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for (query, rare) in self.dns.sorted_queries() {
+            rows.push(vec![
+                query.computer.to_string(),
+                query.query_name.to_string(),
+                query.count.to_string(),
+                query.images.iter().cloned().collect::<Vec<_>>().join(" | "),
+                query.first_seen.to_string(),
+                query.last_seen.to_string(),
+                rare.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "QueryName",
+                "Count",
+                "Images",
+                "FirstSeen",
+                "LastSeen",
+                "Rare",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_service_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("service-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let services = self.services.sorted_services();
+        println!("Service Installation and Driver Load Summary");
+        println!(
+            "{} services installed and {} drivers loaded.",
+            services
+                .iter()
+                .filter(|s| s.kind == ServiceEventKind::Service)
+                .count(),
+            services
+                .iter()
+                .filter(|s| s.kind == ServiceEventKind::Driver)
+                .count()
+        );
+        // 一般ユーザーが書き込めるパスのサービスと署名のないドライバを表示する
+        let suspicious: Vec<_> = services
+            .iter()
+            .filter(|s| s.user_writable || s.signed.starts_with("false"))
+            .collect();
+        if !suspicious.is_empty() {
+            let mut rows = vec![];
+            for service in suspicious.iter() {
+                rows.push(vec![
+                    service.computer.to_string(),
+                    service.kind.as_str().to_string(),
+                    service.name.to_string(),
+                    service.image_path.to_string(),
+                    service.signed.to_string(),
+                ]);
+            }
+            print_summary_table(&["Computer", "Type", "Name", "Image Path", "Signed"], &rows);
+        }
+        println!();
+
+        self.tm_service_csv()
+            .save("service installation summary", &csv_path);
+    }
+
+    // ホスト毎のサービスのインストールとドライバのロードの集計結果をCSVファイルに出力する内容を作成する
+    fn tm_service_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for service in self.services.sorted_services() {
+            rows.push(vec![
+                service.computer.to_string(),
+                service.kind.as_str().to_string(),
+                service.name.to_string(),
+                service.image_path.to_string(),
+                service.account.to_string(),
+                service.start_type.to_string(),
+                service.signed.to_string(),
+                service.count.to_string(),
+                service.first_seen.to_string(),
+                service.last_seen.to_string(),
+                service.user_writable.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "Type",
+                "Name",
+                "ImagePath",
+                "Account",
+                "StartType",
+                "Signed",
+                "Count",
+                "FirstSeen",
+                "LastSeen",
+                "UserWritablePath",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_task_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("task-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let events = self.tasks.sorted_events();
+        println!("Scheduled Task Summary");
+        if events.is_empty() {
+            println!("No scheduled task creation, update or deletion events were found.");
+        } else {
+            let mut rows = vec![];
+            for event in events.iter() {
+                rows.push(vec![
+                    event.computer.to_string(),
+                    event.timestamp.to_string(),
+                    event.action.to_string(),
+                    event.task_name.to_string(),
+                    event.user.to_string(),
+                    event.command.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Computer",
+                    "Timestamp",
+                    "Action",
+                    "Task Name",
+                    "User",
+                    "Command",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        self.tm_task_csv().save("scheduled task summary", &csv_path);
+    }
+
+    // ホスト毎のタスクの登録、更新、削除をCSVファイルに出力する内容を作成する
+    fn tm_task_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for event in self.tasks.sorted_events() {
+            rows.push(vec![
+                event.computer.to_string(),
+                event.timestamp.to_string(),
+                event.action.to_string(),
+                event.task_name.to_string(),
+                event.user.to_string(),
+                event.command.to_string(),
+                event.channel.to_string(),
+                event.eventid.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "Timestamp",
+                "Action",
+                "TaskName",
+                "User",
+                "Command",
+                "Channel",
+                "EventID",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_account_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("account-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        println!("Account Management Summary");
+        let counts = self.accounts.counts();
+        if counts.is_empty() {
+            println!("No account management events were found.");
+        } else {
+            let mut rows = vec![];
+            for ((domain, action), count) in counts.iter() {
+                rows.push(vec![
+                    domain.to_string(),
+                    action.to_string(),
+                    count.to_string(),
+                ]);
+            }
+            print_summary_table(&["Domain", "Action", "Count"], &rows);
+        }
+        // 特権を持つグループへの追加を表示する
+        let privileged: Vec<_> = self
+            .accounts
+            .sorted_events()
+            .into_iter()
+            .filter(|event| event.privileged)
+            .collect();
+        if !privileged.is_empty() {
+            println!("Privileged Group Additions");
+            let mut rows = vec![];
+            for event in privileged.iter() {
+                rows.push(vec![
+                    event.timestamp.to_string(),
+                    event.computer.to_string(),
+                    event.group.to_string(),
+                    event.target.to_string(),
+                    event.actor.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &["Timestamp", "Computer", "Group", "Member", "Actor"],
+                &rows,
+            );
+        }
+        println!();
+
+        self.tm_account_csv()
+            .save("account management summary", &csv_path);
+    }
+
+    // ドメイン毎のアカウントの管理操作をCSVファイルに出力する内容を作成する
+    fn tm_account_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for event in self.accounts.sorted_events() {
+            rows.push(vec![
+                event.domain.to_string(),
+                event.timestamp.to_string(),
+                event.computer.to_string(),
+                event.eventid.to_string(),
+                event.action.to_string(),
+                event.actor.to_string(),
+                event.target.to_string(),
+                event.group.to_string(),
+                event.privileged.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Domain",
+                "Timestamp",
+                "Computer",
+                "EventID",
+                "Action",
+                "Actor",
+                "Target",
+                "Group",
+                "PrivilegedGroup",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_kerberos_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("kerberos-analytics")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let findings = self.kerberos.findings();
+        println!("Kerberos Analytics");
+        if findings.is_empty() {
+            println!("No Kerberoasting, AS-REP roasting or excessive pre-authentication failures were found.");
+        } else {
+            let mut rows = vec![];
+            for finding in findings.iter() {
+                rows.push(vec![
+                    finding.analytic.to_string(),
+                    finding.account.to_string(),
+                    finding.sources.join("\n"),
+                    finding.count.to_string(),
+                    format_time(&finding.first_seen),
+                    format_time(&finding.last_seen),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Analytic",
+                    "Account",
+                    "Sources",
+                    "Count",
+                    "First Timestamp",
+                    "Last Timestamp",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        Timeline::tm_kerberos_csv(&findings).save("Kerberos analytics", &csv_path);
+    }
+
+    // Kerberosの検知結果をCSVファイルに出力する内容を作成する
+    fn tm_kerberos_csv(findings: &[KerberosFinding]) -> SummaryCsv {
+        let mut rows = vec![];
+        for finding in findings.iter() {
+            rows.push(vec![
+                finding.analytic.to_string(),
+                finding.account.to_string(),
+                finding.computers.join(" | "),
+                finding.sources.join(" | "),
+                finding.count.to_string(),
+                finding.services.join(" | "),
+                format_time(&finding.first_seen),
+                format_time(&finding.last_seen),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Analytic",
+                "Account",
+                "Computers",
+                "Sources",
+                "Count",
+                "Services",
+                "FirstTimestamp",
+                "LastTimestamp",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_lateral_dsp_msg(&self) {
+        let (csv_path, dot_path) = {
+            let config = configs::CONFIG.read().unwrap();
+            (
+                config.args.value_of("lateral-movement").map(String::from),
+                config
+                    .args
+                    .value_of("lateral-movement-dot")
+                    .map(String::from),
+            )
+        };
+        if csv_path.is_none() && dot_path.is_none() {
+            return;
+        }
+        let edges = self.lateral.edges();
+        println!("Lateral Movement Overview");
+        if edges.is_empty() {
+            println!("No remote logons, explicit credential use, share access or remote service creations were found.");
+        } else {
+            let mut rows = vec![];
+            for edge in edges.iter() {
+                let activities: Vec<&str> = edge.activities.iter().copied().collect();
+                let users: Vec<&str> = edge.users.iter().map(|user| user.as_str()).collect();
+                rows.push(vec![
+                    edge.source.to_string(),
+                    edge.destination.to_string(),
+                    activities.join("\n"),
+                    users.join("\n"),
+                    edge.count.to_string(),
+                    format_time(&edge.first_seen),
+                    format_time(&edge.last_seen),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Source",
+                    "Destination",
+                    "Activities",
+                    "Users",
+                    "Count",
+                    "First Timestamp",
+                    "Last Timestamp",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        if let Some(csv_path) = csv_path {
+            Timeline::tm_lateral_csv(&edges).save("lateral movement overview", &csv_path);
+        }
+        if let Some(dot_path) = dot_path {
+            match fs::write(
+                &dot_path,
+                anonymize::anonymize_output(&lateral::to_dot(&edges)),
+            ) {
+                Ok(_) => println!("Saved lateral movement graph to {}\n", dot_path),
+                Err(err) => {
+                    AlertMessage::alert(
+                        &mut BufWriter::new(std::io::stderr().lock()),
+                        &format!("Failed to write lateral movement dot file. {}", err),
+                    )
+                    .ok();
+                }
+            }
+        }
+    }
+
+    // ホスト間の横展開の集計結果をCSVファイルに出力する内容を作成する
+    fn tm_lateral_csv(edges: &[LateralEdge]) -> SummaryCsv {
+        let mut rows = vec![];
+        for edge in edges.iter() {
+            let activities: Vec<&str> = edge.activities.iter().copied().collect();
+            let users: Vec<&str> = edge.users.iter().map(|user| user.as_str()).collect();
+            let details: Vec<&str> = edge.details.iter().map(|detail| detail.as_str()).collect();
+            rows.push(vec![
+                edge.source.to_string(),
+                edge.destination.to_string(),
+                activities.join(" | "),
+                users.join(" | "),
+                details.join(" | "),
+                edge.count.to_string(),
+                format_time(&edge.first_seen),
+                format_time(&edge.last_seen),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Source",
+                "Destination",
+                "Activities",
+                "Users",
+                "Details",
+                "Count",
+                "FirstTimestamp",
+                "LastTimestamp",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_registry_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("registry-persistence")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let writes = self.registry.sorted_writes();
+        println!("Registry Persistence Summary");
+        if writes.is_empty() {
+            println!("No Sysmon registry events (EventID 12/13/14) were found for the persistence registry paths.");
+        } else {
+            // ホストと種類毎の書き込みの件数を表示する
+            let mut counts: BTreeMap<(&str, &str), (usize, usize)> = BTreeMap::new();
+            for write in writes.iter() {
+                let count = counts
+                    .entry((write.computer.as_str(), write.category.as_str()))
+                    .or_insert((0, 0));
+                count.0 += 1;
+                count.1 += write.count;
+            }
+            let mut rows = vec![];
+            for ((computer, category), (keys, events)) in counts.iter() {
+                rows.push(vec![
+                    computer.to_string(),
+                    category.to_string(),
+                    keys.to_string(),
+                    events.to_string(),
+                ]);
+            }
+            print_summary_table(&["Computer", "Category", "Keys", "Events"], &rows);
+        }
+        println!();
+
+        self.tm_registry_csv()
+            .save("registry persistence summary", &csv_path);
+    }
+
+    // ホスト毎の自動起動に使われるレジストリへの書き込みの集計結果をCSVファイルに出力する内容を作成する
+    fn tm_registry_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for write in self.registry.sorted_writes() {
+            rows.push(vec![
+                write.computer.to_string(),
+                write.category.as_str().to_string(),
+                write.event_type.to_string(),
+                write.target_object.to_string(),
+                write.details.to_string(),
+                write.image.to_string(),
+                write.count.to_string(),
+                write.first_seen.to_string(),
+                write.last_seen.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "Category",
+                "EventType",
+                "TargetObject",
+                "Details",
+                "Image",
+                "Count",
+                "FirstSeen",
+                "LastSeen",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_wmi_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG.read().unwrap().args.value_of("wmi-summary") {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let events = self.wmi.sorted_events();
+        println!("WMI Persistence and Activity Summary");
+        if events.is_empty() {
+            println!("No WMI event filter, consumer or binding events were found.");
+        } else {
+            let mut rows = vec![];
+            for event in events.iter() {
+                rows.push(vec![
+                    event.timestamp.to_string(),
+                    event.computer.to_string(),
+                    event.activity.to_string(),
+                    event.name.to_string(),
+                    event.details.to_string(),
+                    (if event.suspicious { "Yes" } else { "" }).to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Timestamp",
+                    "Computer",
+                    "Activity",
+                    "Name",
+                    "Details",
+                    "Suspicious",
+                ],
+                &rows,
+            );
+        }
+        let mut failures: Vec<(&String, &usize)> = self.wmi.failures.iter().collect();
+        failures.sort();
+        for (computer, count) in failures {
+            println!(
+                "{}: {} failed WMI operations (EventID 5858)",
+                anonymize::anonymize_output(computer),
+                count
+            );
+        }
+        println!();
+
+        self.tm_wmi_csv().save("WMI summary", &csv_path);
+    }
+
+    // ホスト毎のWMIのイベントをCSVファイルに出力する内容を作成する
+    fn tm_wmi_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for event in self.wmi.sorted_events() {
+            rows.push(vec![
+                event.timestamp.to_string(),
+                event.computer.to_string(),
+                event.activity.to_string(),
+                event.operation.to_string(),
+                event.name.to_string(),
+                event.details.to_string(),
+                event.user.to_string(),
+                event.suspicious.to_string(),
+                event.channel.to_string(),
+                event.eventid.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Activity",
+                "Operation",
+                "Name",
+                "Details",
+                "User",
+                "Suspicious",
+                "Channel",
+                "EventID",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_bits_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("bits-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let jobs = self.bits.sorted_jobs();
+        println!("BITS Job Summary");
+        let non_allowlisted: Vec<_> = jobs.iter().filter(|job| job.non_allowlisted).collect();
+        println!(
+            "{} BITS jobs found. {} jobs transferred files with domains that are not in the allowlist.",
+            jobs.len(),
+            non_allowlisted.len()
+        );
+        // 許可リストにないドメインとの転送を表示する
+        if !non_allowlisted.is_empty() {
+            let mut rows = vec![];
+            for job in non_allowlisted.iter() {
+                let urls: Vec<&str> = job.urls.iter().map(|url| url.as_str()).collect();
+                rows.push(vec![
+                    job.computer.to_string(),
+                    job.first_seen.to_string(),
+                    job.title.to_string(),
+                    urls.join("\n"),
+                    job.bytes_transferred.to_string(),
+                    job.process.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Computer",
+                    "First Timestamp",
+                    "Job Title",
+                    "URLs",
+                    "Bytes Transferred",
+                    "Process",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        self.tm_bits_csv().save("BITS job summary", &csv_path);
+    }
+
+    // ホスト毎のBITSのジョブの集計結果をCSVファイルに出力する内容を作成する
+    fn tm_bits_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for job in self.bits.sorted_jobs() {
+            let urls: Vec<&str> = job.urls.iter().map(|url| url.as_str()).collect();
+            rows.push(vec![
+                job.computer.to_string(),
+                job.job_id.to_string(),
+                job.title.to_string(),
+                job.owner.to_string(),
+                job.process.to_string(),
+                urls.join(" | "),
+                job.bytes_total.to_string(),
+                job.bytes_transferred.to_string(),
+                job.first_seen.to_string(),
+                job.last_seen.to_string(),
+                job.non_allowlisted.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Computer",
+                "JobID",
+                "JobTitle",
+                "Owner",
+                "Process",
+                "URLs",
+                "BytesTotal",
+                "BytesTransferred",
+                "FirstSeen",
+                "LastSeen",
+                "NonAllowlisted",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_defender_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("defender-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let events = self.defender.sorted_events();
+        println!("Windows Defender Summary");
+        if events.is_empty() {
+            println!("No Windows Defender detections, actions or exclusion changes were found.");
+        } else {
+            let mut rows = vec![];
+            for event in events.iter() {
+                rows.push(vec![
+                    event.timestamp.to_string(),
+                    event.computer.to_string(),
+                    event.activity.to_string(),
+                    event.threat.to_string(),
+                    event.path.to_string(),
+                    event.action.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Timestamp",
+                    "Computer",
+                    "Activity",
+                    "Threat",
+                    "Path",
+                    "Action",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        self.tm_defender_csv()
+            .save("Windows Defender summary", &csv_path);
+    }
+
+    // ホスト毎のWindows Defenderのイベントを時系列順にCSVファイルに出力する内容を作成する
+    fn tm_defender_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for event in self.defender.sorted_events() {
+            rows.push(vec![
+                event.timestamp.to_string(),
+                event.computer.to_string(),
+                event.activity.to_string(),
+                event.threat.to_string(),
+                event.severity.to_string(),
+                event.path.to_string(),
+                event.user.to_string(),
+                event.process.to_string(),
+                event.action.to_string(),
+                event.eventid.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Activity",
+                "Threat",
+                "Severity",
+                "Path",
+                "User",
+                "Process",
+                "Action",
+                "EventID",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_firewall_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("firewall-summary")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let changes = self.firewall.sorted_changes();
+        println!("Windows Firewall Rule Change Summary");
+        if changes.is_empty() {
+            println!("No Windows Firewall rule changes (EventID 2004/2005/2006/2033) were found.");
+        } else {
+            let mut rows = vec![];
+            for change in changes.iter() {
+                rows.push(vec![
+                    change.timestamp.to_string(),
+                    change.computer.to_string(),
+                    change.change.to_string(),
+                    change.rule_name.to_string(),
+                    change.direction.to_string(),
+                    change.action.to_string(),
+                    change.modifying_application.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Timestamp",
+                    "Computer",
+                    "Change",
+                    "Rule Name",
+                    "Direction",
+                    "Action",
+                    "Modifying Application",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        self.tm_firewall_csv()
+            .save("Windows Firewall rule change summary", &csv_path);
+    }
+
+    // ホスト毎のファイアウォールのルールの変更を時系列順にCSVファイルに出力する内容を作成する
+    fn tm_firewall_csv(&self) -> SummaryCsv {
+        let mut rows = vec![];
+        for change in self.firewall.sorted_changes() {
+            rows.push(vec![
+                change.timestamp.to_string(),
+                change.computer.to_string(),
+                change.change.to_string(),
+                change.rule_id.to_string(),
+                change.rule_name.to_string(),
+                change.direction.to_string(),
+                change.action.to_string(),
+                change.application.to_string(),
+                change.protocol.to_string(),
+                change.local_ports.to_string(),
+                change.remote_addresses.to_string(),
+                change.modifying_application.to_string(),
+                change.modifying_user.to_string(),
+                change.eventid.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Change",
+                "RuleId",
+                "RuleName",
+                "Direction",
+                "Action",
+                "Application",
+                "Protocol",
+                "LocalPorts",
+                "RemoteAddresses",
+                "ModifyingApplication",
+                "ModifyingUser",
+                "EventID",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_adcs_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("adcs-analytics")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let activities = self.adcs.activities();
+        println!("Certificate Services (AD CS) Analytics");
+        // 不審な証明書の要求とログオンを表示する
+        let suspicious: Vec<_> = activities
+            .iter()
+            .filter(|activity| !activity.reasons.is_empty())
+            .collect();
+        println!(
+            "{} certificate requests and logons found. {} are suspicious.",
+            activities.len(),
+            suspicious.len()
+        );
+        if !suspicious.is_empty() {
+            let mut rows = vec![];
+            for activity in suspicious.iter() {
+                rows.push(vec![
+                    activity.timestamp.to_string(),
+                    activity.computer.to_string(),
+                    activity.activity.to_string(),
+                    activity.requester.to_string(),
+                    activity.template.to_string(),
+                    activity.subject_alt_names.join("\n"),
+                    activity.status.to_string(),
+                    activity.reasons.join("\n"),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Timestamp",
+                    "Computer",
+                    "Activity",
+                    "Account",
+                    "Template",
+                    "SAN",
+                    "Status",
+                    "Reason",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        Timeline::tm_adcs_csv(&activities).save("AD CS analytics", &csv_path);
+    }
+
+    // 証明書の要求と証明書を使ったログオンをCSVファイルに出力する内容を作成する
+    fn tm_adcs_csv(activities: &[CertificateActivity]) -> SummaryCsv {
+        let mut rows = vec![];
+        for activity in activities.iter() {
+            rows.push(vec![
+                activity.timestamp.to_string(),
+                activity.computer.to_string(),
+                activity.activity.to_string(),
+                activity.request_id.to_string(),
+                activity.requester.to_string(),
+                activity.template.to_string(),
+                activity.subject_alt_names.join(" | "),
+                activity.status.to_string(),
+                activity.cert_issuer.to_string(),
+                activity.cert_serial_number.to_string(),
+                activity.reasons.join(" | "),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Activity",
+                "RequestID",
+                "Account",
+                "Template",
+                "SubjectAltNames",
+                "Status",
+                "CertIssuer",
+                "CertSerialNumber",
+                "Reasons",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_ioc_dsp_msg(&self) {
+        if !configs::CONFIG.read().unwrap().args.is_present("ioc-file") {
+            return;
+        }
+        let matches = self.ioc.sorted_matches();
+        println!("IOC Matches");
+        println!(
+            "{} events matched {} IOCs.",
+            matches.len(),
+            self.ioc.ioc_count()
+        );
+        if !matches.is_empty() {
+            // IOC毎に一致したイベントの件数とホストをまとめて表示する
+            let mut hits: BTreeMap<(&str, &str), (usize, Vec<&str>, &str, &str)> = BTreeMap::new();
+            for ioc_match in matches.iter() {
+                let hit = hits
+                    .entry((
+                        ioc_match.ioc.ioc_type.as_str(),
+                        ioc_match.ioc.value.as_str(),
+                    ))
+                    .or_insert((
+                        0,
+                        vec![],
+                        ioc_match.timestamp.as_str(),
+                        ioc_match.timestamp.as_str(),
+                    ));
+                hit.0 += 1;
+                if !hit.1.contains(&ioc_match.computer.as_str()) {
+                    hit.1.push(ioc_match.computer.as_str());
+                }
+                hit.3 = ioc_match.timestamp.as_str();
+            }
+            let mut rows = vec![];
+            for ((ioc_type, value), (count, computers, first, last)) in hits.iter() {
+                rows.push(vec![
+                    ioc_type.to_string(),
+                    value.to_string(),
+                    count.to_string(),
+                    computers.join("\n"),
+                    first.to_string(),
+                    last.to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Type",
+                    "IOC",
+                    "Events",
+                    "Computers",
+                    "First Timestamp",
+                    "Last Timestamp",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        // ioc-outputオプションが指定されている場合は一致したイベントをCSVファイルに出力する
+        if let Some(csv_path) = configs::CONFIG.read().unwrap().args.value_of("ioc-output") {
+            Timeline::tm_ioc_csv(&matches).save("IOC matches", csv_path);
+        }
+    }
+
+    // IOCに一致したイベントをCSVファイルに出力する内容を作成する
+    fn tm_ioc_csv(matches: &[&IocMatch]) -> SummaryCsv {
+        let mut rows = vec![];
+        for ioc_match in matches.iter() {
+            rows.push(vec![
+                ioc_match.timestamp.to_string(),
+                ioc_match.computer.to_string(),
+                ioc_match.channel.to_string(),
+                ioc_match.eventid.to_string(),
+                ioc_match.record_id.to_string(),
+                ioc_match.ioc.ioc_type.as_str().to_string(),
+                ioc_match.ioc.value.to_string(),
+                ioc_match.ioc.description.to_string(),
+                ioc_match.field.to_string(),
+                ioc_match.value.to_string(),
+                ioc_match.filepath.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Channel",
+                "EventID",
+                "RecordID",
+                "IOCType",
+                "IOC",
+                "Description",
+                "Field",
+                "Value",
+                "EvtxFile",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_time_integrity_dsp_msg(&self) {
+        let csv_path = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("time-integrity")
+        {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        let anomalies = self.time_integrity.sorted_anomalies();
+        println!("Time Integrity");
+        let time_changes = anomalies
+            .iter()
+            .filter(|anomaly| anomaly.kind == TimeAnomalyKind::TimeChange)
+            .count();
+        println!(
+            "{} time anomalies found. {} of them are system time changes.",
+            anomalies.len(),
+            time_changes
+        );
+        if !anomalies.is_empty() {
+            let mut rows = vec![];
+            for anomaly in anomalies.iter() {
+                rows.push(vec![
+                    anomaly.kind.as_str().to_string(),
+                    anomaly.computer.to_string(),
+                    anomaly.channel.to_string(),
+                    format_time(&anomaly.timestamp),
+                    anomaly.event_id.to_string(),
+                    anomaly.record_id.to_string(),
+                    anomaly.count.to_string(),
+                    anomaly.details.to_string(),
+                    (if anomaly.near_time_change { "Yes" } else { "" }).to_string(),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Type",
+                    "Computer",
+                    "Channel",
+                    "Timestamp",
+                    "Event ID",
+                    "Record ID",
+                    "Count",
+                    "Details",
+                    "Near Time Change",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        Timeline::tm_time_integrity_csv(&anomalies).save("time integrity findings", &csv_path);
+    }
+
+    // タイムスタンプの異常をCSVファイルに出力する内容を作成する
+    fn tm_time_integrity_csv(anomalies: &[TimeAnomaly]) -> SummaryCsv {
+        let mut rows = vec![];
+        for anomaly in anomalies.iter() {
+            rows.push(vec![
+                anomaly.kind.as_str().to_string(),
+                anomaly.computer.to_string(),
+                anomaly.channel.to_string(),
+                format_time(&anomaly.timestamp),
+                anomaly.event_id.to_string(),
+                anomaly.record_id.to_string(),
+                anomaly.count.to_string(),
+                anomaly.details.to_string(),
+                anomaly.near_time_change.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Type",
+                "Computer",
+                "Channel",
+                "Timestamp",
+                "EventID",
+                "RecordID",
+                "Count",
+                "Details",
+                "NearTimeChange",
+            ],
+            rows,
+        )
+    }
+
+    pub fn tm_user_timeline_dsp_msg(&self) {
+        let user = match configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .value_of("user-timeline")
+        {
+            Some(user) => user.to_string(),
+            None => return,
+        };
+        let csv_path = configs::output_path(
+            &configs::CONFIG.read().unwrap().args,
+            "user-timeline-output",
+        )
+        .unwrap_or_default();
+        let events = self.user_timeline.sorted_events();
+        println!("User Timeline: {}", anonymize::anonymize_output(&user));
+        println!("{} events found.", events.len());
+        if !events.is_empty() {
+            // 分類とComputer毎にイベント数と最初と最後の日時をまとめて表示する
+            let mut summaries: BTreeMap<
+                (UserEventCategory, &str),
+                (usize, &UserEvent, &UserEvent),
+            > = BTreeMap::new();
+            for event in events.iter() {
+                let summary = summaries
+                    .entry((event.category, event.computer.as_str()))
+                    .or_insert((0, event, event));
+                summary.0 += 1;
+                summary.2 = event;
+            }
+            let mut rows = vec![];
+            for ((category, computer), (count, first, last)) in summaries.iter() {
+                rows.push(vec![
+                    category.as_str().to_string(),
+                    computer.to_string(),
+                    count.to_string(),
+                    format_time(&first.timestamp),
+                    format_time(&last.timestamp),
+                ]);
+            }
+            print_summary_table(
+                &[
+                    "Category",
+                    "Computer",
+                    "Events",
+                    "First Timestamp",
+                    "Last Timestamp",
+                ],
+                &rows,
+            );
+        }
+        println!();
+
+        Timeline::tm_user_timeline_csv(&events).save("user timeline", &csv_path);
+    }
+
+    // 指定したアカウントのイベントを時系列順にCSVファイルに出力する内容を作成する
+    fn tm_user_timeline_csv(events: &[&UserEvent]) -> SummaryCsv {
+        let mut rows = vec![];
+        for event in events.iter() {
+            rows.push(vec![
+                format_time(&event.timestamp),
+                event.computer.to_string(),
+                event.category.as_str().to_string(),
+                event.activity.to_string(),
+                event.channel.to_string(),
+                event.eventid.to_string(),
+                event.record_id.to_string(),
+                event.matched_field.to_string(),
+                event.logon_id.to_string(),
+                event.details.to_string(),
+            ]);
+        }
+        SummaryCsv::new(
+            &[
+                "Timestamp",
+                "Computer",
+                "Category",
+                "Activity",
+                "Channel",
+                "EventID",
+                "RecordID",
+                "MatchedField",
+                "LogonID",
+                "Details",
+            ],
+            rows,
+        )
+    }
+
     pub fn tm_logon_stats_dsp_msg(&mut self) {
         if !configs::CONFIG
             .read()
@@ -91,45 +2008,90 @@ impl Timeline {
         }
 
         self.tm_loginstats_tb_set_msg();
+        self.tm_spray_tb_set_msg();
     }
 
-    // イベントID毎の出力メッセージ生成
-    fn tm_stats_set_msg(&self, mapsorted: Vec<(&std::string::String, &usize)>) -> Vec<String> {
-        let mut msges: Vec<String> = Vec::new();
+    // 件数の割合を算出
+    fn tm_stats_rate(&self, cnt: usize) -> f32 {
+        let rate: f32 = cnt as f32 / self.stats.total as f32;
+        (rate * 1000.0).round() / 10.0
+    }
+
+    // statistics_event_info.txtに登録あるものはイベントのタイトルを返す
+    fn tm_stats_evttitle(&self, event_id: &str) -> String {
+        match configs::CONFIG
+            .read()
+            .unwrap()
+            .event_timeline_config
+            .get_event_id(event_id)
+        {
+            Some(e) => e.evttitle.to_string(),
+            None => "Unknown".to_string(),
+        }
+    }
 
-        for (event_id, event_cnt) in mapsorted.iter() {
-            // 件数の割合を算出
-            let rate: f32 = **event_cnt as f32 / self.stats.total as f32;
+    // Computer毎、Channel毎、イベントID毎の出力メッセージ生成
+    fn tm_stats_set_msg(&self, grouped: &[ComputerStatistics]) -> Vec<String> {
+        let mut msges: Vec<String> = Vec::new();
 
-            // イベント情報取得(eventtitleなど)
-            let conf = configs::CONFIG.read().unwrap();
-            // statistics_event_info.txtに登録あるものは情報設定
-            match conf.event_timeline_config.get_event_id(*event_id) {
-                Some(e) => {
+        for computer in grouped.iter() {
+            msges.push(format!(
+                "Computer: {} ({} / {:.1}%)",
+                computer.computer,
+                computer.total,
+                self.tm_stats_rate(computer.total),
+            ));
+            for channel in computer.channels.iter() {
+                msges.push(format!(
+                    "Channel: {} ({} / {:.1}%)\n",
+                    channel.channel,
+                    channel.total,
+                    self.tm_stats_rate(channel.total),
+                ));
+                msges.push("Count (Percent)\tID\tEvent\t".to_string());
+                msges.push("--------------- ------- ---------------".to_string());
+                for (event_id, event_cnt) in channel.eventids.iter() {
                     // 出力メッセージ1行作成
                     msges.push(format!(
                         "{0} ({1:.1}%)\t{2}\t{3}",
                         event_cnt,
-                        (rate * 1000.0).round() / 10.0,
+                        self.tm_stats_rate(*event_cnt),
                         event_id,
-                        e.evttitle,
-                    ));
-                }
-                None => {
-                    // 出力メッセージ1行作成
-                    msges.push(format!(
-                        "{0} ({1:.1}%)\t{2}\t{3}",
-                        event_cnt,
-                        (rate * 1000.0).round() / 10.0,
-                        event_id,
-                        "Unknown",
+                        self.tm_stats_evttitle(event_id),
                     ));
                 }
+                msges.push(String::default());
             }
+            msges.push("---------------------------------------".to_string());
         }
-        msges.push("---------------------------------------".to_string());
         msges
     }
+
+    // Computer毎、Channel毎、イベントID毎の集計結果をCSVファイルに出力する内容を作成する
+    fn tm_stats_csv(&self, grouped: &[ComputerStatistics]) -> SummaryCsv {
+        let mut rows = vec![];
+        for computer in grouped.iter() {
+            for channel in computer.channels.iter() {
+                for (event_id, event_cnt) in channel.eventids.iter() {
+                    rows.push(vec![
+                        computer.computer.to_string(),
+                        channel.channel.to_string(),
+                        event_id.to_string(),
+                        self.tm_stats_evttitle(event_id),
+                        event_cnt.to_string(),
+                        format!("{:.1}", self.tm_stats_rate(*event_cnt)),
+                    ]);
+                }
+            }
+        }
+        SummaryCsv::new(
+            &[
+                "Computer", "Channel", "EventID", "Event", "Count", "Percent",
+            ],
+            rows,
+        )
+    }
+
     // ユーザ毎のログイン統計情報出力メッセージ生成
     fn tm_loginstats_tb_set_msg(&self) {
         println!("Logon Summary");
@@ -142,8 +2104,7 @@ impl Timeline {
                 println!("{}", msgprint);
             }
         } else {
-            let mut logins_stats_tb = Table::new();
-            logins_stats_tb.set_titles(row!["User", "Failed", "Successful"]);
+            let mut rows = vec![];
             // 集計件数でソート
             let mut mapsorted: Vec<_> = self.stats.stats_login_list.iter().collect();
             mapsorted.sort_by(|x, y| x.0.cmp(y.0));
@@ -154,14 +2115,97 @@ impl Timeline {
                 //key.to_string().pop();
                 username.pop();
                 username.remove(0);
-                logins_stats_tb.add_row(Row::new(vec![
-                    Cell::new(&username),
-                    Cell::new(&values[1].to_string()),
-                    Cell::new(&values[0].to_string()),
-                ]));
+                rows.push(vec![username, values[1].to_string(), values[0].to_string()]);
             }
-            logins_stats_tb.printstd();
+            print_summary_table(&["User", "Failed", "Successful"], &rows);
             println!();
         }
     }
+
+    // 送信元とアカウント毎の認証の失敗とパスワードスプレーの検知結果の出力メッセージ生成
+    fn tm_spray_tb_set_msg(&self) {
+        if self.spray.is_empty() {
+            return;
+        }
+        println!("Failed Logons by Source and Target");
+        let mut rows = vec![];
+        for cell in self.spray.matrix().iter().take(FAILED_LOGON_MATRIX_ROWS) {
+            rows.push(vec![
+                cell.source.to_string(),
+                cell.user.to_string(),
+                cell.count.to_string(),
+                format_time(&cell.first_seen),
+                format_time(&cell.last_seen),
+            ]);
+        }
+        print_summary_table(
+            &[
+                "Source",
+                "Target User",
+                "Failed",
+                "First Timestamp",
+                "Last Timestamp",
+            ],
+            &rows,
+        );
+        println!();
+
+        let findings = self.spray.findings();
+        if findings.is_empty() {
+            return;
+        }
+        println!("Password Spraying and Brute Force");
+        let mut rows = vec![];
+        for finding in findings.iter() {
+            rows.push(vec![
+                finding.pattern.to_string(),
+                finding.source.to_string(),
+                finding.users.len().to_string(),
+                finding.count.to_string(),
+                format_time(&finding.first_seen),
+                format_time(&finding.last_seen),
+            ]);
+        }
+        print_summary_table(
+            &[
+                "Pattern",
+                "Source",
+                "Users",
+                "Failed",
+                "First Timestamp",
+                "Last Timestamp",
+            ],
+            &rows,
+        );
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::timelines::{summary_options, Timeline};
+
+    #[test]
+    fn test_format_filesize() {
+        assert_eq!(Timeline::tm_format_filesize(512), "512 B");
+        assert_eq!(Timeline::tm_format_filesize(1024 * 68), "68.0 KB");
+        assert_eq!(
+            Timeline::tm_format_filesize(1024 * 1024 * 20 + 1024 * 512),
+            "20.5 MB"
+        );
+    }
+
+    #[test]
+    fn test_summary_options() {
+        // 全てのレコードから抽出するオプションは、ルールで使わないチャンネルのファイルも解析する
+        let options: Vec<&str> = summary_options().collect();
+        for option in [
+            "statistics",
+            "logon-summary",
+            "lateral-movement-dot",
+            "user-timeline",
+        ] {
+            assert!(options.contains(&option), "{}", option);
+        }
+    }
 }
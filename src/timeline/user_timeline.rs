@@ -0,0 +1,360 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+const SECURITY_CHANNEL: &str = "Security";
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+
+// アカウント名のフィールド。SysmonのUserはDOMAIN\nameの形式
+const USER_NAME_FIELDS: [&str; 3] = ["TargetUserName", "SubjectUserName", "User"];
+// SIDのフィールド
+const USER_SID_FIELDS: [&str; 3] = ["TargetUserSid", "SubjectUserSid", "TargetSid"];
+// アカウント名のフィールドと同じイベントにあるドメイン名のフィールド
+const DOMAIN_FIELDS: [(&str, &str); 2] = [
+    ("TargetUserName", "TargetDomainName"),
+    ("SubjectUserName", "SubjectDomainName"),
+];
+// ログオンIDのフィールド。先にあるものを優先する
+const LOGON_ID_FIELDS: [&str; 3] = ["TargetLogonId", "SubjectLogonId", "LogonId"];
+// Details列に出力するフィールド。イベントにあるものだけを出力する
+const DETAIL_FIELDS: [&str; 17] = [
+    "LogonType",
+    "IpAddress",
+    "WorkstationName",
+    "Status",
+    "SubStatus",
+    "ServiceName",
+    "Image",
+    "NewProcessName",
+    "CommandLine",
+    "ParentImage",
+    "ParentProcessName",
+    "ObjectType",
+    "ObjectName",
+    "ShareName",
+    "RelativeTargetName",
+    "AccessList",
+    "ProcessName",
+];
+
+/// ユーザのタイムラインに含めるイベントの分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UserEventCategory {
+    Authentication,
+    Process,
+    ObjectAccess,
+}
+
+impl UserEventCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserEventCategory::Authentication => "Authentication",
+            UserEventCategory::Process => "Process",
+            UserEventCategory::ObjectAccess => "Object Access",
+        }
+    }
+}
+
+/// チャンネルとイベントIDから、イベントの分類と内容を返す。対象外のイベントはNone
+fn classify(channel: &str, eventid: &str) -> Option<(UserEventCategory, &'static str)> {
+    use UserEventCategory::*;
+    let activity = match (channel, eventid) {
+        (SECURITY_CHANNEL, "4624") => (Authentication, "Logon"),
+        (SECURITY_CHANNEL, "4625") => (Authentication, "Failed Logon"),
+        (SECURITY_CHANNEL, "4634") | (SECURITY_CHANNEL, "4647") => (Authentication, "Logoff"),
+        (SECURITY_CHANNEL, "4648") => (Authentication, "Explicit Credential Logon"),
+        (SECURITY_CHANNEL, "4672") => (Authentication, "Special Privileges Assigned"),
+        (SECURITY_CHANNEL, "4768") => (Authentication, "Kerberos TGT Request"),
+        (SECURITY_CHANNEL, "4769") => (Authentication, "Kerberos Service Ticket Request"),
+        (SECURITY_CHANNEL, "4771") => (Authentication, "Kerberos Pre-authentication Failed"),
+        (SECURITY_CHANNEL, "4776") => (Authentication, "NTLM Authentication"),
+        (SECURITY_CHANNEL, "4778") => (Authentication, "Session Reconnected"),
+        (SECURITY_CHANNEL, "4779") => (Authentication, "Session Disconnected"),
+        (SECURITY_CHANNEL, "4688") | (SYSMON_CHANNEL, "1") => (Process, "Process Created"),
+        (SECURITY_CHANNEL, "4689") | (SYSMON_CHANNEL, "5") => (Process, "Process Terminated"),
+        (SECURITY_CHANNEL, "4656") => (ObjectAccess, "Handle Requested"),
+        (SECURITY_CHANNEL, "4660") => (ObjectAccess, "Object Deleted"),
+        (SECURITY_CHANNEL, "4663") => (ObjectAccess, "Object Accessed"),
+        (SECURITY_CHANNEL, "4670") => (ObjectAccess, "Permissions Changed"),
+        (SECURITY_CHANNEL, "5140") => (ObjectAccess, "Network Share Accessed"),
+        (SECURITY_CHANNEL, "5145") => (ObjectAccess, "Network Share Object Checked"),
+        _ => return None,
+    };
+    Some(activity)
+}
+
+/// 指定したアカウントのイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserEvent {
+    pub timestamp: DateTime<Utc>,
+    pub computer: String,
+    pub category: UserEventCategory,
+    pub activity: &'static str,
+    pub channel: String,
+    pub eventid: String,
+    pub record_id: String,
+    /// アカウントが一致したフィールド
+    pub matched_field: String,
+    pub logon_id: String,
+    pub details: String,
+}
+
+/// --user-timelineで指定したアカウント。名前はDOMAIN\name、name@domain、nameかSIDで指定する
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TargetAccount {
+    name: String,
+    domain: Option<String>,
+    sid: Option<String>,
+}
+
+impl TargetAccount {
+    pub fn parse(account: &str) -> TargetAccount {
+        let account = account.trim().to_lowercase();
+        if account.starts_with("s-1-") {
+            return TargetAccount {
+                name: String::default(),
+                domain: None,
+                sid: Some(account),
+            };
+        }
+        let (domain, name) = match (account.split_once('\\'), account.split_once('@')) {
+            (Some((domain, name)), _) => (Some(domain), name),
+            (None, Some((name, domain))) => (Some(domain), name),
+            (None, None) => (None, account.as_str()),
+        };
+        TargetAccount {
+            name: name.to_string(),
+            domain: domain.map(|domain| domain.to_string()),
+            sid: None,
+        }
+    }
+
+    /// レコードのアカウントが一致したフィールドを返す
+    fn matched_field(&self, record: &Value) -> Option<&'static str> {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .map(|value| value.to_lowercase())
+        };
+        if let Some(sid) = &self.sid {
+            return USER_SID_FIELDS
+                .into_iter()
+                .find(|field| get(field).as_ref() == Some(sid));
+        }
+        USER_NAME_FIELDS.into_iter().find(|field| {
+            let value = match get(field) {
+                Some(value) => value,
+                None => return false,
+            };
+            let (domain, name) = match value.split_once('\\') {
+                Some((domain, name)) => (Some(domain.to_string()), name.to_string()),
+                None => (
+                    DOMAIN_FIELDS
+                        .iter()
+                        .find(|(name_field, _)| name_field == field)
+                        .and_then(|(_, domain_field)| get(domain_field)),
+                    value,
+                ),
+            };
+            // ドメインを指定した場合は、ドメインがレコードにあれば比較する。NetBIOS名とFQDNの違いは先頭のラベルで比較する
+            let domain_match = match (&self.domain, &domain) {
+                (Some(target), Some(domain)) if !domain.is_empty() && domain != "-" => {
+                    target.split('.').next() == domain.split('.').next()
+                }
+                _ => true,
+            };
+            name == self.name && domain_match
+        })
+    }
+}
+
+/**
+* 指定したアカウント(名前かSID)の認証、プロセスの実行、オブジェクトへのアクセスのイベントを全てのファイルから抽出して、
+* 時系列順のタイムラインにする。アカウントの侵害の調査で、そのアカウントの活動だけを確認するため。
+*/
+#[derive(Debug, Default)]
+pub struct UserTimeline {
+    target: Option<TargetAccount>,
+    pub events: Vec<UserEvent>,
+}
+
+impl UserTimeline {
+    pub fn new() -> UserTimeline {
+        UserTimeline {
+            target: configs::CONFIG
+                .read()
+                .unwrap()
+                .args
+                .value_of("user-timeline")
+                .map(TargetAccount::parse),
+            events: vec![],
+        }
+    }
+
+    pub fn user_timeline_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でuser-timelineオプションが指定されている時だけ抽出する。
+        if self.target.is_none() {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &Value) {
+        let target = match &self.target {
+            Some(target) => target,
+            None => return,
+        };
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        let (category, activity) = match classify(&channel, &eventid) {
+            Some(classified) => classified,
+            None => return,
+        };
+        let matched_field = match target.matched_field(record) {
+            Some(field) => field,
+            None => return,
+        };
+        let timestamp = match utils::str_time_to_datetime(&get(
+            "Event.System.TimeCreated_attributes.SystemTime",
+        )) {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let details: Vec<String> = DETAIL_FIELDS
+            .iter()
+            .map(|field| (field, get(field)))
+            .filter(|(_, value)| !value.is_empty() && value != "-")
+            .map(|(field, value)| format!("{}: {}", field, value))
+            .collect();
+        self.events.push(UserEvent {
+            timestamp,
+            computer: get("Event.System.Computer"),
+            category,
+            activity,
+            channel,
+            eventid,
+            record_id: get("Event.System.EventRecordID"),
+            matched_field: matched_field.to_string(),
+            logon_id: LOGON_ID_FIELDS
+                .iter()
+                .map(|field| get(field))
+                .find(|value| !value.is_empty())
+                .unwrap_or_default(),
+            details: details.join(" ¦ "),
+        });
+    }
+
+    /// 別のUserTimelineの抽出結果を追加する
+    pub fn merge(&mut self, other: UserTimeline) {
+        self.events.extend(other.events);
+    }
+
+    /// 全てのファイルのイベントを時系列順に並べて返す
+    pub fn sorted_events(&self) -> Vec<&UserEvent> {
+        let mut events: Vec<&UserEvent> = self.events.iter().collect();
+        events.sort_by(|x, y| {
+            x.timestamp
+                .cmp(&y.timestamp)
+                .then_with(|| x.computer.cmp(&y.computer))
+                .then_with(|| x.record_id.cmp(&y.record_id))
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::user_timeline::{TargetAccount, UserEventCategory, UserTimeline};
+    use serde_json::{json, Value};
+
+    fn event(channel: &str, eventid: u64, time: &str, event_data: Value) -> Value {
+        json!({
+            "Event": {
+                "System": {
+                    "Channel": channel,
+                    "EventID": eventid,
+                    "EventRecordID": 1,
+                    "Computer": "DC01.example.local",
+                    "TimeCreated_attributes": { "SystemTime": time },
+                },
+                "EventData": event_data,
+            }
+        })
+    }
+
+    fn user_timeline(account: &str) -> UserTimeline {
+        let mut timeline = UserTimeline {
+            target: Some(TargetAccount::parse(account)),
+            events: vec![],
+        };
+        for record in [
+            event(
+                "Security",
+                4624,
+                "2022-05-20T10:00:00Z",
+                json!({"TargetUserName": "taro", "TargetDomainName": "EXAMPLE", "TargetUserSid": "S-1-5-21-1-2-3-1104", "TargetLogonId": "0x1234", "LogonType": 3, "IpAddress": "10.0.0.5"}),
+            ),
+            event(
+                "Microsoft-Windows-Sysmon/Operational",
+                1,
+                "2022-05-20T10:01:00Z",
+                json!({"User": "EXAMPLE\\Taro", "Image": "C:\\Windows\\System32\\cmd.exe", "LogonId": "0x1234"}),
+            ),
+            event(
+                "Security",
+                4663,
+                "2022-05-20T09:59:00Z",
+                json!({"SubjectUserName": "taro", "SubjectDomainName": "OTHER", "ObjectName": "C:\\secret.txt"}),
+            ),
+            event(
+                "Security",
+                4624,
+                "2022-05-20T10:02:00Z",
+                json!({"TargetUserName": "hanako", "SubjectUserName": "-"}),
+            ),
+            event(
+                "Security",
+                4720,
+                "2022-05-20T10:03:00Z",
+                json!({"TargetUserName": "taro"}),
+            ),
+        ] {
+            timeline.add(&record);
+        }
+        timeline
+    }
+
+    #[test]
+    fn test_user_timeline_by_name() {
+        let timeline = user_timeline("taro");
+        let events = timeline.sorted_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].category, UserEventCategory::ObjectAccess);
+        assert_eq!(events[0].details, "ObjectName: C:\\secret.txt");
+        assert_eq!(events[1].activity, "Logon");
+        assert_eq!(events[1].matched_field, "TargetUserName");
+        assert_eq!(events[1].logon_id, "0x1234");
+        assert_eq!(events[1].details, "LogonType: 3 ¦ IpAddress: 10.0.0.5");
+        assert_eq!(events[2].category, UserEventCategory::Process);
+        assert_eq!(events[2].matched_field, "User");
+
+        // ドメインを指定した場合は別のドメインの同じ名前のアカウントを含めない
+        assert_eq!(user_timeline("example.local\\taro").events.len(), 2);
+        assert_eq!(user_timeline("taro@example.local").events.len(), 2);
+    }
+
+    #[test]
+    fn test_user_timeline_by_sid() {
+        let timeline = user_timeline("S-1-5-21-1-2-3-1104");
+        assert_eq!(timeline.events.len(), 1);
+        assert_eq!(timeline.events[0].matched_field, "TargetUserSid");
+        assert!(user_timeline("S-1-5-18").events.is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+
+/**
+* 最も新しいイベントからtimeframe以内のイベントだけを保持するスライディングウィンドウ。
+* 全てのイベントをメモリに溜めずに、期間内の件数で判定する集計に使う。
+*/
+#[derive(Debug)]
+pub struct SlidingWindow<T> {
+    timeframe: Duration,
+    // 期間内のイベント。古い順
+    events: VecDeque<(DateTime<Utc>, T)>,
+}
+
+impl<T> SlidingWindow<T> {
+    pub fn new(timeframe: Duration) -> SlidingWindow<T> {
+        SlidingWindow {
+            timeframe,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// イベントを時刻順の位置に追加し、期間外になった古いイベントをevictedに渡して取り除く。
+    /// 最も新しいイベントから見て既に期間外のイベントは追加せずにfalseを返す。
+    pub fn push(&mut self, time: DateTime<Utc>, value: T, mut evicted: impl FnMut(T)) -> bool {
+        if let Some((latest, _)) = self.events.back() {
+            if *latest - time > self.timeframe {
+                return false;
+            }
+        }
+        // レコードはほぼ時刻順に読み込まれるので、後ろから挿入する位置を探す
+        let idx = self
+            .events
+            .iter()
+            .rposition(|(event_time, _)| *event_time <= time)
+            .map_or(0, |idx| idx + 1);
+        self.events.insert(idx, (time, value));
+        let latest = self.events.back().unwrap().0;
+        while let Some((oldest, _)) = self.events.front() {
+            if latest - *oldest <= self.timeframe {
+                break;
+            }
+            evicted(self.events.pop_front().unwrap().1);
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// 期間内のイベントを古い順に返す
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &(DateTime<Utc>, T)> {
+        self.events.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::window::SlidingWindow;
+    use chrono::{DateTime, Duration, Utc};
+
+    #[test]
+    fn test_sliding_window() {
+        let time = |minute: i64| {
+            "2021-12-12T10:00:00Z".parse::<DateTime<Utc>>().unwrap() + Duration::minutes(minute)
+        };
+        let mut window = SlidingWindow::new(Duration::minutes(30));
+        let mut evicted = vec![];
+        for minute in [0, 20, 10, 40] {
+            assert!(window.push(time(minute), minute, |value| evicted.push(value)));
+        }
+        // 40分のイベントから30分より前の0分のイベントは取り除き、前後したイベントは時刻順に並べる
+        assert_eq!(evicted, vec![0]);
+        let values: Vec<i64> = window.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![10, 20, 40]);
+
+        // 既に期間外の遅れたイベントは追加しない
+        assert!(!window.push(time(5), 5, |value| evicted.push(value)));
+        assert_eq!(window.len(), 3);
+
+        assert!(window.push(time(100), 100, |value| evicted.push(value)));
+        assert_eq!(evicted, vec![0, 10, 20, 40]);
+        assert_eq!(window.len(), 1);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::detections::{configs, detection::EvtxRecordInfo, utils};
+use hashbrown::HashMap;
+
+const WMI_CHANNEL: &str = "Microsoft-Windows-WMI-Activity/Operational";
+const SYSMON_CHANNEL: &str = "Microsoft-Windows-Sysmon/Operational";
+// コマンドやスクリプトを実行できるコンシューマ。小文字で比較する
+const SUSPICIOUS_CONSUMERS: [&str; 2] = ["commandlineeventconsumer", "activescripteventconsumer"];
+// 永続化や監視によく使われるイベントのクエリ。小文字で比較する
+const SUSPICIOUS_QUERIES: [&str; 5] = [
+    "win32_process",
+    "win32_logonsession",
+    "win32_localtime",
+    "win32_ntlogevent",
+    "__timerevent",
+];
+// 標準のWMIプロバイダのディレクトリ。小文字で比較する
+const DEFAULT_PROVIDER_DIR: &str = "\\system32\\wbem\\";
+
+/// WMIのイベントフィルタ、コンシューマ、バインディングの作成などのイベント1件分
+#[derive(Debug, Clone, PartialEq)]
+pub struct WmiEvent {
+    pub timestamp: String,
+    pub computer: String,
+    pub activity: &'static str,
+    pub operation: String,
+    pub name: String,
+    /// フィルタのクエリ、コンシューマの実行内容、ロードしたプロバイダのパスなど
+    pub details: String,
+    pub user: String,
+    pub suspicious: bool,
+    pub channel: String,
+    pub eventid: String,
+}
+
+/**
+* WMI-ActivityのOperationalログのイベントID 5857-5861とSysmonのイベントID 19/20/21から、
+* ホスト毎のWMIのイベントフィルタ、コンシューマ、バインディングの作成と一時的なコンシューマの登録を一覧にする。
+* コマンドやスクリプトを実行するコンシューマと、プロセスの起動やログオンなどを監視するクエリを強調して出力する。
+*/
+#[derive(Debug, Default)]
+pub struct WmiSummary {
+    pub events: Vec<WmiEvent>,
+    /// ホスト毎のWMIの操作の失敗(5858)の件数
+    pub failures: HashMap<String, usize>,
+}
+
+impl WmiSummary {
+    pub fn new() -> WmiSummary {
+        WmiSummary::default()
+    }
+
+    pub fn wmi_start(&mut self, records: &[EvtxRecordInfo]) {
+        // 引数でwmi-summaryオプションが指定されている時だけ集計する。
+        if !configs::CONFIG
+            .read()
+            .unwrap()
+            .args
+            .is_present("wmi-summary")
+        {
+            return;
+        }
+        for record in records.iter() {
+            self.add(&record.record);
+        }
+    }
+
+    fn add(&mut self, record: &serde_json::Value) {
+        let get = |key: &str| {
+            utils::get_event_value(key, record)
+                .and_then(utils::value_to_string)
+                .unwrap_or_default()
+        };
+        let channel = get("Event.System.Channel");
+        let eventid = get("Event.System.EventID");
+        let computer = get("Event.System.Computer");
+        let (activity, operation, name, details, user) = match (channel.as_str(), eventid.as_str())
+        {
+            (WMI_CHANNEL, "5857") => {
+                // 標準のディレクトリ以外からロードされたプロバイダだけを対象にする
+                let path = user_data(record, "ProviderPath");
+                if path.to_lowercase().contains(DEFAULT_PROVIDER_DIR) {
+                    return;
+                }
+                let host_process = user_data(record, "HostProcess");
+                (
+                    "Provider Loaded",
+                    host_process,
+                    user_data(record, "ProviderName"),
+                    path,
+                    String::default(),
+                )
+            }
+            (WMI_CHANNEL, "5858") => {
+                *self.failures.entry(computer).or_insert(0) += 1;
+                return;
+            }
+            (WMI_CHANNEL, "5859") => (
+                "Event Provider Query",
+                user_data(record, "Provider"),
+                user_data(record, "NamespaceName"),
+                user_data(record, "Query"),
+                user_data(record, "User"),
+            ),
+            (WMI_CHANNEL, "5860") => (
+                "Temporary Consumer",
+                "Registered".to_string(),
+                user_data(record, "NamespaceName"),
+                user_data(record, "Query"),
+                user_data(record, "User"),
+            ),
+            (WMI_CHANNEL, "5861") => (
+                "Binding",
+                "Registered".to_string(),
+                user_data(record, "Namespace"),
+                format!(
+                    "{} -> {}",
+                    user_data(record, "ESS"),
+                    user_data(record, "CONSUMER")
+                ),
+                String::default(),
+            ),
+            (SYSMON_CHANNEL, "19") => (
+                "Filter",
+                get("Operation"),
+                get("Name"),
+                get("Query"),
+                get("User"),
+            ),
+            (SYSMON_CHANNEL, "20") => (
+                "Consumer",
+                get("Operation"),
+                get("Name"),
+                format!("{}: {}", get("Type"), get("Destination")),
+                get("User"),
+            ),
+            (SYSMON_CHANNEL, "21") => (
+                "Binding",
+                get("Operation"),
+                get("Consumer"),
+                get("Filter"),
+                get("User"),
+            ),
+            _ => return,
+        };
+        let suspicious = match activity {
+            "Provider Loaded" => true,
+            "Temporary Consumer" | "Filter" | "Event Provider Query" => {
+                contains_any(&details, &SUSPICIOUS_QUERIES)
+            }
+            // SysmonのTypeは"Command Line"か"Script"になる
+            "Consumer" => details.starts_with("Command Line") || details.starts_with("Script"),
+            _ => {
+                contains_any(&details, &SUSPICIOUS_CONSUMERS)
+                    || contains_any(&name, &SUSPICIOUS_CONSUMERS)
+            }
+        };
+        self.events.push(WmiEvent {
+            timestamp: get("Event.System.TimeCreated_attributes.SystemTime"),
+            computer,
+            activity,
+            operation,
+            name,
+            details,
+            user,
+            suspicious,
+            channel,
+            eventid,
+        });
+    }
+
+    /// 別のWmiSummaryの集計結果を追加する
+    pub fn merge(&mut self, other: WmiSummary) {
+        self.events.extend(other.events);
+        for (computer, count) in other.failures {
+            *self.failures.entry(computer).or_insert(0) += count;
+        }
+    }
+
+    /// ホスト毎に時系列順に並べたイベントを返す
+    pub fn sorted_events(&self) -> Vec<&WmiEvent> {
+        let mut events: Vec<&WmiEvent> = self.events.iter().collect();
+        events.sort_by(|x, y| {
+            x.computer
+                .cmp(&y.computer)
+                .then_with(|| x.timestamp.cmp(&y.timestamp))
+                .then_with(|| x.activity.cmp(y.activity))
+        });
+        events
+    }
+}
+
+/// WMI-ActivityのイベントはUserDataの下の操作毎の要素に値が入っているので、その要素から値を取り出す
+fn user_data(record: &serde_json::Value, field: &str) -> String {
+    record["Event"]["UserData"]
+        .as_object()
+        .and_then(|user_data| {
+            user_data
+                .values()
+                .find_map(|operation| operation.get(field))
+                .and_then(utils::value_to_string)
+        })
+        .unwrap_or_default()
+}
+
+fn contains_any(value: &str, patterns: &[&str]) -> bool {
+    let value = value.to_lowercase();
+    patterns.iter().any(|pattern| value.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeline::wmi::WmiSummary;
+    use serde_json::json;
+
+    #[test]
+    fn test_wmi_summary() {
+        let mut summary = WmiSummary::new();
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 5861,
+                    "Channel": "Microsoft-Windows-WMI-Activity/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:01Z" },
+                },
+                "UserData": {
+                    "Operation_ESStoConsumerBinding": {
+                        "Namespace": "//./root/subscription",
+                        "ESS": "Updater",
+                        "CONSUMER": "CommandLineEventConsumer=\"Updater\"",
+                    },
+                },
+            }
+        }));
+        summary.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 19,
+                    "Channel": "Microsoft-Windows-Sysmon/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:00Z" },
+                },
+                "EventData": {
+                    "Operation": "Created",
+                    "User": "CORP\\admin",
+                    "Name": "\"Updater\"",
+                    "Query": "SELECT * FROM __InstanceModificationEvent WITHIN 60 WHERE TargetInstance ISA 'Win32_PerfFormattedData_PerfOS_System'",
+                },
+            }
+        }));
+        let mut other = WmiSummary::new();
+        other.add(&json!({
+            "Event": {
+                "System": {
+                    "EventID": 5858,
+                    "Channel": "Microsoft-Windows-WMI-Activity/Operational",
+                    "Computer": "PC01",
+                    "TimeCreated_attributes": { "SystemTime": "2021-12-12T10:00:02Z" },
+                },
+                "UserData": {
+                    "Operation_ClientFailure": { "ResultCode": "0x80041032" },
+                },
+            }
+        }));
+        summary.merge(other);
+
+        let events = summary.sorted_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].activity, "Filter");
+        assert_eq!(events[0].operation, "Created");
+        assert!(!events[0].suspicious);
+        assert_eq!(events[1].activity, "Binding");
+        assert_eq!(
+            events[1].details,
+            "Updater -> CommandLineEventConsumer=\"Updater\""
+        );
+        assert!(events[1].suspicious);
+        assert_eq!(summary.failures["PC01"], 1);
+    }
+}
@@ -0,0 +1,121 @@
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::{Component, Path};
+use std::sync::RwLock;
+
+lazy_static! {
+    /// --triageで見つけたevtxファイルのパスと推定したホスト名の対応
+    pub static ref TRIAGE_HOSTS: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+    // Velociraptorのコレクションのディレクトリ名(例: Collection-WIN10-2022-05-20T01_02_03Z)
+    static ref VELOCIRAPTOR_COLLECTION_REGEX: Regex =
+        Regex::new(r"^Collection-(.+?)-\d{4}-\d{2}-\d{2}T").unwrap();
+}
+
+// ホスト名ではないKAPEやVelociraptorのディレクトリ名
+const LAYOUT_DIRS: [&str; 6] = ["uploads", "auto", "ntfs", "file", "mft", "vss"];
+
+/// 検知結果のファイルパスから推定したホスト名を取得する
+pub fn get_triage_host(filepath: &str) -> Option<String> {
+    TRIAGE_HOSTS.read().unwrap().get(filepath).cloned()
+}
+
+/// ファイルパスと推定したホスト名を登録する
+pub fn register_triage_host(filepath: &str, host: &str) {
+    TRIAGE_HOSTS
+        .write()
+        .unwrap()
+        .insert(filepath.to_string(), host.to_string());
+}
+
+/**
+* KAPE(<出力先>\<ホスト名>\C\Windows\System32\winevt\Logs)やVelociraptor(Collection-<ホスト名>-<日時>\uploads\auto\C%3A\Windows\...)の
+* ディレクトリ構成から、evtxファイルを収集したホスト名を推定する。
+* Windowsディレクトリより上のディレクトリを遡り、ドライブ名やツール固有のディレクトリではない最初のディレクトリ名をホスト名とする。
+*/
+pub fn infer_hostname(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut dirs: Vec<String> = vec![];
+    if let Some(root_name) = root.file_name().and_then(|name| name.to_str()) {
+        dirs.push(root_name.to_string());
+    }
+    dirs.extend(relative.parent().into_iter().flat_map(|parent| {
+        parent.components().filter_map(|component| match component {
+            Component::Normal(name) => name.to_str().map(|name| name.to_string()),
+            _ => None,
+        })
+    }));
+
+    // Windows\System32\winevt\Logsの構成の場合はWindowsディレクトリより上を探す。それ以外の場合はevtxファイルがあるディレクトリから探す
+    let end = dirs
+        .iter()
+        .position(|dir| dir.eq_ignore_ascii_case("windows"))
+        .unwrap_or(dirs.len());
+    dirs[..end]
+        .iter()
+        .rev()
+        .filter(|dir| !is_layout_dir(dir))
+        .map(|dir| {
+            VELOCIRAPTOR_COLLECTION_REGEX
+                .captures(dir)
+                .map(|caps| caps[1].to_string())
+                .unwrap_or_else(|| dir.to_string())
+        })
+        .next()
+}
+
+// ドライブ名(C、C%3A、%5C%5C.%5CC%3A)やツール固有のディレクトリ名かを判定する
+fn is_layout_dir(dir: &str) -> bool {
+    let lower = dir.to_lowercase();
+    (dir.len() == 1 && dir.chars().all(|c| c.is_ascii_alphabetic()))
+        || lower.contains("%3a")
+        || lower.contains("%5c")
+        || LAYOUT_DIRS.contains(&lower.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::triage::infer_hostname;
+    use std::path::Path;
+
+    #[test]
+    fn test_infer_hostname() {
+        let root = Path::new("/triage");
+        // KAPE
+        assert_eq!(
+            infer_hostname(
+                root,
+                Path::new("/triage/WIN10-PC/C/Windows/System32/winevt/Logs/Security.evtx")
+            ),
+            Some("WIN10-PC".to_string())
+        );
+        // Velociraptor
+        assert_eq!(
+            infer_hostname(
+                root,
+                Path::new("/triage/Collection-DC01-2022-05-20T01_02_03Z/uploads/auto/C%3A/Windows/System32/winevt/Logs/Security.evtx")
+            ),
+            Some("DC01".to_string())
+        );
+        assert_eq!(
+            infer_hostname(
+                root,
+                Path::new("/triage/Collection-DC01-2022-05-20T01_02_03Z/uploads/ntfs/%5C%5C.%5CC%3A/Windows/System32/winevt/Logs/System.evtx")
+            ),
+            Some("DC01".to_string())
+        );
+        // ホスト毎のディレクトリにevtxファイルがある場合
+        assert_eq!(
+            infer_hostname(root, Path::new("/triage/FS01/Security.evtx")),
+            Some("FS01".to_string())
+        );
+        // コレクションのディレクトリ自体を指定した場合
+        assert_eq!(
+            infer_hostname(
+                Path::new("/Collection-WS02-2022-05-20T01_02_03Z"),
+                Path::new("/Collection-WS02-2022-05-20T01_02_03Z/uploads/auto/C%3A/Windows/System32/winevt/Logs/Security.evtx")
+            ),
+            Some("WS02".to_string())
+        );
+    }
+}
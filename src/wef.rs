@@ -0,0 +1,173 @@
+use crate::detections::utils;
+use hashbrown::HashSet;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::RwLock;
+
+// WEF(Windows Event Forwarding)のコレクターが転送されたイベントを保存するログの名前
+const FORWARDED_EVENTS_LOG: &str = "forwardedevents";
+
+lazy_static! {
+    // ファイル名からは分からないが、レコードから転送されたイベントだと分かったファイル
+    static ref FORWARDED_FILES: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+}
+
+/**
+* ForwardedEvents.evtxのように、WEFで転送されたイベントを保存したファイルかを判定する。
+* アーカイブしたArchive-ForwardedEvents-*.evtxや、zipやgzに含まれるファイルも対象とする。
+* 名前を変えてコピーしたファイルなどは、レコードからmark_forwardedで登録したものを対象とする。
+*/
+pub fn is_forwarded_events_file(filepath: &str) -> bool {
+    Path::new(filepath)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| {
+            name.to_lowercase().contains(FORWARDED_EVENTS_LOG)
+        })
+        || FORWARDED_FILES.read().unwrap().contains(filepath)
+}
+
+/// レコードから転送されたイベントだと分かったファイルを登録する
+pub fn mark_forwarded(filepath: &str) {
+    FORWARDED_FILES
+        .write()
+        .unwrap()
+        .insert(filepath.to_string());
+}
+
+/**
+* ファイルのレコードから、WEFで転送されたイベントのファイルかを判定する。
+* 転送されたイベントにはコレクターが付けたRenderingInfoがあり、複数のコンピュータのイベントが混ざるので、
+* RenderingInfoがあるか、ホスト名が異なるComputerのレコードがあれば転送されたイベントとみなす。
+*/
+#[derive(Debug, Default)]
+pub struct ForwardedDetector {
+    // 最初のレコードのComputerのホスト名(ドメインを除いた小文字)
+    computer: Option<String>,
+    forwarded: bool,
+}
+
+impl ForwardedDetector {
+    pub fn new() -> ForwardedDetector {
+        ForwardedDetector::default()
+    }
+
+    pub fn observe(&mut self, record: &Value) {
+        if self.forwarded {
+            return;
+        }
+        if !record["Event"]["RenderingInfo"].is_null() {
+            self.forwarded = true;
+            return;
+        }
+        let computer = match utils::get_event_value("Event.System.Computer", record)
+            .and_then(|computer| computer.as_str())
+        {
+            Some(computer) => host_name(computer),
+            None => return,
+        };
+        match &self.computer {
+            Some(first) => self.forwarded = *first != computer,
+            None => self.computer = Some(computer),
+        }
+    }
+
+    pub fn is_forwarded(&self) -> bool {
+        self.forwarded
+    }
+}
+
+// ドメインに参加した前後でComputerがFQDNに変わっても同じコンピュータとみなすように、最初のラベルだけを比べる
+fn host_name(computer: &str) -> String {
+    computer
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/**
+* 検知結果のホスト名を返す。
+* 転送されたイベントはコレクターのファイルに保存されているので、ファイルから推定したホスト名ではなくイベントを記録した元のコンピュータ名にする。
+*/
+pub fn source_host(filepath: &str, computer: &str, file_host: Option<String>) -> Option<String> {
+    if is_forwarded_events_file(filepath) && !computer.is_empty() && computer != "-" {
+        Some(computer.to_string())
+    } else {
+        file_host
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wef::{is_forwarded_events_file, mark_forwarded, source_host, ForwardedDetector};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_forwarded_events_file() {
+        assert!(is_forwarded_events_file(
+            "C:\\Windows\\System32\\winevt\\Logs\\ForwardedEvents.evtx"
+        ));
+        assert!(is_forwarded_events_file(
+            "logs/Archive-ForwardedEvents-2022-05-20-01-02-03-456.evtx"
+        ));
+        assert!(is_forwarded_events_file(
+            "logs.zip/WEC01/ForwardedEvents.evtx.gz"
+        ));
+        assert!(!is_forwarded_events_file("logs/Security.evtx"));
+
+        mark_forwarded("logs/collector-security.evtx");
+        assert!(is_forwarded_events_file("logs/collector-security.evtx"));
+    }
+
+    #[test]
+    fn test_forwarded_detector() {
+        let record = |computer: &str| json!({ "Event": { "System": { "Computer": computer, "Channel": "Security" } } });
+
+        // 同じコンピュータのイベントだけのファイルは転送されたイベントではない
+        let mut detector = ForwardedDetector::new();
+        detector.observe(&record("PC01"));
+        detector.observe(&record("PC01.example.local"));
+        assert!(!detector.is_forwarded());
+
+        // 異なるコンピュータのイベントが混ざっている
+        detector.observe(&record("PC02.example.local"));
+        assert!(detector.is_forwarded());
+
+        // コレクターが付けたRenderingInfoがある
+        let mut detector = ForwardedDetector::new();
+        detector.observe(&json!({
+            "Event": {
+                "System": { "Computer": "PC01.example.local" },
+                "RenderingInfo": { "Level": "Information" }
+            }
+        }));
+        assert!(detector.is_forwarded());
+    }
+
+    #[test]
+    fn test_source_host() {
+        let collector = Some("WEC01".to_string());
+        assert_eq!(
+            source_host(
+                "WEC01/ForwardedEvents.evtx",
+                "PC01.example.local",
+                collector.clone()
+            ),
+            Some("PC01.example.local".to_string())
+        );
+        assert_eq!(
+            source_host("WEC01/ForwardedEvents.evtx", "-", collector.clone()),
+            collector
+        );
+        assert_eq!(
+            source_host(
+                "WEC01/Security.evtx",
+                "WEC01.example.local",
+                collector.clone()
+            ),
+            collector
+        );
+    }
+}
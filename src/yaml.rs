@@ -2,15 +2,17 @@ extern crate serde_derive;
 extern crate yaml_rust;
 
 use crate::detections::configs;
-use crate::detections::print::AlertMessage;
+use crate::detections::macros;
 use crate::detections::print::ERROR_LOG_STACK;
 use crate::detections::print::QUIET_ERRORS_FLAG;
+use crate::detections::print::{ErrorClass, ErrorLog};
 use crate::filter::RuleExclude;
-use hashbrown::HashMap;
+use crate::logging;
+use crate::options::sigma_convert;
+use hashbrown::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::io::BufWriter;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 use yaml_rust::Yaml;
@@ -21,6 +23,10 @@ pub struct ParseYaml {
     pub rulecounter: HashMap<String, u128>,
     pub ignorerule_count: u128,
     pub errorrule_count: u128,
+    // trueの場合は読み込んだSigmaのルールをhayabusaのルールに変換する(--sigma-rules)
+    pub convert_sigma: bool,
+    // 指定した場合はこのレベルのルールだけを読み込む(--exact-level)
+    pub exact_levels: Option<HashSet<String>>,
 }
 
 impl Default for ParseYaml {
@@ -36,6 +42,8 @@ impl ParseYaml {
             rulecounter: HashMap::new(),
             ignorerule_count: 0,
             errorrule_count: 0,
+            convert_sigma: false,
+            exact_levels: configs::EXACT_LEVELS.clone(),
         }
     }
 
@@ -59,19 +67,17 @@ impl ParseYaml {
         exclude_ids: &RuleExclude,
     ) -> io::Result<String> {
         let metadata = fs::metadata(path.as_ref());
-        if metadata.is_err() {
+        if let Err(err) = &metadata {
             let errmsg = format!(
                 "fail to read metadata of file: {}",
                 path.as_ref().to_path_buf().display(),
             );
-            if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                AlertMessage::alert(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)?;
-            }
+            logging::verbose_error(&errmsg);
             if !*QUIET_ERRORS_FLAG {
-                ERROR_LOG_STACK
-                    .lock()
-                    .unwrap()
-                    .push(format!("[ERROR] {}", errmsg));
+                ERROR_LOG_STACK.lock().unwrap().push(
+                    ErrorLog::error(ErrorClass::from_io_error(err), &errmsg)
+                        .with_file_path(&path.as_ref().display().to_string()),
+                );
             }
             return io::Result::Ok(String::default());
         }
@@ -90,20 +96,18 @@ impl ParseYaml {
 
             // 個別のファイルの読み込みは即終了としない。
             let read_content = self.read_file(path.as_ref().to_path_buf());
-            if read_content.is_err() {
+            if let Err(err) = &read_content {
                 let errmsg = format!(
                     "fail to read file: {}\n{} ",
                     path.as_ref().to_path_buf().display(),
-                    read_content.unwrap_err()
+                    err
                 );
-                if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                    AlertMessage::warn(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)?;
-                }
+                logging::verbose_warn(&errmsg);
                 if !*QUIET_ERRORS_FLAG {
-                    ERROR_LOG_STACK
-                        .lock()
-                        .unwrap()
-                        .push(format!("[WARN] {}", errmsg));
+                    ERROR_LOG_STACK.lock().unwrap().push(
+                        ErrorLog::warn(ErrorClass::FileRead, &errmsg)
+                            .with_file_path(&path.as_ref().display().to_string()),
+                    );
                 }
                 self.errorrule_count += 1;
                 return io::Result::Ok(String::default());
@@ -117,14 +121,12 @@ impl ParseYaml {
                     path.as_ref().to_path_buf().display(),
                     yaml_contents.unwrap_err()
                 );
-                if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                    AlertMessage::warn(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)?;
-                }
+                logging::verbose_warn(&errmsg);
                 if !*QUIET_ERRORS_FLAG {
-                    ERROR_LOG_STACK
-                        .lock()
-                        .unwrap()
-                        .push(format!("[WARN] {}", errmsg));
+                    ERROR_LOG_STACK.lock().unwrap().push(
+                        ErrorLog::warn(ErrorClass::RuleParse, &errmsg)
+                            .with_file_path(&path.as_ref().display().to_string()),
+                    );
                 }
                 self.errorrule_count += 1;
                 return io::Result::Ok(String::default());
@@ -163,20 +165,14 @@ impl ParseYaml {
 
                 // 個別のファイルの読み込みは即終了としない。
                 let read_content = self.read_file(path);
-                if read_content.is_err() {
-                    let errmsg = format!(
-                        "fail to read file: {}\n{} ",
-                        entry.path().display(),
-                        read_content.unwrap_err()
-                    );
-                    if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                        AlertMessage::warn(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)?;
-                    }
+                if let Err(err) = &read_content {
+                    let errmsg = format!("fail to read file: {}\n{} ", entry.path().display(), err);
+                    logging::verbose_warn(&errmsg);
                     if !*QUIET_ERRORS_FLAG {
-                        ERROR_LOG_STACK
-                            .lock()
-                            .unwrap()
-                            .push(format!("[WARN] {}", errmsg));
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::warn(ErrorClass::FileRead, &errmsg)
+                                .with_file_path(&entry.path().display().to_string()),
+                        );
                     }
                     self.errorrule_count += 1;
                     return io::Result::Ok(ret);
@@ -190,14 +186,12 @@ impl ParseYaml {
                         entry.path().display(),
                         yaml_contents.unwrap_err()
                     );
-                    if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                        AlertMessage::warn(&mut BufWriter::new(std::io::stderr().lock()), &errmsg)?;
-                    }
+                    logging::verbose_warn(&errmsg);
                     if !*QUIET_ERRORS_FLAG {
-                        ERROR_LOG_STACK
-                            .lock()
-                            .unwrap()
-                            .push(format!("[WARN] {}", errmsg));
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::warn(ErrorClass::RuleParse, &errmsg)
+                                .with_file_path(&entry.path().display().to_string()),
+                        );
                     }
                     self.errorrule_count += 1;
                     return io::Result::Ok(ret);
@@ -212,6 +206,11 @@ impl ParseYaml {
             })?;
         }
 
+        if self.convert_sigma {
+            yaml_docs = self.convert_sigma_rules(yaml_docs);
+        }
+        yaml_docs = self.expand_macros(yaml_docs)?;
+
         let files: Vec<(String, Yaml)> = yaml_docs
             .into_iter()
             .filter_map(|(filepath, yaml_doc)| {
@@ -238,9 +237,7 @@ impl ParseYaml {
                         + 1,
                 );
 
-                if configs::CONFIG.read().unwrap().args.is_present("verbose") {
-                    println!("Loaded yml file path: {}", filepath);
-                }
+                tracing::info!("Loaded yml file path: {}", filepath);
 
                 // 指定されたレベルより低いルールは無視する
                 let doc_level = &yaml_doc["level"]
@@ -253,6 +250,17 @@ impl ParseYaml {
                 if doc_level_num < args_level_num {
                     return Option::None;
                 }
+                // --exact-levelが指定されている場合は、指定されたレベル以外のルールも無視する
+                if let Some(exact_levels) = &self.exact_levels {
+                    let doc_level = if configs::LEVELMAP.contains_key(doc_level) {
+                        doc_level.as_str()
+                    } else {
+                        "INFORMATIONAL"
+                    };
+                    if !exact_levels.contains(doc_level) {
+                        return Option::None;
+                    }
+                }
 
                 if !configs::CONFIG
                     .read()
@@ -273,6 +281,54 @@ impl ParseYaml {
         self.files.extend(files);
         io::Result::Ok(String::default())
     }
+
+    /// detectionのexpand修飾子のマクロを展開する。展開できないルールは読み込みエラーとして扱う
+    fn expand_macros(&mut self, yaml_docs: Vec<(String, Yaml)>) -> io::Result<Vec<(String, Yaml)>> {
+        let mut expanded = vec![];
+        for (filepath, yaml_doc) in yaml_docs {
+            match macros::expand_rule_macros(yaml_doc) {
+                Ok(yaml_doc) => expanded.push((filepath, yaml_doc)),
+                Err(err) => {
+                    let errmsg = format!(
+                        "Failed to expand the detection macros: {}\n{} ",
+                        filepath, err
+                    );
+                    logging::verbose_warn(&errmsg);
+                    if !*QUIET_ERRORS_FLAG {
+                        ERROR_LOG_STACK.lock().unwrap().push(
+                            ErrorLog::warn(ErrorClass::RuleParse, &errmsg)
+                                .with_file_path(&filepath),
+                        );
+                    }
+                    self.errorrule_count += 1;
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// SigmaHQのルールをconvert-sigmaと同じ変換でhayabusaのルールにする。変換済みのルールはそのまま使う
+    fn convert_sigma_rules(&mut self, yaml_docs: Vec<(String, Yaml)>) -> Vec<(String, Yaml)> {
+        let mut converted = vec![];
+        for (filepath, yaml_doc) in yaml_docs {
+            if sigma_convert::is_converted(&yaml_doc) {
+                converted.push((filepath, yaml_doc));
+                continue;
+            }
+            match sigma_convert::convert_rule(&yaml_doc) {
+                Ok(rules) => converted.extend(
+                    rules
+                        .into_iter()
+                        .map(|(_, rule)| (filepath.to_string(), rule)),
+                ),
+                Err(err) => {
+                    tracing::info!("Skipped Sigma rule: {} ({})", filepath, err);
+                    self.ignorerule_count += 1;
+                }
+            }
+        }
+        converted
+    }
 }
 
 #[cfg(test)]
@@ -356,6 +412,26 @@ mod tests {
             .unwrap();
         assert_eq!(yaml.files.len(), 5);
     }
+    #[test]
+    fn test_exact_level_read_yaml() {
+        let mut yaml = yaml::ParseYaml::new();
+        yaml.exact_levels = Some(
+            ["HIGH", "CRITICAL"]
+                .iter()
+                .map(|level| level.to_string())
+                .collect(),
+        );
+        let path = Path::new("test_files/rules/level_yaml");
+        yaml.read_dir(path, "", &filter::exclude_ids()).unwrap();
+        assert_eq!(yaml.files.len(), 2);
+
+        // levelがinfoのルールはinformationalとして扱う
+        let mut yaml = yaml::ParseYaml::new();
+        yaml.exact_levels = Some(["INFORMATIONAL".to_string()].into_iter().collect());
+        yaml.read_dir(path, "", &filter::exclude_ids()).unwrap();
+        assert_eq!(yaml.files.len(), 1);
+    }
+
     #[test]
     fn test_low_level_read_yaml() {
         let mut yaml = yaml::ParseYaml::new();
@@ -413,4 +489,19 @@ mod tests {
         yaml.read_dir(path, "", &exclude_ids).unwrap();
         assert_eq!(yaml.ignorerule_count, 1);
     }
+    #[test]
+    fn test_convert_sigma_rules_file() {
+        let mut yaml = yaml::ParseYaml::new();
+        yaml.convert_sigma = true;
+        let path = Path::new("test_files/rules/sigma");
+        let exclude_ids = RuleExclude::default();
+        yaml.read_dir(path, "", &exclude_ids).unwrap();
+        // process_creationのルールはSysmonとSecurityの2つのルールになり、Linuxのルールは無視される
+        assert_eq!(yaml.files.len(), 2);
+        assert_eq!(yaml.ignorerule_count, 1);
+        assert_eq!(
+            yaml.files[1].1["detection"]["hayabusa_logsource"]["Channel"].as_str(),
+            Some("Security")
+        );
+    }
 }